@@ -0,0 +1,61 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rayca::*;
+
+/// Scene with a single dense mesh of about 20k triangles
+fn icosphere_scene() -> Scene {
+    let mut model = Model::new();
+    let primitive = Primitive::builder()
+        .triangles(Triangles::icosphere(1.0, 5))
+        .build();
+    let primitive = model.primitives.push(primitive);
+    let mesh = model.meshes.push(Mesh::new(vec![primitive]));
+    let node = model.nodes.push(Node::builder().mesh(mesh).build());
+    model.root.children.push(node);
+
+    let mut scene = Scene::new();
+    scene.push(model);
+    scene.push_default_model();
+    scene
+}
+
+fn build(c: &mut Criterion) {
+    let scenes = vec![
+        ("icosphere", icosphere_scene()),
+        ("cornell box", Scene::cornell_box()),
+    ];
+    for (name, mut scene) in scenes {
+        c.bench_function(&format!("bvh build {}", name), |b| {
+            b.iter(|| {
+                let bvh = scene.build_bvh();
+                bvh.recycle(&mut scene.arena);
+            })
+        });
+    }
+}
+
+fn traverse(c: &mut Criterion) {
+    let mut scene = icosphere_scene();
+    let bvh = scene.build_bvh();
+    let rays: Vec<Ray> = (0..64)
+        .map(|i| {
+            let x = (i % 8) as f32 / 4.0 - 1.0;
+            let y = (i / 8) as f32 / 4.0 - 1.0;
+            Ray::new(Point3::new(x, y, 4.0), Vec3::new(0.0, 0.0, -1.0))
+        })
+        .collect();
+
+    c.bench_function("bvh traverse icosphere", |b| {
+        b.iter(|| {
+            rays.iter()
+                .filter(|ray| bvh.intersects_iter(&scene.model, ray).is_some())
+                .count()
+        })
+    });
+}
+
+criterion_group!(benches, build, traverse);
+criterion_main!(benches);
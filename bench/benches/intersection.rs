@@ -0,0 +1,39 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rayca::*;
+
+fn aabb(c: &mut Criterion) {
+    let aabb = AABB::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+    let hit = Ray::new(Point3::new(0.0, 0.0, 4.0), Vec3::new(0.1, 0.1, -1.0));
+    let miss = Ray::new(Point3::new(0.0, 0.0, 4.0), Vec3::new(1.0, 0.0, 0.0));
+
+    c.bench_function("aabb hit", |b| {
+        b.iter(|| black_box(&aabb).intersects(black_box(&hit)))
+    });
+    c.bench_function("aabb miss", |b| {
+        b.iter(|| black_box(&aabb).intersects(black_box(&miss)))
+    });
+}
+
+fn triangle(c: &mut Criterion) {
+    let triangle = BvhTriangle::new(
+        Vertex::new(-1.0, -1.0, 0.0),
+        Vertex::new(1.0, -1.0, 0.0),
+        Vertex::new(0.0, 1.0, 0.0),
+    );
+    let hit = Ray::new(Point3::new(0.0, 0.0, 4.0), Vec3::new(0.0, 0.0, -1.0));
+    let miss = Ray::new(Point3::new(4.0, 0.0, 4.0), Vec3::new(0.0, 0.0, -1.0));
+
+    c.bench_function("triangle hit", |b| {
+        b.iter(|| black_box(&triangle).intersects(black_box(&hit)))
+    });
+    c.bench_function("triangle miss", |b| {
+        b.iter(|| black_box(&triangle).intersects(black_box(&miss)))
+    });
+}
+
+criterion_group!(benches, aabb, triangle);
+criterion_main!(benches);
@@ -0,0 +1,26 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rayca::*;
+
+/// Full frames of the standard scenes
+fn render(c: &mut Criterion) {
+    let scenes = vec![
+        ("cornell box", Scene::cornell_box()),
+        ("veach mis", Scene::veach_mis()),
+        ("pbr test grid", Scene::pbr_test_grid()),
+    ];
+
+    let mut group = c.benchmark_group("render");
+    group.sample_size(10);
+    for (name, mut scene) in scenes {
+        let mut image = Image::new(128, 128, ColorType::RGBA8);
+        group.bench_function(name, |b| b.iter(|| scene.draw(&mut image)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
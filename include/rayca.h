@@ -0,0 +1,45 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+// C interface of rayca, available when the crate is built with the `ffi` feature
+
+#ifndef RAYCA_H
+#define RAYCA_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define RAYCA_OK 0
+#define RAYCA_INVALID_ARGUMENT -1
+#define RAYCA_LOAD_FAILED -2
+// Returned when the renderer panicked, the scene should be destroyed
+#define RAYCA_PANIC -3
+
+typedef struct RaycaScene RaycaScene;
+
+// Returns NULL on failure
+RaycaScene *rayca_scene_create(void);
+void rayca_scene_destroy(RaycaScene *scene);
+
+// Loads a binary glTF, or a glTF with embedded resources, from memory.
+// Embedded images can be PNG, JPEG, or uncompressed KTX2.
+int rayca_scene_load_gltf(RaycaScene *scene, const uint8_t *data, size_t len);
+
+// `translation` points to 3 floats, `rotation` to a quaternion in x, y, z, w order
+int rayca_scene_set_camera(RaycaScene *scene, const float *translation, const float *rotation,
+                           float yfov_radians);
+
+// Renders into `buffer`, which must hold at least `width * height * 4` bytes
+int rayca_scene_render(RaycaScene *scene, uint32_t width, uint32_t height, uint8_t *buffer,
+                       size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // RAYCA_H
@@ -0,0 +1,223 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Arbitrary output variables: geometric data of the primary hits written to
+//! float buffers in place of colors, for tools projecting textures or relighting
+//! renders after the fact.
+
+use std::{error::Error, path::Path};
+
+use crate::*;
+
+/// Data written by every pixel of an AOV buffer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Aov {
+    /// Position of the hit in world space
+    #[default]
+    WorldPosition,
+
+    /// Position of the hit in the space of the node it belongs to
+    ObjectPosition,
+
+    /// Shading normal of the hit in the space of the node it belongs to
+    ObjectNormal,
+
+    /// Offset in pixels from the hit to where it was in the previous frame,
+    /// following both its node and the camera, in red and green
+    Motion,
+}
+
+impl Aov {
+    /// Returns the value of this AOV for `hit`, seen from the first camera on an image
+    /// of `width` x `height` pixels, with alpha set to one
+    pub fn get(
+        &self,
+        model: &Model,
+        hit: &Hit,
+        primitive: &BvhPrimitive,
+        (width, height): (u32, u32),
+    ) -> Color {
+        let trs = &model.solved_trs.get(&primitive.node).unwrap().trs;
+        let (x, y, z) = match self {
+            Aov::WorldPosition => (hit.point.get_x(), hit.point.get_y(), hit.point.get_z()),
+            Aov::ObjectPosition => {
+                let point = &trs.get_inversed() * hit.point;
+                (point.get_x(), point.get_y(), point.get_z())
+            }
+            Aov::ObjectNormal => {
+                // Normals go to world space through the inverse transpose
+                // of the transform, so the transpose brings them back
+                let normal = primitive.get_normal(model, hit);
+                let normal = (&Mat3::from(trs).get_transpose() * normal).get_normalized();
+                (normal.get_x(), normal.get_y(), normal.get_z())
+            }
+            Aov::Motion => {
+                let (width, height) = (width as f32, height as f32);
+                let camera_node_handle = model.camera_nodes[0];
+                let camera_node = model.nodes.get(camera_node_handle).unwrap();
+                let angle = model.cameras.get(camera_node.camera).unwrap().get_angle();
+                let camera_trs = &model.solved_trs.get(&camera_node_handle).unwrap().trs;
+                let previous_camera_trs = model.get_previous_trs(camera_node_handle).unwrap();
+
+                // The hit moves rigidly with its node
+                let point = &trs.get_inversed() * hit.point;
+                let previous_trs = model.get_previous_trs(primitive.node).unwrap();
+                let previous_point = previous_trs * point;
+
+                let current = get_pixel_position(camera_trs, angle, width, height, hit.point);
+                let previous =
+                    get_pixel_position(previous_camera_trs, angle, width, height, previous_point);
+                match (current, previous) {
+                    (Some(current), Some(previous)) => {
+                        (previous.x - current.x, previous.y - current.y, 0.0)
+                    }
+                    _ => (0.0, 0.0, 0.0),
+                }
+            }
+        };
+        Color::new(x, y, z, 1.0)
+    }
+}
+
+/// Row major, top-left origin buffer of RGBA32F pixels, where
+/// pixels seeing nothing are zero, alpha included
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AovBuffer {
+    pub aov: Aov,
+    pub pixels: Vec<Color>,
+    width: u32,
+    height: u32,
+}
+
+impl AovBuffer {
+    pub fn new(aov: Aov, width: u32, height: u32) -> Self {
+        Self {
+            aov,
+            pixels: vec![Color::new(0.0, 0.0, 0.0, 0.0); width as usize * height as usize],
+            width,
+            height,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Color {
+        self.pixels[y as usize * self.width as usize + x as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: Color) {
+        self.pixels[y as usize * self.width as usize + x as usize] = color;
+    }
+
+    /// Returns the pixels as little endian RGBA32F, without any header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(self.pixels.len() * 16);
+        for pixel in &self.pixels {
+            for channel in [pixel.r, pixel.g, pixel.b, pixel.a] {
+                ret.extend_from_slice(&channel.to_le_bytes());
+            }
+        }
+        ret
+    }
+
+    /// Writes the pixels to `path` as returned by `to_bytes()`
+    pub fn dump<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn object_space() {
+        let mut model = Model::new();
+        let plane = model.primitives.push(
+            Primitive::builder()
+                .triangles(Triangles::plane(2.0, 2.0, 1, 1))
+                .build(),
+        );
+        let mesh = model.meshes.push(Mesh::new(vec![plane]));
+        // Plane standing in front of the camera, moved away from the origin
+        let node = model.nodes.push(
+            Node::builder()
+                .mesh(mesh)
+                .translation(Vec3::new(0.0, 0.0, -4.0))
+                .rotation(Quat::axis_angle(
+                    Vec3::new(1.0, 0.0, 0.0),
+                    std::f32::consts::FRAC_PI_2,
+                ))
+                .build(),
+        );
+        model.root.children.push(node);
+        let mut scene = Scene::new();
+        scene.push(model);
+        scene.push_default_model();
+
+        let world = scene.render_aov(Aov::WorldPosition, 4, 4);
+        assert_eq!(world.width(), 4);
+        let center = world.get(2, 2);
+        assert!((center.b + 4.0).abs() < 1e-4);
+        assert_eq!(center.a, 1.0);
+
+        let object = scene.render_aov(Aov::ObjectPosition, 4, 4);
+        let center = object.get(2, 2);
+        assert!(center.g.abs() < 1e-4);
+
+        let normal = scene.render_aov(Aov::ObjectNormal, 4, 4);
+        let center = normal.get(2, 2);
+        assert!((center.g - 1.0).abs() < 1e-4);
+        assert_eq!(normal.to_bytes().len(), 4 * 4 * 16);
+    }
+
+    #[test]
+    fn motion() {
+        let mut model = Model::new();
+        let plane = model.primitives.push(
+            Primitive::builder()
+                .triangles(Triangles::plane(4.0, 4.0, 1, 1))
+                .build(),
+        );
+        let mesh = model.meshes.push(Mesh::new(vec![plane]));
+        let node = model.nodes.push(
+            Node::builder()
+                .mesh(mesh)
+                .translation(Vec3::new(0.0, 0.0, -4.0))
+                .rotation(Quat::axis_angle(
+                    Vec3::new(1.0, 0.0, 0.0),
+                    std::f32::consts::FRAC_PI_2,
+                ))
+                .build(),
+        );
+        model.root.children.push(node);
+        let mut scene = Scene::new();
+        scene.push(model);
+        scene.push_default_model();
+
+        let mut image = Image::new(8, 8, ColorType::RGBA8);
+        scene.draw(&mut image);
+        scene.draw(&mut image);
+        // Nothing moved between the two frames
+        let motion = scene.render_aov(Aov::Motion, 8, 8);
+        assert_eq!(motion.get(4, 4).r, 0.0);
+
+        // The plane moving right was on the left in the previous frame
+        scene.model.nodes.get_mut(node).unwrap().trs.translation += Vec3::new(0.5, 0.0, 0.0);
+        scene.model.mark_dirty(node);
+        scene.draw(&mut image);
+        let motion = scene.render_aov(Aov::Motion, 8, 8);
+        let center = motion.get(4, 4);
+        assert!(center.r < -0.1, "{}", center.r);
+        assert!(center.g.abs() < 1e-4);
+        assert_eq!(center.a, 1.0);
+    }
+}
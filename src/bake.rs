@@ -0,0 +1,393 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::f32::consts::PI;
+
+#[cfg(feature = "parallel")]
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+
+use crate::*;
+
+/// A texel of the lightmap covered by a triangle of the baked node
+struct Texel {
+    x: u32,
+    y: u32,
+    point: Point3,
+    normal: Vec3,
+}
+
+/// Bakes the light reaching the surfaces of a node into an image, using the
+/// texture coordinates of its triangles as lightmap chart. Every texel stores
+/// the irradiance divided by PI, which is the radiance reflected by a white
+/// diffuse surface, and its alpha tells whether the texel is covered by the chart.
+pub struct LightmapBaker {
+    pub width: u32,
+    pub height: u32,
+
+    /// Number of hemisphere samples estimating indirect light for every texel
+    pub sample_count: u32,
+
+    /// Number of texels chart edges are grown by, so that bilinear filtering
+    /// does not bleed uncovered texels into the surfaces
+    pub dilation: u32,
+
+    /// Set of texture coordinates used as lightmap chart, see `Triangles::unwrap()`
+    pub uv_set: usize,
+}
+
+impl Default for LightmapBaker {
+    fn default() -> Self {
+        Self::new(256, 256)
+    }
+}
+
+impl LightmapBaker {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            sample_count: 64,
+            dilation: 2,
+            uv_set: 0,
+        }
+    }
+
+    /// Finds the texels covered by the triangles of `node`
+    fn rasterize(&self, bvh: &Bvh, node: Handle<Node>) -> Vec<Texel> {
+        let mut ret = vec![];
+        let mut covered = vec![false; self.width as usize * self.height as usize];
+
+        for primitive in &bvh.primitives {
+            if primitive.node != node {
+                continue;
+            }
+            let BvhGeometry::Triangle(triangle) = &primitive.geometry else {
+                continue;
+            };
+
+            // Texture coordinates in texel space
+            let to_texels =
+                |uv: Vec2| Vec2::new(uv.x * self.width as f32, uv.y * self.height as f32);
+            let a = to_texels(triangle.vertices[0].ext.get_uv(self.uv_set));
+            let b = to_texels(triangle.vertices[1].ext.get_uv(self.uv_set));
+            let c = to_texels(triangle.vertices[2].ext.get_uv(self.uv_set));
+
+            let area = (b.x - a.x) * (c.y - a.y) - (c.x - a.x) * (b.y - a.y);
+            if area.abs() < f32::EPSILON {
+                continue;
+            }
+
+            let min_x = a.x.min(b.x).min(c.x).floor().max(0.0) as u32;
+            let min_y = a.y.min(b.y).min(c.y).floor().max(0.0) as u32;
+            let max_x = (a.x.max(b.x).max(c.x).ceil() as u32).min(self.width);
+            let max_y = (a.y.max(b.y).max(c.y).ceil() as u32).min(self.height);
+
+            for y in min_y..max_y {
+                for x in min_x..max_x {
+                    let p = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                    // Barycentric coordinates with the same convention of hits
+                    let w0 = ((b.x - p.x) * (c.y - p.y) - (c.x - p.x) * (b.y - p.y)) / area;
+                    let w1 = ((c.x - p.x) * (a.y - p.y) - (a.x - p.x) * (c.y - p.y)) / area;
+                    let w2 = 1.0 - w0 - w1;
+                    if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                        continue;
+                    }
+
+                    let index = (y * self.width + x) as usize;
+                    if covered[index] {
+                        continue;
+                    }
+                    covered[index] = true;
+
+                    let point = Point3::from(
+                        Vec3::from(triangle.vertices[0].pos) * w0
+                            + Vec3::from(triangle.vertices[1].pos) * w1
+                            + Vec3::from(triangle.vertices[2].pos) * w2,
+                    );
+                    let normal = triangle.interpolate_normals(&Vec2::new(w0, w1));
+                    ret.push(Texel {
+                        x,
+                        y,
+                        point,
+                        normal,
+                    });
+                }
+            }
+        }
+
+        ret
+    }
+
+    /// Estimates the irradiance at a point: direct light comes from the lights of
+    /// the scene, while indirect light is sampled with the integrator of the scene
+    fn irradiance(&self, scene: &Scene, bvh: &Bvh, texel: &Texel, rng: &mut Rng) -> Color {
+        let model = &scene.model;
+        let n = texel.normal;
+
+        let mut ret = Color::black();
+
+        for light_node_handle in &model.light_nodes {
+            let light_node = model.nodes.get(*light_node_handle).unwrap();
+            let light = model.lights.get(light_node.light).unwrap();
+            let node_trs = &model.solved_trs.get(light_node_handle).unwrap().trs;
+            let light_trs = &light.sample_trs(node_trs, rng);
+            let l = light.get_direction(light_trs, &texel.point);
+            let n_dot_l = n.dot(&l);
+            if n_dot_l <= 0.0 {
+                continue;
+            }
+
+            let shadow_ray = Ray::spawn(&texel.point, &n, l);
+            bvh.stats.add_shadow_ray();
+            if let Some((hit, _)) = bvh.intersects_occluder(model, &shadow_ray) {
+                if hit.depth < light.get_distance(light_trs, &texel.point) {
+                    continue;
+                }
+            }
+
+            ret += model.get_light_intensity(light, light_trs, &texel.point)
+                * light.get_emission(node_trs, light_trs)
+                * n_dot_l;
+        }
+
+        // Cosine weighted samples, where the PDF cancels out the cosine term and PI
+        let mut indirect = Color::black();
+        for _ in 0..self.sample_count {
+            let dir = rng.next_cosine_hemisphere(&n);
+            let ray = Ray::spawn(&texel.point, &n, dir);
+            bvh.stats.add_bounce_ray();
+            if let Some(radiance) = scene.config.integrator.trace(model, ray, bvh, 1, rng) {
+                indirect += radiance;
+            }
+        }
+        if self.sample_count > 0 {
+            ret += indirect * PI / self.sample_count as f32;
+        }
+
+        ret / PI
+    }
+
+    /// Fills uncovered texels next to covered ones with the average of their neighbours
+    fn dilate(&self, colors: &mut [Color]) {
+        let width = self.width as i32;
+        let height = self.height as i32;
+
+        for _ in 0..self.dilation {
+            let source = colors.to_vec();
+            for y in 0..height {
+                for x in 0..width {
+                    let index = (y * width + x) as usize;
+                    if source[index].a > 0.0 {
+                        continue;
+                    }
+
+                    let mut sum = Color::new(0.0, 0.0, 0.0, 0.0);
+                    let mut count = 0;
+                    for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                        let (nx, ny) = (x + dx, y + dy);
+                        if nx < 0 || ny < 0 || nx >= width || ny >= height {
+                            continue;
+                        }
+                        let neighbour = source[(ny * width + nx) as usize];
+                        if neighbour.a > 0.0 {
+                            sum += neighbour;
+                            count += 1;
+                        }
+                    }
+                    if count > 0 {
+                        colors[index] = sum / count as f32;
+                        colors[index].a = 1.0;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn bake(&self, scene: &mut Scene, node: Handle<Node>) -> Image {
+        let mut timer = Timer::new();
+
+        let bvh = scene.build_bvh();
+        let texels = self.rasterize(&bvh, node);
+
+        #[cfg(feature = "parallel")]
+        let texel_iter = texels.par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let texel_iter = texels.iter();
+
+        let scene: &Scene = scene;
+        let irradiances: Vec<Color> = texel_iter
+            .map(|texel| {
+                let mut rng = Rng::for_pixel(scene.config.seed, texel.x, texel.y);
+                let mut color = self.irradiance(scene, &bvh, texel, &mut rng);
+                color.a = 1.0;
+                color
+            })
+            .collect();
+
+        let mut colors = vec![Color::new(0.0, 0.0, 0.0, 0.0); (self.width * self.height) as usize];
+        for (texel, color) in texels.iter().zip(irradiances) {
+            colors[(texel.y * self.width + texel.x) as usize] = color;
+        }
+        self.dilate(&mut colors);
+
+        let mut image = Image::new(self.width, self.height, ColorType::RGBA8);
+        for (pixel, color) in image.data_mut::<RGBA8>().iter_mut().zip(colors) {
+            *pixel = color.into();
+        }
+
+        print_success!(
+            "Baked",
+            "{} texels in {:.2}ms",
+            texels.len(),
+            timer.get_delta().as_millis()
+        );
+        image
+    }
+}
+
+/// Bakes irradiance probes, capturing the radiance reaching a point
+/// from every direction as spherical harmonics up to band L2.
+/// Their irradiance can be evaluated with `ShL2::evaluate_irradiance()`.
+pub struct ProbeBaker {
+    /// Number of directions sampled for every probe
+    pub sample_count: u32,
+}
+
+impl Default for ProbeBaker {
+    fn default() -> Self {
+        Self::new(256)
+    }
+}
+
+impl ProbeBaker {
+    pub fn new(sample_count: u32) -> Self {
+        Self { sample_count }
+    }
+
+    pub fn bake(&self, scene: &mut Scene, positions: &[Point3]) -> Vec<ShL2> {
+        let mut timer = Timer::new();
+        let bvh = scene.build_bvh();
+
+        #[cfg(feature = "parallel")]
+        let position_iter = positions.par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let position_iter = positions.iter();
+
+        let scene: &Scene = scene;
+        let ret = position_iter
+            .enumerate()
+            .map(|(i, position)| {
+                let mut rng = Rng::for_pixel(scene.config.seed, i as u32, 0);
+                let mut sh = ShL2::new();
+
+                // Uniform sphere sampling
+                let weight = 4.0 * PI / self.sample_count as f32;
+                for _ in 0..self.sample_count {
+                    let dir = rng.next_sphere();
+                    let ray = Ray::new(*position, dir);
+                    bvh.stats.add_primary_ray();
+                    if let Some(radiance) =
+                        scene
+                            .config
+                            .integrator
+                            .trace(&scene.model, ray, &bvh, 0, &mut rng)
+                    {
+                        sh.add_sample(&dir, radiance, weight);
+                    }
+                }
+                sh
+            })
+            .collect();
+
+        print_success!(
+            "Baked",
+            "{} probes in {:.2}ms",
+            positions.len(),
+            timer.get_delta().as_millis()
+        );
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn triangle() {
+        let mut model = Model::new();
+        let mut vertices = vec![
+            Vertex::new(-1.0, 0.0, 0.0),
+            Vertex::new(1.0, 0.0, 0.0),
+            Vertex::new(-1.0, 2.0, 0.0),
+        ];
+        for (vertex, uv) in vertices
+            .iter_mut()
+            .zip([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)])
+        {
+            vertex.ext.uv = Vec2::new(uv.0, uv.1);
+            vertex.ext.normal = Vec3::new(0.0, 0.0, 1.0);
+        }
+        let primitive = Primitive::builder()
+            .vertices(vertices)
+            .indices(vec![0, 1, 2])
+            .build();
+        let mesh = Mesh::new(vec![model.primitives.push(primitive)]);
+        let node = model
+            .nodes
+            .push(Node::builder().mesh(model.meshes.push(mesh)).build());
+        model.root.children.push(node);
+
+        let mut scene = Scene::new();
+        scene.push(model);
+        scene.push_default_model();
+        // Nodes are offset when appended to the scene
+        let node = scene.model.root.children[0];
+        let node = scene.model.nodes.get(node).unwrap().children[0];
+
+        let mut baker = LightmapBaker::new(16, 16);
+        baker.sample_count = 4;
+        baker.dilation = 1;
+        let lightmap = baker.bake(&mut scene, node);
+
+        // Lit texel inside the chart
+        let inside = lightmap.get::<RGBA8>(2, 2);
+        assert_eq!(inside.a, 255);
+        assert!(inside.r > 0);
+
+        // Texel next to the diagonal edge, filled by dilation
+        let dilated = lightmap.get::<RGBA8>(8, 8);
+        assert_eq!(dilated.a, 255);
+
+        // Texel far from the chart
+        let outside = lightmap.get::<RGBA8>(15, 15);
+        assert_eq!(outside.a, 0);
+    }
+
+    #[test]
+    fn probes() {
+        // A probe in front of a white sphere lit by the default lights
+        let mut model = Model::new();
+        let mesh = Mesh::new(vec![model.primitives.push(Primitive::unit_sphere())]);
+        let node = model.nodes.push(
+            Node::builder()
+                .mesh(model.meshes.push(mesh))
+                .translation(Vec3::new(0.0, 0.0, -2.0))
+                .build(),
+        );
+        model.root.children.push(node);
+
+        let mut scene = Scene::new();
+        scene.push(model);
+        scene.push_default_model();
+
+        let probes = ProbeBaker::new(256).bake(&mut scene, &[Point3::new(0.0, 0.0, 0.0)]);
+        assert_eq!(probes.len(), 1);
+
+        // More light comes from the sphere than from the empty space behind
+        let towards_sphere = probes[0].evaluate_irradiance(&Vec3::new(0.0, 0.0, -1.0));
+        let away = probes[0].evaluate_irradiance(&Vec3::new(0.0, 0.0, 1.0));
+        assert!(towards_sphere.r > away.r);
+    }
+}
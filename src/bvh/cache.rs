@@ -0,0 +1,382 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+use instant::Duration;
+
+use crate::*;
+
+const MAGIC: &[u8; 4] = b"RBVH";
+const VERSION: u32 = 1;
+
+/// FNV-1a, which is stable across runs and platforms unlike the standard hasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Identifies a primitive by its bounds, which is all a BVH cares about
+fn primitive_key(model: &Model, primitive: &BvhPrimitive) -> u64 {
+    let min = primitive.min(model);
+    let max = primitive.max(model);
+    let mut bytes = [0u8; 24];
+    for (i, value) in min.simd[..3].iter().chain(&max.simd[..3]).enumerate() {
+        bytes[i * 4..i * 4 + 4].copy_from_slice(&value.to_le_bytes());
+    }
+    fnv1a(&bytes)
+}
+
+/// Hash of the primitives which does not depend on their order,
+/// so that it is the same before and after building the BVH
+pub fn content_hash(model: &Model, primitives: &[BvhPrimitive], max_depth: usize) -> u64 {
+    let mut hash = primitives.iter().fold(0u64, |hash, primitive| {
+        hash.wrapping_add(primitive_key(model, primitive).wrapping_mul(0x9e3779b97f4a7c15))
+    });
+    hash ^= fnv1a(&(primitives.len() as u64).to_le_bytes());
+    hash ^= fnv1a(&(max_depth as u64).to_le_bytes()).rotate_left(32);
+    hash
+}
+
+/// Reads little endian values from serialized data
+pub(crate) struct Reader<'d> {
+    pub data: &'d [u8],
+}
+
+impl<'d> Reader<'d> {
+    pub fn bytes(&mut self, count: usize) -> Result<&'d [u8], Box<dyn Error>> {
+        if self.data.len() < count {
+            return Err("Unexpected end of data".into());
+        }
+        let (ret, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(ret)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Box<dyn Error>> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into()?))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, Box<dyn Error>> {
+        Ok(f32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+}
+
+impl Bvh {
+    /// Serializes the nodes of the BVH together with the order of its primitives.
+    /// Primitives themselves are not stored, as they can be collected from the model again.
+    /// Quantized BVHs can not be serialized, but they can be quantized after deserializing.
+    pub fn serialize(&self, model: &Model) -> Vec<u8> {
+        assert!(self.quantized.is_none());
+
+        // Flatten the tree, root first, then children in the order of their handles
+        let mut nodes = vec![&self.root];
+        for i in 0..self.nodes.len() {
+            nodes.push(self.nodes.get(Handle::new(i)).unwrap());
+        }
+
+        let mut ret = vec![];
+        ret.extend_from_slice(MAGIC);
+        ret.extend_from_slice(&VERSION.to_le_bytes());
+        ret.extend_from_slice(&self.content_hash.unwrap_or_default().to_le_bytes());
+        ret.extend_from_slice(&(self.primitives.len() as u32).to_le_bytes());
+        ret.extend_from_slice(&(nodes.len() as u32).to_le_bytes());
+
+        // Zero is used for missing children, as the root can not be a child
+        let child_index = |handle: Handle<BvhNode>| {
+            if handle.valid() {
+                handle.id as u32 + 1
+            } else {
+                0
+            }
+        };
+        for node in nodes {
+            let bounds = node.get_bounds();
+            for value in bounds.a.simd[..3].iter().chain(&bounds.b.simd[..3]) {
+                ret.extend_from_slice(&value.to_le_bytes());
+            }
+            ret.extend_from_slice(&child_index(node.get_left()).to_le_bytes());
+            ret.extend_from_slice(&child_index(node.get_right()).to_le_bytes());
+            let range = node.get_primitives();
+            ret.extend_from_slice(&range.get_offset().to_le_bytes());
+            ret.extend_from_slice(&(range.len() as u32).to_le_bytes());
+        }
+
+        for primitive in &self.primitives {
+            ret.extend_from_slice(&primitive_key(model, primitive).to_le_bytes());
+        }
+
+        ret
+    }
+
+    /// Restores a BVH serialized with `serialize()`, using freshly collected `primitives`.
+    /// Fails when the data is corrupted or it does not match the primitives
+    pub fn deserialize(
+        model: &Model,
+        primitives: Vec<BvhPrimitive>,
+        data: &[u8],
+    ) -> Result<Bvh, Box<dyn Error>> {
+        let mut timer = Timer::new();
+        let layout = SerializedBvh::read(model, &primitives, data)?;
+        Ok(layout.into_bvh(primitives, timer.get_delta()))
+    }
+}
+
+/// Nodes and primitive order read from serialized data
+struct SerializedBvh {
+    content_hash: u64,
+    nodes: Vec<BvhNode>,
+    order: Vec<usize>,
+}
+
+impl SerializedBvh {
+    /// Reads without taking the primitives, so they can still be used when this fails
+    fn read(
+        model: &Model,
+        primitives: &[BvhPrimitive],
+        data: &[u8],
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut reader = Reader { data };
+
+        if reader.bytes(4)? != MAGIC {
+            return Err("Not a BVH".into());
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(format!("Unsupported BVH version {}", version).into());
+        }
+        let content_hash = reader.u64()?;
+        let primitive_count = reader.u32()? as usize;
+        if primitive_count != primitives.len() {
+            return Err("BVH primitive count mismatch".into());
+        }
+        let node_count = reader.u32()? as usize;
+        if node_count == 0 {
+            return Err("BVH without root".into());
+        }
+
+        let child = |index: usize| {
+            if index == 0 {
+                Handle::NONE
+            } else {
+                Handle::new(index - 1)
+            }
+        };
+        let mut nodes = Vec::with_capacity(node_count);
+        for _ in 0..node_count {
+            let a = Point3::new(reader.f32()?, reader.f32()?, reader.f32()?);
+            let b = Point3::new(reader.f32()?, reader.f32()?, reader.f32()?);
+            let left = reader.u32()? as usize;
+            let right = reader.u32()? as usize;
+            let offset = reader.u32()?;
+            let count = reader.u32()?;
+            if left >= node_count || right >= node_count {
+                return Err("BVH child out of range".into());
+            }
+            if offset as usize + count as usize > primitive_count {
+                return Err("BVH primitive range out of range".into());
+            }
+            let range = BvhRange::new(offset, count);
+            let node = BvhNode::from_parts(AABB::new(a, b), child(left), child(right), range);
+            nodes.push(node);
+        }
+
+        // Find where every primitive goes by matching their keys
+        let mut slots: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, primitive) in primitives.iter().enumerate() {
+            slots
+                .entry(primitive_key(model, primitive))
+                .or_default()
+                .push(i);
+        }
+        let mut order = Vec::with_capacity(primitive_count);
+        for _ in 0..primitive_count {
+            let key = reader.u64()?;
+            let index = slots
+                .get_mut(&key)
+                .and_then(|indices| indices.pop())
+                .ok_or("BVH does not match primitives")?;
+            order.push(index);
+        }
+
+        Ok(Self {
+            content_hash,
+            nodes,
+            order,
+        })
+    }
+
+    fn into_bvh(self, primitives: Vec<BvhPrimitive>, build_time: Duration) -> Bvh {
+        let mut taken: Vec<Option<BvhPrimitive>> = primitives.into_iter().map(Some).collect();
+        let primitives = self
+            .order
+            .into_iter()
+            .map(|index| taken[index].take().unwrap())
+            .collect();
+
+        let mut flat_nodes = self.nodes.into_iter();
+        let root = flat_nodes.next().unwrap();
+        let mut nodes = Pack::new();
+        for node in flat_nodes {
+            nodes.push(node);
+        }
+
+        Bvh {
+            root,
+            nodes,
+            triangle_count: 0,
+            primitives,
+            quantized: None,
+            content_hash: Some(self.content_hash),
+            build_time,
+            stats: Stats::new(),
+            degenerate_count: 0,
+        }
+    }
+}
+
+/// Stores serialized BVHs in a directory, keyed by the content hash of their primitives
+pub struct BvhCache {
+    dir: PathBuf,
+}
+
+impl BvhCache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn get_path(&self, content_hash: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.bvh", content_hash))
+    }
+
+    /// Returns the cached BVH for these primitives, or builds and caches a new one
+    pub fn get_or_build(
+        &self,
+        model: &Model,
+        primitives: Vec<BvhPrimitive>,
+        max_depth: usize,
+    ) -> Bvh {
+        let content_hash = content_hash(model, &primitives, max_depth);
+        let path = self.get_path(content_hash);
+
+        if let Ok(data) = std::fs::read(&path) {
+            let mut timer = Timer::new();
+            match SerializedBvh::read(model, &primitives, &data) {
+                Ok(layout) => {
+                    print_success!("BVH", "loaded from {}", path.display());
+                    return layout.into_bvh(primitives, timer.get_delta());
+                }
+                Err(err) => print_warning!("BVH", "ignoring {}: {}", path.display(), err),
+            }
+        }
+
+        let mut bvh = Bvh::new(model, primitives, max_depth);
+        bvh.content_hash = Some(content_hash);
+        let result = std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::write(&path, bvh.serialize(model)));
+        if let Err(err) = result {
+            print_warning!("BVH", "failed to cache {}: {}", path.display(), err);
+        }
+        bvh
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn create_model() -> Model {
+        let mut model = Model::new();
+        let sphere = model.primitives.push(Primitive::unit_sphere());
+        let triangle = model.primitives.push(Primitive::unit_triangle());
+        let mesh = model.meshes.push(Mesh::new(vec![sphere, triangle]));
+        for i in 0..16 {
+            let translation = Vec3::new((i % 4) as f32 * 3.0, (i / 4) as f32 * 3.0, -8.0);
+            let node = Node::builder().mesh(mesh).translation(translation).build();
+            let node = model.nodes.push(node);
+            model.root.children.push(node);
+        }
+        model
+    }
+
+    #[test]
+    fn serialize() {
+        let mut model = create_model();
+        let bvh = Bvh::builder()
+            .primitives(model.collect())
+            .hash(true)
+            .build(&model);
+        assert!(bvh.content_hash.is_some());
+        let data = bvh.serialize(&model);
+
+        // Primitives do not need to be in the same order
+        let mut primitives = model.collect();
+        primitives.reverse();
+        let restored = Bvh::deserialize(&model, primitives, &data).unwrap();
+        assert_eq!(restored.content_hash, bvh.content_hash);
+        assert_eq!(restored.nodes.len(), bvh.nodes.len());
+
+        for y in 0..16 {
+            for x in 0..16 {
+                let dir = Vec3::new(x as f32 - 4.0, y as f32 - 4.0, -8.0);
+                let a = bvh.raycast(&model, Point3::default(), dir, f32::MAX);
+                let b = restored.raycast(&model, Point3::default(), dir, f32::MAX);
+                assert!(a.map(|hit| hit.depth) == b.map(|hit| hit.depth));
+            }
+        }
+
+        let primitives = model.collect();
+        assert!(Bvh::deserialize(&model, primitives, &data[..data.len() - 1]).is_err());
+        let primitives = model.collect();
+        assert!(Bvh::deserialize(&model, primitives, b"invalid").is_err());
+
+        // A different scene
+        let mut other = create_model();
+        other.nodes.get_mut(Handle::new(0)).unwrap().trs.translation = Vec3::splat(1.0);
+        let primitives = other.collect();
+        assert!(Bvh::deserialize(&other, primitives, &data).is_err());
+    }
+
+    #[test]
+    fn cache() {
+        let dir = std::env::temp_dir().join(format!("rayca-bvh-cache-{}", std::process::id()));
+        let mut model = create_model();
+
+        let built = Bvh::builder()
+            .primitives(model.collect())
+            .cache(&dir)
+            .build(&model);
+        let path = dir.join(format!("{:016x}.bvh", built.content_hash.unwrap()));
+        assert!(path.exists());
+
+        let loaded = Bvh::builder()
+            .primitives(model.collect())
+            .cache(&dir)
+            .build(&model);
+        assert!(Bvh::builder()
+            .primitives(model.collect())
+            .build(&model)
+            .content_hash
+            .is_none());
+        assert_eq!(loaded.content_hash, built.content_hash);
+        assert_eq!(loaded.nodes.len(), built.nodes.len());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use crate::*;
+
+/// Cubic Bezier segment with a width varying along the curve, stored in world space.
+/// It is rendered as a thin tube, which is good enough for hair and fur.
+#[derive(Clone)]
+pub struct BvhCurve {
+    pub points: [Point3; 4],
+    pub widths: [f32; 4],
+    pub color: Color,
+}
+
+impl BvhCurve {
+    /// Number of linear pieces the curve is split into when intersecting it
+    pub const PIECES: usize = 8;
+
+    pub fn new(points: [Point3; 4], widths: [f32; 4]) -> Self {
+        Self {
+            points,
+            widths,
+            color: Color::white(),
+        }
+    }
+
+    fn basis(v: f32) -> [f32; 4] {
+        let u = 1.0 - v;
+        [u * u * u, 3.0 * u * u * v, 3.0 * u * v * v, v * v * v]
+    }
+
+    /// Returns the point of the curve at parameter `v` in `[0, 1]`
+    pub fn get_point(&self, v: f32) -> Point3 {
+        let basis = Self::basis(v);
+        let mut ret = Vec3::default();
+        for (point, b) in self.points.iter().zip(basis) {
+            ret += Vec3::from(*point) * b;
+        }
+        Point3::from(ret)
+    }
+
+    pub fn get_width(&self, v: f32) -> f32 {
+        let basis = Self::basis(v);
+        self.widths.iter().zip(basis).map(|(w, b)| w * b).sum()
+    }
+
+    /// Returns the normalized derivative of the curve at parameter `v`
+    pub fn get_tangent(&self, v: f32) -> Vec3 {
+        let u = 1.0 - v;
+        let [p0, p1, p2, p3] = self.points;
+        let d = (p1 - p0) * (3.0 * u * u) + (p2 - p1) * (6.0 * u * v) + (p3 - p2) * (3.0 * v * v);
+        d.get_normalized()
+    }
+
+    /// The normal points from the center of the tube towards the hit point
+    pub fn get_normal(&self, hit: &Hit) -> Vec3 {
+        let v = hit.uv.x;
+        let tangent = self.get_tangent(v);
+        let offset = hit.point - self.get_point(v);
+        (offset - tangent * tangent.dot(&offset)).get_normalized()
+    }
+
+    /// The curve lies within the convex hull of its control points
+    pub fn min(&self) -> Point3 {
+        let radius = self.widths.iter().fold(0.0f32, |a, b| a.max(*b)) * 0.5;
+        let min = self
+            .points
+            .iter()
+            .fold(Point3::new(f32::MAX, f32::MAX, f32::MAX), |a, b| a.min(b));
+        min - Vec3::splat(radius)
+    }
+
+    pub fn max(&self) -> Point3 {
+        let radius = self.widths.iter().fold(0.0f32, |a, b| a.max(*b)) * 0.5;
+        let max = self
+            .points
+            .iter()
+            .fold(Point3::new(f32::MIN, f32::MIN, f32::MIN), |a, b| a.max(b));
+        max + Vec3::splat(radius)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        self.get_point(0.5)
+    }
+
+    /// Splits the curve into linear pieces and finds the closest approach of the ray
+    /// to each of them. The ray hits when it passes closer than the local radius.
+    /// The returned hit stores the curve parameter in `uv.x`, and the distance from the
+    /// center relative to the radius in `uv.y`. Its point is moved on the surface of the
+    /// tube facing the ray, so that normals can be recovered from it.
+    pub fn intersects(&self, ray: &Ray) -> Option<Hit> {
+        let d = ray.dir;
+        let a = d.dot(&d);
+
+        let mut best: Option<(f32, f32, f32, Vec3)> = None;
+
+        let mut v0 = 0.0;
+        let mut p0 = self.points[0];
+        for i in 1..=Self::PIECES {
+            let v1 = i as f32 / Self::PIECES as f32;
+            let p1 = self.get_point(v1);
+
+            let u = p1 - p0;
+            let w0 = ray.origin - p0;
+            let b = d.dot(&u);
+            let c = u.dot(&u);
+            let dd = d.dot(&w0);
+            let e = u.dot(&w0);
+            let denom = a * c - b * b;
+
+            // Parameter of the closest point on the piece
+            let s = if denom.abs() > f32::EPSILON {
+                ((a * e - b * dd) / denom).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            // Parameter of the closest point on the ray
+            let t = (s * b - dd) / a;
+
+            let v = v0 + (v1 - v0) * s;
+            let radius = self.get_width(v) * 0.5;
+            let q = p0 + u * s;
+            let offset = (ray.origin + d * t) - q;
+            let distance = offset.len();
+
+            if t > 0.0 && distance < radius && best.is_none_or(|(best_t, ..)| t < best_t) {
+                best = Some((t, v, distance / radius, offset));
+            }
+
+            v0 = v1;
+            p0 = p1;
+        }
+
+        let (t, v, h, offset) = best?;
+
+        // Move the point from the center of the ribbon to the surface of the tube
+        let radius = self.get_width(v) * 0.5;
+        let tangent = self.get_tangent(v);
+        let mut towards = -d - tangent * tangent.dot(&-d);
+        towards.normalize();
+        let mut side = offset - tangent * tangent.dot(&offset);
+        if h > f32::EPSILON {
+            side.normalize();
+        }
+        let depth_offset = (1.0 - h * h).max(0.0).sqrt();
+        let point = self.get_point(v) + (side * h + towards * depth_offset) * radius;
+        let depth = (t - depth_offset * radius / a.sqrt()).max(0.0);
+
+        Some(Hit::new(depth, point, Vec2::new(v, h)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intersect() {
+        // Straight curve along the X axis
+        let curve = BvhCurve::new(
+            [
+                Point3::new(-1.0, 0.0, 0.0),
+                Point3::new(-0.5, 0.0, 0.0),
+                Point3::new(0.5, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+            ],
+            [0.2, 0.2, 0.1, 0.1],
+        );
+
+        let forward = Vec3::new(0.0, 0.0, -1.0);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), forward);
+        let hit = curve.intersects(&ray).unwrap();
+        assert!((hit.uv.x - 0.5).abs() < 1e-3);
+        assert!((hit.depth - (1.0 - curve.get_width(0.5) * 0.5)).abs() < 1e-3);
+        assert!(curve.get_normal(&hit).close(&Vec3::new(0.0, 0.0, 1.0)));
+
+        // Passing above the tip where the curve is thinner
+        let ray = Ray::new(Point3::new(0.9, 0.08, 1.0), forward);
+        assert!(curve.intersects(&ray).is_none());
+        let ray = Ray::new(Point3::new(-0.9, 0.08, 1.0), forward);
+        let hit = curve.intersects(&ray).unwrap();
+        assert!(curve.get_normal(&hit).get_y() > 0.0);
+    }
+}
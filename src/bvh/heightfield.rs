@@ -0,0 +1,246 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use crate::*;
+
+/// Möller–Trumbore ray-triangle intersection returning the distance along the ray
+fn intersects_triangle(ray: &Ray, a: &Point3, b: &Point3, c: &Point3) -> Option<f32> {
+    let ab = b - a;
+    let ac = c - a;
+    let p = ray.dir.cross(&ac);
+    let det = ab.dot(&p);
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let ao = ray.origin - a;
+    let u = ao.dot(&p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = ao.cross(&ab);
+    let v = ray.dir.dot(&q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = ac.dot(&q) * inv_det;
+    if t > 0.0 {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+/// Heightfield in model space, ready to be intersected
+#[derive(Clone)]
+pub struct BvhHeightfield {
+    pub heightfield: Heightfield,
+    min_height: f32,
+    max_height: f32,
+}
+
+impl BvhHeightfield {
+    pub fn new(heightfield: Heightfield) -> Self {
+        let min_height = heightfield.heights.iter().fold(f32::MAX, |a, b| a.min(*b));
+        let max_height = heightfield.heights.iter().fold(f32::MIN, |a, b| a.max(*b));
+        Self {
+            heightfield,
+            min_height,
+            max_height,
+        }
+    }
+
+    fn half_extent(&self) -> (f32, f32) {
+        let hf = &self.heightfield;
+        (
+            (hf.width - 1) as f32 * hf.cell_size * 0.5,
+            (hf.depth - 1) as f32 * hf.cell_size * 0.5,
+        )
+    }
+
+    fn get_point(&self, x: usize, z: usize) -> Point3 {
+        let hf = &self.heightfield;
+        let (half_x, half_z) = self.half_extent();
+        Point3::new(
+            x as f32 * hf.cell_size - half_x,
+            hf.get_height(x, z),
+            z as f32 * hf.cell_size - half_z,
+        )
+    }
+
+    /// Returns the corners of the bounding box in model space
+    pub fn corners(&self) -> [Point3; 8] {
+        let (x, z) = self.half_extent();
+        let (a, b) = (self.min_height, self.max_height);
+        [
+            Point3::new(-x, a, -z),
+            Point3::new(x, a, -z),
+            Point3::new(-x, b, -z),
+            Point3::new(x, b, -z),
+            Point3::new(-x, a, z),
+            Point3::new(x, a, z),
+            Point3::new(-x, b, z),
+            Point3::new(x, b, z),
+        ]
+    }
+
+    /// Bilinear interpolation of the heights at grid coordinates
+    fn get_height(&self, gx: f32, gz: f32) -> f32 {
+        let hf = &self.heightfield;
+        let gx = gx.clamp(0.0, (hf.width - 1) as f32);
+        let gz = gz.clamp(0.0, (hf.depth - 1) as f32);
+        let x = (gx as usize).min(hf.width - 2);
+        let z = (gz as usize).min(hf.depth - 2);
+        let (fx, fz) = (gx - x as f32, gz - z as f32);
+        let top = hf.get_height(x, z) * (1.0 - fx) + hf.get_height(x + 1, z) * fx;
+        let bottom = hf.get_height(x, z + 1) * (1.0 - fx) + hf.get_height(x + 1, z + 1) * fx;
+        top * (1.0 - fz) + bottom * fz
+    }
+
+    /// Returns the normal in model space, from the height gradient at the hit point.
+    /// The hit stores the position over the grid in `uv`, from zero to one
+    pub fn get_normal(&self, hit: &Hit) -> Vec3 {
+        let hf = &self.heightfield;
+        let gx = hit.uv.x * (hf.width - 1) as f32;
+        let gz = hit.uv.y * (hf.depth - 1) as f32;
+        let dx = (self.get_height(gx + 0.5, gz) - self.get_height(gx - 0.5, gz)) / hf.cell_size;
+        let dz = (self.get_height(gx, gz + 0.5) - self.get_height(gx, gz - 0.5)) / hf.cell_size;
+        Vec3::new(-dx, 1.0, -dz).get_normalized()
+    }
+
+    /// Walks the cells crossed by the ray with a 2D-DDA, testing the two triangles
+    /// of a cell only when the ray passes within the heights of its corners.
+    /// Ray should be in model space
+    pub fn intersects(&self, ray: &Ray) -> Option<Hit> {
+        let hf = &self.heightfield;
+        let (half_x, half_z) = self.half_extent();
+
+        // Clip the ray against the bounding box
+        let mut t_enter = 0.0f32;
+        let mut t_exit = f32::MAX;
+        let origin = [ray.origin.get_x(), ray.origin.get_y(), ray.origin.get_z()];
+        let dir = [ray.dir.get_x(), ray.dir.get_y(), ray.dir.get_z()];
+        let min = [-half_x, self.min_height, -half_z];
+        let max = [half_x, self.max_height, half_z];
+        for axis in 0..3 {
+            if dir[axis].abs() < f32::EPSILON {
+                if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (min[axis] - origin[axis]) / dir[axis];
+            let t1 = (max[axis] - origin[axis]) / dir[axis];
+            t_enter = t_enter.max(t0.min(t1));
+            t_exit = t_exit.min(t0.max(t1));
+        }
+        if t_enter > t_exit {
+            return None;
+        }
+
+        // Grid coordinates of the entry point
+        let gx = (origin[0] + dir[0] * t_enter + half_x) / hf.cell_size;
+        let gz = (origin[2] + dir[2] * t_enter + half_z) / hf.cell_size;
+        let mut x = (gx.max(0.0) as usize).min(hf.width - 2) as isize;
+        let mut z = (gz.max(0.0) as usize).min(hf.depth - 2) as isize;
+
+        let step_x: isize = if dir[0] >= 0.0 { 1 } else { -1 };
+        let step_z: isize = if dir[2] >= 0.0 { 1 } else { -1 };
+        let delta_x = (hf.cell_size / dir[0]).abs();
+        let delta_z = (hf.cell_size / dir[2]).abs();
+        let next_boundary = |cell: isize, step: isize, o: f32, d: f32, half: f32| {
+            if d.abs() < f32::EPSILON {
+                return f32::MAX;
+            }
+            let boundary = (cell + step.max(0)) as f32 * hf.cell_size - half;
+            (boundary - o) / d
+        };
+        let mut t_max_x = next_boundary(x, step_x, origin[0], dir[0], half_x);
+        let mut t_max_z = next_boundary(z, step_z, origin[2], dir[2], half_z);
+
+        let mut t_cell = t_enter;
+        while x >= 0 && z >= 0 && x < hf.width as isize - 1 && z < hf.depth as isize - 1 {
+            let t_next = t_max_x.min(t_max_z).min(t_exit);
+
+            // Skip the cell when the ray stays above or below its corners
+            let (ux, uz) = (x as usize, z as usize);
+            let corners = [
+                self.get_point(ux, uz),
+                self.get_point(ux + 1, uz),
+                self.get_point(ux, uz + 1),
+                self.get_point(ux + 1, uz + 1),
+            ];
+            let cell_min = corners.iter().fold(f32::MAX, |a, p| a.min(p.get_y()));
+            let cell_max = corners.iter().fold(f32::MIN, |a, p| a.max(p.get_y()));
+            let y0 = origin[1] + dir[1] * t_cell;
+            let y1 = origin[1] + dir[1] * t_next;
+            if y0.min(y1) <= cell_max && y0.max(y1) >= cell_min {
+                let t = [
+                    intersects_triangle(ray, &corners[0], &corners[1], &corners[3]),
+                    intersects_triangle(ray, &corners[0], &corners[3], &corners[2]),
+                ]
+                .iter()
+                .flatten()
+                .copied()
+                .reduce(f32::min);
+
+                if let Some(t) = t {
+                    let point = ray.origin + ray.dir * t;
+                    let uv = Vec2::new(
+                        (point.get_x() + half_x) / (2.0 * half_x),
+                        (point.get_z() + half_z) / (2.0 * half_z),
+                    );
+                    return Some(Hit::new(t, point, uv));
+                }
+            }
+
+            if t_next >= t_exit {
+                break;
+            }
+            t_cell = t_next;
+            if t_max_x < t_max_z {
+                x += step_x;
+                t_max_x += delta_x;
+            } else {
+                z += step_z;
+                t_max_z += delta_z;
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intersect() {
+        // A ridge along Z in the middle of a 3x3 grid
+        let heights = vec![0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0];
+        let heightfield = BvhHeightfield::new(Heightfield::new(3, 3, heights, 1.0));
+
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        let ray = Ray::new(Point3::new(0.0, 2.0, 0.0), down);
+        let hit = heightfield.intersects(&ray).unwrap();
+        assert!((hit.depth - 1.0).abs() < 1e-4);
+        assert!((hit.uv.x - 0.5).abs() < 1e-4);
+
+        let ray = Ray::new(Point3::new(0.5, 2.0, 0.0), down);
+        let hit = heightfield.intersects(&ray).unwrap();
+        assert!((hit.depth - 1.5).abs() < 1e-4);
+        let normal = heightfield.get_normal(&hit);
+        assert!(normal.get_x() > 0.0 && normal.get_y() > 0.0);
+
+        // Grazing ray travelling across the ridge
+        let ray = Ray::new(Point3::new(-2.0, 0.5, 0.3), Vec3::new(1.0, 0.0, 0.0));
+        let hit = heightfield.intersects(&ray).unwrap();
+        assert!((hit.point.get_x() + 0.5).abs() < 1e-4);
+
+        // Missing the terrain
+        let ray = Ray::new(Point3::new(0.0, 2.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(heightfield.intersects(&ray).is_none());
+    }
+}
@@ -2,14 +2,24 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
+mod cache;
+mod curve;
+mod heightfield;
 mod light;
 mod primitive;
+mod quantized;
+mod sdf;
 mod sphere;
 mod structure;
 mod triangle;
 
+pub use cache::*;
+pub use curve::*;
+pub use heightfield::*;
 pub use light::*;
 pub use primitive::*;
+pub use quantized::*;
+pub use sdf::*;
 pub use sphere::*;
 pub use structure::*;
 pub use triangle::*;
@@ -4,59 +4,63 @@
 
 use crate::*;
 
+#[derive(Clone)]
 pub enum BvhGeometry {
     Triangle(Box<BvhTriangle>),
     Sphere(BvhSphere),
+    Curve(Box<BvhCurve>),
+    Heightfield(Box<BvhHeightfield>),
+    Sdf(Box<BvhSdf>),
 }
 
 impl BvhGeometry {
     pub fn get_color(&self, hit: &Hit) -> Color {
         match self {
             BvhGeometry::Triangle(triangle) => triangle.interpolate_colors(&hit.uv),
-            BvhGeometry::Sphere(_) => Color::white(),
+            BvhGeometry::Sphere(sphere) => sphere.color,
+            BvhGeometry::Curve(curve) => curve.color,
+            BvhGeometry::Heightfield(_) | BvhGeometry::Sdf(_) => Color::white(),
         }
     }
 
-    pub fn get_uv(&self, hit: &Hit) -> Vec2 {
+    /// Returns how texture coordinates change when moving to the next pixel
+    pub fn get_uv_derivatives(&self, hit: &Hit) -> (Vec2, Vec2) {
         match self {
-            BvhGeometry::Triangle(triangle) => triangle.interpolate_uvs(&hit.uv),
-            // TODO spherical coordinates?
-            BvhGeometry::Sphere(_) => Vec2::default(),
-        }
-    }
-
-    pub fn get_normal(&self, hit: &Hit) -> Vec3 {
-        match self {
-            BvhGeometry::Triangle(triangle) => triangle.interpolate_normals(&hit.uv),
-            BvhGeometry::Sphere(sphere) => sphere.get_normal(&hit.point),
-        }
-    }
-
-    pub fn get_tangent(&self, hit: &Hit) -> Vec3 {
-        match self {
-            BvhGeometry::Triangle(triangle) => triangle.interpolate_tangents(&hit.uv),
-            BvhGeometry::Sphere(_) => Vec3::default(),
+            BvhGeometry::Triangle(triangle) => triangle.get_uv_derivatives(hit.dpdx, hit.dpdy),
+            BvhGeometry::Sphere(_)
+            | BvhGeometry::Curve(_)
+            | BvhGeometry::Heightfield(_)
+            | BvhGeometry::Sdf(_) => (Vec2::default(), Vec2::default()),
         }
     }
+}
 
-    pub fn get_bitangent(&self, hit: &Hit) -> Vec3 {
-        match &self {
-            BvhGeometry::Triangle(triangle) => triangle.interpolate_bitangents(&hit.uv),
-            BvhGeometry::Sphere(_) => Vec3::default(),
-        }
-    }
+/// Returns the corners of the box from `a` to `b`, which are needed to bound the box
+/// once rotated
+fn get_corners(a: Point3, b: Point3) -> [Point3; 8] {
+    [0, 1, 2, 3, 4, 5, 6, 7].map(|i| {
+        Point3::new(
+            if i & 1 == 0 { a.get_x() } else { b.get_x() },
+            if i & 2 == 0 { a.get_y() } else { b.get_y() },
+            if i & 4 == 0 { a.get_z() } else { b.get_z() },
+        )
+    })
 }
 
+#[derive(Clone)]
 pub struct BvhPrimitive {
     pub geometry: BvhGeometry,
     pub node: Handle<Node>,
 
+    /// Primitive of the model this was generated from
+    pub primitive: Handle<Primitive>,
+
     /// We store handle and model here as we will anyway need to use the model
     /// when quering material properties such as textures.
     pub material: Handle<Material>,
 }
 
-const WHITE_MATERIAL: Material = Material {
+static WHITE_MATERIAL: Material = Material {
     color: Color {
         r: 1.0,
         g: 1.0,
@@ -64,10 +68,25 @@ const WHITE_MATERIAL: Material = Material {
         a: 1.0,
     },
     albedo_texture: Handle::NONE,
+    albedo_transform: TextureTransform::IDENTITY,
     normal_texture: Handle::NONE,
+    normal_transform: TextureTransform::IDENTITY,
     metallic_factor: 1.0,
     roughness_factor: 1.0,
     metallic_roughness_texture: Handle::NONE,
+    metallic_roughness_transform: TextureTransform::IDENTITY,
+    occlusion_texture: Handle::NONE,
+    occlusion_transform: TextureTransform::IDENTITY,
+    occlusion_strength: 1.0,
+    displacement_texture: Handle::NONE,
+    displacement_scale: 1.0,
+    vertex_color: true,
+    mix: None,
+    conductor: None,
+    shadow_catcher: false,
+    graph: None,
+    bevel_radius: 0.0,
+    extras: Extras::new(),
 };
 
 impl BvhPrimitive {
@@ -75,6 +94,7 @@ impl BvhPrimitive {
         Self {
             geometry,
             node,
+            primitive: Handle::NONE,
             material,
         }
     }
@@ -82,19 +102,51 @@ impl BvhPrimitive {
     pub fn centroid(&self, model: &Model) -> Point3 {
         match &self.geometry {
             BvhGeometry::Triangle(triangle) => triangle.centroid,
+            BvhGeometry::Curve(curve) => curve.centroid(),
             BvhGeometry::Sphere(sphere) => {
                 let trs = model.solved_trs.get(&self.node).unwrap();
                 &trs.trs * sphere.center
             }
+            BvhGeometry::Heightfield(heightfield) => {
+                let trs = model.solved_trs.get(&self.node).unwrap();
+                let [a, .., b] = heightfield.corners();
+                &trs.trs * Point3::from((Vec3::from(a) + Vec3::from(b)) * 0.5)
+            }
+            BvhGeometry::Sdf(sdf) => {
+                let trs = model.solved_trs.get(&self.node).unwrap();
+                &trs.trs * Point3::from((Vec3::from(sdf.min()) + Vec3::from(sdf.max())) * 0.5)
+            }
         }
     }
 
     pub fn min(&self, model: &Model) -> Point3 {
         match &self.geometry {
             BvhGeometry::Triangle(triangle) => triangle.min(),
+            BvhGeometry::Curve(curve) => curve.min(),
             BvhGeometry::Sphere(sphere) => {
                 let trs = model.solved_trs.get(&self.node).unwrap();
-                &trs.trs * sphere.min()
+                get_corners(sphere.min(), sphere.max())
+                    .iter()
+                    .fold(Point3::new(f32::MAX, f32::MAX, f32::MAX), |a, b| {
+                        a.min(&(&trs.trs * *b))
+                    })
+            }
+            BvhGeometry::Heightfield(heightfield) => {
+                let trs = model.solved_trs.get(&self.node).unwrap();
+                heightfield
+                    .corners()
+                    .iter()
+                    .fold(Point3::new(f32::MAX, f32::MAX, f32::MAX), |a, b| {
+                        a.min(&(&trs.trs * *b))
+                    })
+            }
+            BvhGeometry::Sdf(sdf) => {
+                let trs = model.solved_trs.get(&self.node).unwrap();
+                get_corners(sdf.min(), sdf.max())
+                    .iter()
+                    .fold(Point3::new(f32::MAX, f32::MAX, f32::MAX), |a, b| {
+                        a.min(&(&trs.trs * *b))
+                    })
             }
         }
     }
@@ -102,31 +154,91 @@ impl BvhPrimitive {
     pub fn max(&self, model: &Model) -> Point3 {
         match &self.geometry {
             BvhGeometry::Triangle(triangle) => triangle.max(),
+            BvhGeometry::Curve(curve) => curve.max(),
             BvhGeometry::Sphere(sphere) => {
                 let trs = model.solved_trs.get(&self.node).unwrap();
-                &trs.trs * sphere.max()
+                get_corners(sphere.min(), sphere.max())
+                    .iter()
+                    .fold(Point3::new(f32::MIN, f32::MIN, f32::MIN), |a, b| {
+                        a.max(&(&trs.trs * *b))
+                    })
+            }
+            BvhGeometry::Heightfield(heightfield) => {
+                let trs = model.solved_trs.get(&self.node).unwrap();
+                heightfield
+                    .corners()
+                    .iter()
+                    .fold(Point3::new(f32::MIN, f32::MIN, f32::MIN), |a, b| {
+                        a.max(&(&trs.trs * *b))
+                    })
+            }
+            BvhGeometry::Sdf(sdf) => {
+                let trs = model.solved_trs.get(&self.node).unwrap();
+                get_corners(sdf.min(), sdf.max())
+                    .iter()
+                    .fold(Point3::new(f32::MIN, f32::MIN, f32::MIN), |a, b| {
+                        a.max(&(&trs.trs * *b))
+                    })
+            }
+        }
+    }
+
+    /// Returns whether this primitive has non-finite `bounds`, which would spread to the
+    /// bounds of the BVH nodes containing it, or a surface which can never be hit
+    pub fn is_degenerate(&self, bounds: &AABB) -> bool {
+        let (min, max) = (bounds.a, bounds.b);
+        let finite = (0..3).all(|axis| min[axis].is_finite() && max[axis].is_finite());
+        if !finite {
+            return true;
+        }
+        match &self.geometry {
+            BvhGeometry::Triangle(triangle) => {
+                // A NaN vertex makes the area NaN as well
+                let [a, b, c] = &triangle.vertices;
+                let area = (b.pos - a.pos).cross(&(c.pos - a.pos)).len();
+                !area.is_finite() || area == 0.0
             }
+            BvhGeometry::Sphere(sphere) => sphere.get_radius() <= 0.0,
+            _ => false,
         }
     }
 
     pub fn intersects(&self, model: &Model, ray: &Ray) -> Option<Hit> {
         match &self.geometry {
             BvhGeometry::Triangle(triangle) => triangle.intersects(ray),
+            BvhGeometry::Curve(curve) => curve.intersects(ray),
             BvhGeometry::Sphere(sphere) => {
-                let ray = ray.clone();
-                let trs = model.solved_trs.get(&self.node).unwrap();
-                let inverse = Inversed::from(&trs.trs);
-                let inverse_ray = &inverse * ray;
-                let mut hit = sphere.intersects(&inverse_ray);
-                if let Some(hit) = hit.as_mut() {
-                    let transformed_point = hit.point;
-                    hit.point = &trs.trs * transformed_point;
-                }
-                hit
+                self.intersects_in_model_space(model, ray, |ray| sphere.intersects(ray))
+            }
+            BvhGeometry::Heightfield(heightfield) => {
+                self.intersects_in_model_space(model, ray, |ray| heightfield.intersects(ray))
+            }
+            BvhGeometry::Sdf(sdf) => {
+                self.intersects_in_model_space(model, ray, |ray| sdf.intersects(ray))
             }
         }
     }
 
+    /// Transforms the ray into model space before intersecting the geometry,
+    /// then transforms the hit point back into world space
+    fn intersects_in_model_space(
+        &self,
+        model: &Model,
+        ray: &Ray,
+        intersects: impl FnOnce(&Ray) -> Option<Hit>,
+    ) -> Option<Hit> {
+        let ray = ray.clone();
+        let trs = model.solved_trs.get(&self.node).unwrap();
+        let inverse = Inversed::from(&trs.trs);
+        let inverse_ray = &inverse * ray;
+        let mut hit = intersects(&inverse_ray);
+        if let Some(hit) = hit.as_mut() {
+            let transformed_point = hit.point;
+            hit.point = &trs.trs * transformed_point;
+        }
+        hit
+    }
+
     pub fn get_material<'m>(&self, model: &'m Model) -> &'m Material {
         let material = model
             .materials
@@ -136,47 +248,138 @@ impl BvhPrimitive {
     }
 
     pub fn get_color(&self, model: &Model, hit: &Hit) -> Color {
-        let geometry_color = self.geometry.get_color(hit);
-        let uv = self.geometry.get_uv(hit);
-        let material_color = self.get_material(model).get_color(model, &uv);
-        geometry_color * material_color
+        let (duvdx, duvdy) = self.geometry.get_uv_derivatives(hit);
+        let material = self.get_material(model);
+        let frame = hit.frame();
+        let material_color =
+            material.get_filtered_color(model, &frame.get_uvs(), duvdx, duvdy, frame.n_dot_v);
+        let color = if material.vertex_color {
+            self.geometry.get_color(hit) * material_color
+        } else {
+            material_color
+        };
+        model.working_space.from_srgb(color)
+    }
+
+    /// Interpolates the attributes of the surface at the hit point in world space.
+    /// This is done once by the BVH for the closest hit of shading queries, which stores
+    /// it into `hit.frame`
+    pub fn get_shading_frame(&self, model: &Model, hit: &Hit) -> ShadingFrame {
+        // Transforms a normal from model space to world space
+        let to_world = |normal: Vec3| {
+            let trs = model.solved_trs.get(&self.node).unwrap();
+            trs.transform_normal(normal)
+        };
+        // Surfaces without tangents get an arbitrary one
+        let from_normal = |normal: Vec3, geometric_normal: Vec3, uv: Vec2| {
+            let (tangent, bitangent) = normal.get_orthonormal_basis();
+            ShadingFrame {
+                normal,
+                tangent,
+                bitangent,
+                geometric_normal,
+                uv,
+                uv1: uv,
+                n_dot_v: 1.0,
+            }
+        };
+
+        match &self.geometry {
+            BvhGeometry::Triangle(triangle) => ShadingFrame {
+                normal: triangle.interpolate_normals(&hit.uv),
+                tangent: triangle.interpolate_tangents(&hit.uv),
+                bitangent: triangle.interpolate_bitangents(&hit.uv),
+                geometric_normal: triangle.get_geometric_normal(),
+                uv: triangle.interpolate_uvs(&hit.uv, 0),
+                uv1: triangle.interpolate_uvs(&hit.uv, 1),
+                n_dot_v: 1.0,
+            },
+            BvhGeometry::Sphere(sphere) => {
+                let trs = model.solved_trs.get(&self.node).unwrap();
+                let hit_point = &trs.get_inversed() * hit.point;
+                let normal = to_world(sphere.get_normal(&hit_point));
+                from_normal(normal, normal, Vec2::default())
+            }
+            BvhGeometry::Curve(curve) => {
+                let normal = curve.get_normal(hit);
+                let tangent = curve.get_tangent(hit.uv.x);
+                ShadingFrame {
+                    normal,
+                    tangent,
+                    bitangent: normal.cross(&tangent),
+                    geometric_normal: normal,
+                    uv: hit.uv,
+                    uv1: hit.uv,
+                    n_dot_v: 1.0,
+                }
+            }
+            BvhGeometry::Heightfield(heightfield) => {
+                let normal = to_world(heightfield.get_normal(hit));
+                from_normal(normal, normal, hit.uv)
+            }
+            BvhGeometry::Sdf(sdf) => {
+                let trs = model.solved_trs.get(&self.node).unwrap();
+                let hit_point = &trs.get_inversed() * hit.point;
+                let normal = to_world(sdf.get_normal(&hit_point));
+                from_normal(normal, normal, Vec2::default())
+            }
+        }
     }
 
+    /// Returns the shading normal, where the normal texture is applied.
+    /// It expects a hit returned by the BVH, which comes with its shading frame
     pub fn get_normal(&self, model: &Model, hit: &Hit) -> Vec3 {
+        let frame = hit.frame();
         match &self.geometry {
             BvhGeometry::Triangle(_) => {
-                let uv = self.geometry.get_uv(hit);
-                let normal = self.geometry.get_normal(hit);
-                let tangent = self.geometry.get_tangent(hit);
-                let bitangent = self.geometry.get_bitangent(hit);
+                let derivatives = self.geometry.get_uv_derivatives(hit);
                 let material = self.get_material(model);
-                material.get_normal(model, &uv, normal, tangent, bitangent)
-            }
-            BvhGeometry::Sphere(sphere) => {
-                let trs = model.solved_trs.get(&self.node).unwrap();
-                let inverse = trs.get_inversed();
-                let hit_point = &inverse * hit.point;
-                let normal = sphere.get_normal(&hit_point);
-                let normal_matrix = Mat3::from(&inverse).get_transpose();
-                (&normal_matrix * normal).get_normalized()
+                material.get_filtered_normal(
+                    model,
+                    &frame.get_uvs(),
+                    derivatives,
+                    frame.normal,
+                    frame.tangent,
+                    frame.bitangent,
+                )
             }
+            BvhGeometry::Sphere(_)
+            | BvhGeometry::Curve(_)
+            | BvhGeometry::Heightfield(_)
+            | BvhGeometry::Sdf(_) => frame.normal,
         }
     }
 
     pub fn get_metallic_roughness(&self, model: &Model, hit: &Hit) -> (f32, f32) {
         match &self.geometry {
-            BvhGeometry::Triangle(_) => {
+            BvhGeometry::Triangle(_)
+            | BvhGeometry::Curve(_)
+            | BvhGeometry::Heightfield(_)
+            | BvhGeometry::Sdf(_) => {
                 let material = self.get_material(model);
-                let uv = self.geometry.get_uv(hit);
-                material.get_metallic_roughness(model, &uv)
+                let frame = hit.frame();
+                material.get_metallic_roughness(model, &frame.get_uvs(), frame.n_dot_v)
             }
             // TODO remember to transform hit point into model sphere
             BvhGeometry::Sphere(_) => (1.0, 1.0),
         }
     }
 
+    /// Returns how much ambient light reaches the hit point, from 0 to 1
+    pub fn get_occlusion(&self, model: &Model, hit: &Hit) -> f32 {
+        self.get_material(model)
+            .get_occlusion(model, &hit.frame().get_uvs())
+    }
+
     /// Calculates the light coming out towards the viewer at a certain intersection
     pub fn get_radiance(&self, model: &Model, ir: &Irradiance) -> Color {
-        self.get_material(model).get_radiance(ir, model)
+        let material = self.get_material(model);
+        match &self.geometry {
+            BvhGeometry::Curve(curve) => {
+                let tangent = curve.get_tangent(ir.hit.uv.x);
+                material.get_hair_radiance(ir, &tangent, model)
+            }
+            _ => material.get_radiance(ir, model),
+        }
     }
 }
@@ -0,0 +1,301 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use crate::*;
+
+/// How BVH nodes store the bounds of their children
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BvhLayout {
+    /// Full precision floating point bounds
+    #[default]
+    Full,
+    /// Bounds quantized to 8 bits relative to the parent bounds
+    Quantized8,
+    /// Bounds quantized to 16 bits relative to the parent bounds
+    Quantized16,
+}
+
+/// Integer type used to store quantized coordinates
+pub trait Quantum: Copy + Default {
+    const MAX: u32;
+    fn from_u32(value: u32) -> Self;
+    fn to_u32(self) -> u32;
+}
+
+impl Quantum for u8 {
+    const MAX: u32 = u8::MAX as u32;
+    fn from_u32(value: u32) -> Self {
+        value as u8
+    }
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+}
+
+impl Quantum for u16 {
+    const MAX: u32 = u16::MAX as u32;
+    fn from_u32(value: u32) -> Self {
+        value as u16
+    }
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+}
+
+fn decode<Q: Quantum>(q: Q, min: f32, extent: f32) -> f32 {
+    min + q.to_u32() as f32 * extent / Q::MAX as f32
+}
+
+/// Quantizes a child box conservatively, so that the decoded box always contains it
+fn encode<Q: Quantum>(child: &AABB, parent: &AABB) -> [Q; 6] {
+    let mut ret = [Q::default(); 6];
+    for (i, axis) in [Axis3::X, Axis3::Y, Axis3::Z].iter().copied().enumerate() {
+        let min = parent.a[axis];
+        let extent = parent.b[axis] - min;
+        if extent <= 0.0 {
+            ret[i + 3] = Q::from_u32(Q::MAX);
+            continue;
+        }
+        let scale = Q::MAX as f32 / extent;
+
+        let mut a = (((child.a[axis] - min) * scale).floor().max(0.0) as u32).min(Q::MAX);
+        while a > 0 && decode(Q::from_u32(a), min, extent) > child.a[axis] {
+            a -= 1;
+        }
+        let mut b = (((child.b[axis] - min) * scale).ceil().max(0.0) as u32).min(Q::MAX);
+        while b < Q::MAX && decode(Q::from_u32(b), min, extent) < child.b[axis] {
+            b += 1;
+        }
+
+        ret[i] = Q::from_u32(a);
+        ret[i + 3] = Q::from_u32(b);
+    }
+    ret
+}
+
+/// Compressed BVH node storing the bounds of its two children relative to its own bounds,
+/// which are known while traversing from the root
+#[derive(Clone, Copy, Default)]
+pub struct QuantizedBvhNode<Q: Quantum> {
+    child_bounds: [[Q; 6]; 2],
+    /// Indices of the children, or zero for leaves as the root can not be a child
+    children: [u32; 2],
+    primitives: BvhRange<BvhPrimitive>,
+}
+
+impl<Q: Quantum> QuantizedBvhNode<Q> {
+    pub fn is_leaf(&self) -> bool {
+        self.children[0] == 0
+    }
+
+    /// Returns the bounds of a child given the bounds of this node
+    pub fn get_child_bounds(&self, child: usize, bounds: &AABB) -> AABB {
+        let q = &self.child_bounds[child];
+        let extent = bounds.b - bounds.a;
+        let min = |i: usize| decode(q[i], bounds.a.simd[i], extent.simd[i]);
+        let max = |i: usize| decode(q[i + 3], bounds.a.simd[i], extent.simd[i]);
+        AABB::new(
+            Point3::new(min(0), min(1), min(2)),
+            Point3::new(max(0), max(1), max(2)),
+        )
+    }
+
+    /// Converts a tree of full precision nodes into a list of quantized nodes, root first
+    pub fn from_nodes(root: &BvhNode, nodes: &Pack<BvhNode>) -> Vec<Self> {
+        let mut ret = vec![Self::default()];
+        Self::push_children(0, root, root.get_bounds(), nodes, &mut ret);
+        ret
+    }
+
+    fn push_children(
+        index: usize,
+        node: &BvhNode,
+        bounds: &AABB,
+        nodes: &Pack<BvhNode>,
+        ret: &mut Vec<Self>,
+    ) {
+        ret[index].primitives = node.get_primitives();
+        if node.is_leaf() {
+            return;
+        }
+
+        let children = [
+            nodes.get(node.get_left()).unwrap(),
+            nodes.get(node.get_right()).unwrap(),
+        ];
+        for (i, child) in children.iter().enumerate() {
+            ret[index].child_bounds[i] = encode(child.get_bounds(), bounds);
+            ret[index].children[i] = ret.len() as u32;
+            ret.push(Self::default());
+        }
+
+        for (i, child) in children.iter().enumerate() {
+            // Children are quantized relative to the decoded bounds, which are
+            // the ones available while traversing
+            let child_bounds = ret[index].get_child_bounds(i, bounds);
+            let child_index = ret[index].children[i] as usize;
+            Self::push_children(child_index, child, &child_bounds, nodes, ret);
+        }
+    }
+
+    /// Same as `Bvh::intersects_iter`, decoding the bounds of the nodes along the way
+    pub fn intersects<'b>(
+        nodes: &[Self],
+        root_bounds: &AABB,
+        primitives: &'b [BvhPrimitive],
+        model: &Model,
+        ray: &Ray,
+        stats: &Stats,
+    ) -> Option<(Hit, &'b BvhPrimitive)> {
+        let mut node = &nodes[0];
+        let mut bounds = AABB::new(root_bounds.a, root_bounds.b);
+        let mut stack = vec![];
+
+        let mut ret_hit = None;
+        let mut max_depth = f32::MAX;
+
+        let mut node_tests = 0;
+        let mut primitive_tests = 0;
+
+        loop {
+            if node.is_leaf() {
+                primitive_tests += node.primitives.len() as u64;
+                for pri_index in &node.primitives {
+                    let pri = &primitives[pri_index];
+                    if let Some(hit) = pri.intersects(model, ray) {
+                        if hit.depth < max_depth {
+                            max_depth = hit.depth;
+                            ret_hit = Some((hit, pri));
+                        }
+                    }
+                }
+                match stack.pop() {
+                    Some((next, next_bounds)) => {
+                        node = next;
+                        bounds = next_bounds;
+                    }
+                    None => break,
+                }
+                continue;
+            }
+
+            let mut child1 = (
+                &nodes[node.children[0] as usize],
+                node.get_child_bounds(0, &bounds),
+            );
+            let mut child2 = (
+                &nodes[node.children[1] as usize],
+                node.get_child_bounds(1, &bounds),
+            );
+            let mut dist1 = child1.1.intersects(ray);
+            let mut dist2 = child2.1.intersects(ray);
+            node_tests += 2;
+
+            if dist1 > dist2 {
+                std::mem::swap(&mut dist1, &mut dist2);
+                std::mem::swap(&mut child1, &mut child2);
+            }
+            if dist1 == f32::MAX {
+                match stack.pop() {
+                    Some((next, next_bounds)) => {
+                        node = next;
+                        bounds = next_bounds;
+                    }
+                    None => break,
+                }
+            } else {
+                node = child1.0;
+                bounds = child1.1;
+                if dist2 != f32::MAX {
+                    stack.push(child2);
+                }
+            }
+        }
+
+        stats.add_tests(node_tests, primitive_tests);
+        ret_hit
+    }
+}
+
+/// Nodes of a BVH built with a quantized layout
+pub enum QuantizedBvhNodes {
+    U8(Vec<QuantizedBvhNode<u8>>),
+    U16(Vec<QuantizedBvhNode<u16>>),
+}
+
+impl QuantizedBvhNodes {
+    pub fn new(layout: BvhLayout, root: &BvhNode, nodes: &Pack<BvhNode>) -> Option<Self> {
+        match layout {
+            BvhLayout::Full => None,
+            BvhLayout::Quantized8 => Some(Self::U8(QuantizedBvhNode::from_nodes(root, nodes))),
+            BvhLayout::Quantized16 => Some(Self::U16(QuantizedBvhNode::from_nodes(root, nodes))),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::U8(nodes) => nodes.len(),
+            Self::U16(nodes) => nodes.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Memory used by the nodes in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Self::U8(nodes) => std::mem::size_of_val(nodes.as_slice()),
+            Self::U16(nodes) => std::mem::size_of_val(nodes.as_slice()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn layouts() {
+        let mut model = Model::new();
+        let sphere = model.primitives.push(Primitive::unit_sphere());
+        let mesh = model.meshes.push(Mesh::new(vec![sphere]));
+        for i in 0..64 {
+            let translation = Vec3::new((i % 8) as f32 * 3.0, (i / 8) as f32 * 3.0, -8.0);
+            let node = Node::builder()
+                .mesh(mesh)
+                .translation(translation)
+                .scale(Vec3::splat(0.5 + (i % 3) as f32 * 0.3))
+                .build();
+            let node = model.nodes.push(node);
+            model.root.children.push(node);
+        }
+
+        let full = Bvh::builder().primitives(model.collect()).build(&model);
+        let full_size = std::mem::size_of::<BvhNode>() * (full.nodes.len() + 1);
+
+        for layout in [BvhLayout::Quantized8, BvhLayout::Quantized16] {
+            let bvh = Bvh::builder()
+                .primitives(model.collect())
+                .layout(layout)
+                .build(&model);
+            assert_eq!(bvh.get_layout(), layout);
+            assert!(bvh.nodes.is_empty());
+            let quantized = bvh.quantized.as_ref().unwrap();
+            assert_eq!(quantized.len(), full.nodes.len() + 1);
+            assert!(quantized.size() * 3 / 2 < full_size);
+
+            // Same hits as the full precision BVH
+            for y in 0..32 {
+                for x in 0..32 {
+                    let dir = Vec3::new(x as f32 - 8.0, y as f32 - 8.0, -8.0);
+                    let a = full.raycast(&model, Point3::default(), dir, f32::MAX);
+                    let b = bvh.raycast(&model, Point3::default(), dir, f32::MAX);
+                    assert!(a.map(|hit| hit.node.id) == b.map(|hit| hit.node.id));
+                }
+            }
+        }
+    }
+}
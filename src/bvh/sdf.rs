@@ -0,0 +1,135 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use crate::*;
+
+/// SDF in model space, ready to be intersected
+#[derive(Clone)]
+pub struct BvhSdf {
+    pub sdf: Sdf,
+    min: Point3,
+    max: Point3,
+}
+
+impl BvhSdf {
+    pub fn new(sdf: Sdf) -> Self {
+        let (min, max) = sdf.get_bounds();
+        Self { sdf, min, max }
+    }
+
+    pub fn min(&self) -> Point3 {
+        self.min
+    }
+
+    pub fn max(&self) -> Point3 {
+        self.max
+    }
+
+    /// Returns the normal in model space, from the gradient of the distance at `point`.
+    /// Point should be in model space
+    pub fn get_normal(&self, point: &Point3) -> Vec3 {
+        let h = self.sdf.epsilon.max(1e-4);
+        let mut gradient = Vec3::default();
+        for axis in 0..3 {
+            let mut offset = Vec3::default();
+            offset.simd[axis] = h;
+            gradient.simd[axis] = self.sdf.get_distance(&(*point + offset))
+                - self.sdf.get_distance(&(*point - offset));
+        }
+        gradient.get_normalized()
+    }
+
+    /// Sphere tracing: steps along the ray by the distance from the surface, which
+    /// never crosses it, until getting closer than epsilon. Ray should be in model space
+    pub fn intersects(&self, ray: &Ray) -> Option<Hit> {
+        // Clip the ray against the bounding box
+        let mut t_enter = 0.0f32;
+        let mut t_exit = f32::MAX;
+        for axis in 0..3 {
+            let origin = ray.origin[axis];
+            let dir = ray.dir.simd[axis];
+            if dir.abs() < f32::EPSILON {
+                if origin < self.min[axis] || origin > self.max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (self.min[axis] - origin) / dir;
+            let t1 = (self.max[axis] - origin) / dir;
+            t_enter = t_enter.max(t0.min(t1));
+            t_exit = t_exit.min(t0.max(t1));
+        }
+        if t_enter > t_exit {
+            return None;
+        }
+
+        // Rays in model space may be scaled, while distances are not
+        let dir_len = ray.dir.len();
+        let mut t = t_enter;
+        // Rays spawned from the surface start closer than epsilon to it,
+        // so their hits only count once the march has moved away from it
+        let mut left_surface = self.sdf.get_distance(&ray.origin).abs() >= self.sdf.epsilon;
+        for _ in 0..self.sdf.max_steps {
+            let point = ray.origin + ray.dir * t;
+            let distance = self.sdf.get_distance(&point);
+            if distance.abs() >= self.sdf.epsilon {
+                left_surface = true;
+            } else if left_surface {
+                return Some(Hit::new(t, point, Vec2::default()));
+            }
+            // Rays leaving from inside step towards the surface as well
+            t += distance.abs().max(self.sdf.epsilon) / dir_len;
+            if t > t_exit {
+                break;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intersect() {
+        let a = SdfExpr::sphere(Point3::new(-0.6, 0.0, 0.0), 0.5);
+        let b = SdfExpr::sphere(Point3::new(0.6, 0.0, 0.0), 0.5);
+        let sdf = BvhSdf::new(Sdf::expr(a.smooth_union(b, 0.5)));
+
+        // The blend fills the gap between the spheres
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        let hit = sdf
+            .intersects(&Ray::new(Point3::new(0.0, 2.0, 0.0), down))
+            .unwrap();
+        assert!(hit.depth > 1.5 && hit.depth < 2.0);
+        let normal = sdf.get_normal(&hit.point);
+        assert!(normal.close(&Vec3::new(0.0, 1.0, 0.0)));
+
+        let hit = sdf
+            .intersects(&Ray::new(Point3::new(0.6, 2.0, 0.0), down))
+            .unwrap();
+        assert!((hit.depth - 1.5).abs() < 1e-3);
+
+        // Scaled rays keep their depth along the ray
+        let hit = sdf
+            .intersects(&Ray::new(Point3::new(0.6, 2.0, 0.0), down * 0.5))
+            .unwrap();
+        assert!((hit.depth - 3.0).abs() < 2e-3);
+
+        // Rays spawned from a hit do not hit the same surface again
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let normal = sdf.get_normal(&hit.point);
+        let ray = Ray::spawn(&hit.point, &normal, up);
+        assert!(sdf.intersects(&ray).is_none());
+        let ray = Ray::spawn(&hit.point, &normal, -up);
+        let hit = sdf.intersects(&ray).unwrap();
+        assert!((hit.depth - 1.0).abs() < 1e-3);
+
+        let right = Vec3::new(1.0, 0.0, 0.0);
+        assert!(sdf
+            .intersects(&Ray::new(Point3::new(-2.0, 1.0, 0.0), right))
+            .is_none());
+    }
+}
@@ -4,10 +4,13 @@
 
 use crate::*;
 
+#[derive(Clone)]
 pub struct BvhSphere {
     pub center: Point3,
     radius: f32,
-    radius2: f32,
+
+    /// Spheres coming from point clouds have their own color
+    pub color: Color,
 }
 
 impl BvhSphere {
@@ -16,7 +19,7 @@ impl BvhSphere {
         Self {
             center,
             radius,
-            radius2: radius * radius,
+            color: Color::white(),
         }
     }
 
@@ -43,16 +46,20 @@ impl BvhSphere {
     /// Geometric formula.
     /// Ray should be in model space
     pub fn intersects(&self, ray: &Ray) -> Option<Hit> {
+        let dir = RealVec3::from(ray.dir);
+        let origin = RealVec3::from(ray.origin);
+
         // a = p1 * p1
-        let a = ray.dir.dot(&ray.dir);
+        let a = dir.dot(&dir);
 
         // b = 2(p1 * (p0 - c))
         // sphere center to ray origin vector
-        let c_to_r = ray.origin - self.center;
-        let b = 2.0 * c_to_r.dot(&ray.dir);
+        let c_to_r = origin - RealVec3::from(self.center);
+        let b = 2.0 * c_to_r.dot(&dir);
 
         // c = (p0 - c) * (p0 - c) - r^2
-        let c = c_to_r.dot(&c_to_r) - self.radius2;
+        let radius = to_real(self.radius);
+        let c = c_to_r.dot(&c_to_r) - radius * radius;
 
         // (-b +- sqrt(b^2 - 4ac) ) / 2a;
         let det = b * b - 4.0 * a * c;
@@ -77,8 +84,8 @@ impl BvhSphere {
             t1
         };
 
-        let point = ray.origin + ray.dir * t;
-        let hit = Hit::new(t, point, Vec2::default());
+        let point = Point3::from(origin + dir * t);
+        let hit = Hit::new(from_real(t), point, Vec2::default());
 
         Some(hit)
     }
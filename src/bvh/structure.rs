@@ -2,7 +2,9 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
-use std::{marker::PhantomData, ops::Range};
+use std::{marker::PhantomData, ops::Range, path::Path};
+
+use instant::Duration;
 
 use crate::*;
 
@@ -27,37 +29,8 @@ impl AABB {
         self.b = self.b.max(p);
     }
 
-    fn grow_triangle(&mut self, triangle: &BvhTriangle) {
-        self.grow(&triangle.vertices[0].pos);
-        self.grow(&triangle.vertices[1].pos);
-        self.grow(&triangle.vertices[2].pos);
-    }
-
-    fn grow_sphere(&mut self, sphere: &BvhSphere, trs: &Trs) {
-        let radius = sphere.get_radius();
-        let center = trs * sphere.center;
-        self.grow(&(center + Vec3::new(-radius, 0.0, 0.0)));
-        self.grow(&(center + Vec3::new(radius, 0.0, 0.0)));
-        self.grow(&(center + Vec3::new(0.0, -radius, 0.0)));
-        self.grow(&(center + Vec3::new(0.0, radius, 0.0)));
-        self.grow(&(center + Vec3::new(0.0, 0.0, -radius)));
-        self.grow(&(center + Vec3::new(0.0, 0.0, radius)));
-    }
-
-    fn grow_primitive(&mut self, model: &Model, primitive: &BvhPrimitive) {
-        match &primitive.geometry {
-            BvhGeometry::Triangle(triangle) => {
-                self.grow_triangle(triangle);
-            }
-            BvhGeometry::Sphere(sphere) => {
-                let trs = model.solved_trs.get(&primitive.node).unwrap();
-                self.grow_sphere(sphere, &trs.trs);
-            }
-        }
-    }
-
     /// Slab test. We do not care where we hit the box; only info we need is a yes/no answer.
-    fn intersects(&self, ray: &Ray) -> f32 {
+    pub fn intersects(&self, ray: &Ray) -> f32 {
         let origin_vec = Vec3::from(ray.origin);
         let t1 = (self.a - origin_vec) * ray.rdir;
         let t2 = (self.b - origin_vec) * ray.rdir;
@@ -76,13 +49,21 @@ impl AABB {
     }
 }
 
-#[derive(Clone, Copy)]
 pub struct BvhRange<T> {
     offset: u32,
     count: u32,
     phantom: PhantomData<T>,
 }
 
+// Derive would require T to be Copy as well
+impl<T> Clone for BvhRange<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for BvhRange<T> {}
+
 impl<T> Default for BvhRange<T> {
     fn default() -> Self {
         Self::new(0, 0)
@@ -106,6 +87,10 @@ impl<T> BvhRange<T> {
         self.count as usize
     }
 
+    pub fn get_offset(&self) -> u32 {
+        self.offset
+    }
+
     pub fn split_off(&mut self, offset: usize) -> Self {
         let o = offset as u32;
         let right_count = self.count - o;
@@ -147,182 +132,77 @@ impl BvhNode {
         Self::default()
     }
 
+    pub fn from_parts(
+        bounds: AABB,
+        left: Handle<BvhNode>,
+        right: Handle<BvhNode>,
+        primitives: BvhRange<BvhPrimitive>,
+    ) -> Self {
+        Self {
+            bounds,
+            left,
+            right,
+            primitives,
+        }
+    }
+
     pub fn is_leaf(&self) -> bool {
         self.left.is_none() && self.right.is_none()
     }
 
-    pub fn set_primitives(
-        &mut self,
-        model: &Model,
-        primitives_range: BvhRange<BvhPrimitive>,
-        primitives: &mut [BvhPrimitive],
-        max_depth: usize,
-        nodes: &mut Pack<BvhNode>,
-    ) {
-        let mut timer = Timer::new();
-        self.set_primitives_recursive(model, primitives_range, primitives, max_depth, 0, nodes);
-        print_success!("BVH", "built in {:.2}ms", timer.get_delta().as_millis());
+    pub fn get_bounds(&self) -> &AABB {
+        &self.bounds
     }
 
-    /// Surface Area Heuristics:
-    /// The cost of a split is proportional to the summed cost of intersecting the two
-    /// resulting boxes, including the triangles they store.
-    fn evaluate_sah(
-        &self,
-        model: &Model,
-        axis: Axis3,
-        pos: f32,
-        primitives: &[BvhPrimitive],
-    ) -> f32 {
-        // determine triangle counts and bounds for this split candidate
-        let mut left_box = AABB::default();
-        let mut right_box = AABB::default();
-        let mut left_count = 0;
-        let mut right_count = 0;
-
-        for pri_index in &self.primitives {
-            let pri = &primitives[pri_index];
-            let centroid = pri.centroid(model);
-            if centroid[axis] < pos {
-                left_count += 1;
-                left_box.grow_primitive(model, pri);
-            } else {
-                right_count += 1;
-                right_box.grow_primitive(model, pri);
-            }
-        }
-
-        let cost = left_count as f32 * left_box.area() + right_count as f32 * right_box.area();
-        if cost > 0.0 {
-            cost
-        } else {
-            f32::MAX
-        }
+    pub fn get_left(&self) -> Handle<BvhNode> {
+        self.left
     }
 
-    /// Finds the optimal split plane position and axis
-    /// - Returns (split axis, split pos, split cost)
-    fn find_best_split_plane(
-        &self,
-        model: &Model,
-        primitives: &[BvhPrimitive],
-    ) -> (Axis3, f32, f32) {
-        const ALL_AXIS: [Axis3; 3] = [Axis3::X, Axis3::Y, Axis3::Z];
-
-        let mut best_cost = f32::MAX;
-        let mut best_axis = Axis3::X;
-        let mut split_pos = 0.0;
-
-        for axis in ALL_AXIS {
-            let bounds_min = self.bounds.a[axis];
-            let bounds_max = self.bounds.b[axis];
-            if bounds_min == bounds_max {
-                continue;
-            }
-
-            // TODO tweak this
-            const AREA_COUNT: i32 = 64;
-            let scale = (bounds_max - bounds_min) / AREA_COUNT as f32;
-
-            for i in 1..AREA_COUNT {
-                let candidate_pos = bounds_min + i as f32 * scale;
-                let cost = self.evaluate_sah(model, axis, candidate_pos, primitives);
-                if cost < best_cost {
-                    best_cost = cost;
-                    best_axis = axis;
-                    split_pos = candidate_pos;
-                }
-            }
-        }
-
-        (best_axis, split_pos, best_cost)
+    pub fn get_right(&self) -> Handle<BvhNode> {
+        self.right
     }
 
-    fn calculate_cost(&self) -> f32 {
-        self.primitives.len() as f32 * self.bounds.area()
+    pub fn get_primitives(&self) -> BvhRange<BvhPrimitive> {
+        self.primitives
     }
 
-    fn set_primitives_recursive(
+    pub fn set_primitives(
         &mut self,
         model: &Model,
         primitives_range: BvhRange<BvhPrimitive>,
         primitives: &mut [BvhPrimitive],
         max_depth: usize,
-        level: usize,
         nodes: &mut Pack<BvhNode>,
     ) {
-        assert!(!primitives.is_empty());
-        self.primitives = primitives_range;
-
-        self.bounds.a = Point3::new(f32::MAX, f32::MAX, f32::MAX);
-        self.bounds.b = Point3::new(f32::MIN, f32::MIN, f32::MIN);
-
-        // Visit each vertex of the primitives to find the lowest and highest x, y, and z
-        for pri_index in &self.primitives {
-            let pri = &primitives[pri_index];
-            self.bounds.a = self.bounds.a.min(&pri.min(model));
-            self.bounds.b = self.bounds.b.max(&pri.max(model));
-        }
-
-        if level >= max_depth {
-            return;
-        }
-
-        // Surface Area Heuristics
-        let (split_axis, split_pos, split_cost) = self.find_best_split_plane(model, primitives);
-
-        let no_split_cost = self.calculate_cost();
-        if split_cost > no_split_cost {
-            return;
-        }
-
-        // Partition-in-place to obtain two groups of triangles on both sides of the split plane
-        let mut i = 0;
-        let mut j = self.primitives.len();
-        while i < j {
-            let index = self.primitives.offset as usize + i;
-
-            let centroid = primitives[index].centroid(model);
-            if centroid[split_axis] < split_pos {
-                i += 1;
-            } else {
-                let jndex = self.primitives.offset as usize + j;
-                primitives.swap(index, jndex - 1);
-                j -= 1;
-            }
-        }
-
-        // Create child nodes for each half
-        let left_count = i;
-        let right_count = self.primitives.len() - left_count;
-        if left_count > 0 && right_count > 0 {
-            let right_primitives = self.primitives.split_off(left_count);
-            let left_primitives = self.primitives.split_off(0);
-
-            // Create two nodes
-            let mut left_child = BvhNode::new();
-            left_child.set_primitives_recursive(
-                model,
-                left_primitives,
-                primitives,
-                max_depth,
-                level + 1,
-                nodes,
-            );
+        let range = primitives_range.offset as usize
+            ..primitives_range.offset as usize + primitives_range.len();
+        let mut infos = PrimitiveBounds::collect(model, &primitives[range.clone()]);
+        self.set_primitives_with_bounds(
+            primitives_range,
+            &mut primitives[range],
+            &mut infos,
+            max_depth,
+            nodes,
+        );
+    }
 
-            let mut right_child = BvhNode::new();
-            right_child.set_primitives_recursive(
-                model,
-                right_primitives,
-                primitives,
-                max_depth,
-                level + 1,
-                nodes,
-            );
+    /// Same as `set_primitives()` with the bounds of `primitives` computed already
+    fn set_primitives_with_bounds(
+        &mut self,
+        primitives_range: BvhRange<BvhPrimitive>,
+        primitives: &mut [BvhPrimitive],
+        infos: &mut [PrimitiveBounds],
+        max_depth: usize,
+        nodes: &mut Pack<BvhNode>,
+    ) {
+        let mut timer = Timer::new();
+        let tree = BuildNode::build(primitives_range, primitives, infos, max_depth, 0);
+        *self = tree.flatten(nodes);
+        print_success!("BVH", "built in {:.2}ms", timer.get_delta().as_millis());
+    }
 
-            self.left = nodes.push(left_child);
-            self.right = nodes.push(right_child);
-        }
+    fn calculate_cost(&self) -> f32 {
+        self.primitives.len() as f32 * self.bounds.area()
     }
 
     fn intersects<'b>(
@@ -376,9 +256,282 @@ impl BvhNode {
     }
 }
 
+/// Below this number of primitives, subtrees are built on the current thread
+/// as the overhead of spawning tasks would outweigh the benefits
+#[cfg(feature = "parallel")]
+const PARALLEL_THRESHOLD: usize = 4096;
+
+/// Bounds and centroid of a primitive, computed once per node while building
+struct PrimitiveBounds {
+    bounds: AABB,
+    centroid: Point3,
+}
+
+impl PrimitiveBounds {
+    fn new(model: &Model, primitive: &BvhPrimitive) -> Self {
+        Self {
+            bounds: AABB::new(primitive.min(model), primitive.max(model)),
+            centroid: primitive.centroid(model),
+        }
+    }
+
+    fn collect(model: &Model, primitives: &[BvhPrimitive]) -> Vec<Self> {
+        primitives
+            .iter()
+            .map(|primitive| Self::new(model, primitive))
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+    a: Point3,
+    b: Point3,
+    count: u32,
+}
+
+impl Default for Bin {
+    fn default() -> Self {
+        Self {
+            a: Point3::new(f32::MAX, f32::MAX, f32::MAX),
+            b: Point3::new(f32::MIN, f32::MIN, f32::MIN),
+            count: 0,
+        }
+    }
+}
+
+impl Bin {
+    fn grow(&mut self, bounds: &AABB) {
+        self.a = self.a.min(&bounds.a);
+        self.b = self.b.max(&bounds.b);
+    }
+
+    fn merge(&mut self, other: &Bin) {
+        self.a = self.a.min(&other.a);
+        self.b = self.b.max(&other.b);
+        self.count += other.count;
+    }
+
+    /// Surface Area Heuristics:
+    /// The cost of a split is proportional to the summed cost of intersecting the two
+    /// resulting boxes, including the triangles they store.
+    fn cost(&self) -> f32 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.count as f32 * AABB::new(self.a, self.b).area()
+        }
+    }
+}
+
+/// Number of intervals the centroid bounds are divided into on every axis
+const BIN_COUNT: usize = 32;
+
+/// Probe rays traced along every axis of the shading frame to round edges,
+/// see `Bvh::shade_hit()`
+const BEVEL_SAMPLES: u32 = 4;
+
+/// Binned SAH: primitives are put into bins according to their centroids, then the
+/// planes between bins are evaluated with prefix sums, all in one pass per axis.
+/// - Returns (split pos, split cost)
+fn find_best_split_on_axis(
+    axis: Axis3,
+    centroid_bounds: &AABB,
+    infos: &[PrimitiveBounds],
+) -> (f32, f32) {
+    let bounds_min = centroid_bounds.a[axis];
+    let bounds_max = centroid_bounds.b[axis];
+    if bounds_min == bounds_max {
+        return (0.0, f32::MAX);
+    }
+
+    let mut bins = [Bin::default(); BIN_COUNT];
+    let scale = BIN_COUNT as f32 / (bounds_max - bounds_min);
+    for info in infos {
+        let index = ((info.centroid[axis] - bounds_min) * scale) as usize;
+        let bin = &mut bins[index.min(BIN_COUNT - 1)];
+        bin.count += 1;
+        bin.grow(&info.bounds);
+    }
+
+    // Costs of the left side of every plane, sweeping from the left. Counts tell empty
+    // sides apart from sides of flat primitives, which cost nothing as well
+    let mut left_costs = [0.0; BIN_COUNT - 1];
+    let mut left_counts = [0; BIN_COUNT - 1];
+    let mut left = Bin::default();
+    for i in 0..BIN_COUNT - 1 {
+        left.merge(&bins[i]);
+        left_costs[i] = left.cost();
+        left_counts[i] = left.count;
+    }
+
+    // Then adding the costs of the right sides, sweeping from the right
+    let mut best = (0.0, f32::MAX);
+    let mut right = Bin::default();
+    for i in (1..BIN_COUNT).rev() {
+        right.merge(&bins[i]);
+        let plane = i - 1;
+        let cost = left_costs[plane] + right.cost();
+        if left_counts[plane] > 0 && right.count > 0 && cost < best.1 {
+            best = (bounds_min + i as f32 / scale, cost);
+        }
+    }
+    best
+}
+
+/// Finds the optimal split plane position and axis
+/// - Returns (split axis, split pos, split cost)
+fn find_best_split_plane(infos: &[PrimitiveBounds]) -> (Axis3, f32, f32) {
+    const ALL_AXIS: [Axis3; 3] = [Axis3::X, Axis3::Y, Axis3::Z];
+
+    let mut centroid_bounds = AABB::new(
+        Point3::new(f32::MAX, f32::MAX, f32::MAX),
+        Point3::new(f32::MIN, f32::MIN, f32::MIN),
+    );
+    for info in infos {
+        centroid_bounds.grow(&info.centroid);
+    }
+
+    let split_on_axis = |axis: Axis3| {
+        let (pos, cost) = find_best_split_on_axis(axis, &centroid_bounds, infos);
+        (axis, pos, cost)
+    };
+    let best = |a: (Axis3, f32, f32), b: (Axis3, f32, f32)| if b.2 < a.2 { b } else { a };
+    let none = (Axis3::X, 0.0, f32::MAX);
+
+    #[cfg(feature = "parallel")]
+    if infos.len() >= PARALLEL_THRESHOLD {
+        use rayon::prelude::*;
+        return ALL_AXIS
+            .par_iter()
+            .map(|axis| split_on_axis(*axis))
+            .reduce(|| none, best);
+    }
+
+    ALL_AXIS
+        .iter()
+        .map(|axis| split_on_axis(*axis))
+        .fold(none, best)
+}
+
+/// Node of a tree which is built in parallel before being flattened into a pack
+struct BuildNode {
+    node: BvhNode,
+    children: Option<Box<(BuildNode, BuildNode)>>,
+}
+
+impl BuildNode {
+    /// Builds the subtree of `primitives`, which are the ones in `range`, where `infos`
+    /// are their bounds and are reordered together with them
+    fn build(
+        range: BvhRange<BvhPrimitive>,
+        primitives: &mut [BvhPrimitive],
+        infos: &mut [PrimitiveBounds],
+        max_depth: usize,
+        level: usize,
+    ) -> Self {
+        assert!(!primitives.is_empty());
+        assert_eq!(primitives.len(), infos.len());
+        let mut node = BvhNode::new();
+        node.primitives = range;
+
+        node.bounds.a = Point3::new(f32::MAX, f32::MAX, f32::MAX);
+        node.bounds.b = Point3::new(f32::MIN, f32::MIN, f32::MIN);
+
+        for info in infos.iter() {
+            node.bounds.a = node.bounds.a.min(&info.bounds.a);
+            node.bounds.b = node.bounds.b.max(&info.bounds.b);
+        }
+
+        let leaf = |node| Self {
+            node,
+            children: None,
+        };
+
+        if level >= max_depth {
+            return leaf(node);
+        }
+
+        // Surface Area Heuristics
+        let (split_axis, split_pos, split_cost) = find_best_split_plane(infos);
+
+        let no_split_cost = node.calculate_cost();
+        if split_cost > no_split_cost {
+            return leaf(node);
+        }
+
+        // Partition-in-place to obtain two groups of triangles on both sides of the split plane
+        let mut i = 0;
+        let mut j = primitives.len();
+        while i < j {
+            if infos[i].centroid[split_axis] < split_pos {
+                i += 1;
+            } else {
+                primitives.swap(i, j - 1);
+                infos.swap(i, j - 1);
+                j -= 1;
+            }
+        }
+
+        // Create child nodes for each half
+        let left_count = i;
+        let right_count = primitives.len() - left_count;
+        if left_count == 0 || right_count == 0 {
+            return leaf(node);
+        }
+
+        let right_range = node.primitives.split_off(left_count);
+        let left_range = node.primitives.split_off(0);
+        let (left_primitives, right_primitives) = primitives.split_at_mut(left_count);
+        let (left_infos, right_infos) = infos.split_at_mut(left_count);
+
+        // Moving the slices out of the closures makes them callable once, like join requires
+        let build_left = move || {
+            let (primitives, infos) = (left_primitives, left_infos);
+            Self::build(left_range, primitives, infos, max_depth, level + 1)
+        };
+        let build_right = move || {
+            let (primitives, infos) = (right_primitives, right_infos);
+            Self::build(right_range, primitives, infos, max_depth, level + 1)
+        };
+
+        #[cfg(feature = "parallel")]
+        let (left, right) = if left_count + right_count >= PARALLEL_THRESHOLD {
+            rayon::join(build_left, build_right)
+        } else {
+            (build_left(), build_right())
+        };
+        #[cfg(not(feature = "parallel"))]
+        let (left, right) = (build_left(), build_right());
+
+        Self {
+            node,
+            children: Some(Box::new((left, right))),
+        }
+    }
+
+    /// Moves the children of the tree into `nodes`, returning the root
+    fn flatten(self, nodes: &mut Pack<BvhNode>) -> BvhNode {
+        let mut node = self.node;
+        if let Some(children) = self.children {
+            let (left, right) = *children;
+            let left = left.flatten(nodes);
+            let right = right.flatten(nodes);
+            node.left = nodes.push(left);
+            node.right = nodes.push(right);
+        }
+        node
+    }
+}
+
 pub struct BvhBuilder {
     primitives: Vec<BvhPrimitive>,
     max_depth: usize,
+    layout: BvhLayout,
+    cache: Option<BvhCache>,
+    nodes: Pack<BvhNode>,
+    skip_degenerate: bool,
+    hash: bool,
 }
 
 impl Default for BvhBuilder {
@@ -392,6 +545,11 @@ impl BvhBuilder {
         Self {
             primitives: vec![],
             max_depth: usize::MAX,
+            layout: BvhLayout::default(),
+            cache: None,
+            nodes: Pack::new(),
+            skip_degenerate: false,
+            hash: false,
         }
     }
 
@@ -405,17 +563,111 @@ impl BvhBuilder {
         self
     }
 
+    /// Quantized layouts use less memory, at the cost of slightly larger boxes
+    pub fn layout(mut self, layout: BvhLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Loads the BVH from a cache directory when it has been built already for the same
+    /// primitives, otherwise builds it and stores it there for subsequent runs.
+    /// Cached BVHs always have a `Bvh::content_hash`
+    pub fn cache<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.cache = Some(BvhCache::new(dir));
+        self
+    }
+
+    /// Whether to compute `Bvh::content_hash`, which needs another pass over the primitives
+    pub fn hash(mut self, hash: bool) -> Self {
+        self.hash = hash;
+        self
+    }
+
+    /// Storage for the nodes, usually taken from a `FrameArena` to reuse its memory
+    pub fn nodes(mut self, nodes: Pack<BvhNode>) -> Self {
+        self.nodes = nodes;
+        self
+    }
+
+    /// Leaves out primitives which can not be rendered, see `BvhPrimitive::is_degenerate()`.
+    /// Their number ends up in `Bvh::degenerate_count`
+    pub fn skip_degenerate(mut self, skip_degenerate: bool) -> Self {
+        self.skip_degenerate = skip_degenerate;
+        self
+    }
+
     pub fn build(self, model: &Model) -> Bvh {
-        Bvh::new(model, self.primitives, self.max_depth)
+        let mut primitives = self.primitives;
+        let mut infos = PrimitiveBounds::collect(model, &primitives);
+        let count = primitives.len();
+        if self.skip_degenerate {
+            let keep: Vec<bool> = primitives
+                .iter()
+                .zip(&infos)
+                .map(|(primitive, info)| !primitive.is_degenerate(&info.bounds))
+                .collect();
+            let mut keep_primitive = keep.iter();
+            primitives.retain(|_| *keep_primitive.next().unwrap());
+            let mut keep_info = keep.iter();
+            infos.retain(|_| *keep_info.next().unwrap());
+        }
+        let degenerate_count = count - primitives.len();
+
+        let max_depth = self.max_depth;
+        let mut bvh = match &self.cache {
+            Some(cache) => cache.get_or_build(model, primitives, max_depth),
+            None => {
+                let hash = self
+                    .hash
+                    .then(|| content_hash(model, &primitives, max_depth));
+                let mut bvh = Bvh::new_with_bounds(primitives, infos, max_depth, self.nodes);
+                bvh.content_hash = hash;
+                bvh
+            }
+        };
+        bvh.degenerate_count = degenerate_count;
+        bvh.set_layout(self.layout);
+        bvh
     }
 }
 
+/// Result of a ray query, with the information tools usually need about the hit surface
+pub struct RaycastHit {
+    /// Distance from the ray origin
+    pub depth: f32,
+    pub point: Point3,
+    pub normal: Vec3,
+
+    /// Texture coordinates of the hit point
+    pub uv: Vec2,
+
+    pub material: Handle<Material>,
+    pub node: Handle<Node>,
+    pub primitive: Handle<Primitive>,
+}
+
 pub struct Bvh {
     pub root: BvhNode,
     pub nodes: Pack<BvhNode>,
     pub triangle_count: usize,
 
     pub primitives: Vec<BvhPrimitive>,
+
+    /// Compressed nodes replacing `nodes` when built with a quantized layout
+    pub quantized: Option<QuantizedBvhNodes>,
+
+    /// Hash of the primitives this BVH was built for, see `content_hash()`.
+    /// Only computed when asked to the builder, see `BvhBuilder::hash()`
+    pub content_hash: Option<u64>,
+
+    /// Time spent building this BVH
+    pub build_time: Duration,
+
+    /// Counters updated while tracing rays against this BVH
+    pub stats: Stats,
+
+    /// Primitives left out by the builder, see `BvhBuilder::skip_degenerate()`
+    pub degenerate_count: usize,
 }
 
 impl Bvh {
@@ -423,34 +675,232 @@ impl Bvh {
         BvhBuilder::new()
     }
 
-    pub fn new(model: &Model, mut primitives: Vec<BvhPrimitive>, max_depth: usize) -> Self {
-        let mut nodes = Pack::new();
+    pub fn new(model: &Model, primitives: Vec<BvhPrimitive>, max_depth: usize) -> Self {
+        Self::new_with_nodes(model, primitives, max_depth, Pack::new())
+    }
+
+    /// Builds the BVH storing its nodes into `nodes`, which is cleared first
+    pub fn new_with_nodes(
+        model: &Model,
+        primitives: Vec<BvhPrimitive>,
+        max_depth: usize,
+        nodes: Pack<BvhNode>,
+    ) -> Self {
+        let infos = PrimitiveBounds::collect(model, &primitives);
+        Self::new_with_bounds(primitives, infos, max_depth, nodes)
+    }
+
+    /// Same as `new_with_nodes()` with the bounds of `primitives` computed already
+    fn new_with_bounds(
+        mut primitives: Vec<BvhPrimitive>,
+        mut infos: Vec<PrimitiveBounds>,
+        max_depth: usize,
+        mut nodes: Pack<BvhNode>,
+    ) -> Self {
+        let mut timer = Timer::new();
+        nodes.clear();
 
         let mut root = BvhNode::new();
         root.bounds = AABB::new(
             Point3::new(f32::MAX, f32::MAX, f32::MAX),
             Point3::new(f32::MIN, f32::MIN, f32::MIN),
         );
-        let range = BvhRange::new(0, primitives.len() as u32);
-        root.set_primitives(model, range, &mut primitives, max_depth, &mut nodes);
+        // An empty scene results in a leaf root with no primitives
+        if !primitives.is_empty() {
+            let range = BvhRange::new(0, primitives.len() as u32);
+            root.set_primitives_with_bounds(
+                range,
+                &mut primitives,
+                &mut infos,
+                max_depth,
+                &mut nodes,
+            );
+        }
 
         Self {
             root,
             nodes,
             triangle_count: 0,
             primitives,
+            quantized: None,
+            content_hash: None,
+            build_time: timer.get_delta(),
+            stats: Stats::new(),
+            degenerate_count: 0,
+        }
+    }
+
+    /// Converts the nodes to another layout. Quantized layouts replace the full precision
+    /// nodes, which can not be recovered afterwards
+    pub fn set_layout(&mut self, layout: BvhLayout) {
+        if self.quantized.is_some() || layout == BvhLayout::Full {
+            return;
+        }
+        self.quantized = QuantizedBvhNodes::new(layout, &self.root, &self.nodes);
+        self.nodes = Pack::new();
+    }
+
+    /// Gives the memory of primitives and nodes back to `arena`, for the next frame to reuse
+    pub fn recycle(self, arena: &mut FrameArena) {
+        arena.give(self.primitives);
+        arena.give(self.nodes);
+    }
+
+    pub fn get_layout(&self) -> BvhLayout {
+        match &self.quantized {
+            None => BvhLayout::Full,
+            Some(QuantizedBvhNodes::U8(_)) => BvhLayout::Quantized8,
+            Some(QuantizedBvhNodes::U16(_)) => BvhLayout::Quantized16,
+        }
+    }
+
+    /// Completes the closest hit with its shading frame, and with how its point changes
+    /// across pixels when the ray comes from a pixel
+    fn complete_hit<'b>(
+        &self,
+        model: &Model,
+        ray: &Ray,
+        closest: Option<(Hit, &'b BvhPrimitive)>,
+    ) -> Option<(Hit, &'b BvhPrimitive)> {
+        let (mut hit, primitive) = closest?;
+        let mut frame = primitive.get_shading_frame(model, &hit);
+        frame.n_dot_v = frame.normal.dot(-ray.dir).abs().min(1.0);
+        hit.frame = Some(frame);
+        if let Some(differential) = &ray.differential {
+            let normal = hit.frame().geometric_normal;
+            (hit.dpdx, hit.dpdy) = differential.transfer(ray, hit.depth, normal);
+        }
+        Some((hit, primitive))
+    }
+
+    /// Rounds the shading frame of `hit` when the material of `primitive` has a bevel
+    /// radius. Integrators call this only for hits they shade, as it traces probe rays
+    pub fn shade_hit(&self, model: &Model, hit: &mut Hit, primitive: &BvhPrimitive) {
+        let bevel_radius = primitive.get_material(model).bevel_radius;
+        if bevel_radius <= 0.0 {
+            return;
+        }
+        let normal = self.get_bevel_normal(model, hit, primitive, bevel_radius);
+        let Some(frame) = hit.frame.as_mut() else {
+            return;
+        };
+        // Tangents follow the rounded normal
+        let tangent = frame.tangent - normal * normal.dot(frame.tangent);
+        if tangent.len() > 0.0 {
+            frame.tangent = tangent.get_normalized();
+            let bitangent = normal.cross(&frame.tangent);
+            let sign = if bitangent.dot(frame.bitangent) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            frame.bitangent = bitangent * sign;
         }
+        frame.normal = normal;
     }
 
+    /// Returns the normal of `hit` blended with the normals of the surfaces of its node
+    /// within `radius`, which rounds the edges of hard-surface models without remodeling
+    /// them. Like the bevel shader of Cycles, probe rays cross disks around the hit along
+    /// the normal and the tangents, but through fixed points so that edges have no noise
+    pub fn get_bevel_normal(
+        &self,
+        model: &Model,
+        hit: &Hit,
+        primitive: &BvhPrimitive,
+        radius: f32,
+    ) -> Vec3 {
+        let normal = hit.frame().normal;
+        let (tangent, bitangent) = normal.get_orthonormal_basis();
+        let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+
+        let mut sum = Vec3::default();
+        for axis in [normal, tangent, -tangent, bitangent, -bitangent] {
+            let (u, v) = axis.get_orthonormal_basis();
+            for i in 0..BEVEL_SAMPLES {
+                let r = radius * ((i as f32 + 0.5) / BEVEL_SAMPLES as f32).sqrt();
+                let phi = golden_angle * i as f32;
+                let offset = u * (r * phi.cos()) + v * (r * phi.sin());
+                // Probes come from outside of the disk towards the surface
+                let probe = Ray::new(hit.point + offset + axis * radius, -axis);
+                let Some((probe_hit, probe_primitive)) = self.find_closest(model, &probe) else {
+                    continue;
+                };
+                if probe_primitive.node != primitive.node || probe_hit.depth > 2.0 * radius {
+                    continue;
+                }
+                // Closer surfaces weigh more, so that edges fade in smoothly
+                let distance = (probe_hit.point - hit.point).len();
+                let weight = (1.0 - distance / (2.0 * radius)).max(0.0);
+                let frame = probe_primitive.get_shading_frame(model, &probe_hit);
+                // Probes starting inside the surface see it from the back
+                if frame.geometric_normal.dot(axis) <= 0.0 {
+                    continue;
+                }
+                sum += frame.normal * weight;
+            }
+        }
+
+        if sum.len() > 0.0 {
+            sum.get_normalized()
+        } else {
+            normal
+        }
+    }
+
+    /// Returns the closest hit along `ray`, completed with what shading it needs
     pub fn intersects_iter(&self, model: &Model, ray: &Ray) -> Option<(Hit, &BvhPrimitive)> {
+        let closest = self.find_closest(model, ray);
+        self.complete_hit(model, ray, closest)
+    }
+
+    /// Returns the closest hit along `ray` without a shading frame, for shadow and
+    /// occlusion rays which only need to know what is in the way, and how far
+    pub fn intersects_occluder(&self, model: &Model, ray: &Ray) -> Option<(Hit, &BvhPrimitive)> {
+        self.find_closest(model, ray)
+    }
+
+    fn find_closest(&self, model: &Model, ray: &Ray) -> Option<(Hit, &BvhPrimitive)> {
+        match &self.quantized {
+            None => (),
+            Some(QuantizedBvhNodes::U8(nodes)) => {
+                let bounds = &self.root.bounds;
+                let primitives = &self.primitives;
+                return QuantizedBvhNode::intersects(
+                    nodes,
+                    bounds,
+                    primitives,
+                    model,
+                    ray,
+                    &self.stats,
+                );
+            }
+            Some(QuantizedBvhNodes::U16(nodes)) => {
+                let bounds = &self.root.bounds;
+                let primitives = &self.primitives;
+                return QuantizedBvhNode::intersects(
+                    nodes,
+                    bounds,
+                    primitives,
+                    model,
+                    ray,
+                    &self.stats,
+                );
+            }
+        }
+
         let mut node = &self.root;
         let mut stack = vec![];
 
         let mut ret_hit = None;
         let mut max_depth = f32::MAX;
 
+        let mut node_tests = 0;
+        let mut primitive_tests = 0;
+
         loop {
             if node.is_leaf() {
+                primitive_tests += node.primitives.len() as u64;
                 for pri_index in &node.primitives {
                     let pri = &self.primitives[pri_index];
                     if let Some(hit) = pri.intersects(model, ray) {
@@ -473,6 +923,7 @@ impl Bvh {
             let mut child2 = self.nodes.get(node.right).unwrap();
             let mut dist1 = child1.bounds.intersects(ray);
             let mut dist2 = child2.bounds.intersects(ray);
+            node_tests += 2;
 
             if dist1 > dist2 {
                 std::mem::swap(&mut dist1, &mut dist2);
@@ -492,12 +943,43 @@ impl Bvh {
             }
         }
 
+        self.stats.add_tests(node_tests, primitive_tests);
         ret_hit
     }
 
+    /// Finds the closest surface along a ray within `t_max` from its origin.
+    /// Useful for collision checks and tooling which do not care about integrators
+    pub fn raycast(
+        &self,
+        model: &Model,
+        origin: Point3,
+        dir: Vec3,
+        t_max: f32,
+    ) -> Option<RaycastHit> {
+        let ray = Ray::new(origin, dir.get_normalized());
+        let (hit, primitive) = self.intersects_iter(model, &ray)?;
+        if hit.depth > t_max {
+            return None;
+        }
+
+        Some(RaycastHit {
+            depth: hit.depth,
+            point: hit.point,
+            normal: primitive.get_normal(model, &hit),
+            uv: hit.frame().uv,
+            material: primitive.material,
+            node: primitive.node,
+            primitive: primitive.primitive,
+        })
+    }
+
     pub fn intersects(&self, model: &Model, ray: &Ray) -> Option<(Hit, &BvhPrimitive)> {
+        if self.quantized.is_some() {
+            return self.intersects_iter(model, ray);
+        }
         let mut triangle_count = 0;
-        self.root.intersects(model, ray, self, &mut triangle_count)
+        let closest = self.root.intersects(model, ray, self, &mut triangle_count);
+        self.complete_hit(model, ray, closest)
     }
 
     pub fn intersects_stats(
@@ -506,12 +988,17 @@ impl Bvh {
         ray: &Ray,
         triangle_count: &mut usize,
     ) -> Option<(Hit, &BvhPrimitive)> {
-        self.root.intersects(model, ray, self, triangle_count)
+        if self.quantized.is_some() {
+            return self.intersects_iter(model, ray);
+        }
+        let closest = self.root.intersects(model, ray, self, triangle_count);
+        self.complete_hit(model, ray, closest)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::{find_best_split_on_axis, PrimitiveBounds};
     use crate::*;
 
     #[test]
@@ -534,6 +1021,140 @@ mod test {
         assert!(!bvh.root.primitives.is_empty());
     }
 
+    #[test]
+    fn bevel() {
+        let mut model = Model::new();
+        let material = model.materials.push(Material {
+            bevel_radius: 0.1,
+            ..Material::new()
+        });
+        let cube = Sdf::expr(SdfExpr::cube(Point3::default(), Vec3::splat(1.0)));
+        let cube = Primitive::builder().sdf(cube).material(material).build();
+        let mesh = model
+            .meshes
+            .push(Mesh::new(vec![model.primitives.push(cube)]));
+        let node = model.nodes.push(Node::builder().mesh(mesh).build());
+        model.root.children.push(node);
+        let primitives = model.collect();
+        let bvh = Bvh::builder().primitives(primitives).build(&model);
+
+        // Faces keep their normal away from edges
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        let ray = Ray::new(Point3::new(0.0, 2.0, 0.0), down);
+        let (mut hit, primitive) = bvh.intersects_iter(&model, &ray).unwrap();
+        bvh.shade_hit(&model, &mut hit, primitive);
+        assert!(hit.frame().normal.close(&Vec3::new(0.0, 1.0, 0.0)));
+
+        // Normals bend towards the side face close to the edge
+        let ray = Ray::new(Point3::new(0.97, 2.0, 0.0), down);
+        let (mut hit, primitive) = bvh.intersects_iter(&model, &ray).unwrap();
+        let geometric_normal = hit.frame().normal;
+        bvh.shade_hit(&model, &mut hit, primitive);
+        let normal = hit.frame().normal;
+        assert!(normal.get_x() > 0.1 && normal.get_y() > normal.get_x());
+        assert!(hit.frame().tangent.dot(normal).abs() < 1e-5);
+
+        // Finding the hit alone leaves its normal as it is
+        assert!(geometric_normal.close(&Vec3::new(0.0, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn shading_frame() {
+        // Sphere squashed along Y, whose normals are not the direction from its center
+        let mut model = Model::new();
+        let sphere = model.primitives.push(Primitive::unit_sphere());
+        let mesh = model.meshes.push(Mesh::new(vec![sphere]));
+        let node = Node::builder()
+            .mesh(mesh)
+            .scale(Vec3::new(1.0, 0.5, 1.0))
+            .build();
+        let node = model.nodes.push(node);
+        model.root.children.push(node);
+        let primitives = model.collect();
+        let bvh = Bvh::builder().primitives(primitives).build(&model);
+
+        let dir = Vec3::new(0.0, -1.0, -1.0).get_normalized();
+        let ray = Ray::new(Point3::new(0.0, 2.0, 2.0), dir);
+        let (hit, primitive) = bvh.intersects_iter(&model, &ray).unwrap();
+        let frame = hit.frame();
+        assert!(frame.normal.close(&primitive.get_normal(&model, &hit)));
+        assert!(frame.normal.get_y() > frame.normal.get_z());
+        assert!(frame.tangent.dot(frame.normal).abs() < 1e-5);
+        assert!(frame.bitangent.dot(frame.normal).abs() < 1e-5);
+
+        // Occlusion queries hit the same point without building a frame
+        let (occluder, _) = bvh.intersects_occluder(&model, &ray).unwrap();
+        assert!(occluder.frame.is_none());
+        assert_eq!(occluder.depth, hit.depth);
+    }
+
+    #[test]
+    fn rotated_bounds() {
+        // Sphere stretched along X, then rotated to stretch along Y
+        let mut model = Model::new();
+        let sphere = model.primitives.push(Primitive::unit_sphere());
+        let mesh = model.meshes.push(Mesh::new(vec![sphere]));
+        let rotation = Quat::axis_angle(Vec3::new(0.0, 0.0, 1.0), std::f32::consts::FRAC_PI_2);
+        let node = Node::builder()
+            .mesh(mesh)
+            .rotation(rotation)
+            .scale(Vec3::new(2.0, 0.5, 1.0))
+            .build();
+        let node = model.nodes.push(node);
+        model.root.children.push(node);
+        let primitives = model.collect();
+        let min = Vec3::from(primitives[0].min(&model));
+        let max = Vec3::from(primitives[0].max(&model));
+        assert!(min.close(&Vec3::new(-0.5, -2.0, -1.0)));
+        assert!(max.close(&Vec3::new(0.5, 2.0, 1.0)));
+
+        // Normals at the tip along Y point up
+        let bvh = Bvh::builder().primitives(primitives).build(&model);
+        let ray = Ray::new(Point3::new(0.2, 4.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let (hit, _) = bvh.intersects_iter(&model, &ray).unwrap();
+        let expected = Vec3::new(0.2 / 0.25, hit.point.get_y() / 4.0, 0.0).get_normalized();
+        assert!(hit.frame().normal.close(&expected));
+    }
+
+    #[test]
+    fn vertex_color() {
+        let mut model = Model::new();
+        let mut vertices = vec![
+            Vertex::new(-1.0, -1.0, 0.0),
+            Vertex::new(1.0, -1.0, 0.0),
+            Vertex::new(0.0, 1.0, 0.0),
+        ];
+        for vertex in &mut vertices {
+            vertex.ext.color = Color::new(1.0, 0.0, 0.0, 1.0);
+        }
+        let material = model.materials.push(
+            Material::builder()
+                .color(Color::new(0.5, 0.5, 0.5, 1.0))
+                .build(),
+        );
+        let primitive = model.primitives.push(
+            Primitive::builder()
+                .vertices(vertices)
+                .indices(vec![0, 1, 2])
+                .material(material)
+                .build(),
+        );
+        let mesh = model.meshes.push(Mesh::new(vec![primitive]));
+        let node = model.nodes.push(Node::builder().mesh(mesh).build());
+        model.root.children.push(node);
+        let primitives = model.collect();
+        let bvh = Bvh::builder().primitives(primitives).build(&model);
+
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let (hit, primitive) = bvh.intersects_iter(&model, &ray).unwrap();
+        let color = primitive.get_color(&model, &hit);
+        assert_eq!((color.r, color.g), (0.5, 0.0));
+
+        model.materials.get_mut(material).unwrap().vertex_color = false;
+        let color = primitive.get_color(&model, &hit);
+        assert_eq!((color.r, color.g), (0.5, 0.5));
+    }
+
     #[test]
     fn two_children() {
         let mut model = Model::new();
@@ -565,4 +1186,107 @@ mod test {
         assert!(bvh.root.right.valid());
         assert!(bvh.root.primitives.is_empty());
     }
+
+    #[test]
+    fn large() {
+        // Enough triangles for subtrees to be built in parallel
+        const SIDE: usize = 96;
+        let mut vertices = vec![];
+        for y in 0..=SIDE {
+            for x in 0..=SIDE {
+                vertices.push(Vertex::new(x as f32, y as f32, (x * y % 7) as f32 * 0.1));
+            }
+        }
+        let mut indices: Vec<u32> = vec![];
+        for y in 0..SIDE as u32 {
+            for x in 0..SIDE as u32 {
+                let i = y * (SIDE as u32 + 1) + x;
+                let row = SIDE as u32 + 1;
+                indices.extend([i, i + 1, i + row + 1, i, i + row + 1, i + row]);
+            }
+        }
+
+        let mut model = Model::new();
+        let primitive = Primitive::builder()
+            .vertices(vertices)
+            .indices(indices.iter().flat_map(|i| i.to_le_bytes()).collect())
+            .index_size(4)
+            .build();
+        let mesh = Mesh::new(vec![model.primitives.push(primitive)]);
+        let node = model
+            .nodes
+            .push(Node::builder().mesh(model.meshes.push(mesh)).build());
+        model.root.children.push(node);
+        let primitives = model.collect();
+        let primitive_count = primitives.len();
+
+        let bvh = Bvh::builder().primitives(primitives).build(&model);
+
+        // Every primitive belongs to exactly one leaf
+        let mut leaf_primitives = 0;
+        for i in 0..bvh.nodes.len() {
+            let node = bvh.nodes.get(Handle::new(i)).unwrap();
+            if node.is_leaf() {
+                leaf_primitives += node.primitives.len();
+            }
+        }
+        assert_eq!(leaf_primitives, primitive_count);
+
+        for i in 0..SIDE {
+            let origin = Point3::new(i as f32 + 0.25, i as f32 + 0.5, 8.0);
+            let dir = Vec3::new(0.0, 0.0, -1.0);
+            assert!(bvh.raycast(&model, origin, dir, f32::MAX).is_some());
+        }
+    }
+
+    #[test]
+    fn raycast() {
+        let mut model = Model::new();
+        let mesh = Mesh::new(vec![model.primitives.push(Primitive::unit_sphere())]);
+        let node = model.nodes.push(
+            Node::builder()
+                .mesh(model.meshes.push(mesh))
+                .translation(Vec3::new(0.0, 0.0, -4.0))
+                .build(),
+        );
+        model.root.children.push(node);
+        let primitives = model.collect();
+        let bvh = Bvh::builder().primitives(primitives).build(&model);
+
+        let origin = Point3::default();
+        let dir = Vec3::new(0.0, 0.0, -1.0);
+        let hit = bvh.raycast(&model, origin, dir, f32::MAX).unwrap();
+        assert!((hit.depth - 3.0).abs() < 1e-3);
+        assert!(hit.normal.close(&Vec3::new(0.0, 0.0, 1.0)));
+        assert!(hit.node == node);
+
+        assert!(bvh.raycast(&model, origin, dir, 2.0).is_none());
+        assert!(bvh.raycast(&model, origin, -dir, f32::MAX).is_none());
+    }
+
+    #[test]
+    fn flat_split() {
+        // Points on the left have no area, yet splitting them from the boxes is the best
+        let info = |a: Point3, b: Point3| PrimitiveBounds {
+            bounds: AABB::new(a, b),
+            centroid: (a + Vec3::from(b)) * 0.5,
+        };
+        let point = Point3::new(0.0, 0.0, 0.0);
+        let infos = [
+            info(point, point),
+            info(point, point),
+            info(Point3::new(9.0, 0.0, 0.0), Point3::new(11.0, 1.0, 1.0)),
+            info(Point3::new(9.0, 0.0, 0.0), Point3::new(11.0, 1.0, 1.0)),
+        ];
+        let mut centroid_bounds = AABB::new(
+            Point3::new(f32::MAX, f32::MAX, f32::MAX),
+            Point3::new(f32::MIN, f32::MIN, f32::MIN),
+        );
+        for info in &infos {
+            centroid_bounds.grow(&info.centroid);
+        }
+        let (pos, cost) = find_best_split_on_axis(Axis3::X, &centroid_bounds, &infos);
+        assert!(pos > 0.0 && pos <= 10.0);
+        assert!(cost < f32::MAX);
+    }
 }
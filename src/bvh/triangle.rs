@@ -4,6 +4,7 @@
 
 use crate::*;
 
+#[derive(Clone)]
 pub struct BvhTriangle {
     pub vertices: [Vertex; 3],
     pub centroid: Point3,
@@ -41,10 +42,10 @@ impl BvhTriangle {
     }
 
     /// Returns the interpolation of the vertices uvs
-    pub fn interpolate_uvs(&self, hit_uv: &Vec2) -> Vec2 {
-        self.vertices[2].ext.uv * (1.0 - hit_uv.x - hit_uv.y)
-            + self.vertices[0].ext.uv * hit_uv.x
-            + self.vertices[1].ext.uv * hit_uv.y
+    pub fn interpolate_uvs(&self, hit_uv: &Vec2, set: usize) -> Vec2 {
+        self.vertices[2].ext.get_uv(set) * (1.0 - hit_uv.x - hit_uv.y)
+            + self.vertices[0].ext.get_uv(set) * hit_uv.x
+            + self.vertices[1].ext.get_uv(set) * hit_uv.y
     }
 
     /// Returns the interpolation of the vertices normals
@@ -73,11 +74,42 @@ impl BvhTriangle {
         b
     }
 
-    /// [Ray-triangle intersection](https://www.scratchapixel.com/lessons/3d-basic-rendering/ray-tracing-rendering-a-triangle/ray-triangle-intersection-geometric-solution)
-    pub fn intersects(&self, ray: &Ray) -> Option<Hit> {
+    pub fn get_geometric_normal(&self) -> Vec3 {
         let v0 = Vec3::from(self.vertices[0].pos);
         let v1 = Vec3::from(self.vertices[1].pos);
         let v2 = Vec3::from(self.vertices[2].pos);
+        (v1 - v0).cross(&(v2 - v0)).get_normalized()
+    }
+
+    /// Returns how texture coordinates change when the hit point moves by `dpdx` and `dpdy`,
+    /// expressing those offsets in terms of the barycentric coordinates of the triangle
+    pub fn get_uv_derivatives(&self, dpdx: Vec3, dpdy: Vec3) -> (Vec2, Vec2) {
+        let e1 = Vec3::from(self.vertices[0].pos) - Vec3::from(self.vertices[2].pos);
+        let e2 = Vec3::from(self.vertices[1].pos) - Vec3::from(self.vertices[2].pos);
+        let (a, b, c) = (e1.dot(e1), e1.dot(e2), e2.dot(e2));
+        let det = a * c - b * b;
+        if det.abs() < f32::EPSILON {
+            return (Vec2::default(), Vec2::default());
+        }
+
+        let uv2 = self.vertices[2].ext.uv;
+        let du1 = self.vertices[0].ext.uv - uv2;
+        let du2 = self.vertices[1].ext.uv - uv2;
+        let get_derivative = |dp: Vec3| {
+            let (p1, p2) = (e1.dot(dp), e2.dot(dp));
+            let b1 = (c * p1 - b * p2) / det;
+            let b2 = (a * p2 - b * p1) / det;
+            du1 * b1 + du2 * b2
+        };
+        (get_derivative(dpdx), get_derivative(dpdy))
+    }
+
+    /// [Ray-triangle intersection](https://www.scratchapixel.com/lessons/3d-basic-rendering/ray-tracing-rendering-a-triangle/ray-triangle-intersection-geometric-solution)
+    pub fn intersects(&self, ray: &Ray) -> Option<Hit> {
+        let v0 = RealVec3::from(self.vertices[0].pos);
+        let v1 = RealVec3::from(self.vertices[1].pos);
+        let v2 = RealVec3::from(self.vertices[2].pos);
+        let dir = RealVec3::from(ray.dir);
 
         // Plane's normal
         let v0v1 = v1 - v0;
@@ -86,25 +118,25 @@ impl BvhTriangle {
         let n = v0v1.cross(&v0v2);
 
         // Back-face test
-        if ray.dir.dot(n) > 0.0 {
+        if dir.dot(&n) > 0.0 {
             return None;
         }
 
-        let denom = n.dot(n);
+        let denom = n.dot(&n);
 
         // Step 1: finding P
 
         // Check if ray and plane are parallel
-        let n_dot_ray_dir = n.dot(ray.dir);
-        if n_dot_ray_dir.abs() < f32::EPSILON {
+        let n_dot_ray_dir = n.dot(&dir);
+        if n_dot_ray_dir.abs() < to_real(f32::EPSILON) {
             // Parallel do not intersect
             return None;
         }
         // Compute d parameter using equation 2
-        let d = -n.dot(v0);
+        let d = -n.dot(&v0);
 
         // Compute t (equation 3)
-        let t = -(n.dot(Vec3::from(ray.origin)) + d) / n_dot_ray_dir;
+        let t = -(n.dot(&RealVec3::from(ray.origin)) + d) / n_dot_ray_dir;
 
         // Check if the triangle is behind the ray
         if t < 0.0 {
@@ -112,39 +144,39 @@ impl BvhTriangle {
         }
 
         // Compute the intersection point using equation 1
-        let p = ray.origin + ray.dir * t;
+        let p = RealVec3::from(ray.origin) + dir * t;
 
         // Step 2: inside-outside test
 
         // Edge 0
         let edge0 = v1 - v0;
-        let vp0 = Vec3::from(p - v0);
+        let vp0 = p - v0;
         // Vector perpendicular to triangle's plane
         let c = edge0.cross(&vp0);
-        if n.dot(c) < 0.0 {
+        if n.dot(&c) < 0.0 {
             return None; // P is on the right side
         }
 
         // Edge 1
         let edge1 = v2 - v1;
-        let vp1 = Vec3::from(p - v1);
+        let vp1 = p - v1;
         let c = edge1.cross(&vp1);
-        let u = n.dot(c);
+        let u = n.dot(&c);
         if u < 0.0 {
             return None; // P is on the right side
         }
 
         // Edge 2
         let edge2 = v0 - v2;
-        let vp2 = Vec3::from(p - v2);
+        let vp2 = p - v2;
         let c = edge2.cross(&vp2);
-        let v = n.dot(c);
+        let v = n.dot(&c);
         if v < 0.0 {
             return None; // P is on the right side;
         }
 
-        let uv = Vec2::new(u / denom, v / denom);
-        let hit = Hit::new(t, p, uv);
+        let uv = Vec2::new(from_real(u / denom), from_real(v / denom));
+        let hit = Hit::new(from_real(t), Point3::from(p), uv);
         Some(hit) // This ray hits the triangle
     }
 }
@@ -170,4 +202,23 @@ mod test {
         let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, 1.0));
         assert!(triangle_ref.intersects(&model, &ray).is_none());
     }
+
+    #[test]
+    fn uv_derivatives() {
+        // Texture coordinates scaled by 2 along X
+        let mut vertices = [
+            Vertex::new(0.0, 0.0, 0.0),
+            Vertex::new(1.0, 0.0, 0.0),
+            Vertex::new(0.0, 1.0, 0.0),
+        ];
+        for vertex in &mut vertices {
+            vertex.ext.uv = Vec2::new(vertex.pos.get_x() * 2.0, vertex.pos.get_y());
+        }
+        let [a, b, c] = vertices;
+        let triangle = BvhTriangle::new(a, b, c);
+        let (duvdx, duvdy) =
+            triangle.get_uv_derivatives(Vec3::new(0.1, 0.0, 0.0), Vec3::new(0.0, 0.1, 0.0));
+        assert!((duvdx.x - 0.2).abs() < 1e-5 && duvdx.y.abs() < 1e-5);
+        assert!(duvdy.x.abs() < 1e-5 && (duvdy.y - 0.1).abs() < 1e-5);
+    }
 }
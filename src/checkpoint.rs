@@ -0,0 +1,244 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Samples accumulated by a render, which can be saved to disk while rendering
+//! and loaded back to resume a render which has been interrupted.
+
+use std::{error::Error, path::Path};
+
+use crate::*;
+
+const MAGIC: &[u8; 4] = b"RCKP";
+const VERSION: u32 = 1;
+
+/// Sum of the samples of a pixel, where colors are premultiplied by their alpha
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PixelSum {
+    pub sum: Color,
+
+    /// Number of samples which saw something
+    pub hits: u32,
+}
+
+impl PixelSum {
+    pub fn add(&mut self, sample: Option<Color>) {
+        if let Some(color) = sample {
+            self.sum.r += color.r * color.a;
+            self.sum.g += color.g * color.a;
+            self.sum.b += color.b * color.a;
+            self.sum.a += color.a;
+            self.hits += 1;
+        }
+    }
+
+    /// Returns the average of `sample_count` samples, where samples seeing nothing are
+    /// transparent, or `None` when none of them saw something
+    pub fn get(&self, sample_count: u32) -> Option<Color> {
+        if self.hits == 0 {
+            return None;
+        }
+        let a = self.sum.a / sample_count.max(1) as f32;
+        // Fully transparent samples keep their color, as shadow catchers do
+        let weight = if self.sum.a > 0.0 {
+            self.sum.a
+        } else {
+            self.hits as f32
+        };
+        Some(Color::new(
+            self.sum.r / weight,
+            self.sum.g / weight,
+            self.sum.b / weight,
+            a,
+        ))
+    }
+}
+
+/// Row of the image with the samples accumulated by its pixels
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tile {
+    /// Samples taken by every pixel of this tile
+    pub sample_count: u32,
+    pub pixels: Vec<PixelSum>,
+}
+
+/// Samples accumulated by the tiles of a render of a certain scene. See `Config::checkpoint`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Checkpoint {
+    pub width: u32,
+    pub height: u32,
+    pub seed: u64,
+
+    /// Hash of the primitives of the scene, see `Bvh::content_hash`
+    pub content_hash: u64,
+
+    pub tiles: Vec<Tile>,
+}
+
+impl Tile {
+    /// Returns a tile of `width` pixels without samples
+    pub fn new(width: u32) -> Self {
+        Self {
+            sample_count: 0,
+            pixels: vec![PixelSum::default(); width as usize],
+        }
+    }
+
+    /// Adds the samples of `other`, which follow the ones of this tile, as returned by
+    /// `Scene::render_samples()`
+    pub fn merge(&mut self, other: &Tile) {
+        for (pixel, other) in self.pixels.iter_mut().zip(&other.pixels) {
+            pixel.sum.r += other.sum.r;
+            pixel.sum.g += other.sum.g;
+            pixel.sum.b += other.sum.b;
+            pixel.sum.a += other.sum.a;
+            pixel.hits += other.hits;
+        }
+        self.sample_count = other.sample_count;
+    }
+
+    pub fn serialize_into(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.sample_count.to_le_bytes());
+        for pixel in &self.pixels {
+            let sum = &pixel.sum;
+            for value in [sum.r, sum.g, sum.b, sum.a] {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            data.extend_from_slice(&pixel.hits.to_le_bytes());
+        }
+    }
+
+    /// Reads a tile of `width` pixels serialized with `serialize_into()`
+    pub(crate) fn read(reader: &mut Reader, width: u32) -> Result<Self, Box<dyn Error>> {
+        let mut ret = Self::new(width);
+        ret.sample_count = reader.u32()?;
+        for pixel in &mut ret.pixels {
+            pixel.sum = Color::new(reader.f32()?, reader.f32()?, reader.f32()?, reader.f32()?);
+            pixel.hits = reader.u32()?;
+        }
+        Ok(ret)
+    }
+}
+
+impl Checkpoint {
+    pub fn new(width: u32, height: u32, seed: u64, content_hash: u64) -> Self {
+        let tile = Tile::new(width);
+        Self {
+            width,
+            height,
+            seed,
+            content_hash,
+            tiles: vec![tile; height as usize],
+        }
+    }
+
+    /// Whether the samples of this checkpoint can be added to a render with these parameters
+    pub fn matches(&self, width: u32, height: u32, seed: u64, content_hash: u64) -> bool {
+        self.width == width
+            && self.height == height
+            && self.seed == seed
+            && self.content_hash == content_hash
+    }
+
+    /// Returns the samples taken by all the pixels
+    pub fn get_sample_count(&self) -> u32 {
+        self.tiles
+            .iter()
+            .map(|tile| tile.sample_count)
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// Returns the average colors of the pixels, see `PixelSum::get()`
+    pub fn get_colors(&self) -> Vec<Option<Color>> {
+        self.tiles
+            .iter()
+            .flat_map(|tile| {
+                let sample_count = tile.sample_count;
+                tile.pixels.iter().map(move |pixel| pixel.get(sample_count))
+            })
+            .collect()
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut ret = vec![];
+        ret.extend_from_slice(MAGIC);
+        ret.extend_from_slice(&VERSION.to_le_bytes());
+        ret.extend_from_slice(&self.width.to_le_bytes());
+        ret.extend_from_slice(&self.height.to_le_bytes());
+        ret.extend_from_slice(&self.seed.to_le_bytes());
+        ret.extend_from_slice(&self.content_hash.to_le_bytes());
+        for tile in &self.tiles {
+            tile.serialize_into(&mut ret);
+        }
+        ret
+    }
+
+    /// Restores a checkpoint serialized with `serialize()`
+    pub fn deserialize(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = Reader { data };
+        if reader.bytes(4)? != MAGIC {
+            return Err("Not a checkpoint".into());
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(format!("Unsupported checkpoint version {}", version).into());
+        }
+        let width = reader.u32()?;
+        let height = reader.u32()?;
+        let seed = reader.u64()?;
+        let content_hash = reader.u64()?;
+
+        // Every pixel takes 20 bytes, which must be there before allocating them
+        let pixel_count = width as usize * height as usize;
+        if reader.data.len() != pixel_count * 20 + height as usize * 4 {
+            return Err("Checkpoint size mismatch".into());
+        }
+
+        let mut ret = Self::new(width, height, seed, content_hash);
+        for tile in &mut ret.tiles {
+            *tile = Tile::read(&mut reader, width)?;
+        }
+        Ok(ret)
+    }
+
+    /// Writes this checkpoint to a temporary file first, so that a render interrupted
+    /// while saving does not lose the previous checkpoint
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        let temp_path = path.with_extension("tmp");
+        std::fs::write(&temp_path, self.serialize())?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Self::deserialize(&std::fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serialize() {
+        let mut checkpoint = Checkpoint::new(3, 2, 7, 42);
+        checkpoint.tiles[1].sample_count = 4;
+        checkpoint.tiles[1].pixels[2].add(Some(Color::new(1.0, 0.5, 0.0, 1.0)));
+        checkpoint.tiles[1].pixels[2].add(None);
+
+        let data = checkpoint.serialize();
+        let restored = Checkpoint::deserialize(&data).unwrap();
+        assert_eq!(restored, checkpoint);
+        assert!(restored.matches(3, 2, 7, 42));
+        assert!(!restored.matches(3, 2, 8, 42));
+
+        let colors = restored.get_colors();
+        assert_eq!(colors[0], None);
+        assert_eq!(colors[5], Some(Color::new(1.0, 0.5, 0.0, 0.25)));
+
+        assert!(Checkpoint::deserialize(&data[..data.len() - 1]).is_err());
+        assert!(Checkpoint::deserialize(b"RBVH").is_err());
+    }
+}
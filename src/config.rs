@@ -2,11 +2,73 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
-use crate::{Integrator, Scratcher};
+use std::path::PathBuf;
+
+use instant::Duration;
+
+use crate::{
+    BvhLayout, CancelToken, ColorSpace, ExposureView, Image, Integrator, PostStack,
+    ProgressCallback, Scratcher,
+};
 
 pub struct Config {
     pub bvh: bool,
+
+    /// Quantized layouts reduce the memory of the BVH of very large scenes
+    pub bvh_layout: BvhLayout,
+
+    /// Directory where BVHs are cached, so that they are not built again for the same scene
+    pub bvh_cache: Option<PathBuf>,
+
+    /// Whether frames only solve the nodes changed through `Scene::set_trs()` or marked
+    /// by `Model::mark_dirty()`, which makes drawing large static scenes faster
+    pub incremental: bool,
+
+    /// Whether building the BVH fails on degenerate primitives, such as triangles without
+    /// area or with non-finite vertices, instead of skipping them
+    pub strict_geometry: bool,
+
     pub integrator: Box<dyn Integrator>,
+
+    /// Image stretched behind the scene, where camera rays see nothing. Unlike an
+    /// environment, it does not light the scene, which is useful for product shots
+    pub backplate: Option<Image>,
+
+    /// Space where lighting is computed, see `ColorSpace`
+    pub working_space: ColorSpace,
+
+    /// Space of the rendered images, which is also written to the files they are saved to
+    pub output_space: ColorSpace,
+
+    /// Effects applied to rendered colors before they are written to the image
+    pub post: PostStack,
+
+    /// Debug view of the exposure drawn after the post effects
+    pub exposure_view: ExposureView,
+
+    /// Global seed for random number generators. Two renders of the
+    /// same scene with the same seed produce identical images.
+    pub seed: u64,
+
+    /// Samples averaged by every pixel, where the first one goes through the center
+    /// of the pixel, and the others through random points within it
+    pub samples_per_pixel: u32,
+
+    /// File where the samples of renders are saved every `checkpoint_interval`, and at
+    /// the end of every frame. Renders of the same scene with the same image size and
+    /// seed start from its samples, skipping rows which already have enough of them
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_interval: Duration,
+
+    /// Whether to count rays and BVH tests, logging them at the end of every frame.
+    /// Without it, only the times of `Scene::stats` are measured
+    pub log_stats: bool,
+
+    /// Called every time a row of the image has been rendered
+    pub progress: Option<ProgressCallback>,
+
+    /// Checked before rendering every row, so that a render can be aborted
+    pub cancel: CancelToken,
 }
 
 impl Default for Config {
@@ -17,6 +79,25 @@ impl Default for Config {
 
 impl Config {
     pub fn new(bvh: bool, integrator: Box<dyn Integrator>) -> Self {
-        Self { bvh, integrator }
+        Self {
+            bvh,
+            bvh_layout: BvhLayout::default(),
+            bvh_cache: None,
+            incremental: false,
+            strict_geometry: false,
+            integrator,
+            backplate: None,
+            working_space: ColorSpace::default(),
+            output_space: ColorSpace::default(),
+            post: PostStack::default(),
+            exposure_view: ExposureView::default(),
+            seed: 0,
+            samples_per_pixel: 1,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(60),
+            log_stats: false,
+            progress: None,
+            cancel: CancelToken::new(),
+        }
     }
 }
@@ -0,0 +1,241 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::f32::consts::FRAC_PI_2;
+
+use super::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    /// The camera rotates around a target, looking at it
+    Orbit,
+    /// The camera rotates around itself and moves freely
+    Fly,
+}
+
+/// Position and orientation of a controlled camera
+#[derive(Clone, Debug, Default)]
+struct CameraState {
+    /// Orbit center in orbit mode, or camera position in fly mode
+    target: Vec3,
+    yaw: f32,
+    pitch: f32,
+    /// Distance from the target, only used in orbit mode
+    distance: f32,
+}
+
+impl CameraState {
+    fn get_rotation(&self) -> Quat {
+        Quat::axis_angle(Vec3::new(0.0, 1.0, 0.0), self.yaw)
+            * Quat::axis_angle(Vec3::new(1.0, 0.0, 0.0), self.pitch)
+    }
+
+    fn lerp(&mut self, other: &CameraState, t: f32) {
+        self.target = self.target + (other.target - self.target) * t;
+        self.yaw += (other.yaw - self.yaw) * t;
+        self.pitch += (other.pitch - self.pitch) * t;
+        self.distance += (other.distance - self.distance) * t;
+    }
+
+    fn close(&self, other: &CameraState) -> bool {
+        const EPSILON: f32 = 1e-4;
+        self.target.close(&other.target)
+            && (self.yaw - other.yaw).abs() < EPSILON
+            && (self.pitch - other.pitch).abs() < EPSILON
+            && (self.distance - other.distance).abs() < EPSILON
+    }
+}
+
+/// Turns user input into camera motion, so that every frontend does not need to
+/// reimplement it. Input methods update a desired state which the camera reaches
+/// smoothly while calling `update()` every frame.
+pub struct CameraController {
+    pub mode: CameraMode,
+
+    /// Units per second when moving
+    pub speed: f32,
+
+    /// Radians per unit of mouse motion
+    pub sensitivity: f32,
+
+    /// Time in seconds the camera takes to get close to the desired state.
+    /// Zero means no smoothing at all
+    pub smoothing: f32,
+
+    desired: CameraState,
+    current: CameraState,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self::orbit(Vec3::default(), 4.0)
+    }
+}
+
+impl CameraController {
+    /// Creates a controller rotating around `target` at `distance`
+    pub fn orbit(target: Vec3, distance: f32) -> Self {
+        let state = CameraState {
+            target,
+            distance,
+            ..Default::default()
+        };
+        Self {
+            mode: CameraMode::Orbit,
+            speed: 1.0,
+            sensitivity: 1.0,
+            smoothing: 0.0,
+            desired: state.clone(),
+            current: state,
+        }
+    }
+
+    /// Creates a controller for a camera free to move from `position`
+    pub fn fly(position: Vec3) -> Self {
+        let mut ret = Self::orbit(position, 0.0);
+        ret.mode = CameraMode::Fly;
+        ret
+    }
+
+    /// Mouse-look: rotates the camera by the mouse motion
+    pub fn rotate(&mut self, dx: f32, dy: f32) {
+        const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+        self.desired.yaw -= dx * self.sensitivity;
+        self.desired.pitch =
+            (self.desired.pitch - dy * self.sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    /// Moves the camera parallel to the view plane.
+    /// In orbit mode the motion is proportional to the distance from the target
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        let rotation = self.desired.get_rotation();
+        let right = rotation * Vec3::new(1.0, 0.0, 0.0);
+        let up = rotation * Vec3::new(0.0, 1.0, 0.0);
+        let scale = match self.mode {
+            CameraMode::Orbit => self.desired.distance,
+            CameraMode::Fly => self.speed,
+        };
+        self.desired.target += right * (-dx * scale) + up * (dy * scale);
+    }
+
+    /// Moves the camera towards the target in orbit mode, or forward in fly mode.
+    /// Positive values move the camera away
+    pub fn zoom(&mut self, delta: f32) {
+        match self.mode {
+            CameraMode::Orbit => {
+                self.desired.distance = (self.desired.distance * (1.0 + delta)).max(0.01);
+            }
+            CameraMode::Fly => {
+                let forward = self.desired.get_rotation() * Vec3::new(0.0, 0.0, -1.0);
+                self.desired.target += forward * (-delta * self.speed);
+            }
+        }
+    }
+
+    /// Moves the camera along a direction expressed in camera space, where
+    /// -Z is forward, X is right and Y is up. Useful for WASD/QE controls
+    pub fn translate(&mut self, direction: Vec3, delta_time: f32) {
+        let rotation = self.desired.get_rotation();
+        self.desired.target += rotation * direction * (self.speed * delta_time);
+    }
+
+    /// Moves the camera towards the desired state.
+    /// Returns whether the camera moved, hence whether a new frame should be rendered
+    pub fn update(&mut self, delta_time: f32) -> bool {
+        if self.current.close(&self.desired) {
+            return false;
+        }
+
+        if self.smoothing > 0.0 {
+            let t = 1.0 - (-delta_time / self.smoothing).exp();
+            self.current.lerp(&self.desired, t);
+        } else {
+            self.current = self.desired.clone();
+        }
+        true
+    }
+
+    /// Returns the current transform of the camera
+    pub fn get_trs(&self) -> Trs {
+        let rotation = self.current.get_rotation();
+        let distance = match self.mode {
+            CameraMode::Orbit => self.current.distance,
+            CameraMode::Fly => 0.0,
+        };
+        let translation = self.current.target + rotation * Vec3::new(0.0, 0.0, distance);
+        Trs::builder()
+            .translation(translation)
+            .rotation(rotation)
+            .build()
+    }
+
+    /// Applies the current transform to a camera node
+    pub fn apply(&self, node: &mut Node) {
+        node.trs = self.get_trs();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    #[test]
+    fn orbit() {
+        let mut controller = CameraController::orbit(Vec3::default(), 2.0);
+        assert!(!controller.update(0.0));
+        assert!(controller
+            .get_trs()
+            .translation
+            .close(&Vec3::new(0.0, 0.0, 2.0)));
+
+        // Half a turn around the target
+        controller.rotate(PI, 0.0);
+        assert!(controller.update(0.1));
+        assert!(controller
+            .get_trs()
+            .translation
+            .close(&Vec3::new(0.0, 0.0, -2.0)));
+
+        controller.zoom(-0.5);
+        controller.update(0.1);
+        assert!(controller
+            .get_trs()
+            .translation
+            .close(&Vec3::new(0.0, 0.0, -1.0)));
+    }
+
+    #[test]
+    fn fly() {
+        let mut controller = CameraController::fly(Vec3::new(0.0, 0.0, 4.0));
+        controller.speed = 2.0;
+        controller.translate(Vec3::new(0.0, 0.0, -1.0), 0.5);
+        controller.update(0.5);
+        assert!(controller
+            .get_trs()
+            .translation
+            .close(&Vec3::new(0.0, 0.0, 3.0)));
+    }
+
+    #[test]
+    fn smoothing() {
+        let mut controller = CameraController::fly(Vec3::default());
+        controller.smoothing = 0.1;
+        controller.translate(Vec3::new(1.0, 0.0, 0.0), 1.0);
+
+        controller.update(0.1);
+        let x = controller.get_trs().translation.get_x();
+        assert!(x > 0.0 && x < 1.0);
+
+        for _ in 0..64 {
+            controller.update(0.1);
+        }
+        assert!(!controller.update(0.1));
+        assert!(controller
+            .get_trs()
+            .translation
+            .close(&Vec3::new(1.0, 0.0, 0.0)));
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Lines showing what is usually invisible, such as camera frustums, light extents,
+//! BVH nodes, and node axes, which can be pushed to a scene as an overlay model to
+//! understand why objects are out of frame or unlit.
+
+use crate::*;
+
+/// Segments in world space, turned into a model of straight curves by `build()`
+pub struct DebugLines {
+    /// Width of the lines
+    pub width: f32,
+    segments: Vec<([Point3; 2], Color)>,
+}
+
+impl Default for DebugLines {
+    fn default() -> Self {
+        Self::new(0.01)
+    }
+}
+
+impl DebugLines {
+    pub fn new(width: f32) -> Self {
+        Self {
+            width,
+            segments: vec![],
+        }
+    }
+
+    /// Returns lines for the cameras, lights, and nodes with a mesh of a collected model
+    pub fn from_model(model: &Model) -> Self {
+        let mut ret = Self::default();
+        let aspect_ratio = if model.height > 0 {
+            model.width as f32 / model.height as f32
+        } else {
+            1.0
+        };
+        for camera_node_handle in &model.camera_nodes {
+            let camera_node = model.nodes.get(*camera_node_handle).unwrap();
+            let camera = model.cameras.get(camera_node.camera).unwrap();
+            let camera_trs = &model.solved_trs.get(camera_node_handle).unwrap().trs;
+            ret.push_frustum(camera_trs, camera, aspect_ratio, 1.0, Color::white());
+        }
+        for light_node_handle in &model.light_nodes {
+            let light_node = model.nodes.get(*light_node_handle).unwrap();
+            let light = model.lights.get(light_node.light).unwrap();
+            let light_trs = &model.solved_trs.get(light_node_handle).unwrap().trs;
+            ret.push_light(light, light_trs, Color::new(1.0, 1.0, 0.0, 1.0));
+        }
+        for (node_handle, solved_trs) in &model.solved_trs {
+            if model.nodes.get(*node_handle).unwrap().mesh.valid() {
+                ret.push_axes(&solved_trs.trs, 0.5);
+            }
+        }
+        ret
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn push_line(&mut self, a: Point3, b: Point3, color: Color) {
+        self.segments.push(([a, b], color));
+    }
+
+    /// Pushes the twelve edges of `bounds`
+    pub fn push_box(&mut self, bounds: &AABB, color: Color) {
+        let corner = |i: usize| {
+            let pick = |axis: usize| {
+                if i & (1 << axis) == 0 {
+                    bounds.a[axis]
+                } else {
+                    bounds.b[axis]
+                }
+            };
+            Point3::new(pick(0), pick(1), pick(2))
+        };
+        for i in 0..8 {
+            for axis in 0..3 {
+                // Edges go from corners on the lower side of an axis to the upper one
+                if i & (1 << axis) == 0 {
+                    self.push_line(corner(i), corner(i | (1 << axis)), color);
+                }
+            }
+        }
+    }
+
+    /// Pushes the X, Y, and Z axes of `trs` in red, green, and blue
+    pub fn push_axes(&mut self, trs: &Trs, size: f32) {
+        let origin = Point3::from(trs.translation);
+        let axes = [
+            (Vec3::new(1.0, 0.0, 0.0), Color::new(1.0, 0.0, 0.0, 1.0)),
+            (Vec3::new(0.0, 1.0, 0.0), Color::new(0.0, 1.0, 0.0, 1.0)),
+            (Vec3::new(0.0, 0.0, 1.0), Color::new(0.0, 0.0, 1.0, 1.0)),
+        ];
+        for (axis, color) in axes {
+            self.push_line(origin, origin + trs.rotation * axis * size, color);
+        }
+    }
+
+    /// Pushes the pyramid seen by `camera` at `camera_trs` up to `depth`,
+    /// for images of `aspect_ratio`
+    pub fn push_frustum(
+        &mut self,
+        camera_trs: &Trs,
+        camera: &Camera,
+        aspect_ratio: f32,
+        depth: f32,
+        color: Color,
+    ) {
+        let origin = Point3::from(camera_trs.translation);
+        let y = camera.get_angle() * depth;
+        let x = y * aspect_ratio;
+        // Cameras look along their negative Z axis
+        let corners = [(-x, -y), (x, -y), (x, y), (-x, y)]
+            .map(|(x, y)| origin + camera_trs.rotation * Vec3::new(x, y, -depth));
+        for i in 0..4 {
+            self.push_line(origin, corners[i], color);
+            self.push_line(corners[i], corners[(i + 1) % 4], color);
+        }
+    }
+
+    /// Pushes the extent of `light` at `light_trs`: a star for point lights, the outline
+    /// and direction of quads and portals, and the direction of lights at infinity
+    pub fn push_light(&mut self, light: &Light, light_trs: &Trs, color: Color) {
+        // Lights rotate their translation, see `Trs::get_translation()`
+        let origin = Point3::from(light_trs.get_translation());
+        let mut push_rect = |width: f32, height: f32| {
+            let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(x, z)| {
+                let offset = Vec3::new(x * width * 0.5, 0.0, z * height * 0.5);
+                let translation = light_trs.translation + light_trs.scale * offset;
+                Point3::from(light_trs.rotation * translation)
+            });
+            for i in 0..4 {
+                self.push_line(corners[i], corners[(i + 1) % 4], color);
+            }
+        };
+        match light {
+            Light::Quad(quad) => push_rect(quad.width, quad.height),
+            Light::Portal(portal) => push_rect(portal.width, portal.height),
+            _ => (),
+        }
+
+        match light {
+            Light::Point(_) => {
+                for axis in 0..3 {
+                    let mut offset = Vec3::default();
+                    offset.simd[axis] = 0.25;
+                    self.push_line(origin - offset, origin + offset, color);
+                }
+            }
+            _ => {
+                let dir = -light.get_direction(light_trs, &origin);
+                self.push_line(origin, origin + dir, color);
+            }
+        }
+    }
+
+    /// Pushes the bounds of the nodes of `bvh` up to `max_depth`, where the root is at zero
+    pub fn push_bvh(&mut self, bvh: &Bvh, max_depth: usize, color: Color) {
+        let mut stack = vec![(&bvh.root, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            self.push_box(node.get_bounds(), color);
+            if node.is_leaf() || depth >= max_depth {
+                continue;
+            }
+            for child in [node.get_left(), node.get_right()] {
+                if let Some(child) = bvh.nodes.get(child) {
+                    stack.push((child, depth + 1));
+                }
+            }
+        }
+    }
+
+    /// Draws the lines on top of `image`, rendered by a camera at `camera_trs` with
+    /// `camera_angle`, see `Camera::get_angle()`. Unlike the model returned by `build()`,
+    /// lines are never hidden by objects and are one pixel wide at any distance
+    pub fn draw(&self, image: &mut Image, camera_trs: &Trs, camera_angle: f32) {
+        let width = image.width() as f32;
+        let height = image.height() as f32;
+        let aspect_ratio = width / height;
+        let inverse_rotation = camera_trs.rotation.get_inverse();
+        let to_camera =
+            |point: &Point3| inverse_rotation * (Vec3::from(*point) - camera_trs.translation);
+        // Inverse of the primary rays of `Scene`, from camera space to pixels
+        let to_pixel = |p: Vec3| {
+            let x = p.get_x() / -p.get_z() / (camera_angle * aspect_ratio);
+            let y = p.get_y() / -p.get_z() / camera_angle;
+            Vec2::new(
+                (x + 1.0) * 0.5 * width - 0.5,
+                (1.0 - y) * 0.5 * height - 0.5,
+            )
+        };
+
+        const NEAR: f32 = -1e-3;
+        for ([a, b], color) in &self.segments {
+            let (mut a, mut b) = (to_camera(a), to_camera(b));
+            // Segments are clipped where they cross the plane of the camera
+            if a.get_z() > NEAR && b.get_z() > NEAR {
+                continue;
+            }
+            if a.get_z() > NEAR {
+                std::mem::swap(&mut a, &mut b);
+            }
+            if b.get_z() > NEAR {
+                let t = (NEAR - a.get_z()) / (b.get_z() - a.get_z());
+                b = a + (b - a) * t;
+            }
+            image.draw_line(to_pixel(a), to_pixel(b), *color);
+        }
+    }
+
+    /// Returns a model with a node drawing the lines as straight curves
+    pub fn build(self) -> Model {
+        let mut model = Model::new();
+        let width = self.width;
+        let curves = self
+            .segments
+            .into_iter()
+            .map(|([a, b], color)| {
+                let points = (0..4).map(|i| a + (b - a) * (i as f32 / 3.0)).collect();
+                let mut curve = Curve::new(points, vec![width; 4]);
+                curve.color = color;
+                curve
+            })
+            .collect();
+        let primitive = Primitive::builder().curves(Curves::new(curves)).build();
+        let primitive = model.primitives.push(primitive);
+        let mesh = model.meshes.push(Mesh::new(vec![primitive]));
+        let node = model.nodes.push(Node::builder().mesh(mesh).build());
+        model.root.children.push(node);
+        model
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn debug_lines() {
+        let mut lines = DebugLines::default();
+        lines.push_box(
+            &AABB::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0)),
+            Color::white(),
+        );
+        assert_eq!(lines.len(), 12);
+
+        let camera = Camera::default();
+        lines.push_frustum(&Trs::default(), &camera, 1.0, 1.0, Color::white());
+        assert_eq!(lines.len(), 20);
+        // Corners lie in front of the camera
+        let (segment, _) = &lines.segments[12];
+        assert_eq!(segment[1].get_z(), -1.0);
+
+        lines.push_light(&Light::point(), &Trs::default(), Color::white());
+        lines.push_light(&Light::quad(1.0, 1.0), &Trs::default(), Color::white());
+        assert_eq!(lines.len(), 28);
+
+        let mut model = lines.build();
+        let primitives = model.collect();
+        assert_eq!(primitives.len(), 28);
+    }
+
+    #[test]
+    fn draw() {
+        let mut lines = DebugLines::default();
+        let red = Color::new(1.0, 0.0, 0.0, 1.0);
+        // A line across the view, and one behind the camera
+        lines.push_line(
+            Point3::new(-1.0, 0.0, -2.0),
+            Point3::new(1.0, 0.0, -2.0),
+            red,
+        );
+        lines.push_line(Point3::new(0.0, 1.0, 1.0), Point3::new(0.0, -1.0, 1.0), red);
+
+        let mut image = Image::new(9, 9, ColorType::RGBA8);
+        let angle = Camera::default().get_angle();
+        lines.draw(&mut image, &Trs::default(), angle);
+        for y in 0..9 {
+            for x in 0..9 {
+                let expected = if y == 4 { 255 } else { 0 };
+                assert_eq!(image.get::<RGBA8>(x, y).r, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,376 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Rendering of a frame split among worker processes, which receive ranges of rows
+//! from a coordinator over TCP and send back their tiles.
+//!
+//! Every message is a little endian `u32` with the length of the payload, followed by
+//! the payload. Jobs sent by the coordinator carry the size of the image, the seed, the
+//! samples per pixel, and the range of rows. Workers reply with the tiles of those rows,
+//! or with an error message. Workers are expected to have loaded the same scene.
+
+use std::{
+    error::Error,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    ops::Range,
+    sync::{Condvar, Mutex},
+    time::Duration,
+};
+
+use crate::*;
+
+const JOB_MAGIC: &[u8; 4] = b"RJOB";
+const TILES_MAGIC: &[u8; 4] = b"RTIL";
+const ERROR_MAGIC: &[u8; 4] = b"RERR";
+const VERSION: u32 = 1;
+
+/// Messages larger than this are considered corrupted
+const MAX_MESSAGE_SIZE: usize = 1 << 30;
+
+/// Jobs for images larger than this on either side are refused by workers
+const MAX_IMAGE_SIZE: u32 = 16384;
+
+/// Workers drop coordinators which send nothing for this long. It is longer than the
+/// default timeout of coordinators, which may wait for the jobs of other workers
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(600);
+
+fn write_message(stream: &mut TcpStream, payload: &[u8]) -> Result<(), Box<dyn Error>> {
+    stream.write_all(&(payload.len() as u32).to_le_bytes())?;
+    stream.write_all(payload)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Returns the payload of the next message, or `None` when the other end has closed
+/// the connection before sending it
+fn read_message(stream: &mut TcpStream) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let mut len = [0u8; 4];
+    match stream.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(format!("Message too large: {} bytes", len).into());
+    }
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+/// Rows of a frame a worker is asked to render
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderJob {
+    pub width: u32,
+    pub height: u32,
+    pub seed: u64,
+    pub samples_per_pixel: u32,
+    pub rows: Range<usize>,
+}
+
+impl RenderJob {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut ret = vec![];
+        ret.extend_from_slice(JOB_MAGIC);
+        ret.extend_from_slice(&VERSION.to_le_bytes());
+        ret.extend_from_slice(&self.width.to_le_bytes());
+        ret.extend_from_slice(&self.height.to_le_bytes());
+        ret.extend_from_slice(&self.seed.to_le_bytes());
+        ret.extend_from_slice(&self.samples_per_pixel.to_le_bytes());
+        ret.extend_from_slice(&(self.rows.start as u32).to_le_bytes());
+        ret.extend_from_slice(&(self.rows.end as u32).to_le_bytes());
+        ret
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = Reader { data };
+        if reader.bytes(4)? != JOB_MAGIC {
+            return Err("Not a render job".into());
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(format!("Unsupported render job version {}", version).into());
+        }
+        let width = reader.u32()?;
+        let height = reader.u32()?;
+        let seed = reader.u64()?;
+        let samples_per_pixel = reader.u32()?;
+        let start = reader.u32()? as usize;
+        let end = reader.u32()? as usize;
+        let valid = 1..=MAX_IMAGE_SIZE;
+        if !valid.contains(&width) || !valid.contains(&height) {
+            return Err(format!("Invalid image size {}x{}", width, height).into());
+        }
+        if start > end || end > height as usize {
+            return Err(format!("Invalid rows {}..{} of {}", start, end, height).into());
+        }
+        Ok(Self {
+            width,
+            height,
+            seed,
+            samples_per_pixel,
+            rows: start..end,
+        })
+    }
+}
+
+/// Returns the reply to `job`, which is made of the tiles rendered by `scene`
+fn render_job(scene: &mut Scene, job: &RenderJob) -> Vec<u8> {
+    scene.config.seed = job.seed;
+    scene.config.samples_per_pixel = job.samples_per_pixel;
+    let tiles = scene.render_rows(job.width, job.height, job.rows.clone());
+
+    let mut ret = vec![];
+    ret.extend_from_slice(TILES_MAGIC);
+    ret.extend_from_slice(&(tiles.len() as u32).to_le_bytes());
+    for tile in &tiles {
+        tile.serialize_into(&mut ret);
+    }
+    ret
+}
+
+/// Renders the jobs received through `stream` with `scene`, until the coordinator
+/// closes the connection
+pub fn serve_connection(scene: &mut Scene, mut stream: TcpStream) -> Result<(), Box<dyn Error>> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+    while let Some(payload) = read_message(&mut stream)? {
+        let reply = match RenderJob::deserialize(&payload) {
+            Ok(job) => render_job(scene, &job),
+            Err(err) => {
+                let mut reply = ERROR_MAGIC.to_vec();
+                reply.extend_from_slice(err.to_string().as_bytes());
+                reply
+            }
+        };
+        write_message(&mut stream, &reply)?;
+    }
+    Ok(())
+}
+
+/// Renders the jobs of the coordinators connecting to `listener` with `scene`, one
+/// connection after the other. Failed connections are logged and do not stop the worker
+pub fn serve(scene: &mut Scene, listener: TcpListener) -> Result<(), Box<dyn Error>> {
+    for stream in listener.incoming() {
+        if let Err(err) = serve_connection(scene, stream?) {
+            print_warning!("Worker", "{}", err);
+        }
+    }
+    Ok(())
+}
+
+/// Sends `job` to the worker at the other end of `stream`, returning its tiles
+fn request_tiles(stream: &mut TcpStream, job: &RenderJob) -> Result<Vec<Tile>, Box<dyn Error>> {
+    write_message(stream, &job.serialize())?;
+    let payload = read_message(stream)?.ok_or("Worker closed the connection")?;
+
+    let mut reader = Reader { data: &payload };
+    let magic = reader.bytes(4)?;
+    if magic == ERROR_MAGIC {
+        return Err(String::from_utf8_lossy(reader.data).into_owned().into());
+    }
+    if magic != TILES_MAGIC {
+        return Err("Not a tiles reply".into());
+    }
+    let count = reader.u32()? as usize;
+    if count != job.rows.len() {
+        return Err(format!("Expected {} tiles, got {}", job.rows.len(), count).into());
+    }
+    (0..count)
+        .map(|_| Tile::read(&mut reader, job.width))
+        .collect()
+}
+
+/// Jobs of a frame shared by the threads talking to the workers
+struct JobQueue {
+    /// Jobs waiting for a worker
+    pending: Vec<RenderJob>,
+
+    /// Jobs whose tiles have not been received yet, pending ones included
+    outstanding: usize,
+}
+
+/// Splits frames into ranges of rows rendered by workers, see `serve()`
+pub struct Coordinator {
+    /// Addresses of the workers, such as `"192.168.1.2:7878"`
+    pub workers: Vec<String>,
+
+    /// Rows sent to a worker at a time
+    pub rows_per_job: usize,
+
+    /// Time a worker has to connect, and to reply to a job. Jobs of workers which
+    /// take longer go to the other workers
+    pub timeout: Duration,
+}
+
+impl Coordinator {
+    pub fn new(workers: Vec<String>) -> Self {
+        Self {
+            workers,
+            rows_per_job: 16,
+            timeout: Duration::from_secs(300),
+        }
+    }
+
+    /// Renders `image` with the workers, using the config of `scene` and resolving the
+    /// tiles with it. Jobs of workers which fail, or time out, go to the other workers,
+    /// which wait for every job to be done, hence this only fails when all of them do,
+    /// or the render is cancelled
+    pub fn render(&self, scene: &mut Scene, image: &mut Image) -> Result<(), Box<dyn Error>> {
+        let (width, height) = (image.width(), image.height());
+        let rows_per_job = self.rows_per_job.max(1);
+        let jobs = (0..height as usize)
+            .step_by(rows_per_job)
+            .map(|start| RenderJob {
+                width,
+                height,
+                seed: scene.config.seed,
+                samples_per_pixel: scene.config.samples_per_pixel,
+                rows: start..(start + rows_per_job).min(height as usize),
+            })
+            .collect::<Vec<_>>();
+
+        let queue = Mutex::new(JobQueue {
+            outstanding: jobs.len(),
+            pending: jobs,
+        });
+        // Notified when jobs are done, or go back to the queue
+        let queue_changed = Condvar::new();
+        let mut checkpoint = Checkpoint::new(width, height, scene.config.seed, 0);
+        let tiles = Mutex::new(&mut checkpoint.tiles);
+        let errors = Mutex::new(vec![]);
+        let progress = ProgressTracker::new(scene.config.progress.as_ref(), height as usize);
+        let cancel = &scene.config.cancel;
+        let timeout = self.timeout;
+
+        std::thread::scope(|scope| {
+            for worker in &self.workers {
+                let (queue, tiles, errors, progress) = (&queue, &tiles, &errors, &progress);
+                let queue_changed = &queue_changed;
+                scope.spawn(move || {
+                    let connect = || -> Result<TcpStream, Box<dyn Error>> {
+                        let address = worker.to_socket_addrs()?.next().ok_or("No address")?;
+                        let stream = TcpStream::connect_timeout(&address, timeout)?;
+                        stream.set_read_timeout(Some(timeout))?;
+                        stream.set_write_timeout(Some(timeout))?;
+                        Ok(stream)
+                    };
+                    let mut stream = match connect() {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            errors.lock().unwrap().push(format!("{}: {}", worker, err));
+                            return;
+                        }
+                    };
+                    loop {
+                        let job = {
+                            let mut queue = queue.lock().unwrap();
+                            loop {
+                                if cancel.is_cancelled() || queue.outstanding == 0 {
+                                    return;
+                                }
+                                if let Some(job) = queue.pending.pop() {
+                                    break job;
+                                }
+                                // Jobs of other workers may fail and come back
+                                let wait = Duration::from_millis(100);
+                                queue = queue_changed.wait_timeout(queue, wait).unwrap().0;
+                            }
+                        };
+                        match request_tiles(&mut stream, &job) {
+                            Ok(job_tiles) => {
+                                let mut tiles = tiles.lock().unwrap();
+                                for (y, tile) in job.rows.clone().zip(job_tiles) {
+                                    tiles[y] = tile;
+                                    progress.complete(y);
+                                }
+                                queue.lock().unwrap().outstanding -= 1;
+                                queue_changed.notify_all();
+                            }
+                            Err(err) => {
+                                // Another worker can take care of it
+                                queue.lock().unwrap().pending.push(job);
+                                queue_changed.notify_all();
+                                errors.lock().unwrap().push(format!("{}: {}", worker, err));
+                                return;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        if cancel.is_cancelled() {
+            return Err("Render cancelled".into());
+        }
+        let errors = errors.into_inner().unwrap();
+        if queue.into_inner().unwrap().outstanding > 0 {
+            return Err(format!("All workers failed: {}", errors.join(", ")).into());
+        }
+        // Other workers took over, but failures should still be visible
+        for err in &errors {
+            print_warning!("Worker", "{}", err);
+        }
+
+        scene.resolve(&checkpoint, image);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn job() {
+        let job = RenderJob {
+            width: 4,
+            height: 8,
+            seed: 3,
+            samples_per_pixel: 2,
+            rows: 2..6,
+        };
+        assert_eq!(RenderJob::deserialize(&job.serialize()).unwrap(), job);
+
+        let mut invalid = job.clone();
+        invalid.rows = 6..9;
+        assert!(RenderJob::deserialize(&invalid.serialize()).is_err());
+
+        let mut huge = job.clone();
+        huge.width = u32::MAX;
+        assert!(RenderJob::deserialize(&huge.serialize()).is_err());
+    }
+    #[test]
+    fn timeout() {
+        // A worker which never replies
+        let hung = TcpListener::bind("127.0.0.1:0").unwrap();
+        let hung_address = hung.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let _streams = hung.incoming().collect::<Vec<_>>();
+        });
+
+        let mut coordinator = Coordinator::new(vec![hung_address.clone()]);
+        coordinator.rows_per_job = 2;
+        coordinator.timeout = Duration::from_millis(100);
+        let mut scene = Scene::new();
+        let mut image = Image::new(4, 4, ColorType::RGBA8);
+        assert!(coordinator.render(&mut scene, &mut image).is_err());
+
+        // Its jobs go to a worker which replies
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        coordinator
+            .workers
+            .push(listener.local_addr().unwrap().to_string());
+        std::thread::spawn(move || {
+            let mut scene = Scene::new();
+            scene.push_default_model();
+            let (stream, _) = listener.accept().unwrap();
+            serve_connection(&mut scene, stream).unwrap();
+        });
+        scene.push_default_model();
+        coordinator.render(&mut scene, &mut image).unwrap();
+    }
+}
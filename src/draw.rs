@@ -2,8 +2,488 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
 use super::*;
 
 pub trait Draw {
     fn draw(&mut self, image: &mut Image);
 }
+
+/// Cooperative cancellation token which can be shared with an in-flight render.
+/// Cancelling it makes the render skip the rows which have not been drawn yet.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Makes the token usable for another render
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns the weight of the bloom of `color`, which goes smoothly from 0 to 1
+/// within `knee` around `threshold` of the brightest channel
+fn get_bloom_weight(color: &Color, threshold: f32, knee: f32) -> f32 {
+    let brightness = color.r.max(color.g).max(color.b);
+    let soft = (brightness - threshold + knee).clamp(0.0, 2.0 * knee);
+    let soft = soft * soft / (4.0 * knee + f32::EPSILON);
+    soft.max(brightness - threshold) / brightness.max(f32::EPSILON)
+}
+
+/// Adds `src` multiplied by `factor` to `dst`
+pub fn add_scaled(dst: &mut [f32], src: &[f32], factor: f32) {
+    assert_eq!(dst.len(), src.len());
+    let wide_factor = Wide::splat(factor);
+    let mut dst_chunks = dst.chunks_exact_mut(LANES);
+    let mut src_chunks = src.chunks_exact(LANES);
+    for (dst, src) in (&mut dst_chunks).zip(&mut src_chunks) {
+        let sum = Wide::from_slice(dst) + Wide::from_slice(src) * wide_factor;
+        sum.copy_to_slice(dst);
+    }
+    // Values which do not fill a vector
+    for (dst, src) in dst_chunks
+        .into_remainder()
+        .iter_mut()
+        .zip(src_chunks.remainder())
+    {
+        *dst += src * factor;
+    }
+}
+
+/// Light of the brightest pixels bleeding into the neighbouring ones
+#[derive(Clone, Copy, Debug)]
+pub struct Bloom {
+    /// Brightness above which pixels start blooming
+    pub threshold: f32,
+
+    /// Width of the transition around the threshold, where 0 makes it a hard cut
+    pub knee: f32,
+
+    pub intensity: f32,
+
+    /// How far light bleeds, as a fraction of the height of the image
+    pub radius: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.5,
+            radius: 0.02,
+        }
+    }
+}
+
+impl Bloom {
+    /// Blurs a channel of `width` x `height` pixels along rows, or along columns when
+    /// not `horizontal`, with a Gaussian kernel of `weights`. Rows are blurred a whole
+    /// range of pixels at a time, which goes through wide SIMD vectors
+    fn blur(
+        channel: &[f32],
+        weights: &[f32],
+        width: usize,
+        height: usize,
+        horizontal: bool,
+    ) -> Vec<f32> {
+        let mut ret = vec![0.0; channel.len()];
+        // Rows of an empty image have no pixels to chunk
+        if width == 0 || height == 0 {
+            return ret;
+        }
+        let radius = weights.len() as isize - 1;
+        for (y, row) in ret.chunks_exact_mut(width).enumerate() {
+            for offset in -radius..=radius {
+                let weight = weights[offset.unsigned_abs()];
+                if horizontal {
+                    // Pixels whose neighbour at `offset` is within the row
+                    let start = (-offset).max(0) as usize;
+                    let end = (width as isize - offset).min(width as isize);
+                    if end <= start as isize {
+                        continue;
+                    }
+                    let end = end as usize;
+                    let first = (y * width + start) as isize + offset;
+                    let src = &channel[first as usize..first as usize + end - start];
+                    add_scaled(&mut row[start..end], src, weight);
+                } else {
+                    let neighbour = y as isize + offset;
+                    if neighbour < 0 || neighbour >= height as isize {
+                        continue;
+                    }
+                    let first = neighbour as usize * width;
+                    add_scaled(row, &channel[first..first + width], weight);
+                }
+            }
+        }
+        ret
+    }
+
+    pub fn apply(&self, hdr: &mut [Option<Color>], width: u32, height: u32) {
+        let bright = hdr
+            .iter()
+            .map(|color| match color {
+                Some(color) => *color * get_bloom_weight(color, self.threshold, self.knee),
+                None => Color::default(),
+            })
+            .collect::<Vec<_>>();
+
+        let radius = (self.radius * height as f32).ceil().max(1.0) as usize;
+        let sigma = radius as f32 / 2.0;
+        let mut weights = (0..=radius)
+            .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+            .collect::<Vec<_>>();
+        let sum = weights[0] + 2.0 * weights[1..].iter().sum::<f32>();
+        weights.iter_mut().for_each(|weight| *weight /= sum);
+
+        let (width, height) = (width as usize, height as usize);
+        let channels = [
+            bright.iter().map(|color| color.r).collect::<Vec<_>>(),
+            bright.iter().map(|color| color.g).collect::<Vec<_>>(),
+            bright.iter().map(|color| color.b).collect::<Vec<_>>(),
+        ]
+        .map(|channel| {
+            let blurred = Self::blur(&channel, &weights, width, height, true);
+            Self::blur(&blurred, &weights, width, height, false)
+        });
+
+        for (i, color) in hdr.iter_mut().enumerate() {
+            if let Some(color) = color {
+                color.r += channels[0][i] * self.intensity;
+                color.g += channels[1][i] * self.intensity;
+                color.b += channels[2][i] * self.intensity;
+            }
+        }
+    }
+}
+
+/// Darkening of the image towards its corners
+#[derive(Clone, Copy, Debug)]
+pub struct Vignette {
+    /// How much corners are darkened, from 0 to 1
+    pub intensity: f32,
+
+    /// Exponent of the distance from the center, where higher values keep
+    /// more of the image untouched
+    pub smoothness: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Self {
+            intensity: 0.5,
+            smoothness: 2.0,
+        }
+    }
+}
+
+impl Vignette {
+    pub fn apply(&self, hdr: &mut [Option<Color>], width: u32, height: u32) {
+        let half_diagonal = (width as f32).hypot(height as f32) * 0.5;
+        for (i, color) in hdr.iter_mut().enumerate() {
+            if let Some(color) = color {
+                let x = (i % width as usize) as f32 + 0.5 - width as f32 * 0.5;
+                let y = (i / width as usize) as f32 + 0.5 - height as f32 * 0.5;
+                let distance = x.hypot(y) / half_diagonal;
+                *color *= 1.0 - self.intensity * distance.powf(self.smoothness);
+            }
+        }
+    }
+}
+
+/// Lens failing to focus all wavelengths on the same point, which splits the red
+/// and the blue channels towards the corners of the image
+#[derive(Clone, Copy, Debug)]
+pub struct ChromaticAberration {
+    /// Distance between the red and the blue channels at the corners, as a fraction
+    /// of the distance from the center
+    pub intensity: f32,
+}
+
+impl Default for ChromaticAberration {
+    fn default() -> Self {
+        Self { intensity: 0.01 }
+    }
+}
+
+impl ChromaticAberration {
+    pub fn apply(&self, hdr: &mut [Option<Color>], width: u32, height: u32) {
+        let source = hdr.to_vec();
+        let center_x = width as f32 * 0.5;
+        let center_y = height as f32 * 0.5;
+        // Returns the color at the pixel `scale` times farther from the center
+        let get_scaled = |x: f32, y: f32, scale: f32| {
+            let x = (center_x + (x - center_x) * scale).clamp(0.0, width as f32 - 1.0);
+            let y = (center_y + (y - center_y) * scale).clamp(0.0, height as f32 - 1.0);
+            source[y as usize * width as usize + x as usize]
+        };
+        for (i, color) in hdr.iter_mut().enumerate() {
+            if let Some(color) = color {
+                let x = (i % width as usize) as f32 + 0.5;
+                let y = (i / width as usize) as f32 + 0.5;
+                let half = self.intensity * 0.5;
+                if let Some(red) = get_scaled(x, y, 1.0 + half) {
+                    color.r = red.r;
+                }
+                if let Some(blue) = get_scaled(x, y, 1.0 - half) {
+                    color.b = blue.b;
+                }
+            }
+        }
+    }
+}
+
+/// Effects applied to the colors of a render before they are clamped to the
+/// range of the image, in the order of the fields
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PostStack {
+    pub bloom: Option<Bloom>,
+    pub chromatic_aberration: Option<ChromaticAberration>,
+    pub vignette: Option<Vignette>,
+}
+
+impl PostStack {
+    pub fn is_empty(&self) -> bool {
+        self.bloom.is_none() && self.chromatic_aberration.is_none() && self.vignette.is_none()
+    }
+
+    /// Applies the effects to `hdr`, the colors of an image of `width` x `height`
+    /// pixels, where pixels with no color are left untouched
+    pub fn apply(&self, hdr: &mut [Option<Color>], width: u32, height: u32) {
+        if let Some(bloom) = &self.bloom {
+            bloom.apply(hdr, width, height);
+        }
+        if let Some(chromatic_aberration) = &self.chromatic_aberration {
+            chromatic_aberration.apply(hdr, width, height);
+        }
+        if let Some(vignette) = &self.vignette {
+            vignette.apply(hdr, width, height);
+        }
+    }
+}
+
+/// Luminance of middle gray, which exposure stops are measured from
+const MIDDLE_GRAY: f32 = 0.18;
+
+/// Distribution of the luminance of the pixels of a render, in stops from middle gray
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    pub bins: Vec<u32>,
+
+    /// Stops of the lower bound of the first bin
+    pub min_stops: f32,
+
+    /// Stops of the upper bound of the last bin
+    pub max_stops: f32,
+}
+
+impl Histogram {
+    /// Returns the histogram of `hdr` with `bin_count` bins from 8 stops under middle gray
+    /// to 4 stops over it, where darker and brighter pixels go to the first and last bin
+    pub fn new(hdr: &[Option<Color>], bin_count: usize) -> Self {
+        let mut ret = Self {
+            bins: vec![0; bin_count.max(1)],
+            min_stops: -8.0,
+            max_stops: 4.0,
+        };
+        for color in hdr.iter().flatten() {
+            let bin = ret.get_bin(color.get_luminance());
+            ret.bins[bin] += 1;
+        }
+        ret
+    }
+
+    /// Returns the bin of `luminance`
+    pub fn get_bin(&self, luminance: f32) -> usize {
+        let stops = get_stops(luminance);
+        let t = (stops - self.min_stops) / (self.max_stops - self.min_stops);
+        let last = self.bins.len() - 1;
+        ((t * self.bins.len() as f32).max(0.0) as usize).min(last)
+    }
+
+    /// Draws the bins as bars over the bottom quarter of `hdr`
+    fn draw(&self, hdr: &mut [Option<Color>], width: u32, height: u32) {
+        let max = self.bins.iter().copied().max().unwrap_or(0).max(1) as f32;
+        let graph_height = (height / 4).max(1) as usize;
+        let (width, height) = (width as usize, height as usize);
+        for x in 0..width {
+            let bin = x * self.bins.len() / width;
+            let bar = (self.bins[bin] as f32 / max * graph_height as f32).ceil() as usize;
+            for y in (height - graph_height)..height {
+                let color = hdr[y * width + x].get_or_insert(Color::black());
+                // Bars are white over a darkened image
+                if height - y <= bar {
+                    *color = Color::white();
+                } else {
+                    *color *= 0.25;
+                }
+            }
+        }
+    }
+}
+
+/// Returns the exposure of `luminance` in stops from middle gray
+fn get_stops(luminance: f32) -> f32 {
+    (luminance.max(f32::MIN_POSITIVE) / MIDDLE_GRAY).log2()
+}
+
+/// Returns the false color of `color`, going from purple for black, through blue, gray,
+/// green for middle gray, gray, and yellow, to red for colors clipped by the image
+fn get_false_color(color: &Color) -> Color {
+    let luminance = color.get_luminance();
+    let stops = get_stops(luminance);
+    let (r, g, b) = if luminance <= 0.0 {
+        (0.5, 0.0, 0.5)
+    } else if stops < -4.0 {
+        (0.0, 0.2, 0.8)
+    } else if (-0.5..0.5).contains(&stops) {
+        (0.2, 0.8, 0.2)
+    } else if luminance >= 1.0 {
+        (1.0, 0.0, 0.0)
+    } else if stops > 2.0 {
+        (1.0, 0.9, 0.0)
+    } else {
+        (luminance, luminance, luminance)
+    };
+    Color::new(r, g, b, color.a)
+}
+
+/// What the image shows instead of, or over, the render, to judge its exposure
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExposureView {
+    /// The render as it is
+    #[default]
+    Off,
+
+    /// Colors replaced by their exposure band, see `get_false_color()`
+    FalseColor,
+
+    /// Red stripes over pixels clipped by the image, and blue stripes
+    /// over pixels more than 4 stops under middle gray
+    Zebra,
+
+    /// Luminance histogram drawn over the bottom of the render, see `Histogram`
+    Histogram,
+}
+
+impl ExposureView {
+    /// Width of the stripes of the zebra view, in pixels
+    const STRIPE_WIDTH: usize = 4;
+
+    /// Number of bins of the histogram view
+    const BIN_COUNT: usize = 48;
+
+    pub fn apply(&self, hdr: &mut [Option<Color>], width: u32, height: u32) {
+        match self {
+            ExposureView::Off => (),
+            ExposureView::FalseColor => {
+                for color in hdr.iter_mut().flatten() {
+                    *color = get_false_color(color);
+                }
+            }
+            ExposureView::Zebra => {
+                for (i, color) in hdr.iter_mut().enumerate() {
+                    let Some(color) = color else {
+                        continue;
+                    };
+                    let (x, y) = (i % width as usize, i / width as usize);
+                    if (x + y) / Self::STRIPE_WIDTH % 2 != 0 {
+                        continue;
+                    }
+                    let luminance = color.get_luminance();
+                    if luminance >= 1.0 {
+                        *color = Color::new(1.0, 0.0, 0.0, 1.0);
+                    } else if get_stops(luminance) < -4.0 {
+                        *color = Color::new(0.0, 0.2, 0.8, 1.0);
+                    }
+                }
+            }
+            ExposureView::Histogram => {
+                Histogram::new(hdr, Self::BIN_COUNT).draw(hdr, width, height);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn add_scaled() {
+        // Longer than a vector, and not a multiple of its lanes
+        let count = LANES * 2 + 3;
+        let mut dst = vec![1.0; count];
+        let src = (0..count).map(|i| i as f32).collect::<Vec<_>>();
+        super::add_scaled(&mut dst, &src, 0.5);
+        for (i, value) in dst.iter().enumerate() {
+            assert_eq!(*value, 1.0 + i as f32 * 0.5);
+        }
+    }
+
+    #[test]
+    fn post() {
+        let (width, height) = (9, 9);
+        let center = 4 * width as usize + 4;
+        let mut hdr = vec![Some(Color::new(0.5, 0.5, 0.5, 1.0)); 81];
+        hdr[center] = Some(Color::new(8.0, 8.0, 8.0, 1.0));
+        hdr[0] = None;
+
+        // Only the bright pixel blooms into its neighbours
+        let bloom = Bloom {
+            radius: 0.3,
+            ..Default::default()
+        };
+        let mut bloomed = hdr.clone();
+        bloom.apply(&mut bloomed, width, height);
+        assert!(bloomed[center + 1].unwrap().r > 0.5);
+        assert_eq!(bloomed[center + 4].unwrap().r, 0.5);
+        assert!(bloomed[0].is_none());
+
+        // Empty images have nothing to bloom
+        let mut empty = vec![];
+        bloom.apply(&mut empty, 0, 0);
+        bloom.apply(&mut empty, 0, height);
+        assert!(empty.is_empty());
+
+        // Corners get darker than the center
+        let mut vignetted = hdr.clone();
+        Vignette::default().apply(&mut vignetted, width, height);
+        assert_eq!(vignetted[center], hdr[center]);
+        assert!(vignetted[80].unwrap().r < 0.5);
+
+        // Bright pixels of the false color view are red
+        let mut false_color = hdr.clone();
+        ExposureView::FalseColor.apply(&mut false_color, width, height);
+        assert_eq!(false_color[center], Some(Color::new(1.0, 0.0, 0.0, 1.0)));
+
+        let histogram = Histogram::new(&hdr, 12);
+        assert_eq!(histogram.bins.iter().sum::<u32>(), 80);
+        assert_eq!(histogram.bins[11], 1);
+
+        let post = PostStack::default();
+        assert!(post.is_empty());
+        let mut unchanged = hdr.clone();
+        post.apply(&mut unchanged, width, height);
+        assert_eq!(unchanged, hdr);
+    }
+}
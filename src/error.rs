@@ -0,0 +1,129 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use thiserror::Error;
+
+/// Errors returned by the loaders of models and images
+#[derive(Debug, Error)]
+pub enum RaycaError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// Malformed data, with a description of what could not be parsed
+    #[error("Failed to parse {0}")]
+    Parse(String),
+
+    /// Valid data using features which are not implemented
+    #[error("Not supported: {0}")]
+    Unsupported(String),
+
+    /// Geometry which can not be rendered, see `Config::strict_geometry`
+    #[error("Invalid geometry: {0}")]
+    InvalidGeometry(String),
+
+    /// Data referring to an element which does not exist
+    #[error("Invalid handle: {0}")]
+    InvalidHandle(String),
+
+    /// Background job which panicked, with the message of the panic
+    #[error("Job panicked: {0}")]
+    Panic(String),
+}
+
+impl From<gltf::Error> for RaycaError {
+    fn from(err: gltf::Error) -> Self {
+        match err {
+            gltf::Error::Io(err) => Self::Io(err),
+            // Typically extensions required by the file, such as mesh compression, or
+            // accessors without data, which is stored compressed when the extension is optional
+            gltf::Error::Validation(errors)
+                if errors.iter().all(|(path, err)| match err {
+                    gltf::json::validation::Error::Unsupported => true,
+                    gltf::json::validation::Error::Missing => {
+                        let path = path.to_string();
+                        path.starts_with("accessors[") && path.ends_with(".bufferView")
+                    }
+                    _ => false,
+                }) =>
+            {
+                let paths = errors
+                    .iter()
+                    .map(|(path, _)| path.to_string())
+                    .collect::<Vec<_>>();
+                Self::Unsupported(format!("glTF {}", paths.join(", ")))
+            }
+            err => Self::Parse(format!("glTF: {}", err)),
+        }
+    }
+}
+
+impl From<gltf::json::Error> for RaycaError {
+    fn from(err: gltf::json::Error) -> Self {
+        Self::Parse(format!("glTF JSON: {}", err))
+    }
+}
+
+impl From<base64::DecodeError> for RaycaError {
+    fn from(err: base64::DecodeError) -> Self {
+        Self::Parse(format!("base64 data: {}", err))
+    }
+}
+
+impl From<png::DecodingError> for RaycaError {
+    fn from(err: png::DecodingError) -> Self {
+        match err {
+            png::DecodingError::IoError(err) => Self::Io(err),
+            err => Self::Parse(format!("PNG: {}", err)),
+        }
+    }
+}
+
+impl From<jpeg_decoder::Error> for RaycaError {
+    fn from(err: jpeg_decoder::Error) -> Self {
+        match err {
+            jpeg_decoder::Error::Io(err) => Self::Io(err),
+            err => Self::Parse(format!("JPG: {}", err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Image;
+
+    #[test]
+    fn convert() {
+        let err = RaycaError::from(base64::decode("!").unwrap_err());
+        assert!(matches!(err, RaycaError::Parse(_)));
+        assert!(err.to_string().starts_with("Failed to parse base64"));
+
+        let result = Image::load_png_file("missing.png");
+        assert!(matches!(result, Err(RaycaError::Io(_))));
+
+        let gltf = r#"{
+            "asset": { "version": "2.0" },
+            "extensionsRequired": ["KHR_draco_mesh_compression"]
+        }"#;
+        let result = crate::Model::builder().data(gltf.as_bytes());
+        assert!(matches!(result, Err(RaycaError::Unsupported(_))));
+
+        // Optional compression fails without uncompressed data
+        let gltf = r#"{
+            "asset": { "version": "2.0" },
+            "extensionsUsed": ["KHR_draco_mesh_compression"],
+            "accessors": [{ "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [0, 0, 0] }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 },
+                "extensions": { "KHR_draco_mesh_compression": {
+                    "bufferView": 0, "attributes": { "POSITION": 0 } } } }] }],
+            "nodes": [{ "mesh": 0 }],
+            "scenes": [{ "nodes": [0] }]
+        }"#;
+        let result = crate::Model::builder().data(gltf.as_bytes());
+        assert!(
+            matches!(result, Err(RaycaError::Unsupported(message)) if message.contains("bufferView"))
+        );
+    }
+}
@@ -0,0 +1,521 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Writes models to glTF, together with what rayca adds on top of it, such as the
+//! analytic primitives of `RAYCA_PRIMITIVES`, so that they load back unchanged.
+//! Images, textures, and cameras are not written yet.
+
+use std::{collections::HashMap, path::Path};
+
+use crate::*;
+
+type Json = gltf::json::Value;
+
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+/// Vertex data and indices of a model, stored in a single glTF buffer
+#[derive(Default)]
+struct GltfBuffer {
+    data: Vec<u8>,
+    views: Vec<Json>,
+    accessors: Vec<Json>,
+}
+
+impl GltfBuffer {
+    /// Adds an accessor to the bytes appended after `offset`, returning its index
+    fn push_accessor(
+        &mut self,
+        offset: usize,
+        component_type: u32,
+        count: usize,
+        kind: &str,
+        mut properties: Vec<(&str, Json)>,
+    ) -> usize {
+        let view: Json = vec![
+            ("buffer", Json::from(0)),
+            ("byteOffset", offset.into()),
+            ("byteLength", (self.data.len() - offset).into()),
+        ]
+        .into_iter()
+        .collect();
+        self.views.push(view);
+
+        properties.extend([
+            ("bufferView", (self.views.len() - 1).into()),
+            ("componentType", component_type.into()),
+            ("count", count.into()),
+            ("type", kind.into()),
+        ]);
+        self.accessors.push(properties.into_iter().collect());
+        self.accessors.len() - 1
+    }
+
+    /// Appends vectors of `N` floats, with their bounds when `bounds` is set
+    fn push_floats<const N: usize>(&mut self, values: &[[f32; N]], bounds: bool) -> usize {
+        let offset = self.data.len();
+        for value in values {
+            for component in value {
+                self.data.extend_from_slice(&component.to_le_bytes());
+            }
+        }
+
+        let mut properties = vec![];
+        if bounds {
+            let mut min = [f32::MAX; N];
+            let mut max = [f32::MIN; N];
+            for value in values {
+                for i in 0..N {
+                    min[i] = min[i].min(value[i]);
+                    max[i] = max[i].max(value[i]);
+                }
+            }
+            properties.push(("min", min.to_vec().into()));
+            properties.push(("max", max.to_vec().into()));
+        }
+
+        let kind = format!("VEC{}", N);
+        self.push_accessor(offset, FLOAT, values.len(), &kind, properties)
+    }
+
+    fn push_indices(&mut self, indices: &[u32]) -> usize {
+        let offset = self.data.len();
+        for index in indices {
+            self.data.extend_from_slice(&index.to_le_bytes());
+        }
+        self.push_accessor(offset, UNSIGNED_INT, indices.len(), "SCALAR", vec![])
+    }
+
+    /// Returns the glTF buffer, embedding its data as a base64 URI
+    fn to_json(&self) -> Json {
+        let uri = format!(
+            "data:application/octet-stream;base64,{}",
+            base64::encode(&self.data)
+        );
+        vec![
+            ("byteLength", Json::from(self.data.len())),
+            ("uri", uri.into()),
+        ]
+        .into_iter()
+        .collect()
+    }
+}
+
+/// Returns the glTF `extras` of `extras`, the inverse of how they are loaded,
+/// where values are JSON text and an empty key holds extras which are not an object
+fn get_extras_json(extras: &Extras) -> Result<Option<Json>, RaycaError> {
+    if extras.is_empty() {
+        return Ok(None);
+    }
+    if let Some(value) = extras.get("") {
+        return Ok(Some(gltf::json::deserialize::from_str(value)?));
+    }
+    let properties = extras
+        .iter()
+        .map(|(key, value)| {
+            Ok((
+                key.clone(),
+                gltf::json::deserialize::from_str::<Json>(value)?,
+            ))
+        })
+        .collect::<Result<Vec<_>, RaycaError>>()?;
+    Ok(Some(properties.into_iter().collect()))
+}
+
+fn get_geometry_name(geometry: &Geometry) -> &'static str {
+    match geometry {
+        Geometry::Triangles(_) => "triangles",
+        Geometry::Sphere(_) => "sphere",
+        Geometry::PointCloud(_) => "point cloud",
+        Geometry::Curves(_) => "curves",
+        Geometry::Heightfield(_) => "heightfield",
+        Geometry::Billboards(_) => "billboards",
+        Geometry::Sdf(_) => "SDF",
+    }
+}
+
+fn get_material_json(material: &Material) -> Result<Json, RaycaError> {
+    let color = &material.color;
+    let pbr: Json = vec![
+        (
+            "baseColorFactor",
+            Json::from(vec![color.r, color.g, color.b, color.a]),
+        ),
+        ("metallicFactor", material.metallic_factor.into()),
+        ("roughnessFactor", material.roughness_factor.into()),
+    ]
+    .into_iter()
+    .collect();
+
+    let mut properties = vec![("pbrMetallicRoughness", pbr)];
+    if let Some(graph) = &material.graph {
+        let extensions = vec![(RAYCA_MATERIAL_GRAPH, graph.to_json())];
+        properties.push(("extensions", extensions.into_iter().collect()));
+    }
+    if let Some(extras) = get_extras_json(&material.extras)? {
+        properties.push(("extras", extras));
+    }
+    Ok(properties.into_iter().collect())
+}
+
+fn get_primitive_json(
+    triangles: &Triangles,
+    material: Option<usize>,
+    buffer: &mut GltfBuffer,
+) -> Json {
+    let vertices = &triangles.vertices;
+    let positions: Vec<[f32; 3]> = vertices
+        .iter()
+        .map(|vertex| [vertex.pos.get_x(), vertex.pos.get_y(), vertex.pos.get_z()])
+        .collect();
+    let normals: Vec<[f32; 3]> = vertices
+        .iter()
+        .map(|vertex| {
+            let normal = &vertex.ext.normal;
+            [normal.get_x(), normal.get_y(), normal.get_z()]
+        })
+        .collect();
+    let uvs: Vec<[f32; 2]> = vertices
+        .iter()
+        .map(|vertex| [vertex.ext.uv.x, vertex.ext.uv.y])
+        .collect();
+
+    let attributes: Json = vec![
+        ("POSITION", Json::from(buffer.push_floats(&positions, true))),
+        ("NORMAL", buffer.push_floats(&normals, false).into()),
+        ("TEXCOORD_0", buffer.push_floats(&uvs, false).into()),
+    ]
+    .into_iter()
+    .collect();
+
+    let mut properties = vec![("attributes", attributes)];
+    if !triangles.indices.is_empty() {
+        let indices = buffer.push_indices(&triangles.get_indices());
+        properties.push(("indices", indices.into()));
+    }
+    if let Some(material) = material {
+        properties.push(("material", material.into()));
+    }
+    properties.into_iter().collect()
+}
+
+impl Model {
+    /// Returns this model as a glTF file, with its data embedded
+    pub fn to_gltf(&self) -> Result<String, RaycaError> {
+        let mut buffer = GltfBuffer::default();
+        let mut extensions_used = vec![];
+
+        let mut material_indices = HashMap::new();
+        let mut materials = vec![];
+        for handle in self.materials.handles() {
+            let material = self.materials.get(handle).unwrap();
+            if material.graph.is_some() && !extensions_used.contains(&RAYCA_MATERIAL_GRAPH) {
+                extensions_used.push(RAYCA_MATERIAL_GRAPH);
+            }
+            material_indices.insert(handle, materials.len());
+            materials.push(get_material_json(material)?);
+        }
+
+        // Spheres go to the nodes using their mesh, as glTF meshes can not represent them
+        let mut mesh_indices = HashMap::new();
+        let mut meshes = vec![];
+        for handle in self.meshes.handles() {
+            let mut primitives = vec![];
+            for primitive in &self.meshes.get(handle).unwrap().primitives {
+                let Some(primitive) = self.primitives.get(*primitive) else {
+                    continue;
+                };
+                match &primitive.geometry {
+                    Geometry::Triangles(triangles) if !triangles.vertices.is_empty() => {
+                        let material = material_indices.get(&primitive.material).copied();
+                        primitives.push(get_primitive_json(triangles, material, &mut buffer));
+                    }
+                    Geometry::Triangles(_) | Geometry::Sphere(_) => (),
+                    geometry => {
+                        print_warning!("Skipping", "{} primitive", get_geometry_name(geometry))
+                    }
+                }
+            }
+            if !primitives.is_empty() {
+                let mesh = vec![("primitives", Json::from(primitives))];
+                mesh_indices.insert(handle, meshes.len());
+                meshes.push(mesh.into_iter().collect::<Json>());
+            }
+        }
+
+        let node_indices: HashMap<_, _> = self
+            .nodes
+            .handles()
+            .enumerate()
+            .map(|(index, handle)| (handle, index))
+            .collect();
+        let get_node_indices = |children: &[Handle<Node>]| {
+            children
+                .iter()
+                .filter_map(|child| node_indices.get(child).copied())
+                .collect::<Vec<_>>()
+        };
+
+        let mut nodes = vec![];
+        for handle in self.nodes.handles() {
+            let node = self.nodes.get(handle).unwrap();
+            let trs = &node.trs;
+            let translation = &trs.translation;
+            let rotation = &trs.rotation;
+            let scale = &trs.scale;
+            let mut properties = vec![
+                ("name", Json::from(node.name.clone())),
+                (
+                    "translation",
+                    vec![
+                        translation.get_x(),
+                        translation.get_y(),
+                        translation.get_z(),
+                    ]
+                    .into(),
+                ),
+                (
+                    "rotation",
+                    vec![
+                        rotation.get_x(),
+                        rotation.get_y(),
+                        rotation.get_z(),
+                        rotation.get_w(),
+                    ]
+                    .into(),
+                ),
+                (
+                    "scale",
+                    vec![scale.get_x(), scale.get_y(), scale.get_z()].into(),
+                ),
+            ];
+            let children = get_node_indices(&node.children);
+            if !children.is_empty() {
+                properties.push(("children", children.into()));
+            }
+            if let Some(mesh) = mesh_indices.get(&node.mesh) {
+                properties.push(("mesh", (*mesh).into()));
+            }
+            if let Some(extras) = get_extras_json(&node.extras)? {
+                properties.push(("extras", extras));
+            }
+
+            let primitives = self.get_node_primitives_json(node, &material_indices);
+            if let Some(primitives) = primitives {
+                if !extensions_used.contains(&RAYCA_PRIMITIVES) {
+                    extensions_used.push(RAYCA_PRIMITIVES);
+                }
+                let extensions = vec![(RAYCA_PRIMITIVES, primitives)];
+                properties.push(("extensions", extensions.into_iter().collect()));
+            }
+            nodes.push(properties.into_iter().collect::<Json>());
+        }
+
+        let asset: Json = vec![("version", "2.0"), ("generator", "rayca")]
+            .into_iter()
+            .collect();
+        let scene: Json = vec![("nodes", Json::from(get_node_indices(&self.root.children)))]
+            .into_iter()
+            .collect();
+        let mut properties = vec![
+            ("asset", asset),
+            ("scene", 0.into()),
+            ("scenes", vec![scene].into()),
+            ("nodes", nodes.into()),
+            ("meshes", meshes.into()),
+            ("materials", materials.into()),
+        ];
+        if !buffer.data.is_empty() {
+            properties.push(("buffers", vec![buffer.to_json()].into()));
+            properties.push(("bufferViews", buffer.views.into()));
+            properties.push(("accessors", buffer.accessors.into()));
+        }
+        if !extensions_used.is_empty() {
+            properties.push(("extensionsUsed", extensions_used.into()));
+        }
+        // Extras of the root go to the whole file, as they come from it when loading
+        if let Some(extras) = get_extras_json(&self.root.extras)? {
+            properties.push(("extras", extras));
+        }
+
+        let gltf: Json = properties.into_iter().collect();
+        Ok(gltf::json::serialize::to_string_pretty(&gltf)?)
+    }
+
+    /// Writes this model to a glTF file, see `to_gltf()`
+    pub fn save_gltf<P: AsRef<Path>>(&self, path: P) -> Result<(), RaycaError> {
+        std::fs::write(path, self.to_gltf()?)?;
+        Ok(())
+    }
+
+    /// Returns the `RAYCA_PRIMITIVES` extension of `node`, unless it has no spheres nor quad light
+    fn get_node_primitives_json(
+        &self,
+        node: &Node,
+        material_indices: &HashMap<Handle<Material>, usize>,
+    ) -> Option<Json> {
+        let mut spheres = vec![];
+        if let Some(mesh) = self.meshes.get(node.mesh) {
+            for primitive in &mesh.primitives {
+                let Some(primitive) = self.primitives.get(*primitive) else {
+                    continue;
+                };
+                if let Geometry::Sphere(sphere) = &primitive.geometry {
+                    let center = &sphere.center;
+                    let mut properties = vec![
+                        (
+                            "center",
+                            Json::from(vec![center.get_x(), center.get_y(), center.get_z()]),
+                        ),
+                        ("radius", sphere.get_radius().into()),
+                    ];
+                    if let Some(material) = material_indices.get(&primitive.material) {
+                        properties.push(("material", (*material).into()));
+                    }
+                    spheres.push(properties.into_iter().collect::<Json>());
+                }
+            }
+        }
+
+        let mut properties = vec![];
+        if !spheres.is_empty() {
+            properties.push(("spheres", Json::from(spheres)));
+        }
+        match self.lights.get(node.light) {
+            Some(Light::Quad(quad)) => {
+                let quad: Json = vec![
+                    ("width", quad.width),
+                    ("height", quad.height),
+                    ("intensity", quad.get_base_intensity()),
+                ]
+                .into_iter()
+                .collect();
+                properties.push(("quadLight", quad));
+            }
+            Some(_) => print_warning!("Skipping", "light of node {}", node.name),
+            None => (),
+        }
+
+        if properties.is_empty() {
+            None
+        } else {
+            Some(properties.into_iter().collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut model = Model::new();
+        let material = model.materials.push(Material {
+            color: Color::new(1.0, 0.5, 0.25, 1.0),
+            ..Material::new()
+        });
+        let triangle = model.primitives.push(
+            Primitive::builder()
+                .vertices(vec![
+                    Vertex::new(-1.0, 0.0, 0.0),
+                    Vertex::new(1.0, 0.0, 0.0),
+                    Vertex::new(0.0, 1.0, 0.0),
+                ])
+                .indices(vec![0, 1, 2])
+                .material(material)
+                .build(),
+        );
+        let sphere = model.primitives.push(
+            Primitive::builder()
+                .sphere(Point3::new(0.0, 1.0, 0.0), 0.5)
+                .material(material)
+                .build(),
+        );
+        let mesh = model.meshes.push(Mesh::new(vec![triangle, sphere]));
+        let mut light = Light::quad(2.0, 3.0);
+        light.set_intensity(4.0);
+        let light = model.lights.push(light);
+        let node = model.nodes.push(
+            Node::builder()
+                .name("Lamp".into())
+                .translation(Vec3::new(1.0, 2.0, 3.0))
+                .mesh(mesh)
+                .light(light)
+                .build(),
+        );
+        model.root.children.push(node);
+
+        let gltf = model.to_gltf().unwrap();
+        let loaded = Model::builder()
+            .data(gltf.as_bytes())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let node = loaded.nodes.get(Handle::new(0)).unwrap();
+        assert_eq!(node.name, "Lamp");
+        assert_eq!(node.trs.translation, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(loaded.root.children, vec![Handle::new(0)]);
+
+        let mesh = loaded.meshes.get(node.mesh).unwrap();
+        assert_eq!(mesh.primitives.len(), 2);
+        let triangle = loaded.primitives.get(mesh.primitives[0]).unwrap();
+        match &triangle.geometry {
+            Geometry::Triangles(triangles) => {
+                assert_eq!(triangles.vertices[2].pos, Point3::new(0.0, 1.0, 0.0));
+                assert_eq!(triangles.get_indices(), vec![0, 1, 2]);
+            }
+            _ => panic!("Expected triangles"),
+        }
+        let sphere = loaded.primitives.get(mesh.primitives[1]).unwrap();
+        match &sphere.geometry {
+            Geometry::Sphere(sphere) => {
+                assert_eq!(sphere.center, Point3::new(0.0, 1.0, 0.0));
+                assert_eq!(sphere.get_radius(), 0.5);
+            }
+            _ => panic!("Expected a sphere"),
+        }
+        let material = loaded.materials.get(sphere.material).unwrap();
+        assert_eq!(material.color, Color::new(1.0, 0.5, 0.25, 1.0));
+
+        match loaded.lights.get(node.light) {
+            Some(Light::Quad(quad)) => {
+                assert_eq!((quad.width, quad.height), (2.0, 3.0));
+                assert_eq!(quad.get_base_intensity(), 4.0);
+            }
+            _ => panic!("Expected a quad light"),
+        }
+    }
+
+    #[test]
+    fn extras() {
+        let mut model = Model::new();
+        let mut material = Material::new();
+        material.extras.insert("tag".into(), r#""metal""#.into());
+        model.materials.push(material);
+        let mut node = Node::new();
+        node.extras.insert("id".into(), "42".into());
+        node.extras
+            .insert("info".into(), r#"{"author":"me"}"#.into());
+        model.root.children.push(model.nodes.push(node));
+        model.root.extras.insert(String::new(), "[1,2]".into());
+
+        let gltf = model.to_gltf().unwrap();
+        let loaded = Model::builder()
+            .data(gltf.as_bytes())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let material = loaded.materials.get(Handle::new(0)).unwrap();
+        assert_eq!(
+            material.extras,
+            model.materials.get(Handle::new(0)).unwrap().extras
+        );
+        let node = loaded.nodes.get(Handle::new(0)).unwrap();
+        assert_eq!(node.extras, model.nodes.get(Handle::new(0)).unwrap().extras);
+        assert_eq!(loaded.root.extras, model.root.extras);
+    }
+}
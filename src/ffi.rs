@@ -0,0 +1,210 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! C ABI for embedding the renderer in other applications.
+//! See `include/rayca.h` for the corresponding declarations.
+
+use std::{
+    os::raw::c_int,
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use crate::*;
+
+/// Returned by functions which succeeded
+pub const RAYCA_OK: c_int = 0;
+/// Returned when an argument is null or invalid
+pub const RAYCA_INVALID_ARGUMENT: c_int = -1;
+/// Returned when a model can not be loaded
+pub const RAYCA_LOAD_FAILED: c_int = -2;
+/// Returned when the renderer panicked, as unwinding into C is undefined behavior
+pub const RAYCA_PANIC: c_int = -3;
+
+/// Runs `f`, stopping any panic at the C boundary
+fn guard<T>(f: impl FnOnce() -> T, on_panic: T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| {
+        print_warning!("FFI", "Recovered from a panic");
+        on_panic
+    })
+}
+
+/// Creates an empty scene, or returns null on failure.
+/// It must be destroyed with `rayca_scene_destroy`.
+#[no_mangle]
+pub extern "C" fn rayca_scene_create() -> *mut Scene {
+    guard(|| Box::into_raw(Box::new(Scene::new())), ptr::null_mut())
+}
+
+/// Destroys a scene created with `rayca_scene_create`. Passing null does nothing.
+///
+/// # Safety
+/// `scene` must be null or a pointer returned by `rayca_scene_create` not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn rayca_scene_destroy(scene: *mut Scene) {
+    if !scene.is_null() {
+        guard(|| drop(Box::from_raw(scene)), ());
+    }
+}
+
+/// Loads a binary glTF, or a glTF with embedded resources, from memory
+/// and appends it to the scene.
+///
+/// # Safety
+/// `scene` must be a valid scene and `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rayca_scene_load_gltf(
+    scene: *mut Scene,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    guard(
+        || {
+            let Some(scene) = scene.as_mut() else {
+                return RAYCA_INVALID_ARGUMENT;
+            };
+            if data.is_null() {
+                return RAYCA_INVALID_ARGUMENT;
+            }
+
+            let data = slice::from_raw_parts(data, len);
+            match Model::builder().data(data).and_then(|mut b| b.build()) {
+                Ok(model) => {
+                    scene.push(model);
+                    RAYCA_OK
+                }
+                Err(err) => {
+                    print_warning!("FFI", "Failed to load glTF: {}", err);
+                    RAYCA_LOAD_FAILED
+                }
+            }
+        },
+        RAYCA_PANIC,
+    )
+}
+
+/// Sets the transform and vertical field of view of the camera used for rendering.
+/// When the scene has no camera, the default camera and lights are added first.
+///
+/// # Safety
+/// `scene` must be a valid scene, `translation` must point to 3 floats,
+/// and `rotation` must point to a quaternion of 4 floats in `x, y, z, w` order.
+#[no_mangle]
+pub unsafe extern "C" fn rayca_scene_set_camera(
+    scene: *mut Scene,
+    translation: *const f32,
+    rotation: *const f32,
+    yfov_radians: f32,
+) -> c_int {
+    guard(
+        || {
+            let Some(scene) = scene.as_mut() else {
+                return RAYCA_INVALID_ARGUMENT;
+            };
+            if translation.is_null() || rotation.is_null() {
+                return RAYCA_INVALID_ARGUMENT;
+            }
+            let t = slice::from_raw_parts(translation, 3);
+            let r = slice::from_raw_parts(rotation, 4);
+
+            let camera_node_handle = match scene.get_camera_node_handle() {
+                Some(handle) => handle,
+                None => {
+                    scene.push_default_model();
+                    scene.get_camera_node_handle().unwrap()
+                }
+            };
+            let camera_node = scene.model.nodes.get_mut(camera_node_handle).unwrap();
+            camera_node.trs.translation = Vec3::new(t[0], t[1], t[2]);
+            camera_node.trs.rotation = Quat::new(r[0], r[1], r[2], r[3]);
+            let camera_handle = camera_node.camera;
+            scene
+                .model
+                .cameras
+                .get_mut(camera_handle)
+                .unwrap()
+                .yfov_radians = yfov_radians;
+
+            RAYCA_OK
+        },
+        RAYCA_PANIC,
+    )
+}
+
+/// Renders the scene into a caller-provided RGBA8 buffer of `width * height * 4` bytes.
+/// When the scene has no camera, the default camera and lights are added first.
+///
+/// # Safety
+/// `scene` must be a valid scene and `buffer` must point to `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rayca_scene_render(
+    scene: *mut Scene,
+    width: u32,
+    height: u32,
+    buffer: *mut u8,
+    len: usize,
+) -> c_int {
+    guard(
+        || {
+            let Some(scene) = scene.as_mut() else {
+                return RAYCA_INVALID_ARGUMENT;
+            };
+            let size = width as usize * height as usize * 4;
+            if buffer.is_null() || len < size || size == 0 {
+                return RAYCA_INVALID_ARGUMENT;
+            }
+
+            if scene.get_camera_node_handle().is_none() {
+                scene.push_default_model();
+            }
+
+            let mut image = Image::new(width, height, ColorType::RGBA8);
+            scene.draw(&mut image);
+
+            let buffer = slice::from_raw_parts_mut(buffer, size);
+            buffer.copy_from_slice(image.bytes());
+
+            RAYCA_OK
+        },
+        RAYCA_PANIC,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render() {
+        unsafe {
+            let scene = rayca_scene_create();
+            let mut buffer = vec![0u8; 8 * 4 * 4];
+            assert_eq!(
+                rayca_scene_render(scene, 8, 4, buffer.as_mut_ptr(), 16),
+                RAYCA_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                rayca_scene_load_gltf(scene, b"invalid".as_ptr(), 7),
+                RAYCA_LOAD_FAILED
+            );
+
+            let translation = [0.0, 0.0, 2.0];
+            let rotation = [0.0, 0.0, 0.0, 1.0];
+            assert_eq!(
+                rayca_scene_set_camera(scene, translation.as_ptr(), rotation.as_ptr(), 1.0),
+                RAYCA_OK
+            );
+            assert_eq!(
+                rayca_scene_render(scene, 8, 4, buffer.as_mut_ptr(), buffer.len()),
+                RAYCA_OK
+            );
+            rayca_scene_destroy(scene);
+        }
+    }
+    #[test]
+    fn panic() {
+        assert_eq!(guard(|| panic!("unwinding"), RAYCA_PANIC), RAYCA_PANIC);
+        assert_eq!(guard(|| RAYCA_OK, RAYCA_PANIC), RAYCA_OK);
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use crate::*;
+
+/// Rectangle turned towards the rendering camera, like a particle or an impostor
+#[derive(Debug, Clone)]
+pub struct Billboard {
+    pub position: Point3,
+    pub width: f32,
+    pub height: f32,
+    pub color: Color,
+}
+
+impl Billboard {
+    pub fn new(position: Point3, width: f32, height: f32) -> Self {
+        Self {
+            position,
+            width,
+            height,
+            color: Color::white(),
+        }
+    }
+}
+
+/// Billboards sharing the material of their primitive, whose textures cover every
+/// billboard from the top-left corner to the bottom-right one. Like displaced triangles,
+/// they are turned towards the camera while collecting primitives, and stored in world space
+#[derive(Debug, Clone, Default)]
+pub struct Billboards {
+    pub billboards: Vec<Billboard>,
+
+    /// When set, billboards fade from their center to their border,
+    /// which hides the edges of particles without a texture
+    pub soft: bool,
+}
+
+impl Billboards {
+    pub fn new(billboards: Vec<Billboard>) -> Self {
+        Self {
+            billboards,
+            soft: false,
+        }
+    }
+
+    pub fn primitives(
+        &self,
+        node: Handle<Node>,
+        material: Handle<Material>,
+        model: &Model,
+    ) -> Vec<BvhPrimitive> {
+        let mut ret = vec![];
+
+        let trs = &model.solved_trs.get(&node).unwrap().trs;
+        let scale = (trs.scale.get_x() + trs.scale.get_y() + trs.scale.get_z()) / 3.0;
+        let camera = model.get_render_camera();
+
+        for billboard in &self.billboards {
+            let center = trs * billboard.position;
+            // Without a camera, billboards face the positive Z axis
+            let normal = camera
+                .map(|(camera_position, _)| camera_position - Vec3::from(center))
+                .filter(|dir| dir.len() > 0.0)
+                .unwrap_or(Vec3::new(0.0, 0.0, 1.0))
+                .get_normalized();
+            // Billboards stay upright unless seen from above or below
+            let up = if normal.get_y().abs() < 0.999 {
+                Vec3::new(0.0, 1.0, 0.0)
+            } else {
+                Vec3::new(0.0, 0.0, -1.0)
+            };
+            let right = up.cross(&normal).get_normalized();
+            let up = normal.cross(&right);
+
+            let vertex = |x: f32, y: f32, alpha: f32| {
+                let offset =
+                    right * (x * billboard.width * scale) + up * (y * billboard.height * scale);
+                let mut color = billboard.color;
+                color.a *= alpha;
+                Vertex {
+                    pos: center + offset,
+                    ext: VertexExt {
+                        uv: Vec2::new(x + 0.5, 0.5 - y),
+                        color,
+                        normal,
+                        tangent: right,
+                        bitangent: up,
+                        ..Default::default()
+                    },
+                }
+            };
+
+            let edge_alpha = if self.soft { 0.0 } else { 1.0 };
+            let corners = [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)]
+                .map(|(x, y)| vertex(x, y, edge_alpha));
+            let triangles = if self.soft {
+                // Alpha goes from the center to the corners
+                let middle = vertex(0.0, 0.0, 1.0);
+                (0..4)
+                    .map(|i| [middle, corners[i], corners[(i + 1) % 4]])
+                    .collect()
+            } else {
+                vec![
+                    [corners[0], corners[1], corners[2]],
+                    [corners[0], corners[2], corners[3]],
+                ]
+            };
+
+            for [a, b, c] in triangles {
+                let geometry = BvhGeometry::Triangle(Box::new(BvhTriangle::new(a, b, c)));
+                ret.push(BvhPrimitive::new(geometry, node, material));
+            }
+        }
+
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn billboards() {
+        let mut model = Model::new();
+        let billboards = Billboards::new(vec![Billboard::new(Point3::default(), 1.0, 2.0)]);
+        let primitive = model
+            .primitives
+            .push(Primitive::builder().billboards(billboards).build());
+        let mesh = model.meshes.push(Mesh::new(vec![primitive]));
+        let node = model.nodes.push(Node::builder().mesh(mesh).build());
+        let camera = model.cameras.push(Camera::default());
+        let camera_node = model.nodes.push(
+            Node::builder()
+                .camera(camera)
+                .translation(Vec3::new(4.0, 0.0, 0.0))
+                .build(),
+        );
+        model.root.children = vec![node, camera_node];
+
+        // Billboards face the camera, keeping their height along the Y axis
+        let primitives = model.collect();
+        assert_eq!(primitives.len(), 2);
+        let BvhGeometry::Triangle(triangle) = &primitives[0].geometry else {
+            panic!("Expected a triangle");
+        };
+        let normal = triangle.get_geometric_normal();
+        assert!(normal.close(&Vec3::new(1.0, 0.0, 0.0)));
+        let max = primitives[0].max(&model);
+        assert!((max.get_y() - 1.0).abs() < 1e-5);
+        assert!(max.get_x().abs() < 1e-5);
+
+        // Soft billboards fade towards their corners
+        let Geometry::Billboards(billboards) =
+            &mut model.primitives.get_mut(primitive).unwrap().geometry
+        else {
+            panic!("Expected billboards");
+        };
+        billboards.soft = true;
+        let primitives = model.collect();
+        assert_eq!(primitives.len(), 4);
+        let BvhGeometry::Triangle(triangle) = &primitives[0].geometry else {
+            panic!("Expected a triangle");
+        };
+        assert_eq!(triangle.vertices[0].ext.color.a, 1.0);
+        assert_eq!(triangle.vertices[1].ext.color.a, 0.0);
+    }
+}
@@ -0,0 +1,75 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use crate::*;
+
+/// A strand made of cubic Bezier segments sharing their end points,
+/// hence with `3 * n + 1` control points for `n` segments.
+#[derive(Debug, Clone, Default)]
+pub struct Curve {
+    pub points: Vec<Point3>,
+    /// One width for each control point
+    pub widths: Vec<f32>,
+    pub color: Color,
+}
+
+impl Curve {
+    pub fn new(points: Vec<Point3>, widths: Vec<f32>) -> Self {
+        Self {
+            points,
+            widths,
+            color: Color::white(),
+        }
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.points.len().saturating_sub(1) / 3
+    }
+}
+
+/// Groomed hair or fur
+#[derive(Debug, Clone, Default)]
+pub struct Curves {
+    pub curves: Vec<Curve>,
+}
+
+impl Curves {
+    pub fn new(curves: Vec<Curve>) -> Self {
+        Self { curves }
+    }
+
+    pub fn primitives(
+        &self,
+        node: Handle<Node>,
+        material: Handle<Material>,
+        model: &Model,
+    ) -> Vec<BvhPrimitive> {
+        let mut ret = vec![];
+
+        // Like triangles, curves are transformed to world space
+        let trs = model.solved_trs.get(&node).unwrap();
+        let scale = &trs.trs.scale;
+        let width_scale = (scale.get_x() + scale.get_y() + scale.get_z()) / 3.0;
+
+        for curve in &self.curves {
+            assert!(curve.widths.len() == curve.points.len());
+
+            for i in 0..curve.segment_count() {
+                let mut points = [Point3::default(); 4];
+                let mut widths = [0.0; 4];
+                for j in 0..4 {
+                    points[j] = &trs.trs * curve.points[i * 3 + j];
+                    widths[j] = curve.widths[i * 3 + j] * width_scale;
+                }
+
+                let mut bvh_curve = BvhCurve::new(points, widths);
+                bvh_curve.color = curve.color;
+                let geometry = BvhGeometry::Curve(Box::new(bvh_curve));
+                ret.push(BvhPrimitive::new(geometry, node, material));
+            }
+        }
+
+        ret
+    }
+}
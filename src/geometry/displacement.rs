@@ -0,0 +1,230 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Displacement of triangles by the height map of their material, tessellated while
+//! collecting primitives so that meshes do not need to be subdivided beforehand.
+
+use crate::*;
+
+/// Returns the vertex at barycentric coordinates `weights` of the triangle `vertices`
+fn interpolate(vertices: &[Vertex; 3], weights: [f32; 3]) -> Vertex {
+    let [a, b, c] = vertices;
+    let [wa, wb, wc] = weights;
+    let mut ret = *a;
+    ret.pos =
+        Point3::from(Vec3::from(a.pos) * wa + Vec3::from(b.pos) * wb + Vec3::from(c.pos) * wc);
+    ret.ext.uv = a.ext.uv * wa + b.ext.uv * wb + c.ext.uv * wc;
+    ret.ext.uv1 = a.ext.uv1 * wa + b.ext.uv1 * wb + c.ext.uv1 * wc;
+    ret.ext.color = a.ext.color * wa + b.ext.color * wb + c.ext.color * wc;
+    ret.ext.normal = a.ext.normal * wa + b.ext.normal * wb + c.ext.normal * wc;
+    ret.ext.tangent = a.ext.tangent * wa + b.ext.tangent * wb + c.ext.tangent * wc;
+    ret.ext.bitangent = a.ext.bitangent * wa + b.ext.bitangent * wb + c.ext.bitangent * wc;
+    ret
+}
+
+/// Controls how finely displaced triangles are tessellated: edges are split
+/// until they are shorter than `edge_pixels` on the image of the rendering camera
+#[derive(Debug, Clone, Copy)]
+pub struct Tessellation {
+    pub edge_pixels: f32,
+
+    /// Height of the rendered image in pixels, which `Scene` keeps up to date
+    pub resolution: u32,
+
+    /// Maximum number of times an edge is split in half
+    pub max_level: u32,
+}
+
+impl Default for Tessellation {
+    fn default() -> Self {
+        Self {
+            edge_pixels: 4.0,
+            resolution: 512,
+            max_level: 6,
+        }
+    }
+}
+
+impl Tessellation {
+    /// Returns how many times the edge from `a` to `b` should be split in half when seen
+    /// by a camera at `camera_position` with `camera_angle`. Without a camera, edges are
+    /// split as much as possible. Edges shared by triangles get the same level
+    pub fn get_edge_level(&self, a: &Point3, b: &Point3, camera: Option<(Vec3, f32)>) -> u32 {
+        let Some((camera_position, camera_angle)) = camera else {
+            return self.max_level;
+        };
+        let length = (*b - *a).len();
+        let middle = (Vec3::from(*a) + Vec3::from(*b)) * 0.5;
+        let distance = (middle - camera_position).len().max(f32::EPSILON);
+        // Screens are `2 * distance * camera_angle` high at that distance
+        let mut pixels = length / (2.0 * distance * camera_angle) * self.resolution as f32;
+        let mut level = 0;
+        while pixels > self.edge_pixels && level < self.max_level {
+            pixels /= 2.0;
+            level += 1;
+        }
+        level
+    }
+
+    /// Splits the triangle `vertices` in world space, moving the new vertices along their
+    /// normals by the height map of `material`. Vertices on the edges of the triangle are
+    /// placed on the tessellation of the edge alone, so that neighbours split differently
+    /// do not crack
+    pub fn displace(
+        &self,
+        vertices: [Vertex; 3],
+        material: &Material,
+        model: &Model,
+        camera: Option<(Vec3, f32)>,
+    ) -> Vec<[Vertex; 3]> {
+        let Some(texture) = model.textures.get(material.displacement_texture) else {
+            return vec![vertices];
+        };
+        let image = model.images.get(texture.image).unwrap();
+        let sampler = Sampler::default();
+        let displace = |mut vertex: Vertex| {
+            let height = sampler.sample(image, &vertex.ext.uv).r;
+            let normal = vertex.ext.normal.get_normalized();
+            vertex.pos += normal * (height * material.displacement_scale);
+            vertex
+        };
+
+        // Edges go from a to b, from b to c, and from c to a
+        let edge_levels = [(0, 1), (1, 2), (2, 0)].map(|(start, end)| {
+            self.get_edge_level(&vertices[start].pos, &vertices[end].pos, camera)
+        });
+        let level = edge_levels.iter().copied().max().unwrap();
+        let n = 1usize << level;
+
+        // Returns the displaced point at step `s` of `n` along an edge of `level`
+        let get_edge_point = |edge: usize, s: usize| {
+            let (start, end) = [(0, 1), (1, 2), (2, 0)][edge];
+            let step = 1 << (level - edge_levels[edge]);
+            let point_at = |s: usize| {
+                let mut weights = [0.0; 3];
+                weights[start] = 1.0 - s as f32 / n as f32;
+                weights[end] = s as f32 / n as f32;
+                Vec3::from(displace(interpolate(&vertices, weights)).pos)
+            };
+            let s0 = s / step * step;
+            if s0 == s {
+                return point_at(s);
+            }
+            let t = (s - s0) as f32 / step as f32;
+            point_at(s0) * (1.0 - t) + point_at(s0 + step) * t
+        };
+
+        // Grid of vertices where `i` goes towards b, and `j` towards c,
+        // hence row `j` has `n + 1 - j` vertices
+        let index = |i: usize, j: usize| j * (2 * n + 3 - j) / 2 + i;
+        let mut grid = vec![];
+        for j in 0..=n {
+            for i in 0..=(n - j) {
+                let weights = [
+                    (n - i - j) as f32 / n as f32,
+                    i as f32 / n as f32,
+                    j as f32 / n as f32,
+                ];
+                let mut vertex = interpolate(&vertices, weights);
+                let pos = if j == 0 {
+                    get_edge_point(0, i)
+                } else if i + j == n {
+                    get_edge_point(1, j)
+                } else if i == 0 {
+                    get_edge_point(2, n - j)
+                } else {
+                    Vec3::from(displace(vertex).pos)
+                };
+                vertex.pos = Point3::from(pos);
+                grid.push(vertex);
+            }
+        }
+        debug_assert_eq!(grid.len(), (n + 1) * (n + 2) / 2);
+
+        let mut triangles = vec![];
+        for j in 0..n {
+            for i in 0..(n - j) {
+                triangles.push([index(i, j), index(i + 1, j), index(i, j + 1)]);
+                if i + j + 1 < n {
+                    triangles.push([index(i + 1, j), index(i + 1, j + 1), index(i, j + 1)]);
+                }
+            }
+        }
+
+        // Shading normals follow the displaced surface
+        let mut normals = vec![Vec3::default(); grid.len()];
+        for [a, b, c] in &triangles {
+            let ab = grid[*b].pos - grid[*a].pos;
+            let ac = grid[*c].pos - grid[*a].pos;
+            let normal = ab.cross(&ac);
+            for vertex in [a, b, c] {
+                normals[*vertex] += normal;
+            }
+        }
+        for (vertex, normal) in grid.iter_mut().zip(normals) {
+            if normal.len() > 0.0 {
+                let normal = normal.get_normalized();
+                // Keep the side the original normals were facing
+                let sign = if normal.dot(&vertex.ext.normal) < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                vertex.ext.normal = normal * sign;
+            }
+        }
+
+        triangles
+            .into_iter()
+            .map(|[a, b, c]| [grid[a], grid[b], grid[c]])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn displacement() {
+        let mut model = Model::new();
+        let mut image = Image::new(1, 1, ColorType::RGBA8);
+        image.bytes_mut().fill(0xFF);
+        let image = model.images.push(image);
+        let texture = model.textures.push(Texture::new(image, Handle::NONE));
+        let material = Material {
+            displacement_texture: texture,
+            displacement_scale: 0.5,
+            ..Material::new()
+        };
+
+        let vertices = [
+            Vertex::new(0.0, 0.0, 0.0),
+            Vertex::new(1.0, 0.0, 0.0),
+            Vertex::new(0.0, 1.0, 0.0),
+        ];
+        let tessellation = Tessellation {
+            max_level: 2,
+            ..Default::default()
+        };
+
+        // A white height map lifts the whole triangle along its normal
+        let triangles = tessellation.displace(vertices, &material, &model, None);
+        assert_eq!(triangles.len(), 16);
+        for vertex in triangles.iter().flatten() {
+            assert!((vertex.pos.get_z() - 0.5).abs() < 1e-5);
+            assert!(vertex.ext.normal.close(&Vec3::new(0.0, 0.0, 1.0)));
+        }
+
+        // Far away triangles are not split
+        let camera = Some((Vec3::new(0.0, 0.0, 1000.0), 1.0));
+        let triangles = tessellation.displace(vertices, &material, &model, camera);
+        assert_eq!(triangles.len(), 1);
+
+        // Without a height map, nothing changes
+        let triangles = tessellation.displace(vertices, &Material::new(), &model, None);
+        assert_eq!(triangles.len(), 1);
+        assert_eq!(triangles[0][1].pos, vertices[1].pos);
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use crate::*;
+
+/// Terrain described by a regular grid of heights on the XZ plane, centered at the origin.
+/// It is intersected directly without being triangulated first.
+#[derive(Debug, Clone)]
+pub struct Heightfield {
+    /// Number of grid points along X
+    pub width: usize,
+    /// Number of grid points along Z
+    pub depth: usize,
+    /// Row major heights, `width * depth` of them
+    pub heights: Vec<f32>,
+    /// Distance between two neighbouring grid points
+    pub cell_size: f32,
+}
+
+impl Default for Heightfield {
+    fn default() -> Self {
+        Self::new(2, 2, vec![0.0; 4], 1.0)
+    }
+}
+
+impl Heightfield {
+    pub fn new(width: usize, depth: usize, heights: Vec<f32>, cell_size: f32) -> Self {
+        assert!(width > 1 && depth > 1);
+        assert!(heights.len() == width * depth);
+        Self {
+            width,
+            depth,
+            heights,
+            cell_size,
+        }
+    }
+
+    /// Samples the red channel of a grayscale image with a grid of `width * depth` points,
+    /// interpolating neighbouring pixels to avoid terraces when the grid is finer than the image.
+    /// White maps to `height_scale` and black to zero. The grid spans one unit along
+    /// its longest side, so its size can be controlled with the node scale.
+    pub fn from_image(image: &Image, width: usize, depth: usize, height_scale: f32) -> Self {
+        let sampler = Sampler::default();
+        let (image_width, image_height) = (image.width() as usize, image.height() as usize);
+        let texel = |x: usize, y: usize| {
+            let uv = Vec2::new(
+                (x as f32 + 0.5) / image_width as f32,
+                (y as f32 + 0.5) / image_height as f32,
+            );
+            sampler.sample(image, &uv).r
+        };
+
+        let mut heights = Vec::with_capacity(width * depth);
+        for z in 0..depth {
+            let py = z as f32 / (depth - 1) as f32 * (image_height - 1) as f32;
+            let y0 = py as usize;
+            let y1 = (y0 + 1).min(image_height - 1);
+            let fy = py - y0 as f32;
+            for x in 0..width {
+                let px = x as f32 / (width - 1) as f32 * (image_width - 1) as f32;
+                let x0 = px as usize;
+                let x1 = (x0 + 1).min(image_width - 1);
+                let fx = px - x0 as f32;
+                let top = texel(x0, y0) * (1.0 - fx) + texel(x1, y0) * fx;
+                let bottom = texel(x0, y1) * (1.0 - fx) + texel(x1, y1) * fx;
+                heights.push((top * (1.0 - fy) + bottom * fy) * height_scale);
+            }
+        }
+        let cell_size = 1.0 / (width.max(depth) - 1) as f32;
+        Self::new(width, depth, heights, cell_size)
+    }
+
+    pub fn get_height(&self, x: usize, z: usize) -> f32 {
+        self.heights[z * self.width + x]
+    }
+
+    pub fn primitives(&self, node: Handle<Node>, material: Handle<Material>) -> Vec<BvhPrimitive> {
+        // Like spheres, the heightfield is stored in model space and rays are transformed
+        let heightfield = BvhHeightfield::new(self.clone());
+        let geometry = BvhGeometry::Heightfield(Box::new(heightfield));
+        vec![BvhPrimitive::new(geometry, node, material)]
+    }
+}
@@ -2,18 +2,41 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
+pub mod billboard;
+pub mod curves;
+pub mod displacement;
+pub mod heightfield;
+pub mod normals;
+pub mod point_cloud;
+pub mod sdf;
+pub mod shapes;
+pub mod simplify;
 pub mod sphere;
 pub mod triangles;
+pub mod unwrap;
 pub mod vertex;
+pub mod weld;
 
+pub use billboard::*;
+pub use curves::*;
+pub use displacement::*;
+pub use heightfield::*;
+pub use point_cloud::*;
+pub use sdf::*;
 pub use sphere::*;
 pub use triangles::*;
+pub use unwrap::*;
 pub use vertex::*;
 
 #[derive(Debug, Clone)]
 pub enum Geometry {
     Triangles(Triangles),
     Sphere(Sphere),
+    PointCloud(PointCloud),
+    Curves(Curves),
+    Heightfield(Heightfield),
+    Billboards(Billboards),
+    Sdf(Sdf),
 }
 
 impl Default for Geometry {
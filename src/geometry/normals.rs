@@ -0,0 +1,194 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::collections::HashMap;
+
+use crate::*;
+
+/// Returns a key identifying vertices at the same position
+fn position_key(pos: &Point3) -> [u32; 3] {
+    [
+        pos.get_x().to_bits(),
+        pos.get_y().to_bits(),
+        pos.get_z().to_bits(),
+    ]
+}
+
+impl Triangles {
+    /// Computes vertex normals averaging the normals of the triangles around each vertex,
+    /// weighted by their area. Triangles whose normals differ by more than `angle_threshold`
+    /// radians do not contribute to each other, leaving a hard edge between them, so that
+    /// zero results in flat shading. Vertices are split when they need different normals.
+    pub fn compute_smooth_normals(&mut self, angle_threshold: f32) {
+        let indices = self.get_indices();
+        if indices.is_empty() {
+            return;
+        }
+        let triangle_count = indices.len() / 3;
+        let cos_threshold = angle_threshold.cos() - 1e-4;
+
+        // Area weighted, and normalized normals of the triangles
+        let mut area_normals = Vec::with_capacity(triangle_count);
+        let mut unit_normals = Vec::with_capacity(triangle_count);
+        for triangle in indices.chunks_exact(3) {
+            let a = self.vertices[triangle[0] as usize].pos;
+            let b = self.vertices[triangle[1] as usize].pos;
+            let c = self.vertices[triangle[2] as usize].pos;
+            let normal = (b - a).cross(&(c - a));
+            area_normals.push(normal);
+            unit_normals.push(if normal.len() > 0.0 {
+                normal.get_normalized()
+            } else {
+                Vec3::default()
+            });
+        }
+
+        // Triangles around each position, so that seams of the texture coordinates
+        // do not show up as hard edges
+        let mut triangles_at = HashMap::<[u32; 3], Vec<usize>>::new();
+        for (t, triangle) in indices.chunks_exact(3).enumerate() {
+            for &v in triangle {
+                let key = position_key(&self.vertices[v as usize].pos);
+                triangles_at.entry(key).or_default().push(t);
+            }
+        }
+
+        let mut vertices = Vec::with_capacity(self.vertices.len());
+        let mut new_indices = Vec::with_capacity(indices.len());
+        let mut split = HashMap::<(u32, [u32; 3]), u32>::new();
+        for (t, triangle) in indices.chunks_exact(3).enumerate() {
+            for &v in triangle {
+                let vertex = &self.vertices[v as usize];
+                let mut normal = Vec3::default();
+                for &other in &triangles_at[&position_key(&vertex.pos)] {
+                    if unit_normals[t].dot(unit_normals[other]) >= cos_threshold {
+                        normal += area_normals[other];
+                    }
+                }
+                let normal = if normal.len() > 0.0 {
+                    normal.get_normalized()
+                } else {
+                    vertex.ext.normal
+                };
+
+                let key = [
+                    normal.get_x().to_bits(),
+                    normal.get_y().to_bits(),
+                    normal.get_z().to_bits(),
+                ];
+                let index = *split.entry((v, key)).or_insert_with(|| {
+                    let mut vertex = *vertex;
+                    vertex.ext.normal = normal;
+                    vertices.push(vertex);
+                    vertices.len() as u32 - 1
+                });
+                new_indices.push(index);
+            }
+        }
+
+        self.vertices = vertices;
+        self.set_indices(&new_indices);
+    }
+
+    /// Computes tangents and bitangents from normals and texture coordinates,
+    /// following the same conventions of MikkTSpace used by glTF normal maps:
+    /// tangents are orthogonal to normals, and bitangents are `normal x tangent`
+    /// flipped when the texture is mirrored.
+    pub fn compute_tangents(&mut self) {
+        let indices = self.get_indices();
+
+        let mut tangents = vec![Vec3::default(); self.vertices.len()];
+        let mut bitangents = vec![Vec3::default(); self.vertices.len()];
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| &self.vertices[triangle[i] as usize]);
+            let e1 = b.pos - a.pos;
+            let e2 = c.pos - a.pos;
+            let (du1, dv1) = (b.ext.uv.x - a.ext.uv.x, b.ext.uv.y - a.ext.uv.y);
+            let (du2, dv2) = (c.ext.uv.x - a.ext.uv.x, c.ext.uv.y - a.ext.uv.y);
+
+            let det = du1 * dv2 - du2 * dv1;
+            if det.abs() < f32::EPSILON {
+                continue;
+            }
+            let r = 1.0 / det;
+            let tangent = (e1 * dv2 - e2 * dv1) * r;
+            let bitangent = (e2 * du1 - e1 * du2) * r;
+            for &i in triangle {
+                tangents[i as usize] += tangent;
+                bitangents[i as usize] += bitangent;
+            }
+        }
+
+        for ((vertex, tangent), bitangent) in self.vertices.iter_mut().zip(tangents).zip(bitangents)
+        {
+            let normal = vertex.ext.normal;
+
+            // Gram-Schmidt orthogonalization
+            let mut tangent = tangent - normal * normal.dot(tangent);
+            if tangent.len() < f32::EPSILON {
+                tangent = normal.get_orthonormal_basis().0;
+            }
+            tangent.normalize();
+
+            let handedness = if normal.cross(&tangent).dot(bitangent) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            vertex.ext.tangent = tangent;
+            vertex.ext.bitangent = normal.cross(&tangent) * handedness;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Two faces of a cube sharing an edge
+    fn corner() -> Triangles {
+        let mut vertices = vec![
+            Vertex::new(0.0, 0.0, 0.0),
+            Vertex::new(1.0, 0.0, 0.0),
+            Vertex::new(1.0, 1.0, 0.0),
+            Vertex::new(0.0, 1.0, 0.0),
+            Vertex::new(0.0, 0.0, -1.0),
+            Vertex::new(0.0, 1.0, -1.0),
+        ];
+        for vertex in &mut vertices {
+            vertex.ext.uv = Vec2::new(vertex.pos.get_x(), vertex.pos.get_y());
+        }
+        let mut triangles = Triangles::new(vertices, vec![]);
+        triangles.set_indices(&[0, 1, 2, 0, 2, 3, 4, 0, 3, 4, 3, 5]);
+        triangles
+    }
+
+    #[test]
+    fn normals() {
+        let mut smooth = corner();
+        smooth.compute_smooth_normals(std::f32::consts::PI);
+        assert_eq!(smooth.vertices.len(), 6);
+        let normal = smooth.vertices[0].ext.normal;
+        assert!(normal.get_x() < 0.0 && normal.get_z() > 0.0);
+
+        let mut flat = corner();
+        flat.compute_smooth_normals(0.0);
+        // The vertices on the shared edge are split
+        assert_eq!(flat.vertices.len(), 8);
+        for vertex in &flat.vertices {
+            let n = vertex.ext.normal;
+            assert!(n.close(&Vec3::new(0.0, 0.0, 1.0)) || n.close(&Vec3::new(-1.0, 0.0, 0.0)));
+        }
+    }
+
+    #[test]
+    fn tangents() {
+        let mut triangles = corner();
+        triangles.compute_smooth_normals(0.0);
+        triangles.compute_tangents();
+        let vertex = triangles.vertices[1];
+        assert!(vertex.ext.tangent.close(&Vec3::new(1.0, 0.0, 0.0)));
+        assert!(vertex.ext.bitangent.close(&Vec3::new(0.0, 1.0, 0.0)));
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use crate::*;
+
+/// Points rendered as tiny spheres, useful to visualize scanned data without meshing it.
+/// Every point has its own radius and color.
+#[derive(Debug, Clone, Default)]
+pub struct PointCloud {
+    pub positions: Vec<Point3>,
+    pub radii: Vec<f32>,
+    pub colors: Vec<Color>,
+}
+
+impl PointCloud {
+    /// Creates white points with the same radius
+    pub fn new(positions: Vec<Point3>, radius: f32) -> Self {
+        let radii = vec![radius; positions.len()];
+        let colors = vec![Color::white(); positions.len()];
+        Self {
+            positions,
+            radii,
+            colors,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn primitives(&self, node: Handle<Node>, material: Handle<Material>) -> Vec<BvhPrimitive> {
+        assert!(self.radii.len() == self.len());
+        assert!(self.colors.len() == self.len());
+
+        // Like spheres, points are stored in model space
+        self.positions
+            .iter()
+            .zip(&self.radii)
+            .zip(&self.colors)
+            .map(|((position, radius), color)| {
+                let mut sphere = BvhSphere::new(*position, *radius);
+                sphere.color = *color;
+                BvhPrimitive::new(BvhGeometry::Sphere(sphere), node, material)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn primitives() {
+        let mut cloud = PointCloud::new(
+            vec![Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0)],
+            0.1,
+        );
+        cloud.colors[1] = Color::new(1.0, 0.0, 0.0, 1.0);
+
+        let primitives = cloud.primitives(Handle::NONE, Handle::NONE);
+        assert_eq!(primitives.len(), 2);
+
+        let hit = Hit::new(0.0, Point3::new(1.0, 0.1, 0.0), Vec2::default());
+        let color = primitives[1].geometry.get_color(&hit);
+        assert_eq!(color.g, 0.0);
+    }
+}
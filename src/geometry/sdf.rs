@@ -0,0 +1,332 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use crate::*;
+
+/// Expression returning the signed distance of a point from a shape,
+/// negative inside of it. Distances of blended shapes are bounds rather
+/// than exact distances, which sphere tracing handles with smaller steps
+#[derive(Debug, Clone)]
+pub enum SdfExpr {
+    Sphere {
+        center: Point3,
+        radius: f32,
+    },
+    Box {
+        center: Point3,
+        half_extent: Vec3,
+    },
+    /// Torus lying on the XZ plane, where `major` is the radius of the ring
+    /// and `minor` the radius of its section
+    Torus {
+        center: Point3,
+        major: f32,
+        minor: f32,
+    },
+    Union(Box<SdfExpr>, Box<SdfExpr>),
+    Intersection(Box<SdfExpr>, Box<SdfExpr>),
+    /// The first shape without the second one
+    Subtraction(Box<SdfExpr>, Box<SdfExpr>),
+    /// Union blending the shapes where they are closer than the smoothness
+    SmoothUnion(Box<SdfExpr>, Box<SdfExpr>, f32),
+    /// Inflates a shape, rounding its edges
+    Round(Box<SdfExpr>, f32),
+}
+
+impl SdfExpr {
+    pub fn sphere(center: Point3, radius: f32) -> Self {
+        Self::Sphere { center, radius }
+    }
+
+    pub fn cube(center: Point3, half_extent: Vec3) -> Self {
+        Self::Box {
+            center,
+            half_extent,
+        }
+    }
+
+    pub fn torus(center: Point3, major: f32, minor: f32) -> Self {
+        Self::Torus {
+            center,
+            major,
+            minor,
+        }
+    }
+
+    pub fn union(self, other: SdfExpr) -> Self {
+        Self::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersection(self, other: SdfExpr) -> Self {
+        Self::Intersection(Box::new(self), Box::new(other))
+    }
+
+    pub fn subtraction(self, other: SdfExpr) -> Self {
+        Self::Subtraction(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: SdfExpr, smoothness: f32) -> Self {
+        Self::SmoothUnion(Box::new(self), Box::new(other), smoothness)
+    }
+
+    pub fn round(self, radius: f32) -> Self {
+        Self::Round(Box::new(self), radius)
+    }
+
+    /// See https://iquilezles.org/articles/distfunctions/
+    pub fn get_distance(&self, point: &Point3) -> f32 {
+        match self {
+            Self::Sphere { center, radius } => (point - center).len() - radius,
+            Self::Box {
+                center,
+                half_extent,
+            } => {
+                let p = point - center;
+                let q = Vec3::new(
+                    p.get_x().abs() - half_extent.get_x(),
+                    p.get_y().abs() - half_extent.get_y(),
+                    p.get_z().abs() - half_extent.get_z(),
+                );
+                let outside = Vec3::new(q.get_x().max(0.0), q.get_y().max(0.0), q.get_z().max(0.0));
+                let inside = q.get_x().max(q.get_y()).max(q.get_z()).min(0.0);
+                outside.len() + inside
+            }
+            Self::Torus {
+                center,
+                major,
+                minor,
+            } => {
+                let p = point - center;
+                let ring = (p.get_x() * p.get_x() + p.get_z() * p.get_z()).sqrt() - major;
+                (ring * ring + p.get_y() * p.get_y()).sqrt() - minor
+            }
+            Self::Union(a, b) => a.get_distance(point).min(b.get_distance(point)),
+            Self::Intersection(a, b) => a.get_distance(point).max(b.get_distance(point)),
+            Self::Subtraction(a, b) => a.get_distance(point).max(-b.get_distance(point)),
+            Self::SmoothUnion(a, b, smoothness) => {
+                let (da, db) = (a.get_distance(point), b.get_distance(point));
+                let k = smoothness.max(f32::EPSILON);
+                let h = (0.5 + 0.5 * (db - da) / k).clamp(0.0, 1.0);
+                db * (1.0 - h) + da * h - k * h * (1.0 - h)
+            }
+            Self::Round(shape, radius) => shape.get_distance(point) - radius,
+        }
+    }
+
+    /// Returns the minimum and maximum points of a box containing the shape
+    pub fn get_bounds(&self) -> (Point3, Point3) {
+        match self {
+            Self::Sphere { center, radius } => {
+                let extent = Vec3::splat(*radius);
+                (*center - extent, *center + extent)
+            }
+            Self::Box {
+                center,
+                half_extent,
+            } => (*center - *half_extent, *center + *half_extent),
+            Self::Torus {
+                center,
+                major,
+                minor,
+            } => {
+                let extent = Vec3::new(major + minor, *minor, major + minor);
+                (*center - extent, *center + extent)
+            }
+            Self::Union(a, b) | Self::SmoothUnion(a, b, _) => {
+                let (a, b) = (a.get_bounds(), b.get_bounds());
+                (a.0.min(&b.0), a.1.max(&b.1))
+            }
+            Self::Intersection(a, b) => {
+                let (a, b) = (a.get_bounds(), b.get_bounds());
+                (a.0.max(&b.0), a.1.min(&b.1))
+            }
+            Self::Subtraction(a, _) => a.get_bounds(),
+            Self::Round(shape, radius) => {
+                let (min, max) = shape.get_bounds();
+                let extent = Vec3::splat(*radius);
+                (min - extent, max + extent)
+            }
+        }
+    }
+}
+
+/// Signed distances sampled on a regular grid, interpolated between grid points
+#[derive(Debug, Clone)]
+pub struct SdfGrid {
+    /// Number of grid points along X, Y, and Z
+    pub size: [usize; 3],
+    /// Distances ordered by X, then Y, then Z
+    pub distances: Vec<f32>,
+    /// Position of the first grid point
+    pub origin: Point3,
+    /// Distance between two neighbouring grid points
+    pub cell_size: f32,
+}
+
+impl SdfGrid {
+    pub fn new(size: [usize; 3], distances: Vec<f32>, origin: Point3, cell_size: f32) -> Self {
+        assert!(size.iter().all(|&n| n > 1));
+        assert!(distances.len() == size[0] * size[1] * size[2]);
+        Self {
+            size,
+            distances,
+            origin,
+            cell_size,
+        }
+    }
+
+    /// Samples `expr` with a grid of `size` points covering its bounds
+    pub fn from_expr(expr: &SdfExpr, size: [usize; 3]) -> Self {
+        let (min, max) = expr.get_bounds();
+        let extent = max - min;
+        let cell_size = (0..3)
+            .map(|axis| extent.simd[axis] / (size[axis] - 1) as f32)
+            .fold(f32::EPSILON, f32::max);
+        let mut distances = Vec::with_capacity(size[0] * size[1] * size[2]);
+        for z in 0..size[2] {
+            for y in 0..size[1] {
+                for x in 0..size[0] {
+                    let offset = Vec3::new(x as f32, y as f32, z as f32) * cell_size;
+                    distances.push(expr.get_distance(&(min + offset)));
+                }
+            }
+        }
+        Self::new(size, distances, min, cell_size)
+    }
+
+    fn get(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.distances[(z * self.size[1] + y) * self.size[0] + x]
+    }
+
+    /// Trilinear interpolation of the distances. Points outside of the grid
+    /// add their distance from the grid to the one of the closest grid point
+    pub fn get_distance(&self, point: &Point3) -> f32 {
+        let local = (point - self.origin) * (1.0 / self.cell_size);
+        let mut cell = [0; 3];
+        let mut fraction = [0.0; 3];
+        let mut outside = Vec3::default();
+        for axis in 0..3 {
+            let last = (self.size[axis] - 1) as f32;
+            let g = local.simd[axis];
+            let clamped = g.clamp(0.0, last);
+            outside.simd[axis] = (g - clamped) * self.cell_size;
+            cell[axis] = (clamped as usize).min(self.size[axis] - 2);
+            fraction[axis] = clamped - cell[axis] as f32;
+        }
+
+        let mut ret = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = [0; 3];
+            for axis in 0..3 {
+                let upper = corner & (1 << axis) != 0;
+                index[axis] = cell[axis] + upper as usize;
+                weight *= if upper {
+                    fraction[axis]
+                } else {
+                    1.0 - fraction[axis]
+                };
+            }
+            ret += self.get(index[0], index[1], index[2]) * weight;
+        }
+        ret + outside.len()
+    }
+
+    pub fn get_bounds(&self) -> (Point3, Point3) {
+        let extent = Vec3::new(
+            (self.size[0] - 1) as f32,
+            (self.size[1] - 1) as f32,
+            (self.size[2] - 1) as f32,
+        ) * self.cell_size;
+        (self.origin, self.origin + extent)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SdfShape {
+    Expr(SdfExpr),
+    Grid(SdfGrid),
+}
+
+/// Implicit surface where a signed distance function is zero, which is
+/// intersected by sphere tracing without being triangulated first
+#[derive(Debug, Clone)]
+pub struct Sdf {
+    pub shape: SdfShape,
+    /// Steps taken along a ray before giving up
+    pub max_steps: u32,
+    /// Rays closer than this to the surface hit it
+    pub epsilon: f32,
+}
+
+impl Sdf {
+    pub fn new(shape: SdfShape) -> Self {
+        Self {
+            shape,
+            max_steps: 128,
+            epsilon: 1e-4,
+        }
+    }
+
+    pub fn expr(expr: SdfExpr) -> Self {
+        Self::new(SdfShape::Expr(expr))
+    }
+
+    pub fn grid(grid: SdfGrid) -> Self {
+        Self::new(SdfShape::Grid(grid))
+    }
+
+    pub fn get_distance(&self, point: &Point3) -> f32 {
+        match &self.shape {
+            SdfShape::Expr(expr) => expr.get_distance(point),
+            SdfShape::Grid(grid) => grid.get_distance(point),
+        }
+    }
+
+    pub fn get_bounds(&self) -> (Point3, Point3) {
+        match &self.shape {
+            SdfShape::Expr(expr) => expr.get_bounds(),
+            SdfShape::Grid(grid) => grid.get_bounds(),
+        }
+    }
+
+    pub fn primitives(&self, node: Handle<Node>, material: Handle<Material>) -> Vec<BvhPrimitive> {
+        // Like spheres, the SDF is stored in model space and rays are transformed
+        let sdf = BvhSdf::new(self.clone());
+        let geometry = BvhGeometry::Sdf(Box::new(sdf));
+        vec![BvhPrimitive::new(geometry, node, material)]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn distance() {
+        let sphere = SdfExpr::sphere(Point3::default(), 1.0);
+        assert_eq!(sphere.get_distance(&Point3::new(2.0, 0.0, 0.0)), 1.0);
+        assert_eq!(sphere.get_distance(&Point3::default()), -1.0);
+
+        let cube = SdfExpr::cube(Point3::new(1.0, 0.0, 0.0), Vec3::splat(0.5));
+        assert_eq!(cube.get_distance(&Point3::new(3.0, 0.0, 0.0)), 1.5);
+
+        // Blending fills the gap between shapes
+        let a = SdfExpr::sphere(Point3::new(-1.1, 0.0, 0.0), 1.0);
+        let b = SdfExpr::sphere(Point3::new(1.1, 0.0, 0.0), 1.0);
+        let point = Point3::default();
+        assert!(a.clone().union(b.clone()).get_distance(&point) > 0.0);
+        assert!(a.clone().smooth_union(b, 0.5).get_distance(&point) < 0.0);
+        let (min, max) = a.round(0.5).get_bounds();
+        assert_eq!(min.get_x(), -2.6);
+        assert_eq!(max.get_y(), 1.5);
+
+        // Grids approximate expressions between their points
+        let grid = SdfGrid::from_expr(&sphere, [17, 17, 17]);
+        for point in [Point3::new(0.3, 0.2, 0.1), Point3::new(2.0, 0.0, 0.0)] {
+            let error = grid.get_distance(&point) - sphere.get_distance(&point);
+            assert!(error.abs() < 0.05, "{}", error);
+        }
+    }
+}
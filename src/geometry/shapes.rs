@@ -0,0 +1,320 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Parametric meshes, centered at the origin with Y up, counter-clockwise front faces,
+//! normals, texture coordinates, and tangents.
+
+use std::{
+    collections::HashMap,
+    f32::consts::{PI, TAU},
+};
+
+use crate::*;
+
+fn vertex(pos: Vec3, normal: Vec3, u: f32, v: f32) -> Vertex {
+    let mut ret = Vertex::new(pos.get_x(), pos.get_y(), pos.get_z());
+    ret.ext.normal = normal;
+    ret.ext.uv = Vec2::new(u, v);
+    ret
+}
+
+/// Indices of a grid of `(columns + 1) * (rows + 1)` vertices stored row by row
+fn grid_indices(columns: u32, rows: u32, indices: &mut Vec<u32>) {
+    for row in 0..rows {
+        for column in 0..columns {
+            let a = row * (columns + 1) + column;
+            let b = a + 1;
+            let c = a + columns + 1;
+            let d = c + 1;
+            indices.extend([a, c, b, b, c, d]);
+        }
+    }
+}
+
+impl Triangles {
+    fn from_parts(vertices: Vec<Vertex>, indices: &[u32]) -> Self {
+        let mut ret = Self::new(vertices, vec![]);
+        ret.set_indices(indices);
+        ret.compute_tangents();
+        ret
+    }
+
+    /// Plane on XZ facing up, split into `columns` x `rows` quads
+    pub fn plane(width: f32, depth: f32, columns: u32, rows: u32) -> Self {
+        let (columns, rows) = (columns.max(1), rows.max(1));
+        let mut vertices = vec![];
+        for row in 0..=rows {
+            let v = row as f32 / rows as f32;
+            for column in 0..=columns {
+                let u = column as f32 / columns as f32;
+                let pos = Vec3::new((u - 0.5) * width, 0.0, (v - 0.5) * depth);
+                vertices.push(vertex(pos, Vec3::new(0.0, 1.0, 0.0), u, v));
+            }
+        }
+        let mut indices = vec![];
+        grid_indices(columns, rows, &mut indices);
+        Self::from_parts(vertices, &indices)
+    }
+
+    /// Sphere made of `segments` meridians and `rings` parallels.
+    /// Texture coordinates map the whole texture around it, from the top
+    pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Self {
+        let (segments, rings) = (segments.max(3), rings.max(2));
+        let mut vertices = vec![];
+        for ring in 0..=rings {
+            let v = ring as f32 / rings as f32;
+            let phi = v * PI;
+            for segment in 0..=segments {
+                let u = segment as f32 / segments as f32;
+                let theta = u * TAU;
+                let normal =
+                    Vec3::new(phi.sin() * theta.cos(), phi.cos(), -phi.sin() * theta.sin());
+                vertices.push(vertex(normal * radius, normal, u, v));
+            }
+        }
+
+        let mut indices = vec![];
+        grid_indices(segments, rings, &mut indices);
+        // Remove triangles degenerating at the poles
+        let quads_per_ring = segments as usize * 6;
+        let last = indices.len() - quads_per_ring;
+        let indices: Vec<u32> = indices
+            .chunks_exact(3)
+            .enumerate()
+            .filter(|(t, _)| {
+                let i = t * 3;
+                let first_of_quad = t % 2 == 0;
+                !(i < quads_per_ring && first_of_quad || i >= last && !first_of_quad)
+            })
+            .flat_map(|(_, triangle)| triangle.to_vec())
+            .collect();
+
+        Self::from_parts(vertices, &indices)
+    }
+
+    /// Sphere made by subdividing an icosahedron, with triangles of about the same size
+    pub fn icosphere(radius: f32, subdivisions: u32) -> Self {
+        let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+        let mut positions: Vec<Vec3> = [
+            (-1.0, t, 0.0),
+            (1.0, t, 0.0),
+            (-1.0, -t, 0.0),
+            (1.0, -t, 0.0),
+            (0.0, -1.0, t),
+            (0.0, 1.0, t),
+            (0.0, -1.0, -t),
+            (0.0, 1.0, -t),
+            (t, 0.0, -1.0),
+            (t, 0.0, 1.0),
+            (-t, 0.0, -1.0),
+            (-t, 0.0, 1.0),
+        ]
+        .iter()
+        .map(|&(x, y, z)| Vec3::new(x, y, z).get_normalized())
+        .collect();
+        let mut faces: Vec<[u32; 3]> = vec![
+            [0, 11, 5],
+            [0, 5, 1],
+            [0, 1, 7],
+            [0, 7, 10],
+            [0, 10, 11],
+            [1, 5, 9],
+            [5, 11, 4],
+            [11, 10, 2],
+            [10, 7, 6],
+            [7, 1, 8],
+            [3, 9, 4],
+            [3, 4, 2],
+            [3, 2, 6],
+            [3, 6, 8],
+            [3, 8, 9],
+            [4, 9, 5],
+            [2, 4, 11],
+            [6, 2, 10],
+            [8, 6, 7],
+            [9, 8, 1],
+        ];
+
+        for _ in 0..subdivisions {
+            let mut midpoints = HashMap::<(u32, u32), u32>::new();
+            let mut midpoint = |a: u32, b: u32| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    let mid = (positions[a as usize] + positions[b as usize]).get_normalized();
+                    positions.push(mid);
+                    positions.len() as u32 - 1
+                })
+            };
+            faces = faces
+                .iter()
+                .flat_map(|&[a, b, c]| {
+                    let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                    [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        // Same mapping of the UV sphere
+        let get_uv = |normal: &Vec3| {
+            let u = (-normal.get_z()).atan2(normal.get_x()) / TAU;
+            let u = if u < 0.0 { u + 1.0 } else { u };
+            let v = normal.get_y().clamp(-1.0, 1.0).acos() / PI;
+            (u, v)
+        };
+        let mut vertices: Vec<Vertex> = positions
+            .iter()
+            .map(|normal| {
+                let (u, v) = get_uv(normal);
+                vertex(*normal * radius, *normal, u, v)
+            })
+            .collect();
+
+        // Triangles crossing the seam of the texture get their own vertices
+        // on the far side of it
+        let mut wrapped = HashMap::<u32, u32>::new();
+        let mut indices = Vec::with_capacity(faces.len() * 3);
+        for face in faces {
+            let us = face.map(|i| vertices[i as usize].ext.uv.x);
+            let crossing = us.iter().fold(0.0f32, |m, u| m.max(*u))
+                - us.iter().fold(1.0f32, |m, u| m.min(*u))
+                > 0.5;
+            for (i, u) in face.iter().zip(us) {
+                if crossing && u < 0.5 {
+                    let index = *wrapped.entry(*i).or_insert_with(|| {
+                        let mut vertex = vertices[*i as usize];
+                        vertex.ext.uv.x += 1.0;
+                        vertices.push(vertex);
+                        vertices.len() as u32 - 1
+                    });
+                    indices.push(index);
+                } else {
+                    indices.push(*i);
+                }
+            }
+        }
+
+        Self::from_parts(vertices, &indices)
+    }
+
+    /// Ring of `segments` around the Y axis, with a tube of `minor_radius` made of `sides`
+    pub fn torus(major_radius: f32, minor_radius: f32, segments: u32, sides: u32) -> Self {
+        let (segments, sides) = (segments.max(3), sides.max(3));
+        let mut vertices = vec![];
+        for segment in 0..=segments {
+            let u = segment as f32 / segments as f32;
+            let theta = u * TAU;
+            let center = Vec3::new(theta.cos(), 0.0, -theta.sin()) * major_radius;
+            for side in 0..=sides {
+                let v = side as f32 / sides as f32;
+                let phi = v * TAU;
+                let normal =
+                    Vec3::new(phi.cos() * theta.cos(), phi.sin(), -phi.cos() * theta.sin());
+                vertices.push(vertex(center + normal * minor_radius, normal, u, v));
+            }
+        }
+        let mut indices = vec![];
+        grid_indices(sides, segments, &mut indices);
+        Self::from_parts(vertices, &indices)
+    }
+
+    /// Cylinder along the Y axis, closed at both ends
+    pub fn cylinder(radius: f32, height: f32, segments: u32) -> Self {
+        Self::frustum(radius, radius, height, segments)
+    }
+
+    /// Cone along the Y axis with the tip on top, closed at the bottom
+    pub fn cone(radius: f32, height: f32, segments: u32) -> Self {
+        Self::frustum(radius, 0.0, height, segments)
+    }
+
+    /// Cylinder with different radii at the bottom and the top
+    fn frustum(bottom_radius: f32, top_radius: f32, height: f32, segments: u32) -> Self {
+        let segments = segments.max(3);
+        let half = height * 0.5;
+        let mut vertices = vec![];
+        let mut indices = vec![];
+
+        // Side
+        for (v, radius, y) in [(0.0, top_radius, half), (1.0, bottom_radius, -half)] {
+            for segment in 0..=segments {
+                let u = segment as f32 / segments as f32;
+                let theta = u * TAU;
+                let (cos, sin) = (theta.cos(), -theta.sin());
+                let normal = Vec3::new(cos * height, bottom_radius - top_radius, sin * height)
+                    .get_normalized();
+                let pos = Vec3::new(cos * radius, y, sin * radius);
+                vertices.push(vertex(pos, normal, u, v));
+            }
+        }
+        grid_indices(segments, 1, &mut indices);
+
+        // Caps
+        for (radius, y) in [(top_radius, half), (bottom_radius, -half)] {
+            if radius <= 0.0 {
+                continue;
+            }
+            let up = y > 0.0;
+            let normal = Vec3::new(0.0, if up { 1.0 } else { -1.0 }, 0.0);
+            let center = vertices.len() as u32;
+            vertices.push(vertex(Vec3::new(0.0, y, 0.0), normal, 0.5, 0.5));
+            for segment in 0..=segments {
+                let theta = segment as f32 / segments as f32 * TAU;
+                let (cos, sin) = (theta.cos(), -theta.sin());
+                let pos = Vec3::new(cos * radius, y, sin * radius);
+                vertices.push(vertex(pos, normal, 0.5 + cos * 0.5, 0.5 + sin * 0.5));
+            }
+            for segment in 0..segments {
+                let a = center + 1 + segment;
+                if up {
+                    indices.extend([center, a, a + 1]);
+                } else {
+                    indices.extend([center, a + 1, a]);
+                }
+            }
+        }
+
+        Self::from_parts(vertices, &indices)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Checks that triangles face the same side of their vertex normals
+    fn check(triangles: &Triangles) {
+        let indices = triangles.get_indices();
+        assert!(!indices.is_empty());
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangles.vertices[triangle[i] as usize]);
+            let n = (b.pos - a.pos).cross(&(c.pos - a.pos));
+            if n.len() < 1e-6 {
+                continue;
+            }
+            let normal = a.ext.normal + b.ext.normal + c.ext.normal;
+            assert!(n.dot(normal) > 0.0);
+        }
+        for vertex in &triangles.vertices {
+            assert!((vertex.ext.normal.len() - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn shapes() {
+        check(&Triangles::plane(2.0, 2.0, 4, 4));
+        check(&Triangles::uv_sphere(1.0, 16, 8));
+        check(&Triangles::icosphere(1.0, 2));
+        check(&Triangles::torus(1.0, 0.25, 16, 8));
+        check(&Triangles::cylinder(1.0, 2.0, 16));
+        check(&Triangles::cone(1.0, 2.0, 16));
+
+        let icosphere = Triangles::icosphere(1.0, 1);
+        assert_eq!(icosphere.get_indices().len(), 80 * 3);
+        for triangle in icosphere.get_indices().chunks_exact(3) {
+            let us = [0, 1, 2].map(|i| icosphere.vertices[triangle[i] as usize].ext.uv.x);
+            let span =
+                us.iter().fold(0.0f32, |m, u| m.max(*u)) - us.iter().fold(2.0f32, |m, u| m.min(*u));
+            assert!(span <= 0.5);
+        }
+    }
+}
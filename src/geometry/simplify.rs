@@ -0,0 +1,471 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Mesh decimation based on "Surface Simplification Using Quadric Error Metrics"
+//! by Michael Garland and Paul S. Heckbert.
+
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
+use crate::*;
+
+/// Weight of the planes keeping boundary edges in place
+const BOUNDARY_WEIGHT: f64 = 1000.0;
+
+/// Symmetric 4x4 matrix measuring the squared distance of a point from a set of planes
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(a: f64, b: f64, c: f64, d: f64, weight: f64) -> Self {
+        Self(
+            [
+                a * a,
+                a * b,
+                a * c,
+                a * d,
+                b * b,
+                b * c,
+                b * d,
+                c * c,
+                c * d,
+                d * d,
+            ]
+            .map(|q| q * weight),
+        )
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (q, o) in self.0.iter_mut().zip(other.0.iter()) {
+            *q += o;
+        }
+    }
+
+    fn error(&self, p: &[f64; 3]) -> f64 {
+        let q = &self.0;
+        let [x, y, z] = *p;
+        q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9]
+    }
+
+    /// Returns the point minimizing the error, if the matrix can be inverted
+    fn optimal(&self) -> Option<[f64; 3]> {
+        let q = &self.0;
+        let m = [[q[0], q[1], q[2]], [q[1], q[4], q[5]], [q[2], q[5], q[7]]];
+        let r = [-q[3], -q[6], -q[8]];
+
+        let det = |m: &[[f64; 3]; 3]| {
+            m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+                - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+                + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+        };
+        let d = det(&m);
+        if d.abs() < 1e-12 {
+            return None;
+        }
+
+        // Cramer's rule
+        let mut ret = [0.0; 3];
+        for (i, value) in ret.iter_mut().enumerate() {
+            let mut mi = m;
+            for row in 0..3 {
+                mi[row][i] = r[row];
+            }
+            *value = det(&mi) / d;
+        }
+        Some(ret)
+    }
+}
+
+fn sub(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalized(a: &[f64; 3]) -> Option<[f64; 3]> {
+    let len = dot(a, a).sqrt();
+    if len < 1e-12 {
+        None
+    } else {
+        Some([a[0] / len, a[1] / len, a[2] / len])
+    }
+}
+
+/// Candidate edge collapse, ordered by increasing cost
+struct Collapse {
+    cost: f64,
+    a: usize,
+    b: usize,
+    /// Versions of the vertices when the cost was computed
+    versions: (u32, u32),
+    position: [f64; 3],
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed so that the binary heap pops the cheapest collapse first
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+/// State of the decimation, working on vertices welded by position
+struct Simplifier {
+    positions: Vec<[f64; 3]>,
+    quadrics: Vec<Quadric>,
+    versions: Vec<u32>,
+    alive: Vec<bool>,
+    /// Welded vertex indices of each triangle
+    triangles: Vec<[usize; 3]>,
+    triangle_alive: Vec<bool>,
+    /// Triangles using each vertex
+    adjacency: Vec<Vec<usize>>,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl Simplifier {
+    fn new(positions: Vec<[f64; 3]>, triangles: Vec<[usize; 3]>) -> Self {
+        let vertex_count = positions.len();
+        let mut ret = Self {
+            positions,
+            quadrics: vec![Quadric::default(); vertex_count],
+            versions: vec![0; vertex_count],
+            alive: vec![true; vertex_count],
+            triangle_alive: vec![true; triangles.len()],
+            adjacency: vec![vec![]; vertex_count],
+            triangles,
+            heap: BinaryHeap::new(),
+        };
+
+        let mut edge_use = HashMap::<(usize, usize), u32>::new();
+        for (t, triangle) in ret.triangles.iter().enumerate() {
+            for &v in triangle {
+                ret.adjacency[v].push(t);
+            }
+            for i in 0..3 {
+                let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                *edge_use.entry((a.min(b), a.max(b))).or_default() += 1;
+            }
+
+            let [a, b, c] = triangle.map(|v| ret.positions[v]);
+            let Some(n) = normalized(&cross(&sub(&b, &a), &sub(&c, &a))) else {
+                continue;
+            };
+            let quadric = Quadric::from_plane(n[0], n[1], n[2], -dot(&n, &a), 1.0);
+            for &v in triangle {
+                ret.quadrics[v].add(&quadric);
+            }
+        }
+
+        // Planes perpendicular to the triangles keep open boundaries in place
+        for triangle in &ret.triangles {
+            let [pa, pb, pc] = triangle.map(|v| ret.positions[v]);
+            let Some(n) = normalized(&cross(&sub(&pb, &pa), &sub(&pc, &pa))) else {
+                continue;
+            };
+            for i in 0..3 {
+                let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                if edge_use[&(a.min(b), a.max(b))] != 1 {
+                    continue;
+                }
+                let edge = sub(&ret.positions[b], &ret.positions[a]);
+                let Some(m) = normalized(&cross(&edge, &n)) else {
+                    continue;
+                };
+                let quadric = Quadric::from_plane(
+                    m[0],
+                    m[1],
+                    m[2],
+                    -dot(&m, &ret.positions[a]),
+                    BOUNDARY_WEIGHT,
+                );
+                ret.quadrics[a].add(&quadric);
+                ret.quadrics[b].add(&quadric);
+            }
+        }
+
+        for &(a, b) in edge_use.keys() {
+            ret.push_collapse(a, b);
+        }
+        ret
+    }
+
+    fn push_collapse(&mut self, a: usize, b: usize) {
+        let mut quadric = self.quadrics[a];
+        quadric.add(&self.quadrics[b]);
+
+        let (pa, pb) = (self.positions[a], self.positions[b]);
+        let mid = [
+            (pa[0] + pb[0]) * 0.5,
+            (pa[1] + pb[1]) * 0.5,
+            (pa[2] + pb[2]) * 0.5,
+        ];
+        let mut candidates = vec![pa, pb, mid];
+        if let Some(optimal) = quadric.optimal() {
+            candidates.insert(0, optimal);
+        }
+
+        let (cost, position) = candidates
+            .into_iter()
+            .map(|p| (quadric.error(&p), p))
+            .min_by(|x, y| x.0.total_cmp(&y.0))
+            .unwrap();
+
+        self.heap.push(Collapse {
+            cost,
+            a,
+            b,
+            versions: (self.versions[a], self.versions[b]),
+            position,
+        });
+    }
+
+    /// Whether moving the triangles around `a` and `b` to `position` flips any of them
+    fn flips(&self, a: usize, b: usize, position: &[f64; 3]) -> bool {
+        for &v in &[a, b] {
+            for &t in &self.adjacency[v] {
+                let triangle = &self.triangles[t];
+                if !self.triangle_alive[t] || (triangle.contains(&a) && triangle.contains(&b)) {
+                    continue;
+                }
+
+                let before = triangle.map(|i| self.positions[i]);
+                let after = triangle.map(|i| {
+                    if i == a || i == b {
+                        *position
+                    } else {
+                        self.positions[i]
+                    }
+                });
+                let n0 = cross(&sub(&before[1], &before[0]), &sub(&before[2], &before[0]));
+                let n1 = cross(&sub(&after[1], &after[0]), &sub(&after[2], &after[0]));
+                if dot(&n0, &n1) <= 0.0 {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Collapses `b` into `a`, returning the number of triangles removed
+    fn collapse(&mut self, a: usize, b: usize, position: [f64; 3]) -> usize {
+        let mut removed = 0;
+
+        self.positions[a] = position;
+        let quadric = self.quadrics[b];
+        self.quadrics[a].add(&quadric);
+        self.alive[b] = false;
+        self.versions[a] += 1;
+
+        let b_triangles = std::mem::take(&mut self.adjacency[b]);
+        for t in b_triangles {
+            if !self.triangle_alive[t] {
+                continue;
+            }
+            if self.triangles[t].contains(&a) {
+                self.triangle_alive[t] = false;
+                removed += 1;
+            } else {
+                for v in &mut self.triangles[t] {
+                    if *v == b {
+                        *v = a;
+                    }
+                }
+                self.adjacency[a].push(t);
+            }
+        }
+        let triangle_alive = &self.triangle_alive;
+        self.adjacency[a].retain(|t| triangle_alive[*t]);
+
+        // Update the costs of the edges around `a`
+        let mut neighbors: Vec<usize> = self.adjacency[a]
+            .iter()
+            .flat_map(|t| self.triangles[*t])
+            .filter(|v| *v != a)
+            .collect();
+        neighbors.sort_unstable();
+        neighbors.dedup();
+        for v in neighbors {
+            self.push_collapse(a, v);
+        }
+
+        removed
+    }
+
+    fn run(&mut self, target_triangle_count: usize) {
+        let mut triangle_count = self.triangle_alive.iter().filter(|alive| **alive).count();
+
+        while triangle_count > target_triangle_count {
+            let Some(collapse) = self.heap.pop() else {
+                break;
+            };
+            let (a, b) = (collapse.a, collapse.b);
+            if !self.alive[a]
+                || !self.alive[b]
+                || collapse.versions != (self.versions[a], self.versions[b])
+            {
+                // Outdated
+                continue;
+            }
+            if self.flips(a, b, &collapse.position) {
+                continue;
+            }
+            triangle_count -= self.collapse(a, b, collapse.position);
+        }
+    }
+}
+
+impl Triangles {
+    /// Returns a decimated copy of this mesh with about `target_ratio` of its triangles,
+    /// collapsing the edges which change the shape the least first. Vertices sharing
+    /// the same position are welded, so that seams of the texture coordinates do not
+    /// open holes, and open boundaries are preserved as much as possible.
+    pub fn simplify(&self, target_ratio: f32) -> Triangles {
+        let indices = self.get_indices();
+
+        // Weld vertices with the same position
+        let mut welded = HashMap::<[u32; 3], usize>::new();
+        let mut vertex_to_welded = Vec::with_capacity(self.vertices.len());
+        let mut welded_to_vertex = vec![];
+        let mut positions = vec![];
+        for (i, vertex) in self.vertices.iter().enumerate() {
+            let key = [
+                vertex.pos.get_x().to_bits(),
+                vertex.pos.get_y().to_bits(),
+                vertex.pos.get_z().to_bits(),
+            ];
+            let index = *welded.entry(key).or_insert_with(|| {
+                welded_to_vertex.push(i);
+                positions.push([
+                    vertex.pos.get_x() as f64,
+                    vertex.pos.get_y() as f64,
+                    vertex.pos.get_z() as f64,
+                ]);
+                positions.len() - 1
+            });
+            vertex_to_welded.push(index);
+        }
+
+        let triangles: Vec<[usize; 3]> = indices
+            .chunks_exact(3)
+            .map(|t| [0, 1, 2].map(|i| vertex_to_welded[t[i] as usize]))
+            .filter(|[a, b, c]| a != b && b != c && a != c)
+            .collect();
+
+        let target = (triangles.len() as f32 * target_ratio.clamp(0.0, 1.0)).ceil() as usize;
+        let mut simplifier = Simplifier::new(positions, triangles);
+        simplifier.run(target);
+
+        // Keep the attributes of the original vertices which survived
+        let mut remap = HashMap::<usize, u32>::new();
+        let mut ret = Triangles::default();
+        let mut ret_indices = vec![];
+        for (t, triangle) in simplifier.triangles.iter().enumerate() {
+            if !simplifier.triangle_alive[t] {
+                continue;
+            }
+            for &w in triangle {
+                let index = *remap.entry(w).or_insert_with(|| {
+                    let mut vertex = self.vertices[welded_to_vertex[w]];
+                    let p = simplifier.positions[w];
+                    vertex.pos = Point3::new(p[0] as f32, p[1] as f32, p[2] as f32);
+                    ret.vertices.push(vertex);
+                    ret.vertices.len() as u32 - 1
+                });
+                ret_indices.push(index);
+            }
+        }
+        ret.set_indices(&ret_indices);
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Grid of `n` x `n` quads on the XY plane
+    fn grid(n: usize) -> Triangles {
+        let mut vertices = vec![];
+        for y in 0..=n {
+            for x in 0..=n {
+                vertices.push(Vertex::new(x as f32, y as f32, 0.0));
+            }
+        }
+        let mut indices = vec![];
+        for y in 0..n {
+            for x in 0..n {
+                let i = (y * (n + 1) + x) as u32;
+                let j = i + n as u32 + 1;
+                indices.extend([i, i + 1, j, i + 1, j + 1, j]);
+            }
+        }
+        let mut triangles = Triangles::new(vertices, vec![]);
+        triangles.set_indices(&indices);
+        triangles
+    }
+
+    #[test]
+    fn indices() {
+        let triangles = grid(16);
+        assert_eq!(triangles.index_size_in_bytes, 2);
+        assert_eq!(triangles.get_indices().len(), 16 * 16 * 6);
+    }
+
+    #[test]
+    fn simplify() {
+        let triangles = grid(16);
+        let simplified = triangles.simplify(0.25);
+        let indices = simplified.get_indices();
+        let triangle_count = indices.len() / 3;
+        assert!(triangle_count <= 16 * 16 * 2 / 4);
+        assert!(triangle_count > 0);
+
+        // A plane stays a plane, and its boundary stays in place
+        for vertex in &simplified.vertices {
+            assert!(vertex.pos.get_z().abs() < 1e-4);
+            assert!(vertex.pos.get_x() > -1e-4 && vertex.pos.get_x() < 16.0 + 1e-4);
+            assert!(vertex.pos.get_y() > -1e-4 && vertex.pos.get_y() < 16.0 + 1e-4);
+        }
+        let corner = Point3::new(16.0, 16.0, 0.0);
+        assert!(simplified
+            .vertices
+            .iter()
+            .any(|vertex| (vertex.pos - corner).len() < 1e-3));
+    }
+}
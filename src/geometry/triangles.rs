@@ -31,40 +31,93 @@ impl Triangles {
         }
     }
 
-    fn primitives_impl<'m, Index: NumCast>(
+    /// Returns the indices converted to `u32`, whatever their size
+    pub fn get_indices(&self) -> Vec<u32> {
+        match self.index_size_in_bytes {
+            1 => self.indices.iter().map(|&i| i as u32).collect(),
+            2 => self
+                .indices
+                .chunks_exact(2)
+                .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]) as u32)
+                .collect(),
+            4 => self
+                .indices
+                .chunks_exact(4)
+                .map(|bytes| u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect(),
+            _ => panic!("Index size not supported"),
+        }
+    }
+
+    /// Stores `indices` using the smallest index size able to address all vertices
+    pub fn set_indices(&mut self, indices: &[u32]) {
+        self.index_size_in_bytes = if self.vertices.len() <= u8::MAX as usize + 1 {
+            1
+        } else if self.vertices.len() <= u16::MAX as usize + 1 {
+            2
+        } else {
+            4
+        };
+
+        self.indices = match self.index_size_in_bytes {
+            1 => indices.iter().map(|&i| i as u8).collect(),
+            2 => indices
+                .iter()
+                .flat_map(|&i| (i as u16).to_ne_bytes())
+                .collect(),
+            _ => indices.iter().flat_map(|&i| i.to_ne_bytes()).collect(),
+        };
+    }
+
+    fn primitives_impl<Index: NumCast>(
         &self,
         node: Handle<Node>,
         material: Handle<Material>,
-        model: &'m Model,
+        model: &Model,
+        trs: &SolvedTrs,
         indices: &[Index],
     ) -> Vec<BvhPrimitive> {
         let mut ret = vec![];
 
-        let trs = model.solved_trs.get(&node).unwrap();
         let tangent_matrix = Mat3::from(&trs.trs);
 
-        let inverse_trs = Inversed::from(&trs.trs);
-        let normal_matrix = Mat3::from(&inverse_trs).get_transpose();
+        let normal_matrix = &trs.normal_matrix;
+
+        let displaced = model
+            .materials
+            .get(material)
+            .filter(|material| material.displacement_texture.valid());
+        let camera = model.get_render_camera();
 
         for i in 0..(indices.len() / 3) {
             let mut a = self.vertices[indices[i * 3].to_usize().unwrap()];
             a.pos = &trs.trs * a.pos;
-            a.ext.normal = &normal_matrix * a.ext.normal;
+            a.ext.normal = normal_matrix * a.ext.normal;
             a.ext.tangent = &tangent_matrix * a.ext.tangent;
             a.ext.bitangent = &tangent_matrix * a.ext.bitangent;
 
             let mut b = self.vertices[indices[i * 3 + 1].to_usize().unwrap()];
             b.pos = &trs.trs * b.pos;
-            b.ext.normal = &normal_matrix * b.ext.normal;
+            b.ext.normal = normal_matrix * b.ext.normal;
             b.ext.tangent = &tangent_matrix * b.ext.tangent;
             b.ext.bitangent = &tangent_matrix * b.ext.bitangent;
 
             let mut c = self.vertices[indices[i * 3 + 2].to_usize().unwrap()];
             c.pos = &trs.trs * c.pos;
-            c.ext.normal = &normal_matrix * c.ext.normal;
+            c.ext.normal = normal_matrix * c.ext.normal;
             c.ext.tangent = &tangent_matrix * c.ext.tangent;
             c.ext.bitangent = &tangent_matrix * c.ext.bitangent;
 
+            if let Some(displaced) = displaced {
+                let tessellation = &model.tessellation;
+                for [a, b, c] in tessellation.displace([a, b, c], displaced, model, camera) {
+                    let triangle = Box::new(BvhTriangle::new(a, b, c));
+                    let geometry = BvhGeometry::Triangle(triangle);
+                    ret.push(BvhPrimitive::new(geometry, node, material));
+                }
+                continue;
+            }
+
             let triangle = Box::new(BvhTriangle::new(a, b, c));
             let geometry = BvhGeometry::Triangle(triangle);
             let primitive = BvhPrimitive::new(geometry, node, material);
@@ -79,24 +132,37 @@ impl Triangles {
         node: Handle<Node>,
         material: Handle<Material>,
         model: &Model,
+    ) -> Vec<BvhPrimitive> {
+        let trs = model.solved_trs.get(&node).unwrap();
+        self.primitives_with_trs(node, material, model, trs)
+    }
+
+    /// Same as `primitives()`, but transforms vertices to world space with `trs`
+    /// instead of the solved transform of `node`
+    pub fn primitives_with_trs(
+        &self,
+        node: Handle<Node>,
+        material: Handle<Material>,
+        model: &Model,
+        trs: &SolvedTrs,
     ) -> Vec<BvhPrimitive> {
         let indices_len = self.indices.len() / self.index_size_in_bytes;
 
         match self.index_size_in_bytes {
-            1 => self.primitives_impl(node, material, model, &self.indices),
+            1 => self.primitives_impl(node, material, model, trs, &self.indices),
             2 => {
                 let indices = unsafe {
                     std::slice::from_raw_parts(self.indices.as_ptr() as *const u16, indices_len)
                 };
 
-                self.primitives_impl(node, material, model, indices)
+                self.primitives_impl(node, material, model, trs, indices)
             }
             4 => {
                 let indices = unsafe {
                     std::slice::from_raw_parts(self.indices.as_ptr() as *const u32, indices_len)
                 };
 
-                self.primitives_impl(node, material, model, indices)
+                self.primitives_impl(node, material, model, trs, indices)
             }
             _ => panic!("Index size not supported"),
         }
@@ -0,0 +1,287 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::collections::HashMap;
+
+use crate::*;
+
+/// How positions are projected to texture coordinates
+#[derive(Clone, Copy)]
+pub enum UvProjection {
+    /// All triangles are projected along the same axis
+    Planar(Axis3),
+    /// Every triangle is projected along the axis closest to its normal,
+    /// like a cube wrapped around the mesh
+    Box,
+}
+
+/// A group of connected triangles projected along the same direction
+struct Chart {
+    triangles: Vec<usize>,
+    min: Vec2,
+    max: Vec2,
+}
+
+/// Projects `pos` along the positive or negative `axis`
+fn project(pos: &Point3, axis: Axis3, positive: bool) -> Vec2 {
+    let s = if positive { 1.0 } else { -1.0 };
+    let (x, y, z) = (pos.get_x(), pos.get_y(), pos.get_z());
+    match axis {
+        Axis3::X => Vec2::new(-s * z, y),
+        Axis3::Y => Vec2::new(x, -s * z),
+        Axis3::Z => Vec2::new(s * x, y),
+    }
+}
+
+/// Returns the index of a union-find set
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+impl Triangles {
+    /// Splits the mesh into charts of connected triangles sharing the same projection.
+    /// Returns new vertices, indices, the projected coordinates of each vertex, and the charts
+    fn split_charts(
+        &self,
+        projection: UvProjection,
+    ) -> (Vec<Vertex>, Vec<u32>, Vec<Vec2>, Vec<Chart>) {
+        let indices = self.get_indices();
+        let triangle_count = indices.len() / 3;
+
+        // Direction each triangle is projected along
+        let directions: Vec<(Axis3, bool)> = indices
+            .chunks_exact(3)
+            .map(|triangle| match projection {
+                UvProjection::Planar(axis) => (axis, true),
+                UvProjection::Box => {
+                    let [a, b, c] = [0, 1, 2].map(|i| self.vertices[triangle[i] as usize].pos);
+                    let n = (b - a).cross(&(c - a));
+                    let (x, y, z) = (n.get_x(), n.get_y(), n.get_z());
+                    if x.abs() >= y.abs() && x.abs() >= z.abs() {
+                        (Axis3::X, x >= 0.0)
+                    } else if y.abs() >= z.abs() {
+                        (Axis3::Y, y >= 0.0)
+                    } else {
+                        (Axis3::Z, z >= 0.0)
+                    }
+                }
+            })
+            .collect();
+        let direction_id = |t: usize| {
+            let (axis, positive) = directions[t];
+            axis as usize * 2 + positive as usize
+        };
+
+        // Connect triangles sharing a vertex position and a direction
+        let mut parents: Vec<usize> = (0..triangle_count).collect();
+        let mut first_at = HashMap::<([u32; 3], usize), usize>::new();
+        for (t, triangle) in indices.chunks_exact(3).enumerate() {
+            for &v in triangle {
+                let pos = &self.vertices[v as usize].pos;
+                let key = (
+                    [
+                        pos.get_x().to_bits(),
+                        pos.get_y().to_bits(),
+                        pos.get_z().to_bits(),
+                    ],
+                    direction_id(t),
+                );
+                let other = *first_at.entry(key).or_insert(t);
+                let (a, b) = (find(&mut parents, t), find(&mut parents, other));
+                parents[a] = b;
+            }
+        }
+
+        let mut charts = vec![];
+        let mut chart_of_root = HashMap::<usize, usize>::new();
+        let mut vertices = vec![];
+        let mut new_indices = Vec::with_capacity(indices.len());
+        let mut uvs = vec![];
+        let mut split = HashMap::<(usize, u32), u32>::new();
+        for (t, triangle) in indices.chunks_exact(3).enumerate() {
+            let root = find(&mut parents, t);
+            let chart = *chart_of_root.entry(root).or_insert_with(|| {
+                charts.push(Chart {
+                    triangles: vec![],
+                    min: Vec2::new(f32::MAX, f32::MAX),
+                    max: Vec2::new(f32::MIN, f32::MIN),
+                });
+                charts.len() - 1
+            });
+            charts[chart].triangles.push(t);
+
+            let (axis, positive) = directions[t];
+            for &v in triangle {
+                let index = *split.entry((chart, v)).or_insert_with(|| {
+                    let vertex = self.vertices[v as usize];
+                    let uv = project(&vertex.pos, axis, positive);
+                    let chart = &mut charts[chart];
+                    chart.min = Vec2::new(chart.min.x.min(uv.x), chart.min.y.min(uv.y));
+                    chart.max = Vec2::new(chart.max.x.max(uv.x), chart.max.y.max(uv.y));
+                    vertices.push(vertex);
+                    uvs.push(uv);
+                    vertices.len() as u32 - 1
+                });
+                new_indices.push(index);
+            }
+        }
+
+        (vertices, new_indices, uvs, charts)
+    }
+
+    /// Writes projected positions scaled by `scale` into the first set of texture
+    /// coordinates, which is useful to texture procedural geometry
+    pub fn project_uvs(&mut self, projection: UvProjection, scale: f32) {
+        let (mut vertices, indices, uvs, _) = self.split_charts(projection);
+        for (vertex, uv) in vertices.iter_mut().zip(uvs) {
+            vertex.ext.uv = Vec2::new(uv.x * scale, uv.y * scale);
+        }
+        self.vertices = vertices;
+        self.set_indices(&indices);
+    }
+
+    /// Generates non-overlapping texture coordinates into the second set, as needed by
+    /// lightmaps. Triangles are projected into charts which are packed into the unit
+    /// square, keeping about `padding` between each other to avoid bleeding.
+    /// A planar projection only avoids overlaps for mostly flat meshes.
+    pub fn unwrap(&mut self, projection: UvProjection, padding: f32) {
+        let (mut vertices, indices, uvs, mut charts) = self.split_charts(projection);
+        if charts.is_empty() {
+            return;
+        }
+
+        // Shelf packing of the charts, from the tallest one, into a square of about
+        // the same area of the charts. Padding is relative to the side of that square
+        let area: f32 = charts
+            .iter()
+            .map(|chart| (chart.max.x - chart.min.x) * (chart.max.y - chart.min.y))
+            .sum();
+        let widest = charts
+            .iter()
+            .map(|chart| chart.max.x - chart.min.x)
+            .fold(0.0, f32::max);
+        let side = area.sqrt().max(widest).max(f32::EPSILON);
+        let gap = padding * side;
+
+        let mut order: Vec<usize> = (0..charts.len()).collect();
+        order.sort_by(|a, b| {
+            let height = |chart: &Chart| chart.max.y - chart.min.y;
+            height(&charts[*b]).total_cmp(&height(&charts[*a]))
+        });
+
+        let mut offsets = vec![Vec2::default(); charts.len()];
+        let (mut x, mut y, mut shelf_height, mut extent) = (gap, gap, 0.0f32, 0.0f32);
+        for &c in &order {
+            let chart = &charts[c];
+            let width = chart.max.x - chart.min.x;
+            let height = chart.max.y - chart.min.y;
+            if x > gap && x + width + gap > side + 2.0 * gap {
+                x = gap;
+                y += shelf_height + gap;
+                shelf_height = 0.0;
+            }
+            offsets[c] = Vec2::new(x - chart.min.x, y - chart.min.y);
+            x += width + gap;
+            shelf_height = shelf_height.max(height);
+            extent = extent.max(x).max(y + shelf_height + gap);
+        }
+
+        // Charts of each vertex
+        let mut vertex_chart = vec![0; vertices.len()];
+        for (c, chart) in charts.iter_mut().enumerate() {
+            for t in chart.triangles.drain(..) {
+                for i in 0..3 {
+                    vertex_chart[indices[t * 3 + i] as usize] = c;
+                }
+            }
+        }
+
+        let scale = 1.0 / extent;
+        for ((vertex, uv), chart) in vertices.iter_mut().zip(uvs).zip(vertex_chart) {
+            let offset = offsets[chart];
+            vertex.ext.uv1 = Vec2::new((uv.x + offset.x) * scale, (uv.y + offset.y) * scale);
+        }
+        self.vertices = vertices;
+        self.set_indices(&indices);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Unit cube with 8 shared vertices
+    fn cube() -> Triangles {
+        let mut vertices = vec![];
+        for i in 0..8 {
+            vertices.push(Vertex::new(
+                (i & 1) as f32,
+                ((i >> 1) & 1) as f32,
+                ((i >> 2) & 1) as f32,
+            ));
+        }
+        let mut triangles = Triangles::new(vertices, vec![]);
+        triangles.set_indices(&[
+            0, 2, 1, 1, 2, 3, // -Z
+            4, 5, 6, 5, 7, 6, // +Z
+            0, 1, 4, 1, 5, 4, // -Y
+            2, 6, 3, 3, 6, 7, // +Y
+            0, 4, 2, 2, 4, 6, // -X
+            1, 3, 5, 3, 7, 5, // +X
+        ]);
+        triangles
+    }
+
+    #[test]
+    fn unwrap() {
+        let mut triangles = cube();
+        triangles.unwrap(UvProjection::Box, 0.05);
+
+        // Every face is a chart with its own 4 vertices
+        assert_eq!(triangles.vertices.len(), 24);
+        for vertex in &triangles.vertices {
+            let uv = vertex.ext.uv1;
+            assert!(uv.x >= 0.0 && uv.x <= 1.0 && uv.y >= 0.0 && uv.y <= 1.0);
+        }
+
+        // Charts do not overlap: rasterize them and count the covered texels
+        let size = 64;
+        let mut covered = vec![0; size * size];
+        let indices = triangles.get_indices();
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangles.vertices[triangle[i] as usize].ext.uv1);
+            for y in 0..size {
+                for x in 0..size {
+                    let p = Vec2::new(
+                        (x as f32 + 0.5) / size as f32,
+                        (y as f32 + 0.5) / size as f32,
+                    );
+                    let edge =
+                        |a: Vec2, b: Vec2| (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x);
+                    let (e0, e1, e2) = (edge(a, b), edge(b, c), edge(c, a));
+                    if (e0 > 0.0 && e1 > 0.0 && e2 > 0.0) || (e0 < 0.0 && e1 < 0.0 && e2 < 0.0) {
+                        covered[y * size + x] += 1;
+                    }
+                }
+            }
+        }
+        assert!(covered.iter().all(|count| *count <= 1));
+        assert!(covered.iter().filter(|count| **count == 1).count() > size * size / 4);
+    }
+
+    #[test]
+    fn project() {
+        let mut triangles = cube();
+        triangles.project_uvs(UvProjection::Planar(Axis3::Z), 2.0);
+        assert_eq!(triangles.vertices.len(), 8);
+        for vertex in &triangles.vertices {
+            assert_eq!(vertex.ext.uv.x, vertex.pos.get_x() * 2.0);
+            assert_eq!(vertex.ext.uv.y, vertex.pos.get_y() * 2.0);
+        }
+    }
+}
@@ -24,6 +24,8 @@ impl Vertex {
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct VertexExt {
     pub uv: Vec2,
+    /// Second set of texture coordinates, usually a lightmap chart
+    pub uv1: Vec2,
     pub color: Color,
 
     pub normal: Vec3,
@@ -31,10 +33,21 @@ pub struct VertexExt {
     pub bitangent: Vec3,
 }
 
+impl VertexExt {
+    /// Returns the texture coordinates of set `index`, which is either 0 or 1
+    pub fn get_uv(&self, index: usize) -> Vec2 {
+        match index {
+            0 => self.uv,
+            _ => self.uv1,
+        }
+    }
+}
+
 impl Default for VertexExt {
     fn default() -> Self {
         Self {
             uv: Vec2::default(),
+            uv1: Vec2::default(),
             color: Color::from(0xFFFFFFFF),
             normal: Vec3::new(0.0, 0.0, 1.0),
             tangent: Vec3::new(0.0, 0.0, 0.0),
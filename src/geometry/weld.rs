@@ -0,0 +1,114 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::collections::HashMap;
+
+use crate::*;
+
+impl Triangles {
+    /// Merges vertices closer than `epsilon` to each other and rebuilds the indices,
+    /// removing the triangles collapsing to a line or a point. Vertices with different
+    /// texture coordinates are kept apart so that texture seams survive. Merged vertices
+    /// keep the attributes of the first of them, hence `compute_smooth_normals()` is
+    /// usually called afterwards. Meshes without indices are treated as a list of triangles.
+    pub fn weld(&mut self, epsilon: f32) {
+        let indices = if self.indices.is_empty() {
+            (0..self.vertices.len() as u32).collect()
+        } else {
+            self.get_indices()
+        };
+
+        // Vertices are sorted into cells of a grid, so that only neighbor cells are searched.
+        // Cells larger than epsilon are fine, they only contain more candidates
+        let cell_size = epsilon.max(1e-6);
+        let get_cell = |pos: &Point3| {
+            [
+                (pos.get_x() / cell_size).floor() as i64,
+                (pos.get_y() / cell_size).floor() as i64,
+                (pos.get_z() / cell_size).floor() as i64,
+            ]
+        };
+
+        let mut grid = HashMap::<[i64; 3], Vec<u32>>::new();
+        let mut vertices: Vec<Vertex> = Vec::with_capacity(self.vertices.len());
+        let mut remap = Vec::with_capacity(self.vertices.len());
+        for vertex in &self.vertices {
+            let cell = get_cell(&vertex.pos);
+
+            let mut found = None;
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        let neighbor = [
+                            cell[0].wrapping_add(dx),
+                            cell[1].wrapping_add(dy),
+                            cell[2].wrapping_add(dz),
+                        ];
+                        for &candidate in grid.get(&neighbor).into_iter().flatten() {
+                            let other = &vertices[candidate as usize];
+                            if (other.pos - vertex.pos).len() <= epsilon
+                                && (other.ext.uv.x - vertex.ext.uv.x).abs() <= epsilon
+                                && (other.ext.uv.y - vertex.ext.uv.y).abs() <= epsilon
+                            {
+                                found = Some(candidate);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+
+            let index = found.unwrap_or_else(|| {
+                let index = vertices.len() as u32;
+                vertices.push(*vertex);
+                grid.entry(cell).or_default().push(index);
+                index
+            });
+            remap.push(index);
+        }
+
+        let mut new_indices = Vec::with_capacity(indices.len());
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| remap[triangle[i] as usize]);
+            if a != b && b != c && a != c {
+                new_indices.extend([a, b, c]);
+            }
+        }
+
+        self.vertices = vertices;
+        self.set_indices(&new_indices);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn weld() {
+        // A quad as a list of triangles, with a little noise and a degenerate triangle
+        let vertices = vec![
+            Vertex::new(0.0, 0.0, 0.0),
+            Vertex::new(1.0, 0.0, 0.0),
+            Vertex::new(1.0, 1.0, 0.0),
+            Vertex::new(0.0, 0.0, 0.0),
+            Vertex::new(1.0, 1.0, 0.000001),
+            Vertex::new(0.0, 1.0, 0.0),
+            Vertex::new(0.0, 1.0, 0.0),
+            Vertex::new(0.0, 1.0, 0.0),
+            Vertex::new(1.0, 1.0, 0.0),
+        ];
+        let mut triangles = Triangles::new(vertices, vec![]);
+        triangles.weld(0.0001);
+        assert_eq!(triangles.vertices.len(), 4);
+        assert_eq!(triangles.get_indices(), vec![0, 1, 2, 0, 2, 3]);
+
+        // Seams are kept
+        let mut vertices = vec![Vertex::new(0.0, 0.0, 0.0); 3];
+        vertices[1].ext.uv = Vec2::new(1.0, 0.0);
+        let mut seam = Triangles::new(vertices, vec![]);
+        seam.weld(0.0001);
+        assert_eq!(seam.vertices.len(), 2);
+    }
+}
@@ -19,6 +19,10 @@ pub struct Image {
 
     /// Row major, top-left origin
     pub color_type: ColorType,
+
+    /// Primaries of the colors, written to the files this image is saved to
+    pub color_space: ColorSpace,
+
     buffer: Vec<u8>,
 
     width: u32,
@@ -27,12 +31,12 @@ pub struct Image {
 
 impl Image {
     pub fn new(width: u32, height: u32, color_type: ColorType) -> Self {
-        let mut buffer = Vec::new();
-        buffer.resize(width as usize * height as usize * color_type.channels(), 0);
+        let buffer = vec![0; width as usize * height as usize * color_type.channels()];
 
         Self {
             id: 0,
             color_type,
+            color_space: ColorSpace::default(),
             buffer,
             width,
             height,
@@ -119,64 +123,79 @@ impl Image {
         self.data_mut().fill(color);
     }
 
-    pub fn load_png_data(data: &[u8]) -> Image {
-        let mut decoder = png::Decoder::new(data);
+    /// Draws a line one pixel wide from `a` to `b`, in pixel coordinates,
+    /// skipping the pixels out of the image
+    pub fn draw_line(&mut self, a: Vec2, b: Vec2, color: Color) {
+        let rgba8 = RGBA8::from(color);
+        let steps = (b.x - a.x).abs().max((b.y - a.y).abs()).ceil().max(1.0) as u32;
+        for step in 0..=steps {
+            let t = step as f32 / steps as f32;
+            let x = (a.x + (b.x - a.x) * t).round();
+            let y = (a.y + (b.y - a.y) * t).round();
+            if x < 0.0 || y < 0.0 || x >= self.width as f32 || y >= self.height as f32 {
+                continue;
+            }
+            let (x, y) = (x as u32, y as u32);
+            match self.color_type {
+                ColorType::RGBA8 => self.set(x, y, rgba8),
+                ColorType::RGB8 => {
+                    let rgb8 = RGB8 {
+                        r: rgba8.r,
+                        g: rgba8.g,
+                        b: rgba8.b,
+                    };
+                    self.set(x, y, rgb8)
+                }
+            }
+        }
+    }
+
+    fn read_png<R: std::io::Read>(read: R) -> Result<Image, RaycaError> {
+        let mut decoder = png::Decoder::new(read);
         decoder.set_transformations(Transformations::normalize_to_color8());
 
-        let mut reader = decoder.read_info().unwrap();
+        let mut reader = decoder.read_info()?;
         let info = reader.info();
 
         let color_type = match info.color_type {
             png::ColorType::Rgb => ColorType::RGB8,
             png::ColorType::Rgba => ColorType::RGBA8,
             png::ColorType::Indexed => ColorType::RGB8,
-            _ => panic!("Color type not supported: {:?}", info.color_type),
+            _ => {
+                return Err(RaycaError::Unsupported(format!(
+                    "PNG color type {:?}",
+                    info.color_type
+                )))
+            }
         };
 
         let mut ret = Self::new(info.width, info.height, color_type);
-        reader
-            .next_frame(ret.bytes_mut())
-            .expect("Failed to read frame from PNG data");
-        ret
+        reader.next_frame(ret.bytes_mut())?;
+        Ok(ret)
     }
 
-    /// Opens a PNG file without loading data yet
-    pub fn load_png_file<P: AsRef<Path>>(path: P) -> Image {
-        let current_dir = std::env::current_dir().expect("Failed to get current dir");
-        let err_msg = fail!(
-            "to load PNG file: {}/{}",
-            current_dir.display(),
-            path.as_ref().display()
-        );
-        let file = File::open(path.as_ref()).expect(&err_msg);
-
-        let mut decoder = png::Decoder::new(file);
-        decoder.set_transformations(Transformations::normalize_to_color8());
-
-        let mut reader = decoder.read_info().unwrap();
-        let info = reader.info();
-
-        let color_type = match info.color_type {
-            png::ColorType::Rgb => ColorType::RGB8,
-            png::ColorType::Rgba => ColorType::RGBA8,
-            png::ColorType::Indexed => ColorType::RGB8,
-            _ => panic!("Color type not supported: {:?}", info.color_type),
-        };
+    pub fn load_png_data(data: &[u8]) -> Result<Image, RaycaError> {
+        Self::read_png(data)
+    }
 
-        let mut ret = Self::new(info.width, info.height, color_type);
-        let err_msg = format!(
-            "Failed to read frame from PNG file: {}/{}",
-            current_dir.display(),
-            path.as_ref().display()
-        );
-        reader.next_frame(ret.bytes_mut()).expect(&err_msg);
-        ret
+    pub fn load_png_file<P: AsRef<Path>>(path: P) -> Result<Image, RaycaError> {
+        let file = File::open(path.as_ref())?;
+        Self::read_png(BufReader::new(file))
     }
 
     pub fn dump_png<P: AsRef<Path>>(&self, path: P) {
         let file = File::create(path).expect(&fail!("to create PNG file"));
-        let w = BufWriter::new(file);
+        self.write_png(BufWriter::new(file));
+    }
 
+    /// Returns the bytes of a PNG file with the contents of this image
+    pub fn encode_png(&self) -> Vec<u8> {
+        let mut ret = vec![];
+        self.write_png(&mut ret);
+        ret
+    }
+
+    fn write_png<W: std::io::Write>(&self, w: W) {
         let mut encoder = png::Encoder::new(w, self.width, self.height);
 
         let png_color_type = match self.color_type {
@@ -190,23 +209,29 @@ impl Image {
         // 1.0 / 2.2, unscaled, but rounded
         encoder.set_source_gamma(png::ScaledFloat::new(1.0 / 2.2));
         // Using unscaled instantiation here
-        let source_chromaticities = png::SourceChromaticities::new(
-            (0.31270, 0.32900),
-            (0.64000, 0.33000),
-            (0.30000, 0.60000),
-            (0.15000, 0.06000),
-        );
+        let [white, red, green, blue] = self.color_space.get_chromaticities();
+        let source_chromaticities = png::SourceChromaticities::new(white, red, green, blue);
         encoder.set_source_chromaticities(source_chromaticities);
         let mut writer = encoder.write_header().unwrap();
 
         writer.write_image_data(self.bytes()).unwrap(); // Save
     }
 
-    pub fn load_jpg_file<P: AsRef<Path>>(path: P) -> Image {
-        let file = File::open(path).expect("Failed to open JPG file");
-        let mut decoder = jpeg::Decoder::new(BufReader::new(file));
-        let pixels = decoder.decode().expect("Failed to decode JPG image");
-        let metadata = decoder.info().unwrap();
+    pub fn load_jpg_data(data: &[u8]) -> Result<Image, RaycaError> {
+        Self::read_jpg(data)
+    }
+
+    pub fn load_jpg_file<P: AsRef<Path>>(path: P) -> Result<Image, RaycaError> {
+        let file = File::open(path)?;
+        Self::read_jpg(BufReader::new(file))
+    }
+
+    fn read_jpg<R: std::io::Read>(r: R) -> Result<Image, RaycaError> {
+        let mut decoder = jpeg::Decoder::new(r);
+        let pixels = decoder.decode()?;
+        let metadata = decoder
+            .info()
+            .ok_or_else(|| RaycaError::Parse("JPG: missing metadata".into()))?;
 
         let mut image = Image::new(
             metadata.width as u32,
@@ -214,24 +239,108 @@ impl Image {
             ColorType::RGB8,
         );
         image.buffer = pixels;
-        image
+        Ok(image)
     }
 
-    pub fn load_file<P: AsRef<Path>>(path: P) -> Image {
-        let ext = path.as_ref().extension().unwrap();
+    /// Loads a PNG, JPG, or KTX2 file, according to its extension
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Image, RaycaError> {
+        let ext = path
+            .as_ref()
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy();
 
         if ext.eq_ignore_ascii_case("png") {
             Self::load_png_file(path)
         } else if ext.eq_ignore_ascii_case("jpg") {
             Self::load_jpg_file(path)
+        } else if ext.eq_ignore_ascii_case("ktx2") {
+            Self::load_ktx2_file(path)
         } else {
-            panic!(
-                "Unsupported image extension: {} ({})",
-                ext.to_string_lossy(),
+            Err(RaycaError::Unsupported(format!(
+                "image extension \"{}\" ({})",
+                ext,
                 path.as_ref().display()
-            )
+            )))
+        }
+    }
+}
+
+/// Returns the bytes of an uncompressed OpenEXR file with the RGBA32F `pixels`
+/// of an image of `width` x `height` pixels, row major with top-left origin
+pub fn encode_exr(width: u32, height: u32, pixels: &[Color]) -> Vec<u8> {
+    assert!(pixels.len() == width as usize * height as usize);
+
+    fn attribute(ret: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+        for text in [name, kind] {
+            ret.extend_from_slice(text.as_bytes());
+            ret.push(0);
+        }
+        ret.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        ret.extend_from_slice(value);
+    }
+
+    // Channels are stored by name, in alphabetical order
+    const CHANNELS: [&str; 4] = ["A", "B", "G", "R"];
+    const FLOAT: i32 = 2;
+
+    let mut ret = vec![0x76, 0x2f, 0x31, 0x01];
+    ret.extend_from_slice(&2u32.to_le_bytes());
+
+    let mut channels = vec![];
+    for name in CHANNELS {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&FLOAT.to_le_bytes());
+        // Linear flag and reserved bytes
+        channels.extend_from_slice(&[0; 4]);
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+    attribute(&mut ret, "channels", "chlist", &channels);
+    attribute(&mut ret, "compression", "compression", &[0]);
+    let window = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect::<Vec<_>>();
+    attribute(&mut ret, "dataWindow", "box2i", &window);
+    attribute(&mut ret, "displayWindow", "box2i", &window);
+    attribute(&mut ret, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut ret, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    attribute(&mut ret, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut ret,
+        "screenWindowWidth",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+    ret.push(0);
+
+    // Offsets of the scanlines follow the header
+    let line_size = width as usize * CHANNELS.len() * 4;
+    let first_line = ret.len() + height as usize * 8;
+    for y in 0..height as usize {
+        let offset = first_line + y * (8 + line_size);
+        ret.extend_from_slice(&(offset as u64).to_le_bytes());
+    }
+
+    for (y, row) in pixels.chunks_exact(width.max(1) as usize).enumerate() {
+        ret.extend_from_slice(&(y as i32).to_le_bytes());
+        ret.extend_from_slice(&(line_size as i32).to_le_bytes());
+        for channel in CHANNELS {
+            for pixel in row {
+                let value = match channel {
+                    "A" => pixel.a,
+                    "B" => pixel.b,
+                    "G" => pixel.g,
+                    _ => pixel.r,
+                };
+                ret.extend_from_slice(&value.to_le_bytes());
+            }
         }
     }
+    ret
 }
 
 #[cfg(test)]
@@ -269,7 +378,7 @@ mod test {
         let blue_path = "target/blue.png";
         image.dump_png(blue_path);
 
-        let image = Image::load_png_file(blue_path);
+        let image = Image::load_png_file(blue_path).unwrap();
         assert!(image
             .data::<RGBA8>()
             .iter()
@@ -280,8 +389,29 @@ mod test {
     fn base64() {
         const DUCK_BASE64: &str = include_str!("../tests/model/duck/duck.base64");
         let duck_data = base64::decode(DUCK_BASE64).expect("Failed to decode duck base64");
-        let image = Image::load_png_data(&duck_data);
+        let image = Image::load_png_data(&duck_data).unwrap();
         image.dump_png("target/duck-texture.png");
         rlog!("{:?}", image.data::<RGB8>()[0]);
     }
+
+    #[test]
+    fn exr() {
+        use std::convert::TryInto;
+
+        let pixels = [Color::new(0.25, 0.5, 2.0, 1.0), Color::black()];
+        let exr = encode_exr(2, 1, &pixels);
+        assert_eq!(exr[..4], [0x76, 0x2f, 0x31, 0x01]);
+
+        // The only scanline is at the end, after its offset
+        let line_size = 2 * 4 * 4;
+        let line = exr.len() - line_size - 8;
+        let offset = u64::from_le_bytes(exr[line - 8..line].try_into().unwrap());
+        assert_eq!(offset as usize, line);
+        let values = exr[line + 8..]
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        // Alpha, blue, green, and red of both pixels
+        assert_eq!(values, [1.0, 1.0, 2.0, 0.0, 0.5, 0.0, 0.25, 0.0]);
+    }
 }
@@ -0,0 +1,145 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::f32::consts::PI;
+
+use crate::*;
+
+/// Estimates the light reflected by a material towards `v` when it is lit by a
+/// uniform white environment, also known as its directional albedo.
+/// A material which conserves energy should never reflect more than it receives,
+/// while a perfectly white and lossless material should reflect exactly 1.
+#[allow(clippy::too_many_arguments)]
+pub fn white_furnace(
+    material: &Material,
+    model: &Model,
+    hit: &Hit,
+    n: Vec3,
+    v: Vec3,
+    albedo: Color,
+    uvs: [Vec2; 2],
+    sample_count: u32,
+    rng: &mut Rng,
+) -> Color {
+    let mut ret = Color::black();
+
+    // Uniform hemisphere sampling
+    let pdf = 1.0 / (2.0 * PI);
+
+    for _ in 0..sample_count {
+        let l = rng.next_hemisphere(&n);
+        let ir = Irradiance::new(Color::white(), hit, l, n, v, albedo, uvs);
+        ret += material.get_radiance(&ir, model) / pdf;
+    }
+
+    ret / sample_count as f32
+}
+
+/// Debug integrator placing the scene in a uniform white environment.
+/// Every pixel shows the directional albedo of the first surface hit,
+/// hence values above 1 reveal materials creating energy.
+pub struct Furnace {
+    sample_count: u32,
+}
+
+impl Default for Furnace {
+    fn default() -> Self {
+        Self::new(64)
+    }
+}
+
+impl Furnace {
+    pub fn new(sample_count: u32) -> Self {
+        Self { sample_count }
+    }
+}
+
+impl Integrator for Furnace {
+    fn trace(
+        &self,
+        model: &Model,
+        ray: Ray,
+        bvh: &Bvh,
+        _depth: u32,
+        rng: &mut Rng,
+    ) -> Option<Color> {
+        let Some((mut hit, primitive)) = bvh.intersects_iter(model, &ray) else {
+            // The environment
+            return Some(Color::white());
+        };
+        bvh.shade_hit(model, &mut hit, primitive);
+
+        let n = primitive.get_normal(model, &hit);
+        let albedo = primitive.get_color(model, &hit);
+        let uvs = hit.frame().get_uvs();
+        let material = primitive.get_material(model);
+
+        let color = white_furnace(
+            material,
+            model,
+            &hit,
+            n,
+            -ray.dir,
+            albedo,
+            uvs,
+            self.sample_count,
+            rng,
+        );
+        Some(color)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn directional_albedo(metallic: f32, roughness: f32, n_dot_v: f32) -> Color {
+        let model = Model::new();
+        let mut material = Material::new();
+        material.metallic_factor = metallic;
+        material.roughness_factor = roughness;
+
+        let hit = Hit::new(1.0, Point3::default(), Vec2::default());
+        let n = Vec3::new(0.0, 0.0, 1.0);
+        let v = Vec3::new((1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v);
+        let mut rng = Rng::new(0);
+
+        white_furnace(
+            &material,
+            &model,
+            &hit,
+            n,
+            v,
+            Color::white(),
+            [Vec2::default(); 2],
+            16384,
+            &mut rng,
+        )
+    }
+
+    #[test]
+    fn metal() {
+        // Pure specular reflection should never create energy, although single
+        // scattering microfacets lose energy as roughness increases
+        for roughness in [0.1, 0.5, 1.0] {
+            for n_dot_v in [0.2, 0.5, 1.0] {
+                let albedo = directional_albedo(1.0, roughness, n_dot_v);
+                assert!(albedo.r > 0.25 && albedo.r <= 1.01, "{:?}", albedo);
+            }
+        }
+    }
+
+    #[test]
+    fn dielectric() {
+        // A white rough dielectric should reflect almost all the light it receives.
+        // Smooth dielectrics at grazing angles are excluded as the diffuse weight
+        // does not account for the energy of the specular lobe there
+        for roughness in [0.5, 1.0] {
+            for n_dot_v in [0.2, 0.5, 1.0] {
+                let albedo = directional_albedo(0.0, roughness, n_dot_v);
+                assert!(albedo.r > 0.95 && albedo.r < 1.02, "{:?}", albedo);
+            }
+        }
+    }
+}
@@ -0,0 +1,563 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Path guiding following "Practical Path Guiding for Efficient Light-Transport
+//! Simulation" by Müller, Gross, and Novák. A spatial binary tree splits the scene,
+//! and every leaf keeps a quadtree over the directions learning where light comes from.
+
+use std::{
+    f32::consts::{FRAC_1_PI, PI, TAU},
+    sync::{Mutex, RwLock},
+};
+
+use crate::*;
+
+/// Maximum depth of directional quadtrees, where leaves are about 0.1° wide,
+/// as deeper ones would be smaller than the precision of the mapping to directions
+const MAX_DIRECTIONAL_DEPTH: u32 = 12;
+
+/// Maps a direction to the unit square preserving areas, hence uniform
+/// densities on the square are uniform densities on the sphere
+fn dir_to_square(dir: &Vec3) -> Vec2 {
+    let cos_theta = dir.get_z().clamp(-1.0, 1.0);
+    let mut phi = dir.get_y().atan2(dir.get_x());
+    if phi < 0.0 {
+        phi += TAU;
+    }
+    Vec2::new(
+        ((cos_theta + 1.0) / 2.0).min(0.999_999),
+        (phi / TAU).min(0.999_999),
+    )
+}
+
+fn square_to_dir(point: &Vec2) -> Vec3 {
+    let cos_theta = 2.0 * point.x - 1.0;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = TAU * point.y;
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// Returns the quadrant of `point`, and `point` in the space of the quadrant
+fn get_quadrant(point: &Vec2) -> (usize, Vec2) {
+    let right = point.x >= 0.5;
+    let top = point.y >= 0.5;
+    let quadrant = right as usize + 2 * top as usize;
+    let local = Vec2::new(
+        point.x * 2.0 - right as u32 as f32,
+        point.y * 2.0 - top as u32 as f32,
+    );
+    (quadrant, local)
+}
+
+#[derive(Clone, Default)]
+struct QuadNode {
+    /// Radiance recorded by every quadrant
+    sums: [f32; 4],
+
+    /// Child nodes of the quadrants, where 0 stands for none as the root is never a child
+    children: [usize; 4],
+}
+
+impl QuadNode {
+    fn get_total(&self) -> f32 {
+        self.sums.iter().sum()
+    }
+}
+
+/// Distribution of the incoming radiance over the sphere of directions
+#[derive(Clone)]
+pub struct DirectionalTree {
+    nodes: Vec<QuadNode>,
+}
+
+impl Default for DirectionalTree {
+    fn default() -> Self {
+        Self {
+            nodes: vec![QuadNode::default()],
+        }
+    }
+}
+
+impl DirectionalTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_total(&self) -> f32 {
+        self.nodes[0].get_total()
+    }
+
+    /// Adds `radiance` coming from `dir` to the quadrants containing it
+    pub fn record(&mut self, dir: &Vec3, radiance: f32) {
+        if !radiance.is_finite() || radiance <= 0.0 {
+            return;
+        }
+        let mut point = dir_to_square(dir);
+        let mut index = 0;
+        loop {
+            let (quadrant, local) = get_quadrant(&point);
+            let node = &mut self.nodes[index];
+            node.sums[quadrant] += radiance;
+            if node.children[quadrant] == 0 {
+                break;
+            }
+            index = node.children[quadrant];
+            point = local;
+        }
+    }
+
+    /// Returns the probability density of sampling `dir` with respect to solid angle.
+    /// Trees without records sample the sphere uniformly
+    pub fn get_pdf(&self, dir: &Vec3) -> f32 {
+        if self.get_total() <= 0.0 {
+            return 1.0 / (4.0 * PI);
+        }
+        let mut point = dir_to_square(dir);
+        let mut index = 0;
+        let mut pdf = 1.0;
+        loop {
+            let node = &self.nodes[index];
+            let (quadrant, local) = get_quadrant(&point);
+            pdf *= 4.0 * node.sums[quadrant] / node.get_total();
+            if node.children[quadrant] == 0 {
+                break;
+            }
+            index = node.children[quadrant];
+            point = local;
+        }
+        pdf / (4.0 * PI)
+    }
+
+    /// Returns a direction distributed proportionally to the recorded radiance
+    pub fn sample(&self, rng: &mut Rng) -> Vec3 {
+        if self.get_total() <= 0.0 {
+            return rng.next_sphere();
+        }
+        let mut origin = Vec2::new(0.0, 0.0);
+        let mut size = 1.0;
+        let mut index = 0;
+        loop {
+            let node = &self.nodes[index];
+            let mut pick = rng.next_f32() * node.get_total();
+            let mut quadrant = 0;
+            for (i, sum) in node.sums.iter().enumerate() {
+                // Rounding errors should not pick empty quadrants
+                if *sum > 0.0 {
+                    quadrant = i;
+                    if pick < *sum {
+                        break;
+                    }
+                }
+                pick -= sum;
+            }
+            size /= 2.0;
+            origin.x += (quadrant % 2) as f32 * size;
+            origin.y += (quadrant / 2) as f32 * size;
+            if node.children[quadrant] == 0 {
+                break;
+            }
+            index = node.children[quadrant];
+        }
+        let point = Vec2::new(
+            origin.x + rng.next_f32() * size,
+            origin.y + rng.next_f32() * size,
+        );
+        square_to_dir(&point)
+    }
+
+    /// Returns an empty tree whose quadrants are subdivided where they recorded more
+    /// than `threshold` of the total radiance, and merged where they recorded less
+    pub fn get_refined(&self, threshold: f32) -> Self {
+        let mut ret = Self::new();
+        if self.get_total() > 0.0 {
+            self.refine_into(&mut ret, Some(0), 0, 1.0, threshold, 0);
+        }
+        ret
+    }
+
+    /// Subdivides node `target` of `tree` after node `source` of this tree, which
+    /// recorded `flux`, or after a node recording it uniformly when `source` is none
+    fn refine_into(
+        &self,
+        tree: &mut Self,
+        source: Option<usize>,
+        target: usize,
+        flux: f32,
+        threshold: f32,
+        depth: u32,
+    ) {
+        for quadrant in 0..4 {
+            let (sum, child) = match source {
+                Some(source) => {
+                    let node = &self.nodes[source];
+                    let total = node.get_total();
+                    let sum = if total > 0.0 {
+                        flux * node.sums[quadrant] / total
+                    } else {
+                        flux / 4.0
+                    };
+                    let child = Some(node.children[quadrant]).filter(|child| *child != 0);
+                    (sum, child)
+                }
+                None => (flux / 4.0, None),
+            };
+            if sum > threshold && depth < MAX_DIRECTIONAL_DEPTH {
+                let index = tree.nodes.len();
+                tree.nodes.push(QuadNode::default());
+                tree.nodes[target].children[quadrant] = index;
+                self.refine_into(tree, child, index, sum, threshold, depth + 1);
+            }
+        }
+    }
+}
+
+enum SpatialNode {
+    Inner {
+        axis: usize,
+        children: [usize; 2],
+    },
+    Leaf {
+        /// Learned during the previous iteration, used for sampling
+        sampling: Box<DirectionalTree>,
+        /// Learned during the current iteration
+        building: Box<DirectionalTree>,
+        sample_count: u32,
+    },
+}
+
+/// Spatial-directional tree learning the incoming radiance of a scene while rendering,
+/// so that integrators can importance sample bounces towards the light. Rendering goes
+/// by iterations: records of an iteration are used for sampling by the next one
+pub struct GuidingTree {
+    min: Point3,
+    max: Point3,
+    nodes: Vec<SpatialNode>,
+
+    /// Leaves with more records than this are split in two
+    pub max_sample_count: u32,
+
+    /// Fraction of the radiance above which directional quadrants are subdivided
+    pub directional_threshold: f32,
+}
+
+impl GuidingTree {
+    /// Creates a tree for the scene within `min` and `max`
+    pub fn new(min: Point3, max: Point3) -> Self {
+        Self {
+            min,
+            max,
+            nodes: vec![SpatialNode::Leaf {
+                sampling: Box::default(),
+                building: Box::default(),
+                sample_count: 0,
+            }],
+            max_sample_count: 4000,
+            directional_threshold: 0.01,
+        }
+    }
+
+    fn get_leaf(&self, point: &Point3) -> usize {
+        let mut min = self.min;
+        let mut max = self.max;
+        let mut index = 0;
+        while let SpatialNode::Inner { axis, children } = &self.nodes[index] {
+            let axis = *axis;
+            let middle = (min[axis] + max[axis]) / 2.0;
+            let (mut lower, mut upper) = ([min[0], min[1], min[2]], [max[0], max[1], max[2]]);
+            if point[axis] < middle {
+                upper[axis] = middle;
+                index = children[0];
+            } else {
+                lower[axis] = middle;
+                index = children[1];
+            }
+            min = Point3::new(lower[0], lower[1], lower[2]);
+            max = Point3::new(upper[0], upper[1], upper[2]);
+        }
+        index
+    }
+
+    fn get_sampling(&self, point: &Point3) -> &DirectionalTree {
+        match &self.nodes[self.get_leaf(point)] {
+            SpatialNode::Leaf { sampling, .. } => sampling,
+            SpatialNode::Inner { .. } => unreachable!(),
+        }
+    }
+
+    /// Records `radiance` coming from `dir` at `point`
+    pub fn record(&mut self, point: &Point3, dir: &Vec3, radiance: &Color) {
+        let leaf = self.get_leaf(point);
+        if let SpatialNode::Leaf {
+            building,
+            sample_count,
+            ..
+        } = &mut self.nodes[leaf]
+        {
+            building.record(dir, (radiance.r + radiance.g + radiance.b) / 3.0);
+            *sample_count += 1;
+        }
+    }
+
+    /// Returns a direction at `point` sampled after the previous iteration,
+    /// together with its probability density with respect to solid angle
+    pub fn sample(&self, point: &Point3, rng: &mut Rng) -> (Vec3, f32) {
+        let tree = self.get_sampling(point);
+        let dir = tree.sample(rng);
+        (dir, tree.get_pdf(&dir))
+    }
+
+    pub fn get_pdf(&self, point: &Point3, dir: &Vec3) -> f32 {
+        self.get_sampling(point).get_pdf(dir)
+    }
+
+    /// Ends an iteration: leaves with too many records are split, and records
+    /// of this iteration become the distributions sampled by the next one
+    pub fn refine(&mut self) {
+        self.refine_node(0, 0);
+    }
+
+    fn refine_node(&mut self, index: usize, depth: usize) {
+        match &mut self.nodes[index] {
+            SpatialNode::Inner { children, .. } => {
+                let children = *children;
+                for child in children {
+                    self.refine_node(child, depth + 1);
+                }
+            }
+            SpatialNode::Leaf {
+                sampling,
+                building,
+                sample_count,
+            } => {
+                let split = *sample_count > self.max_sample_count;
+                let refined = Box::new(building.get_refined(self.directional_threshold));
+                *sampling = std::mem::replace(building, refined);
+                *sample_count = 0;
+
+                if split {
+                    let leaf = || SpatialNode::Leaf {
+                        sampling: sampling.clone(),
+                        building: building.clone(),
+                        sample_count: 0,
+                    };
+                    let (lower, upper) = (leaf(), leaf());
+                    let children = [self.nodes.len(), self.nodes.len() + 1];
+                    self.nodes[index] = SpatialNode::Inner {
+                        axis: depth % 3,
+                        children,
+                    };
+                    self.nodes.push(lower);
+                    self.nodes.push(upper);
+                }
+            }
+        }
+    }
+
+    pub fn get_leaf_count(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|node| matches!(node, SpatialNode::Leaf { .. }))
+            .count()
+    }
+}
+
+/// Renders like the `Scratcher`, adding one bounce of indirect light. Bounce directions
+/// are picked either from a `GuidingTree`, or around the normal by cosine, and combined
+/// by multiple importance sampling. The tree learns from the bounces of every frame,
+/// and guides the ones of the next frame
+pub struct Guided {
+    scratcher: Scratcher,
+
+    /// Probability of picking bounce directions from the guiding tree
+    pub guiding_fraction: f32,
+
+    tree: RwLock<Option<GuidingTree>>,
+
+    /// Radiance found by the bounces of the current frame
+    records: Mutex<Vec<(Point3, Vec3, Color)>>,
+}
+
+impl Default for Guided {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Guided {
+    pub fn new() -> Self {
+        Self {
+            scratcher: Scratcher::new(),
+            guiding_fraction: 0.5,
+            tree: RwLock::new(None),
+            records: Mutex::new(vec![]),
+        }
+    }
+
+    /// Returns the light coming from a bounce off `hit` reflected towards `-ray.dir`
+    #[allow(clippy::too_many_arguments)]
+    fn bounce(
+        &self,
+        model: &Model,
+        ray: &Ray,
+        hit: &Hit,
+        primitive: &BvhPrimitive,
+        bvh: &Bvh,
+        tree: &GuidingTree,
+        rng: &mut Rng,
+    ) -> Color {
+        let n = primitive.get_normal(model, hit);
+        let fraction = self.guiding_fraction.clamp(0.0, 1.0);
+        let dir = if rng.next_f32() < fraction {
+            tree.sample(&hit.point, rng).0
+        } else {
+            rng.next_cosine_hemisphere(&n)
+        };
+        let cosine_pdf = n.dot(dir).max(0.0) * FRAC_1_PI;
+        if cosine_pdf <= 0.0 {
+            return Color::black();
+        }
+        // Balance heuristic of picking from either distribution
+        let pdf = fraction * tree.get_pdf(&hit.point, &dir) + (1.0 - fraction) * cosine_pdf;
+
+        let bounce_ray = Ray::spawn(&hit.point, &hit.frame().geometric_normal, dir);
+        let Some(incoming) = self.scratcher.trace(model, bounce_ray, bvh, 1, rng) else {
+            return Color::black();
+        };
+        let incoming = incoming / pdf;
+        self.records
+            .lock()
+            .unwrap()
+            .push((hit.point, dir, incoming));
+
+        let albedo = primitive.get_color(model, hit);
+        let uvs = hit.frame().get_uvs();
+        let ir = Irradiance::new(incoming, hit, dir, n, -ray.dir, albedo, uvs);
+        primitive.get_radiance(model, &ir)
+    }
+}
+
+impl Integrator for Guided {
+    /// Ends a guiding iteration, learning from the bounces of the previous frame
+    fn prepare(&self, _model: &Model, bvh: &Bvh, _seed: u64) {
+        let records = std::mem::take(&mut *self.records.lock().unwrap());
+        let mut tree = self.tree.write().unwrap();
+        match tree.as_mut() {
+            Some(tree) => {
+                for (point, dir, radiance) in &records {
+                    tree.record(point, dir, radiance);
+                }
+                tree.refine();
+            }
+            None => {
+                let bounds = bvh.root.get_bounds();
+                if bounds.a.get_x() <= bounds.b.get_x() {
+                    *tree = Some(GuidingTree::new(bounds.a, bounds.b));
+                }
+            }
+        }
+    }
+
+    fn trace(
+        &self,
+        model: &Model,
+        ray: Ray,
+        bvh: &Bvh,
+        depth: u32,
+        rng: &mut Rng,
+    ) -> Option<Color> {
+        if depth > 0 {
+            return self.scratcher.trace(model, ray, bvh, depth, rng);
+        }
+
+        let Some((mut hit, primitive)) = bvh.intersects_iter(model, &ray) else {
+            return self.scratcher.trace(model, ray, bvh, depth, rng);
+        };
+        bvh.shade_hit(model, &mut hit, primitive);
+        let mut color = self
+            .scratcher
+            .shade(model, &ray, &hit, primitive, bvh, depth, rng);
+        if primitive.get_material(model).shadow_catcher {
+            return Some(color);
+        }
+
+        if let Some(tree) = self.tree.read().unwrap().as_ref() {
+            let coverage = color.a;
+            color += self.bounce(model, &ray, &hit, primitive, bvh, tree, rng);
+            color.a = coverage;
+        }
+        Some(color)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn directional() {
+        let mut rng = Rng::new(0);
+        let light = Vec3::new(0.3, 0.4, 1.0).get_normalized();
+
+        // Radiance comes from a small light and from a dim uniform environment
+        let mut tree = DirectionalTree::new();
+        let mut record = |tree: &mut DirectionalTree| {
+            for _ in 0..10000 {
+                let light_dir = (light + rng.next_sphere() * 0.02).get_normalized();
+                tree.record(&light_dir, 1.0);
+                tree.record(&rng.next_sphere(), 0.1);
+            }
+        };
+        for _ in 0..4 {
+            record(&mut tree);
+            tree = tree.get_refined(0.01);
+        }
+        record(&mut tree);
+        assert!(tree.nodes.len() > 4);
+
+        // Samples are distributed as the density says, which covers the whole sphere.
+        // Mapping samples back to the tree can rarely cross the border of a leaf
+        let count = 100000;
+        let pdfs = (0..count)
+            .map(|_| tree.get_pdf(&tree.sample(&mut rng)))
+            .filter(|pdf| *pdf > 0.0)
+            .collect::<Vec<_>>();
+        assert!(pdfs.len() > count * 999 / 1000);
+        let area = pdfs.iter().map(|pdf| 1.0 / pdf).sum::<f32>() / pdfs.len() as f32;
+        assert!((area / (4.0 * PI) - 1.0).abs() < 0.05);
+
+        // Most samples go towards the light
+        let towards_light = (0..1000)
+            .filter(|_| tree.sample(&mut rng).dot(light) > 0.99)
+            .count();
+        assert!(towards_light > 800);
+        assert!(tree.get_pdf(&light) > 100.0 * tree.get_pdf(&-light));
+    }
+
+    #[test]
+    fn spatial() {
+        let mut rng = Rng::new(0);
+        let mut tree = GuidingTree::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+        tree.max_sample_count = 100;
+
+        // Light comes from up on the right side, and from down on the left one
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let right = Point3::new(0.5, 0.0, 0.0);
+        let left = Point3::new(-0.5, 0.0, 0.0);
+        let white = Color::white();
+        for _ in 0..4 {
+            for _ in 0..1000 {
+                tree.record(&right, &up, &white);
+                tree.record(&left, &-up, &white);
+            }
+            tree.refine();
+        }
+        assert!(tree.get_leaf_count() > 1);
+
+        let (dir, pdf) = tree.sample(&right, &mut rng);
+        assert!(dir.get_z() > 0.99);
+        assert!(pdf > 1.0);
+        let (dir, _) = tree.sample(&left, &mut rng);
+        assert!(dir.get_z() < -0.99);
+    }
+}
@@ -2,11 +2,39 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
+pub mod furnace;
+pub mod guiding;
+pub mod photon;
+pub mod preview;
+pub mod restir;
 pub mod scratcher;
+
+pub use furnace::*;
+pub use guiding::*;
+pub use photon::*;
+pub use preview::*;
+pub use restir::*;
 pub use scratcher::*;
 
 use crate::*;
 
 pub trait Integrator: Sync {
-    fn trace(&self, model: &Model, ray: Ray, bvh: &Bvh, depth: u32) -> Option<Color>;
+    /// Called once per frame before tracing any ray, for integrators
+    /// which need to precompute something about the scene
+    fn prepare(&self, _model: &Model, _bvh: &Bvh, _seed: u64) {}
+
+    fn trace(&self, model: &Model, ray: Ray, bvh: &Bvh, depth: u32, rng: &mut Rng)
+        -> Option<Color>;
+}
+
+/// Weight of a sample drawn with probability density `pdf` when combined with another
+/// sampling strategy which would draw it with `other_pdf`, following "Optimally Combining
+/// Sampling Techniques for Monte Carlo Rendering" by Veach and Guibas
+pub fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
 }
@@ -0,0 +1,325 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Progressive photon mapping for caustics, following "Progressive Photon Mapping:
+//! A Probabilistic Approach" by Knaus and Zwicker, where every frame traces a new
+//! set of photons and gathers them with a radius shrinking from one frame to the next.
+
+use std::{
+    collections::HashMap,
+    f32::consts::PI,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        RwLock,
+    },
+};
+
+use crate::*;
+
+/// Maximum number of bounces of a photon
+const MAX_PHOTON_DEPTH: u32 = 8;
+
+/// Photon stored on a diffuse surface after bouncing on specular ones
+pub struct Photon {
+    pub point: Point3,
+    /// Direction the photon was travelling along
+    pub dir: Vec3,
+    pub power: Color,
+}
+
+/// Photons stored in a hash grid whose cells are as large as the gathering radius
+#[derive(Default)]
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    cells: HashMap<[i32; 3], Vec<u32>>,
+    radius: f32,
+}
+
+impl PhotonMap {
+    pub fn new(photons: Vec<Photon>, radius: f32) -> Self {
+        let mut ret = Self {
+            photons,
+            cells: HashMap::new(),
+            radius,
+        };
+        for (i, photon) in ret.photons.iter().enumerate() {
+            let cell = ret.get_cell(&photon.point);
+            ret.cells.entry(cell).or_default().push(i as u32);
+        }
+        ret
+    }
+
+    fn get_cell(&self, point: &Point3) -> [i32; 3] {
+        [0, 1, 2].map(|axis| (point[axis] / self.radius).floor() as i32)
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    pub fn get_radius(&self) -> f32 {
+        self.radius
+    }
+
+    /// Returns the photons within the gathering radius of `point`
+    pub fn gather(&self, point: &Point3) -> impl Iterator<Item = &Photon> {
+        let [x, y, z] = self.get_cell(point);
+        let point = *point;
+        let radius2 = self.radius * self.radius;
+        (-1..=1)
+            .flat_map(move |i| (-1..=1).flat_map(move |j| (-1..=1).map(move |k| [i, j, k])))
+            .filter_map(move |[i, j, k]| self.cells.get(&[x + i, y + j, z + k]))
+            .flatten()
+            .map(move |index| &self.photons[*index as usize])
+            .filter(move |photon| (photon.point - point).norm() <= radius2)
+    }
+}
+
+/// Whether light bounces off `primitive` at `hit` as off a mirror
+fn is_specular(model: &Model, primitive: &BvhPrimitive, hit: &Hit) -> bool {
+    let (metallic, roughness) = primitive.get_metallic_roughness(model, hit);
+    metallic > 0.5 && roughness < 0.1
+}
+
+/// Renders like the `Scratcher`, adding caustics to the surfaces seen by the camera:
+/// light reaching diffuse surfaces after bouncing on specular ones, which shadow rays
+/// towards the lights can not find. Photons are traced from point, quad, directional,
+/// and sky lights, where the sky only contributes its sun
+pub struct PhotonMapper {
+    scratcher: Scratcher,
+
+    /// Photons traced from the lights every frame
+    pub photon_count: u32,
+
+    /// Gathering radius of the first frame
+    pub initial_radius: f32,
+
+    /// How fast the radius shrinks, from 0 to 1, where lower values shrink faster
+    pub alpha: f32,
+
+    map: RwLock<PhotonMap>,
+    frame: AtomicU32,
+}
+
+impl Default for PhotonMapper {
+    fn default() -> Self {
+        Self::new(100000, 0.05)
+    }
+}
+
+impl PhotonMapper {
+    pub fn new(photon_count: u32, initial_radius: f32) -> Self {
+        Self {
+            scratcher: Scratcher::new(),
+            photon_count,
+            initial_radius,
+            alpha: 0.7,
+            map: RwLock::new(PhotonMap::default()),
+            frame: AtomicU32::new(0),
+        }
+    }
+
+    /// Returns the gathering radius of `frame`, where the area of the gathering disk
+    /// shrinks by `(frame + alpha) / (frame + 1)` every frame
+    pub fn get_radius(&self, frame: u32) -> f32 {
+        let mut radius2 = self.initial_radius * self.initial_radius;
+        for i in 1..=frame {
+            radius2 *= (i as f32 - 1.0 + self.alpha) / i as f32;
+        }
+        radius2.sqrt()
+    }
+
+    /// Returns the number of photons in the current map
+    pub fn get_photon_count(&self) -> usize {
+        self.map.read().unwrap().len()
+    }
+
+    /// Returns the origin, direction, and power of a photon leaving `light`, where
+    /// `bounds` are the minimum and maximum points of the scene
+    fn emit(light: &Light, node_trs: &Trs, bounds: &AABB, rng: &mut Rng) -> (Point3, Vec3, Color) {
+        let light_trs = light.sample_trs(node_trs, rng);
+        let origin = Point3::from(light_trs.get_translation());
+        match light {
+            Light::Point(_) => {
+                let dir = rng.next_sphere();
+                let frag = origin + dir;
+                let intensity = light.get_intensity(&light_trs, &frag);
+                let power = intensity * light.get_fallof(&light_trs, &frag) * 4.0;
+                (origin, dir, power)
+            }
+            Light::Quad(_) => {
+                let normal = light_trs.rotation * Vec3::new(0.0, -1.0, 0.0);
+                let dir = rng.next_cosine_hemisphere(&normal);
+                let frag = origin + normal;
+                let power = light.get_intensity(&light_trs, &frag)
+                    * light.get_fallof(&light_trs, &frag)
+                    * light.get_emission(node_trs, &light_trs);
+                (origin, dir, power)
+            }
+            _ => {
+                // Parallel photons leave a disk covering the scene
+                let center = Vec3::from(bounds.a + Vec3::from(bounds.b)) * 0.5;
+                let radius = (bounds.b - bounds.a).len() * 0.5;
+                let dir = -light.get_direction(&light_trs, &Point3::from(center));
+                let (tangent, bitangent) = dir.get_orthonormal_basis();
+                let r = radius * rng.next_f32().sqrt();
+                let phi = 2.0 * PI * rng.next_f32();
+                let offset = tangent * (r * phi.cos()) + bitangent * (r * phi.sin());
+                let origin = Point3::from(center + offset - dir * (radius * 2.0));
+                let frag = Point3::from(center);
+                let power = light.get_intensity(&light_trs, &frag)
+                    * light.get_fallof(&light_trs, &frag)
+                    * (PI * radius * radius);
+                (origin, dir, power)
+            }
+        }
+    }
+
+    /// Follows a photon through specular bounces, returning where it lands on a diffuse
+    /// surface. Photons landing without bouncing are direct light, which is not stored
+    fn trace_photon(
+        model: &Model,
+        bvh: &Bvh,
+        mut ray: Ray,
+        mut power: Color,
+        rng: &mut Rng,
+    ) -> Option<Photon> {
+        for depth in 0..MAX_PHOTON_DEPTH {
+            let (mut hit, primitive) = bvh.intersects_iter(model, &ray)?;
+            bvh.shade_hit(model, &mut hit, primitive);
+            if !is_specular(model, primitive, &hit) {
+                return if depth > 0 {
+                    Some(Photon {
+                        point: hit.point,
+                        dir: ray.dir,
+                        power,
+                    })
+                } else {
+                    None
+                };
+            }
+
+            // Russian roulette on the reflectance keeps the power of photons stable
+            let reflectance = primitive.get_color(model, &hit);
+            let probability = reflectance.r.max(reflectance.g).max(reflectance.b);
+            if rng.next_f32() >= probability {
+                return None;
+            }
+            power = power * reflectance / probability;
+
+            let n = primitive.get_normal(model, &hit);
+            let dir = ray.dir.reflect(&n).get_normalized();
+            ray = Ray::spawn(&hit.point, &hit.frame().geometric_normal, dir);
+        }
+        None
+    }
+}
+
+impl Integrator for PhotonMapper {
+    /// Traces a new set of photons, with a smaller radius than the previous frame
+    fn prepare(&self, model: &Model, bvh: &Bvh, seed: u64) {
+        let frame = self.frame.fetch_add(1, Ordering::Relaxed);
+        let mut rng = Rng::new(seed ^ (frame as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+
+        let lights = model
+            .light_nodes
+            .iter()
+            .filter_map(|handle| {
+                let light_node = model.nodes.get(*handle).unwrap();
+                let light = model.lights.get(light_node.light).unwrap();
+                let light_trs = &model.solved_trs.get(handle).unwrap().trs;
+                match light {
+                    Light::Portal(_) => None,
+                    _ => Some((light, light_trs)),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let bounds = bvh.root.get_bounds();
+        let mut photons = vec![];
+        if !lights.is_empty() && bounds.a.get_x() <= bounds.b.get_x() {
+            // Every light emits the same number of photons
+            let photon_count = self.photon_count / lights.len() as u32;
+            for (light, light_trs) in lights {
+                for _ in 0..photon_count {
+                    let (origin, dir, power) = Self::emit(light, light_trs, bounds, &mut rng);
+                    let ray = Ray::new(origin, dir);
+                    let power = power / photon_count as f32;
+                    if let Some(photon) = Self::trace_photon(model, bvh, ray, power, &mut rng) {
+                        photons.push(photon);
+                    }
+                }
+            }
+        }
+
+        *self.map.write().unwrap() = PhotonMap::new(photons, self.get_radius(frame));
+    }
+
+    fn trace(
+        &self,
+        model: &Model,
+        ray: Ray,
+        bvh: &Bvh,
+        depth: u32,
+        rng: &mut Rng,
+    ) -> Option<Color> {
+        if depth > 0 {
+            return self.scratcher.trace(model, ray, bvh, depth, rng);
+        }
+
+        let Some((mut hit, primitive)) = bvh.intersects_iter(model, &ray) else {
+            return self.scratcher.trace(model, ray, bvh, depth, rng);
+        };
+        bvh.shade_hit(model, &mut hit, primitive);
+        let mut color = self
+            .scratcher
+            .shade(model, &ray, &hit, primitive, bvh, depth, rng);
+
+        // Density estimation of the photons around the hit, reflected by a Lambertian BRDF
+        let map = self.map.read().unwrap();
+        if !map.is_empty() && !is_specular(model, primitive, &hit) {
+            let normal = hit.frame().geometric_normal;
+            let power = map
+                .gather(&hit.point)
+                .filter(|photon| photon.dir.dot(&normal) * ray.dir.dot(&normal) > 0.0)
+                .fold(Color::black(), |sum, photon| sum + photon.power);
+            let (metallic, _) = primitive.get_metallic_roughness(model, &hit);
+            let albedo = primitive.get_color(model, &hit);
+            let radius = map.get_radius();
+            color += albedo * (1.0 - metallic) * power / (PI * PI * radius * radius);
+        }
+
+        Some(color)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn radius() {
+        let mapper = PhotonMapper::new(1, 1.0);
+        assert_eq!(mapper.get_radius(0), 1.0);
+        assert!(mapper.get_radius(1) < 1.0);
+        assert!(mapper.get_radius(10) < mapper.get_radius(1));
+    }
+
+    #[test]
+    fn gather() {
+        let photon = |x| Photon {
+            point: Point3::new(x, 0.0, 0.0),
+            dir: Vec3::new(0.0, -1.0, 0.0),
+            power: Color::white(),
+        };
+        let map = PhotonMap::new(vec![photon(0.05), photon(-0.05), photon(0.5)], 0.1);
+        assert_eq!(map.gather(&Point3::new(0.0, 0.0, 0.0)).count(), 2);
+        assert_eq!(map.gather(&Point3::new(0.45, 0.0, 0.0)).count(), 1);
+        assert_eq!(map.gather(&Point3::new(0.0, 1.0, 0.0)).count(), 0);
+    }
+}
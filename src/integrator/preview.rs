@@ -0,0 +1,163 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use crate::*;
+
+/// Fast integrator for interactive viewers, producing images without noise.
+/// Lights are sampled at their center without a light tree, while indirect light
+/// is replaced by a constant ambient term darkened by an ambient occlusion, which
+/// traces the same directions around every normal instead of random ones
+pub struct Preview {
+    /// Light coming from every direction in place of indirect light
+    pub ambient: Color,
+
+    /// Obstacles farther than this do not occlude the ambient term
+    pub occlusion_radius: f32,
+
+    /// Directions traced to estimate the ambient occlusion,
+    /// where zero disables the ambient occlusion
+    pub occlusion_samples: u32,
+}
+
+impl Default for Preview {
+    fn default() -> Self {
+        Self {
+            ambient: Color::new(0.125, 0.125, 0.125, 1.0),
+            occlusion_radius: 1.0,
+            occlusion_samples: 8,
+        }
+    }
+}
+
+impl Preview {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the fraction of the ambient light reaching `point`, from the harmonic
+    /// mean of the distances of the obstacles around `normal` up to `occlusion_radius`.
+    /// Unlike the arithmetic mean, it is dominated by close obstacles, which keeps
+    /// creases dark even when most of the directions are open
+    pub fn get_occlusion(&self, model: &Model, bvh: &Bvh, point: &Point3, normal: &Vec3) -> f32 {
+        if self.occlusion_samples == 0 || self.occlusion_radius <= 0.0 {
+            return 1.0;
+        }
+
+        let (t, b) = normal.get_orthonormal_basis();
+        let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+        let mut inverse_sum = 0.0;
+        for i in 0..self.occlusion_samples {
+            // Fibonacci spiral covering the hemisphere evenly
+            let cos_theta = 1.0 - (i as f32 + 0.5) / self.occlusion_samples as f32;
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = golden_angle * i as f32;
+            let dir =
+                t * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin()) + normal * cos_theta;
+
+            let ray = Ray::spawn(point, normal, dir);
+            bvh.stats.add_bounce_ray();
+            let distance = bvh
+                .intersects_occluder(model, &ray)
+                .map_or(self.occlusion_radius, |(hit, _)| hit.depth)
+                .clamp(f32::EPSILON, self.occlusion_radius);
+            inverse_sum += 1.0 / distance;
+        }
+
+        let harmonic_mean = self.occlusion_samples as f32 / inverse_sum;
+        harmonic_mean / self.occlusion_radius
+    }
+}
+
+impl Integrator for Preview {
+    fn trace(
+        &self,
+        model: &Model,
+        ray: Ray,
+        bvh: &Bvh,
+        _depth: u32,
+        _rng: &mut Rng,
+    ) -> Option<Color> {
+        let Some((mut hit, primitive)) = bvh.intersects_iter(model, &ray) else {
+            return model.get_background(ray.dir);
+        };
+        bvh.shade_hit(model, &mut hit, primitive);
+
+        let n = primitive.get_normal(model, &hit);
+        let albedo_color = primitive.get_color(model, &hit);
+        let uvs = hit.frame().get_uvs();
+        let geometric_normal = hit.frame().geometric_normal;
+
+        let occlusion = primitive.get_occlusion(model, &hit)
+            * self.get_occlusion(model, bvh, &hit.point, &geometric_normal);
+        let mut pixel_color = albedo_color * self.ambient * occlusion;
+
+        // Direct component
+        for light_node_handle in &model.light_nodes {
+            if !model.is_linked(*light_node_handle, primitive.node) {
+                continue;
+            }
+            let light_node = model.nodes.get(*light_node_handle).unwrap();
+            let light = model.lights.get(light_node.light).unwrap();
+            let light_trs = &light_node.trs;
+            let light_dir = light.get_direction(light_trs, &hit.point);
+
+            let shadow_ray = Ray::spawn(&hit.point, &geometric_normal, light_dir);
+            bvh.stats.add_shadow_ray();
+            let is_light =
+                bvh.intersects_occluder(model, &shadow_ray)
+                    .is_none_or(|(shadow_hit, _)| {
+                        shadow_hit.depth > light.get_distance(light_trs, &hit.point)
+                    });
+            if is_light {
+                let intensity = model.get_light_intensity(light, light_trs, &hit.point)
+                    * light.get_emission(light_trs, light_trs);
+                let ir =
+                    Irradiance::new(intensity, &hit, light_dir, n, -ray.dir, albedo_color, uvs);
+                pixel_color += primitive.get_radiance(model, &ir);
+            }
+        }
+
+        pixel_color.a = 1.0;
+        Some(pixel_color)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn occlusion() {
+        let mut model = Model::new();
+        let plane = model.primitives.push(
+            Primitive::builder()
+                .triangles(Triangles::plane(8.0, 8.0, 1, 1))
+                .build(),
+        );
+        let mesh = model.meshes.push(Mesh::new(vec![plane]));
+        let floor = model.nodes.push(
+            Node::builder()
+                .mesh(mesh)
+                .translation(Vec3::new(0.0, -0.5, 0.0))
+                .build(),
+        );
+        model.root.children.push(floor);
+        let mut scene = Scene::new();
+        scene.push(model);
+        let bvh = scene.build_bvh();
+        let model = &scene.model;
+
+        let preview = Preview::new();
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        // Nothing blocks the ambient light above the floor
+        let open = preview.get_occlusion(model, &bvh, &Point3::new(0.0, 0.0, 0.0), &up);
+        assert_eq!(open, 1.0);
+        // The close floor below is dominating the harmonic mean
+        let occluded = preview.get_occlusion(model, &bvh, &Point3::new(0.0, 0.0, 0.0), &-up);
+        assert!(occluded > 0.5 && occluded < 0.9);
+        // The same directions are traced every time
+        let again = preview.get_occlusion(model, &bvh, &Point3::new(0.0, 0.0, 0.0), &-up);
+        assert_eq!(occluded, again);
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Direct lighting with reservoir resampling, following "Spatiotemporal reservoir
+//! resampling for real-time ray tracing with dynamic direct lighting" by Bitterli et al.
+//! Without screen-space buffers, reservoirs are shared through a hash grid in world
+//! space, as in "World-space spatiotemporal reservoir reuse" by Boissé, where the
+//! reservoirs of the previous frame are reused by every hit falling in the same cell.
+//!
+//! This is the CPU analogue of ReSTIR DI. There is no GPU compute renderer in this crate,
+//! so resampling on the GPU at interactive rates is left to one.
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, RwLock},
+};
+
+use crate::*;
+
+/// A point on a light, picked as a candidate to light a hit
+#[derive(Clone)]
+pub struct LightSample {
+    pub light_node: Handle<Node>,
+    /// Transform of the light moved to the sampled point, see `Light::sample_trs()`
+    pub trs: Trs,
+}
+
+/// Keeps one light sample out of a stream of weighted candidates,
+/// so that the sample is picked proportionally to its weight
+#[derive(Clone, Default)]
+pub struct Reservoir {
+    pub sample: Option<LightSample>,
+    /// Target function of the sample where it was picked
+    pub target: f32,
+    pub weight_sum: f32,
+    /// Number of candidates seen by this reservoir
+    pub count: u32,
+}
+
+impl Reservoir {
+    /// Streams a candidate through the reservoir, returning whether it was picked
+    pub fn update(&mut self, sample: LightSample, target: f32, weight: f32, rng: &mut Rng) -> bool {
+        self.weight_sum += weight;
+        self.count += 1;
+        if weight > 0.0 && rng.next_f32() * self.weight_sum < weight {
+            self.sample = Some(sample);
+            self.target = target;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the weight of the sample, which turns its contribution
+    /// into an estimate of the contribution of all the candidates
+    pub fn get_weight(&self) -> f32 {
+        if self.target > 0.0 && self.count > 0 {
+            self.weight_sum / (self.count as f32 * self.target)
+        } else {
+            0.0
+        }
+    }
+
+    /// Streams the sample of `other` through this reservoir, where `target` is the
+    /// target function of that sample evaluated where this reservoir is used
+    pub fn merge(&mut self, other: &Reservoir, target: f32, rng: &mut Rng) {
+        let count = self.count + other.count;
+        if let Some(sample) = &other.sample {
+            let weight = target * other.get_weight() * other.count as f32;
+            self.update(sample.clone(), target, weight, rng);
+        }
+        self.count = count;
+    }
+
+    /// Limits the candidates seen by the reservoir to `max_count`, so that
+    /// reused samples do not outweigh new ones forever
+    pub fn clamp_count(&mut self, max_count: u32) {
+        if self.count > max_count {
+            self.weight_sum *= max_count as f32 / self.count as f32;
+            self.count = max_count;
+        }
+    }
+}
+
+type CellKey = (i32, i32, i32);
+
+/// Integrator lighting surfaces by resampling many point and quad light candidates, and
+/// tracing a single shadow ray for the one picked, which keeps scenes with many lights
+/// fast at one sample per pixel. Lights at infinity are sampled directly, and there
+/// is no indirect light. Reuse is biased, as it ignores visibility between hits
+pub struct Restir {
+    /// Candidates picked from the light tree for every hit
+    pub candidates: u32,
+
+    /// Side of the cells of the grid sharing reservoirs
+    pub cell_size: f32,
+
+    /// Whether hits reuse the reservoirs of the previous frame in their cell
+    pub reuse: bool,
+
+    /// Maximum number of candidates reused reservoirs may represent,
+    /// as a multiple of `candidates`
+    pub max_history: u32,
+
+    previous: RwLock<HashMap<CellKey, Reservoir>>,
+    current: Mutex<HashMap<CellKey, Reservoir>>,
+}
+
+impl Default for Restir {
+    fn default() -> Self {
+        Self::new(8)
+    }
+}
+
+impl Restir {
+    pub fn new(candidates: u32) -> Self {
+        Self {
+            candidates,
+            cell_size: 0.25,
+            reuse: true,
+            max_history: 20,
+            previous: RwLock::new(HashMap::new()),
+            current: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get_cell(&self, point: &Point3) -> CellKey {
+        let cell = |x: f32| (x / self.cell_size).floor() as i32;
+        (
+            cell(point.get_x()),
+            cell(point.get_y()),
+            cell(point.get_z()),
+        )
+    }
+
+    /// Returns the radiance `sample` reflects from `hit` towards `v` ignoring visibility,
+    /// together with its target function, which is its luminance
+    #[allow(clippy::too_many_arguments)]
+    fn evaluate(
+        model: &Model,
+        hit: &Hit,
+        primitive: &BvhPrimitive,
+        n: Vec3,
+        v: Vec3,
+        albedo: Color,
+        sample: &LightSample,
+    ) -> (Color, f32) {
+        let Some(light_node) = model.nodes.get(sample.light_node) else {
+            return (Color::black(), 0.0);
+        };
+        let Some(light) = model.lights.get(light_node.light) else {
+            return (Color::black(), 0.0);
+        };
+        if !model.is_linked(sample.light_node, primitive.node) {
+            return (Color::black(), 0.0);
+        }
+        let light_dir = light.get_direction(&sample.trs, &hit.point);
+        let intensity = model.get_light_intensity(light, &sample.trs, &hit.point)
+            * light.get_emission(&light_node.trs, &sample.trs);
+        let uvs = hit.frame().get_uvs();
+        let ir = Irradiance::new(intensity, hit, light_dir, n, v, albedo, uvs);
+        let radiance = primitive.get_radiance(model, &ir);
+        let target = ((radiance.r + radiance.g + radiance.b) / 3.0).max(0.0);
+        (radiance, target)
+    }
+
+    /// Returns whether `sample` is visible from `hit`
+    fn is_visible(model: &Model, bvh: &Bvh, hit: &Hit, sample: &LightSample) -> bool {
+        let light_node = model.nodes.get(sample.light_node).unwrap();
+        let light = model.lights.get(light_node.light).unwrap();
+        let light_dir = light.get_direction(&sample.trs, &hit.point);
+        Scratcher::is_light(model, bvh, light, &sample.trs, hit, light_dir)
+    }
+}
+
+impl Integrator for Restir {
+    /// Reservoirs written during the last frame become the ones reused by the next one
+    fn prepare(&self, _model: &Model, _bvh: &Bvh, _seed: u64) {
+        let mut current = self.current.lock().unwrap();
+        *self.previous.write().unwrap() = std::mem::take(&mut *current);
+    }
+
+    fn trace(
+        &self,
+        model: &Model,
+        ray: Ray,
+        bvh: &Bvh,
+        _depth: u32,
+        rng: &mut Rng,
+    ) -> Option<Color> {
+        let Some((mut hit, primitive)) = bvh.intersects_iter(model, &ray) else {
+            return model.get_background(ray.dir);
+        };
+        bvh.shade_hit(model, &mut hit, primitive);
+
+        let n = primitive.get_normal(model, &hit);
+        let v = -ray.dir;
+        let albedo = primitive.get_color(model, &hit);
+        let mut pixel_color = Color::black();
+
+        // Lights at infinity are not part of the light tree
+        for light_node_handle in model.light_tree.get_infinite_lights() {
+            let light_node = model.nodes.get(*light_node_handle).unwrap();
+            let light = model.lights.get(light_node.light).unwrap();
+            let sample = LightSample {
+                light_node: *light_node_handle,
+                trs: light.sample_trs(&light_node.trs, rng),
+            };
+            let (radiance, _) = Self::evaluate(model, &hit, primitive, n, v, albedo, &sample);
+            if Self::is_visible(model, bvh, &hit, &sample) {
+                pixel_color += radiance;
+            }
+        }
+
+        // Resampled importance sampling of the candidates
+        let mut reservoir = Reservoir::default();
+        for _ in 0..self.candidates {
+            let Some((light_node_handle, pdf)) = model.light_tree.sample(&hit.point, rng) else {
+                break;
+            };
+            let light_node = model.nodes.get(light_node_handle).unwrap();
+            let light = model.lights.get(light_node.light).unwrap();
+            let sample = LightSample {
+                light_node: light_node_handle,
+                trs: light.sample_trs(&light_node.trs, rng),
+            };
+            let (_, target) = Self::evaluate(model, &hit, primitive, n, v, albedo, &sample);
+            reservoir.update(sample, target, target / pdf, rng);
+        }
+
+        let cell = self.get_cell(&hit.point);
+        if self.reuse {
+            let previous = self.previous.read().unwrap().get(&cell).cloned();
+            if let Some(previous) = previous {
+                let target = previous.sample.as_ref().map_or(0.0, |sample| {
+                    Self::evaluate(model, &hit, primitive, n, v, albedo, sample).1
+                });
+                reservoir.merge(&previous, target, rng);
+            }
+            let mut current = self.current.lock().unwrap();
+            let shared = current.entry(cell).or_default();
+            shared.merge(&reservoir, reservoir.target, rng);
+            shared.clamp_count(self.candidates.max(1) * self.max_history);
+        }
+
+        if let Some(sample) = &reservoir.sample {
+            if Self::is_visible(model, bvh, &hit, sample) {
+                let (radiance, _) = Self::evaluate(model, &hit, primitive, n, v, albedo, sample);
+                pixel_color += radiance * reservoir.get_weight();
+            }
+        }
+
+        pixel_color.a = 1.0;
+        Some(pixel_color)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reservoir() {
+        let sample = |i: u32| LightSample {
+            light_node: Handle::new(i as usize),
+            trs: Trs::default(),
+        };
+
+        // Candidates are picked proportionally to their weight
+        let mut rng = Rng::new(0);
+        let mut picked = [0; 2];
+        for _ in 0..1000 {
+            let mut reservoir = Reservoir::default();
+            reservoir.update(sample(0), 1.0, 1.0, &mut rng);
+            reservoir.update(sample(1), 3.0, 3.0, &mut rng);
+            reservoir.update(sample(2), 0.0, 0.0, &mut rng);
+            assert_eq!(reservoir.count, 3);
+            let id = reservoir.sample.unwrap().light_node.id;
+            picked[id] += 1;
+        }
+        assert!(picked[1] > 700 && picked[1] < 800);
+
+        // Merging keeps the candidates of both reservoirs
+        let mut a = Reservoir::default();
+        a.update(sample(0), 2.0, 2.0, &mut rng);
+        let mut b = Reservoir::default();
+        b.update(sample(1), 2.0, 2.0, &mut rng);
+        b.update(sample(1), 2.0, 2.0, &mut rng);
+        a.merge(&b, 2.0, &mut rng);
+        assert_eq!(a.count, 3);
+        assert_eq!(a.get_weight(), 1.0);
+
+        a.clamp_count(1);
+        assert_eq!(a.count, 1);
+        assert_eq!(a.get_weight(), 1.0);
+    }
+}
@@ -4,8 +4,33 @@
 
 use crate::*;
 
-#[derive(Default)]
-pub struct Scratcher {}
+pub struct Scratcher {
+    /// Point and quad lights sampled for every hit. Scenes with more lights than
+    /// these pick them from the `LightTree` of the model instead
+    pub max_lights: usize,
+
+    /// Quad lights are split in a grid of `light_stratify` x `light_stratify` cells,
+    /// and sampled `light_samples` times per cell, which reduces the noise of soft shadows
+    pub light_stratify: u32,
+    pub light_samples: u32,
+
+    /// Directions where the sky is sampled for every hit. Each sample traces a direction
+    /// from the `SkyDistribution` of the model, and a cosine-weighted one around the normal
+    /// standing in for BSDF sampling, combined by multiple importance sampling. When set,
+    /// bounces leaving the scene see nothing, as the sky would be counted twice
+    pub sky_samples: u32,
+}
+
+impl Default for Scratcher {
+    fn default() -> Self {
+        Self {
+            max_lights: 8,
+            light_stratify: 1,
+            light_samples: 1,
+            sky_samples: 0,
+        }
+    }
+}
 
 impl Scratcher {
     pub fn new() -> Self {
@@ -14,87 +39,262 @@ impl Scratcher {
 }
 
 impl Integrator for Scratcher {
-    fn trace(&self, model: &Model, ray: Ray, bvh: &Bvh, depth: u32) -> Option<Color> {
+    fn trace(
+        &self,
+        model: &Model,
+        ray: Ray,
+        bvh: &Bvh,
+        depth: u32,
+        rng: &mut Rng,
+    ) -> Option<Color> {
         if depth > 1 {
             return None;
         }
+        if depth > 0 {
+            bvh.stats.add_bounce_ray();
+        }
+
+        let Some((mut hit, primitive)) = bvh.intersects_iter(model, &ray) else {
+            // Bounces already sample the sun as a light, and the sky through portals
+            if depth == 0 {
+                return model.get_background(ray.dir);
+            }
+            if model.has_portals() || self.samples_sky(model) {
+                return None;
+            }
+            return model.get_sky_radiance(ray.dir, false);
+        };
+        bvh.shade_hit(model, &mut hit, primitive);
+
+        Some(self.shade(model, &ray, &hit, primitive, bvh, depth, rng))
+    }
+}
 
-        let (hit, primitive) = bvh.intersects_iter(model, &ray)?;
+impl Scratcher {
+    /// Returns the color of `hit` on `primitive`, which `ray` found
+    #[allow(clippy::too_many_arguments)]
+    pub fn shade(
+        &self,
+        model: &Model,
+        ray: &Ray,
+        hit: &Hit,
+        primitive: &BvhPrimitive,
+        bvh: &Bvh,
+        depth: u32,
+        rng: &mut Rng,
+    ) -> Color {
+        if primitive.get_material(model).shadow_catcher {
+            return self.catch_shadow(model, hit, primitive, bvh, rng);
+        }
 
-        let n = primitive.get_normal(model, &hit);
+        let n = primitive.get_normal(model, hit);
 
-        let albedo_color = primitive.get_color(model, &hit);
+        let albedo_color = primitive.get_color(model, hit);
 
         // Ambient?
-        let mut pixel_color = Color::black() + albedo_color / 8.0;
-        const RAY_BIAS: f32 = 1e-3;
+        let occlusion = primitive.get_occlusion(model, hit);
+        let mut pixel_color = Color::black() + albedo_color / 8.0 * occlusion;
+        // New rays leave from the actual surface, not the shading one
+        let geometric_normal = hit.frame().geometric_normal;
+
+        // Fraction of the ray blocked by this surface and the ones behind it
+        let mut coverage = 1.0;
 
         if albedo_color.a < 1.0 {
-            let transmit_origin = hit.point + -n * RAY_BIAS;
-            let transmit_ray = Ray::new(transmit_origin, ray.dir);
-            let transmit_result = self.trace(model, transmit_ray, bvh, depth + 1);
-
-            if let Some(mut transmit_color) = transmit_result {
-                // continue with the rest of the shading?
-                transmit_color.over(albedo_color);
-                pixel_color += transmit_color;
-            }
-        }
+            let transmit_ray = Ray::spawn(&hit.point, &geometric_normal, ray.dir);
+            let transmit_result = self.trace(model, transmit_ray, bvh, depth + 1, rng);
 
-        // Before getting color, we should check whether it is visible from the sun
-        let next_origin = hit.point + n * RAY_BIAS;
+            // Nothing behind leaves the background visible through the surface
+            let mut transmit_color = transmit_result.unwrap_or(Color::new(0.0, 0.0, 0.0, 0.0));
+            // continue with the rest of the shading?
+            transmit_color.over(albedo_color);
+            coverage = transmit_color.a;
+            transmit_color.a = 1.0;
+            pixel_color += transmit_color;
+        }
 
-        let uv = primitive.geometry.get_uv(&hit);
+        let uvs = hit.frame().get_uvs();
 
         // Direct component
-        for light_node_handle in &model.light_nodes {
-            let light_node = model.nodes.get(*light_node_handle).unwrap();
+        let lights = model.light_tree.select(&hit.point, self.max_lights, rng);
+        for (light_node_handle, weight) in lights {
+            if !model.is_linked(light_node_handle, primitive.node) {
+                continue;
+            }
+            let light_node = model.nodes.get(light_node_handle).unwrap();
             let light = model.lights.get(light_node.light).unwrap();
-            let light_dir = light.get_direction(&light_node.trs, &hit.point);
-
-            let shadow_ray = Ray::new(next_origin, light_dir);
-            let shadow_result = bvh.intersects_iter(model, &shadow_ray);
-
-            // Whether this object is light (verb) by a light (noun)
-            let is_light = match shadow_result {
-                None => true,
-                Some((shadow_hit, primitive)) => {
-                    // Distance between current surface and the light source
-                    let light_distance = light.get_distance(&light_node.trs, &hit.point);
-                    // If the obstacle is beyong the light source then the current surface is light
-                    if shadow_hit.depth > light_distance {
-                        true
-                    } else {
-                        // Check whether the obstacle is a transparent surface
-                        let shadow_color = primitive.get_color(model, &shadow_hit);
-                        shadow_color.a < 1.0
-                    }
-                }
+            let (grid, samples) = match light {
+                Light::Quad(_) => (self.light_stratify.max(1), self.light_samples.max(1)),
+                _ => (1, 1),
             };
+            let sample_count = grid * grid * samples;
+            let weight = weight / sample_count as f32;
+
+            for sample in 0..sample_count {
+                let light_trs = light.sample_cell_trs(&light_node.trs, sample / samples, grid, rng);
+                let light_dir = light.get_direction(&light_trs, &hit.point);
 
-            if is_light {
-                let intensity = light.get_intensity(&light_node.trs, &hit.point);
-                let ir = Irradiance::new(intensity, &hit, light_dir, n, -ray.dir, albedo_color, uv);
-                pixel_color += primitive.get_radiance(model, &ir);
+                // Whether this object is light (verb) by a light (noun)
+                let is_light = Self::is_light(model, bvh, light, &light_trs, hit, light_dir);
+                if is_light {
+                    let intensity = model.get_light_intensity(light, &light_trs, &hit.point)
+                        * light.get_emission(&light_node.trs, &light_trs)
+                        * weight;
+                    let ir =
+                        Irradiance::new(intensity, hit, light_dir, n, -ray.dir, albedo_color, uvs);
+                    pixel_color += primitive.get_radiance(model, &ir);
+                }
             }
         } // end iterate light
 
+        // Sky component
+        if self.samples_sky(model) {
+            let v = -ray.dir;
+            pixel_color += self.sample_sky(model, hit, primitive, bvh, n, v, albedo_color, rng);
+        }
+
         // Reflection component
         let reflection_dir = ray.dir.reflect(&n).get_normalized();
-        let reflection_ray = Ray::new(next_origin, reflection_dir);
-        if let Some(reflection_intensity) = self.trace(model, reflection_ray, bvh, depth + 1) {
+        let reflection_ray = Ray::spawn(&hit.point, &geometric_normal, reflection_dir);
+        if let Some(reflection_intensity) = self.trace(model, reflection_ray, bvh, depth + 1, rng) {
             let ir = Irradiance::new(
                 reflection_intensity,
-                &hit,
+                hit,
                 reflection_dir,
                 n,
                 -ray.dir,
                 albedo_color,
-                uv,
+                uvs,
             );
             pixel_color += primitive.get_radiance(model, &ir);
         }
 
-        Some(pixel_color)
+        pixel_color.a = coverage;
+        pixel_color
+    }
+
+    /// Whether hits sample the sky, which portals let in already
+    fn samples_sky(&self, model: &Model) -> bool {
+        self.sky_samples > 0 && model.sky_distribution.is_some() && !model.has_portals()
+    }
+
+    /// Returns the light of the sky reflected by `hit` towards `v`, see `sky_samples`
+    #[allow(clippy::too_many_arguments)]
+    fn sample_sky(
+        &self,
+        model: &Model,
+        hit: &Hit,
+        primitive: &BvhPrimitive,
+        bvh: &Bvh,
+        n: Vec3,
+        v: Vec3,
+        albedo: Color,
+        rng: &mut Rng,
+    ) -> Color {
+        let sky = model.sky_distribution.as_ref().unwrap();
+        let get_cosine_pdf = |dir: &Vec3| n.dot(*dir).max(0.0) * std::f32::consts::FRAC_1_PI;
+
+        // Light of the sky coming from `dir` reflected towards `v`
+        let get_reflected = |dir: Vec3| {
+            let ray = Ray::spawn(&hit.point, &hit.frame().geometric_normal, dir);
+            bvh.stats.add_shadow_ray();
+            if bvh.intersects_occluder(model, &ray).is_some() {
+                return Color::black();
+            }
+            let radiance = model.get_sky_radiance(dir, false).unwrap_or(Color::black());
+            let ir = Irradiance::new(radiance, hit, dir, n, v, albedo, hit.frame().get_uvs());
+            primitive.get_radiance(model, &ir)
+        };
+
+        let mut ret = Color::black();
+        for _ in 0..self.sky_samples {
+            if let Some((dir, pdf)) = sky.sample(rng) {
+                if get_cosine_pdf(&dir) > 0.0 {
+                    let weight = power_heuristic(pdf, get_cosine_pdf(&dir));
+                    ret += get_reflected(dir) * (weight / pdf);
+                }
+            }
+
+            let dir = rng.next_cosine_hemisphere(&n);
+            let pdf = get_cosine_pdf(&dir);
+            if pdf > 0.0 {
+                let weight = power_heuristic(pdf, sky.get_pdf(&dir));
+                ret += get_reflected(dir) * (weight / pdf);
+            }
+        }
+        ret / self.sky_samples as f32
+    }
+
+    /// Returns whether `hit` receives light from `light` along `light_dir`, tracing a shadow
+    /// ray which goes through transparent surfaces and ignores obstacles beyond the light
+    pub(crate) fn is_light(
+        model: &Model,
+        bvh: &Bvh,
+        light: &Light,
+        light_trs: &Trs,
+        hit: &Hit,
+        light_dir: Vec3,
+    ) -> bool {
+        let shadow_ray = Ray::spawn(&hit.point, &hit.frame().geometric_normal, light_dir);
+        bvh.stats.add_shadow_ray();
+        match bvh.intersects_occluder(model, &shadow_ray) {
+            None => true,
+            Some((mut shadow_hit, primitive)) => {
+                // Distance between current surface and the light source
+                let light_distance = light.get_distance(light_trs, &hit.point);
+                // If the obstacle is beyong the light source then the current surface is light
+                if shadow_hit.depth > light_distance {
+                    true
+                } else {
+                    // Check whether the obstacle is a transparent surface,
+                    // whose color needs the texture coordinates of its frame
+                    shadow_hit.frame = Some(primitive.get_shading_frame(model, &shadow_hit));
+                    let shadow_color = primitive.get_color(model, &shadow_hit);
+                    shadow_color.a < 1.0
+                }
+            }
+        }
+    }
+
+    /// Returns a black color whose alpha is the fraction of the light reaching `hit`
+    /// which other objects block, as seen by a shadow catcher
+    fn catch_shadow(
+        &self,
+        model: &Model,
+        hit: &Hit,
+        primitive: &BvhPrimitive,
+        bvh: &Bvh,
+        rng: &mut Rng,
+    ) -> Color {
+        let n = primitive.get_normal(model, hit);
+        let mut total = 0.0;
+        let mut blocked = 0.0;
+        let lights = model.light_tree.select(&hit.point, self.max_lights, rng);
+        for (light_node_handle, light_weight) in lights {
+            if !model.is_linked(light_node_handle, primitive.node) {
+                continue;
+            }
+            let light_node = model.nodes.get(light_node_handle).unwrap();
+            let light = model.lights.get(light_node.light).unwrap();
+            let light_trs = light.sample_trs(&light_node.trs, rng);
+            let light_dir = light.get_direction(&light_trs, &hit.point);
+            let n_dot_l = n.dot(&light_dir);
+            if n_dot_l <= 0.0 {
+                continue;
+            }
+
+            // Lights weigh by how much they would brighten the surface
+            let intensity = model.get_light_intensity(light, &light_trs, &hit.point)
+                * light.get_emission(&light_node.trs, &light_trs)
+                * light.get_fallof(&light_trs, &hit.point);
+            let weight = (intensity.r + intensity.g + intensity.b) / 3.0 * n_dot_l * light_weight;
+            total += weight;
+            if !Self::is_light(model, bvh, light, &light_trs, hit, light_dir) {
+                blocked += weight;
+            }
+        }
+
+        let shadow = if total > 0.0 { blocked / total } else { 0.0 };
+        Color::new(0.0, 0.0, 0.0, shadow)
     }
 }
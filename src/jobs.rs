@@ -0,0 +1,181 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::{
+    panic::AssertUnwindSafe,
+    sync::{Arc, Condvar, Mutex},
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    sync::mpsc::{self, Sender},
+    thread::{self, JoinHandle},
+};
+
+use crate::RaycaError;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Result of a job, filled in by the thread running it, or the panic which stopped it
+struct Slot<T> {
+    value: Mutex<Option<Result<T, RaycaError>>>,
+    ready: Condvar,
+}
+
+/// Future-like handle to the result of a job running in a `JobPool`
+pub struct JobHandle<T> {
+    slot: Arc<Slot<T>>,
+}
+
+impl<T> JobHandle<T> {
+    pub fn is_ready(&self) -> bool {
+        self.slot.value.lock().unwrap().is_some()
+    }
+
+    /// Returns the result when the job has finished, without blocking, or
+    /// `RaycaError::Panic` when it panicked. The result is returned only once
+    pub fn poll(&mut self) -> Option<Result<T, RaycaError>> {
+        self.slot.value.lock().unwrap().take()
+    }
+
+    /// Blocks until the job has finished, returning its result, see `poll()`
+    pub fn wait(self) -> Result<T, RaycaError> {
+        let mut value = self.slot.value.lock().unwrap();
+        loop {
+            if let Some(ret) = value.take() {
+                return ret;
+            }
+            value = self.slot.ready.wait(value).unwrap();
+        }
+    }
+}
+
+/// Runs jobs such as loading models on background threads, so that the caller can keep
+/// rendering in the meantime. Where threads are not available, jobs run immediately.
+pub struct JobPool {
+    #[cfg(not(target_arch = "wasm32"))]
+    sender: Option<Sender<Job>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Default for JobPool {
+    fn default() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let thread_count = thread::available_parallelism().map_or(1, |count| count.get());
+        #[cfg(target_arch = "wasm32")]
+        let thread_count = 1;
+        Self::new(thread_count)
+    }
+}
+
+impl JobPool {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new(thread_count: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..thread_count.max(1))
+            .map(|_| {
+                let receiver = receiver.clone();
+                thread::spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        // The pool has been dropped
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn new(_thread_count: usize) -> Self {
+        Self {}
+    }
+
+    /// Queues `job` and returns a handle to its result
+    pub fn spawn<T, F>(&self, job: F) -> JobHandle<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let slot = Arc::new(Slot {
+            value: Mutex::new(None),
+            ready: Condvar::new(),
+        });
+        let job_slot = slot.clone();
+        let job: Job = Box::new(move || {
+            // Panics stop the job, not the thread running it
+            let value = std::panic::catch_unwind(AssertUnwindSafe(job)).map_err(|panic| {
+                let message = match panic.downcast_ref::<&str>() {
+                    Some(message) => message.to_string(),
+                    None => panic.downcast_ref::<String>().cloned().unwrap_or_default(),
+                };
+                RaycaError::Panic(message)
+            });
+            *job_slot.value.lock().unwrap() = Some(value);
+            job_slot.ready.notify_all();
+        });
+
+        #[cfg(not(target_arch = "wasm32"))]
+        self.sender.as_ref().unwrap().send(job).unwrap();
+        #[cfg(target_arch = "wasm32")]
+        job();
+
+        JobHandle { slot }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for JobPool {
+    fn drop(&mut self) {
+        // Workers stop once the channel is closed and the queued jobs are done
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn jobs() {
+        let pool = JobPool::new(2);
+        let handles: Vec<JobHandle<u32>> = (0..8).map(|i| pool.spawn(move || i * i)).collect();
+        let results = handles
+            .into_iter()
+            .map(JobHandle::wait)
+            .collect::<Result<Vec<u32>, _>>()
+            .unwrap();
+        assert_eq!(results, vec![0, 1, 4, 9, 16, 25, 36, 49]);
+
+        let mut handle = pool.spawn(|| "done");
+        while !handle.is_ready() {
+            std::thread::yield_now();
+        }
+        assert_eq!(handle.poll().unwrap().unwrap(), "done");
+        assert!(handle.poll().is_none());
+    }
+
+    #[test]
+    fn panic() {
+        let pool = JobPool::new(1);
+        let handle = pool.spawn(|| -> u32 { panic!("{}", "broken job") });
+        let err = handle.wait().unwrap_err();
+        assert!(matches!(err, RaycaError::Panic(_)));
+        assert_eq!(err.to_string(), "Job panicked: broken job");
+
+        // The thread survives the panic and runs the next job
+        assert_eq!(pool.spawn(|| 2).wait().unwrap(), 2);
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::{convert::TryInto, path::Path};
+
+use super::*;
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const HEADER_SIZE: usize = 80;
+const LEVEL_SIZE: usize = 24;
+
+const VK_FORMAT_UNDEFINED: u32 = 0;
+const VK_FORMAT_R8G8B8_UNORM: u32 = 23;
+const VK_FORMAT_R8G8B8_SRGB: u32 = 29;
+const VK_FORMAT_R8G8B8A8_UNORM: u32 = 37;
+const VK_FORMAT_R8G8B8A8_SRGB: u32 = 43;
+
+const SUPERCOMPRESSION_NONE: u32 = 0;
+const SUPERCOMPRESSION_BASIS_LZ: u32 = 1;
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+impl Image {
+    /// Loads the base level of a KTX2 texture. Only uncompressed 8-bit RGB and RGBA
+    /// formats are supported, Basis Universal payloads need a transcoder which is not
+    /// available, hence they are reported as errors. glTF textures using them through
+    /// `KHR_texture_basisu` fall back to their PNG or JPEG source.
+    pub fn load_ktx2_data(data: &[u8]) -> Result<Image, RaycaError> {
+        if data.len() < HEADER_SIZE || data[..IDENTIFIER.len()] != IDENTIFIER {
+            return Err(RaycaError::Parse("KTX2: missing identifier".into()));
+        }
+
+        let vk_format = read_u32(data, 12);
+        let width = read_u32(data, 20);
+        let height = read_u32(data, 24).max(1);
+        let depth = read_u32(data, 28);
+        let layer_count = read_u32(data, 32);
+        let face_count = read_u32(data, 36);
+        let supercompression = read_u32(data, 44);
+
+        if vk_format == VK_FORMAT_UNDEFINED || supercompression == SUPERCOMPRESSION_BASIS_LZ {
+            return Err(RaycaError::Unsupported(
+                "Basis Universal KTX2 textures".into(),
+            ));
+        }
+        if supercompression != SUPERCOMPRESSION_NONE {
+            return Err(RaycaError::Unsupported(format!(
+                "KTX2 supercompression {}",
+                supercompression
+            )));
+        }
+        if depth > 1 || layer_count > 1 || face_count > 1 {
+            return Err(RaycaError::Unsupported(
+                "KTX2 textures which are not 2D".into(),
+            ));
+        }
+
+        let color_type = match vk_format {
+            VK_FORMAT_R8G8B8_UNORM | VK_FORMAT_R8G8B8_SRGB => ColorType::RGB8,
+            VK_FORMAT_R8G8B8A8_UNORM | VK_FORMAT_R8G8B8A8_SRGB => ColorType::RGBA8,
+            _ => {
+                return Err(RaycaError::Unsupported(format!(
+                    "KTX2 format {}",
+                    vk_format
+                )))
+            }
+        };
+
+        // Level 0 is the largest one, which is the first of the index
+        if data.len() < HEADER_SIZE + LEVEL_SIZE {
+            return Err(RaycaError::Parse("KTX2: missing levels".into()));
+        }
+        let offset = read_u64(data, HEADER_SIZE);
+        let length = read_u64(data, HEADER_SIZE + 8);
+
+        // Sizes come from the file, hence they are checked before allocating the image
+        let expected_length = (width as u64)
+            .checked_mul(height as u64)
+            .and_then(|pixels| pixels.checked_mul(color_type.channels() as u64));
+        let end = offset.checked_add(length);
+        if expected_length != Some(length) || end.is_none_or(|end| end > data.len() as u64) {
+            return Err(RaycaError::Parse("KTX2: invalid level size".into()));
+        }
+        let (offset, length) = (offset as usize, length as usize);
+
+        let mut ret = Self::new(width, height, color_type);
+        ret.bytes_mut()
+            .copy_from_slice(&data[offset..offset + length]);
+        Ok(ret)
+    }
+
+    pub fn load_ktx2_file<P: AsRef<Path>>(path: P) -> Result<Image, RaycaError> {
+        let data = std::fs::read(path)?;
+        Self::load_ktx2_data(&data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Writes a single level KTX2 file
+    fn encode(vk_format: u32, supercompression: u32, width: u32, height: u32) -> Vec<u8> {
+        let level = vec![0x7F; width as usize * height as usize * 4];
+        let mut data = IDENTIFIER.to_vec();
+        for value in [vk_format, 1, width, height, 0, 0, 1, 1, supercompression] {
+            data.extend(value.to_le_bytes());
+        }
+        data.extend([0; 32]);
+        let offset = (HEADER_SIZE + LEVEL_SIZE) as u64;
+        for value in [offset, level.len() as u64, level.len() as u64] {
+            data.extend(value.to_le_bytes());
+        }
+        data.extend(level);
+        data
+    }
+
+    #[test]
+    fn ktx2() {
+        let data = encode(VK_FORMAT_R8G8B8A8_SRGB, SUPERCOMPRESSION_NONE, 2, 3);
+        let image = Image::load_ktx2_data(&data).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 3));
+        assert!(image.color_type == ColorType::RGBA8);
+        assert!(image.bytes().iter().all(|&byte| byte == 0x7F));
+
+        let basis = encode(VK_FORMAT_UNDEFINED, SUPERCOMPRESSION_BASIS_LZ, 2, 3);
+        assert!(Image::load_ktx2_data(&basis).is_err());
+        assert!(Image::load_ktx2_data(&data[..HEADER_SIZE]).is_err());
+
+        // Huge sizes in the header are refused before allocating anything
+        let mut huge = encode(VK_FORMAT_R8G8B8A8_SRGB, SUPERCOMPRESSION_NONE, 2, 3);
+        huge[20..28].copy_from_slice(&[0xFF; 8]);
+        assert!(Image::load_ktx2_data(&huge).is_err());
+        let mut truncated = data.clone();
+        truncated.pop();
+        assert!(Image::load_ktx2_data(&truncated).is_err());
+    }
+    #[test]
+    fn basisu() {
+        // Textures prefer the KTX2 image of KHR_texture_basisu, falling back to their
+        // source when it can not be decoded
+        let load = |ktx2: Vec<u8>| {
+            let png = Image::new(1, 1, ColorType::RGBA8).encode_png();
+            let mut bin = ktx2.clone();
+            bin.extend(&png);
+            let gltf = format!(
+                r#"{{
+                    "asset": {{ "version": "2.0" }},
+                    "extensionsUsed": ["KHR_texture_basisu"],
+                    "buffers": [{{
+                        "byteLength": {},
+                        "uri": "data:application/octet-stream;base64,{}"
+                    }}],
+                    "bufferViews": [
+                        {{ "buffer": 0, "byteLength": {} }},
+                        {{ "buffer": 0, "byteOffset": {}, "byteLength": {} }}
+                    ],
+                    "images": [
+                        {{ "bufferView": 0, "mimeType": "image/ktx2" }},
+                        {{ "bufferView": 1, "mimeType": "image/png" }}
+                    ],
+                    "textures": [{{
+                        "source": 1,
+                        "extensions": {{ "KHR_texture_basisu": {{ "source": 0 }} }}
+                    }}],
+                    "scenes": [{{ "nodes": [] }}]
+                }}"#,
+                bin.len(),
+                base64::encode(&bin),
+                ktx2.len(),
+                ktx2.len(),
+                png.len()
+            );
+            let model = Model::builder().data(gltf.as_bytes())?.build()?;
+            Ok::<_, RaycaError>(model.textures.get(Handle::new(0)).unwrap().image)
+        };
+
+        let data = encode(VK_FORMAT_R8G8B8A8_SRGB, SUPERCOMPRESSION_NONE, 2, 3);
+        assert_eq!(load(data).unwrap(), Handle::new(0));
+        let basis = encode(VK_FORMAT_UNDEFINED, SUPERCOMPRESSION_BASIS_LZ, 2, 3);
+        assert_eq!(load(basis).unwrap(), Handle::new(1));
+    }
+}
@@ -2,45 +2,99 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
-#![feature(portable_simd)]
+#![cfg_attr(feature = "simd", feature(portable_simd))]
 
+pub mod aov;
+pub mod bake;
 pub mod bvh;
 pub mod camera;
+pub mod checkpoint;
 pub mod config;
+pub mod controller;
+pub mod debug_lines;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod distributed;
 pub mod draw;
+pub mod error;
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod geometry;
 pub mod image;
 pub mod integrator;
+pub mod jobs;
+pub mod ktx2;
 pub mod light;
+pub mod light_tree;
 pub mod log;
 pub mod material;
+pub mod material_graph;
 pub mod math;
 pub mod mesh;
 pub mod model;
 pub mod node;
+pub mod observer;
+pub mod particle;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod rng;
 pub mod sampler;
 pub mod scene;
+pub mod scenes;
+pub mod script;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod sky;
+pub mod stats;
+pub mod streaming;
+pub mod temporal;
+pub mod text;
 pub mod texture;
 pub mod util;
+pub mod validation;
 #[cfg(target_arch = "wasm32")]
 pub mod www;
 
+pub use aov::*;
+pub use bake::*;
 pub use bvh::*;
 pub use camera::*;
+pub use checkpoint::*;
 pub use config::*;
+pub use controller::*;
+pub use debug_lines::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use distributed::*;
 pub use draw::*;
+pub use error::*;
 pub use geometry::*;
 pub use image::*;
 pub use integrator::*;
+pub use jobs::*;
 pub use light::*;
+pub use light_tree::*;
+pub use log::*;
 pub use material::*;
+pub use material_graph::*;
 pub use math::*;
 pub use mesh::*;
 pub use model::*;
 pub use node::*;
+pub use observer::*;
+pub use particle::*;
+pub use rng::*;
 pub use sampler::*;
 pub use scene::*;
+pub use script::*;
+#[cfg(feature = "server")]
+pub use server::*;
+pub use sky::*;
+pub use stats::*;
+pub use streaming::*;
+pub use temporal::*;
+pub use text::*;
 pub use texture::*;
 pub use util::*;
+pub use validation::*;
 #[cfg(target_arch = "wasm32")]
 pub use www::*;
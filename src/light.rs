@@ -7,6 +7,9 @@ use super::*;
 pub enum Light {
     Directional(DirectionalLight),
     Point(PointLight),
+    Quad(QuadLight),
+    Sky(SkyLight),
+    Portal(PortalLight),
 }
 
 impl Light {
@@ -18,10 +21,27 @@ impl Light {
         Self::Point(PointLight::new())
     }
 
+    pub fn quad(width: f32, height: f32) -> Self {
+        Self::Quad(QuadLight::new(width, height))
+    }
+
+    /// Sky lit by the sun in `sun_direction`, see `SkyLight`
+    pub fn sky(sun_direction: Vec3, turbidity: f32) -> Self {
+        Self::Sky(SkyLight::new(sun_direction, turbidity))
+    }
+
+    /// Opening letting the sky into an interior, see `PortalLight`
+    pub fn portal(width: f32, height: f32) -> Self {
+        Self::Portal(PortalLight::new(width, height))
+    }
+
     pub fn set_intensity(&mut self, intensity: f32) {
         match self {
             Light::Directional(light) => light.set_intensity(intensity),
             Light::Point(light) => light.set_intensity(intensity),
+            Light::Quad(light) => light.set_intensity(intensity),
+            Light::Sky(light) => light.set_intensity(intensity),
+            Light::Portal(_) => (),
         }
     }
 
@@ -29,13 +49,21 @@ impl Light {
         match self {
             Light::Directional(light) => light.get_distance(light_trs, frag_pos),
             Light::Point(light) => light.get_distance(light_trs, frag_pos),
+            Light::Quad(light) => light.get_distance(light_trs, frag_pos),
+            Light::Sky(light) => light.get_distance(),
+            Light::Portal(light) => light.get_distance(light_trs, frag_pos),
         }
     }
 
+    /// Portals return their solid angle as seen from `frag_pos`, which should be
+    /// multiplied by the radiance of the sky, see `Model::get_light_intensity()`
     pub fn get_intensity(&self, light_trs: &Trs, frag_pos: &Point3) -> Color {
         match self {
             Light::Directional(light) => light.get_intensity(),
             Light::Point(light) => light.get_intensity(light_trs, frag_pos),
+            Light::Quad(light) => light.get_intensity(light_trs, frag_pos),
+            Light::Sky(light) => light.get_intensity(),
+            Light::Portal(light) => Color::white() * light.get_solid_angle(light_trs, frag_pos),
         }
     }
 
@@ -43,6 +71,19 @@ impl Light {
         match self {
             Light::Directional(light) => light.get_fallof(),
             Light::Point(light) => light.get_fallof(light_trs, frag_pos),
+            Light::Quad(light) => light.get_fallof(light_trs, frag_pos),
+            Light::Sky(light) => light.get_fallof(),
+            Light::Portal(light) => light.get_fallof(light_trs, frag_pos),
+        }
+    }
+
+    /// Returns the power used to sample the light among others, or `None` for lights
+    /// at infinity, see `LightTree`
+    pub fn get_power(&self) -> Option<f32> {
+        match self {
+            Light::Point(light) => Some(light.get_power()),
+            Light::Quad(light) => Some(light.get_power()),
+            _ => None,
         }
     }
 
@@ -51,6 +92,40 @@ impl Light {
         match self {
             Light::Directional(light) => light.get_direction(light_trs),
             Light::Point(light) => light.get_direction(light_trs, frag_pos),
+            Light::Quad(light) => light.get_direction(light_trs, frag_pos),
+            Light::Sky(light) => light.get_direction(light_trs),
+            Light::Portal(light) => light.get_direction(light_trs, frag_pos),
+        }
+    }
+
+    /// Returns the transform of a random point on the surface of the light, which other
+    /// functions can use to light a fragment from that point. Lights without a surface
+    /// return their own transform
+    pub fn sample_trs(&self, light_trs: &Trs, rng: &mut Rng) -> Trs {
+        match self {
+            Light::Quad(light) => light.sample_trs(light_trs, rng),
+            Light::Sky(light) => light.sample_trs(light_trs, rng),
+            Light::Portal(light) => light.sample_trs(light_trs, rng),
+            _ => light_trs.clone(),
+        }
+    }
+
+    /// Returns how much the point sampled at `sampled_trs` emits, divided by the probability
+    /// of sampling it, for lights at `light_trs` whose surface is not uniform. Intensities
+    /// of points returned by `sample_trs()` should be multiplied by it, see `LightTexture`
+    pub fn get_emission(&self, light_trs: &Trs, sampled_trs: &Trs) -> Color {
+        match self {
+            Light::Quad(light) => light.get_emission(light_trs, sampled_trs),
+            _ => Color::white(),
+        }
+    }
+
+    /// Same as `sample_trs()`, where quad lights are split in a `grid` x `grid` layout
+    /// and the point is jittered within the `cell` of it, see `QuadLight::sample_cell_trs()`
+    pub fn sample_cell_trs(&self, light_trs: &Trs, cell: u32, grid: u32, rng: &mut Rng) -> Trs {
+        match self {
+            Light::Quad(light) => light.sample_cell_trs(light_trs, cell, grid, rng),
+            _ => self.sample_trs(light_trs, rng),
         }
     }
 }
@@ -136,6 +211,10 @@ impl PointLight {
         dist.normalize();
         -dist
     }
+
+    pub fn get_power(&self) -> f32 {
+        self.intensity * (self.color.r + self.color.g + self.color.b) / 3.0
+    }
 }
 
 impl Default for PointLight {
@@ -144,6 +223,330 @@ impl Default for PointLight {
     }
 }
 
+/// Piecewise constant distribution over the unit square, made of `width` x `height` cells
+/// with non-negative weights. Points are sampled where weights are higher, picking a row
+/// first and a cell within it, which keeps stratified samples apart
+#[derive(Clone)]
+pub struct Distribution2D {
+    width: usize,
+    height: usize,
+    weights: Vec<f32>,
+    /// Cumulative weight of the rows, from 0 to 1
+    row_cdf: Vec<f32>,
+    /// Cumulative weight of the cells within every row, from 0 to 1
+    column_cdfs: Vec<f32>,
+    /// Average weight of the cells
+    mean: f32,
+}
+
+impl Distribution2D {
+    /// Weights are expected row by row
+    pub fn new(width: usize, height: usize, weights: Vec<f32>) -> Self {
+        assert!(weights.len() == width * height);
+        let mut row_cdf = vec![0.0; height + 1];
+        let mut column_cdfs = vec![0.0; height * (width + 1)];
+        for y in 0..height {
+            let cdf = &mut column_cdfs[y * (width + 1)..(y + 1) * (width + 1)];
+            for x in 0..width {
+                cdf[x + 1] = cdf[x] + weights[y * width + x];
+            }
+            row_cdf[y + 1] = row_cdf[y] + cdf[width];
+            Self::normalize(cdf);
+        }
+        let mean = row_cdf[height] / (width * height) as f32;
+        Self::normalize(&mut row_cdf);
+
+        Self {
+            width,
+            height,
+            weights,
+            row_cdf,
+            column_cdfs,
+            mean,
+        }
+    }
+
+    /// Divides a cumulative distribution by its total, or makes it uniform without one
+    fn normalize(cdf: &mut [f32]) {
+        let count = cdf.len() - 1;
+        let total = cdf[count];
+        for (i, value) in cdf.iter_mut().enumerate() {
+            *value = if total > 0.0 {
+                *value / total
+            } else {
+                i as f32 / count as f32
+            };
+        }
+    }
+
+    /// Returns the index of the interval of `cdf` containing `u`, and where `u` lies within it
+    fn invert(cdf: &[f32], u: f32) -> (usize, f32) {
+        let index = cdf
+            .partition_point(|value| *value <= u)
+            .clamp(1, cdf.len() - 1)
+            - 1;
+        let (start, end) = (cdf[index], cdf[index + 1]);
+        let t = if end > start {
+            (u - start) / (end - start)
+        } else {
+            0.5
+        };
+        (index, t.clamp(0.0, 1.0))
+    }
+
+    /// Returns the average weight of the cells
+    pub fn get_mean(&self) -> f32 {
+        self.mean
+    }
+
+    /// Maps uniform coordinates `u` and `v` to a point of the unit square
+    /// where cells with higher weights are more likely
+    pub fn warp(&self, u: f32, v: f32) -> Vec2 {
+        let (y, dy) = Self::invert(&self.row_cdf, v);
+        let row = &self.column_cdfs[y * (self.width + 1)..(y + 1) * (self.width + 1)];
+        let (x, dx) = Self::invert(row, u);
+        Vec2::new(
+            (x as f32 + dx) / self.width as f32,
+            (y as f32 + dy) / self.height as f32,
+        )
+    }
+
+    /// Returns the index of the cell containing `uv`
+    pub fn get_cell(&self, uv: &Vec2) -> usize {
+        let x = ((uv.x * self.width as f32) as usize).min(self.width - 1);
+        let y = ((uv.y * self.height as f32) as usize).min(self.height - 1);
+        y * self.width + x
+    }
+
+    /// Returns the probability density of `warp()` returning `uv`
+    pub fn get_pdf(&self, uv: &Vec2) -> f32 {
+        if self.mean > 0.0 {
+            self.weights[self.get_cell(uv)] / self.mean
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Image modulating the light emitted across the surface of a quad light, like a window
+/// casting the shape of its frame or a projector. Points are sampled where the image is
+/// brighter, see `Distribution2D`
+pub struct LightTexture {
+    texels: Vec<Color>,
+    distribution: Distribution2D,
+}
+
+impl LightTexture {
+    pub fn new(image: &Image) -> Self {
+        let width = image.width() as usize;
+        let height = image.height() as usize;
+        let sampler = Sampler::default();
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let uv = Vec2::new(
+                    (x as f32 + 0.5) / width as f32,
+                    (y as f32 + 0.5) / height as f32,
+                );
+                texels.push(sampler.sample(image, &uv));
+            }
+        }
+
+        let brightness = texels.iter().map(Self::get_brightness).collect();
+        let distribution = Distribution2D::new(width, height, brightness);
+        Self {
+            texels,
+            distribution,
+        }
+    }
+
+    fn get_brightness(color: &Color) -> f32 {
+        (color.r + color.g + color.b) / 3.0
+    }
+
+    /// Returns the average brightness of the image
+    pub fn get_mean(&self) -> f32 {
+        self.distribution.get_mean()
+    }
+
+    /// Maps uniform coordinates `u` and `v` to texture coordinates where brighter texels
+    /// are more likely
+    pub fn warp(&self, u: f32, v: f32) -> Vec2 {
+        self.distribution.warp(u, v)
+    }
+
+    pub fn get_color(&self, uv: &Vec2) -> Color {
+        self.texels[self.distribution.get_cell(uv)]
+    }
+
+    /// Returns the probability density of `warp()` returning `uv`
+    pub fn get_pdf(&self, uv: &Vec2) -> f32 {
+        self.distribution.get_pdf(uv)
+    }
+}
+
+/// Rectangular light on the XZ plane of its node, centered at its origin,
+/// which emits light downwards like a ceiling panel, along its negative Y axis.
+/// Intensity is the one of a point light of the same power looking straight at it
+pub struct QuadLight {
+    color: Color,
+    intensity: f32,
+    pub width: f32,
+    pub height: f32,
+
+    /// Image modulating the emitted light, where the first texture coordinate goes along
+    /// the X axis and the second one along the Z axis of the light
+    pub texture: Option<LightTexture>,
+}
+
+impl QuadLight {
+    pub fn new(width: f32, height: f32) -> Self {
+        Self {
+            color: Color::new(1.0, 1.0, 1.0, 1.0),
+            intensity: 1.0,
+            width,
+            height,
+            texture: None,
+        }
+    }
+
+    /// Modulates the emitted light with `image`, see `LightTexture`
+    pub fn set_image(&mut self, image: &Image) {
+        self.texture = Some(LightTexture::new(image));
+    }
+
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+
+    /// Returns the intensity set with `set_intensity()`, before any fallof
+    pub fn get_base_intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    pub fn get_distance(&self, light_trs: &Trs, frag_pos: &Point3) -> f32 {
+        let dist = frag_pos - light_trs.get_translation();
+        Vec3::from(dist).len()
+    }
+
+    /// The surface emits less light at grazing angles, as it appears smaller
+    pub fn get_intensity(&self, light_trs: &Trs, frag_pos: &Point3) -> Color {
+        let normal = light_trs.rotation * Vec3::new(0.0, -1.0, 0.0);
+        let cos_theta = normal.dot(&-self.get_direction(light_trs, frag_pos));
+        (self.intensity * cos_theta.max(0.0) * self.color) / self.get_fallof(light_trs, frag_pos)
+    }
+
+    pub fn get_power(&self) -> f32 {
+        let mean = self.texture.as_ref().map_or(1.0, LightTexture::get_mean);
+        self.intensity * (self.color.r + self.color.g + self.color.b) / 3.0 * mean
+    }
+
+    pub fn get_fallof(&self, light_trs: &Trs, frag_pos: &Point3) -> f32 {
+        let dist = Vec3::from(frag_pos) - light_trs.get_translation();
+        let r2 = dist.norm();
+        // Square fallof
+        1.0 * std::f32::consts::PI * r2
+    }
+
+    pub fn get_direction(&self, light_trs: &Trs, frag_pos: &Point3) -> Vec3 {
+        let mut dist = Vec3::from(frag_pos) - light_trs.get_translation();
+        dist.normalize();
+        -dist
+    }
+
+    pub fn sample_trs(&self, light_trs: &Trs, rng: &mut Rng) -> Trs {
+        self.sample_cell_trs(light_trs, 0, 1, rng)
+    }
+
+    /// Returns the transform of a random point within `cell` of the surface split in
+    /// `grid` x `grid` cells, where cells go row by row along the X axis. Drawing the
+    /// same number of samples from every cell spreads them more evenly than `sample_trs()`
+    pub fn sample_cell_trs(&self, light_trs: &Trs, cell: u32, grid: u32, rng: &mut Rng) -> Trs {
+        let (x, z) = (cell % grid, cell / grid);
+        let u = (x as f32 + rng.next_f32()) / grid as f32;
+        let v = (z as f32 + rng.next_f32()) / grid as f32;
+        let uv = match &self.texture {
+            Some(texture) => texture.warp(u, v),
+            None => Vec2::new(u, v),
+        };
+        let offset = Vec3::new((uv.x - 0.5) * self.width, 0.0, (uv.y - 0.5) * self.height);
+        let mut ret = light_trs.clone();
+        // Translation is rotated by `get_translation()`, hence the offset is in local space
+        ret.translation += light_trs.scale * offset;
+        ret
+    }
+
+    /// Returns the color of the texture at the point sampled at `sampled_trs`,
+    /// divided by the probability of sampling it, see `Light::get_emission()`
+    pub fn get_emission(&self, light_trs: &Trs, sampled_trs: &Trs) -> Color {
+        let Some(texture) = &self.texture else {
+            return Color::white();
+        };
+        let offset = sampled_trs.translation - light_trs.translation;
+        let uv = Vec2::new(
+            offset.get_x() / (light_trs.scale.get_x() * self.width) + 0.5,
+            offset.get_z() / (light_trs.scale.get_z() * self.height) + 0.5,
+        );
+        let pdf = texture.get_pdf(&uv);
+        if pdf > 0.0 {
+            texture.get_color(&uv) / pdf
+        } else {
+            Color::black()
+        }
+    }
+}
+
+/// Opening of an interior, such as a window, which does not emit light by itself
+/// but lets the sky in. It lies on the XZ plane of its node like a `QuadLight`, with
+/// the interior along its negative Y axis. Sampling the sky through portals finds the
+/// few directions reaching the interior, which random bounces would rarely hit
+pub struct PortalLight {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl PortalLight {
+    pub fn new(width: f32, height: f32) -> Self {
+        Self { width, height }
+    }
+
+    pub fn get_distance(&self, light_trs: &Trs, frag_pos: &Point3) -> f32 {
+        let dist = frag_pos - light_trs.get_translation();
+        Vec3::from(dist).len()
+    }
+
+    /// Solid angle of the portal as seen from `frag_pos`, estimated from the point
+    /// of the portal at `light_trs`. Points outside the interior see no opening
+    pub fn get_solid_angle(&self, light_trs: &Trs, frag_pos: &Point3) -> f32 {
+        let normal = light_trs.rotation * Vec3::new(0.0, -1.0, 0.0);
+        let cos_theta = normal.dot(&-self.get_direction(light_trs, frag_pos));
+        let area = self.width * light_trs.scale.get_x() * self.height * light_trs.scale.get_z();
+        area * cos_theta.max(0.0) / self.get_fallof(light_trs, frag_pos)
+    }
+
+    pub fn get_fallof(&self, light_trs: &Trs, frag_pos: &Point3) -> f32 {
+        let dist = Vec3::from(frag_pos) - light_trs.get_translation();
+        dist.norm().max(f32::EPSILON)
+    }
+
+    pub fn get_direction(&self, light_trs: &Trs, frag_pos: &Point3) -> Vec3 {
+        let mut dist = Vec3::from(frag_pos) - light_trs.get_translation();
+        dist.normalize();
+        -dist
+    }
+
+    pub fn sample_trs(&self, light_trs: &Trs, rng: &mut Rng) -> Trs {
+        let offset = Vec3::new(
+            (rng.next_f32() - 0.5) * self.width,
+            0.0,
+            (rng.next_f32() - 0.5) * self.height,
+        );
+        let mut ret = light_trs.clone();
+        ret.translation += light_trs.scale * offset;
+        ret
+    }
+}
+
 impl Default for Light {
     fn default() -> Self {
         Self::Directional(DirectionalLight::new())
@@ -158,6 +561,9 @@ pub struct Irradiance<'m> {
     /// Hit
     pub hit: &'m Hit,
 
+    /// Light direction
+    pub l: Vec3,
+
     /// Surface normal
     pub n: Vec3,
     pub n_dot_v: f32,
@@ -170,7 +576,8 @@ pub struct Irradiance<'m> {
 
     /// Albedo color
     pub albedo: Color,
-    pub uv: Vec2,
+    /// Texture coordinates of every set
+    pub uvs: [Vec2; 2],
 }
 
 impl<'m> Irradiance<'m> {
@@ -184,7 +591,7 @@ impl<'m> Irradiance<'m> {
         n: Vec3,
         v: Vec3,
         albedo: Color,
-        uv: Vec2,
+        uvs: [Vec2; 2],
     ) -> Self {
         let n_dot_v = n.dot(&v).clamp(0.0, 1.0) + 1e-5;
         let n_dot_l = n.dot(&l).clamp(0.0, 1.0);
@@ -195,6 +602,7 @@ impl<'m> Irradiance<'m> {
         Self {
             intensity,
             hit,
+            l,
             n,
             n_dot_v,
             n_dot_l,
@@ -202,7 +610,49 @@ impl<'m> Irradiance<'m> {
             n_dot_h,
             l_dot_h,
             albedo,
-            uv,
+            uvs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stratified() {
+        let light = QuadLight::new(2.0, 2.0);
+        let trs = Trs::default();
+        let mut rng = Rng::new(0);
+        // Cells of a 2 x 2 grid are the quadrants of the light
+        let quadrants = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)];
+        for (cell, (x, z)) in quadrants.iter().enumerate() {
+            for _ in 0..16 {
+                let sample = light.sample_cell_trs(&trs, cell as u32, 2, &mut rng);
+                let translation = sample.get_translation();
+                assert!(translation.get_x() * x >= 0.0 && translation.get_x().abs() <= 1.0);
+                assert!(translation.get_z() * z >= 0.0 && translation.get_z().abs() <= 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn textured() {
+        // Only the right half of the light emits
+        let mut image = Image::new(2, 1, ColorType::RGBA8);
+        image.bytes_mut()[4..8].fill(0xFF);
+        let mut light = QuadLight::new(2.0, 2.0);
+        light.set_image(&image);
+        assert_eq!(light.get_power(), 0.5);
+
+        let trs = Trs::default();
+        let mut rng = Rng::new(0);
+        for cell in 0..4 {
+            let sample = light.sample_cell_trs(&trs, cell, 2, &mut rng);
+            assert!(sample.get_translation().get_x() >= 0.0);
+            // White texels sampled twice as often as uniformly emit half as much
+            let emission = light.get_emission(&trs, &sample);
+            assert!((emission.r - 0.5).abs() < 1e-5);
         }
     }
 }
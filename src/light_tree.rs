@@ -0,0 +1,207 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Light tree for next event estimation with many lights, following "Importance Sampling
+//! of Many Lights with Adaptive Tree Splitting" by Conty and Kulla, where point and quad
+//! lights are grouped by position, and fragments pick them going down the tree towards
+//! the brightest and closest groups.
+
+use crate::*;
+
+/// Group of lights, whose power is the sum of the power of its lights
+struct LightTreeNode {
+    bounds: AABB,
+    power: f32,
+    /// Children nodes, or `None` for leaves with a single light
+    children: Option<[usize; 2]>,
+    /// Index of the light of a leaf
+    light: usize,
+}
+
+impl LightTreeNode {
+    /// Returns how much this group of lights is expected to contribute to `point`
+    fn get_importance(&self, point: &Point3) -> f32 {
+        let center = (Vec3::from(self.bounds.a) + Vec3::from(self.bounds.b)) * 0.5;
+        let radius2 = (self.bounds.b - self.bounds.a).norm() * 0.25;
+        // Points within the group do not favour any of its lights
+        let distance2 = (Vec3::from(*point) - center).norm().max(radius2);
+        self.power / distance2.max(f32::EPSILON)
+    }
+}
+
+/// Lights of a model sorted for sampling. Point and quad lights are stored in a binary
+/// tree, while lights at infinity such as directional, sky, and portal lights are always
+/// sampled, as they light every fragment from the same direction
+#[derive(Default)]
+pub struct LightTree {
+    nodes: Vec<LightTreeNode>,
+    local_lights: Vec<Handle<Node>>,
+    infinite_lights: Vec<Handle<Node>>,
+}
+
+impl LightTree {
+    /// Builds a tree with the `light_nodes` of `model`
+    pub fn new(model: &Model, light_nodes: &[Handle<Node>]) -> Self {
+        let mut ret = Self::default();
+        let mut leaves = vec![];
+        for handle in light_nodes {
+            let light_node = model.nodes.get(*handle).unwrap();
+            let light = model.lights.get(light_node.light).unwrap();
+            match light.get_power() {
+                Some(power) => {
+                    let position = Point3::from(light_node.trs.get_translation());
+                    leaves.push(LightTreeNode {
+                        bounds: AABB::new(position, position),
+                        power,
+                        children: None,
+                        light: ret.local_lights.len(),
+                    });
+                    ret.local_lights.push(*handle);
+                }
+                None => ret.infinite_lights.push(*handle),
+            }
+        }
+        if !leaves.is_empty() {
+            ret.build(leaves);
+        }
+        ret
+    }
+
+    /// Splits `leaves` in half along the largest axis of their bounds,
+    /// returning the index of the node containing all of them
+    fn build(&mut self, mut leaves: Vec<LightTreeNode>) -> usize {
+        if leaves.len() == 1 {
+            self.nodes.push(leaves.pop().unwrap());
+            return self.nodes.len() - 1;
+        }
+
+        let mut bounds = AABB::new(leaves[0].bounds.a, leaves[0].bounds.b);
+        for leaf in &leaves[1..] {
+            bounds.a = bounds.a.min(&leaf.bounds.a);
+            bounds.b = bounds.b.max(&leaf.bounds.b);
+        }
+        let extent = bounds.b - bounds.a;
+        let axis = (0..3)
+            .max_by(|i, j| extent.simd[*i].total_cmp(&extent.simd[*j]))
+            .unwrap();
+        leaves.sort_by(|l, r| l.bounds.a[axis].total_cmp(&r.bounds.a[axis]));
+        let right_leaves = leaves.split_off(leaves.len() / 2);
+
+        let power = leaves.iter().chain(&right_leaves).map(|l| l.power).sum();
+        let left = self.build(leaves);
+        let right = self.build(right_leaves);
+        self.nodes.push(LightTreeNode {
+            bounds,
+            power,
+            children: Some([left, right]),
+            light: 0,
+        });
+        self.nodes.len() - 1
+    }
+
+    /// Returns the number of point and quad lights in the tree
+    pub fn get_local_count(&self) -> usize {
+        self.local_lights.len()
+    }
+
+    /// Returns the lights which are always sampled
+    pub fn get_infinite_lights(&self) -> &[Handle<Node>] {
+        &self.infinite_lights
+    }
+
+    /// Returns a point or quad light picked going down the tree towards groups
+    /// of lights contributing more to `point`, together with its probability
+    pub fn sample(&self, point: &Point3, rng: &mut Rng) -> Option<(Handle<Node>, f32)> {
+        let mut node = self.nodes.last()?;
+        let mut pdf = 1.0;
+        while let Some([left, right]) = node.children {
+            let left_importance = self.nodes[left].get_importance(point);
+            let right_importance = self.nodes[right].get_importance(point);
+            let total = left_importance + right_importance;
+            let left_probability = if total > 0.0 {
+                left_importance / total
+            } else {
+                0.5
+            };
+            if rng.next_f32() < left_probability {
+                node = &self.nodes[left];
+                pdf *= left_probability;
+            } else {
+                node = &self.nodes[right];
+                pdf *= 1.0 - left_probability;
+            }
+        }
+        Some((self.local_lights[node.light], pdf))
+    }
+
+    /// Returns the lights to sample for `point` with the weight of their contribution.
+    /// Up to `max_lights` point and quad lights are all returned with weight one, while
+    /// more than that are sampled `max_lights` times from the tree
+    pub fn select(
+        &self,
+        point: &Point3,
+        max_lights: usize,
+        rng: &mut Rng,
+    ) -> Vec<(Handle<Node>, f32)> {
+        let mut ret: Vec<_> = self
+            .infinite_lights
+            .iter()
+            .map(|handle| (*handle, 1.0))
+            .collect();
+        if self.local_lights.len() <= max_lights {
+            ret.extend(self.local_lights.iter().map(|handle| (*handle, 1.0)));
+        } else {
+            for _ in 0..max_lights {
+                if let Some((handle, pdf)) = self.sample(point, rng) {
+                    ret.push((handle, 1.0 / (pdf * max_lights as f32)));
+                }
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sample() {
+        let mut model = Model::new();
+        let mut push_light = |light: Light, x: f32| {
+            let light = model.lights.push(light);
+            let node = Node::builder()
+                .light(light)
+                .translation(Vec3::new(x, 0.0, 0.0))
+                .build();
+            model.nodes.push(node)
+        };
+        let near = push_light(Light::point(), 0.0);
+        let far = push_light(Light::point(), 100.0);
+        let quad = push_light(Light::quad(1.0, 1.0), 101.0);
+        let sun = push_light(Light::directional(), 0.0);
+        let tree = LightTree::new(&model, &[near, far, quad, sun]);
+        assert_eq!(tree.get_local_count(), 3);
+        assert_eq!(tree.get_infinite_lights(), &[sun]);
+
+        // Lights close to the fragment are sampled more often
+        let mut rng = Rng::new(0);
+        let point = Point3::new(1.0, 0.0, 0.0);
+        let mut near_count = 0;
+        for _ in 0..1000 {
+            let (handle, pdf) = tree.sample(&point, &mut rng).unwrap();
+            assert!(pdf > 0.0 && pdf <= 1.0);
+            if handle == near {
+                near_count += 1;
+            }
+        }
+        assert!(near_count > 900);
+
+        // Few lights are all returned
+        assert_eq!(tree.select(&point, 3, &mut rng).len(), 4);
+        let selection = tree.select(&point, 1, &mut rng);
+        assert_eq!(selection.len(), 2);
+        assert_eq!(selection[0], (sun, 1.0));
+    }
+}
@@ -9,3 +9,156 @@ macro_rules! rlog {
         println!($( $t )*)
     }
 }
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    RwLock,
+};
+
+use instant::{Duration, Instant};
+
+/// How much is logged by `print_info!()` and the other printing macros
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off,
+    Warning,
+    Info,
+}
+
+/// Levels set for modules, see `set_log_level()`
+static LOG_LEVELS: RwLock<Vec<(String, LogLevel)>> = RwLock::new(Vec::new());
+
+/// Sets the level of the messages logged by `module` and its submodules, for example
+/// `"rayca::bvh"`, overriding the level of its parents. The empty module sets the level
+/// of every module, which is `LogLevel::Info` by default
+pub fn set_log_level(module: &str, level: LogLevel) {
+    let mut levels = LOG_LEVELS.write().unwrap();
+    levels.retain(|(other, _)| other != module);
+    levels.push((module.to_string(), level));
+}
+
+/// Returns whether `module` logs messages of `level`, according to the closest
+/// of its ancestors with a level, see `set_log_level()`
+pub fn log_enabled(module: &str, level: LogLevel) -> bool {
+    let levels = LOG_LEVELS.read().unwrap();
+    let contains = |parent: &str| {
+        parent.is_empty()
+            || module == parent
+            || (module.starts_with(parent) && module[parent.len()..].starts_with("::"))
+    };
+    let closest = levels
+        .iter()
+        .filter(|(parent, _)| contains(parent))
+        .max_by_key(|(parent, _)| parent.len());
+    level <= closest.map_or(LogLevel::Info, |(_, level)| *level)
+}
+
+/// Progress of a render, reported to frontends every time a row is completed
+#[derive(Clone, Debug)]
+pub struct Progress {
+    /// Index of the row which has just been completed
+    pub row: usize,
+    pub row_count: usize,
+
+    /// Number of rows completed so far
+    pub completed: usize,
+
+    /// Estimated time remaining, based on the average time per row
+    pub eta: Duration,
+}
+
+impl Progress {
+    /// Percentage of rows completed so far, in `[0, 100]`
+    pub fn percent(&self) -> f32 {
+        if self.row_count == 0 {
+            100.0
+        } else {
+            self.completed as f32 * 100.0 / self.row_count as f32
+        }
+    }
+}
+
+/// Callback frontends can subscribe to in order to receive progress updates.
+/// It can be called concurrently from multiple threads.
+pub type ProgressCallback = Box<dyn Fn(&Progress) + Send + Sync>;
+
+/// Keeps track of the completed rows of a render and notifies a callback
+pub struct ProgressTracker<'c> {
+    callback: Option<&'c ProgressCallback>,
+    start: Instant,
+    row_count: usize,
+    completed: AtomicUsize,
+}
+
+impl<'c> ProgressTracker<'c> {
+    pub fn new(callback: Option<&'c ProgressCallback>, row_count: usize) -> Self {
+        Self {
+            callback,
+            start: Instant::now(),
+            row_count,
+            completed: AtomicUsize::new(0),
+        }
+    }
+
+    /// Marks `row` as completed and reports the new progress
+    pub fn complete(&self, row: usize) {
+        let completed = self.completed.fetch_add(1, Ordering::Relaxed) + 1;
+        let Some(callback) = self.callback else {
+            return;
+        };
+
+        let elapsed = self.start.elapsed();
+        let remaining = self.row_count.saturating_sub(completed) as u32;
+        let eta = elapsed / completed as u32 * remaining;
+
+        callback(&Progress {
+            row,
+            row_count: self.row_count,
+            completed,
+            eta,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn progress() {
+        let reports = Arc::new(Mutex::new(vec![]));
+        let callback_reports = reports.clone();
+        let callback: ProgressCallback = Box::new(move |progress: &Progress| {
+            callback_reports.lock().unwrap().push(progress.clone());
+        });
+
+        let tracker = ProgressTracker::new(Some(&callback), 4);
+        for row in 0..4 {
+            tracker.complete(row);
+        }
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 4);
+        assert_eq!(reports[1].percent(), 50.0);
+        assert_eq!(reports[3].percent(), 100.0);
+        assert_eq!(reports[3].eta, Duration::ZERO);
+    }
+
+    #[test]
+    fn levels() {
+        assert!(log_enabled("rayca::levels", LogLevel::Info));
+
+        set_log_level("rayca::levels", LogLevel::Warning);
+        set_log_level("rayca::levels::quiet", LogLevel::Off);
+        assert!(!log_enabled("rayca::levels", LogLevel::Info));
+        assert!(log_enabled("rayca::levels::loud", LogLevel::Warning));
+        assert!(!log_enabled(
+            "rayca::levels::quiet::child",
+            LogLevel::Warning
+        ));
+        // Modules only sharing a prefix of the name are not children
+        assert!(log_enabled("rayca::levelsx", LogLevel::Info));
+    }
+}
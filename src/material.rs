@@ -21,6 +21,71 @@ fn fresnel_schlick(cos_theta: f32, f0: Vec3) -> Vec3 {
     f0 + (Vec3::splat(1.0) - f0) * f
 }
 
+/// Exact Fresnel reflectance of unpolarized light on a conductor of complex index of
+/// refraction `eta + i k`, for a single wavelength
+fn fresnel_conductor(cos_theta: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_theta * cos_theta;
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rp + rs)
+}
+
+/// Optical constants of a metal for red, green, and blue light, which give a more
+/// accurate reflectance than tinting Schlick's approximation with the base color
+#[derive(Clone, Copy, Debug)]
+pub struct Conductor {
+    /// Real part of the index of refraction
+    pub eta: Vec3,
+    /// Extinction coefficient, the imaginary part of the index of refraction
+    pub k: Vec3,
+}
+
+impl Conductor {
+    pub const GOLD: Conductor = Conductor::new(
+        Vec3::new(0.143, 0.374, 1.442),
+        Vec3::new(3.983, 2.385, 1.603),
+    );
+    pub const COPPER: Conductor = Conductor::new(
+        Vec3::new(0.200, 0.924, 1.102),
+        Vec3::new(3.912, 2.452, 2.142),
+    );
+    pub const ALUMINUM: Conductor = Conductor::new(
+        Vec3::new(1.657, 0.880, 0.521),
+        Vec3::new(9.224, 6.270, 4.837),
+    );
+    pub const SILVER: Conductor = Conductor::new(
+        Vec3::new(0.155, 0.117, 0.138),
+        Vec3::new(4.828, 3.122, 2.147),
+    );
+
+    pub const fn new(eta: Vec3, k: Vec3) -> Self {
+        Self { eta, k }
+    }
+
+    /// Returns the reflectance for light hitting the surface at `cos_theta` from its normal
+    pub fn get_fresnel(&self, cos_theta: f32) -> Vec3 {
+        let cos_theta = cos_theta.clamp(0.0, 1.0);
+        Vec3::new(
+            fresnel_conductor(cos_theta, self.eta.get_x(), self.k.get_x()),
+            fresnel_conductor(cos_theta, self.eta.get_y(), self.k.get_y()),
+            fresnel_conductor(cos_theta, self.eta.get_z(), self.k.get_z()),
+        )
+    }
+}
+
 /// Models the visibility of the microfacets, or occlusion or shadow-masking
 fn geometry_smith_ggx(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
     let a = roughness;
@@ -29,15 +94,74 @@ fn geometry_smith_ggx(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
     0.5 / (ggxv + ggxl)
 }
 
+/// Kajiya-Kay specular lobe of a hair fiber, which reflects light in a cone around
+/// its tangent. The exponent is normalized so that rougher fibers get wider highlights.
+fn hair_specular(t_dot_h: f32, roughness: f32) -> f32 {
+    let exponent = (2.0 / (roughness * roughness).max(1e-4) - 2.0).max(1.0);
+    let sin_th = (1.0 - t_dot_h * t_dot_h).max(0.0).sqrt();
+    sin_th.powf(exponent) * (exponent + 2.0) * 0.5 * std::f32::consts::FRAC_1_PI
+}
+
+/// Mixes nested deeper than this use the properties of their own material instead,
+/// which stops mixes referring back to themselves
+const MAX_MIX_DEPTH: u32 = 8;
+
+/// Blends two materials of a model, which is useful for layered looks such as
+/// rust over metal. The properties of the two materials are interpolated per hit
+#[derive(Clone, Copy)]
+pub struct MaterialMix {
+    pub a: Handle<Material>,
+    pub b: Handle<Material>,
+
+    /// Weight of `b`, used when there is no mask
+    pub factor: f32,
+
+    /// The red channel of this texture, when present, is the weight of `b`
+    pub mask: Handle<Texture>,
+}
+
+impl MaterialMix {
+    pub fn new(a: Handle<Material>, b: Handle<Material>, factor: f32) -> Self {
+        Self {
+            a,
+            b,
+            factor,
+            mask: Handle::NONE,
+        }
+    }
+
+    /// The mask is sampled with the first set of texture coordinates
+    pub fn get_factor(&self, model: &Model, uvs: &[Vec2]) -> f32 {
+        if let Some(mask) = model.textures.get(self.mask) {
+            let sampler = Sampler::default();
+            let image = model.images.get(mask.image).unwrap();
+            sampler.sample(image, &uvs[0]).r
+        } else {
+            self.factor
+        }
+    }
+
+    /// Returns the two materials with the weight of the second one at `uvs`
+    fn resolve<'m>(&self, model: &'m Model, uvs: &[Vec2]) -> (&'m Material, &'m Material, f32) {
+        // Constants owning memory, like extras, are not borrowed for longer than this function
+        static WHITE: Material = Material::WHITE;
+        let a = model.materials.get(self.a).unwrap_or(&WHITE);
+        let b = model.materials.get(self.b).unwrap_or(&WHITE);
+        (a, b, self.get_factor(model, uvs).clamp(0.0, 1.0))
+    }
+}
+
 #[derive(Default)]
 pub struct MaterialBuilder {
     color: Color,
+    conductor: Option<Conductor>,
 }
 
 impl MaterialBuilder {
     pub fn new() -> Self {
         Self {
             color: Color::default(),
+            conductor: None,
         }
     }
 
@@ -46,9 +170,15 @@ impl MaterialBuilder {
         self
     }
 
+    pub fn conductor(mut self, conductor: Conductor) -> Self {
+        self.conductor = Some(conductor);
+        self
+    }
+
     pub fn build(self) -> Material {
         let mut material = Material::new();
         material.color = self.color;
+        material.conductor = self.conductor;
         material
     }
 }
@@ -56,11 +186,49 @@ impl MaterialBuilder {
 pub struct Material {
     pub color: Color,
     pub albedo_texture: Handle<Texture>,
+    pub albedo_transform: TextureTransform,
     pub normal_texture: Handle<Texture>,
+    pub normal_transform: TextureTransform,
 
     pub metallic_factor: f32,
     pub roughness_factor: f32,
     pub metallic_roughness_texture: Handle<Texture>,
+    pub metallic_roughness_transform: TextureTransform,
+
+    /// Ambient occlusion in the red channel, scaled by the strength
+    pub occlusion_texture: Handle<Texture>,
+    pub occlusion_transform: TextureTransform,
+    pub occlusion_strength: f32,
+
+    /// Height map in the red channel, moving triangles along their normals by up to
+    /// the scale, in world units. See `Tessellation`
+    pub displacement_texture: Handle<Texture>,
+    pub displacement_scale: f32,
+
+    /// Whether the base color is multiplied by the color of the vertices,
+    /// as glTF does for meshes with a `COLOR_0` attribute
+    pub vertex_color: bool,
+
+    /// When present, the other properties are ignored in favour of the blend
+    /// of two other materials, up to `MAX_MIX_DEPTH` nested mixes
+    pub mix: Option<MaterialMix>,
+
+    /// Optical constants replacing the base color in the Fresnel term of the metallic part
+    pub conductor: Option<Conductor>,
+
+    /// Whether the surface only shows the shadows it receives, as the alpha of a black
+    /// color, so that renders can be composited over photographs of the real ground
+    pub shadow_catcher: bool,
+
+    /// Nodes evaluated at shade time, whose outputs replace the properties above
+    pub graph: Option<MaterialGraph>,
+
+    /// Radius of the rounded edges faked at shade time by blending the normals of the
+    /// geometry around hit points, where zero disables them. See `Bvh::get_bevel_normal()`
+    pub bevel_radius: f32,
+
+    /// Properties which are not used for rendering, kept for pipeline tools
+    pub extras: Extras,
 }
 
 impl Material {
@@ -72,10 +240,25 @@ impl Material {
             a: 1.0,
         },
         albedo_texture: Handle::NONE,
+        albedo_transform: TextureTransform::IDENTITY,
         normal_texture: Handle::NONE,
+        normal_transform: TextureTransform::IDENTITY,
         metallic_factor: 1.0,
         roughness_factor: 1.0,
         metallic_roughness_texture: Handle::NONE,
+        metallic_roughness_transform: TextureTransform::IDENTITY,
+        occlusion_texture: Handle::NONE,
+        occlusion_transform: TextureTransform::IDENTITY,
+        occlusion_strength: 1.0,
+        displacement_texture: Handle::NONE,
+        displacement_scale: 1.0,
+        vertex_color: true,
+        mix: None,
+        conductor: None,
+        shadow_catcher: false,
+        graph: None,
+        bevel_radius: 0.0,
+        extras: Extras::new(),
     };
 
     pub fn builder() -> MaterialBuilder {
@@ -86,18 +269,107 @@ impl Material {
         Self {
             color: Color::white(),
             albedo_texture: Handle::NONE,
+            albedo_transform: TextureTransform::IDENTITY,
             normal_texture: Handle::NONE,
+            normal_transform: TextureTransform::IDENTITY,
             metallic_factor: 1.0,
             roughness_factor: 1.0,
             metallic_roughness_texture: Handle::NONE,
+            metallic_roughness_transform: TextureTransform::IDENTITY,
+            occlusion_texture: Handle::NONE,
+            occlusion_transform: TextureTransform::IDENTITY,
+            occlusion_strength: 1.0,
+            displacement_texture: Handle::NONE,
+            displacement_scale: 1.0,
+            vertex_color: true,
+            mix: None,
+            conductor: None,
+            shadow_catcher: false,
+            graph: None,
+            bevel_radius: 0.0,
+            extras: Extras::new(),
+        }
+    }
+
+    /// Returns a material blending `a` and `b` by `factor`, see `MaterialMix`
+    pub fn mix(a: Handle<Material>, b: Handle<Material>, factor: f32) -> Self {
+        Self {
+            mix: Some(MaterialMix::new(a, b, factor)),
+            ..Self::new()
         }
     }
 
-    pub fn get_color(&self, model: &Model, uv: &Vec2) -> Color {
+    /// Returns the base color at `uvs`, which are the texture coordinates
+    /// of the point to shade, one for every set
+    pub fn get_color(&self, model: &Model, uvs: &[Vec2]) -> Color {
+        self.get_filtered_color(model, uvs, Vec2::default(), Vec2::default(), 1.0)
+    }
+
+    /// Returns the color averaged over the area of the texture covered by a pixel,
+    /// where `duvdx` and `duvdy` are the texture coordinates derivatives of that pixel.
+    /// The graph of the material, if any, sees the surface at an angle of cosine `n_dot_v`
+    pub fn get_filtered_color(
+        &self,
+        model: &Model,
+        uvs: &[Vec2],
+        duvdx: Vec2,
+        duvdy: Vec2,
+        n_dot_v: f32,
+    ) -> Color {
+        self.get_mixed_color(model, uvs, (duvdx, duvdy), n_dot_v, 0)
+    }
+
+    fn get_graph_input(uvs: &[Vec2], n_dot_v: f32) -> GraphInput {
+        GraphInput {
+            uv: uvs[0],
+            n_dot_v,
+        }
+    }
+
+    /// Returns the two materials of the mix with the weight of the second one,
+    /// unless this is not a mix, or it is nested `depth` times in other mixes
+    fn resolve_mix<'m>(
+        &self,
+        model: &'m Model,
+        uvs: &[Vec2],
+        depth: u32,
+    ) -> Option<(&'m Material, &'m Material, f32)> {
+        let mix = self.mix.as_ref().filter(|_| depth < MAX_MIX_DEPTH)?;
+        Some(mix.resolve(model, uvs))
+    }
+
+    fn get_mixed_color(
+        &self,
+        model: &Model,
+        uvs: &[Vec2],
+        (duvdx, duvdy): (Vec2, Vec2),
+        n_dot_v: f32,
+        depth: u32,
+    ) -> Color {
+        if let Some((a, b, t)) = self.resolve_mix(model, uvs, depth) {
+            let a = a.get_mixed_color(model, uvs, (duvdx, duvdy), n_dot_v, depth + 1);
+            let b = b.get_mixed_color(model, uvs, (duvdx, duvdy), n_dot_v, depth + 1);
+            return a * (1.0 - t) + b * t;
+        }
+
+        if let Some(graph) = &self.graph {
+            if let Some(color) = graph.color {
+                let input = Self::get_graph_input(uvs, n_dot_v);
+                return graph.evaluate(model, color, &input);
+            }
+        }
+
         if let Some(albedo_texture) = model.textures.get(self.albedo_texture) {
             let sampler = Sampler::default();
             let image = model.images.get(albedo_texture.image).unwrap();
-            self.color * sampler.sample(image, uv)
+            let transform = &self.albedo_transform;
+            self.color
+                * sampler.sample_filtered(
+                    image,
+                    &transform.get_uv(uvs),
+                    transform.apply_vector(duvdx),
+                    transform.apply_vector(duvdy),
+                )
         } else {
             self.color
         }
@@ -106,15 +378,64 @@ impl Material {
     pub fn get_normal(
         &self,
         model: &Model,
-        uv: &Vec2,
+        uvs: &[Vec2],
+        normal: Vec3,
+        tangent: Vec3,
+        bitangent: Vec3,
+    ) -> Vec3 {
+        let derivatives = (Vec2::default(), Vec2::default());
+        self.get_filtered_normal(model, uvs, derivatives, normal, tangent, bitangent)
+    }
+
+    /// Like `get_filtered_color()`, bumps are smoothed when a pixel covers many of them
+    pub fn get_filtered_normal(
+        &self,
+        model: &Model,
+        uvs: &[Vec2],
+        (duvdx, duvdy): (Vec2, Vec2),
         normal: Vec3,
         tangent: Vec3,
         bitangent: Vec3,
     ) -> Vec3 {
+        let tbn = (normal, tangent, bitangent);
+        self.get_mixed_normal(model, uvs, (duvdx, duvdy), tbn, 0)
+    }
+
+    fn get_mixed_normal(
+        &self,
+        model: &Model,
+        uvs: &[Vec2],
+        (duvdx, duvdy): (Vec2, Vec2),
+        (normal, tangent, bitangent): (Vec3, Vec3, Vec3),
+        depth: u32,
+    ) -> Vec3 {
+        if let Some((a, b, t)) = self.resolve_mix(model, uvs, depth) {
+            let (derivatives, tbn) = ((duvdx, duvdy), (normal, tangent, bitangent));
+            let a = a.get_mixed_normal(model, uvs, derivatives, tbn, depth + 1);
+            let b = b.get_mixed_normal(model, uvs, derivatives, tbn, depth + 1);
+            return (a * (1.0 - t) + b * t).get_normalized();
+        }
+
+        if let Some(graph) = &self.graph {
+            if let Some(output) = graph.normal {
+                let encoded = graph.evaluate(model, output, &GraphInput::new(uvs[0]));
+                let graph_normal = Vec3::from(encoded) * 2.0 - 1.0;
+                let tbn = Mat3::tbn(&tangent, &bitangent, &normal);
+                return (&tbn * graph_normal).get_normalized();
+            }
+        }
+
         if let Some(normal_texture) = model.textures.get(self.normal_texture) {
             let sampler = Sampler::default();
             let image = model.images.get(normal_texture.image).unwrap();
-            let mut sampled_normal = Vec3::from(sampler.sample(image, uv));
+            let transform = &self.normal_transform;
+            let sampled = sampler.sample_filtered(
+                image,
+                &transform.get_uv(uvs),
+                transform.apply_vector(duvdx),
+                transform.apply_vector(duvdy),
+            );
+            let mut sampled_normal = Vec3::from(sampled);
             sampled_normal = sampled_normal * 2.0 - 1.0;
 
             let tbn = Mat3::tbn(&tangent, &bitangent, &normal);
@@ -124,26 +445,95 @@ impl Material {
         }
     }
 
-    pub fn get_metallic_roughness(&self, model: &Model, uv: &Vec2) -> (f32, f32) {
-        if let Some(mr_texture) = model.textures.get(self.metallic_roughness_texture) {
+    /// Returns metallic and roughness at `uvs`, where the graph of the material,
+    /// if any, sees the surface at an angle of cosine `n_dot_v`
+    pub fn get_metallic_roughness(&self, model: &Model, uvs: &[Vec2], n_dot_v: f32) -> (f32, f32) {
+        self.get_mixed_metallic_roughness(model, uvs, n_dot_v, 0)
+    }
+
+    fn get_mixed_metallic_roughness(
+        &self,
+        model: &Model,
+        uvs: &[Vec2],
+        n_dot_v: f32,
+        depth: u32,
+    ) -> (f32, f32) {
+        if let Some((a, b, t)) = self.resolve_mix(model, uvs, depth) {
+            let (a_metallic, a_roughness) =
+                a.get_mixed_metallic_roughness(model, uvs, n_dot_v, depth + 1);
+            let (b_metallic, b_roughness) =
+                b.get_mixed_metallic_roughness(model, uvs, n_dot_v, depth + 1);
+            return (
+                a_metallic * (1.0 - t) + b_metallic * t,
+                a_roughness * (1.0 - t) + b_roughness * t,
+            );
+        }
+
+        let (metallic, roughness) =
+            if let Some(mr_texture) = model.textures.get(self.metallic_roughness_texture) {
+                let sampler = Sampler::default();
+                let image = model.images.get(mr_texture.image).unwrap();
+                let color = sampler.sample(image, &self.metallic_roughness_transform.get_uv(uvs));
+                // Blue channel contains metalness value
+                // Red channel contains roughness value
+                (color.b, color.r)
+            } else {
+                (self.metallic_factor, self.roughness_factor)
+            };
+
+        if let Some(graph) = &self.graph {
+            let input = Self::get_graph_input(uvs, n_dot_v);
+            return (
+                graph
+                    .evaluate_scalar(model, graph.metallic, &input)
+                    .unwrap_or(metallic),
+                graph
+                    .evaluate_scalar(model, graph.roughness, &input)
+                    .unwrap_or(roughness),
+            );
+        }
+        (metallic, roughness)
+    }
+
+    /// Returns how much ambient light reaches the point at `uvs`, from 0 to 1
+    pub fn get_occlusion(&self, model: &Model, uvs: &[Vec2]) -> f32 {
+        self.get_mixed_occlusion(model, uvs, 0)
+    }
+
+    fn get_mixed_occlusion(&self, model: &Model, uvs: &[Vec2], depth: u32) -> f32 {
+        if let Some((a, b, t)) = self.resolve_mix(model, uvs, depth) {
+            let a = a.get_mixed_occlusion(model, uvs, depth + 1);
+            let b = b.get_mixed_occlusion(model, uvs, depth + 1);
+            return a * (1.0 - t) + b * t;
+        }
+
+        if let Some(occlusion_texture) = model.textures.get(self.occlusion_texture) {
             let sampler = Sampler::default();
-            let image = model.images.get(mr_texture.image).unwrap();
-            let color = sampler.sample(image, uv);
-            // Blue channel contains metalness value
-            // Red channel contains roughness value
-            (color.b, color.r)
+            let image = model.images.get(occlusion_texture.image).unwrap();
+            let occlusion = sampler
+                .sample(image, &self.occlusion_transform.get_uv(uvs))
+                .r;
+            1.0 + self.occlusion_strength * (occlusion - 1.0)
         } else {
-            (self.metallic_factor, self.roughness_factor)
+            1.0
         }
     }
 
     pub fn get_radiance(&self, ir: &Irradiance, model: &Model) -> Color {
-        let (metallic, roughness) = self.get_metallic_roughness(model, &ir.uv);
+        let (metallic, roughness) = self.get_metallic_roughness(model, &ir.uvs, ir.n_dot_v);
 
         let d = distribution_ggx(ir.n_dot_h, roughness);
 
-        let f0 = Vec3::splat(0.04) * (1.0 - metallic) + Vec3::from(&ir.albedo) * metallic;
-        let f = fresnel_schlick(ir.l_dot_h, f0);
+        let f = match &self.conductor {
+            Some(conductor) => {
+                let dielectric = fresnel_schlick(ir.l_dot_h, Vec3::splat(0.04));
+                dielectric * (1.0 - metallic) + conductor.get_fresnel(ir.l_dot_h) * metallic
+            }
+            None => {
+                let f0 = Vec3::splat(0.04) * (1.0 - metallic) + Vec3::from(&ir.albedo) * metallic;
+                fresnel_schlick(ir.l_dot_h, f0)
+            }
+        };
 
         let ks = f;
         let kd = (Vec3::splat(1.0) - ks) * (1.0 - metallic);
@@ -157,6 +547,24 @@ impl Material {
 
         (fd + fr) * ir.intensity * ir.n_dot_l
     }
+
+    /// Hair BSDF used by curves, where the shading depends on the tangent of
+    /// the fiber rather than on its normal
+    pub fn get_hair_radiance(&self, ir: &Irradiance, tangent: &Vec3, model: &Model) -> Color {
+        let (_, roughness) = self.get_metallic_roughness(model, &ir.uvs, ir.n_dot_v);
+
+        let t_dot_l = tangent.dot(&ir.l);
+        let sin_tl = (1.0 - t_dot_l * t_dot_l).max(0.0).sqrt();
+
+        // Diffuse term is proportional to the projected fiber area
+        let fd = ir.albedo * sin_tl * std::f32::consts::FRAC_1_PI;
+
+        // Hair has an index of refraction of about 1.55, so its reflectance is about 4.6%
+        let f = fresnel_schlick(ir.l_dot_h, Vec3::splat(0.046));
+        let fs = Color::from(f) * hair_specular(tangent.dot(&ir.h), roughness);
+
+        (fd + fs) * ir.intensity
+    }
 }
 
 impl Default for Material {
@@ -164,3 +572,123 @@ impl Default for Material {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    use super::*;
+
+    #[test]
+    fn ggx_normalization() {
+        // The projected area of the microfacets should be equal to the macro surface area
+        const STEPS: usize = 1 << 16;
+        let d_theta = FRAC_PI_2 / STEPS as f32;
+
+        for roughness in [0.2, 0.5, 1.0] {
+            let mut area = 0.0;
+            for i in 0..STEPS {
+                let theta = (i as f32 + 0.5) * d_theta;
+                let d = distribution_ggx(theta.cos(), roughness);
+                area += d * theta.cos() * theta.sin() * d_theta * 2.0 * PI;
+            }
+            assert!(
+                (area - 1.0).abs() < 1e-2,
+                "roughness {}: {}",
+                roughness,
+                area
+            );
+        }
+    }
+
+    #[test]
+    fn mix() {
+        let mut model = Model::new();
+        let mut rust = Material::builder()
+            .color(Color::new(0.4, 0.2, 0.0, 1.0))
+            .build();
+        rust.metallic_factor = 0.0;
+        let rust = model.materials.push(rust);
+        let metal = model.materials.push(Material::new());
+        let mix = Material::mix(rust, metal, 0.25);
+
+        let uv = Vec2::default();
+        let color = mix.get_color(&model, &[uv]);
+        assert!((color.r - 0.55).abs() < 1e-5);
+        assert!((color.b - 0.25).abs() < 1e-5);
+        let (metallic, roughness) = mix.get_metallic_roughness(&model, &[uv], 1.0);
+        assert!((metallic - 0.25).abs() < 1e-5);
+        assert!((roughness - 1.0).abs() < 1e-5);
+
+        // A white mask selects the second material
+        let mut image = Image::new(1, 1, ColorType::RGBA8);
+        image.bytes_mut().fill(0xFF);
+        let image = model.images.push(image);
+        let mask = model.textures.push(Texture::new(image, Handle::NONE));
+        let mut mix = mix;
+        mix.mix.as_mut().unwrap().mask = mask;
+        let color = mix.get_color(&model, &[uv]);
+        assert_eq!((color.r, color.g, color.b), (1.0, 1.0, 1.0));
+
+        // Mixes referring to themselves stop after a few levels
+        let cycle = model.materials.push(Material::new());
+        *model.materials.get_mut(cycle).unwrap() = Material::mix(cycle, rust, 0.5);
+        let cycle = model.materials.get(cycle).unwrap();
+        let color = cycle.get_color(&model, &[uv]);
+        assert!(color.r > 0.4 && color.r < 1.0);
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let tangent = Vec3::new(1.0, 0.0, 0.0);
+        let bitangent = Vec3::new(0.0, 1.0, 0.0);
+        let cycle_normal = cycle.get_normal(&model, &[uv], normal, tangent, bitangent);
+        assert_eq!(cycle_normal, normal);
+        let (metallic, _) = cycle.get_metallic_roughness(&model, &[uv], 1.0);
+        assert!(metallic < 1.0);
+        assert_eq!(cycle.get_occlusion(&model, &[uv]), 1.0);
+    }
+
+    #[test]
+    fn occlusion() {
+        let mut model = Model::new();
+        let mut image = Image::new(2, 1, ColorType::RGBA8);
+        // Left texel is fully occluded, right one is not
+        image.bytes_mut()[4..].fill(0xFF);
+        let image = model.images.push(image);
+        let texture = model.textures.push(Texture::new(image, Handle::NONE));
+
+        let mut material = Material::new();
+        material.occlusion_texture = texture;
+        material.occlusion_strength = 0.5;
+        material.occlusion_transform.tex_coord = 1;
+
+        let left = Vec2::new(0.25, 0.5);
+        let right = Vec2::new(0.75, 0.5);
+        assert_eq!(material.get_occlusion(&model, &[right, left]), 0.5);
+        assert_eq!(material.get_occlusion(&model, &[left, right]), 1.0);
+        assert_eq!(Material::new().get_occlusion(&model, &[left, left]), 1.0);
+    }
+
+    #[test]
+    fn conductor() {
+        // Reflectance at normal incidence matches its closed form
+        let gold = Conductor::GOLD;
+        let f0 = gold.get_fresnel(1.0);
+        let eta = gold.eta.get_x();
+        let k = gold.k.get_x();
+        let expected = ((eta - 1.0).powi(2) + k * k) / ((eta + 1.0).powi(2) + k * k);
+        assert!((f0.get_x() - expected).abs() < 1e-5);
+
+        // Gold reflects red more than blue, and everything at grazing angles
+        assert!(f0.get_x() > 0.9 && f0.get_z() < 0.4);
+        let grazing = gold.get_fresnel(0.0);
+        assert!(grazing.close(&Vec3::splat(1.0)));
+        for conductor in [Conductor::COPPER, Conductor::ALUMINUM, Conductor::SILVER] {
+            let f = conductor.get_fresnel(0.5);
+            assert!([f.get_x(), f.get_y(), f.get_z()]
+                .iter()
+                .all(|f| (0.0..=1.0).contains(f)));
+        }
+
+        let material = Material::builder().conductor(Conductor::COPPER).build();
+        assert!(material.conductor.is_some());
+    }
+}
@@ -0,0 +1,511 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Small graph of nodes computing material properties at shade time, for looks
+//! which cannot be expressed by the fixed properties of a `Material`.
+
+use super::*;
+
+/// Node of a `MaterialGraph`, where inputs are indices of nodes added before it.
+/// Every node outputs a color, and scalar inputs read its red channel
+#[derive(Clone)]
+pub enum GraphNode {
+    Constant(Color),
+
+    /// Texture coordinates of the hit in the red and green channels
+    Uv,
+
+    /// Texture sampled at the output of the `uv` node, or at the texture
+    /// coordinates of the hit when there is no such node
+    Texture {
+        texture: Handle<Texture>,
+        uv: Option<usize>,
+    },
+
+    Add(usize, usize),
+    Multiply(usize, usize),
+
+    /// Linear interpolation from `a` to `b`
+    Mix {
+        a: usize,
+        b: usize,
+        factor: usize,
+    },
+
+    /// Schlick approximation of the reflectance of a dielectric with index of
+    /// refraction `ior`, seen from the direction of the viewer
+    Fresnel {
+        ior: f32,
+    },
+
+    /// Tangent-space normal encoded as a color, perturbed by the slope of the
+    /// `height` node around the texture coordinates of the hit
+    Bump {
+        height: usize,
+        strength: f32,
+    },
+}
+
+impl GraphNode {
+    fn get_inputs(&self) -> Vec<usize> {
+        match self {
+            GraphNode::Constant(_) | GraphNode::Uv | GraphNode::Fresnel { .. } => vec![],
+            GraphNode::Texture { uv, .. } => uv.iter().copied().collect(),
+            GraphNode::Add(a, b) | GraphNode::Multiply(a, b) => vec![*a, *b],
+            GraphNode::Mix { a, b, factor } => vec![*a, *b, *factor],
+            GraphNode::Bump { height, .. } => vec![*height],
+        }
+    }
+
+    fn to_json(&self) -> gltf::json::Value {
+        let (kind, mut properties): (&str, Vec<(&str, gltf::json::Value)>) = match self {
+            GraphNode::Constant(color) => (
+                "constant",
+                vec![("color", vec![color.r, color.g, color.b, color.a].into())],
+            ),
+            GraphNode::Uv => ("uv", vec![]),
+            GraphNode::Texture { texture, uv } => {
+                let mut properties = vec![("texture", texture.id.into())];
+                if let Some(uv) = uv {
+                    properties.push(("uv", (*uv).into()));
+                }
+                ("texture", properties)
+            }
+            GraphNode::Add(a, b) => ("add", vec![("a", (*a).into()), ("b", (*b).into())]),
+            GraphNode::Multiply(a, b) => ("multiply", vec![("a", (*a).into()), ("b", (*b).into())]),
+            GraphNode::Mix { a, b, factor } => (
+                "mix",
+                vec![
+                    ("a", (*a).into()),
+                    ("b", (*b).into()),
+                    ("factor", (*factor).into()),
+                ],
+            ),
+            GraphNode::Fresnel { ior } => ("fresnel", vec![("ior", (*ior).into())]),
+            GraphNode::Bump { height, strength } => (
+                "bump",
+                vec![
+                    ("height", (*height).into()),
+                    ("strength", (*strength).into()),
+                ],
+            ),
+        };
+        properties.insert(0, ("type", kind.into()));
+        properties.into_iter().collect()
+    }
+
+    fn from_json(value: &gltf::json::Value) -> Result<Self, RaycaError> {
+        let get_index = |key: &str| {
+            value
+                .get(key)
+                .and_then(|index| index.as_u64())
+                .map(|index| index as usize)
+                .ok_or_else(|| {
+                    RaycaError::Parse(format!("{} node {} as an index", RAYCA_MATERIAL_GRAPH, key))
+                })
+        };
+        let get_number = |key: &str| {
+            value
+                .get(key)
+                .and_then(|number| number.as_f64())
+                .map(|number| number as f32)
+                .ok_or_else(|| {
+                    RaycaError::Parse(format!("{} node {} as a number", RAYCA_MATERIAL_GRAPH, key))
+                })
+        };
+
+        let kind = value.get("type").and_then(|kind| kind.as_str());
+        let node = match kind {
+            Some("constant") => {
+                let color = value
+                    .get("color")
+                    .and_then(|array| array.as_array())
+                    .filter(|array| array.len() == 4)
+                    .and_then(|array| array.iter().map(|n| n.as_f64()).collect::<Option<Vec<_>>>())
+                    .ok_or_else(|| {
+                        RaycaError::Parse(format!(
+                            "{} node color as four numbers",
+                            RAYCA_MATERIAL_GRAPH
+                        ))
+                    })?;
+                GraphNode::Constant(Color::new(
+                    color[0] as f32,
+                    color[1] as f32,
+                    color[2] as f32,
+                    color[3] as f32,
+                ))
+            }
+            Some("uv") => GraphNode::Uv,
+            Some("texture") => GraphNode::Texture {
+                texture: Handle::new(get_index("texture")?),
+                uv: match value.get("uv") {
+                    Some(_) => Some(get_index("uv")?),
+                    None => None,
+                },
+            },
+            Some("add") => GraphNode::Add(get_index("a")?, get_index("b")?),
+            Some("multiply") => GraphNode::Multiply(get_index("a")?, get_index("b")?),
+            Some("mix") => GraphNode::Mix {
+                a: get_index("a")?,
+                b: get_index("b")?,
+                factor: get_index("factor")?,
+            },
+            Some("fresnel") => GraphNode::Fresnel {
+                ior: get_number("ior")?,
+            },
+            Some("bump") => GraphNode::Bump {
+                height: get_index("height")?,
+                strength: get_number("strength")?,
+            },
+            kind => {
+                return Err(RaycaError::Parse(format!(
+                    "{} node type {:?}",
+                    RAYCA_MATERIAL_GRAPH, kind
+                )))
+            }
+        };
+        Ok(node)
+    }
+}
+
+/// Name of the glTF material extension storing a `MaterialGraph`, whose nodes refer to
+/// each other by index and to textures by glTF index:
+/// `{ "nodes": [{ "type": "uv" }, { "type": "texture", "texture": 0, "uv": 0 }], "color": 1 }`
+pub const RAYCA_MATERIAL_GRAPH: &str = "RAYCA_material_graph";
+
+/// Properties of a hit read by the nodes of a `MaterialGraph`
+#[derive(Clone, Copy)]
+pub struct GraphInput {
+    pub uv: Vec2,
+
+    /// Cosine of the angle between the normal and the direction towards the viewer
+    pub n_dot_v: f32,
+}
+
+impl GraphInput {
+    /// Returns the input of a hit seen straight on
+    pub fn new(uv: Vec2) -> Self {
+        Self { uv, n_dot_v: 1.0 }
+    }
+}
+
+/// Nodes evaluated when shading a hit, whose outputs replace the corresponding
+/// properties of the material owning the graph. Properties without an output
+/// keep using the fixed properties of the material, which remain the fast path
+#[derive(Clone, Default)]
+pub struct MaterialGraph {
+    nodes: Vec<GraphNode>,
+
+    pub color: Option<usize>,
+    pub metallic: Option<usize>,
+    pub roughness: Option<usize>,
+
+    /// Tangent-space normal encoded as a color, such as the output of a `Bump` node
+    pub normal: Option<usize>,
+}
+
+impl MaterialGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the textures sampled by the nodes of this graph
+    pub fn get_textures(&self) -> Vec<Handle<Texture>> {
+        self.nodes
+            .iter()
+            .filter_map(|node| match node {
+                GraphNode::Texture { texture, .. } => Some(*texture),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Adds a node to the graph, returning its index. Inputs of the node should
+    /// refer to nodes already in the graph, which prevents cycles
+    pub fn push(&mut self, node: GraphNode) -> usize {
+        let index = self.nodes.len();
+        assert!(
+            node.get_inputs().iter().all(|input| *input < index),
+            "Invalid input for graph node {}",
+            index
+        );
+        self.nodes.push(node);
+        index
+    }
+
+    pub fn get_nodes(&self) -> &[GraphNode] {
+        &self.nodes
+    }
+
+    /// Maps the texture handles of the nodes to `textures`, useful when appending a model
+    /// to another one, see `Pack::append()`
+    pub fn remap_textures(&mut self, textures: &[Handle<Texture>]) {
+        for node in &mut self.nodes {
+            if let GraphNode::Texture { texture, .. } = node {
+                texture.remap(textures);
+            }
+        }
+    }
+
+    /// Returns the output of node `index` for a hit with properties `input`
+    pub fn evaluate(&self, model: &Model, index: usize, input: &GraphInput) -> Color {
+        match &self.nodes[index] {
+            GraphNode::Constant(color) => *color,
+            GraphNode::Uv => Color::new(input.uv.x, input.uv.y, 0.0, 1.0),
+            GraphNode::Texture { texture, uv } => {
+                let coords = match uv {
+                    Some(uv) => {
+                        let color = self.evaluate(model, *uv, input);
+                        Vec2::new(color.r, color.g)
+                    }
+                    None => input.uv,
+                };
+                match model.textures.get(*texture) {
+                    Some(texture) => {
+                        let sampler = Sampler::default();
+                        let image = model.images.get(texture.image).unwrap();
+                        sampler.sample(image, &coords)
+                    }
+                    None => Color::white(),
+                }
+            }
+            GraphNode::Add(a, b) => {
+                self.evaluate(model, *a, input) + self.evaluate(model, *b, input)
+            }
+            GraphNode::Multiply(a, b) => {
+                self.evaluate(model, *a, input) * self.evaluate(model, *b, input)
+            }
+            GraphNode::Mix { a, b, factor } => {
+                let t = self.evaluate(model, *factor, input).r.clamp(0.0, 1.0);
+                self.evaluate(model, *a, input) * (1.0 - t) + self.evaluate(model, *b, input) * t
+            }
+            GraphNode::Fresnel { ior } => {
+                let f0 = ((ior - 1.0) / (ior + 1.0)).powi(2);
+                let cos_theta = input.n_dot_v.clamp(0.0, 1.0);
+                let f = f0 + (1.0 - f0) * (1.0 - cos_theta).powi(5);
+                Color::new(f, f, f, 1.0)
+            }
+            GraphNode::Bump { height, strength } => {
+                // Central differences of the height along U and V
+                const DELTA: f32 = 1.0 / 1024.0;
+                let height_at = |offset: Vec2| {
+                    let input = GraphInput {
+                        uv: input.uv + offset,
+                        ..*input
+                    };
+                    self.evaluate(model, *height, &input).r
+                };
+                let dhdu = (height_at(Vec2::new(DELTA, 0.0)) - height_at(Vec2::new(-DELTA, 0.0)))
+                    / (2.0 * DELTA);
+                let dhdv = (height_at(Vec2::new(0.0, DELTA)) - height_at(Vec2::new(0.0, -DELTA)))
+                    / (2.0 * DELTA);
+                let normal = Vec3::new(-strength * dhdu, -strength * dhdv, 1.0).get_normalized();
+                Color::from(normal * 0.5 + Vec3::splat(0.5))
+            }
+        }
+    }
+
+    /// Returns the red channel of `output`, if present
+    pub fn evaluate_scalar(
+        &self,
+        model: &Model,
+        output: Option<usize>,
+        input: &GraphInput,
+    ) -> Option<f32> {
+        output.map(|index| self.evaluate(model, index, input).r)
+    }
+
+    /// Returns the JSON of the `RAYCA_MATERIAL_GRAPH` extension, see `from_json()`
+    pub fn to_json(&self) -> gltf::json::Value {
+        let mut properties = vec![(
+            "nodes".to_string(),
+            gltf::json::Value::Array(self.nodes.iter().map(GraphNode::to_json).collect()),
+        )];
+        let outputs = [
+            ("color", self.color),
+            ("metallic", self.metallic),
+            ("roughness", self.roughness),
+            ("normal", self.normal),
+        ];
+        for (key, output) in outputs {
+            if let Some(output) = output {
+                properties.push((key.to_string(), output.into()));
+            }
+        }
+        properties.into_iter().collect()
+    }
+
+    /// Parses the JSON of the `RAYCA_MATERIAL_GRAPH` extension. Inputs and outputs
+    /// must refer to existing nodes, where inputs refer to nodes before them
+    pub fn from_json(value: &gltf::json::Value) -> Result<Self, RaycaError> {
+        let nodes = value
+            .get("nodes")
+            .and_then(|nodes| nodes.as_array())
+            .ok_or_else(|| {
+                RaycaError::Parse(format!("{} nodes as an array", RAYCA_MATERIAL_GRAPH))
+            })?;
+
+        let mut ret = Self::new();
+        for (index, node) in nodes.iter().enumerate() {
+            let node = GraphNode::from_json(node)?;
+            if node.get_inputs().iter().any(|input| *input >= index) {
+                return Err(RaycaError::Parse(format!(
+                    "{} node {} with inputs not before it",
+                    RAYCA_MATERIAL_GRAPH, index
+                )));
+            }
+            ret.push(node);
+        }
+
+        let get_output = |key: &str| match value.get(key) {
+            None => Ok(None),
+            Some(output) => output
+                .as_u64()
+                .map(|output| output as usize)
+                .filter(|output| *output < nodes.len())
+                .map(Some)
+                .ok_or_else(|| {
+                    RaycaError::Parse(format!("{} {} as a node index", RAYCA_MATERIAL_GRAPH, key))
+                }),
+        };
+        ret.color = get_output("color")?;
+        ret.metallic = get_output("metallic")?;
+        ret.roughness = get_output("roughness")?;
+        ret.normal = get_output("normal")?;
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn graph() {
+        let model = Model::new();
+
+        // Gradient from black to red along U, darkened by half
+        let mut graph = MaterialGraph::new();
+        let black = graph.push(GraphNode::Constant(Color::black()));
+        let red = graph.push(GraphNode::Constant(Color::new(1.0, 0.0, 0.0, 1.0)));
+        let uv = graph.push(GraphNode::Uv);
+        let gradient = graph.push(GraphNode::Mix {
+            a: black,
+            b: red,
+            factor: uv,
+        });
+        let half = graph.push(GraphNode::Constant(Color::new(0.5, 0.5, 0.5, 1.0)));
+        graph.color = Some(graph.push(GraphNode::Multiply(gradient, half)));
+        graph.roughness = Some(uv);
+
+        let material = Material {
+            graph: Some(graph),
+            ..Material::new()
+        };
+        let uv = Vec2::new(0.5, 0.25);
+        let color = material.get_color(&model, &[uv]);
+        assert!((color.r - 0.25).abs() < 1e-5);
+        assert_eq!(color.g, 0.0);
+        assert_eq!(
+            material.get_metallic_roughness(&model, &[uv], 1.0),
+            (1.0, 0.5)
+        );
+    }
+
+    #[test]
+    fn fresnel() {
+        let model = Model::new();
+        let mut graph = MaterialGraph::new();
+        let fresnel = graph.push(GraphNode::Fresnel { ior: 1.5 });
+
+        // Glass reflects 4% of the light seen straight on, and all of it at grazing angles
+        let front = GraphInput::new(Vec2::default());
+        assert!((graph.evaluate(&model, fresnel, &front).r - 0.04).abs() < 1e-5);
+        let grazing = GraphInput {
+            n_dot_v: 0.0,
+            ..front
+        };
+        assert!((graph.evaluate(&model, fresnel, &grazing).r - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn bump() {
+        let model = Model::new();
+
+        // Height increasing along U tilts the normal towards negative U
+        let mut graph = MaterialGraph::new();
+        let uv = graph.push(GraphNode::Uv);
+        let bump = graph.push(GraphNode::Bump {
+            height: uv,
+            strength: 1.0,
+        });
+        let color = graph.evaluate(&model, bump, &GraphInput::new(Vec2::new(0.5, 0.5)));
+        let normal = Vec3::from(color) * 2.0 - 1.0;
+        assert!(normal.close(&Vec3::new(-1.0, 0.0, 1.0).get_normalized()));
+
+        // Flat height keeps the normal of the surface
+        let mut graph = MaterialGraph::new();
+        let height = graph.push(GraphNode::Constant(Color::white()));
+        graph.normal = Some(graph.push(GraphNode::Bump {
+            height,
+            strength: 1.0,
+        }));
+        let material = Material {
+            graph: Some(graph),
+            ..Material::new()
+        };
+        let normal = material.get_normal(
+            &model,
+            &[Vec2::default()],
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+        assert!(normal.close(&Vec3::new(0.0, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn json() {
+        let mut graph = MaterialGraph::new();
+        let uv = graph.push(GraphNode::Uv);
+        let texture = graph.push(GraphNode::Texture {
+            texture: Handle::new(2),
+            uv: Some(uv),
+        });
+        let fresnel = graph.push(GraphNode::Fresnel { ior: 1.5 });
+        let white = graph.push(GraphNode::Constant(Color::white()));
+        graph.color = Some(graph.push(GraphNode::Mix {
+            a: texture,
+            b: white,
+            factor: fresnel,
+        }));
+        graph.normal = Some(graph.push(GraphNode::Bump {
+            height: texture,
+            strength: 0.5,
+        }));
+
+        let json = graph.to_json();
+        let parsed = MaterialGraph::from_json(&json).unwrap();
+        assert_eq!(parsed.get_nodes().len(), graph.get_nodes().len());
+        assert_eq!(parsed.get_textures(), vec![Handle::new(2)]);
+        assert_eq!(parsed.color, graph.color);
+        assert_eq!(parsed.normal, graph.normal);
+        assert_eq!(parsed.metallic, None);
+        assert_eq!(parsed.to_json(), json);
+
+        // Inputs after the node would allow cycles
+        let cycle: gltf::json::Value = gltf::json::deserialize::from_str(
+            r#"{ "nodes": [{ "type": "add", "a": 0, "b": 0 }] }"#,
+        )
+        .unwrap();
+        assert!(MaterialGraph::from_json(&cycle).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn cycle() {
+        let mut graph = MaterialGraph::new();
+        graph.push(GraphNode::Add(0, 1));
+    }
+}
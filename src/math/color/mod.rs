@@ -4,17 +4,20 @@
 
 pub mod rgb8;
 pub mod rgba8;
+pub mod space;
 
 pub use rgb8::*;
 pub use rgba8::*;
+pub use space::*;
 
 use crate::{Point3, Vec3};
 
 use std::ops::{Add, AddAssign, Div, Mul, MulAssign};
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
 pub enum ColorType {
     RGB8,
+    #[default]
     RGBA8,
 }
 
@@ -27,12 +30,6 @@ impl ColorType {
     }
 }
 
-impl Default for ColorType {
-    fn default() -> Self {
-        Self::RGBA8
-    }
-}
-
 pub trait ColorTyped: Copy {
     fn color_type() -> ColorType;
 }
@@ -58,11 +55,24 @@ impl Color {
         Self::new(1.0, 1.0, 1.0, 1.0)
     }
 
+    /// Returns the relative luminance of this color, with the weights of Rec.709 primaries
+    pub fn get_luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    /// Composites `top` over this color, where both colors are not premultiplied
+    /// by their alpha, which becomes the coverage of the two layers together
     pub fn over(&mut self, top: Color) {
-        self.r = top.r * top.a + self.r * (1.0 - top.a);
-        self.g = top.g * top.a + self.g * (1.0 - top.a);
-        self.b = top.b * top.a + self.b * (1.0 - top.a);
-        self.a = 1.0;
+        let a = top.a + self.a * (1.0 - top.a);
+        if a <= 0.0 {
+            *self = Self::new(0.0, 0.0, 0.0, 0.0);
+            return;
+        }
+        let bottom = self.a * (1.0 - top.a);
+        self.r = (top.r * top.a + self.r * bottom) / a;
+        self.g = (top.g * top.a + self.g * bottom) / a;
+        self.b = (top.b * top.a + self.b * bottom) / a;
+        self.a = a;
     }
 }
 
@@ -268,3 +278,25 @@ impl Div<f32> for &Color {
         Self::Output::new(self.r / rhs, self.g / rhs, self.b / rhs, self.a)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn over() {
+        // Opaque backgrounds stay opaque
+        let mut color = Color::black();
+        color.over(Color::new(1.0, 0.0, 0.0, 0.5));
+        assert_eq!(color, Color::new(0.5, 0.0, 0.0, 1.0));
+
+        // Transparent backgrounds take the color and coverage of the top layer
+        let mut color = Color::new(0.0, 0.0, 0.0, 0.0);
+        color.over(Color::new(1.0, 0.0, 0.0, 0.5));
+        assert_eq!(color, Color::new(1.0, 0.0, 0.0, 0.5));
+
+        // Coverage of layers accumulates
+        color.over(Color::new(0.0, 1.0, 0.0, 0.5));
+        assert_eq!(color.a, 0.75);
+    }
+}
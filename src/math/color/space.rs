@@ -0,0 +1,107 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use super::Color;
+
+/// Linear sRGB to ACEScg, with the Bradford adaptation from D65 to the ACES white point
+const SRGB_TO_ACESCG: [[f32; 3]; 3] = [
+    [0.613097, 0.339523, 0.047379],
+    [0.070194, 0.916354, 0.013452],
+    [0.020616, 0.109570, 0.869815],
+];
+
+/// ACEScg to linear sRGB, the inverse of `SRGB_TO_ACESCG`
+const ACESCG_TO_SRGB: [[f32; 3]; 3] = [
+    [1.704859, -0.621715, -0.083299],
+    [-0.130078, 1.140734, -0.010560],
+    [-0.023964, -0.128975, 1.153013],
+];
+
+fn transform(matrix: &[[f32; 3]; 3], color: Color) -> Color {
+    let [r, g, b] = matrix.map(|row| row[0] * color.r + row[1] * color.g + row[2] * color.b);
+    Color::new(r, g, b, color.a)
+}
+
+/// Linear color spaces, telling apart the primaries colors are expressed with.
+/// Textures and lights are authored in sRGB, and converted to the working space
+/// of the renderer, while rendered images are converted to an output space
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Rec.709 primaries with the D65 white point
+    #[default]
+    Srgb,
+
+    /// AP1 primaries with the ACES white point, as used by ACES pipelines
+    AcesCg,
+}
+
+impl ColorSpace {
+    /// Returns `color`, in sRGB, converted to this space
+    pub fn from_srgb(&self, color: Color) -> Color {
+        match self {
+            ColorSpace::Srgb => color,
+            ColorSpace::AcesCg => transform(&SRGB_TO_ACESCG, color),
+        }
+    }
+
+    /// Returns `color`, in this space, converted to sRGB
+    pub fn to_srgb(&self, color: Color) -> Color {
+        match self {
+            ColorSpace::Srgb => color,
+            ColorSpace::AcesCg => transform(&ACESCG_TO_SRGB, color),
+        }
+    }
+
+    /// Returns `color`, in this space, converted to `space`
+    pub fn convert(&self, color: Color, space: ColorSpace) -> Color {
+        if *self == space {
+            return color;
+        }
+        space.from_srgb(self.to_srgb(color))
+    }
+
+    /// Returns the chromaticities of the white point, and of the red, green,
+    /// and blue primaries, in this order
+    pub fn get_chromaticities(&self) -> [(f32, f32); 4] {
+        match self {
+            ColorSpace::Srgb => [
+                (0.31270, 0.32900),
+                (0.64000, 0.33000),
+                (0.30000, 0.60000),
+                (0.15000, 0.06000),
+            ],
+            ColorSpace::AcesCg => [
+                (0.32168, 0.33767),
+                (0.71300, 0.29300),
+                (0.16500, 0.83000),
+                (0.12800, 0.04400),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn convert() {
+        // White is the same in both spaces
+        let white = ColorSpace::AcesCg.from_srgb(Color::white());
+        assert!((white.r - 1.0).abs() < 1e-5);
+        assert!((white.g - 1.0).abs() < 1e-5);
+        assert!((white.b - 1.0).abs() < 1e-5);
+
+        // Saturated sRGB colors are less saturated in the wider ACEScg gamut
+        let red = Color::new(1.0, 0.0, 0.0, 0.5);
+        let aces_red = ColorSpace::Srgb.convert(red, ColorSpace::AcesCg);
+        assert!(aces_red.r < 1.0 && aces_red.g > 0.0 && aces_red.b > 0.0);
+        assert_eq!(aces_red.a, 0.5);
+
+        let back = ColorSpace::AcesCg.convert(aces_red, ColorSpace::Srgb);
+        assert!((back.r - 1.0).abs() < 1e-3);
+        assert!(back.g.abs() < 1e-3);
+        assert!(back.b.abs() < 1e-3);
+    }
+}
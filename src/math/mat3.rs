@@ -2,15 +2,12 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
-use std::{
-    ops::{Index, IndexMut, Mul},
-    simd::f32x4,
-};
+use std::ops::{Index, IndexMut, Mul};
 
-use super::*;
+use super::{simd::f32x4, *};
 
 #[repr(C, align(16))]
-#[derive(Default, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct Mat3 {
     /// Row-major
     values: [[f32; 3]; 3],
@@ -87,6 +84,78 @@ impl Mat3 {
         }
         ret
     }
+
+    /// Returns the `i`-th column
+    pub fn get_column(&self, i: usize) -> Vec3 {
+        Vec3::new(self[0][i], self[1][i], self[2][i])
+    }
+
+    pub fn get_determinant(&self) -> f32 {
+        self.get_column(0)
+            .dot(self.get_column(1).cross(&self.get_column(2)))
+    }
+
+    /// Returns the inverse matrix, or `None` when the matrix is singular
+    pub fn get_inverse(&self) -> Option<Self> {
+        let det = self.get_determinant();
+        if det.abs() < f32::EPSILON * f32::EPSILON {
+            return None;
+        }
+        // Rows of the inverse are the cross products of the columns
+        let [a, b, c] = [0, 1, 2].map(|i| self.get_column(i));
+        let rows = [b.cross(&c), c.cross(&a), a.cross(&b)].map(|row| row / det);
+        Some(Self::from(
+            rows.map(|row| [row.get_x(), row.get_y(), row.get_z()]),
+        ))
+    }
+
+    /// Splits the matrix into a rotation and a stretch along the axes of the rotation,
+    /// so that transforms with shear get their closest rotation and scale. Reflections
+    /// are returned as a negative scale along X
+    pub fn decompose(&self) -> (Quat, Vec3) {
+        let mut m = *self;
+        let reflection = m.get_determinant() < 0.0;
+        if reflection {
+            for row in 0..3 {
+                m[row][0] = -m[row][0];
+            }
+        }
+        let Some(mut inverse) = m.get_inverse() else {
+            // Singular matrices only keep the length of their columns
+            let scale = Vec3::new(
+                m.get_column(0).len(),
+                m.get_column(1).len(),
+                m.get_column(2).len(),
+            );
+            return (Quat::default(), scale);
+        };
+
+        // Polar decomposition, averaging the matrix with its inverse transpose
+        // converges to the rotation
+        let mut rotation = m;
+        for _ in 0..32 {
+            let inverse_transpose = inverse.get_transpose();
+            let mut delta = 0.0f32;
+            for i in 0..3 {
+                for j in 0..3 {
+                    let value = (rotation[i][j] + inverse_transpose[i][j]) * 0.5;
+                    delta = delta.max((value - rotation[i][j]).abs());
+                    rotation[i][j] = value;
+                }
+            }
+            if delta < 1e-6 {
+                break;
+            }
+            inverse = rotation.get_inverse().unwrap();
+        }
+
+        let stretch = rotation.get_transpose() * m;
+        let mut scale = Vec3::new(stretch[0][0], stretch[1][1], stretch[2][2]);
+        if reflection {
+            scale.set_x(-scale.get_x());
+        }
+        (Quat::from(&rotation), scale)
+    }
 }
 
 impl From<&Mat4> for Mat3 {
@@ -2,14 +2,14 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
-use std::{
-    ops::{Index, IndexMut, Mul},
-    simd::{f32x4, num::SimdFloat},
-};
+use std::ops::{Index, IndexMut, Mul};
 
-use super::*;
+use super::{
+    simd::{f32x4, SimdFloat},
+    *,
+};
 
-#[derive(Default, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 /// Row-major 4x4 Matrix
 pub struct Mat4 {
     values: [f32x4; 4],
@@ -96,12 +96,14 @@ impl Mat4 {
         self[2][3] += translation.get_z();
     }
 
+    /// Returns the scale of an affine transform, see `decompose()`
     pub fn get_scale(&self) -> Vec3 {
-        Vec3::new(self[0][0], self[1][1], self[2][2])
+        Mat3::from(self).decompose().1
     }
 
+    /// Returns the rotation of an affine transform, see `decompose()`
     pub fn get_rotation(&self) -> Quat {
-        Quat::from(self)
+        Mat3::from(self).decompose().0
     }
 
     pub fn get_translation(&self) -> Vec3 {
@@ -123,6 +125,52 @@ impl Mat4 {
         }
         ret
     }
+
+    /// Returns the determinant of the minor without row `row` and column `col`
+    fn get_minor(&self, row: usize, col: usize) -> f32 {
+        let mut minor = Mat3::new();
+        let others = |skip: usize| (0..4).filter(move |i| *i != skip);
+        for (i, r) in others(row).enumerate() {
+            for (j, c) in others(col).enumerate() {
+                minor[i][j] = self[r][c];
+            }
+        }
+        minor.get_determinant()
+    }
+
+    pub fn get_determinant(&self) -> f32 {
+        (0..4)
+            .map(|col| {
+                let sign = if col % 2 == 0 { 1.0 } else { -1.0 };
+                sign * self[0][col] * self.get_minor(0, col)
+            })
+            .sum()
+    }
+
+    /// Returns the inverse of any invertible matrix, including projections,
+    /// or `None` when the matrix is singular
+    pub fn get_inverse(&self) -> Option<Self> {
+        let det = self.get_determinant();
+        if det.abs() < f32::EPSILON * f32::EPSILON {
+            return None;
+        }
+        // Adjugate over determinant, where the adjugate is the transposed cofactor matrix
+        let mut ret = Self::default();
+        for i in 0..4 {
+            for j in 0..4 {
+                let sign = if (i + j) % 2 == 0 { 1.0 } else { -1.0 };
+                ret[j][i] = sign * self.get_minor(i, j) / det;
+            }
+        }
+        Some(ret)
+    }
+
+    /// Splits an affine transform into translation, rotation, and scale, supporting
+    /// non-uniform scales and reflections, and approximating shears, see `Mat3::decompose()`
+    pub fn decompose(&self) -> Trs {
+        let (rotation, scale) = Mat3::from(self).decompose();
+        Trs::new(self.get_translation(), rotation, scale)
+    }
 }
 
 impl Index<usize> for Mat4 {
@@ -203,8 +251,9 @@ impl From<&Quat> for Mat4 {
 
 impl From<&Trs> for Mat4 {
     fn from(trs: &Trs) -> Self {
-        Mat4::from_scale(&trs.scale)
-            * (Mat4::from_rotation(&trs.rotation) * Mat4::from_translation(&trs.translation))
+        // Points are scaled first, then rotated, and translated last
+        Mat4::from_translation(&trs.translation)
+            * (Mat4::from_rotation(&trs.rotation) * Mat4::from_scale(&trs.scale))
     }
 }
 
@@ -216,9 +265,10 @@ impl From<Trs> for Mat4 {
 
 impl From<&Inversed<&Trs>> for Mat4 {
     fn from(inv_trs: &Inversed<&Trs>) -> Self {
-        Mat4::from_translation(&inv_trs.get_translation())
+        // (T * R * S)^(-1) = S^(-1) * R^(-1) * T^(-1)
+        Mat4::from_scale(&inv_trs.get_scale())
             * (Mat4::from_rotation(&inv_trs.get_rotation())
-                * Mat4::from_scale(&inv_trs.get_scale()))
+                * Mat4::from_translation(&inv_trs.get_translation()))
     }
 }
 
@@ -230,9 +280,10 @@ impl From<Inversed<&Trs>> for Mat4 {
 
 impl From<&Inversed<Trs>> for Mat4 {
     fn from(inv_trs: &Inversed<Trs>) -> Self {
-        Mat4::from_translation(&inv_trs.get_translation())
+        // (T * R * S)^(-1) = S^(-1) * R^(-1) * T^(-1)
+        Mat4::from_scale(&inv_trs.get_scale())
             * (Mat4::from_rotation(&inv_trs.get_rotation())
-                * Mat4::from_scale(&inv_trs.get_scale()))
+                * Mat4::from_translation(&inv_trs.get_translation()))
     }
 }
 
@@ -298,4 +349,53 @@ mod test {
         assert_eq!(-mat.get_translation(), eye);
         assert_eq!(mat.get_rotation(), Quat::default());
     }
+
+    fn close(a: &Mat4, b: &Mat4) -> bool {
+        (0..4).all(|i| (0..4).all(|j| (a[i][j] - b[i][j]).abs() < 1e-4))
+    }
+
+    #[test]
+    fn inverse() {
+        let trs = Trs::builder()
+            .translation(Vec3::new(1.0, 2.0, 3.0))
+            .rotation(Quat::axis_angle(Vec3::new(0.0, 1.0, 0.0), 0.5))
+            .scale(Vec3::new(2.0, 3.0, 4.0))
+            .build();
+        let mat = Mat4::from(&trs);
+        let inverse = mat.get_inverse().unwrap();
+        assert!(close(&(mat * inverse), &Mat4::identity()));
+        assert!(close(&inverse, &Mat4::from(&trs.get_inversed())));
+
+        // Projections are not affine
+        let mut projection = Mat4::identity();
+        projection[3] = f32x4::from_array([0.0, 0.0, -1.0, 0.0]);
+        projection[2][3] = -1.0;
+        let inverse = projection.get_inverse().unwrap();
+        assert!(close(&(projection * inverse), &Mat4::identity()));
+
+        assert!(Mat4::default().get_inverse().is_none());
+    }
+
+    #[test]
+    fn decompose() {
+        let trs = Trs::builder()
+            .translation(Vec3::new(1.0, 2.0, 3.0))
+            .rotation(Quat::axis_angle(
+                Vec3::new(1.0, 1.0, 0.0).get_normalized(),
+                1.0,
+            ))
+            .scale(Vec3::new(0.5, 2.0, 3.0))
+            .build();
+        let mat = Mat4::from(&trs);
+        let decomposed = mat.decompose();
+        assert!(decomposed.translation.close(&trs.translation));
+        assert!(decomposed.scale.close(&trs.scale));
+        assert!(decomposed.rotation.dot(&trs.rotation).abs() > 0.9999);
+        assert!(close(&Mat4::from(&decomposed), &mat));
+
+        // Mirrored transforms are rebuilt by a negative scale
+        let mut mirrored = Mat4::from_scale(&Vec3::new(1.0, -1.0, 1.0));
+        mirrored.rotate(&trs.rotation);
+        assert!(close(&Mat4::from(&mirrored.decompose()), &mirrored));
+    }
 }
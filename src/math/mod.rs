@@ -9,9 +9,14 @@ pub mod ops;
 pub mod point3;
 pub mod quat;
 pub mod ray;
+pub mod real;
+pub mod sh;
+pub mod simd;
+pub mod spline;
 pub mod trs;
 pub mod vec2;
 pub mod vec3;
+pub mod wide;
 
 pub use color::*;
 pub use mat3::*;
@@ -20,6 +25,10 @@ pub use ops::*;
 pub use point3::*;
 pub use quat::*;
 pub use ray::*;
+pub use real::*;
+pub use sh::*;
+pub use spline::*;
 pub use trs::*;
 pub use vec2::*;
 pub use vec3::*;
+pub use wide::*;
@@ -2,11 +2,9 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
-use std::{
-    ops::{Add, AddAssign, Index, Mul, MulAssign, Sub},
-    simd::{f32x4, mask32x4, num::SimdFloat},
-};
+use std::ops::{Add, AddAssign, Index, Mul, MulAssign, Sub};
 
+use super::simd::{f32x4, mask32x4, Select, SimdFloat};
 use crate::*;
 
 #[repr(C)]
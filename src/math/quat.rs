@@ -2,11 +2,9 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
-use std::{
-    ops::{Mul, MulAssign},
-    simd::{f32x4, mask32x4, num::SimdFloat},
-};
+use std::ops::{Add, Mul, MulAssign, Neg};
 
+use super::simd::{f32x4, mask32x4, Select, SimdFloat};
 use crate::*;
 
 /// Quaternion structure
@@ -56,6 +54,21 @@ impl Quat {
         ret
     }
 
+    /// Returns the shortest rotation bringing direction `from` onto direction `to`
+    pub fn rotation_between(from: Vec3, to: Vec3) -> Self {
+        let from = from.get_normalized();
+        let to = to.get_normalized();
+        let mut axis = from.cross(&to);
+        if axis.len() < f32::EPSILON {
+            if from.dot(to) > 0.0 {
+                return Quat::default();
+            }
+            // Opposite directions, any axis orthogonal to them works
+            axis = from.get_orthonormal_basis().0;
+        }
+        Quat::axis_angle(axis.get_normalized(), from.dot(to).clamp(-1.0, 1.0).acos())
+    }
+
     /// Standard euclidean for product in 4D
     pub fn dot(&self, rhs: &Quat) -> f32 {
         (self.simd * rhs.simd).reduce_sum()
@@ -83,6 +96,72 @@ impl Quat {
         assert!(self.is_normalized());
         self.get_conjugate()
     }
+
+    pub fn get_normalized(&self) -> Self {
+        let mut ret = *self;
+        ret.normalize();
+        ret
+    }
+
+    /// Spherical linear interpolation from `self` to `rhs` along the shortest path,
+    /// at constant angular speed
+    pub fn slerp(&self, rhs: &Quat, t: f32) -> Self {
+        let mut cos = self.dot(rhs);
+        let mut rhs = *rhs;
+        if cos < 0.0 {
+            rhs = -rhs;
+            cos = -cos;
+        }
+        // Almost equal rotations are blended linearly to avoid dividing by zero
+        if cos > 0.9995 {
+            return (*self * (1.0 - t) + rhs * t).get_normalized();
+        }
+        let angle = cos.acos();
+        let sin = angle.sin();
+        let a = ((1.0 - t) * angle).sin() / sin;
+        let b = (t * angle).sin() / sin;
+        (*self * a + rhs * b).get_normalized()
+    }
+
+    /// Natural logarithm of a unit quaternion, which is a pure quaternion
+    pub fn log(&self) -> Self {
+        let xyz = self.get_xyz();
+        let sin = xyz.len();
+        if sin < f32::EPSILON {
+            return Quat::new(0.0, 0.0, 0.0, 0.0);
+        }
+        let angle = sin.atan2(self.get_w());
+        let xyz = xyz * (angle / sin);
+        Quat::new(xyz.get_x(), xyz.get_y(), xyz.get_z(), 0.0)
+    }
+
+    /// Exponential of a pure quaternion, which is a unit quaternion
+    pub fn exp(&self) -> Self {
+        let xyz = self.get_xyz();
+        let angle = xyz.len();
+        if angle < f32::EPSILON {
+            return Quat::default();
+        }
+        let xyz = xyz * (angle.sin() / angle);
+        Quat::new(xyz.get_x(), xyz.get_y(), xyz.get_z(), angle.cos())
+    }
+
+    /// Returns the control point of `self` for `squad()`, where `prev` and `next`
+    /// are the rotations of the keys around it
+    pub fn get_squad_control(&self, prev: &Quat, next: &Quat) -> Self {
+        let inverse = self.get_conjugate();
+        let a = (inverse * *prev).log();
+        let b = (inverse * *next).log();
+        *self * ((a + b) * -0.25).exp()
+    }
+
+    /// Spherical cubic interpolation from `self` to `rhs`, which is smooth across keys,
+    /// where `a` and `b` are their control points, see `get_squad_control()`
+    pub fn squad(&self, a: &Quat, b: &Quat, rhs: &Quat, t: f32) -> Self {
+        let outer = self.slerp(rhs, t);
+        let inner = a.slerp(b, t);
+        outer.slerp(&inner, 2.0 * t * (1.0 - t))
+    }
 }
 
 impl Default for Quat {
@@ -206,6 +285,30 @@ impl Mul<Quat> for Quat {
     }
 }
 
+impl Add for Quat {
+    type Output = Quat;
+
+    fn add(self, rhs: Quat) -> Self::Output {
+        Self::simd(self.simd + rhs.simd)
+    }
+}
+
+impl Mul<f32> for Quat {
+    type Output = Quat;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        Self::simd(self.simd * f32x4::splat(rhs))
+    }
+}
+
+impl Neg for Quat {
+    type Output = Quat;
+
+    fn neg(self) -> Self::Output {
+        Self::simd(-self.simd)
+    }
+}
+
 impl MulAssign<Quat> for Quat {
     fn mul_assign(&mut self, rhs: Quat) {
         *self = *self * rhs;
@@ -223,7 +326,7 @@ impl Mul<Vec3> for Quat {
 
 #[cfg(test)]
 mod test {
-    use std::f32::consts::FRAC_PI_4;
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
 
     use super::*;
 
@@ -238,4 +341,36 @@ mod test {
         assert!(a.get_w() == b.get_w());
         assert!(b.is_normalized());
     }
+
+    fn close(a: &Quat, b: &Quat) -> bool {
+        a.dot(b).abs() > 0.9999
+    }
+
+    #[test]
+    fn slerp() {
+        let axis = Vec3::new(0.0, 1.0, 0.0);
+        let a = Quat::default();
+        let b = Quat::axis_angle(axis, FRAC_PI_2);
+        assert!(close(&a.slerp(&b, 0.0), &a));
+        assert!(close(&a.slerp(&b, 1.0), &b));
+        assert!(close(&a.slerp(&b, 0.5), &Quat::axis_angle(axis, FRAC_PI_4)));
+
+        // The shortest path goes through the identity
+        let c = Quat::axis_angle(axis, -FRAC_PI_4);
+        let d = -Quat::axis_angle(axis, FRAC_PI_4);
+        assert!(close(&c.slerp(&d, 0.5), &Quat::default()));
+    }
+
+    #[test]
+    fn squad() {
+        let axis = Vec3::new(0.0, 0.0, 1.0);
+        let keys = [-FRAC_PI_2, 0.0, FRAC_PI_2, PI].map(|angle| Quat::axis_angle(axis, angle));
+        assert!(close(&keys[2].log().exp(), &keys[2]));
+
+        // Keys at constant angular speed are interpolated like slerp
+        let a = keys[1].get_squad_control(&keys[0], &keys[2]);
+        let b = keys[2].get_squad_control(&keys[1], &keys[3]);
+        let q = keys[1].squad(&a, &b, &keys[2], 0.5);
+        assert!(close(&q, &Quat::axis_angle(axis, FRAC_PI_4)));
+    }
 }
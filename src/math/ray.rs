@@ -4,6 +4,58 @@
 
 use super::*;
 
+/// How the origin and the direction of a ray change when moving to the next pixel,
+/// horizontally and vertically. Useful to estimate the area covered by a pixel
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RayDifferential {
+    pub dodx: Vec3,
+    pub dody: Vec3,
+    pub dddx: Vec3,
+    pub dddy: Vec3,
+}
+
+impl RayDifferential {
+    /// Returns how the hit point at `depth` along `ray` changes when moving to the next pixel,
+    /// assuming the surface is a plane with `normal` around that point
+    pub fn transfer(&self, ray: &Ray, depth: f32, normal: Vec3) -> (Vec3, Vec3) {
+        let d_dot_n = ray.dir.dot(normal);
+        if d_dot_n.abs() < f32::EPSILON {
+            return (Vec3::default(), Vec3::default());
+        }
+        let transfer = |dodx: Vec3, dddx: Vec3| {
+            let dpdx = dodx + dddx * depth;
+            // Slide along the ray to get back onto the plane
+            dpdx - ray.dir * (dpdx.dot(normal) / d_dot_n)
+        };
+        (
+            transfer(self.dodx, self.dddx),
+            transfer(self.dody, self.dddy),
+        )
+    }
+
+    fn scale(&mut self, scale: &Vec3) {
+        for v in [
+            &mut self.dodx,
+            &mut self.dody,
+            &mut self.dddx,
+            &mut self.dddy,
+        ] {
+            v.scale(scale);
+        }
+    }
+
+    fn rotate(&mut self, rotation: &Quat) {
+        for v in [
+            &mut self.dodx,
+            &mut self.dody,
+            &mut self.dddx,
+            &mut self.dddy,
+        ] {
+            v.rotate(rotation);
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Ray {
     pub origin: Point3,
@@ -11,19 +63,69 @@ pub struct Ray {
 
     // Reciprocal of direction
     pub rdir: Vec3,
+
+    /// Only primary rays know the pixel they come from
+    pub differential: Option<RayDifferential>,
 }
 
 impl Ray {
     pub fn new(mut origin: Point3, dir: Vec3) -> Self {
         let rdir = dir.get_reciprocal();
         origin.simd[3] = 1.0;
-        Self { origin, dir, rdir }
+        Self {
+            origin,
+            dir,
+            rdir,
+            differential: None,
+        }
+    }
+
+    /// Offsets a `point` on a surface along its geometric `normal`, so that rays leaving
+    /// from there do not hit the same surface again due to floating point errors.
+    /// The offset grows with the magnitude of the coordinates, as described by
+    /// Wächter and Binder in Ray Tracing Gems, chapter 6
+    pub fn offset_origin(point: &Point3, normal: &Vec3) -> Point3 {
+        const ORIGIN: f32 = 1.0 / 32.0;
+        const FLOAT_SCALE: f32 = 1.0 / 65536.0;
+        const INT_SCALE: f32 = 256.0;
+
+        let offset = |p: f32, n: f32| {
+            if p.abs() < ORIGIN {
+                // Close to the origin, ulps are too small
+                p + FLOAT_SCALE * n
+            } else {
+                // Move by a number of ulps, in the direction of the normal
+                let offset = (INT_SCALE * n) as i32;
+                let offset = if p < 0.0 { -offset } else { offset };
+                f32::from_bits((p.to_bits() as i32).wrapping_add(offset) as u32)
+            }
+        };
+
+        Point3::new(
+            offset(point.get_x(), normal.get_x()),
+            offset(point.get_y(), normal.get_y()),
+            offset(point.get_z(), normal.get_z()),
+        )
+    }
+
+    /// Returns a secondary ray leaving a surface at `point` towards `dir`, with its origin
+    /// offset to the side of the surface it is heading to
+    pub fn spawn(point: &Point3, normal: &Vec3, dir: Vec3) -> Self {
+        let normal = if normal.dot(dir) < 0.0 {
+            -*normal
+        } else {
+            *normal
+        };
+        Self::new(Self::offset_origin(point, &normal), dir)
     }
 
     pub fn scale(&mut self, scale: &Vec3) {
         self.dir.scale(scale);
         self.rdir = self.dir.get_reciprocal();
         self.origin.scale(scale);
+        if let Some(differential) = self.differential.as_mut() {
+            differential.scale(scale);
+        }
 
         assert!(self.origin.simd[3] == 1.0);
     }
@@ -36,10 +138,12 @@ impl Ray {
 
     pub fn rotate(&mut self, rotation: &Quat) {
         self.origin.rotate(rotation);
+        self.origin.simd[3] = 1.0;
         self.dir.rotate(rotation);
         self.rdir = self.dir.get_reciprocal();
-        self.origin.rotate(rotation);
-        self.origin.simd[3] = 1.0;
+        if let Some(differential) = self.differential.as_mut() {
+            differential.rotate(rotation);
+        }
     }
 }
 
@@ -49,6 +153,34 @@ impl Default for Ray {
     }
 }
 
+/// Attributes of a surface at a hit point in world space, interpolated from its vertices
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShadingFrame {
+    /// Interpolated normal, before applying any normal texture
+    pub normal: Vec3,
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+
+    /// Normal of the actual surface, such as the plane of a triangle
+    pub geometric_normal: Vec3,
+
+    /// Texture coordinates
+    pub uv: Vec2,
+    /// Second set of texture coordinates
+    pub uv1: Vec2,
+
+    /// Cosine of the angle between the normal and the direction towards the viewer,
+    /// which is 1 until the BVH knows the ray of the hit
+    pub n_dot_v: f32,
+}
+
+impl ShadingFrame {
+    /// Returns both sets of texture coordinates, as expected by materials
+    pub fn get_uvs(&self) -> [Vec2; 2] {
+        [self.uv, self.uv1]
+    }
+}
+
 pub struct Hit {
     pub depth: f32,
     pub point: Point3,
@@ -56,17 +188,41 @@ pub struct Hit {
     /// Barycentric coordinates expressing the hit point in terms of the primitive.
     /// Useful to interpolate vertex data of such a primitive
     pub uv: Vec2,
+
+    /// How the hit point changes when moving to the next pixel, which is zero
+    /// when the ray has no differential
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
+
+    /// Filled in by the BVH for the closest hit of shading queries,
+    /// see `Bvh::intersects_iter()` and `BvhPrimitive::get_shading_frame()`
+    pub frame: Option<ShadingFrame>,
 }
 
 impl Hit {
     pub fn new(depth: f32, point: Point3, uv: Vec2) -> Self {
-        Self { depth, point, uv }
+        Self {
+            depth,
+            point,
+            uv,
+            dpdx: Vec3::default(),
+            dpdy: Vec3::default(),
+            frame: None,
+        }
+    }
+
+    /// Returns the shading frame, which should have been filled in already
+    pub fn frame(&self) -> &ShadingFrame {
+        self.frame
+            .as_ref()
+            .expect("Hit without a shading frame, see `Bvh::intersects_iter()`")
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{BvhTriangle, Vertex};
 
     #[test]
     fn rotate() {
@@ -75,5 +231,70 @@ mod test {
         ray.rotate(&rot);
         println!("{:?}", ray.dir);
         assert!(ray.dir.close(&Vec3::new(0.0, -0.707, -0.707)));
+
+        // Origins are rotated once like directions
+        let mut ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, 1.0));
+        ray.rotate(&rot);
+        assert!(Vec3::from(ray.origin).close(&ray.dir));
+    }
+
+    #[test]
+    fn differential() {
+        let mut ray = Ray::new(Point3::new(0.0, 0.0, 2.0), Vec3::new(0.0, 0.0, -1.0));
+        ray.differential = Some(RayDifferential {
+            dddx: Vec3::new(0.5, 0.0, 0.0),
+            dddy: Vec3::new(0.0, 0.25, 0.0),
+            ..Default::default()
+        });
+
+        // Facing plane
+        let differential = ray.differential.unwrap();
+        let (dpdx, dpdy) = differential.transfer(&ray, 2.0, Vec3::new(0.0, 0.0, 1.0));
+        assert!(dpdx.close(&Vec3::new(1.0, 0.0, 0.0)));
+        assert!(dpdy.close(&Vec3::new(0.0, 0.5, 0.0)));
+
+        // Footprint stretches on a tilted plane
+        let normal = Vec3::new(1.0, 0.0, 1.0).get_normalized();
+        let (dpdx, _) = differential.transfer(&ray, 2.0, normal);
+        assert!(dpdx.close(&Vec3::new(1.0, 0.0, -1.0)));
+
+        ray.rotate(&Quat::axis_angle(
+            Vec3::new(0.0, 0.0, 1.0),
+            std::f32::consts::FRAC_PI_2,
+        ));
+        assert!(ray
+            .differential
+            .unwrap()
+            .dddx
+            .close(&Vec3::new(0.0, 0.5, 0.0)));
+    }
+
+    #[test]
+    fn spawn() {
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+
+        // A fixed bias gets lost far away from the origin
+        let point = Point3::new(1e5, 1e5, -1e5);
+        assert_eq!((point + normal * 1e-3).get_y(), point.get_y());
+
+        let ray = Ray::spawn(&point, &normal, Vec3::new(1.0, 1.0, 0.0).get_normalized());
+        assert!(ray.origin.get_y() > point.get_y());
+        assert_eq!(ray.origin.get_x(), point.get_x());
+        let ray = Ray::spawn(&point, &normal, Vec3::new(0.0, -1.0, 0.0));
+        assert!(ray.origin.get_y() < point.get_y());
+
+        // Close to the origin the offset is tiny
+        let point = Point3::new(0.0, 0.0, 0.0);
+        let ray = Ray::spawn(&point, &normal, normal);
+        assert!(ray.origin.get_y() > 0.0 && ray.origin.get_y() < 1e-4);
+
+        // Large triangles far from the origin do not shadow themselves
+        let a = Vertex::new(-1e4, 1e4, -1e4);
+        let b = Vertex::new(1e4, 1e4, 1e4);
+        let c = Vertex::new(1e4, 1e4, -1e4);
+        let triangle = BvhTriangle::new(a, b, c);
+        let point = Point3::new(1234.567, 1e4, -2345.678);
+        let ray = Ray::spawn(&point, &triangle.get_geometric_normal(), normal);
+        assert!(triangle.intersects(&ray).is_none());
     }
 }
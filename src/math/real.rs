@@ -0,0 +1,138 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Scalar used by intersections, which is `f64` with the `f64` feature. Geometry is
+//! stored with `f32`, but intersecting rays far from the origin, as with planetary-scale
+//! coordinates, loses most of the precision in the differences of large numbers, which
+//! `f64` keeps.
+
+use std::ops::{Add, Mul, Sub};
+
+use crate::*;
+
+#[cfg(not(feature = "f64"))]
+pub type Real = f32;
+
+#[cfg(feature = "f64")]
+pub type Real = f64;
+
+pub fn to_real(value: f32) -> Real {
+    Real::from(value)
+}
+
+#[cfg(not(feature = "f64"))]
+pub fn from_real(value: Real) -> f32 {
+    value
+}
+
+#[cfg(feature = "f64")]
+pub fn from_real(value: Real) -> f32 {
+    value as f32
+}
+
+/// Vector of `Real`s, for the intermediate results of intersections
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RealVec3 {
+    pub x: Real,
+    pub y: Real,
+    pub z: Real,
+}
+
+impl RealVec3 {
+    pub fn new(x: Real, y: Real, z: Real) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn dot(&self, rhs: &RealVec3) -> Real {
+        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+
+    pub fn cross(&self, rhs: &RealVec3) -> Self {
+        Self::new(
+            self.y * rhs.z - self.z * rhs.y,
+            self.z * rhs.x - self.x * rhs.z,
+            self.x * rhs.y - self.y * rhs.x,
+        )
+    }
+}
+
+impl From<Vec3> for RealVec3 {
+    fn from(vec: Vec3) -> Self {
+        Self::new(
+            to_real(vec.get_x()),
+            to_real(vec.get_y()),
+            to_real(vec.get_z()),
+        )
+    }
+}
+
+impl From<Point3> for RealVec3 {
+    fn from(point: Point3) -> Self {
+        Self::new(
+            to_real(point.get_x()),
+            to_real(point.get_y()),
+            to_real(point.get_z()),
+        )
+    }
+}
+
+impl From<RealVec3> for Point3 {
+    fn from(vec: RealVec3) -> Self {
+        Point3::new(from_real(vec.x), from_real(vec.y), from_real(vec.z))
+    }
+}
+
+impl Add for RealVec3 {
+    type Output = RealVec3;
+
+    fn add(self, rhs: RealVec3) -> Self::Output {
+        Self::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+    }
+}
+
+impl Sub for RealVec3 {
+    type Output = RealVec3;
+
+    fn sub(self, rhs: RealVec3) -> Self::Output {
+        Self::new(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+    }
+}
+
+impl Mul<Real> for RealVec3 {
+    type Output = RealVec3;
+
+    fn mul(self, rhs: Real) -> Self::Output {
+        Self::new(self.x * rhs, self.y * rhs, self.z * rhs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn real() {
+        let a = RealVec3::from(Vec3::new(1.0, 0.0, 0.0));
+        let b = RealVec3::from(Point3::new(0.0, 1.0, 0.0));
+        assert_eq!(a.cross(&b), RealVec3::new(0.0, 0.0, 1.0));
+        assert_eq!(a.dot(&b), 0.0);
+        assert_eq!(Point3::from(a + b * 2.0), Point3::new(1.0, 2.0, 0.0));
+        assert_eq!(from_real(to_real(0.5)), 0.5);
+    }
+
+    #[cfg(feature = "f64")]
+    #[test]
+    fn planet() {
+        // Earth-sized sphere hit from a low orbit
+        let radius = 6.371e6;
+        let sphere = BvhSphere::new(Point3::default(), radius);
+        let ray = Ray::new(
+            Point3::new(0.0, 0.0, radius + 400e3),
+            Vec3::new(0.0, 0.001, -1.0).get_normalized(),
+        );
+        let hit = sphere.intersects(&ray).unwrap();
+        let distance = Vec3::from(hit.point).len();
+        assert!((distance - radius).abs() < 1.0);
+    }
+}
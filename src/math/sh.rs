@@ -0,0 +1,112 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::f32::consts::PI;
+
+use super::*;
+
+/// Number of coefficients of spherical harmonics up to band L2
+pub const SH_L2_COUNT: usize = 9;
+
+/// Evaluates the real spherical harmonics basis up to band L2 for a normalized direction
+pub fn sh_l2_basis(dir: &Vec3) -> [f32; SH_L2_COUNT] {
+    let (x, y, z) = (dir.get_x(), dir.get_y(), dir.get_z());
+    [
+        0.282095,
+        0.488603 * y,
+        0.488603 * z,
+        0.488603 * x,
+        1.092548 * x * y,
+        1.092548 * y * z,
+        0.315392 * (3.0 * z * z - 1.0),
+        1.092548 * x * z,
+        0.546274 * (x * x - y * y),
+    ]
+}
+
+/// Spherical harmonics up to band L2 of a function returning colors,
+/// such as the radiance coming from every direction around a point.
+#[derive(Clone, Debug)]
+pub struct ShL2 {
+    pub coefficients: [Color; SH_L2_COUNT],
+}
+
+impl Default for ShL2 {
+    fn default() -> Self {
+        Self {
+            coefficients: [Color::new(0.0, 0.0, 0.0, 1.0); SH_L2_COUNT],
+        }
+    }
+}
+
+impl ShL2 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accumulates a sample of the function along `dir`, weighted by `weight`
+    /// which is usually the inverse of the sample PDF divided by the sample count
+    pub fn add_sample(&mut self, dir: &Vec3, value: Color, weight: f32) {
+        let basis = sh_l2_basis(dir);
+        for (coefficient, b) in self.coefficients.iter_mut().zip(basis) {
+            *coefficient += value * (b * weight);
+        }
+    }
+
+    /// Reconstructs the function along `dir`
+    pub fn evaluate(&self, dir: &Vec3) -> Color {
+        let basis = sh_l2_basis(dir);
+        let mut ret = Color::new(0.0, 0.0, 0.0, 1.0);
+        for (coefficient, b) in self.coefficients.iter().zip(basis) {
+            ret += coefficient * b;
+        }
+        ret
+    }
+
+    /// Returns the irradiance reaching a surface with normal `n`, assuming these
+    /// are the coefficients of the incoming radiance. The cosine lobe is applied
+    /// as described in "An Efficient Representation for Irradiance Environment Maps"
+    pub fn evaluate_irradiance(&self, n: &Vec3) -> Color {
+        const BAND_FACTORS: [f32; 3] = [PI, 2.0 * PI / 3.0, PI / 4.0];
+        let basis = sh_l2_basis(n);
+        let mut ret = Color::new(0.0, 0.0, 0.0, 1.0);
+        for (i, (coefficient, b)) in self.coefficients.iter().zip(basis).enumerate() {
+            let band = match i {
+                0 => 0,
+                1..=3 => 1,
+                _ => 2,
+            };
+            ret += coefficient * (b * BAND_FACTORS[band]);
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn constant() {
+        // Project a constant white radiance with a few axis aligned samples
+        let mut sh = ShL2::new();
+        let dirs = [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        ];
+        let weight = 4.0 * PI / dirs.len() as f32;
+        for dir in &dirs {
+            sh.add_sample(dir, Color::white(), weight);
+        }
+
+        let n = Vec3::new(0.0, 1.0, 0.0);
+        assert!((sh.evaluate(&n).r - 1.0).abs() < 1e-3);
+        // Uniform radiance L gives irradiance PI * L
+        assert!((sh.evaluate_irradiance(&n).r - PI).abs() < 1e-3);
+    }
+}
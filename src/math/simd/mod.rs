@@ -0,0 +1,15 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! SIMD vectors backing the math types. With the `simd` feature they come from the
+//! portable SIMD module of the standard library, which requires nightly Rust, while
+//! without it a scalar implementation of the same interface builds on stable Rust.
+
+#[cfg(feature = "simd")]
+pub use std::simd::{f32x4, mask32x4, num::SimdFloat, simd_swizzle, Select, Simd, StdFloat};
+
+#[cfg(not(feature = "simd"))]
+pub mod scalar;
+#[cfg(not(feature = "simd"))]
+pub use scalar::*;
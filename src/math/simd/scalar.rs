@@ -0,0 +1,230 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Scalar fallback for the subset of `std::simd` used by the math types,
+//! where every operation loops over the lanes of an array.
+
+use std::{
+    ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign},
+    slice::SliceIndex,
+};
+
+#[repr(C, align(16))]
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Simd<T, const N: usize>([T; N]);
+
+#[allow(non_camel_case_types)]
+pub type f32x4 = Simd<f32, 4>;
+
+#[allow(non_camel_case_types)]
+pub type mask32x4 = Mask<4>;
+
+impl<T: Copy, const N: usize> Simd<T, N> {
+    pub const fn from_array(array: [T; N]) -> Self {
+        Self(array)
+    }
+
+    pub const fn splat(value: T) -> Self {
+        Self([value; N])
+    }
+
+    /// Panics if `slice` is shorter than `N`
+    pub fn from_slice(slice: &[T]) -> Self {
+        let mut ret = [slice[0]; N];
+        ret.copy_from_slice(&slice[..N]);
+        Self(ret)
+    }
+
+    pub fn copy_to_slice(self, slice: &mut [T]) {
+        slice[..N].copy_from_slice(&self.0);
+    }
+
+    pub const fn to_array(self) -> [T; N] {
+        self.0
+    }
+
+    pub const fn as_array(&self) -> &[T; N] {
+        &self.0
+    }
+}
+
+impl<T: Copy + Default, const N: usize> Default for Simd<T, N> {
+    fn default() -> Self {
+        Self::splat(T::default())
+    }
+}
+
+impl<T, I: SliceIndex<[T]>, const N: usize> Index<I> for Simd<T, N> {
+    type Output = I::Output;
+
+    fn index(&self, index: I) -> &Self::Output {
+        &self.0[index]
+    }
+}
+
+impl<T, I: SliceIndex<[T]>, const N: usize> IndexMut<I> for Simd<T, N> {
+    fn index_mut(&mut self, index: I) -> &mut Self::Output {
+        &mut self.0[index]
+    }
+}
+
+macro_rules! impl_op {
+    ($trait:ident, $fn:ident, $assign_trait:ident, $assign_fn:ident, $op:tt) => {
+        impl<const N: usize> $trait for Simd<f32, N> {
+            type Output = Self;
+
+            fn $fn(mut self, rhs: Self) -> Self {
+                self.$assign_fn(rhs);
+                self
+            }
+        }
+
+        impl<const N: usize> $trait<Simd<f32, N>> for &Simd<f32, N> {
+            type Output = Simd<f32, N>;
+
+            fn $fn(self, rhs: Simd<f32, N>) -> Simd<f32, N> {
+                (*self).$fn(rhs)
+            }
+        }
+
+        impl<const N: usize> $trait<&Simd<f32, N>> for Simd<f32, N> {
+            type Output = Self;
+
+            fn $fn(self, rhs: &Self) -> Self {
+                self.$fn(*rhs)
+            }
+        }
+
+        impl<const N: usize> $trait<&Simd<f32, N>> for &Simd<f32, N> {
+            type Output = Simd<f32, N>;
+
+            fn $fn(self, rhs: &Simd<f32, N>) -> Simd<f32, N> {
+                (*self).$fn(*rhs)
+            }
+        }
+
+        impl<const N: usize> $assign_trait for Simd<f32, N> {
+            fn $assign_fn(&mut self, rhs: Self) {
+                for (lhs, rhs) in self.0.iter_mut().zip(rhs.0) {
+                    *lhs = *lhs $op rhs;
+                }
+            }
+        }
+    };
+}
+
+impl_op!(Add, add, AddAssign, add_assign, +);
+impl_op!(Sub, sub, SubAssign, sub_assign, -);
+impl_op!(Mul, mul, MulAssign, mul_assign, *);
+impl_op!(Div, div, DivAssign, div_assign, /);
+
+impl<const N: usize> Neg for Simd<f32, N> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(self.0.map(|value| -value))
+    }
+}
+
+pub trait SimdFloat {
+    fn simd_min(self, other: Self) -> Self;
+    fn simd_max(self, other: Self) -> Self;
+    fn reduce_sum(self) -> f32;
+    fn abs(self) -> Self;
+}
+
+impl<const N: usize> SimdFloat for Simd<f32, N> {
+    fn simd_min(mut self, other: Self) -> Self {
+        for (lhs, rhs) in self.0.iter_mut().zip(other.0) {
+            *lhs = lhs.min(rhs);
+        }
+        self
+    }
+
+    fn simd_max(mut self, other: Self) -> Self {
+        for (lhs, rhs) in self.0.iter_mut().zip(other.0) {
+            *lhs = lhs.max(rhs);
+        }
+        self
+    }
+
+    fn reduce_sum(self) -> f32 {
+        self.0.iter().sum()
+    }
+
+    fn abs(self) -> Self {
+        Self(self.0.map(f32::abs))
+    }
+}
+
+pub trait StdFloat {
+    /// Returns `self * a + b` with a single rounding
+    fn mul_add(self, a: Self, b: Self) -> Self;
+}
+
+impl<const N: usize> StdFloat for Simd<f32, N> {
+    fn mul_add(mut self, a: Self, b: Self) -> Self {
+        for ((lhs, a), b) in self.0.iter_mut().zip(a.0).zip(b.0) {
+            *lhs = lhs.mul_add(a, b);
+        }
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mask<const N: usize>([bool; N]);
+
+impl<const N: usize> Mask<N> {
+    pub const fn from_array(array: [bool; N]) -> Self {
+        Self(array)
+    }
+}
+
+pub trait Select<T> {
+    /// Picks lanes of `true_values` where the mask is set, otherwise of `false_values`
+    fn select(self, true_values: T, false_values: T) -> T;
+}
+
+impl<const N: usize> Select<Simd<f32, N>> for Mask<N> {
+    fn select(self, mut true_values: Simd<f32, N>, false_values: Simd<f32, N>) -> Simd<f32, N> {
+        for ((value, set), false_value) in true_values.0.iter_mut().zip(self.0).zip(false_values.0)
+        {
+            if !set {
+                *value = false_value;
+            }
+        }
+        true_values
+    }
+}
+
+/// Rearranges the lanes of a vector by the array of indices
+macro_rules! simd_swizzle {
+    ($vector:expr, $indices:expr) => {{
+        let vector = $vector;
+        let indices = $indices;
+        $crate::math::simd::Simd::from_array(indices.map(|i: usize| vector[i]))
+    }};
+}
+
+pub(crate) use simd_swizzle;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ops() {
+        let a = f32x4::from_array([1.0, -2.0, 3.0, 0.0]);
+        let b = f32x4::splat(2.0);
+        assert_eq!((a * b + b).to_array(), [4.0, -2.0, 8.0, 2.0]);
+        assert_eq!(a.abs().reduce_sum(), 6.0);
+        assert_eq!(a.simd_min(b).to_array(), [1.0, -2.0, 2.0, 0.0]);
+        let mask = mask32x4::from_array([true, true, true, false]);
+        assert_eq!(mask.select(a, b).to_array(), [1.0, -2.0, 3.0, 2.0]);
+        assert_eq!(
+            simd_swizzle!(a, [1, 2, 0, 3]).to_array(),
+            [-2.0, 3.0, 1.0, 0.0]
+        );
+    }
+}
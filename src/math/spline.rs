@@ -0,0 +1,61 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Cubic interpolation of keyframes, as glTF animations with cubic spline samplers do.
+
+use std::ops::{Add, Mul};
+
+/// Cubic Hermite spline from `start` to `end` at `t` between 0 and 1, where the tangents
+/// are the derivatives of the value over time, and `duration` is the time between the
+/// two keys. Rotations need to be normalized afterwards
+pub fn cubic_hermite<T>(start: T, out_tangent: T, end: T, in_tangent: T, t: f32, duration: f32) -> T
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
+    start * (2.0 * t3 - 3.0 * t2 + 1.0)
+        + out_tangent * (duration * (t3 - 2.0 * t2 + t))
+        + end * (-2.0 * t3 + 3.0 * t2)
+        + in_tangent * (duration * (t3 - t2))
+}
+
+/// Catmull-Rom spline from `b` to `c` at `t` between 0 and 1, passing through every
+/// key, where `a` and `d` are the keys before and after them. Keys are expected to be
+/// evenly spaced in time, which makes it useful for smooth camera moves
+pub fn catmull_rom<T>(a: T, b: T, c: T, d: T, t: f32) -> T
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    // Tangents over a duration of 1 are half the difference of the neighbours
+    let out_tangent = (c + a * -1.0) * 0.5;
+    let in_tangent = (d + b * -1.0) * 0.5;
+    cubic_hermite(b, out_tangent, c, in_tangent, t, 1.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn hermite() {
+        let a = Vec3::new(0.0, 0.0, 0.0);
+        let b = Vec3::new(2.0, 0.0, 0.0);
+        let tangent = Vec3::new(1.0, 0.0, 0.0);
+        assert_eq!(cubic_hermite(a, tangent, b, tangent, 0.0, 2.0), a);
+        assert_eq!(cubic_hermite(a, tangent, b, tangent, 1.0, 2.0), b);
+        // Constant speed makes it linear
+        assert_eq!(
+            cubic_hermite(a, tangent, b, tangent, 0.5, 2.0),
+            Vec3::new(1.0, 0.0, 0.0)
+        );
+
+        let keys = [0.0, 1.0, 4.0, 9.0];
+        assert_eq!(catmull_rom(keys[0], keys[1], keys[2], keys[3], 0.0), 1.0);
+        assert_eq!(catmull_rom(keys[0], keys[1], keys[2], keys[3], 1.0), 4.0);
+        let middle = catmull_rom(keys[0], keys[1], keys[2], keys[3], 0.5);
+        assert!(middle > 2.0 && middle < 2.5);
+    }
+}
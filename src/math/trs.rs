@@ -2,14 +2,11 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
-use std::{
-    ops::{Div, Mul},
-    simd::f32x4,
-};
+use std::ops::{Div, Mul};
 
 use crate::Ray;
 
-use super::*;
+use super::{simd::f32x4, *};
 
 pub struct TrsBuilder {
     translation: Vec3,
@@ -104,14 +101,21 @@ impl Trs {
     pub fn get_translation(&self) -> Vec3 {
         self.rotation * self.translation
     }
+
+    /// Interpolates from `self` to `rhs`, linearly for translation and scale,
+    /// and spherically for rotation
+    pub fn lerp(&self, rhs: &Trs, t: f32) -> Self {
+        Self::new(
+            self.translation * (1.0 - t) + rhs.translation * t,
+            self.rotation.slerp(&rhs.rotation, t),
+            self.scale * (1.0 - t) + rhs.scale * t,
+        )
+    }
 }
 
 impl From<Mat4> for Trs {
     fn from(mat: Mat4) -> Self {
-        let translation = mat.get_translation();
-        let rotation = mat.get_rotation();
-        let scale = Vec3::new(1.0, 1.0, 1.0);
-        Self::new(translation, rotation, scale)
+        mat.decompose()
     }
 }
 
@@ -2,15 +2,12 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
-use core::simd::*;
-use std::{
-    ops::{Add, AddAssign, Div, Index, Mul, MulAssign, Neg, Sub, SubAssign},
-    simd::StdFloat,
-};
+use std::ops::{Add, AddAssign, Div, Index, Mul, MulAssign, Neg, Sub, SubAssign};
 
-use num::SimdFloat;
 use num_traits::MulAdd;
 
+use super::simd::{f32x4, mask32x4, simd_swizzle, Select, SimdFloat, StdFloat};
+
 use crate::{Color, Point3, Quat};
 
 use crate::Dot;
@@ -31,7 +28,7 @@ pub struct Vec3 {
 }
 
 impl Vec3 {
-    pub fn new(x: f32, y: f32, z: f32) -> Vec3 {
+    pub const fn new(x: f32, y: f32, z: f32) -> Vec3 {
         Self {
             simd: f32x4::from_array([x, y, z, 0.0]),
         }
@@ -151,6 +148,21 @@ impl Vec3 {
         Self::simd(one / den)
     }
 
+    /// Returns two vectors which form an orthonormal basis together with this normalized vector.
+    /// See [Building an Orthonormal Basis, Revisited](https://jcgt.org/published/0006/01/01/)
+    pub fn get_orthonormal_basis(&self) -> (Vec3, Vec3) {
+        let sign = 1.0f32.copysign(self.get_z());
+        let a = -1.0 / (sign + self.get_z());
+        let b = self.get_x() * self.get_y() * a;
+        let t = Vec3::new(
+            1.0 + sign * self.get_x() * self.get_x() * a,
+            sign * b,
+            -sign * self.get_x(),
+        );
+        let b = Vec3::new(b, sign + self.get_y() * self.get_y() * a, -self.get_y());
+        (t, b)
+    }
+
     /// Returns the reflection of this vector around a surface normal
     pub fn reflect(&self, normal: &Vec3) -> Self {
         self - 2.0 * self.dot(normal) * normal
@@ -442,5 +454,20 @@ mod test {
             v.rotate(&rot);
             assert!(v.close(&Vec3::new(0.0, 0.707, 0.707)));
         }
+
+        #[test]
+        fn orthonormal_basis() {
+            for n in [
+                Vec3::new(0.0, 0.0, 1.0),
+                Vec3::new(0.0, 0.0, -1.0),
+                Vec3::new(1.0, 2.0, 3.0).get_normalized(),
+            ] {
+                let (t, b) = n.get_orthonormal_basis();
+                assert!(t.is_normalized() && b.is_normalized());
+                assert!(t.dot(n).abs() < 1e-5);
+                assert!(b.dot(n).abs() < 1e-5);
+                assert!(t.dot(b).abs() < 1e-5);
+            }
+        }
     }
 }
@@ -0,0 +1,25 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Batched operations over slices of values, processed by SIMD vectors as wide as the
+//! target allows: 8 lanes with AVX2, 4 lanes otherwise, and 1 lane on wasm, where SIMD
+//! instructions are not guaranteed. The width is selected at compile time, hence AVX2
+//! needs `-C target-feature=+avx2` or `-C target-cpu=native`.
+
+use super::simd::Simd;
+
+#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+pub const LANES: usize = 8;
+
+#[cfg(target_arch = "wasm32")]
+pub const LANES: usize = 1;
+
+#[cfg(not(any(
+    all(target_arch = "x86_64", target_feature = "avx2"),
+    target_arch = "wasm32"
+)))]
+pub const LANES: usize = 4;
+
+/// SIMD vector of `LANES` values
+pub type Wide = Simd<f32, LANES>;
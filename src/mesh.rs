@@ -51,6 +51,11 @@ impl PrimitiveBuilder {
         }
         self
     }
+    pub fn triangles(mut self, triangles: Triangles) -> Self {
+        self.geometry = Geometry::Triangles(triangles);
+        self
+    }
+
     pub fn sphere(mut self, center: Point3, radius: f32) -> Self {
         match &mut self.geometry {
             Geometry::Sphere(sphere) => {
@@ -65,6 +70,31 @@ impl PrimitiveBuilder {
         self
     }
 
+    pub fn point_cloud(mut self, point_cloud: PointCloud) -> Self {
+        self.geometry = Geometry::PointCloud(point_cloud);
+        self
+    }
+
+    pub fn curves(mut self, curves: Curves) -> Self {
+        self.geometry = Geometry::Curves(curves);
+        self
+    }
+
+    pub fn heightfield(mut self, heightfield: Heightfield) -> Self {
+        self.geometry = Geometry::Heightfield(heightfield);
+        self
+    }
+
+    pub fn billboards(mut self, billboards: Billboards) -> Self {
+        self.geometry = Geometry::Billboards(billboards);
+        self
+    }
+
+    pub fn sdf(mut self, sdf: Sdf) -> Self {
+        self.geometry = Geometry::Sdf(sdf);
+        self
+    }
+
     pub fn material(mut self, material: Handle<Material>) -> Self {
         self.material = Some(material);
         self
@@ -121,6 +151,11 @@ impl Primitive {
         match &self.geometry {
             Geometry::Triangles(triangles) => triangles.primitives(node, material, model),
             Geometry::Sphere(sphere) => sphere.primitives(node, material),
+            Geometry::PointCloud(point_cloud) => point_cloud.primitives(node, material),
+            Geometry::Curves(curves) => curves.primitives(node, material, model),
+            Geometry::Heightfield(heightfield) => heightfield.primitives(node, material),
+            Geometry::Billboards(billboards) => billboards.primitives(node, material, model),
+            Geometry::Sdf(sdf) => sdf.primitives(node, material),
         }
     }
 }
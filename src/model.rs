@@ -3,8 +3,8 @@
 // SPDX-License-Identifier: MIT
 
 use std::{
-    collections::HashMap,
-    error::Error,
+    borrow::Cow,
+    collections::{HashMap, HashSet},
     ops::Deref,
     path::{Path, PathBuf},
 };
@@ -27,6 +27,15 @@ fn data_type_as_size(data_type: gltf::accessor::DataType) -> usize {
     }
 }
 
+/// Returns the image of the `KHR_texture_basisu` extension of a texture, if any
+fn get_basisu_source(gtexture: &gltf::Texture) -> Option<usize> {
+    let extension = gtexture.extension_value("KHR_texture_basisu")?;
+    extension
+        .get("source")?
+        .as_u64()
+        .map(|source| source as usize)
+}
+
 fn dimensions_as_size(dimensions: gltf::accessor::Dimensions) -> usize {
     match dimensions {
         gltf::accessor::Dimensions::Scalar => 1,
@@ -49,9 +58,186 @@ fn get_stride(accessor: &gltf::Accessor) -> usize {
     data_type_as_size(accessor.data_type()) * dimensions_as_size(accessor.dimensions())
 }
 
+/// Returns the number of components of the vertex attributes of `accessor`
+fn get_vector_size(accessor: &gltf::Accessor) -> Result<usize, RaycaError> {
+    match accessor.dimensions() {
+        gltf::accessor::Dimensions::Vec2 => Ok(2),
+        gltf::accessor::Dimensions::Vec3 => Ok(3),
+        gltf::accessor::Dimensions::Vec4 => Ok(4),
+        dimensions => Err(RaycaError::Unsupported(format!(
+            "glTF vertex attributes of {:?}",
+            dimensions
+        ))),
+    }
+}
+
+/// Returns an error unless `accessor` has vectors of `len` components
+fn expect_vector_size(accessor: &gltf::Accessor, len: usize) -> Result<(), RaycaError> {
+    let size = get_vector_size(accessor)?;
+    if size != len {
+        return Err(RaycaError::InvalidGeometry(format!(
+            "glTF accessor {} of {} components, while {} are expected",
+            accessor.index(),
+            size,
+            len
+        )));
+    }
+    Ok(())
+}
+
+/// Radius of points, and width of lines, which glTF does not specify
+const POINT_SIZE: f32 = 0.01;
+
+/// Returns the pairs of indices of the segments of `Lines`, `LineStrip`, or `LineLoop`
+fn get_line_segments(mode: gltf::mesh::Mode, indices: &[u32]) -> Vec<[u32; 2]> {
+    let mut ret = match mode {
+        gltf::mesh::Mode::Lines => indices
+            .chunks_exact(2)
+            .map(|pair| [pair[0], pair[1]])
+            .collect(),
+        _ => indices
+            .windows(2)
+            .map(|pair| [pair[0], pair[1]])
+            .collect::<Vec<_>>(),
+    };
+    if mode == gltf::mesh::Mode::LineLoop && indices.len() > 2 {
+        ret.push([indices[indices.len() - 1], indices[0]]);
+    }
+    ret
+}
+
+/// Returns the triangle list of a triangle strip, where every other triangle
+/// swaps its first two vertices to keep the winding. Degenerate triangles,
+/// which strips use to restart, are skipped
+fn triangulate_strip(indices: &[u32]) -> Vec<u32> {
+    let mut ret = vec![];
+    for (i, window) in indices.windows(3).enumerate() {
+        let [a, b, c] = [window[0], window[1], window[2]];
+        if a == b || b == c || a == c {
+            continue;
+        }
+        if i % 2 == 0 {
+            ret.extend([a, b, c]);
+        } else {
+            ret.extend([b, a, c]);
+        }
+    }
+    ret
+}
+
+/// Returns the triangle list of a triangle fan around its first vertex
+fn triangulate_fan(indices: &[u32]) -> Vec<u32> {
+    let mut ret = vec![];
+    if let Some(&center) = indices.first() {
+        for window in indices[1..].windows(2) {
+            ret.extend([window[0], window[1], center]);
+        }
+    }
+    ret
+}
+
+/// Returns the `KHR_texture_transform` of a texture reference, if any,
+/// together with the set of texture coordinates it uses
+fn get_texture_transform(info: &gltf::texture::Info) -> TextureTransform {
+    let mut ret = match info.texture_transform() {
+        Some(transform) => {
+            let offset = transform.offset();
+            let scale = transform.scale();
+            let mut ret = TextureTransform::new(
+                Vec2::new(offset[0], offset[1]),
+                transform.rotation(),
+                Vec2::new(scale[0], scale[1]),
+            );
+            ret.tex_coord = transform.tex_coord().unwrap_or(info.tex_coord()) as usize;
+            return ret;
+        }
+        None => TextureTransform::IDENTITY,
+    };
+    ret.tex_coord = info.tex_coord() as usize;
+    ret
+}
+
+/// Same as `get_texture_transform()` for normal and occlusion textures,
+/// which expose the extension only as raw JSON
+fn get_raw_texture_transform(
+    extension: Option<&gltf::json::Value>,
+    tex_coord: u32,
+) -> Result<TextureTransform, RaycaError> {
+    let mut ret = TextureTransform::IDENTITY;
+    ret.tex_coord = tex_coord as usize;
+    if let Some(value) = extension {
+        let transform: gltf::json::extensions::texture::TextureTransform =
+            gltf::json::deserialize::from_value(value.clone())?;
+        ret.offset = Vec2::new(transform.offset.0[0], transform.offset.0[1]);
+        ret.rotation = transform.rotation.0;
+        ret.scale = Vec2::new(transform.scale.0[0], transform.scale.0[1]);
+        if let Some(tex_coord) = transform.tex_coord {
+            ret.tex_coord = tex_coord as usize;
+        }
+    }
+    Ok(ret)
+}
+
+/// Name of the glTF node extension describing analytic primitives and lights, which glTF
+/// meshes can not represent. Spheres are added to the mesh of the node, with optional
+/// materials, while a quad light becomes the light of the node:
+/// `{ "spheres": [{ "center": [0, 0, 0], "radius": 1, "material": 0 }],
+/// "quadLight": { "width": 1, "height": 1, "intensity": 1 } }`
+pub const RAYCA_PRIMITIVES: &str = "RAYCA_primitives";
+
+/// Returns the properties of glTF `extras`, where extras which are not
+/// a JSON object are kept as a whole under an empty key
+fn get_extras(extras: &gltf::json::Extras) -> Result<Extras, RaycaError> {
+    let mut ret = Extras::new();
+    if let Some(extras) = extras {
+        let value: gltf::json::Value = gltf::json::deserialize::from_str(extras.get())?;
+        match value {
+            gltf::json::Value::Object(properties) => {
+                for (key, value) in properties {
+                    ret.insert(key, value.to_string());
+                }
+            }
+            value => {
+                ret.insert(String::new(), value.to_string());
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// Returns the number at `key` of a JSON object, or `default` when missing
+fn get_json_f32(value: &gltf::json::Value, key: &str, default: f32) -> Result<f32, RaycaError> {
+    match value.get(key) {
+        None => Ok(default),
+        Some(number) => number
+            .as_f64()
+            .map(|number| number as f32)
+            .ok_or_else(|| RaycaError::Parse(format!("{} {} as a number", RAYCA_PRIMITIVES, key))),
+    }
+}
+
+/// Returns the array of three numbers at `key` of a JSON object
+fn get_json_vec3(value: &gltf::json::Value, key: &str) -> Result<Vec3, RaycaError> {
+    let numbers = value
+        .get(key)
+        .and_then(|array| array.as_array())
+        .filter(|array| array.len() == 3)
+        .and_then(|array| array.iter().map(|n| n.as_f64()).collect::<Option<Vec<_>>>())
+        .ok_or_else(|| {
+            RaycaError::Parse(format!("{} {} as three numbers", RAYCA_PRIMITIVES, key))
+        })?;
+    Ok(Vec3::new(
+        numbers[0] as f32,
+        numbers[1] as f32,
+        numbers[2] as f32,
+    ))
+}
+
 #[derive(Default)]
 pub struct ModelBuilder {
     uri_buffers: Vec<Vec<u8>>,
+    /// Images which could not be decoded, whose textures use their fallback source
+    undecoded_images: Vec<usize>,
     parent_dir: Option<PathBuf>,
     gltf: Option<Gltf>,
 }
@@ -60,17 +246,23 @@ impl ModelBuilder {
     pub fn new() -> Self {
         Self {
             uri_buffers: vec![],
+            undecoded_images: vec![],
             parent_dir: None,
             gltf: None,
         }
     }
 
     /// Creates a model loading a GLTF file
-    pub fn path<P: AsRef<Path>>(mut self, path: P) -> Result<Self, Box<dyn Error>> {
+    pub fn path<P: AsRef<Path>>(mut self, path: P) -> Result<Self, RaycaError> {
         self.parent_dir = Some(
             path.as_ref()
                 .parent()
-                .ok_or("Failed to get parent directory")?
+                .ok_or_else(|| {
+                    RaycaError::Io(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        "Failed to get parent directory",
+                    ))
+                })?
                 .into(),
         );
         self.gltf = Some(Gltf::open(path)?);
@@ -78,14 +270,14 @@ impl ModelBuilder {
         Ok(self)
     }
 
-    pub fn data(mut self, data: &[u8]) -> Result<Self, Box<dyn Error>> {
+    pub fn data(mut self, data: &[u8]) -> Result<Self, RaycaError> {
         self.gltf = Some(Gltf::from_slice(data)?);
         Ok(self)
     }
 
-    pub fn load_images(&mut self, images: &mut Pack<Image>) {
+    pub fn load_images(&mut self, images: &mut Pack<Image>) -> Result<(), RaycaError> {
         if self.gltf.is_none() {
-            return;
+            return Ok(());
         }
 
         let gltf = self.gltf.as_ref().unwrap();
@@ -97,35 +289,35 @@ impl ModelBuilder {
         #[cfg(not(feature = "parallel"))]
         let images_iter = gltf.images().enumerate();
 
-        let mut vec: Vec<Image> = images_iter
+        // KTX2 images of KHR_texture_basisu textures may use Basis Universal, which
+        // is not supported, in which case their textures use the fallback source
+        let basisu_sources = gltf
+            .textures()
+            .filter_map(|gtexture| get_basisu_source(&gtexture))
+            .collect::<HashSet<usize>>();
+
+        let mut vec = images_iter
             .map(|(id, image)| {
-                match image.source() {
-                    gltf::image::Source::View { .. } => todo!("Implement image source view"),
-                    gltf::image::Source::Uri { uri, .. } => {
-                        const DATA_URI: &str = "data:image/png;base64,";
-
-                        let mut image = if uri.starts_with(DATA_URI) {
-                            let (_, data_base64) = uri.split_at(DATA_URI.len());
-                            let data = base64::decode(data_base64)
-                                .expect("Failed to decode base64 image data");
-
-                            Image::load_png_data(&data)
-                        } else if let Some(parent_dir) = &self.parent_dir {
-                            // Join gltf parent dir to URI
-                            let path = parent_dir.join(uri);
-                            Image::load_file(path)
-                        } else {
-                            unimplemented!()
-                        };
-
-                        image.id = id;
-                        image
+                let (mut image, decoded) = match self.load_image(&image) {
+                    Ok(image) => (image, true),
+                    Err(RaycaError::Unsupported(message)) if basisu_sources.contains(&id) => {
+                        print_warning!("glTF", "Skipped image {}: {}", id, message);
+                        (Image::new(1, 1, ColorType::RGBA8), false)
                     }
-                }
+                    Err(err) => return Err(err),
+                };
+                image.id = id;
+                Ok((image, decoded))
             })
-            .collect();
+            .collect::<Result<Vec<(Image, bool)>, RaycaError>>()?;
 
-        vec.sort_by_key(|image| image.id);
+        vec.sort_by_key(|(image, _)| image.id);
+        self.undecoded_images = vec
+            .iter()
+            .filter(|(_, decoded)| !decoded)
+            .map(|(image, _)| image.id)
+            .collect();
+        let vec = vec.into_iter().map(|(image, _)| image).collect::<Vec<_>>();
 
         print_info!(
             "Loaded",
@@ -134,27 +326,81 @@ impl ModelBuilder {
         );
 
         *images = Pack::from(vec);
+        Ok(())
     }
 
-    pub fn load_textures(&mut self, textures: &mut Pack<Texture>) {
+    fn load_image(&self, image: &gltf::Image) -> Result<Image, RaycaError> {
+        match image.source() {
+            gltf::image::Source::View { view, mime_type } => {
+                let data = self.get_view_data(&view, 0)?;
+                match mime_type {
+                    "image/png" => Image::load_png_data(data),
+                    "image/jpeg" => Image::load_jpg_data(data),
+                    "image/ktx2" => Image::load_ktx2_data(data),
+                    _ => Err(RaycaError::Unsupported(format!(
+                        "glTF image mime type {}",
+                        mime_type
+                    ))),
+                }
+            }
+            gltf::image::Source::Uri { uri, .. } => {
+                const DATA_URI: &str = "data:image/png;base64,";
+
+                if uri.starts_with(DATA_URI) {
+                    let (_, data_base64) = uri.split_at(DATA_URI.len());
+                    let data = base64::decode(data_base64)?;
+                    Image::load_png_data(&data)
+                } else if let Some(parent_dir) = &self.parent_dir {
+                    // Join gltf parent dir to URI
+                    let path = parent_dir.join(uri);
+                    Image::load_file(path)
+                } else {
+                    Err(RaycaError::Unsupported(format!(
+                        "relative URI without a parent directory: {}",
+                        uri
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Loads textures, whose images should be loaded already
+    pub fn load_textures(
+        &mut self,
+        textures: &mut Pack<Texture>,
+        images: &Pack<Image>,
+    ) -> Result<(), RaycaError> {
         if self.gltf.is_none() {
-            return;
+            return Ok(());
         }
         let gltf = self.gltf.as_ref().unwrap();
 
-        let vec: Vec<Texture> = gltf
+        let vec = gltf
             .textures()
             .map(|gtexture| {
-                let image = Handle::new(gtexture.source().index());
+                // KHR_texture_basisu images are preferred, when they could be decoded
+                let index = match get_basisu_source(&gtexture) {
+                    Some(index) if !self.undecoded_images.contains(&index) => index,
+                    _ => gtexture.source().index(),
+                };
+                if index >= images.len() {
+                    return Err(RaycaError::InvalidHandle(format!(
+                        "texture {} refers to image {}, but there are {} images",
+                        gtexture.index(),
+                        index,
+                        images.len()
+                    )));
+                }
                 let sampler = Handle::none();
-                Texture::new(image, sampler)
+                Ok(Texture::new(Handle::new(index), sampler))
             })
-            .collect();
+            .collect::<Result<Vec<Texture>, RaycaError>>()?;
 
         *textures = Pack::from(vec);
+        Ok(())
     }
 
-    fn load_uri_buffers(&mut self) -> Result<(), Box<dyn Error>> {
+    fn load_uri_buffers(&mut self) -> Result<(), RaycaError> {
         if self.gltf.is_none() {
             return Ok(());
         }
@@ -172,55 +418,166 @@ impl ModelBuilder {
                         let uri = parent_dir.join(uri);
                         std::fs::read(uri)?
                     } else {
-                        unimplemented!();
+                        return Err(RaycaError::Unsupported(format!(
+                            "relative URI without a parent directory: {}",
+                            uri
+                        )));
                     };
-                    assert!(buffer.index() == self.uri_buffers.len());
                     self.uri_buffers.push(data);
                 }
-                _ => unimplemented!(),
+                gltf::buffer::Source::Bin => {
+                    // The binary chunk of a GLB file
+                    let data = gltf.blob.clone().unwrap_or_default();
+                    if data.len() < buffer.length() {
+                        return Err(RaycaError::Parse(format!(
+                            "glTF binary chunk of {} bytes, while its buffer has {}",
+                            data.len(),
+                            buffer.length()
+                        )));
+                    }
+                    self.uri_buffers.push(data);
+                }
+            }
+            if buffer.index() + 1 != self.uri_buffers.len() {
+                return Err(RaycaError::Parse(format!(
+                    "glTF buffer {} out of order",
+                    buffer.index()
+                )));
             }
         }
 
         Ok(())
     }
 
-    fn get_data_start(&self, accessor: &gltf::Accessor) -> &[u8] {
-        let view = accessor.view().unwrap();
+    /// Returns the data of `view` starting at `offset` within the view
+    fn get_view_data(&self, view: &gltf::buffer::View, offset: usize) -> Result<&[u8], RaycaError> {
         let view_len = view.length();
 
         let buffer = view.buffer();
-        if let gltf::buffer::Source::Bin = buffer.source() {
-            unimplemented!()
-        }
-
         let view_offset = view.offset();
-        let accessor_offset = accessor.offset();
-        let offset = accessor_offset + view_offset;
-        assert!(offset < buffer.length());
+        let offset = offset + view_offset;
         let end_offset = view_offset + view_len;
-        assert!(end_offset <= buffer.length());
+        let data = self.uri_buffers.get(buffer.index()).ok_or_else(|| {
+            RaycaError::Parse(format!("glTF buffer {} not loaded", buffer.index()))
+        })?;
+        if offset > end_offset || end_offset > buffer.length() || end_offset > data.len() {
+            return Err(RaycaError::Parse(format!(
+                "glTF buffer view {} from {} to {} out of its buffer of {} bytes",
+                view.index(),
+                offset,
+                end_offset,
+                data.len().min(buffer.length())
+            )));
+        }
+        Ok(&data[offset..end_offset])
+    }
 
-        let data = &self.uri_buffers[buffer.index()];
-        &data[offset..end_offset]
+    /// Returns the data of the elements of `accessor` with their stride. Sparse accessors
+    /// are resolved into a copy where their values replace the ones of the base view, or
+    /// zeros when there is no view
+    fn get_data(&self, accessor: &gltf::Accessor) -> Result<(Cow<'_, [u8]>, usize), RaycaError> {
+        let base = match accessor.view() {
+            Some(view) => Some(self.get_view_data(&view, accessor.offset())?),
+            None => None,
+        };
+        let stride = get_stride(accessor);
+        let count = accessor.count();
+        let size = accessor.size();
+        if let Some(base) = base {
+            // The last element does not need the whole stride
+            let needed = if count > 0 {
+                (count - 1) * stride + size
+            } else {
+                0
+            };
+            if base.len() < needed {
+                return Err(RaycaError::Parse(format!(
+                    "glTF accessor {} of {} bytes, while its view has {}",
+                    accessor.index(),
+                    needed,
+                    base.len()
+                )));
+            }
+        }
+        let Some(sparse) = accessor.sparse() else {
+            let base = base.ok_or_else(|| {
+                RaycaError::Unsupported(format!(
+                    "glTF accessor {} without data, which may be compressed",
+                    accessor.index()
+                ))
+            })?;
+            return Ok((Cow::Borrowed(base), stride));
+        };
+
+        let mut ret = vec![0; count * size];
+        if let Some(base) = base {
+            for (i, element) in ret.chunks_exact_mut(size).enumerate() {
+                element.copy_from_slice(&base[i * stride..i * stride + size]);
+            }
+        }
+
+        let indices = sparse.indices();
+        let index_data = self.get_view_data(&indices.view(), indices.offset())?;
+        let values = sparse.values();
+        let value_data = self.get_view_data(&values.view(), values.offset())?;
+        let index_size = match indices.index_type() {
+            gltf::accessor::sparse::IndexType::U8 => 1,
+            gltf::accessor::sparse::IndexType::U16 => 2,
+            gltf::accessor::sparse::IndexType::U32 => 4,
+        };
+        if index_data.len() < sparse.count() * index_size
+            || value_data.len() < sparse.count() * size
+        {
+            return Err(RaycaError::Parse(format!(
+                "glTF sparse accessor {} with {} elements out of its views",
+                accessor.index(),
+                sparse.count()
+            )));
+        }
+        for i in 0..sparse.count() {
+            let index = match indices.index_type() {
+                gltf::accessor::sparse::IndexType::U8 => index_data[i] as usize,
+                gltf::accessor::sparse::IndexType::U16 => {
+                    u16::from_le_bytes([index_data[i * 2], index_data[i * 2 + 1]]) as usize
+                }
+                gltf::accessor::sparse::IndexType::U32 => {
+                    let bytes = &index_data[i * 4..i * 4 + 4];
+                    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+                }
+            };
+            if index >= count {
+                return Err(RaycaError::InvalidHandle(format!(
+                    "sparse index {} of glTF accessor {} with {} elements",
+                    index,
+                    accessor.index(),
+                    count
+                )));
+            }
+            ret[index * size..(index + 1) * size]
+                .copy_from_slice(&value_data[i * size..(i + 1) * size]);
+        }
+
+        Ok((Cow::Owned(ret), size))
     }
 
-    pub fn build(&mut self) -> Result<Model, Box<dyn Error>> {
+    pub fn build(&mut self) -> Result<Model, RaycaError> {
         let mut model = Model::new();
 
-        self.load_images(&mut model.images);
-        self.load_textures(&mut model.textures);
+        // Buffers come first, as images may be stored in buffer views
         self.load_uri_buffers()?;
+        self.load_images(&mut model.images)?;
+        self.load_textures(&mut model.textures, &model.images)?;
         self.load_materials(&mut model.materials)?;
         self.load_meshes(&mut model)?;
         self.load_cameras(&mut model.cameras)?;
-        self.load_nodes(&mut model);
+        self.load_nodes(&mut model)?;
 
         // TODO collect lights from glTF file
 
         Ok(model)
     }
 
-    pub fn load_cameras(&mut self, cameras: &mut Pack<Camera>) -> Result<(), Box<dyn Error>> {
+    pub fn load_cameras(&mut self, cameras: &mut Pack<Camera>) -> Result<(), RaycaError> {
         if self.gltf.is_none() {
             return Ok(());
         }
@@ -251,7 +608,7 @@ impl ModelBuilder {
         Ok(())
     }
 
-    pub fn load_materials(&mut self, materials: &mut Pack<Material>) -> Result<(), Box<dyn Error>> {
+    pub fn load_materials(&mut self, materials: &mut Pack<Material>) -> Result<(), RaycaError> {
         if self.gltf.is_none() {
             return Ok(());
         }
@@ -270,18 +627,40 @@ impl ModelBuilder {
             // Load albedo
             if let Some(gtexture) = pbr.base_color_texture() {
                 material.albedo_texture = Handle::new(gtexture.texture().index());
+                material.albedo_transform = get_texture_transform(&gtexture);
             }
 
             // Load normal
             if let Some(gtexture) = gmaterial.normal_texture() {
                 material.normal_texture = Handle::new(gtexture.texture().index());
+                material.normal_transform = get_raw_texture_transform(
+                    gtexture.extension_value("KHR_texture_transform"),
+                    gtexture.tex_coord(),
+                )?;
             }
 
+            // Load ambient occlusion, which often uses the second set of texture coordinates
+            if let Some(gtexture) = gmaterial.occlusion_texture() {
+                material.occlusion_texture = Handle::new(gtexture.texture().index());
+                material.occlusion_strength = gtexture.strength();
+                material.occlusion_transform = get_raw_texture_transform(
+                    gtexture.extension_value("KHR_texture_transform"),
+                    gtexture.tex_coord(),
+                )?;
+            }
+
+            material.extras = get_extras(gmaterial.extras())?;
+
             // Load metallic roughness factors and texture
             material.metallic_factor = pbr.metallic_factor();
             material.roughness_factor = pbr.roughness_factor();
             if let Some(gtexture) = pbr.metallic_roughness_texture() {
                 material.metallic_roughness_texture = Handle::new(gtexture.texture().index());
+                material.metallic_roughness_transform = get_texture_transform(&gtexture);
+            }
+
+            if let Some(graph) = gmaterial.extension_value(RAYCA_MATERIAL_GRAPH) {
+                material.graph = Some(MaterialGraph::from_json(graph)?);
             }
 
             materials.push(material);
@@ -290,12 +669,9 @@ impl ModelBuilder {
         Ok(())
     }
 
-    fn load_vertices(&self, gprimitive: &gltf::Primitive) -> Result<Vec<Vertex>, Box<dyn Error>> {
+    fn load_vertices(&self, gprimitive: &gltf::Primitive) -> Result<Vec<Vertex>, RaycaError> {
         let mut vertices = vec![];
 
-        let mode = gprimitive.mode();
-        assert!(mode == gltf::mesh::Mode::Triangles);
-
         // Load normals first, so we can process tangents later
         for (semantic, accessor) in gprimitive.attributes() {
             if semantic == gltf::mesh::Semantic::Normals {
@@ -306,7 +682,9 @@ impl ModelBuilder {
         for (semantic, accessor) in gprimitive.attributes() {
             match semantic {
                 gltf::mesh::Semantic::Positions => self.load_positions(&mut vertices, &accessor)?,
-                gltf::mesh::Semantic::TexCoords(_) => self.load_uvs(&mut vertices, &accessor)?,
+                gltf::mesh::Semantic::TexCoords(set) => {
+                    self.load_uvs(&mut vertices, &accessor, set as usize)?
+                }
                 gltf::mesh::Semantic::Colors(_) => self.load_colors(&mut vertices, &accessor)?,
                 gltf::mesh::Semantic::Normals => (), // Already loaded
                 gltf::mesh::Semantic::Tangents => self.load_tangents(&mut vertices, &accessor)?,
@@ -317,7 +695,7 @@ impl ModelBuilder {
         Ok(vertices)
     }
 
-    fn load_indices(&self, gprimitive: &gltf::Primitive) -> (Vec<u8>, usize) {
+    fn load_indices(&self, gprimitive: &gltf::Primitive) -> Result<(Vec<u8>, usize), RaycaError> {
         let mut indices = vec![];
         let mut index_size = 1;
 
@@ -325,42 +703,122 @@ impl ModelBuilder {
             let data_type = accessor.data_type();
             index_size = data_type_as_size(data_type);
 
-            // Data type can vary
-            let data = self.get_data_start(&accessor);
-            let d = &data[0];
-            let len = accessor.count() * data_type_as_size(data_type);
             // Use bytes regardless of the index data type
-            let slice: &[u8] = unsafe { std::slice::from_raw_parts(d as *const u8 as _, len) };
-            indices = Vec::from(slice);
+            let (data, stride) = self.get_data(&accessor)?;
+            for i in 0..accessor.count() {
+                indices.extend_from_slice(&data[i * stride..i * stride + index_size]);
+            }
         }
 
-        (indices, index_size)
+        Ok((indices, index_size))
     }
 
     fn load_primitive(
         &self,
         model: &mut Model,
         gprimitive: &gltf::Primitive,
-    ) -> Result<Handle<Primitive>, Box<dyn Error>> {
-        let vertices = self.load_vertices(gprimitive)?;
-        let (indices, index_size) = self.load_indices(gprimitive);
+    ) -> Result<Handle<Primitive>, RaycaError> {
+        // Draco decoding is not available, but compressed primitives
+        // may come with uncompressed data as a fallback
+        let compressed = gprimitive
+            .extension_value("KHR_draco_mesh_compression")
+            .is_some();
+        if compressed && gprimitive.attributes().any(|(_, a)| a.view().is_none()) {
+            return Err(RaycaError::Unsupported(
+                "KHR_draco_mesh_compression decoding, and the primitive has no uncompressed data"
+                    .into(),
+            ));
+        }
 
+        let vertices = self.load_vertices(gprimitive)?;
+        let (indices, index_size) = self.load_indices(gprimitive)?;
+        let mut triangles = Triangles::new(vertices, indices);
+        triangles.index_size_in_bytes = index_size;
+
+        // Primitives without indices use their vertices in order
+        let indices = if gprimitive.indices().is_some() {
+            triangles.get_indices()
+        } else {
+            (0..triangles.vertices.len() as u32).collect()
+        };
+        let vertex_count = triangles.vertices.len();
+        if let Some(index) = indices
+            .iter()
+            .find(|&&index| index as usize >= vertex_count)
+        {
+            return Err(RaycaError::InvalidGeometry(format!(
+                "glTF primitive index {} out of {} vertices",
+                index, vertex_count
+            )));
+        }
         let material = gprimitive
             .material()
             .index()
             .map_or(Handle::none(), Handle::new);
+        let vertices = &triangles.vertices;
 
-        let primitive = Primitive::builder()
-            .vertices(vertices)
-            .indices(indices)
-            .index_size(index_size)
-            .material(material)
-            .build();
+        let mode = gprimitive.mode();
+        let builder = match mode {
+            gltf::mesh::Mode::Points => {
+                let mut point_cloud = PointCloud::default();
+                for index in indices {
+                    let vertex = &vertices[index as usize];
+                    point_cloud.positions.push(vertex.pos);
+                    point_cloud.radii.push(POINT_SIZE);
+                    point_cloud.colors.push(vertex.ext.color);
+                }
+                Primitive::builder().point_cloud(point_cloud)
+            }
+            gltf::mesh::Mode::Lines | gltf::mesh::Mode::LineStrip | gltf::mesh::Mode::LineLoop => {
+                let curves = get_line_segments(mode, &indices)
+                    .into_iter()
+                    .map(|[a, b]| {
+                        let (a, b) = (&vertices[a as usize], &vertices[b as usize]);
+                        // Straight segments have control points evenly spaced
+                        let points = (0..4)
+                            .map(|i| a.pos + (b.pos - a.pos) * (i as f32 / 3.0))
+                            .collect();
+                        let mut curve = Curve::new(points, vec![POINT_SIZE; 4]);
+                        curve.color = a.ext.color;
+                        curve
+                    })
+                    .collect();
+                Primitive::builder().curves(Curves::new(curves))
+            }
+            gltf::mesh::Mode::Triangles
+            | gltf::mesh::Mode::TriangleStrip
+            | gltf::mesh::Mode::TriangleFan => {
+                if mode == gltf::mesh::Mode::TriangleStrip {
+                    triangles.set_indices(&triangulate_strip(&indices));
+                } else if mode == gltf::mesh::Mode::TriangleFan {
+                    triangles.set_indices(&triangulate_fan(&indices));
+                }
+                Self::complete_attributes(gprimitive, &mut triangles);
+                Primitive::builder()
+                    .vertices(triangles.vertices)
+                    .indices(triangles.indices)
+                    .index_size(triangles.index_size_in_bytes)
+            }
+        };
+
+        Ok(model.primitives.push(builder.material(material).build()))
+    }
 
-        Ok(model.primitives.push(primitive))
+    /// Generates missing attributes, so that shading and normal mapping work anyway
+    fn complete_attributes(gprimitive: &gltf::Primitive, triangles: &mut Triangles) {
+        if gprimitive.get(&gltf::mesh::Semantic::Normals).is_none() {
+            // glTF requires flat normals in this case
+            triangles.compute_smooth_normals(0.0);
+        }
+        let has_uvs = gprimitive
+            .get(&gltf::mesh::Semantic::TexCoords(0))
+            .is_some();
+        if has_uvs && gprimitive.get(&gltf::mesh::Semantic::Tangents).is_none() {
+            triangles.compute_tangents();
+        }
     }
 
-    fn load_meshes(&self, model: &mut Model) -> Result<(), Box<dyn Error>> {
+    fn load_meshes(&self, model: &mut Model) -> Result<(), RaycaError> {
         if self.gltf.is_none() {
             return Ok(());
         }
@@ -369,12 +827,8 @@ impl ModelBuilder {
         for gmesh in gltf.meshes() {
             let primitive_handles = gmesh
                 .primitives()
-                .into_iter()
-                .map(|gprimitive| {
-                    self.load_primitive(model, &gprimitive)
-                        .expect("Failed to load a primitive")
-                })
-                .collect();
+                .map(|gprimitive| self.load_primitive(model, &gprimitive))
+                .collect::<Result<Vec<_>, RaycaError>>()?;
 
             let mesh = Mesh::new(primitive_handles);
             model.meshes.push(mesh);
@@ -382,41 +836,77 @@ impl ModelBuilder {
         Ok(())
     }
 
-    fn get_slices<'g>(&self, accessor: &'g gltf::Accessor) -> Vec<&'g [f32]> {
+    fn get_slices(&self, accessor: &gltf::Accessor) -> Result<Vec<Vec<f32>>, RaycaError> {
         let data_type = accessor.data_type();
-        assert!(data_type == gltf::accessor::DataType::F32);
+        if data_type != gltf::accessor::DataType::F32 {
+            return Err(RaycaError::Parse(format!(
+                "glTF accessor {} of {:?}, while floats are expected",
+                accessor.index(),
+                data_type
+            )));
+        }
 
         let count = accessor.count();
-        let dimensions = accessor.dimensions();
-        let len = match dimensions {
-            gltf::accessor::Dimensions::Vec2 => 2,
-            gltf::accessor::Dimensions::Vec3 => 3,
-            gltf::accessor::Dimensions::Vec4 => 4,
-            _ => panic!("Invalid dimensions"),
-        };
+        let len = get_vector_size(accessor)?;
 
-        let data = self.get_data_start(accessor);
-        let stride = get_stride(accessor);
+        let (data, stride) = self.get_data(accessor)?;
 
         let mut ret = vec![];
 
         for i in 0..count {
             let offset = i * stride;
-            assert!(offset < data.len());
-            let d = &data[offset];
-            let slice = unsafe { std::slice::from_raw_parts::<f32>(d as *const u8 as _, len) };
+            let slice = (0..len)
+                .map(|c| {
+                    let d = &data[offset + c * 4..offset + c * 4 + 4];
+                    f32::from_le_bytes([d[0], d[1], d[2], d[3]])
+                })
+                .collect();
             ret.push(slice);
         }
 
-        ret
+        Ok(ret)
+    }
+
+    /// Like `get_slices()`, but also accepts normalized unsigned integers,
+    /// which exporters often use for vertex colors and texture coordinates
+    fn get_normalized_slices(
+        &self,
+        accessor: &gltf::Accessor,
+    ) -> Result<Vec<Vec<f32>>, RaycaError> {
+        let data_type = accessor.data_type();
+        if data_type == gltf::accessor::DataType::F32 {
+            return self.get_slices(accessor);
+        }
+        if data_type != gltf::accessor::DataType::U8 && data_type != gltf::accessor::DataType::U16 {
+            return Err(RaycaError::Unsupported(format!(
+                "glTF normalized attributes of {:?}",
+                data_type
+            )));
+        }
+
+        let len = get_vector_size(accessor)?;
+        let (data, stride) = self.get_data(accessor)?;
+
+        Ok((0..accessor.count())
+            .map(|i| {
+                let d = &data[i * stride..];
+                (0..len)
+                    .map(|c| match data_type {
+                        gltf::accessor::DataType::U8 => d[c] as f32 / u8::MAX as f32,
+                        _ => u16::from_le_bytes([d[c * 2], d[c * 2 + 1]]) as f32 / u16::MAX as f32,
+                    })
+                    .collect()
+            })
+            .collect())
     }
 
     fn load_positions(
         &self,
         vertices: &mut Vec<Vertex>,
         accessor: &gltf::Accessor,
-    ) -> Result<(), Box<dyn Error>> {
-        let positions = self.get_slices(accessor);
+    ) -> Result<(), RaycaError> {
+        expect_vector_size(accessor, 3)?;
+        let positions = self.get_slices(accessor)?;
         vertices.resize(positions.len(), Vertex::default());
         for (i, position) in positions.into_iter().enumerate() {
             vertices[i].pos = Point3::new(position[0], position[1], position[2]);
@@ -428,12 +918,19 @@ impl ModelBuilder {
         &self,
         vertices: &mut Vec<Vertex>,
         accessor: &gltf::Accessor,
-    ) -> Result<(), Box<dyn Error>> {
-        let uvs = self.get_slices(accessor);
+        set: usize,
+    ) -> Result<(), RaycaError> {
+        expect_vector_size(accessor, 2)?;
+        let uvs = self.get_normalized_slices(accessor)?;
         vertices.resize(uvs.len(), Vertex::default());
         for (i, uv) in uvs.into_iter().enumerate() {
-            vertices[i].ext.uv.x = uv[0];
-            vertices[i].ext.uv.y = uv[1];
+            let uv = Vec2::new(uv[0], uv[1]);
+            match set {
+                0 => vertices[i].ext.uv = uv,
+                1 => vertices[i].ext.uv1 = uv,
+                // Only two sets are stored
+                _ => (),
+            }
         }
         Ok(())
     }
@@ -442,8 +939,9 @@ impl ModelBuilder {
         &self,
         vertices: &mut Vec<Vertex>,
         accessor: &gltf::Accessor,
-    ) -> Result<(), Box<dyn Error>> {
-        let normals = self.get_slices(accessor);
+    ) -> Result<(), RaycaError> {
+        expect_vector_size(accessor, 3)?;
+        let normals = self.get_slices(accessor)?;
         vertices.resize(normals.len(), Vertex::default());
         for (i, normal) in normals.into_iter().enumerate() {
             vertices[i].ext.normal = Vec3::new(normal[0], normal[1], normal[2]);
@@ -455,8 +953,14 @@ impl ModelBuilder {
         &self,
         vertices: &mut Vec<Vertex>,
         accessor: &gltf::Accessor,
-    ) -> Result<(), Box<dyn Error>> {
-        let colors = self.get_slices(accessor);
+    ) -> Result<(), RaycaError> {
+        if get_vector_size(accessor)? < 3 {
+            return Err(RaycaError::InvalidGeometry(format!(
+                "glTF colors {} without blue",
+                accessor.index()
+            )));
+        }
+        let colors = self.get_normalized_slices(accessor)?;
         vertices.resize(colors.len(), Vertex::default());
         for (i, color) in colors.into_iter().enumerate() {
             vertices[i].ext.color.r = color[0];
@@ -471,32 +975,27 @@ impl ModelBuilder {
         &self,
         vertices: &mut [Vertex],
         accessor: &gltf::Accessor,
-    ) -> Result<(), Box<dyn Error>> {
-        let data_type = accessor.data_type();
-        assert!(data_type == gltf::accessor::DataType::F32);
-        let count = accessor.count();
-        assert_eq!(vertices.len(), count);
-        let dimensions = accessor.dimensions();
-        assert!(dimensions == gltf::accessor::Dimensions::Vec4);
-
-        let view = accessor.view().unwrap();
-        let target = view.target().unwrap_or(gltf::buffer::Target::ArrayBuffer);
-        assert!(target == gltf::buffer::Target::ArrayBuffer);
-
-        let data = self.get_data_start(accessor);
-        let stride = get_stride(accessor);
-
-        vertices.iter_mut().enumerate().for_each(|(index, vertex)| {
-            let offset = index * stride;
-            assert!(offset < data.len());
-            let d = &data[offset];
-            let tangent = unsafe { std::slice::from_raw_parts::<f32>(d as *const u8 as _, 4) };
+    ) -> Result<(), RaycaError> {
+        expect_vector_size(accessor, 4)?;
+        if vertices.len() != accessor.count() {
+            return Err(RaycaError::InvalidGeometry(format!(
+                "glTF tangents {} of {} elements, for {} vertices",
+                accessor.index(),
+                accessor.count(),
+                vertices.len()
+            )));
+        }
 
-            vertex.ext.tangent = Vec3::new(tangent[0], tangent[1], tangent[2]);
+        let tangents = self.get_slices(accessor)?;
+        vertices
+            .iter_mut()
+            .zip(tangents)
+            .for_each(|(vertex, tangent)| {
+                vertex.ext.tangent = Vec3::new(tangent[0], tangent[1], tangent[2]);
 
-            // Compute bitangent as for glTF 2.0 spec
-            vertex.ext.bitangent = vertex.ext.normal.cross(&vertex.ext.tangent) * tangent[3];
-        });
+                // Compute bitangent as for glTF 2.0 spec
+                vertex.ext.bitangent = vertex.ext.normal.cross(&vertex.ext.tangent) * tangent[3];
+            });
 
         Ok(())
     }
@@ -514,16 +1013,21 @@ impl ModelBuilder {
     }
 
     fn create_node(gnode: &gltf::Node) -> Node {
-        let transform = gnode.transform().decomposed();
-
-        let translation = &transform.0;
-        let translation = Vec3::new(translation[0], translation[1], translation[2]);
-
-        let rotation = &transform.1;
-        let rotation = Quat::new(rotation[0], rotation[1], rotation[2], rotation[3]);
-
-        let scale = &transform.2;
-        let scale = Vec3::new(scale[0], scale[1], scale[2]);
+        let trs = match gnode.transform() {
+            // Matrices are column-major, and may have non-uniform scales or reflections
+            gltf::scene::Transform::Matrix { matrix } => {
+                Mat4::from(matrix).get_transpose().decompose()
+            }
+            gltf::scene::Transform::Decomposed {
+                translation,
+                rotation,
+                scale,
+            } => Trs::new(
+                Vec3::new(translation[0], translation[1], translation[2]),
+                Quat::new(rotation[0], rotation[1], rotation[2], rotation[3]),
+                Vec3::new(scale[0], scale[1], scale[2]),
+            ),
+        };
 
         let mut node_builder = Node::builder()
             .id(gnode.index())
@@ -534,9 +1038,7 @@ impl ModelBuilder {
                     .map(|gchild| Handle::new(gchild.index()))
                     .collect(),
             )
-            .translation(translation)
-            .rotation(rotation)
-            .scale(scale);
+            .trs(trs);
 
         if let Some(mesh) = gnode.mesh() {
             node_builder = node_builder.mesh(Handle::new(mesh.index()));
@@ -549,33 +1051,102 @@ impl ModelBuilder {
         node_builder.build()
     }
 
-    fn load_nodes(&self, model: &mut Model) {
+    /// Loads the analytic primitives of `gnode` into `node`, see `RAYCA_PRIMITIVES`
+    fn load_node_primitives(
+        model: &mut Model,
+        gnode: &gltf::Node,
+        node: &mut Node,
+    ) -> Result<(), RaycaError> {
+        let Some(extension) = gnode.extension_value(RAYCA_PRIMITIVES) else {
+            return Ok(());
+        };
+
+        if let Some(spheres) = extension.get("spheres") {
+            let spheres = spheres.as_array().ok_or_else(|| {
+                RaycaError::Parse(format!("{} spheres as an array", RAYCA_PRIMITIVES))
+            })?;
+            // Meshes may be shared, hence spheres go to a new one
+            let mut primitives = model
+                .meshes
+                .get(node.mesh)
+                .map_or(vec![], |mesh| mesh.primitives.clone());
+            for sphere in spheres {
+                let center = Point3::from(get_json_vec3(sphere, "center")?);
+                let radius = get_json_f32(sphere, "radius", 1.0)?;
+                let mut builder = Primitive::builder().sphere(center, radius);
+                if let Some(material) = sphere.get("material") {
+                    let material = material
+                        .as_u64()
+                        .map(|index| Handle::new(index as usize))
+                        .filter(|material| model.materials.contains(*material))
+                        .ok_or_else(|| {
+                            RaycaError::InvalidHandle(format!(
+                                "{} sphere material {}",
+                                RAYCA_PRIMITIVES, material
+                            ))
+                        })?;
+                    builder = builder.material(material);
+                }
+                primitives.push(model.primitives.push(builder.build()));
+            }
+            node.mesh = model.meshes.push(Mesh::new(primitives));
+        }
+
+        if let Some(quad) = extension.get("quadLight") {
+            let width = get_json_f32(quad, "width", 1.0)?;
+            let height = get_json_f32(quad, "height", 1.0)?;
+            let mut light = Light::quad(width, height);
+            light.set_intensity(get_json_f32(quad, "intensity", 1.0)?);
+            node.light = model.lights.push(light);
+        }
+
+        Ok(())
+    }
+
+    fn load_nodes(&self, model: &mut Model) -> Result<(), RaycaError> {
         if self.gltf.is_none() {
-            return;
+            return Ok(());
         }
         let gltf = self.gltf.as_ref().unwrap();
 
         // Load scene
-        let scene = gltf.scenes().next().unwrap();
+        let scene = gltf
+            .default_scene()
+            .or_else(|| gltf.scenes().next())
+            .ok_or_else(|| RaycaError::Parse("glTF without scenes".into()))?;
         model.root = Self::create_root(&scene);
+        // Extras of the whole file go to the node grouping the model once appended
+        model.root.extras = get_extras(&gltf.as_json().extras)?;
 
         // Load nodes
         for gnode in gltf.nodes() {
-            let node = Self::create_node(&gnode);
+            let mut node = Self::create_node(&gnode);
+            node.extras = get_extras(gnode.extras())?;
+            Self::load_node_primitives(model, &gnode, &mut node)?;
             model.nodes.push(node);
         }
+        Ok(())
     }
 }
 
 /// Solved transforms in world space, ready to be used by the renderer
-#[repr(transparent)]
 pub struct SolvedTrs {
     pub trs: Trs,
+
+    /// Inverse transpose of rotation and scale, which keeps normals perpendicular
+    /// to surfaces stretched by non-uniform scales
+    pub normal_matrix: Mat3,
 }
 
 impl SolvedTrs {
     pub fn new(trs: Trs) -> Self {
-        Self { trs }
+        let normal_matrix = Mat3::from(&Inversed::from(&trs)).get_transpose();
+        Self { trs, normal_matrix }
+    }
+
+    /// Transforms a normal from model space to world space
+    pub fn transform_normal(&self, normal: Vec3) -> Vec3 {
+        (&self.normal_matrix * normal).get_normalized()
     }
 }
 
@@ -587,6 +1158,27 @@ impl Deref for SolvedTrs {
     }
 }
 
+/// Handles of the objects of a model appended to another one
+#[derive(Clone)]
+pub struct ModelHandles {
+    /// Node grouping the root nodes of the appended model
+    pub root: Handle<Node>,
+    pub samplers: Vec<Handle<Sampler>>,
+    pub images: Vec<Handle<Image>>,
+    pub textures: Vec<Handle<Texture>>,
+    pub materials: Vec<Handle<Material>>,
+    pub primitives: Vec<Handle<Primitive>>,
+    pub meshes: Vec<Handle<Mesh>>,
+    pub cameras: Vec<Handle<Camera>>,
+    pub lights: Vec<Handle<Light>>,
+    pub nodes: Vec<Handle<Node>>,
+}
+
+/// Returns the handles of the elements appended to a pack, see `Pack::append()`
+fn appended_handles<T>(handles: &[Handle<T>]) -> Vec<Handle<T>> {
+    handles.iter().copied().filter(Handle::valid).collect()
+}
+
 #[derive(Default)]
 pub struct Model {
     pub id: usize,
@@ -605,8 +1197,57 @@ pub struct Model {
 
     // Cleared every frame
     pub solved_trs: HashMap<Handle<Node>, SolvedTrs>,
+    /// World transforms solved for the previous frame, see `store_previous_trs()`
+    pub previous_trs: HashMap<Handle<Node>, Trs>,
+    /// Material overrides inherited by nodes from their closest ancestor
+    pub solved_materials: HashMap<Handle<Node>, Handle<Material>>,
     pub camera_nodes: Vec<Handle<Node>>,
     pub light_nodes: Vec<Handle<Node>>,
+    /// Light links of light nodes, extended to the descendants of their nodes
+    pub solved_light_links: HashMap<Handle<Node>, LightLink>,
+    /// Light nodes sorted for sampling, see `LightTree`
+    pub light_tree: LightTree,
+
+    /// How `sky_distribution` is built
+    pub sky_sampling: SkySampling,
+    /// Sky lights tabulated for sampling, when there are any
+    pub sky_distribution: Option<SkyDistribution>,
+
+    /// How triangles of displaced materials are split while collecting primitives
+    pub tessellation: Tessellation,
+
+    /// Space colors of textures and lights are converted to, which `Scene` keeps up to
+    /// date with its config
+    pub working_space: ColorSpace,
+
+    /// When set, collecting solves again only the nodes marked by `mark_dirty()` and their
+    /// descendants, keeping transforms and primitives of the others from the previous
+    /// collection. `Scene` keeps it up to date with its config
+    pub incremental: bool,
+
+    /// Whether the previous collection can be updated, see `mark_all_dirty()`
+    clean: bool,
+    dirty_nodes: HashSet<Handle<Node>>,
+    parents: HashMap<Handle<Node>, Handle<Node>>,
+
+    /// Primitives collected for every node, and the camera they were collected for
+    collected: HashMap<Handle<Node>, Vec<BvhPrimitive>>,
+    collected_camera: Option<(Vec3, f32)>,
+    /// Nodes solved again since their primitives were collected
+    moved: HashSet<Handle<Node>>,
+
+    /// Incremented every time nodes are solved, see `get_generation()`
+    generation: u64,
+}
+
+/// Maps filled while traversing the nodes of a model
+#[derive(Default)]
+struct Solution {
+    trs: HashMap<Handle<Node>, SolvedTrs>,
+    materials: HashMap<Handle<Node>, Handle<Material>>,
+    parents: HashMap<Handle<Node>, Handle<Node>>,
+    /// Nodes solved by the traversal
+    visited: HashSet<Handle<Node>>,
 }
 
 impl Model {
@@ -620,102 +1261,394 @@ impl Model {
         }
     }
 
-    /// Takes a loaded model and appends all its objects to the objects of the current model
-    pub fn append(&mut self, mut model: Model) {
-        // Create a new root node for the new model
-        let new_model_root = self.nodes.push(model.root);
-        self.root.children.push(new_model_root);
-
-        let sampler_offset = self.samplers.append(&mut model.samplers);
-        let image_offset = self.images.append(&mut model.images);
+    /// Takes a loaded model and appends all its objects to the objects of the current model,
+    /// returning their handles so that they can be removed later
+    pub fn append(&mut self, mut model: Model) -> ModelHandles {
+        let samplers = self.samplers.append(&mut model.samplers);
+        let images = self.images.append(&mut model.images);
         // Update sampler and image handles
         for texture in model.textures.iter_mut() {
-            texture.sampler.offset(sampler_offset);
-            texture.image.offset(image_offset);
+            texture.sampler.remap(&samplers);
+            texture.image.remap(&images);
         }
 
-        let texture_offset = self.textures.append(&mut model.textures);
+        let textures = self.textures.append(&mut model.textures);
         // Update texture handles
         for material in model.materials.iter_mut() {
-            material.albedo_texture.offset(texture_offset);
-            material.metallic_roughness_texture.offset(texture_offset);
+            material.albedo_texture.remap(&textures);
+            material.normal_texture.remap(&textures);
+            material.metallic_roughness_texture.remap(&textures);
+            material.occlusion_texture.remap(&textures);
+            material.displacement_texture.remap(&textures);
+            if let Some(mix) = material.mix.as_mut() {
+                mix.mask.remap(&textures);
+            }
+            if let Some(graph) = material.graph.as_mut() {
+                graph.remap_textures(&textures);
+            }
         }
 
-        let mat_offset = self.materials.append(&mut model.materials);
+        let materials = self.materials.append(&mut model.materials);
         // Update material handles
+        for handle in appended_handles(&materials) {
+            if let Some(mix) = self.materials.get_mut(handle).unwrap().mix.as_mut() {
+                mix.a.remap(&materials);
+                mix.b.remap(&materials);
+            }
+        }
         for prim in model.primitives.iter_mut() {
-            prim.material.offset(mat_offset);
+            prim.material.remap(&materials);
         }
 
-        let prim_offset = self.primitives.append(&mut model.primitives);
+        let primitives = self.primitives.append(&mut model.primitives);
         // Update primitive handles
         for mesh in model.meshes.iter_mut() {
             for primitive_handle in &mut mesh.primitives {
-                primitive_handle.offset(prim_offset);
+                primitive_handle.remap(&primitives);
             }
         }
 
-        let light_offset = self.lights.append(&mut model.lights);
-        let camera_offset = self.cameras.append(&mut model.cameras);
-        let mesh_offset = self.meshes.append(&mut model.meshes);
-        let node_offset = self.nodes.len();
+        let lights = self.lights.append(&mut model.lights);
+        let cameras = self.cameras.append(&mut model.cameras);
+        let meshes = self.meshes.append(&mut model.meshes);
+        let nodes = self.nodes.append(&mut model.nodes);
         // Update mesh and node handles
-        for node in model.nodes.iter_mut() {
-            node.light.offset(light_offset);
-            node.camera.offset(camera_offset);
-            node.mesh.offset(mesh_offset);
+        for node_handle in appended_handles(&nodes) {
+            let node = self.nodes.get_mut(node_handle).unwrap();
+            node.light.remap(&lights);
+            node.camera.remap(&cameras);
+            node.mesh.remap(&meshes);
+            node.material.remap(&materials);
+            node.light_link.remap(&nodes);
+            for lod in node.lod.iter_mut().flat_map(|lod| lod.levels.iter_mut()) {
+                lod.mesh.remap(&meshes);
+            }
+            if let Some(particles) = node.particles.as_mut() {
+                particles.mesh.remap(&meshes);
+            }
             for children in &mut node.children {
-                children.offset(node_offset);
+                children.remap(&nodes);
             }
         }
 
-        self.nodes.append(&mut model.nodes);
-        let new_model_root = self.nodes.get_mut(new_model_root).unwrap();
+        // Create a new root node for the new model
+        let mut new_model_root = model.root;
+        new_model_root.material.remap(&materials);
         for children in &mut new_model_root.children {
-            children.offset(node_offset);
+            children.remap(&nodes);
+        }
+        let root = self.nodes.push(new_model_root);
+        self.root.children.push(root);
+        self.mark_all_dirty();
+
+        ModelHandles {
+            root,
+            samplers: appended_handles(&samplers),
+            images: appended_handles(&images),
+            textures: appended_handles(&textures),
+            materials: appended_handles(&materials),
+            primitives: appended_handles(&primitives),
+            meshes: appended_handles(&meshes),
+            cameras: appended_handles(&cameras),
+            lights: appended_handles(&lights),
+            nodes: appended_handles(&nodes),
+        }
+    }
+
+    /// Removes the objects of a model previously appended to this one
+    pub fn remove(&mut self, handles: &ModelHandles) {
+        self.root.children.retain(|child| *child != handles.root);
+        self.nodes.remove(handles.root);
+        self.nodes.remove_many(&handles.nodes);
+        self.meshes.remove_many(&handles.meshes);
+        self.primitives.remove_many(&handles.primitives);
+        self.materials.remove_many(&handles.materials);
+        self.textures.remove_many(&handles.textures);
+        self.images.remove_many(&handles.images);
+        self.samplers.remove_many(&handles.samplers);
+        self.cameras.remove_many(&handles.cameras);
+        self.lights.remove_many(&handles.lights);
+
+        // Collected handles may refer to removed nodes
+        self.solved_trs.clear();
+        self.previous_trs.clear();
+        self.solved_materials.clear();
+        self.camera_nodes.clear();
+        self.light_nodes.clear();
+        self.solved_light_links.clear();
+        self.light_tree = LightTree::default();
+        self.sky_distribution = None;
+        self.mark_all_dirty();
+    }
+
+    /// Marks the transform, material override, or mesh of `node` as changed, so that an
+    /// incremental collection solves it again together with its descendants. Changes
+    /// to the children of a node need `mark_all_dirty()` instead
+    pub fn mark_dirty(&mut self, node: Handle<Node>) {
+        self.dirty_nodes.insert(node);
+    }
+
+    /// Makes the next collection solve every node
+    pub fn mark_all_dirty(&mut self) {
+        self.clean = false;
+        self.dirty_nodes.clear();
+        self.collected.clear();
+    }
+
+    /// Moves the particle systems of every node forward by `delta` seconds,
+    /// marking their nodes dirty, see `ParticleSystem::update()`
+    pub fn update_particles(&mut self, delta: f32) {
+        let node_handles: Vec<_> = self.nodes.handles().collect();
+        for node_handle in node_handles {
+            let node = self.nodes.get_mut(node_handle).unwrap();
+            if let Some(particles) = node.particles.as_mut() {
+                particles.update(delta);
+                self.mark_dirty(node_handle);
+            }
         }
     }
 
     fn traverse(
         &self,
-        solved_trs: &mut HashMap<Handle<Node>, SolvedTrs>,
+        solution: &mut Solution,
         transform: Trs,
+        material: Handle<Material>,
+        parent: Option<Handle<Node>>,
         node: Handle<Node>,
     ) {
         let current_node = self.nodes.get(node).unwrap();
         let current_transform = &transform * &current_node.trs;
-        solved_trs.insert(node, SolvedTrs::new(current_transform.clone()));
+        solution
+            .trs
+            .insert(node, SolvedTrs::new(current_transform.clone()));
+        match parent {
+            Some(parent) => solution.parents.insert(node, parent),
+            None => solution.parents.remove(&node),
+        };
+        solution.visited.insert(node);
+
+        let current_material = if current_node.material.valid() {
+            current_node.material
+        } else {
+            material
+        };
+        if current_material.valid() {
+            solution.materials.insert(node, current_material);
+        } else {
+            solution.materials.remove(&node);
+        }
 
         for child in &current_node.children {
-            self.traverse(solved_trs, current_transform.clone(), *child);
+            self.traverse(
+                solution,
+                current_transform.clone(),
+                current_material,
+                Some(node),
+                *child,
+            );
         }
     }
 
-    fn collect_trs(&mut self) {
-        let mut ret = HashMap::new();
-        for node in self.root.children.iter() {
-            self.traverse(&mut ret, self.root.trs.clone(), *node);
+    /// Whether some nodes need to be solved again
+    fn is_dirty(&self) -> bool {
+        !self.clean || !self.dirty_nodes.is_empty()
+    }
+
+    /// Solves the nodes which need it, remembering them so that their primitives
+    /// are collected again
+    fn solve(&mut self) {
+        // Reuse the memory of the previous frame
+        let mut solution = Solution {
+            trs: std::mem::take(&mut self.solved_trs),
+            materials: std::mem::take(&mut self.solved_materials),
+            parents: std::mem::take(&mut self.parents),
+            visited: std::mem::take(&mut self.moved),
+        };
+
+        let full = !self.incremental || !self.clean;
+        if full {
+            solution.trs.clear();
+            solution.materials.clear();
+            solution.parents.clear();
+            solution.visited.clear();
+            for node in self.root.children.iter() {
+                self.traverse(
+                    &mut solution,
+                    self.root.trs.clone(),
+                    self.root.material,
+                    None,
+                    *node,
+                );
+            }
+        } else {
+            for node in std::mem::take(&mut self.dirty_nodes) {
+                // Nodes which are not part of the hierarchy are not solved
+                if !solution.trs.contains_key(&node) {
+                    continue;
+                }
+                let (transform, material) = match solution.parents.get(&node) {
+                    Some(parent) => (
+                        solution.trs.get(parent).unwrap().trs.clone(),
+                        solution
+                            .materials
+                            .get(parent)
+                            .copied()
+                            .unwrap_or(Handle::NONE),
+                    ),
+                    None => (self.root.trs.clone(), self.root.material),
+                };
+                let parent = solution.parents.get(&node).copied();
+                self.traverse(&mut solution, transform, material, parent, node);
+            }
         }
-        self.solved_trs = ret;
+
+        self.solved_trs = solution.trs;
+        self.solved_materials = solution.materials;
+        self.parents = solution.parents;
+        self.moved = solution.visited;
+        self.clean = true;
+        self.dirty_nodes.clear();
+        self.generation += 1;
     }
 
-    pub fn collect(&mut self) -> Vec<BvhPrimitive> {
-        self.collect_trs();
+    /// Returns a number which changes every time nodes are solved, so that what is
+    /// computed out of solved transforms can be cached until the next change
+    pub fn get_generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns the parent of `node`, or `None` for the nodes at the root of the model.
+    /// It expects the model to be collected already
+    pub fn get_parent(&self, node: Handle<Node>) -> Option<Handle<Node>> {
+        self.parents.get(&node).copied()
+    }
+
+    /// Returns the world transform of `node`, solving the nodes marked dirty since the
+    /// last collection first. Nodes which are not part of the hierarchy have none
+    pub fn world_trs(&mut self, node: Handle<Node>) -> Option<&Trs> {
+        if self.is_dirty() {
+            self.solve();
+        }
+        self.solved_trs.get(&node).map(|solved| &solved.trs)
+    }
+
+    /// Solves the world transform and the material override of every node,
+    /// or of the nodes marked dirty only when the model is incremental
+    pub fn collect_trs(&mut self) {
+        self.solve();
+    }
 
+    pub fn collect(&mut self) -> Vec<BvhPrimitive> {
         let mut primitives = vec![];
+        self.collect_into(&mut primitives);
+        primitives
+    }
+
+    /// Keeps the world transforms solved so far as the ones of the previous frame,
+    /// which motion vectors are computed against. Call it before solving a new frame
+    pub fn store_previous_trs(&mut self) {
+        self.previous_trs.clear();
+        self.previous_trs.extend(
+            self.solved_trs
+                .iter()
+                .map(|(node, solved)| (*node, solved.trs.clone())),
+        );
+    }
+
+    /// Returns the world transform of `node` in the previous frame, or
+    /// the current one for nodes which were not there yet
+    pub fn get_previous_trs(&self, node: Handle<Node>) -> Option<&Trs> {
+        self.previous_trs
+            .get(&node)
+            .or_else(|| self.solved_trs.get(&node).map(|solved| &solved.trs))
+    }
+
+    /// Returns position and angle of the rendering camera, which is the first one.
+    /// It expects the model to be collected already
+    pub fn get_render_camera(&self) -> Option<(Vec3, f32)> {
+        self.camera_nodes.first().map(|camera_node_handle| {
+            let camera_node = self.nodes.get(*camera_node_handle).unwrap();
+            let camera = self.cameras.get(camera_node.camera).unwrap();
+            let camera_trs = &self.solved_trs.get(camera_node_handle).unwrap().trs;
+            (camera_trs.translation, camera.get_angle())
+        })
+    }
+
+    /// Returns the primitives of `node`, transformed by its solved transform
+    fn collect_node(
+        &self,
+        node_handle: Handle<Node>,
+        camera: Option<(Vec3, f32)>,
+    ) -> Vec<BvhPrimitive> {
+        let mut ret = vec![];
+        let node = self.nodes.get(node_handle).unwrap();
+        let solved_trs = self.solved_trs.get(&node_handle).unwrap();
+        let mesh_handle = node.get_mesh(&solved_trs.trs, camera);
+        let material_override = self.solved_materials.get(&node_handle);
+        if let Some(mesh) = self.meshes.get(mesh_handle) {
+            for prim_handle in mesh.primitives.iter() {
+                let prim = self.primitives.get(*prim_handle).unwrap();
+                let material = *material_override.unwrap_or(&prim.material);
+                let mut prims = prim.primitives(node_handle, material, self);
+                for bvh_prim in &mut prims {
+                    bvh_prim.primitive = *prim_handle;
+                }
+                ret.extend(prims);
+            }
+        }
+        if let Some(particles) = &node.particles {
+            let material = material_override.copied().unwrap_or(Handle::NONE);
+            ret.extend(particles.primitives(node_handle, material, self));
+        }
+        ret
+    }
+
+    /// Extends the light links of the light nodes to the descendants of their nodes
+    fn solve_light_links(&mut self) {
+        self.solved_light_links.clear();
+        for light_node_handle in &self.light_nodes {
+            let light_link = &self.nodes.get(*light_node_handle).unwrap().light_link;
+            if light_link.is_empty() {
+                continue;
+            }
+            let solved = LightLink {
+                include: self.get_descendants(&light_link.include),
+                exclude: self.get_descendants(&light_link.exclude),
+            };
+            self.solved_light_links.insert(*light_node_handle, solved);
+        }
+    }
+
+    /// Returns `nodes` together with all of their descendants
+    fn get_descendants(&self, nodes: &HashSet<Handle<Node>>) -> HashSet<Handle<Node>> {
+        let mut ret = HashSet::new();
+        let mut stack: Vec<_> = nodes.iter().copied().collect();
+        while let Some(node_handle) = stack.pop() {
+            if ret.insert(node_handle) && self.nodes.contains(node_handle) {
+                stack.extend(&self.nodes.get(node_handle).unwrap().children);
+            }
+        }
+        ret
+    }
+
+    /// Returns whether the light of `light_node` lights the primitives of `node`,
+    /// according to its light link
+    pub fn is_linked(&self, light_node: Handle<Node>, node: Handle<Node>) -> bool {
+        self.solved_light_links
+            .get(&light_node)
+            .is_none_or(|light_link| light_link.contains(node))
+    }
+
+    /// Same as `collect()`, but appends the primitives to an existing vector
+    /// so that its memory can be reused from one frame to the next
+    pub fn collect_into(&mut self, primitives: &mut Vec<BvhPrimitive>) {
+        self.solve();
+
         self.camera_nodes.clear();
         self.light_nodes.clear();
 
-        for (node_handle, _) in &self.solved_trs {
-            // Collect primitives
+        for node_handle in self.solved_trs.keys() {
             let node = self.nodes.get(*node_handle).unwrap();
-            if let Some(mesh) = self.meshes.get(node.mesh) {
-                for prim_handle in mesh.primitives.iter() {
-                    let prim = self.primitives.get(*prim_handle).unwrap();
-                    let prims = prim.primitives(*node_handle, prim.material, self);
-                    primitives.extend(prims);
-                }
-            }
 
             // Collect cameras
             if node.camera.valid() {
@@ -728,7 +1661,42 @@ impl Model {
             }
         }
 
-        primitives
+        // Keep the order stable as the first camera is used for rendering
+        self.camera_nodes.sort_by_key(|handle| handle.id);
+        self.light_nodes.sort_by_key(|handle| handle.id);
+        self.light_tree = LightTree::new(self, &self.light_nodes);
+        self.sky_distribution = SkyDistribution::new(self);
+        self.solve_light_links();
+
+        // The rendering camera selects the level of detail of nodes
+        let camera = self.get_render_camera();
+
+        if !self.incremental {
+            self.collected.clear();
+            self.moved.clear();
+            for node_handle in self.solved_trs.keys() {
+                primitives.extend(self.collect_node(*node_handle, camera));
+            }
+            return;
+        }
+
+        // Levels of detail and tessellations change with the camera
+        let moved = std::mem::take(&mut self.moved);
+        let nodes = if self.collected.is_empty() || camera != self.collected_camera {
+            self.collected.clear();
+            self.solved_trs.keys().copied().collect()
+        } else {
+            moved
+        };
+        for node_handle in nodes {
+            let node_primitives = self.collect_node(node_handle, camera);
+            self.collected.insert(node_handle, node_primitives);
+        }
+        self.collected_camera = camera;
+
+        for node_primitives in self.collected.values() {
+            primitives.extend(node_primitives.iter().cloned());
+        }
     }
 }
 
@@ -736,6 +1704,371 @@ impl Model {
 mod test {
     use super::*;
 
+    #[test]
+    fn triangulate() {
+        assert_eq!(
+            triangulate_strip(&[0, 1, 2, 3, 4]),
+            [0, 1, 2, 2, 1, 3, 2, 3, 4]
+        );
+        // Degenerate triangles restarting the strip are skipped
+        assert_eq!(triangulate_strip(&[0, 1, 2, 2, 3, 4]), [0, 1, 2, 3, 2, 4]);
+        assert_eq!(triangulate_fan(&[0, 1, 2, 3]), [1, 2, 0, 2, 3, 0]);
+        assert!(triangulate_fan(&[0, 1]).is_empty());
+
+        let lines = get_line_segments(gltf::mesh::Mode::Lines, &[0, 1, 2, 3]);
+        assert_eq!(lines, [[0, 1], [2, 3]]);
+        let strip = get_line_segments(gltf::mesh::Mode::LineStrip, &[0, 1, 2]);
+        assert_eq!(strip, [[0, 1], [1, 2]]);
+        let lines = get_line_segments(gltf::mesh::Mode::LineLoop, &[0, 1, 2]);
+        assert_eq!(lines, [[0, 1], [1, 2], [2, 0]]);
+    }
+
+    #[test]
+    fn primitive_modes() {
+        // Quad of 4 vertices drawn as a strip without indices, as points, and as a loop
+        let positions = [
+            [0.0f32, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+        ];
+        let bytes = positions
+            .iter()
+            .flatten()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<u8>>();
+        let gltf = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "buffers": [{{
+                    "byteLength": 48,
+                    "uri": "data:application/octet-stream;base64,{}"
+                }}],
+                "bufferViews": [{{ "buffer": 0, "byteLength": 48 }}],
+                "accessors": [{{
+                    "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3",
+                    "min": [0, 0, 0], "max": [1, 1, 0]
+                }}],
+                "meshes": [{{ "primitives": [
+                    {{ "attributes": {{ "POSITION": 0 }}, "mode": 5 }},
+                    {{ "attributes": {{ "POSITION": 0 }}, "mode": 0 }},
+                    {{ "attributes": {{ "POSITION": 0 }}, "mode": 2 }}
+                ] }}],
+                "nodes": [{{ "mesh": 0 }}],
+                "scenes": [{{ "nodes": [0] }}]
+            }}"#,
+            base64::encode(bytes)
+        );
+
+        let model = Model::builder()
+            .data(gltf.as_bytes())
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(model.primitives.len(), 3);
+        let get_geometry = |i| &model.primitives.get(Handle::new(i)).unwrap().geometry;
+        let Geometry::Triangles(triangles) = get_geometry(0) else {
+            panic!("Expected triangles");
+        };
+        assert_eq!(triangles.get_indices(), [0, 1, 2, 2, 1, 3]);
+        let Geometry::PointCloud(point_cloud) = get_geometry(1) else {
+            panic!("Expected points");
+        };
+        assert_eq!(point_cloud.len(), 4);
+        let Geometry::Curves(curves) = get_geometry(2) else {
+            panic!("Expected curves");
+        };
+        assert_eq!(curves.curves.len(), 4);
+        assert_eq!(curves.curves[3].points[3], Point3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn sparse() {
+        // Triangle whose second vertex is moved by a sparse accessor
+        let mut bytes = [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+            .iter()
+            .flatten()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<u8>>();
+        bytes.extend([1, 0, 0, 0]);
+        bytes.extend(
+            [2.0f32, 0.0, 0.0]
+                .iter()
+                .flat_map(|value| value.to_le_bytes()),
+        );
+        let accessor = |sparse: &str| {
+            format!(
+                r#"{{
+                    "componentType": 5126, "count": 3, "type": "VEC3",
+                    "min": [0, 0, 0], "max": [2, 1, 0],
+                    "sparse": {{
+                        "count": 1,
+                        "indices": {{ "bufferView": 1, "componentType": 5121 }},
+                        "values": {{ "bufferView": 2 }}
+                    }}{}
+                }}"#,
+                sparse
+            )
+        };
+        let gltf = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "buffers": [{{
+                    "byteLength": 64,
+                    "uri": "data:application/octet-stream;base64,{}"
+                }}],
+                "bufferViews": [
+                    {{ "buffer": 0, "byteLength": 36 }},
+                    {{ "buffer": 0, "byteOffset": 36, "byteLength": 1 }},
+                    {{ "buffer": 0, "byteOffset": 40, "byteLength": 12 }}
+                ],
+                "accessors": [{}, {}],
+                "meshes": [{{ "primitives": [
+                    {{ "attributes": {{ "POSITION": 0 }} }},
+                    {{ "attributes": {{ "POSITION": 1 }} }}
+                ] }}],
+                "nodes": [{{ "mesh": 0 }}],
+                "scenes": [{{ "nodes": [0] }}]
+            }}"#,
+            base64::encode(&bytes[..52]),
+            accessor(r#", "bufferView": 0"#),
+            accessor("")
+        );
+
+        let model = Model::builder()
+            .data(gltf.as_bytes())
+            .unwrap()
+            .build()
+            .unwrap();
+        let get_positions = |i| {
+            let Geometry::Triangles(triangles) =
+                &model.primitives.get(Handle::new(i)).unwrap().geometry
+            else {
+                panic!("Expected triangles");
+            };
+            triangles.vertices.iter().map(|v| v.pos).collect::<Vec<_>>()
+        };
+        let positions = get_positions(0);
+        assert_eq!(positions[1], Point3::new(2.0, 0.0, 0.0));
+        assert_eq!(positions[2], Point3::new(0.0, 1.0, 0.0));
+        // Without a view, other elements are zeros
+        let positions = get_positions(1);
+        assert_eq!(positions[1], Point3::new(2.0, 0.0, 0.0));
+        assert_eq!(positions[2], Point3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn malformed() {
+        // Triangle with texture coordinates stored as normalized bytes
+        let mut bytes = [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+            .iter()
+            .flatten()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<u8>>();
+        bytes.extend([0, 0, 255, 0, 0, 255, 0, 0]);
+        // Indices 0, 1, 3 where the last one is past the vertex count
+        bytes.extend([0, 1, 3, 0]);
+        let load = |position_view_len: usize, indices: &str, scenes: &str| {
+            let gltf = format!(
+                r#"{{
+                    "asset": {{ "version": "2.0" }},
+                    "buffers": [{{
+                        "byteLength": 48,
+                        "uri": "data:application/octet-stream;base64,{}"
+                    }}],
+                    "bufferViews": [
+                        {{ "buffer": 0, "byteLength": {} }},
+                        {{ "buffer": 0, "byteOffset": 36, "byteLength": 6 }},
+                        {{ "buffer": 0, "byteOffset": 44, "byteLength": 3 }}
+                    ],
+                    "accessors": [
+                        {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                            "min": [0, 0, 0], "max": [1, 1, 0] }},
+                        {{ "bufferView": 1, "componentType": 5121, "normalized": true,
+                            "count": 3, "type": "VEC2" }},
+                        {{ "bufferView": 2, "componentType": 5121, "count": 3, "type": "SCALAR" }}
+                    ],
+                    "meshes": [{{ "primitives": [
+                        {{ "attributes": {{ "POSITION": 0, "TEXCOORD_0": 1 }}{} }}
+                    ] }}],
+                    "nodes": [{{ "mesh": 0 }}]{}
+                }}"#,
+                base64::encode(&bytes),
+                position_view_len,
+                indices,
+                scenes
+            );
+            Model::builder().data(gltf.as_bytes())?.build()
+        };
+
+        let scenes = r#", "scenes": [{ "nodes": [0] }]"#;
+        let model = load(36, "", scenes).unwrap();
+        let Geometry::Triangles(triangles) =
+            &model.primitives.get(Handle::new(0)).unwrap().geometry
+        else {
+            panic!("Expected triangles");
+        };
+        assert_eq!(triangles.vertices[1].ext.uv, Vec2::new(1.0, 0.0));
+        assert_eq!(triangles.vertices[2].ext.uv, Vec2::new(0.0, 1.0));
+
+        // Accessors larger than their views, and files without scenes, are errors
+        assert!(matches!(load(24, "", scenes), Err(RaycaError::Parse(_))));
+        assert!(matches!(load(36, "", ""), Err(RaycaError::Parse(_))));
+
+        // Indices past the vertex count are invalid geometry
+        assert!(matches!(
+            load(36, r#", "indices": 2"#, scenes),
+            Err(RaycaError::InvalidGeometry(_))
+        ));
+    }
+
+    #[test]
+    fn glb() {
+        let mut bin = [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+            .iter()
+            .flatten()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<u8>>();
+        // Texture stored in a buffer view after the positions
+        let mut png = Image::new(2, 1, ColorType::RGBA8);
+        png.set(1, 0, RGBA8::new(255, 0, 0, 255));
+        let png = png.encode_png();
+        bin.extend(&png);
+        while !bin.len().is_multiple_of(4) {
+            bin.push(0);
+        }
+        let mut json = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "buffers": [{{ "byteLength": {} }}],
+                "bufferViews": [
+                    {{ "buffer": 0, "byteLength": 36 }},
+                    {{ "buffer": 0, "byteOffset": 36, "byteLength": {} }}
+                ],
+                "images": [{{ "bufferView": 1, "mimeType": "image/png" }}],
+                "accessors": [{{
+                    "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                    "min": [0, 0, 0], "max": [1, 1, 0]
+                }}],
+                "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }} }}] }}],
+                "nodes": [{{ "mesh": 0 }}],
+                "scenes": [{{ "nodes": [0] }}]
+            }}"#,
+            bin.len(),
+            png.len()
+        )
+        .into_bytes();
+        // Chunks are aligned to four bytes
+        while !json.len().is_multiple_of(4) {
+            json.push(b' ');
+        }
+
+        let mut glb = b"glTF".to_vec();
+        glb.extend(2u32.to_le_bytes());
+        glb.extend(((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes());
+        glb.extend((json.len() as u32).to_le_bytes());
+        glb.extend(b"JSON");
+        glb.extend(&json);
+        glb.extend((bin.len() as u32).to_le_bytes());
+        glb.extend(b"BIN\0");
+        glb.extend(&bin);
+
+        let model = Model::builder().data(&glb).unwrap().build().unwrap();
+        let Geometry::Triangles(triangles) =
+            &model.primitives.get(Handle::new(0)).unwrap().geometry
+        else {
+            panic!("Expected triangles");
+        };
+        assert_eq!(triangles.vertices[2].pos, Point3::new(0.0, 1.0, 0.0));
+        let image = model.images.get(Handle::new(0)).unwrap();
+        assert_eq!(image.get::<RGBA8>(1, 0), RGBA8::new(255, 0, 0, 255));
+    }
+
+    #[test]
+    fn rayca_primitives() {
+        let gltf = r#"{
+            "asset": { "version": "2.0" },
+            "extensionsUsed": ["RAYCA_primitives"],
+            "materials": [{ "pbrMetallicRoughness": { "baseColorFactor": [1, 0, 0, 1] } }],
+            "nodes": [
+                {
+                    "extensions": { "RAYCA_primitives": { "spheres": [
+                        { "center": [0, 1, 0], "radius": 0.5, "material": 0 },
+                        { "center": [2, 0, 0] }
+                    ] } }
+                },
+                {
+                    "translation": [0, 4, 0],
+                    "extensions": { "RAYCA_primitives": {
+                        "quadLight": { "width": 2, "height": 3, "intensity": 8 }
+                    } }
+                }
+            ],
+            "scenes": [{ "nodes": [0, 1] }]
+        }"#;
+
+        let model = Model::builder()
+            .data(gltf.as_bytes())
+            .unwrap()
+            .build()
+            .unwrap();
+        let node = model.nodes.get(Handle::new(0)).unwrap();
+        let mesh = model.meshes.get(node.mesh).unwrap();
+        assert_eq!(mesh.primitives.len(), 2);
+        let primitive = model.primitives.get(mesh.primitives[0]).unwrap();
+        let Geometry::Sphere(sphere) = &primitive.geometry else {
+            panic!("Expected a sphere");
+        };
+        assert_eq!(sphere.center, Point3::new(0.0, 1.0, 0.0));
+        assert_eq!(sphere.get_radius(), 0.5);
+        assert_eq!(primitive.material, Handle::new(0));
+        let primitive = model.primitives.get(mesh.primitives[1]).unwrap();
+        assert!(!primitive.material.valid());
+
+        let node = model.nodes.get(Handle::new(1)).unwrap();
+        let Some(Light::Quad(quad)) = model.lights.get(node.light) else {
+            panic!("Expected a quad light");
+        };
+        assert_eq!((quad.width, quad.height), (2.0, 3.0));
+
+        // Materials must exist
+        let invalid = gltf.replace(r#""material": 0"#, r#""material": 1"#);
+        let result = Model::builder().data(invalid.as_bytes()).unwrap().build();
+        assert!(matches!(result, Err(RaycaError::InvalidHandle(_))));
+    }
+
+    #[test]
+    fn extras() {
+        let gltf = r#"{
+            "asset": { "version": "2.0" },
+            "extras": { "pipeline": "lookdev" },
+            "materials": [{ "extras": { "shader": "car_paint", "layers": [1, 2] } }],
+            "nodes": [{ "extras": { "tag": "hero", "lod": 2 } }, { "extras": 7 }],
+            "scenes": [{ "nodes": [0, 1] }]
+        }"#;
+
+        let model = Model::builder()
+            .data(gltf.as_bytes())
+            .unwrap()
+            .build()
+            .unwrap();
+        let material = model.materials.get(Handle::new(0)).unwrap();
+        assert_eq!(material.extras["shader"], r#""car_paint""#);
+        assert_eq!(material.extras["layers"], "[1,2]");
+        let node = model.nodes.get(Handle::new(0)).unwrap();
+        assert_eq!(node.extras["tag"], r#""hero""#);
+        assert_eq!(node.extras["lod"], "2");
+        // Extras which are not objects are kept whole
+        let node = model.nodes.get(Handle::new(1)).unwrap();
+        assert_eq!(node.extras[""], "7");
+
+        // Extras of the file stay with the appended model
+        let mut scene = Model::new();
+        let handles = scene.append(model);
+        let root = scene.nodes.get(handles.root).unwrap();
+        assert_eq!(root.extras["pipeline"], r#""lookdev""#);
+    }
+
     #[test]
     fn load() {
         let model = Model::builder()
@@ -746,4 +2079,161 @@ mod test {
 
         assert!(model.images.len() == 2);
     }
+
+    #[test]
+    fn material_override() {
+        let create_instance = || {
+            let mut instance = Model::new();
+            let material = instance.materials.push(Material::new());
+            let primitive = instance.primitives.push(
+                Primitive::builder()
+                    .sphere(Point3::default(), 1.0)
+                    .material(material)
+                    .build(),
+            );
+            let mesh = instance.meshes.push(Mesh::new(vec![primitive]));
+            let node = instance.nodes.push(Node::builder().mesh(mesh).build());
+            instance.root.children.push(node);
+            instance
+        };
+
+        let mut model = Model::new();
+        let red = model.materials.push(
+            Material::builder()
+                .color(Color::new(1.0, 0.0, 0.0, 1.0))
+                .build(),
+        );
+        let plain = model.append(create_instance());
+        let recolored = model.append(create_instance());
+        model.nodes.get_mut(recolored.root).unwrap().material = red;
+
+        let primitives = model.collect();
+        assert_eq!(primitives.len(), 2);
+        for primitive in primitives {
+            if primitive.node == plain.nodes[0] {
+                assert!(primitive.material == plain.materials[0]);
+            } else {
+                assert!(primitive.node == recolored.nodes[0]);
+                assert!(primitive.material == red);
+            }
+        }
+    }
+
+    #[test]
+    fn incremental() {
+        let mut model = Model::new();
+        let mesh = model
+            .primitives
+            .push(Primitive::builder().sphere(Point3::default(), 1.0).build());
+        let mesh = model.meshes.push(Mesh::new(vec![mesh]));
+        let child = model.nodes.push(Node::builder().mesh(mesh).build());
+        let parent = model
+            .nodes
+            .push(Node::builder().children(vec![child]).build());
+        model.root.children.push(parent);
+        model.incremental = true;
+        assert_eq!(model.collect().len(), 1);
+
+        let get_translation = |model: &Model| model.solved_trs.get(&child).unwrap().translation;
+
+        // Changes are ignored until nodes are marked
+        model.nodes.get_mut(parent).unwrap().trs.translation = Vec3::new(1.0, 0.0, 0.0);
+        assert_eq!(model.collect().len(), 1);
+        assert_eq!(get_translation(&model), Vec3::default());
+
+        // Descendants of marked nodes are solved too
+        model.mark_dirty(parent);
+        assert_eq!(model.collect().len(), 1);
+        assert_eq!(get_translation(&model), Vec3::new(1.0, 0.0, 0.0));
+
+        // Structural changes solve everything again
+        model.root.children.clear();
+        model.mark_all_dirty();
+        assert!(model.collect().is_empty());
+    }
+
+    #[test]
+    fn light_link() {
+        let mut model = Model::new();
+        let child = model.nodes.push(Node::new());
+        let parent = model
+            .nodes
+            .push(Node::builder().children(vec![child]).build());
+        let other = model.nodes.push(Node::new());
+
+        let mut push_light = |light_link: LightLink| {
+            let light = model.lights.push(Light::point());
+            let node = Node::builder().light(light).light_link(light_link).build();
+            model.nodes.push(node)
+        };
+        let all = push_light(LightLink::default());
+        let key = push_light(LightLink {
+            include: HashSet::from([parent]),
+            ..Default::default()
+        });
+        let fill = push_light(LightLink {
+            exclude: HashSet::from([parent]),
+            ..Default::default()
+        });
+        model.root.children = vec![parent, other, all, key, fill];
+        model.collect();
+
+        assert!(model.is_linked(all, child) && model.is_linked(all, other));
+        // Links extend to the descendants of linked nodes
+        assert!(model.is_linked(key, child) && !model.is_linked(key, other));
+        assert!(!model.is_linked(fill, child) && model.is_linked(fill, other));
+    }
+
+    #[test]
+    fn world_trs() {
+        let mut model = Model::new();
+        let child = model.nodes.push(Node::builder().build());
+        let parent = model.nodes.push(
+            Node::builder()
+                .children(vec![child])
+                .translation(Vec3::new(0.0, 1.0, 0.0))
+                .build(),
+        );
+        model.root.children.push(parent);
+
+        let translation = model.world_trs(child).unwrap().translation;
+        assert_eq!(translation, Vec3::new(0.0, 1.0, 0.0));
+        assert!(model.get_parent(child) == Some(parent));
+        assert!(model.get_parent(parent).is_none());
+
+        // Cached transforms are solved again once nodes are marked
+        let generation = model.get_generation();
+        model.world_trs(child);
+        assert_eq!(model.get_generation(), generation);
+        model.nodes.get_mut(parent).unwrap().trs.translation = Vec3::new(2.0, 0.0, 0.0);
+        model.mark_dirty(parent);
+        let translation = model.world_trs(child).unwrap().translation;
+        assert_eq!(translation, Vec3::new(2.0, 0.0, 0.0));
+        assert!(model.get_generation() > generation);
+    }
+
+    #[test]
+    fn node_matrix() {
+        // Rotation of 90 degrees around Y, scale of (2, 3, 4), and translation of (1, 2, 3)
+        let gltf = r#"{
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{
+                "matrix": [0, 0, -2, 0, 0, 3, 0, 0, 4, 0, 0, 0, 1, 2, 3, 1]
+            }]
+        }"#;
+        let model = Model::builder()
+            .data(gltf.as_bytes())
+            .unwrap()
+            .build()
+            .unwrap();
+        let trs = &model.nodes.get(Handle::new(0)).unwrap().trs;
+        assert!(trs.translation.close(&Vec3::new(1.0, 2.0, 3.0)));
+        assert!(trs.scale.close(&Vec3::new(2.0, 3.0, 4.0)));
+        let rotation = Quat::axis_angle(Vec3::new(0.0, 1.0, 0.0), std::f32::consts::FRAC_PI_2);
+        assert!(trs.rotation.dot(&rotation).abs() > 0.9999);
+
+        let point = trs * Point3::new(1.0, 1.0, 1.0);
+        assert!(Vec3::from(point).close(&Vec3::new(5.0, 5.0, 1.0)));
+    }
 }
@@ -2,10 +2,104 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
-use std::hash::Hash;
+use std::{
+    collections::{BTreeMap, HashSet},
+    hash::Hash,
+};
 
 use super::*;
 
+/// Custom properties which pipeline tools attach to nodes and materials, such as glTF
+/// extras. Values are JSON text, so that they can be passed on without being understood
+pub type Extras = BTreeMap<String, String>;
+
+/// A level of detail of a node
+#[derive(Clone)]
+pub struct Lod {
+    pub mesh: Handle<Mesh>,
+
+    /// Minimum fraction of the screen height the node needs to cover to use this mesh
+    pub screen_size: f32,
+}
+
+impl Lod {
+    pub fn new(mesh: Handle<Mesh>, screen_size: f32) -> Self {
+        Self { mesh, screen_size }
+    }
+}
+
+/// Meshes of decreasing detail, selected by how large the node appears on screen
+#[derive(Clone, Default)]
+pub struct LodGroup {
+    /// Levels from the most detailed, with decreasing screen sizes.
+    /// A node smaller than the last screen size is not drawn at all,
+    /// so use zero for the last level to always draw something
+    pub levels: Vec<Lod>,
+
+    /// Radius of a sphere bounding the meshes in model space
+    pub radius: f32,
+}
+
+impl LodGroup {
+    pub fn new(levels: Vec<Lod>, radius: f32) -> Self {
+        Self { levels, radius }
+    }
+
+    /// Returns the fraction of the screen height covered by the node
+    pub fn get_screen_size(&self, trs: &Trs, camera_position: Vec3, camera_angle: f32) -> f32 {
+        let scale = trs
+            .scale
+            .get_x()
+            .abs()
+            .max(trs.scale.get_y().abs())
+            .max(trs.scale.get_z().abs());
+        let distance = (trs.translation - camera_position).len().max(f32::EPSILON);
+        self.radius * scale / (distance * camera_angle)
+    }
+
+    /// Returns the mesh to draw when the node covers `screen_size` of the screen height
+    pub fn select(&self, screen_size: f32) -> Handle<Mesh> {
+        self.levels
+            .iter()
+            .find(|lod| screen_size >= lod.screen_size)
+            .map_or(Handle::NONE, |lod| lod.mesh)
+    }
+}
+
+/// Nodes lit by the light of a node, where sets also contain the descendants
+/// of their nodes once solved by `Model::collect()`
+#[derive(Clone, Default)]
+pub struct LightLink {
+    /// When not empty, only these nodes are lit
+    pub include: HashSet<Handle<Node>>,
+    /// These nodes are never lit
+    pub exclude: HashSet<Handle<Node>>,
+}
+
+impl LightLink {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Maps the handles of the sets to `nodes`, like `Handle::remap()`
+    pub fn remap(&mut self, nodes: &[Handle<Node>]) {
+        for set in [&mut self.include, &mut self.exclude] {
+            *set = set
+                .drain()
+                .map(|mut handle| {
+                    handle.remap(nodes);
+                    handle
+                })
+                .collect();
+        }
+    }
+
+    /// Returns whether `node` is lit according to these sets
+    pub fn contains(&self, node: Handle<Node>) -> bool {
+        (self.include.is_empty() || self.include.contains(&node)) && !self.exclude.contains(&node)
+    }
+}
+
 pub struct NodeBuilder {
     pub id: usize,
     pub name: String,
@@ -14,6 +108,11 @@ pub struct NodeBuilder {
     pub mesh: Handle<Mesh>,
     pub camera: Handle<Camera>,
     pub light: Handle<Light>,
+    pub lod: Option<LodGroup>,
+    pub material: Handle<Material>,
+    pub light_link: LightLink,
+    pub particles: Option<ParticleSystem>,
+    pub extras: Extras,
 }
 
 impl NodeBuilder {
@@ -26,6 +125,11 @@ impl NodeBuilder {
             mesh: Handle::NONE,
             camera: Handle::NONE,
             light: Handle::NONE,
+            lod: None,
+            material: Handle::NONE,
+            light_link: LightLink::default(),
+            particles: None,
+            extras: Extras::new(),
         }
     }
 
@@ -60,9 +164,7 @@ impl NodeBuilder {
     }
 
     pub fn matrix(mut self, matrix: Mat4) -> Self {
-        self.trs.scale = matrix.get_scale();
-        self.trs.rotation = matrix.get_rotation();
-        self.trs.translation = matrix.get_translation();
+        self.trs = matrix.decompose();
         self
     }
 
@@ -86,6 +188,31 @@ impl NodeBuilder {
         self
     }
 
+    pub fn lod(mut self, lod: LodGroup) -> Self {
+        self.lod = Some(lod);
+        self
+    }
+
+    pub fn material(mut self, material: Handle<Material>) -> Self {
+        self.material = material;
+        self
+    }
+
+    pub fn light_link(mut self, light_link: LightLink) -> Self {
+        self.light_link = light_link;
+        self
+    }
+
+    pub fn particles(mut self, particles: ParticleSystem) -> Self {
+        self.particles = Some(particles);
+        self
+    }
+
+    pub fn extras(mut self, extras: Extras) -> Self {
+        self.extras = extras;
+        self
+    }
+
     pub fn build(self) -> Node {
         let mut node = Node::new();
         node.id = self.id;
@@ -97,6 +224,11 @@ impl NodeBuilder {
         node.mesh = self.mesh;
         node.camera = self.camera;
         node.light = self.light;
+        node.lod = self.lod;
+        node.material = self.material;
+        node.light_link = self.light_link;
+        node.particles = self.particles;
+        node.extras = self.extras;
 
         node
     }
@@ -115,8 +247,19 @@ pub struct Node {
     pub camera: Handle<Camera>,
     pub light: Handle<Light>,
     pub mesh: Handle<Mesh>,
+    /// When present, it replaces `mesh` while rendering from a camera
+    pub lod: Option<LodGroup>,
     pub trs: Trs,
     pub children: Vec<Handle<Node>>,
+    /// When valid, it replaces the material of every primitive of this node
+    /// and of its descendants, unless one of them has its own override
+    pub material: Handle<Material>,
+    /// Nodes lit by the light of this node, which lights all of them by default
+    pub light_link: LightLink,
+    /// Copies of a template mesh drawn together with `mesh`
+    pub particles: Option<ParticleSystem>,
+    /// Properties which are not used for rendering, kept for pipeline tools
+    pub extras: Extras,
 }
 
 impl Node {
@@ -130,6 +273,17 @@ impl Node {
             ..Default::default()
         }
     }
+
+    /// Returns the mesh to draw for this node, selected from its LOD group
+    /// when seen from a camera at `camera_position` with `camera_angle`
+    pub fn get_mesh(&self, trs: &Trs, camera: Option<(Vec3, f32)>) -> Handle<Mesh> {
+        match (&self.lod, camera) {
+            (Some(lod), Some((camera_position, camera_angle))) => {
+                lod.select(lod.get_screen_size(trs, camera_position, camera_angle))
+            }
+            _ => self.mesh,
+        }
+    }
 }
 
 impl Hash for Node {
@@ -145,3 +299,27 @@ impl PartialEq for Node {
 }
 
 impl Eq for Node {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lod() {
+        let high = Handle::new(0);
+        let low = Handle::new(1);
+        let node = Node::builder()
+            .mesh(high)
+            .lod(LodGroup::new(
+                vec![Lod::new(high, 0.5), Lod::new(low, 0.1)],
+                1.0,
+            ))
+            .build();
+
+        let trs = Trs::default();
+        assert!(node.get_mesh(&trs, None) == high);
+        assert!(node.get_mesh(&trs, Some((Vec3::new(0.0, 0.0, 1.0), 1.0))) == high);
+        assert!(node.get_mesh(&trs, Some((Vec3::new(0.0, 0.0, 4.0), 1.0))) == low);
+        assert!(node.get_mesh(&trs, Some((Vec3::new(0.0, 0.0, 64.0), 1.0))) == Handle::NONE);
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Callbacks notified when the scene changes, so that renderers and caches can update
+//! what changed instead of starting from scratch. Only changes made through the methods
+//! of `Scene` are notified, not the ones made by writing its model directly.
+
+use crate::*;
+
+/// Callback receiving the model after the node was changed
+pub type NodeCallback = Box<dyn FnMut(&Model, Handle<Node>) + Send + Sync>;
+
+/// Callback receiving the model after the material was changed
+pub type MaterialCallback = Box<dyn FnMut(&Model, Handle<Material>) + Send + Sync>;
+
+/// Callbacks subscribed to the changes of a scene
+#[derive(Default)]
+pub struct SceneObservers {
+    node_added: Vec<NodeCallback>,
+    transform_changed: Vec<NodeCallback>,
+    material_changed: Vec<MaterialCallback>,
+}
+
+impl SceneObservers {
+    pub fn is_empty(&self) -> bool {
+        self.node_added.is_empty()
+            && self.transform_changed.is_empty()
+            && self.material_changed.is_empty()
+    }
+
+    pub fn notify_node_added(&mut self, model: &Model, node: Handle<Node>) {
+        for callback in &mut self.node_added {
+            callback(model, node);
+        }
+    }
+
+    /// Notifies every node of the model appended with `handles`, starting from its root
+    pub fn notify_model_added(&mut self, model: &Model, handles: &ModelHandles) {
+        self.notify_node_added(model, handles.root);
+        for node in &handles.nodes {
+            self.notify_node_added(model, *node);
+        }
+    }
+
+    pub fn notify_transform_changed(&mut self, model: &Model, node: Handle<Node>) {
+        for callback in &mut self.transform_changed {
+            callback(model, node);
+        }
+    }
+
+    pub fn notify_material_changed(&mut self, model: &Model, material: Handle<Material>) {
+        for callback in &mut self.material_changed {
+            callback(model, material);
+        }
+    }
+}
+
+impl Scene {
+    /// Calls `callback` for every node added to the scene, including the roots
+    /// grouping the nodes of appended models
+    pub fn on_node_added<F: FnMut(&Model, Handle<Node>) + Send + Sync + 'static>(
+        &mut self,
+        callback: F,
+    ) {
+        self.observers.node_added.push(Box::new(callback));
+    }
+
+    /// Calls `callback` for every node whose transform is changed by `set_trs()`
+    pub fn on_transform_changed<F: FnMut(&Model, Handle<Node>) + Send + Sync + 'static>(
+        &mut self,
+        callback: F,
+    ) {
+        self.observers.transform_changed.push(Box::new(callback));
+    }
+
+    /// Calls `callback` for every material changed by `set_material()`
+    pub fn on_material_changed<F: FnMut(&Model, Handle<Material>) + Send + Sync + 'static>(
+        &mut self,
+        callback: F,
+    ) {
+        self.observers.material_changed.push(Box::new(callback));
+    }
+
+    /// Sets the local transform of `node`, returning whether the node exists
+    pub fn set_trs(&mut self, node: Handle<Node>, trs: Trs) -> bool {
+        let Some(node_ref) = self.model.nodes.get_mut(node) else {
+            return false;
+        };
+        node_ref.trs = trs;
+        self.model.mark_dirty(node);
+        self.observers.notify_transform_changed(&self.model, node);
+        true
+    }
+
+    /// Replaces `material`, returning whether the material exists
+    pub fn set_material(&mut self, handle: Handle<Material>, material: Material) -> bool {
+        let Some(material_ref) = self.model.materials.get_mut(handle) else {
+            return false;
+        };
+        // Displacement changes the geometry of the nodes using the material
+        let displaced = material_ref.displacement_texture != material.displacement_texture
+            || material_ref.displacement_scale != material.displacement_scale;
+        *material_ref = material;
+        if displaced {
+            self.model.mark_all_dirty();
+        }
+        self.observers.notify_material_changed(&self.model, handle);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn notify() {
+        let mut scene = Scene::new();
+        let added = Arc::new(Mutex::new(vec![]));
+        let moved = Arc::new(Mutex::new(vec![]));
+        let changed = Arc::new(Mutex::new(vec![]));
+        {
+            let added = added.clone();
+            scene.on_node_added(move |_, node| added.lock().unwrap().push(node));
+            let moved = moved.clone();
+            scene.on_transform_changed(move |model, node| {
+                let translation = model.nodes.get(node).unwrap().trs.translation;
+                moved.lock().unwrap().push(translation);
+            });
+            let changed = changed.clone();
+            scene.on_material_changed(move |_, material| changed.lock().unwrap().push(material));
+        }
+
+        scene.push_default_model();
+        // The root of the default model, its camera, and its two lights
+        assert_eq!(added.lock().unwrap().len(), 4);
+
+        let camera = scene.get_camera_node_handle().unwrap();
+        let trs = Trs::builder().translation(Vec3::new(1.0, 2.0, 3.0)).build();
+        assert!(scene.set_trs(camera, trs));
+        assert_eq!(*moved.lock().unwrap(), vec![Vec3::new(1.0, 2.0, 3.0)]);
+
+        let material = scene.model.materials.push(Material::new());
+        assert!(scene.set_material(material, Material::WHITE));
+        assert!(*changed.lock().unwrap() == vec![material]);
+    }
+}
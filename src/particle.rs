@@ -0,0 +1,207 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Particle systems drawing many copies of a template mesh, each one with its own
+//! transform and color, which can be animated by a simple simulation between frames.
+
+use std::sync::Arc;
+
+use crate::*;
+
+/// Callback moving the particles of a system forward by a delta time in seconds
+pub type ParticleUpdate = dyn Fn(&mut [Particle], f32) + Send + Sync;
+
+/// A copy of the template mesh of a particle system
+#[derive(Clone, Default)]
+pub struct Particle {
+    /// Transform relative to the node of the particle system
+    pub trs: Trs,
+    /// Multiplies the color of the template primitives
+    pub color: Color,
+    /// Units per second, used when the system has no update callback
+    pub velocity: Vec3,
+}
+
+impl Particle {
+    pub fn new(trs: Trs, color: Color) -> Self {
+        Self {
+            trs,
+            color,
+            velocity: Vec3::default(),
+        }
+    }
+}
+
+/// Buffer of particles instancing the same template mesh. Instances are expanded
+/// while collecting the primitives of their node, where triangles are transformed
+/// to world space and spheres to the space of the node, like the template primitives.
+/// Other geometries of the template are not instanced
+#[derive(Clone, Default)]
+pub struct ParticleSystem {
+    pub mesh: Handle<Mesh>,
+    pub particles: Vec<Particle>,
+    update: Option<Arc<ParticleUpdate>>,
+}
+
+impl ParticleSystem {
+    pub fn new(mesh: Handle<Mesh>, particles: Vec<Particle>) -> Self {
+        Self {
+            mesh,
+            particles,
+            update: None,
+        }
+    }
+
+    /// Sets the callback called by `update()` in place of moving particles by their velocity
+    pub fn set_update<F>(&mut self, update: F)
+    where
+        F: Fn(&mut [Particle], f32) + Send + Sync + 'static,
+    {
+        self.update = Some(Arc::new(update));
+    }
+
+    /// Moves the particles forward by `delta` seconds. Remember to mark
+    /// the node dirty afterwards, or use `Model::update_particles()`
+    pub fn update(&mut self, delta: f32) {
+        match &self.update {
+            Some(update) => update(&mut self.particles, delta),
+            None => {
+                for particle in &mut self.particles {
+                    particle.trs.translation += particle.velocity * delta;
+                }
+            }
+        }
+    }
+
+    /// Returns the primitives of every particle of `node`, using `material`
+    /// when valid in place of the materials of the template primitives
+    pub fn primitives(
+        &self,
+        node: Handle<Node>,
+        material: Handle<Material>,
+        model: &Model,
+    ) -> Vec<BvhPrimitive> {
+        let mut ret = vec![];
+        let Some(mesh) = model.meshes.get(self.mesh) else {
+            return ret;
+        };
+        let node_trs = &model.solved_trs.get(&node).unwrap().trs;
+
+        for particle in &self.particles {
+            let particle_trs = SolvedTrs::new(node_trs * &particle.trs);
+            for prim_handle in &mesh.primitives {
+                let prim = model.primitives.get(*prim_handle).unwrap();
+                let material = if material.valid() {
+                    material
+                } else {
+                    prim.material
+                };
+                let mut prims = match &prim.geometry {
+                    Geometry::Triangles(triangles) => {
+                        triangles.primitives_with_trs(node, material, model, &particle_trs)
+                    }
+                    Geometry::Sphere(sphere) => {
+                        // Spheres are transformed by the node while intersecting them
+                        let scale = &particle.trs.scale;
+                        let scale = scale.get_x().max(scale.get_y()).max(scale.get_z());
+                        let center = &particle.trs * sphere.center;
+                        Sphere::new(center, sphere.get_radius() * scale).primitives(node, material)
+                    }
+                    _ => vec![],
+                };
+                for bvh_prim in &mut prims {
+                    bvh_prim.primitive = *prim_handle;
+                    match &mut bvh_prim.geometry {
+                        BvhGeometry::Triangle(triangle) => {
+                            for vertex in &mut triangle.vertices {
+                                vertex.ext.color *= particle.color;
+                            }
+                        }
+                        BvhGeometry::Sphere(sphere) => sphere.color *= particle.color,
+                        _ => (),
+                    }
+                }
+                ret.extend(prims);
+            }
+        }
+
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn particles() {
+        let mut model = Model::new();
+        let quad = model.primitives.push(
+            Primitive::builder()
+                .triangles(Triangles::plane(1.0, 1.0, 1, 1))
+                .build(),
+        );
+        let sphere = model
+            .primitives
+            .push(Primitive::builder().sphere(Point3::default(), 0.5).build());
+        let mesh = model.meshes.push(Mesh::new(vec![quad, sphere]));
+
+        let red = Color::new(1.0, 0.0, 0.0, 1.0);
+        let mut particles = ParticleSystem::new(
+            mesh,
+            vec![
+                Particle::new(Trs::default(), Color::white()),
+                Particle::new(
+                    Trs::builder().translation(Vec3::new(4.0, 0.0, 0.0)).build(),
+                    red,
+                ),
+            ],
+        );
+        particles.particles[0].velocity = Vec3::new(0.0, 1.0, 0.0);
+        let node = model.nodes.push(
+            Node::builder()
+                .particles(particles)
+                .translation(Vec3::new(0.0, 0.0, -2.0))
+                .build(),
+        );
+        model.root.children.push(node);
+
+        // Two triangles and a sphere for each particle
+        let primitives = model.collect();
+        assert_eq!(primitives.len(), 6);
+        let BvhGeometry::Sphere(sphere) = &primitives[5].geometry else {
+            panic!("Expected a sphere");
+        };
+        assert_eq!(sphere.center.get_x(), 4.0);
+        assert_eq!(sphere.color.g, 0.0);
+        let max = primitives[4].max(&model);
+        assert!((max.get_x() - 4.5).abs() < 1e-5);
+        assert!((max.get_z() + 1.5).abs() < 1e-5);
+
+        // Without a callback particles move by their velocity
+        model.update_particles(0.5);
+        let primitives = model.collect();
+        let min = primitives[2].min(&model);
+        assert!((min.get_y() - 0.0).abs() < 1e-5);
+
+        let particles = model
+            .nodes
+            .get_mut(node)
+            .unwrap()
+            .particles
+            .as_mut()
+            .unwrap();
+        particles.set_update(|particles, delta| {
+            for particle in particles {
+                particle.trs.scale *= 1.0 + delta;
+            }
+        });
+        model.update_particles(1.0);
+        let primitives = model.collect();
+        let BvhGeometry::Sphere(sphere) = &primitives[5].geometry else {
+            panic!("Expected a sphere");
+        };
+        assert_eq!(sphere.get_radius(), 1.0);
+    }
+}
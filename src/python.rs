@@ -0,0 +1,149 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Python bindings, available with the `python` feature.
+//! The module can be built with `maturin develop`, then used like this:
+//! ```python
+//! import rayca
+//! scene = rayca.Scene(rayca.Config(seed=42))
+//! scene.load("model.gltf")
+//! image = scene.render(256, 256)  # numpy array of shape (256, 256, 4)
+//! ```
+
+use numpy::{PyArray1, PyArray3, PyArrayMethods};
+use pyo3::{exceptions::PyRuntimeError, prelude::*};
+
+use crate::*;
+
+/// Rendering options which can be scripted from Python
+#[pyclass(name = "Config")]
+#[derive(Clone)]
+pub struct PyConfig {
+    #[pyo3(get, set)]
+    pub bvh: bool,
+    #[pyo3(get, set)]
+    pub seed: u64,
+    #[pyo3(get, set)]
+    pub log_stats: bool,
+}
+
+#[pymethods]
+impl PyConfig {
+    #[new]
+    #[pyo3(signature = (bvh = true, seed = 0, log_stats = false))]
+    fn new(bvh: bool, seed: u64, log_stats: bool) -> Self {
+        Self {
+            bvh,
+            seed,
+            log_stats,
+        }
+    }
+}
+
+impl Default for PyConfig {
+    fn default() -> Self {
+        Self::new(true, 0, false)
+    }
+}
+
+impl PyConfig {
+    fn apply(&self, config: &mut Config) {
+        config.bvh = self.bvh;
+        config.seed = self.seed;
+        config.log_stats = self.log_stats;
+    }
+}
+
+fn to_py_err<E: std::fmt::Display>(err: E) -> PyErr {
+    PyRuntimeError::new_err(err.to_string())
+}
+
+#[pyclass(name = "Scene", unsendable)]
+pub struct PyScene {
+    scene: Scene,
+}
+
+#[pymethods]
+impl PyScene {
+    #[new]
+    #[pyo3(signature = (config = None))]
+    fn new(config: Option<PyConfig>) -> Self {
+        let mut scene = Scene::new();
+        config.unwrap_or_default().apply(&mut scene.config);
+        Self { scene }
+    }
+
+    fn set_config(&mut self, config: &PyConfig) {
+        config.apply(&mut self.scene.config);
+    }
+
+    /// Loads a glTF file from disk
+    fn load(&mut self, path: &str) -> PyResult<()> {
+        self.scene.load(path).map_err(to_py_err)
+    }
+
+    /// Loads a binary glTF, or a glTF with embedded resources, from memory
+    fn load_bytes(&mut self, data: &[u8]) -> PyResult<()> {
+        let model = Model::builder()
+            .data(data)
+            .and_then(|mut builder| builder.build())
+            .map_err(to_py_err)?;
+        self.scene.push(model);
+        Ok(())
+    }
+
+    /// Adds a camera and a couple of lights
+    fn push_default_model(&mut self) {
+        self.scene.push_default_model();
+    }
+
+    /// Renders the scene into a numpy array of shape `(height, width, 4)`
+    fn render<'py>(
+        &mut self,
+        py: Python<'py>,
+        width: u32,
+        height: u32,
+    ) -> PyResult<Bound<'py, PyArray3<u8>>> {
+        if self.scene.get_camera_node_handle().is_none() {
+            self.scene.push_default_model();
+        }
+
+        let mut image = Image::new(width, height, ColorType::RGBA8);
+        self.scene.draw(&mut image);
+
+        PyArray1::from_slice(py, image.bytes()).reshape([height as usize, width as usize, 4])
+    }
+}
+
+#[pymodule]
+fn rayca(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyConfig>()?;
+    module.add_class::<PyScene>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use numpy::PyUntypedArrayMethods;
+
+    use super::*;
+
+    #[test]
+    fn render() {
+        Python::initialize();
+        Python::attach(|py| {
+            let mut scene = PyScene::new(Some(PyConfig::new(true, 42, false)));
+            assert!(scene.load("test").is_err());
+            assert!(scene.load_bytes(b"invalid").is_err());
+
+            // Rendering into an array needs numpy in the Python environment
+            if py.import("numpy").is_err() {
+                return;
+            }
+
+            let image = scene.render(py, 8, 4).unwrap();
+            assert_eq!(image.shape(), [4, 8, 4]);
+        });
+    }
+}
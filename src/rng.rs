@@ -0,0 +1,139 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use crate::Vec3;
+
+/// Mixes the bits of a 64-bit value. See https://prng.di.unimi.it/splitmix64.c
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E3779B97F4A7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D049BB133111EB);
+    x ^ (x >> 31)
+}
+
+/// Small [PCG32](https://www.pcg-random.org) random number generator.
+/// It is cheap to create, hence every pixel gets its own generator derived from
+/// the global seed and its coordinates, which makes renders independent from
+/// the order in which threads process pixels.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+    inc: u64,
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut ret = Self {
+            state: 0,
+            inc: (splitmix64(seed) << 1) | 1,
+        };
+        ret.next_u32();
+        ret.state = ret
+            .state
+            .wrapping_add(splitmix64(seed ^ 0xDA3E39CB94B95BDB));
+        ret.next_u32();
+        ret
+    }
+
+    /// Returns a generator for pixel `(x, y)` of a frame rendered with `seed`
+    pub fn for_pixel(seed: u64, x: u32, y: u32) -> Self {
+        let coords = ((y as u64) << 32) | x as u64;
+        Self::new(splitmix64(seed) ^ splitmix64(coords))
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(6364136223846793005).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    /// Returns a uniformly distributed value in `[0, 1)`
+    pub fn next_f32(&mut self) -> f32 {
+        // Use the upper 24 bits, as that is the precision of an f32 mantissa
+        (self.next_u32() >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+    }
+
+    /// Returns a direction uniformly distributed on the hemisphere around `normal`.
+    /// The probability density of every direction is `1 / 2PI`
+    pub fn next_hemisphere(&mut self, normal: &Vec3) -> Vec3 {
+        let cos_theta = self.next_f32();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * self.next_f32();
+        let (t, b) = normal.get_orthonormal_basis();
+        t * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin()) + normal * cos_theta
+    }
+
+    /// Returns a direction uniformly distributed on the unit sphere.
+    /// The probability density of every direction is `1 / 4PI`
+    pub fn next_sphere(&mut self) -> Vec3 {
+        let z = 1.0 - 2.0 * self.next_f32();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * self.next_f32();
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    /// Returns a direction on the hemisphere around `normal` distributed proportionally
+    /// to the cosine with the normal. The probability density is `cos_theta / PI`
+    pub fn next_cosine_hemisphere(&mut self, normal: &Vec3) -> Vec3 {
+        let r = self.next_f32().sqrt();
+        let phi = 2.0 * std::f32::consts::PI * self.next_f32();
+        let cos_theta = (1.0 - r * r).max(0.0).sqrt();
+        let (t, b) = normal.get_orthonormal_basis();
+        t * (r * phi.cos()) + b * (r * phi.sin()) + normal * cos_theta
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Dot;
+
+    #[test]
+    fn deterministic() {
+        let mut a = Rng::for_pixel(42, 3, 7);
+        let mut b = Rng::for_pixel(42, 3, 7);
+        for _ in 0..16 {
+            assert_eq!(a.next_u32(), b.next_u32());
+        }
+
+        let mut c = Rng::for_pixel(43, 3, 7);
+        let mut d = Rng::for_pixel(42, 7, 3);
+        let a_value = a.next_u32();
+        assert_ne!(a_value, c.next_u32());
+        assert_ne!(a_value, d.next_u32());
+    }
+
+    #[test]
+    fn range() {
+        let mut rng = Rng::new(1);
+        for _ in 0..1024 {
+            let value = rng.next_f32();
+            assert!((0.0..1.0).contains(&value));
+        }
+    }
+
+    #[test]
+    fn hemisphere() {
+        let mut rng = Rng::new(2);
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        for _ in 0..256 {
+            let dir = rng.next_hemisphere(&normal);
+            assert!((dir.len() - 1.0).abs() < 1e-4);
+            assert!(dir.dot(normal) >= 0.0);
+            let dir = rng.next_cosine_hemisphere(&normal);
+            assert!((dir.len() - 1.0).abs() < 1e-4);
+            assert!(dir.dot(normal) >= 0.0);
+            let dir = rng.next_sphere();
+            assert!((dir.len() - 1.0).abs() < 1e-4);
+        }
+    }
+}
@@ -30,6 +30,32 @@ impl Sampler {
             }
         }
     }
+
+    /// Averages the texels within the parallelogram spanned by `duvdx` and `duvdy`
+    /// around `uv`, which is the footprint of a pixel on the texture
+    pub fn sample_filtered(&self, image: &Image, uv: &Vec2, duvdx: Vec2, duvdy: Vec2) -> Color {
+        // Samples along each side, one for every texel covered, up to a limit
+        const MAX_SAMPLES: f32 = 4.0;
+        let get_samples = |d: Vec2| {
+            let texels = (d.x * image.width() as f32).hypot(d.y * image.height() as f32);
+            texels.ceil().clamp(1.0, MAX_SAMPLES) as u32
+        };
+        let (x_samples, y_samples) = (get_samples(duvdx), get_samples(duvdy));
+        if x_samples == 1 && y_samples == 1 {
+            return self.sample(image, uv);
+        }
+
+        let mut ret = Color::black();
+        for j in 0..y_samples {
+            let t = (j as f32 + 0.5) / y_samples as f32 - 0.5;
+            for i in 0..x_samples {
+                let s = (i as f32 + 0.5) / x_samples as f32 - 0.5;
+                let offset = duvdx * s + duvdy * t;
+                ret += self.sample(image, &(*uv + offset));
+            }
+        }
+        ret / (x_samples * y_samples) as f32
+    }
 }
 
 #[cfg(test)]
@@ -51,4 +77,27 @@ mod test {
         let pixel = sampler.sample(&image, &uv);
         assert!(pixel == color);
     }
+
+    #[test]
+    fn filtered() {
+        let sampler = Sampler::default();
+        // Checkerboard of black and white texels
+        let mut image = Image::new(4, 4, ColorType::RGBA8);
+        for y in 0..4 {
+            for x in 0..4 {
+                let value = if (x + y) % 2 == 0 { 255 } else { 0 };
+                image.set(x, y, RGBA8::new(value, value, value, 255));
+            }
+        }
+
+        let uv = Vec2::new(0.125, 0.125);
+        let pixel = sampler.sample_filtered(&image, &uv, Vec2::default(), Vec2::default());
+        assert!(pixel == Color::white());
+
+        // A pixel covering the whole texture sees grey
+        let duvdx = Vec2::new(1.0, 0.0);
+        let duvdy = Vec2::new(0.0, 1.0);
+        let pixel = sampler.sample_filtered(&image, &Vec2::new(0.5, 0.5), duvdx, duvdy);
+        assert!((pixel.r - 0.5).abs() < 1e-3);
+    }
 }
@@ -2,20 +2,120 @@
 // Author: Antonio Caggiano <info@antoniocaggiano.eu>
 // SPDX-License-Identifier: MIT
 
-use std::{error::Error, path::Path};
+use std::{error::Error, ops::Range, path::Path};
+
+use instant::{Duration, Instant};
 
 use owo_colors::OwoColorize;
 
 #[cfg(feature = "parallel")]
-use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
 
 use super::*;
 
+/// Rows rendered between two saves of the checkpoint, if any
+const CHECKPOINT_ROWS: usize = 16;
+
+/// What is visible under a certain pixel
+pub struct PickResult {
+    pub node: Handle<Node>,
+    pub primitive: Handle<Primitive>,
+
+    /// Barycentric coordinates of the hit point within the triangle,
+    /// when the primitive is made of triangles
+    pub barycentric: Option<Vec2>,
+
+    /// World position of the hit point
+    pub point: Point3,
+
+    /// Distance from the camera
+    pub depth: f32,
+}
+
+/// Generates the ray going from the camera through the center of pixel `(x, y)`
+fn get_primary_ray(camera_trs: &Trs, angle: f32, width: f32, height: f32, x: f32, y: f32) -> Ray {
+    let aspectratio = width / height;
+    let xx = (2.0 * ((x + 0.5) / width) - 1.0) * angle * aspectratio;
+    let yy = (1.0 - 2.0 * ((y + 0.5) / height)) * angle;
+    let v = Vec3::new(xx, yy, -1.0);
+    let dir = v.get_normalized();
+
+    // Derivatives of the normalized direction with respect to the pixel coordinates
+    let dvdx = Vec3::new(2.0 / width * angle * aspectratio, 0.0, 0.0);
+    let dvdy = Vec3::new(0.0, -2.0 / height * angle, 0.0);
+    let len = v.len();
+    let get_derivative = |dv: Vec3| (dv * v.dot(v) - v * v.dot(dv)) * (1.0 / (len * len * len));
+
+    let origin = Point3::new(0.0, 0.0, 0.0);
+    let mut ray = Ray::new(origin, dir);
+    ray.differential = Some(RayDifferential {
+        dddx: get_derivative(dvdx),
+        dddy: get_derivative(dvdy),
+        ..Default::default()
+    });
+    camera_trs * ray
+}
+
+/// Returns the position of `point` on an image of `width` x `height` pixels rendered by a
+/// camera at `camera_trs`, which is the inverse of `get_primary_ray()`, or `None` when
+/// the point is behind the camera
+pub(crate) fn get_pixel_position(
+    camera_trs: &Trs,
+    angle: f32,
+    width: f32,
+    height: f32,
+    point: Point3,
+) -> Option<Vec2> {
+    let local = &Inversed::from(camera_trs) * point;
+    if local.get_z() >= 0.0 {
+        return None;
+    }
+    let aspectratio = width / height;
+    let xx = local.get_x() / -local.get_z();
+    let yy = local.get_y() / -local.get_z();
+    let x = (xx / (angle * aspectratio) + 1.0) * 0.5 * width - 0.5;
+    let y = (1.0 - yy / angle) * 0.5 * height - 0.5;
+    Some(Vec2::new(x, y))
+}
+
 pub struct Scene {
     // Single model collecting elements from all loaded models
     pub model: Model,
 
     pub config: Config,
+
+    /// Statistics of the last rendered frame
+    pub stats: StatsReport,
+
+    /// Why the last BVH was built without primitives, see `build_bvh()`
+    pub geometry_error: Option<RaycaError>,
+
+    /// Luminance of the pixels of the last rendered frame, after post effects
+    pub histogram: Histogram,
+
+    /// Memory of per-frame data, reused by the next frame
+    pub arena: FrameArena,
+
+    /// Models loaded on demand, see `update_streaming()`
+    pub sources: Vec<ModelSource>,
+
+    /// Threads loading models in background, created by the first `load_async()`
+    jobs: Option<JobPool>,
+
+    /// Models being loaded in background, see `poll_loading()`
+    loading: Vec<JobHandle<Result<Model, String>>>,
+
+    /// Script animating the model before drawing every frame, see `Script::run()`
+    pub script: Option<Script>,
+
+    /// Number of the frame to draw, which the script animates
+    pub frame: u32,
+
+    /// Callbacks notified of changes, see `on_node_added()`
+    pub(crate) observers: SceneObservers,
+
+    /// Degenerate primitives skipped by the last BVH build, logged when it changes
+    degenerate_count: usize,
 }
 
 impl Default for Scene {
@@ -66,6 +166,17 @@ impl Scene {
         Self {
             model: Default::default(),
             config: Default::default(),
+            stats: Default::default(),
+            histogram: Default::default(),
+            arena: FrameArena::new(),
+            sources: vec![],
+            jobs: None,
+            loading: vec![],
+            script: None,
+            frame: 0,
+            observers: SceneObservers::default(),
+            degenerate_count: 0,
+            geometry_error: None,
         }
     }
 
@@ -75,13 +186,13 @@ impl Scene {
         scene
     }
 
-    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Box<dyn Error>> {
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), RaycaError> {
         let mut timer = Timer::new();
         let path_str = path.as_ref().to_string_lossy().to_string();
 
         // Open glTF model
         let model = Model::builder().path(path)?.build()?;
-        self.model.append(model);
+        self.append_model(model);
 
         print_info!(
             "Loaded",
@@ -92,81 +203,596 @@ impl Scene {
         Ok(())
     }
 
+    /// Starts loading a glTF file in background. The model is added to the scene
+    /// by the first `poll_loading()` after it has been loaded
+    pub fn load_async<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref().to_path_buf();
+        let jobs = self.jobs.get_or_insert_with(JobPool::default);
+        let handle = jobs.spawn(move || {
+            let mut timer = Timer::new();
+            let model = Model::builder()
+                .path(&path)
+                .and_then(|mut builder| builder.build())
+                .map_err(|err| format!("Failed to load \"{}\": {}", path.display(), err))?;
+            print_info!(
+                "Loaded",
+                "{} in {:.2}ms",
+                path.display(),
+                timer.get_delta().as_millis()
+            );
+            Ok(model)
+        });
+        self.loading.push(handle);
+    }
+
+    /// Adds the models loaded in background so far to the scene, returning
+    /// how many are still loading. It is called by `draw()`
+    pub fn poll_loading(&mut self) -> Result<usize, Box<dyn Error>> {
+        let mut errors = vec![];
+        let mut i = 0;
+        while i < self.loading.len() {
+            match self.loading[i].poll() {
+                Some(result) => {
+                    self.loading.swap_remove(i);
+                    match result {
+                        Ok(Ok(model)) => self.push(model),
+                        Ok(Err(err)) => errors.push(err),
+                        Err(err) => errors.push(err.to_string()),
+                    }
+                }
+                None => i += 1,
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(self.loading.len())
+        } else {
+            Err(errors.join("\n").into())
+        }
+    }
+
     pub fn push(&mut self, model: Model) {
-        self.model.append(model);
+        self.append_model(model);
+    }
+
+    /// Pushes an overlay model with the camera frustums, light extents, and node axes of the
+    /// scene, see `DebugLines::from_model()`, together with the bounds of the first
+    /// `bvh_depth` levels of `bvh`. Transforms come from the last collection of the model
+    pub fn push_debug_lines(&mut self, bvh: Option<&Bvh>, bvh_depth: usize) {
+        let mut lines = DebugLines::from_model(&self.model);
+        if let Some(bvh) = bvh {
+            lines.push_bvh(bvh, bvh_depth, Color::new(0.0, 1.0, 1.0, 1.0));
+        }
+        self.push(lines.build());
+    }
+
+    /// Draws `lines` on top of `image` as seen by the camera used for rendering,
+    /// see `DebugLines::draw()`. It expects the model to be collected already
+    pub fn draw_debug_lines(&self, lines: &DebugLines, image: &mut Image) {
+        let (camera_trs, camera_angle) = self.get_camera();
+        lines.draw(image, camera_trs, camera_angle);
+    }
+
+    /// Appends `model` notifying the observers of its nodes
+    pub(crate) fn append_model(&mut self, model: Model) -> ModelHandles {
+        let handles = self.model.append(model);
+        self.observers.notify_model_added(&self.model, &handles);
+        handles
+    }
+
+    /// Appends the models, cameras, lights, and model sources of `other` keeping their
+    /// hierarchies, with all their handles remapped. Returns the handles of the appended
+    /// objects, the root of which is the root of `other`
+    pub fn append_scene(&mut self, mut other: Scene) -> ModelHandles {
+        // Loaded sources refer to objects of the other model,
+        // they are loaded again into this scene when needed
+        for index in 0..other.sources.len() {
+            other.unload_source(index);
+        }
+        self.sources.append(&mut other.sources);
+        self.append_model(other.model)
     }
 
     pub fn push_default_model(&mut self) {
-        self.model.append(Self::create_default_model())
+        self.append_model(Self::create_default_model());
     }
 
-    fn draw_pixel(&self, ray: Ray, bvh: &Bvh, pixel: &mut RGBA8) -> usize {
-        let triangle_count = 0;
-        if let Some(pixel_color) = self.config.integrator.trace(&self.model, ray, bvh, 0) {
-            // No over operation here as transparency should be handled by the lighting model
-            *pixel = pixel_color.into();
+    /// Returns the node of the camera used for rendering, collecting the model if needed
+    pub fn get_camera_node_handle(&mut self) -> Option<Handle<Node>> {
+        if self.model.camera_nodes.is_empty() {
+            self.model.collect();
         }
-        triangle_count
+        self.model.camera_nodes.first().copied()
     }
-}
 
-impl Draw for Scene {
-    fn draw(&mut self, image: &mut Image) {
-        let primitives = self.model.collect();
+    /// Returns the world transform of `node`, which is cached until nodes are changed
+    /// through `set_trs()` or marked dirty, see `Model::world_trs()`
+    pub fn world_trs(&mut self, node: Handle<Node>) -> Option<Trs> {
+        self.model.world_trs(node).cloned()
+    }
+
+    /// Same as `try_build_bvh()`, but on errors it logs them, keeps them in
+    /// `geometry_error`, and returns a BVH without primitives, so that drawing
+    /// with `Config::strict_geometry` shows only the background
+    pub fn build_bvh(&mut self) -> Bvh {
+        match self.try_build_bvh() {
+            Ok(bvh) => {
+                self.geometry_error = None;
+                bvh
+            }
+            Err(err) => {
+                print_warning!("Geometry", "{}", err);
+                self.geometry_error = Some(err);
+                Bvh::builder()
+                    .nodes(self.arena.take())
+                    .primitives(self.arena.take())
+                    .build(&self.model)
+            }
+        }
+    }
+
+    /// Collects the primitives of the model and builds a BVH with them. Degenerate
+    /// primitives are skipped, or reported as errors with `Config::strict_geometry`
+    pub fn try_build_bvh(&mut self) -> Result<Bvh, RaycaError> {
+        self.model.incremental = self.config.incremental;
+        let mut primitives: Vec<BvhPrimitive> = self.arena.take();
+        self.model.collect_into(&mut primitives);
 
-        // Build BVH
-        let mut bvh_builder = Bvh::builder().primitives(primitives);
+        let mut bvh_builder = Bvh::builder()
+            .primitives(primitives)
+            .nodes(self.arena.take())
+            .layout(self.config.bvh_layout)
+            .skip_degenerate(true)
+            // Checkpoints belong to the primitives they were rendered for
+            .hash(self.config.checkpoint.is_some());
         if !self.config.bvh {
             bvh_builder = bvh_builder.max_depth(0);
         }
+        if let Some(dir) = &self.config.bvh_cache {
+            bvh_builder = bvh_builder.cache(dir);
+        }
         let bvh = bvh_builder.build(&self.model);
 
-        let mut timer = Timer::new();
-
-        let width = image.width() as f32;
-        let height = image.height() as f32;
-
-        let inv_width = 1.0 / width;
-        let inv_height = 1.0 / height;
+        let degenerate_count = bvh.degenerate_count;
+        if degenerate_count > 0 && self.config.strict_geometry {
+            bvh.recycle(&mut self.arena);
+            return Err(RaycaError::InvalidGeometry(format!(
+                "{} degenerate primitives",
+                degenerate_count
+            )));
+        }
+        if degenerate_count != self.degenerate_count {
+            self.degenerate_count = degenerate_count;
+            if degenerate_count > 0 {
+                print_warning!("Skipping", "{} degenerate primitives", degenerate_count);
+            }
+        }
+        Ok(bvh)
+    }
 
+    /// Returns the world transform and the angle of the camera used for rendering.
+    /// It expects the model to be collected already
+    fn get_camera(&self) -> (&Trs, f32) {
         assert!(!self.model.camera_nodes.is_empty());
         let camera_node_handle = self.model.camera_nodes[0];
-        let camera_node = self.model.nodes.get(self.model.camera_nodes[0]).unwrap();
+        let camera_node = self.model.nodes.get(camera_node_handle).unwrap();
         let camera = self.model.cameras.get(camera_node.camera).unwrap();
         let camera_trs = self.model.solved_trs.get(&camera_node_handle).unwrap();
+        (&camera_trs.trs, camera.get_angle())
+    }
 
-        let aspectratio = width / height;
-        let angle = camera.get_angle();
+    /// Returns what is visible at pixel `(x, y)` of an image of `width` x `height`
+    /// pixels rendered with the current camera. `bvh` comes from `build_bvh()`, and it
+    /// can be used for many picks as long as the scene does not change
+    pub fn pick(&self, bvh: &Bvh, x: u32, y: u32, width: u32, height: u32) -> Option<PickResult> {
+        let (camera_trs, angle) = self.get_camera();
+        let ray = get_primary_ray(
+            camera_trs,
+            angle,
+            width as f32,
+            height as f32,
+            x as f32,
+            y as f32,
+        );
+
+        bvh.intersects_occluder(&self.model, &ray)
+            .map(|(hit, primitive)| PickResult {
+                node: primitive.node,
+                primitive: primitive.primitive,
+                barycentric: match primitive.geometry {
+                    BvhGeometry::Triangle(_) => Some(hit.uv),
+                    _ => None,
+                },
+                point: hit.point,
+                depth: hit.depth,
+            })
+    }
+
+    /// Renders `aov` with one ray through the center of every pixel of an image of
+    /// `width` x `height` pixels, using the current camera. Motion vectors compare
+    /// against the transforms of the frame drawn before the last one
+    pub fn render_aov(&mut self, aov: Aov, width: u32, height: u32) -> AovBuffer {
+        let bvh = self.build_bvh();
+        let buffer = self.trace_aov(&bvh, aov, width, height);
+        bvh.recycle(&mut self.arena);
+        buffer
+    }
+
+    fn trace_aov(&self, bvh: &Bvh, aov: Aov, width: u32, height: u32) -> AovBuffer {
+        let (camera_trs, angle) = self.get_camera();
+        let mut buffer = AovBuffer::new(aov, width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let ray = get_primary_ray(
+                    camera_trs,
+                    angle,
+                    width as f32,
+                    height as f32,
+                    x as f32,
+                    y as f32,
+                );
+                if let Some((mut hit, primitive)) = bvh.intersects_iter(&self.model, &ray) {
+                    bvh.shade_hit(&self.model, &mut hit, primitive);
+                    buffer.set(x, y, aov.get(&self.model, &hit, primitive, (width, height)));
+                }
+            }
+        }
+        buffer
+    }
+
+    /// Returns the samples of the checkpoint of the config, or empty ones
+    /// when there is none or it belongs to a different render
+    fn load_checkpoint(&self, width: u32, height: u32, content_hash: u64) -> Checkpoint {
+        let seed = self.config.seed;
+        let empty = Checkpoint::new(width, height, seed, content_hash);
+        let Some(path) = &self.config.checkpoint else {
+            return empty;
+        };
+        if !path.exists() {
+            return empty;
+        }
+        match Checkpoint::load(path) {
+            Ok(checkpoint) if checkpoint.matches(width, height, seed, content_hash) => checkpoint,
+            Ok(_) => {
+                print_warning!("Checkpoint", "{} belongs to another render", path.display());
+                empty
+            }
+            Err(err) => {
+                print_warning!("Checkpoint", "Failed to load {}: {}", path.display(), err);
+                empty
+            }
+        }
+    }
+
+    /// Returns sample `sample` of pixel `(x, y)`, which goes through the center of the
+    /// pixel for the first sample, and through a random point within it for the others
+    #[allow(clippy::too_many_arguments)]
+    fn draw_sample(
+        &self,
+        camera_trs: &Trs,
+        angle: f32,
+        bvh: &Bvh,
+        x: usize,
+        y: usize,
+        sample: u32,
+        width: f32,
+        height: f32,
+    ) -> Option<Color> {
+        let seed = self
+            .config
+            .seed
+            .wrapping_add((sample as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        let mut rng = Rng::for_pixel(seed, x as u32, y as u32);
+        let (mut x, mut y) = (x as f32, y as f32);
+        if sample > 0 {
+            x += rng.next_f32() - 0.5;
+            y += rng.next_f32() - 0.5;
+        }
+        let ray = get_primary_ray(camera_trs, angle, width, height, x, y);
+        bvh.stats.add_primary_ray();
+        let uv = Vec2::new((x + 0.5) / width, (y + 0.5) / height);
+        self.draw_pixel(ray, bvh, &mut rng, uv)
+    }
+
+    /// Draws the pixel at `uv` on the image, where `ray` comes from, returning its color
+    /// in the output space, or `None` when nothing is visible there
+    fn draw_pixel(&self, ray: Ray, bvh: &Bvh, rng: &mut Rng, uv: Vec2) -> Option<Color> {
+        // Backplates are photographs in sRGB, while rendered colors are in the working space
+        let output_space = self.config.output_space;
+        let backplate = self
+            .config
+            .backplate
+            .as_ref()
+            .map(|backplate| output_space.from_srgb(Sampler::default().sample(backplate, &uv)));
+        let pixel_color = self
+            .config
+            .integrator
+            .trace(&self.model, ray, bvh, 0, rng)
+            .map(|color| self.config.working_space.convert(color, output_space));
+        match (pixel_color, backplate) {
+            // Transparency should be handled by the lighting model, apart from the shadows
+            // of shadow catchers which darken the backplate
+            (Some(pixel_color), Some(mut backplate)) if pixel_color.a < 1.0 => {
+                backplate.over(pixel_color);
+                Some(backplate)
+            }
+            (Some(pixel_color), _) => Some(pixel_color),
+            (None, backplate) => backplate,
+        }
+    }
+}
+
+impl Scene {
+    /// Gets the scene ready to render a frame of `height` pixels, returning its BVH
+    fn prepare_frame(&mut self, height: u32) -> Bvh {
+        // Stages of the frame record their times as they complete
+        self.stats = StatsReport::default();
+        if let Err(err) = self.poll_loading() {
+            print_warning!("Loading", "{}", err);
+        }
+        // Failing sources are logged by `update_streaming()` itself
+        let _ = self.update_streaming();
+        if let Some(script) = &mut self.script {
+            script.update(&mut self.model, self.frame);
+        }
+
+        // Displaced triangles are split after the size of the image
+        self.model.tessellation.resolution = height;
+        self.model.working_space = self.config.working_space;
+        self.model.store_previous_trs();
+        let mut bvh = self.build_bvh();
+        bvh.stats.set_enabled(self.config.log_stats);
+        self.config
+            .integrator
+            .prepare(&self.model, &bvh, self.config.seed);
+        bvh
+    }
+
+    /// Adds samples to `tiles`, which are the rows of an image of `width` x `height`
+    /// pixels from row `start`, until they have `samples_per_pixel` samples.
+    /// Returns the time spent shading them
+    fn render_tiles(
+        &self,
+        bvh: &Bvh,
+        tiles: &mut [Tile],
+        start: usize,
+        (width, height): (u32, u32),
+        samples_per_pixel: u32,
+        progress: &ProgressTracker,
+    ) -> Duration {
+        let mut timer = Timer::new();
+        let (camera_trs, angle) = self.get_camera();
+        let (width, height) = (width as f32, height as f32);
 
         #[cfg(feature = "parallel")]
-        let row_iter = image.pixels_mut().into_par_iter();
+        let row_iter = tiles.par_iter_mut();
         #[cfg(not(feature = "parallel"))]
-        let row_iter = image.pixels_mut().into_iter();
+        let row_iter = tiles.iter_mut();
+
+        row_iter.enumerate().for_each(|(i, tile)| {
+            let y = start + i;
+            if self.config.cancel.is_cancelled() {
+                return;
+            }
+            let first_sample = tile.sample_count;
+            if first_sample >= samples_per_pixel {
+                progress.complete(y);
+                return;
+            }
 
-        row_iter.enumerate().for_each(|(y, row)| {
             #[cfg(feature = "parallel")]
-            let pixel_iter = row.into_par_iter();
+            let pixel_iter = tile.pixels.par_iter_mut();
             #[cfg(not(feature = "parallel"))]
-            let pixel_iter = row.into_iter();
+            let pixel_iter = tile.pixels.iter_mut();
 
             pixel_iter.enumerate().for_each(|(x, pixel)| {
-                // Generate primary ray
-                let xx = (2.0 * ((x as f32 + 0.5) * inv_width) - 1.0) * angle * aspectratio;
-                let yy = (1.0 - 2.0 * ((y as f32 + 0.5) * inv_height)) * angle;
-                let mut dir = Vec3::new(xx, yy, -1.0);
-                dir.normalize();
-                let origin = Point3::new(0.0, 0.0, 0.0);
-                let ray = &camera_trs.trs * Ray::new(origin, dir);
-
-                self.draw_pixel(ray, &bvh, pixel);
+                for sample in first_sample..samples_per_pixel {
+                    pixel
+                        .add(self.draw_sample(camera_trs, angle, bvh, x, y, sample, width, height));
+                }
             });
+            tile.sample_count = samples_per_pixel;
+
+            progress.complete(y);
         });
+        timer.get_delta()
+    }
 
+    /// Logs and stores the statistics of the frame rendered with `bvh`, together with
+    /// the times of post-processing recorded by `resolve()`
+    fn finish_frame(&mut self, bvh: Bvh, shading_time: Duration, render_time: Duration) {
         rlog!(
             "{:>12} in {:.2}ms",
             "Rendered".green().bold(),
-            timer.get_delta().as_millis()
+            render_time.as_millis()
+        );
+
+        self.stats = StatsReport {
+            bvh_build: bvh.build_time,
+            shading: shading_time,
+            render: render_time,
+            post: self.stats.post,
+            resolve: self.stats.resolve,
+            ..bvh.stats.report()
+        };
+        if self.config.log_stats {
+            self.stats.log();
+        }
+
+        bvh.recycle(&mut self.arena);
+    }
+
+    /// Renders rows `rows` of an image of `width` x `height` pixels, returning their tiles.
+    /// Together with `resolve()`, this allows splitting a frame among different scenes
+    pub fn render_rows(&mut self, width: u32, height: u32, rows: Range<usize>) -> Vec<Tile> {
+        let samples_per_pixel = self.config.samples_per_pixel.max(1);
+        self.render_samples(width, height, rows, 0..samples_per_pixel)
+    }
+
+    /// Same as `render_rows()`, but the pixels of the tiles only take samples `samples`.
+    /// They can be added to tiles with the previous samples through `Tile::merge()`
+    pub fn render_samples(
+        &mut self,
+        width: u32,
+        height: u32,
+        rows: Range<usize>,
+        samples: Range<u32>,
+    ) -> Vec<Tile> {
+        let bvh = self.prepare_frame(height);
+        let mut timer = Timer::new();
+
+        let rows = rows.start.min(height as usize)..rows.end.min(height as usize);
+        let mut tiles = vec![Tile::new(width); rows.len()];
+        for tile in &mut tiles {
+            tile.sample_count = samples.start;
+        }
+        let progress = ProgressTracker::new(self.config.progress.as_ref(), tiles.len());
+        let shading_time = self.render_tiles(
+            &bvh,
+            &mut tiles,
+            rows.start,
+            (width, height),
+            samples.end,
+            &progress,
         );
+
+        self.finish_frame(bvh, shading_time, timer.get_delta());
+        tiles
+    }
+
+    /// Adds one sample to every pixel of `checkpoint`, up to the samples per pixel of the
+    /// config, then resolves it to `image`. Calling this on every frame refines the image
+    /// progressively, as long as `checkpoint` is reset when the scene changes.
+    /// Returns whether samples were added
+    pub fn draw_progressive(&mut self, checkpoint: &mut Checkpoint, image: &mut Image) -> bool {
+        let (width, height, seed) = (image.width(), image.height(), self.config.seed);
+        if !checkpoint.matches(width, height, seed, checkpoint.content_hash) {
+            *checkpoint = Checkpoint::new(width, height, seed, 0);
+        }
+        let sample_count = checkpoint.get_sample_count();
+        if sample_count >= self.config.samples_per_pixel.max(1) {
+            return false;
+        }
+
+        let bvh = self.prepare_frame(image.height());
+        let mut timer = Timer::new();
+
+        let size = (image.width(), image.height());
+        let progress = ProgressTracker::new(self.config.progress.as_ref(), checkpoint.tiles.len());
+        let shading_time = self.render_tiles(
+            &bvh,
+            &mut checkpoint.tiles,
+            0,
+            size,
+            sample_count + 1,
+            &progress,
+        );
+
+        self.resolve(checkpoint, image);
+        self.finish_frame(bvh, shading_time, timer.get_delta());
+        true
+    }
+
+    /// Draws a frame with one sample per pixel, blended with the previous frames of
+    /// `temporal` reprojected through motion vectors. This reduces the noise of
+    /// interactive viewers, while the camera and the scene move
+    pub fn draw_temporal(&mut self, temporal: &mut TemporalAccumulation, image: &mut Image) {
+        let bvh = self.prepare_frame(image.height());
+        let mut timer = Timer::new();
+
+        // Every frame takes a different sample of its pixels
+        let (width, height) = (image.width(), image.height());
+        let sample = temporal.get_frame_count();
+        let mut tiles = vec![Tile::new(width); height as usize];
+        for tile in &mut tiles {
+            tile.sample_count = sample;
+        }
+        let progress = ProgressTracker::new(self.config.progress.as_ref(), tiles.len());
+        let shading_time =
+            self.render_tiles(&bvh, &mut tiles, 0, (width, height), sample + 1, &progress);
+        let mut colors = tiles
+            .iter()
+            .flat_map(|tile| tile.pixels.iter().map(|pixel| pixel.get(1)))
+            .collect::<Vec<_>>();
+
+        let motion = self.trace_aov(&bvh, Aov::Motion, width, height);
+        temporal.accumulate(&mut colors, &motion);
+
+        self.resolve_colors(colors, image);
+        self.finish_frame(bvh, shading_time, timer.get_delta());
+    }
+
+    /// Writes the average colors of the tiles of `checkpoint` to `image`, after applying
+    /// post effects and the exposure view of the config. Their times are recorded in the
+    /// statistics of the scene
+    pub fn resolve(&mut self, checkpoint: &Checkpoint, image: &mut Image) {
+        // Colors are kept in high dynamic range until post effects are applied
+        self.resolve_colors(checkpoint.get_colors(), image);
+    }
+
+    fn resolve_colors(&mut self, mut hdr: Vec<Option<Color>>, image: &mut Image) {
+        image.color_space = self.config.output_space;
+
+        let mut timer = Timer::new();
+        self.config
+            .post
+            .apply(&mut hdr, image.width(), image.height());
+        self.stats.post = timer.get_delta();
+
+        self.histogram = Histogram::new(&hdr, 64);
+        self.config
+            .exposure_view
+            .apply(&mut hdr, image.width(), image.height());
+        // Pixels where nothing is visible keep what the image had
+        for (pixel, color) in image.data_mut::<RGBA8>().iter_mut().zip(hdr) {
+            if let Some(color) = color {
+                *pixel = color.into();
+            }
+        }
+        self.stats.resolve = timer.get_delta();
+    }
+}
+
+impl Draw for Scene {
+    fn draw(&mut self, image: &mut Image) {
+        let bvh = self.prepare_frame(image.height());
+        let mut timer = Timer::new();
+
+        let size = (image.width(), image.height());
+        let progress = ProgressTracker::new(self.config.progress.as_ref(), image.height() as usize);
+
+        let content_hash = bvh.content_hash.unwrap_or_default();
+        let mut checkpoint = self.load_checkpoint(image.width(), image.height(), content_hash);
+        // Without checkpoints, all rows are rendered together
+        let rows_between_saves = if self.config.checkpoint.is_some() {
+            CHECKPOINT_ROWS
+        } else {
+            checkpoint.tiles.len().max(1)
+        };
+        let mut last_save = Instant::now();
+        let samples_per_pixel = self.config.samples_per_pixel.max(1);
+        let mut shading_time = Duration::ZERO;
+
+        for start in (0..checkpoint.tiles.len()).step_by(rows_between_saves) {
+            let end = (start + rows_between_saves).min(checkpoint.tiles.len());
+            let tiles = &mut checkpoint.tiles[start..end];
+            shading_time +=
+                self.render_tiles(&bvh, tiles, start, size, samples_per_pixel, &progress);
+
+            if let Some(path) = &self.config.checkpoint {
+                if last_save.elapsed() >= self.config.checkpoint_interval
+                    || end == checkpoint.tiles.len()
+                {
+                    if let Err(err) = checkpoint.save(path) {
+                        print_warning!("Checkpoint", "{}", err);
+                    }
+                    last_save = Instant::now();
+                }
+            }
+        }
+
+        self.resolve(&checkpoint, image);
+        self.finish_frame(bvh, shading_time, timer.get_delta());
     }
 }
 
@@ -176,6 +802,231 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn load_async() {
+        let mut scene = Scene::new();
+        scene.load_async("test");
+        scene.load_async("test");
+        while scene.loading.iter().any(|handle| !handle.is_ready()) {
+            std::thread::yield_now();
+        }
+        let err = scene.poll_loading().unwrap_err();
+        assert_eq!(err.to_string().lines().count(), 2);
+        assert_eq!(scene.poll_loading().unwrap(), 0);
+
+        // Loaded models are added to the scene
+        let path = "target/load_async.gltf";
+        let gltf = r#"{
+            "asset": { "version": "2.0" },
+            "nodes": [{ "name": "loaded" }],
+            "scenes": [{ "nodes": [0] }]
+        }"#;
+        std::fs::write(path, gltf).unwrap();
+        scene.load_async(path);
+        while scene.loading.iter().any(|handle| !handle.is_ready()) {
+            std::thread::yield_now();
+        }
+        assert_eq!(scene.poll_loading().unwrap(), 0);
+        assert!(scene.model.nodes.iter().any(|node| node.name == "loaded"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn append_scene() {
+        let mut scene = Scene::new();
+        scene.push_default_model();
+
+        let mut other = Scene::new();
+        let mut model = Model::new();
+        let image = model.images.push(Image::new(1, 1, ColorType::RGBA8));
+        let texture = model.textures.push(Texture::new(image, Handle::NONE));
+        let mut material = Material::new();
+        material.normal_texture = texture;
+        let material = model.materials.push(material);
+        let mut primitive = Primitive::unit_triangle();
+        primitive.material = material;
+        let primitive = model.primitives.push(primitive);
+        let mesh = model.meshes.push(Mesh::new(vec![primitive]));
+        let child = model.nodes.push(Node::builder().mesh(mesh).build());
+        let parent = model
+            .nodes
+            .push(Node::builder().children(vec![child]).build());
+        model.root.children.push(parent);
+        other.push(model);
+
+        let handles = scene.append_scene(other);
+        assert_eq!(scene.model.root.children.len(), 2);
+
+        // Walk down the appended hierarchy
+        let root = scene.model.nodes.get(handles.root).unwrap();
+        let scene_root = scene.model.nodes.get(root.children[0]).unwrap();
+        let parent = scene.model.nodes.get(scene_root.children[0]).unwrap();
+        let child = scene.model.nodes.get(parent.children[0]).unwrap();
+        let mesh = scene.model.meshes.get(child.mesh).unwrap();
+        let primitive = scene.model.primitives.get(mesh.primitives[0]).unwrap();
+        let material = scene.model.materials.get(primitive.material).unwrap();
+        let texture = scene.model.textures.get(material.normal_texture).unwrap();
+        assert!(scene.model.images.get(texture.image).is_some());
+
+        // The camera of the first scene is still used for rendering
+        let camera = scene.get_camera_node_handle().unwrap();
+        assert!(scene.model.nodes.get(camera).unwrap().camera.valid());
+        let mut image = Image::new(4, 4, ColorType::RGBA8);
+        scene.draw(&mut image);
+    }
+
+    #[test]
+    fn debug_lines() {
+        let mut scene = Scene::new();
+        scene.push_default_model();
+        let bvh = scene.build_bvh();
+        let primitive_count = bvh.primitives.len();
+        scene.push_debug_lines(Some(&bvh), 0);
+        // The default model brings a camera and lights, and the BVH root is a box
+        assert!(scene.build_bvh().primitives.len() > primitive_count + 12);
+    }
+
+    #[test]
+    fn degenerate() {
+        let mut model = Model::new();
+        let triangles = Triangles::new(
+            vec![
+                Vertex::new(0.0, 0.0, 0.0),
+                Vertex::new(1.0, 0.0, 0.0),
+                Vertex::new(0.0, 1.0, 0.0),
+                Vertex::new(2.0, 0.0, 0.0),
+                Vertex::new(f32::NAN, 0.0, 0.0),
+            ],
+            // A valid triangle, one on a line, and one with a NaN vertex
+            vec![0, 1, 2, 0, 1, 3, 0, 1, 4],
+        );
+        let primitive = model
+            .primitives
+            .push(Primitive::builder().triangles(triangles).build());
+        let mesh = model.meshes.push(Mesh::new(vec![primitive]));
+        let node = model.nodes.push(Node::builder().mesh(mesh).build());
+        model.root.children.push(node);
+
+        let mut scene = Scene::new();
+        scene.push(model);
+        let bvh = scene.build_bvh();
+        assert_eq!(bvh.primitives.len(), 1);
+        assert!(bvh.root.get_bounds().b.get_x().is_finite());
+
+        scene.config.strict_geometry = true;
+        assert!(matches!(
+            scene.try_build_bvh(),
+            Err(RaycaError::InvalidGeometry(_))
+        ));
+
+        // Drawing does not panic, but renders only the background
+        scene.push_default_model();
+        let mut image = Image::new(4, 4, ColorType::RGBA8);
+        scene.draw(&mut image);
+        assert!(matches!(
+            scene.geometry_error,
+            Some(RaycaError::InvalidGeometry(_))
+        ));
+        assert!(scene.build_bvh().primitives.is_empty());
+    }
+
+    #[test]
+    fn stats() {
+        let mut scene = Scene::new();
+        scene.push_default_model();
+        let mut image = Image::new(4, 4, ColorType::RGBA8);
+        scene.draw(&mut image);
+        // Stages are timed within the whole frame
+        let stats = &scene.stats;
+        assert!(stats.shading > Duration::ZERO);
+        assert!(stats.shading + stats.post + stats.resolve <= stats.render);
+
+        // Rendering rows does not resolve them
+        scene.render_rows(4, 4, 0..4);
+        assert!(scene.stats.shading > Duration::ZERO);
+        assert_eq!(scene.stats.post, Duration::ZERO);
+        assert_eq!(scene.stats.resolve, Duration::ZERO);
+    }
+
+    #[test]
+    fn draw_progressive() {
+        let mut scene = Scene::new();
+        let mut model = Model::new();
+        let primitive = model.primitives.push(Primitive::unit_triangle());
+        let mesh = model.meshes.push(Mesh::new(vec![primitive]));
+        let node = model.nodes.push(Node::builder().mesh(mesh).build());
+        model.root.children.push(node);
+        scene.push(model);
+        scene.push_default_model();
+        scene.config.samples_per_pixel = 3;
+
+        // Every call adds a sample, until the samples per pixel of the config
+        let mut checkpoint = Checkpoint::default();
+        let mut progressive = Image::new(4, 4, ColorType::RGBA8);
+        for sample_count in 1..=3 {
+            assert!(scene.draw_progressive(&mut checkpoint, &mut progressive));
+            assert_eq!(checkpoint.get_sample_count(), sample_count);
+        }
+        assert!(!scene.draw_progressive(&mut checkpoint, &mut progressive));
+
+        // The result is the same as drawing all the samples at once
+        let mut image = Image::new(4, 4, ColorType::RGBA8);
+        scene.draw(&mut image);
+        assert_eq!(progressive.bytes(), image.bytes());
+
+        // Images of another size start from scratch
+        let mut image = Image::new(2, 2, ColorType::RGBA8);
+        assert!(scene.draw_progressive(&mut checkpoint, &mut image));
+        assert_eq!(checkpoint.get_sample_count(), 1);
+    }
+
+    #[test]
+    fn draw_temporal() {
+        let mut scene = Scene::new();
+        scene.push_default_model();
+        let primitive = scene.model.primitives.push(Primitive::unit_triangle());
+        let mesh = scene.model.meshes.push(Mesh::new(vec![primitive]));
+        let node = scene.model.nodes.push(Node::builder().mesh(mesh).build());
+        scene.model.root.children.push(node);
+
+        let mut temporal = TemporalAccumulation::default();
+        let mut image = Image::new(8, 8, ColorType::RGBA8);
+        scene.draw_temporal(&mut temporal, &mut image);
+        let first = image.bytes().to_vec();
+
+        // The history follows the triangle moving around
+        for _ in 0..3 {
+            scene.model.nodes.get_mut(node).unwrap().trs.translation += Vec3::new(0.1, 0.0, 0.0);
+            scene.model.mark_dirty(node);
+            scene.draw_temporal(&mut temporal, &mut image);
+        }
+        assert_eq!(temporal.get_frame_count(), 4);
+        assert_ne!(image.bytes(), first.as_slice());
+    }
+
+    #[test]
+    fn render_samples() {
+        let mut scene = Scene::new();
+        scene.push_default_model();
+        let primitive = scene.model.primitives.push(Primitive::unit_triangle());
+        let mesh = scene.model.meshes.push(Mesh::new(vec![primitive]));
+        let node = scene.model.nodes.push(Node::builder().mesh(mesh).build());
+        scene.model.root.children.push(node);
+        scene.config.samples_per_pixel = 2;
+
+        // Samples rendered separately add up to the ones rendered together
+        let tiles = scene.render_rows(4, 4, 1..3);
+        let mut merged = scene.render_samples(4, 4, 1..3, 0..1);
+        for (tile, next) in merged
+            .iter_mut()
+            .zip(scene.render_samples(4, 4, 1..3, 1..2))
+        {
+            assert_eq!(next.sample_count, 2);
+            tile.merge(&next);
+        }
+        assert_eq!(merged, tiles);
+    }
+
     #[test]
     fn load() {
         let mut scene = Scene::new();
@@ -0,0 +1,253 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Standard scenes built programmatically, so that examples, benchmarks,
+//! and regression tests can share the same setups.
+
+use super::*;
+
+/// Diffuse material of a certain color
+fn diffuse(color: Color) -> Material {
+    let mut ret = Material::builder().color(color).build();
+    ret.metallic_factor = 0.0;
+    ret
+}
+
+/// Adds a node with a mesh made of a single primitive to the root of `model`
+fn push_primitive(model: &mut Model, geometry: PrimitiveBuilder, material: Material, trs: Trs) {
+    let material = model.materials.push(material);
+    let primitive = model.primitives.push(geometry.material(material).build());
+    let mesh = model.meshes.push(Mesh::new(vec![primitive]));
+    let node = model
+        .nodes
+        .push(Node::builder().mesh(mesh).trs(trs).build());
+    model.root.children.push(node);
+}
+
+fn push_quad_light(model: &mut Model, size: f32, intensity: f32, translation: Vec3) {
+    let mut light = Light::quad(size, size);
+    light.set_intensity(intensity);
+    let light = model.lights.push(light);
+    let node = Node::builder()
+        .light(light)
+        .translation(translation)
+        .build();
+    let node = model.nodes.push(node);
+    model.root.children.push(node);
+}
+
+/// Adds a camera looking towards the negative Z axis
+fn push_camera(model: &mut Model, translation: Vec3) {
+    let camera = model.cameras.push(Camera::default());
+    let node = Node::builder()
+        .camera(camera)
+        .translation(translation)
+        .build();
+    let node = model.nodes.push(node);
+    model.root.children.push(node);
+}
+
+impl Scene {
+    /// Classic grid of 5 x 5 spheres useful to validate materials at a glance,
+    /// see `pbr_test_grid_with()`
+    pub fn pbr_test_grid() -> Self {
+        Self::pbr_test_grid_with(Model::new(), Handle::NONE, 5)
+    }
+
+    /// Grid of `size` x `size` spheres with the color and textures of material `base`
+    /// of `source`, or of a default material when not valid. Images, textures, and
+    /// samplers of `source` move to the grid, so that texture handles stay valid.
+    /// Metallic grows from the bottom row to the top one, and roughness from the left
+    /// column to the right one. Spheres are lit by a directional light coming from the
+    /// top-left, and by the point lights of the default model
+    pub fn pbr_test_grid_with(mut source: Model, base: Handle<Material>, size: u32) -> Self {
+        let size = size.max(1);
+        let spacing = 2.5;
+        let half_extent = (size - 1) as f32 * spacing * 0.5;
+        let get_factor = |i: u32| {
+            if size > 1 {
+                i as f32 / (size - 1) as f32
+            } else {
+                0.5
+            }
+        };
+
+        let default = Material::new();
+        let base = source.materials.get(base).unwrap_or(&default);
+        let mut model = Model::new();
+        model.samplers = std::mem::take(&mut source.samplers);
+        model.images = std::mem::take(&mut source.images);
+        model.textures = std::mem::take(&mut source.textures);
+        for row in 0..size {
+            for column in 0..size {
+                let material = Material {
+                    color: base.color,
+                    albedo_texture: base.albedo_texture,
+                    albedo_transform: base.albedo_transform,
+                    normal_texture: base.normal_texture,
+                    normal_transform: base.normal_transform,
+                    metallic_factor: get_factor(row),
+                    // Perfectly smooth spheres would only reflect the lights as points
+                    roughness_factor: get_factor(column).max(0.05),
+                    metallic_roughness_texture: Handle::NONE,
+                    metallic_roughness_transform: TextureTransform::IDENTITY,
+                    occlusion_texture: base.occlusion_texture,
+                    occlusion_transform: base.occlusion_transform,
+                    occlusion_strength: base.occlusion_strength,
+                    displacement_texture: base.displacement_texture,
+                    displacement_scale: base.displacement_scale,
+                    vertex_color: base.vertex_color,
+                    mix: None,
+                    conductor: base.conductor,
+                    shadow_catcher: base.shadow_catcher,
+                    graph: None,
+                    bevel_radius: base.bevel_radius,
+                    extras: base.extras.clone(),
+                };
+                let translation = Vec3::new(
+                    column as f32 * spacing - half_extent,
+                    row as f32 * spacing - half_extent,
+                    0.0,
+                );
+                push_primitive(
+                    &mut model,
+                    Primitive::builder().sphere(Point3::default(), 1.0),
+                    material,
+                    Trs::builder().translation(translation).build(),
+                );
+            }
+        }
+
+        // Directional light rotated from its default direction to the top-left front
+        let rotation = Quat::rotation_between(Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.5, -1.0, -1.0));
+        let mut light = Light::directional();
+        light.set_intensity(2.0);
+        let light = model.lights.push(light);
+        let light_node = Node::builder().light(light).rotation(rotation).build();
+        let light_node = model.nodes.push(light_node);
+        model.root.children.push(light_node);
+
+        // The default model brings a camera, which is moved back to frame the grid
+        let mut default_model = Self::create_default_model();
+        let camera_node = default_model.root.children[0];
+        let camera = default_model.cameras.get(Handle::new(0)).unwrap();
+        let distance = (half_extent + 1.5) / camera.get_angle() + 1.0;
+        default_model.nodes.get_mut(camera_node).unwrap().trs = Trs::builder()
+            .translation(Vec3::new(0.0, 0.0, distance))
+            .build();
+
+        let mut scene = Self::new();
+        scene.push(model);
+        scene.push(default_model);
+        scene
+    }
+
+    /// Cornell box of side 2 with the floor at the origin, a red wall on the left,
+    /// a green wall on the right, a quad light on the ceiling, and two spheres on the
+    /// floor: a diffuse one and a metallic one
+    pub fn cornell_box() -> Self {
+        let mut model = Model::new();
+        let white = Color::new(0.73, 0.73, 0.73, 1.0);
+        let red = Color::new(0.65, 0.05, 0.05, 1.0);
+        let green = Color::new(0.12, 0.45, 0.15, 1.0);
+
+        // Planes face up, hence walls are rotated to face the inside of the box
+        let walls = [
+            (white, Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+            (white, Vec3::new(0.0, 2.0, 0.0), Vec3::new(0.0, -1.0, 0.0)),
+            (white, Vec3::new(0.0, 1.0, -1.0), Vec3::new(0.0, 0.0, 1.0)),
+            (red, Vec3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0)),
+            (green, Vec3::new(1.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)),
+        ];
+        for (color, translation, normal) in walls {
+            let trs = Trs::builder()
+                .translation(translation)
+                .rotation(Quat::rotation_between(Vec3::new(0.0, 1.0, 0.0), normal))
+                .build();
+            push_primitive(
+                &mut model,
+                Primitive::builder().triangles(Triangles::plane(2.0, 2.0, 1, 1)),
+                diffuse(color),
+                trs,
+            );
+        }
+
+        let spheres = [
+            (Vec3::new(-0.4, 0.35, -0.3), diffuse(white)),
+            (Vec3::new(0.45, 0.35, 0.3), {
+                let mut metal = Material::new();
+                metal.roughness_factor = 0.1;
+                metal
+            }),
+        ];
+        for (translation, material) in spheres {
+            push_primitive(
+                &mut model,
+                Primitive::builder().sphere(Point3::default(), 0.35),
+                material,
+                Trs::builder().translation(translation).build(),
+            );
+        }
+
+        push_quad_light(&mut model, 0.5, 16.0, Vec3::new(0.0, 1.99, 0.0));
+        push_camera(&mut model, Vec3::new(0.0, 1.0, 3.4));
+
+        let mut scene = Self::new();
+        scene.push(model);
+        scene
+    }
+
+    /// Scene of Veach's thesis showing the strengths of different sampling strategies:
+    /// four glossy plates, from the sharpest to the roughest one, reflect four quad
+    /// lights of the same power, from the smallest to the largest one
+    pub fn veach_mis() -> Self {
+        let mut model = Model::new();
+        let camera = Vec3::new(0.0, 2.0, 6.0);
+        let light_center = Vec3::new(0.0, 3.5, -3.0);
+
+        for (i, roughness) in [0.05, 0.15, 0.35, 0.7].iter().enumerate() {
+            // Plates are placed like steps going away from the camera,
+            // and tilted to reflect the lights towards it
+            let translation = Vec3::new(0.0, i as f32 * 0.5, i as f32 * -0.8);
+            let to_camera = (camera - translation).get_normalized();
+            let to_lights = (light_center - translation).get_normalized();
+            let trs = Trs::builder()
+                .translation(translation)
+                .rotation(Quat::rotation_between(
+                    Vec3::new(0.0, 1.0, 0.0),
+                    to_camera + to_lights,
+                ))
+                .build();
+            let mut plate = Material::builder()
+                .color(Color::new(0.8, 0.8, 0.8, 1.0))
+                .build();
+            plate.roughness_factor = *roughness;
+            push_primitive(
+                &mut model,
+                Primitive::builder().triangles(Triangles::plane(4.0, 0.6, 1, 1)),
+                plate,
+                trs,
+            );
+        }
+
+        for (i, size) in [0.05, 0.15, 0.3, 0.6].iter().enumerate() {
+            let translation = light_center + Vec3::new(i as f32 - 1.5, 0.0, 0.0);
+            push_quad_light(&mut model, *size, 16.0, translation);
+        }
+
+        push_camera(&mut model, camera);
+
+        let mut scene = Self::new();
+        scene.push(model);
+        scene
+    }
+
+    /// The grid of `pbr_test_grid()` rendered by the `Furnace` integrator,
+    /// which is useful to spot materials reflecting more light than they receive
+    pub fn furnace() -> Self {
+        let mut scene = Self::pbr_test_grid();
+        scene.config.integrator = Box::new(Furnace::default());
+        scene
+    }
+}
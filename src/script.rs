@@ -0,0 +1,628 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Small language to set up and animate scenes, one command per line, where `#` starts a
+//! comment. Nodes are referred to by name, either created by the script or found in the
+//! scene, and angles are in degrees.
+//!
+//! ```text
+//! material <name> <r> <g> <b> [metallic <factor>] [roughness <factor>]
+//! sphere <node> <radius> [<material>]
+//! plane <node> <width> <depth> [<material>]
+//! light <node> point|directional <intensity>
+//! camera <node> [<yfov>]
+//! translate <node> <x> <y> <z>
+//! rotate <node> <axis x> <axis y> <axis z> <angle>
+//! scale <node> <factor>
+//! assign <node> <material>
+//! key <node> <frame> translation <x> <y> <z>
+//! key <node> <frame> rotation <axis x> <axis y> <axis z> <angle>
+//! key <node> <frame> scale <factor>
+//! at <frame> <command>
+//! render <path> <width> <height> [<frames>]
+//! ```
+//!
+//! Keys animate nodes by interpolating their properties between frames, and `at` runs a
+//! command when a frame is drawn. Both happen every time the scene draws a frame, see
+//! `Scene::script`. Renders write PNG files, where `{frame}` in the path is replaced by
+//! the number of the frame.
+
+use std::{collections::HashMap, error::Error, str::SplitWhitespace};
+
+use crate::*;
+
+/// Value of an animated property at a certain frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Translation(Vec3),
+    /// Axis and angle in degrees
+    Rotation(Vec3, f32),
+    Scale(f32),
+}
+
+impl Key {
+    /// Whether `self` and `other` animate the same property
+    fn same_property(&self, other: &Key) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    fn get_rotation(axis: Vec3, degrees: f32) -> Quat {
+        Quat::axis_angle(axis.get_normalized(), degrees.to_radians())
+    }
+
+    /// Sets the property of this key, interpolated towards `next` by `t`, to `trs`
+    fn apply(&self, next: &Key, t: f32, trs: &mut Trs) {
+        match (self, next) {
+            (Key::Translation(a), Key::Translation(b)) => {
+                trs.translation = *a * (1.0 - t) + *b * t;
+            }
+            (Key::Rotation(axis_a, degrees_a), Key::Rotation(axis_b, degrees_b)) => {
+                let a = Self::get_rotation(*axis_a, *degrees_a);
+                let b = Self::get_rotation(*axis_b, *degrees_b);
+                trs.rotation = a.slerp(&b, t);
+            }
+            (Key::Scale(a), Key::Scale(b)) => {
+                let scale = a * (1.0 - t) + b * t;
+                trs.scale = Vec3::new(scale, scale, scale);
+            }
+            _ => unreachable!("Keys of different properties"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightKind {
+    Point,
+    Directional,
+}
+
+/// Parsed line of a script, see the module documentation
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Material {
+        name: String,
+        color: Color,
+        metallic: f32,
+        roughness: f32,
+    },
+    Sphere {
+        node: String,
+        radius: f32,
+        material: Option<String>,
+    },
+    Plane {
+        node: String,
+        width: f32,
+        depth: f32,
+        material: Option<String>,
+    },
+    Light {
+        node: String,
+        kind: LightKind,
+        intensity: f32,
+    },
+    Camera {
+        node: String,
+        yfov: Option<f32>,
+    },
+    Translate {
+        node: String,
+        translation: Vec3,
+    },
+    Rotate {
+        node: String,
+        axis: Vec3,
+        degrees: f32,
+    },
+    Scale {
+        node: String,
+        scale: f32,
+    },
+    Assign {
+        node: String,
+        material: String,
+    },
+    Key {
+        node: String,
+        frame: u32,
+        key: Key,
+    },
+    At {
+        frame: u32,
+        command: Box<Command>,
+    },
+    Render {
+        path: String,
+        width: u32,
+        height: u32,
+        frames: u32,
+    },
+}
+
+/// Tokens of a line
+struct Tokens<'s> {
+    tokens: SplitWhitespace<'s>,
+}
+
+impl<'s> Tokens<'s> {
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        Ok(self.tokens.next().ok_or("Missing argument")?.to_string())
+    }
+
+    fn optional(&mut self) -> Option<String> {
+        self.tokens.next().map(str::to_string)
+    }
+
+    fn f32(&mut self) -> Result<f32, Box<dyn Error>> {
+        let token = self.tokens.next().ok_or("Missing number")?;
+        token
+            .parse()
+            .map_err(|_| format!("Invalid number {}", token).into())
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        let token = self.tokens.next().ok_or("Missing integer")?;
+        token
+            .parse()
+            .map_err(|_| format!("Invalid integer {}", token).into())
+    }
+
+    fn vec3(&mut self) -> Result<Vec3, Box<dyn Error>> {
+        Ok(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn end(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.tokens.next() {
+            Some(token) => Err(format!("Unexpected {}", token).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Command {
+    /// Parses a line without comments which is not empty
+    fn parse(line: &str) -> Result<Self, Box<dyn Error>> {
+        let mut tokens = Tokens {
+            tokens: line.split_whitespace(),
+        };
+        let name = tokens.string()?;
+        let ret = match name.as_str() {
+            "material" => {
+                let name = tokens.string()?;
+                let color = tokens.vec3()?;
+                let mut ret = Command::Material {
+                    name,
+                    color: Color::new(color.get_x(), color.get_y(), color.get_z(), 1.0),
+                    metallic: 0.0,
+                    roughness: 1.0,
+                };
+                while let Some(property) = tokens.optional() {
+                    let Command::Material {
+                        metallic,
+                        roughness,
+                        ..
+                    } = &mut ret
+                    else {
+                        unreachable!()
+                    };
+                    match property.as_str() {
+                        "metallic" => *metallic = tokens.f32()?,
+                        "roughness" => *roughness = tokens.f32()?,
+                        _ => return Err(format!("Unknown material property {}", property).into()),
+                    }
+                }
+                ret
+            }
+            "sphere" => Command::Sphere {
+                node: tokens.string()?,
+                radius: tokens.f32()?,
+                material: tokens.optional(),
+            },
+            "plane" => Command::Plane {
+                node: tokens.string()?,
+                width: tokens.f32()?,
+                depth: tokens.f32()?,
+                material: tokens.optional(),
+            },
+            "light" => {
+                let node = tokens.string()?;
+                let kind = match tokens.string()?.as_str() {
+                    "point" => LightKind::Point,
+                    "directional" => LightKind::Directional,
+                    kind => return Err(format!("Unknown light {}", kind).into()),
+                };
+                Command::Light {
+                    node,
+                    kind,
+                    intensity: tokens.f32()?,
+                }
+            }
+            "camera" => Command::Camera {
+                node: tokens.string()?,
+                yfov: tokens.optional().map(|yfov| yfov.parse()).transpose()?,
+            },
+            "translate" => Command::Translate {
+                node: tokens.string()?,
+                translation: tokens.vec3()?,
+            },
+            "rotate" => Command::Rotate {
+                node: tokens.string()?,
+                axis: tokens.vec3()?,
+                degrees: tokens.f32()?,
+            },
+            "scale" => Command::Scale {
+                node: tokens.string()?,
+                scale: tokens.f32()?,
+            },
+            "assign" => Command::Assign {
+                node: tokens.string()?,
+                material: tokens.string()?,
+            },
+            "key" => {
+                let node = tokens.string()?;
+                let frame = tokens.u32()?;
+                let key = match tokens.string()?.as_str() {
+                    "translation" => Key::Translation(tokens.vec3()?),
+                    "rotation" => Key::Rotation(tokens.vec3()?, tokens.f32()?),
+                    "scale" => Key::Scale(tokens.f32()?),
+                    property => return Err(format!("Unknown property {}", property).into()),
+                };
+                Command::Key { node, frame, key }
+            }
+            "at" => {
+                let frame = tokens.u32()?;
+                let rest = tokens.tokens.collect::<Vec<_>>().join(" ");
+                let command = Command::parse(&rest)?;
+                if matches!(command, Command::Render { .. } | Command::At { .. }) {
+                    return Err("Renders can not be triggered by frames".into());
+                }
+                return Ok(Command::At {
+                    frame,
+                    command: Box::new(command),
+                });
+            }
+            "render" => Command::Render {
+                path: tokens.string()?,
+                width: tokens.u32()?,
+                height: tokens.u32()?,
+                frames: tokens
+                    .optional()
+                    .map(|frames| frames.parse())
+                    .transpose()?
+                    .unwrap_or(1),
+            },
+            _ => return Err(format!("Unknown command {}", name).into()),
+        };
+        tokens.end()?;
+        Ok(ret)
+    }
+}
+
+/// Parsed script, with the objects it created, see the module documentation
+#[derive(Default)]
+pub struct Script {
+    pub commands: Vec<Command>,
+
+    materials: HashMap<String, Handle<Material>>,
+    nodes: HashMap<String, Handle<Node>>,
+
+    /// Keys of every animated node, sorted by frame
+    keys: HashMap<String, Vec<(u32, Key)>>,
+
+    /// Commands run when a frame is drawn
+    hooks: Vec<(u32, Command)>,
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Self, Box<dyn Error>> {
+        let mut commands = vec![];
+        for (i, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let command = Command::parse(line).map_err(|err| format!("Line {}: {}", i + 1, err))?;
+            commands.push(command);
+        }
+        Ok(Self {
+            commands,
+            ..Default::default()
+        })
+    }
+
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Runs the commands of the script on `scene`, which keeps the script
+    /// to animate the frames it draws afterwards
+    pub fn run(self, scene: &mut Scene) -> Result<(), Box<dyn Error>> {
+        let commands = self.commands.clone();
+        scene.script = Some(self);
+        for command in &commands {
+            match command {
+                Command::Render {
+                    path,
+                    width,
+                    height,
+                    frames,
+                } => {
+                    if scene.get_camera_node_handle().is_none() {
+                        scene.push_default_model();
+                    }
+                    for frame in 0..*frames {
+                        scene.frame = frame;
+                        let mut image = Image::new(*width, *height, ColorType::RGBA8);
+                        scene.draw(&mut image);
+                        image.dump_png(path.replace("{frame}", &format!("{:04}", frame)));
+                    }
+                }
+                command => {
+                    let script = scene.script.as_mut().unwrap();
+                    script.execute(&mut scene.model, command)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the node called `name`, created by the script or found in `model`
+    fn get_node(&self, model: &Model, name: &str) -> Result<Handle<Node>, Box<dyn Error>> {
+        if let Some(node) = self.nodes.get(name) {
+            return Ok(*node);
+        }
+        let mut stack = model.root.children.clone();
+        while let Some(handle) = stack.pop() {
+            if let Some(node) = model.nodes.get(handle) {
+                if node.name == name {
+                    return Ok(handle);
+                }
+                stack.extend_from_slice(&node.children);
+            }
+        }
+        Err(format!("No node called {}", name).into())
+    }
+
+    fn get_material(&self, name: &str) -> Result<Handle<Material>, Box<dyn Error>> {
+        self.materials
+            .get(name)
+            .copied()
+            .ok_or_else(|| format!("No material called {}", name).into())
+    }
+
+    /// Adds a node called `name` to the root of `model`
+    fn push_node(&mut self, model: &mut Model, name: &str, builder: NodeBuilder) {
+        let node = model.nodes.push(builder.name(name.to_string()).build());
+        model.root.children.push(node);
+        model.mark_all_dirty();
+        self.nodes.insert(name.to_string(), node);
+    }
+
+    fn push_shape(
+        &mut self,
+        model: &mut Model,
+        name: &str,
+        geometry: PrimitiveBuilder,
+        material: &Option<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        let material = match material {
+            Some(material) => self.get_material(material)?,
+            None => Handle::NONE,
+        };
+        let primitive = model.primitives.push(geometry.material(material).build());
+        let mesh = model.meshes.push(Mesh::new(vec![primitive]));
+        self.push_node(model, name, Node::builder().mesh(mesh));
+        Ok(())
+    }
+
+    /// Runs any command but renders on `model`
+    pub fn execute(&mut self, model: &mut Model, command: &Command) -> Result<(), Box<dyn Error>> {
+        match command {
+            Command::Material {
+                name,
+                color,
+                metallic,
+                roughness,
+            } => {
+                let material = Material {
+                    color: *color,
+                    metallic_factor: *metallic,
+                    roughness_factor: *roughness,
+                    ..Material::new()
+                };
+                let material = model.materials.push(material);
+                self.materials.insert(name.clone(), material);
+            }
+            Command::Sphere {
+                node,
+                radius,
+                material,
+            } => {
+                let geometry = Primitive::builder().sphere(Point3::default(), *radius);
+                self.push_shape(model, node, geometry, material)?;
+            }
+            Command::Plane {
+                node,
+                width,
+                depth,
+                material,
+            } => {
+                let geometry =
+                    Primitive::builder().triangles(Triangles::plane(*width, *depth, 1, 1));
+                self.push_shape(model, node, geometry, material)?;
+            }
+            Command::Light {
+                node,
+                kind,
+                intensity,
+            } => {
+                let mut light = match kind {
+                    LightKind::Point => Light::point(),
+                    LightKind::Directional => Light::directional(),
+                };
+                light.set_intensity(*intensity);
+                let light = model.lights.push(light);
+                self.push_node(model, node, Node::builder().light(light));
+            }
+            Command::Camera { node, yfov } => {
+                let mut camera = Camera::default();
+                if let Some(yfov) = yfov {
+                    camera.yfov_radians = yfov.to_radians();
+                }
+                let camera = model.cameras.push(camera);
+                self.push_node(model, node, Node::builder().camera(camera));
+            }
+            Command::Translate { node, translation } => {
+                let node = self.get_node(model, node)?;
+                model.nodes.get_mut(node).unwrap().trs.translation = *translation;
+                model.mark_dirty(node);
+            }
+            Command::Rotate {
+                node,
+                axis,
+                degrees,
+            } => {
+                let node = self.get_node(model, node)?;
+                model.nodes.get_mut(node).unwrap().trs.rotation =
+                    Key::get_rotation(*axis, *degrees);
+                model.mark_dirty(node);
+            }
+            Command::Scale { node, scale } => {
+                let node = self.get_node(model, node)?;
+                model.nodes.get_mut(node).unwrap().trs.scale = Vec3::new(*scale, *scale, *scale);
+                model.mark_dirty(node);
+            }
+            Command::Assign { node, material } => {
+                let material = self.get_material(material)?;
+                let node = self.get_node(model, node)?;
+                model.nodes.get_mut(node).unwrap().material = material;
+                model.mark_dirty(node);
+            }
+            Command::Key { node, frame, key } => {
+                self.get_node(model, node)?;
+                let keys = self.keys.entry(node.clone()).or_default();
+                keys.push((*frame, *key));
+                keys.sort_by_key(|(frame, _)| *frame);
+            }
+            Command::At { frame, command } => {
+                self.hooks.push((*frame, command.as_ref().clone()));
+            }
+            Command::Render { .. } => return Err("Renders need a scene".into()),
+        }
+        Ok(())
+    }
+
+    /// Animates `model` for `frame`, running the commands of that frame and interpolating
+    /// the keys of the nodes. It is called by the scene before drawing every frame
+    pub fn update(&mut self, model: &mut Model, frame: u32) {
+        let hooks = self
+            .hooks
+            .iter()
+            .filter(|(hook_frame, _)| *hook_frame == frame)
+            .map(|(_, command)| command.clone())
+            .collect::<Vec<_>>();
+        for command in &hooks {
+            if let Err(err) = self.execute(model, command) {
+                print_warning!("Script", "Frame {}: {}", frame, err);
+            }
+        }
+
+        for (name, keys) in &self.keys {
+            let Ok(node) = self.get_node(model, name) else {
+                continue;
+            };
+            model.mark_dirty(node);
+            let trs = &mut model.nodes.get_mut(node).unwrap().trs;
+            // Every property is animated by its own keys
+            for (i, (_, key)) in keys.iter().enumerate() {
+                let first = keys[..i].iter().all(|(_, other)| !other.same_property(key));
+                if !first {
+                    continue;
+                }
+                let property_keys = keys
+                    .iter()
+                    .filter(|(_, other)| other.same_property(key))
+                    .collect::<Vec<_>>();
+                let next = property_keys
+                    .iter()
+                    .position(|(key_frame, _)| *key_frame > frame)
+                    .unwrap_or(property_keys.len());
+                let (a, b) = match next {
+                    0 => (property_keys[0], property_keys[0]),
+                    next if next == property_keys.len() => {
+                        (property_keys[next - 1], property_keys[next - 1])
+                    }
+                    next => (property_keys[next - 1], property_keys[next]),
+                };
+                let span = b.0.saturating_sub(a.0);
+                let t = if span > 0 {
+                    (frame - a.0) as f32 / span as f32
+                } else {
+                    0.0
+                };
+                a.1.apply(&b.1, t, trs);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let script = Script::parse(
+            "# A red ball\n\
+             material red 1 0 0 roughness 0.5\n\
+             sphere ball 1 red\n\
+             key ball 0 translation 0 0 0\n\
+             at 2 scale ball 2\n",
+        )
+        .unwrap();
+        assert_eq!(script.commands.len(), 4);
+        assert_eq!(
+            script.commands[0],
+            Command::Material {
+                name: "red".to_string(),
+                color: Color::new(1.0, 0.0, 0.0, 1.0),
+                metallic: 0.0,
+                roughness: 0.5,
+            }
+        );
+
+        let err = Script::parse("sphere ball\n").err().unwrap();
+        assert_eq!(err.to_string(), "Line 1: Missing number");
+        assert!(Script::parse("at 1 render a.png 1 1\n").is_err());
+        assert!(Script::parse("scale ball 1 2\n").is_err());
+    }
+
+    #[test]
+    fn animate() {
+        let script = Script::parse(
+            "sphere ball 1\n\
+             key ball 0 translation 0 0 0\n\
+             key ball 10 translation 10 0 0\n\
+             key ball 0 scale 1\n\
+             at 5 assign ball missing\n\
+             at 8 scale ball 3\n",
+        )
+        .unwrap();
+        let mut scene = Scene::new();
+        script.run(&mut scene).unwrap();
+
+        let mut script = scene.script.take().unwrap();
+        let ball = script.get_node(&scene.model, "ball").unwrap();
+        let get_trs = |model: &Model| model.nodes.get(ball).unwrap().trs.clone();
+
+        script.update(&mut scene.model, 5);
+        assert_eq!(get_trs(&scene.model).translation, Vec3::new(5.0, 0.0, 0.0));
+        script.update(&mut scene.model, 20);
+        assert_eq!(get_trs(&scene.model).translation, Vec3::new(10.0, 0.0, 0.0));
+
+        // Keys win over commands run at the same frame
+        script.update(&mut scene.model, 8);
+        assert_eq!(get_trs(&scene.model).scale, Vec3::new(1.0, 1.0, 1.0));
+    }
+}
@@ -0,0 +1,587 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Headless render server, so that web services can drive renders over HTTP:
+//!
+//! - `POST /renders?width=W&height=H&samples=S` with a binary glTF, or a glTF with embedded
+//!   resources, as body starts a render and replies with its id, as in `{"id":0}`.
+//!   Scenes without cameras get the default camera and lights. While `MAX_ACTIVE_RENDERS`
+//!   renders are running, new ones are refused with `503 Service Unavailable`.
+//! - `GET /renders/{id}` replies with the status of the render, as in
+//!   `{"status":"rendering","progress":42.0}`, where status can also be `done` or `failed`.
+//! - `GET /renders/{id}/image.png` replies with the rendered image, once done.
+//! - `GET /renders/{id}/image.exr` replies with the linear colors of the rendered image,
+//!   before post effects and exposure, once done.
+//!
+//! Only the last `MAX_FINISHED_RENDERS` finished renders are kept, older ones are not found.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::*;
+
+/// Bodies larger than this are refused
+const MAX_BODY_SIZE: usize = 32 << 20;
+
+/// Request and header lines longer than this are refused
+const MAX_LINE_SIZE: usize = 8 << 10;
+
+/// Requests with more headers than this are refused
+const MAX_HEADER_COUNT: usize = 64;
+
+/// Images larger than this on either side are refused
+const MAX_IMAGE_SIZE: u32 = 8192;
+
+/// Renders with more samples per pixel than this are refused
+const MAX_SAMPLES: u32 = 4096;
+
+/// Renders tracing more camera rays than this, counting every sample of every pixel,
+/// are refused, as they would keep a thread busy for too long
+const MAX_RAY_COUNT: u64 = 1 << 30;
+
+/// Renders loading or running at the same time
+const MAX_ACTIVE_RENDERS: usize = 4;
+
+/// Connections handled at the same time, each by its own thread, after which new
+/// ones are refused with `503 Service Unavailable`
+const MAX_CONNECTIONS: usize = 64;
+
+/// Time a connection may wait for the client to send or to receive data,
+/// so that slow clients do not keep a thread busy forever
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Finished renders kept for their results, after which the oldest ones are dropped
+const MAX_FINISHED_RENDERS: usize = 32;
+
+/// Escapes `text` to go within the quotes of a JSON string
+fn escape_json(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => ret.push_str("\\\\"),
+            '"' => ret.push_str("\\\""),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+/// Files of a finished render
+struct RenderOutput {
+    png: Vec<u8>,
+    exr: Vec<u8>,
+}
+
+/// Render submitted to the server
+#[derive(Default)]
+struct Render {
+    /// Percentage of completed rows, stored as the bits of a `f32`
+    progress: AtomicU32,
+
+    /// Output of the render once done, or the reason of the failure
+    result: Mutex<Option<Result<RenderOutput, String>>>,
+}
+
+impl Render {
+    fn is_finished(&self) -> bool {
+        self.result.lock().unwrap().is_some()
+    }
+
+    fn get_progress(&self) -> f32 {
+        f32::from_bits(self.progress.load(Ordering::Relaxed))
+    }
+
+    fn get_status(&self) -> String {
+        match &*self.result.lock().unwrap() {
+            None => format!(
+                "{{\"status\":\"rendering\",\"progress\":{:.1}}}",
+                self.get_progress()
+            ),
+            Some(Ok(_)) => "{\"status\":\"done\",\"progress\":100.0}".to_string(),
+            Some(Err(err)) => format!(
+                "{{\"status\":\"failed\",\"error\":\"{}\"}}",
+                escape_json(err)
+            ),
+        }
+    }
+}
+
+/// Parameters of a render, from the query of the request submitting it
+struct RenderParams {
+    width: u32,
+    height: u32,
+    samples_per_pixel: u32,
+}
+
+impl RenderParams {
+    fn parse(query: &str) -> Result<Self, Box<dyn Error>> {
+        let mut ret = Self {
+            width: 512,
+            height: 512,
+            samples_per_pixel: 1,
+        };
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').ok_or("Invalid query")?;
+            let value = value.parse::<u32>()?;
+            match key {
+                "width" => ret.width = value,
+                "height" => ret.height = value,
+                "samples" => ret.samples_per_pixel = value,
+                _ => return Err(format!("Unknown parameter {}", key).into()),
+            }
+        }
+        let valid = 1..=MAX_IMAGE_SIZE;
+        if !valid.contains(&ret.width) || !valid.contains(&ret.height) {
+            return Err("Invalid image size".into());
+        }
+        if !(1..=MAX_SAMPLES).contains(&ret.samples_per_pixel) {
+            return Err("Invalid samples per pixel".into());
+        }
+        let ray_count = ret.width as u64 * ret.height as u64 * ret.samples_per_pixel as u64;
+        if ray_count > MAX_RAY_COUNT {
+            return Err("Too many samples for the image size".into());
+        }
+        Ok(ret)
+    }
+}
+
+/// HTTP request, which is all the server needs of it
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    body: Vec<u8>,
+}
+
+/// Reads a line of at most `MAX_LINE_SIZE` bytes into `line`, replacing its contents
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> Result<(), Box<dyn Error>> {
+    line.clear();
+    reader.by_ref().take(MAX_LINE_SIZE as u64).read_line(line)?;
+    if !line.ends_with('\n') {
+        return Err(if line.len() < MAX_LINE_SIZE {
+            "Unexpected end of request".into()
+        } else {
+            "Line too long".into()
+        });
+    }
+    Ok(())
+}
+
+impl Request {
+    fn read(stream: &mut TcpStream) -> Result<Self, Box<dyn Error>> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        read_line(&mut reader, &mut line)?;
+        let mut parts = line.split_whitespace();
+        let method = parts.next().ok_or("Missing method")?.to_string();
+        let target = parts.next().ok_or("Missing path")?;
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let (path, query) = (path.to_string(), query.to_string());
+
+        let mut headers = HashMap::new();
+        loop {
+            read_line(&mut reader, &mut line)?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if headers.len() >= MAX_HEADER_COUNT {
+                return Err("Too many headers".into());
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+            }
+        }
+
+        let len = match headers.get("content-length") {
+            Some(len) => len.parse::<usize>()?,
+            None => 0,
+        };
+        if len > MAX_BODY_SIZE {
+            return Err("Body too large".into());
+        }
+        // Bytes are stored as they arrive, rather than trusting the length up front
+        let mut body = vec![];
+        reader.take(len as u64).read_to_end(&mut body)?;
+        if body.len() < len {
+            return Err("Unexpected end of request".into());
+        }
+
+        Ok(Self {
+            method,
+            path,
+            query,
+            body,
+        })
+    }
+}
+
+fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Responds with a status and a JSON body
+fn write_json(stream: &mut TcpStream, status: &str, json: &str) -> Result<(), Box<dyn Error>> {
+    write_response(stream, status, "application/json", json.as_bytes())
+}
+
+fn write_error(stream: &mut TcpStream, status: &str, error: &str) -> Result<(), Box<dyn Error>> {
+    write_json(
+        stream,
+        status,
+        &format!("{{\"error\":\"{}\"}}", escape_json(error)),
+    )
+}
+
+/// Renders of the server by id
+#[derive(Default)]
+struct Renders {
+    next_id: usize,
+    renders: BTreeMap<usize, Arc<Render>>,
+}
+
+impl Renders {
+    fn push(&mut self, render: Arc<Render>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.renders.insert(id, render);
+
+        // Ids grow with time, hence the first finished renders are the oldest
+        let finished = self
+            .renders
+            .iter()
+            .filter(|(_, render)| render.is_finished())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        let excess = finished.len().saturating_sub(MAX_FINISHED_RENDERS);
+        for id in &finished[..excess] {
+            self.renders.remove(id);
+        }
+        id
+    }
+}
+
+/// Renders submitted by HTTP requests, each in its own thread, see the module documentation
+#[derive(Clone, Default)]
+pub struct RenderServer {
+    renders: Arc<Mutex<Renders>>,
+
+    /// Renders loading or running
+    active_count: Arc<AtomicUsize>,
+
+    /// Connections being handled
+    connection_count: Arc<AtomicUsize>,
+}
+
+impl RenderServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles the requests of the connections to `listener`, each in its own thread,
+    /// up to `MAX_CONNECTIONS` at the same time
+    pub fn serve(&self, listener: TcpListener) -> Result<(), Box<dyn Error>> {
+        for stream in listener.incoming() {
+            let mut stream = stream?;
+            let connections =
+                self.connection_count
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                        Some(count + 1).filter(|count| *count <= MAX_CONNECTIONS)
+                    });
+            if connections.is_err() {
+                let result = stream
+                    .set_write_timeout(Some(CONNECTION_TIMEOUT))
+                    .map_err(|err| err.into())
+                    .and_then(|_| {
+                        write_error(
+                            &mut stream,
+                            "503 Service Unavailable",
+                            "Too many connections",
+                        )
+                    });
+                if let Err(err) = result {
+                    print_warning!("Server", "{}", err);
+                }
+                continue;
+            }
+
+            let server = self.clone();
+            std::thread::spawn(move || {
+                if let Err(err) = server.handle(stream) {
+                    print_warning!("Server", "{}", err);
+                }
+                server.connection_count.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads a request from `stream` and writes the response back, giving up on clients
+    /// which do not send or receive data within `CONNECTION_TIMEOUT`
+    pub fn handle(&self, mut stream: TcpStream) -> Result<(), Box<dyn Error>> {
+        stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+        stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+        let request = match Request::read(&mut stream) {
+            Ok(request) => request,
+            Err(err) => return write_error(&mut stream, "400 Bad Request", &err.to_string()),
+        };
+
+        let segments = request
+            .path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("POST", ["renders"]) => {
+                let active =
+                    self.active_count
+                        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                            Some(count + 1).filter(|count| *count <= MAX_ACTIVE_RENDERS)
+                        });
+                if active.is_err() {
+                    return write_error(&mut stream, "503 Service Unavailable", "Too many renders");
+                }
+                // Loaders may panic on malformed data
+                let result = std::panic::catch_unwind(AssertUnwindSafe(|| self.submit(&request)));
+                if !matches!(result, Ok(Ok(_))) {
+                    self.active_count.fetch_sub(1, Ordering::SeqCst);
+                }
+                match result {
+                    Ok(Ok(id)) => {
+                        write_json(&mut stream, "201 Created", &format!("{{\"id\":{}}}", id))
+                    }
+                    Ok(Err(err)) => write_error(&mut stream, "400 Bad Request", &err.to_string()),
+                    Err(_) => write_error(
+                        &mut stream,
+                        "500 Internal Server Error",
+                        "Loading the scene panicked",
+                    ),
+                }
+            }
+            ("GET", ["renders", id]) => match self.get_render(id) {
+                Some(render) => write_json(&mut stream, "200 OK", &render.get_status()),
+                None => write_error(&mut stream, "404 Not Found", "No such render"),
+            },
+            ("GET", ["renders", id, file @ ("image.png" | "image.exr")]) => {
+                let Some(render) = self.get_render(id) else {
+                    return write_error(&mut stream, "404 Not Found", "No such render");
+                };
+                let result = render.result.lock().unwrap();
+                match &*result {
+                    Some(Ok(output)) if *file == "image.png" => {
+                        write_response(&mut stream, "200 OK", "image/png", &output.png)
+                    }
+                    Some(Ok(output)) => {
+                        write_response(&mut stream, "200 OK", "image/x-exr", &output.exr)
+                    }
+                    Some(Err(err)) => write_error(&mut stream, "409 Conflict", err),
+                    None => write_error(&mut stream, "409 Conflict", "Render not done yet"),
+                }
+            }
+            _ => write_error(&mut stream, "404 Not Found", "No such endpoint"),
+        }
+    }
+
+    fn get_render(&self, id: &str) -> Option<Arc<Render>> {
+        let id = id.parse::<usize>().ok()?;
+        self.renders.lock().unwrap().renders.get(&id).cloned()
+    }
+
+    /// Loads the scene of `request` and starts rendering it, returning the id of the render.
+    /// Once the render finishes, it leaves its place to other active renders
+    fn submit(&self, request: &Request) -> Result<usize, Box<dyn Error>> {
+        let params = RenderParams::parse(&request.query)?;
+        let model = Model::builder().data(&request.body)?.build()?;
+
+        let render = Arc::new(Render::default());
+        let id = self.renders.lock().unwrap().push(render.clone());
+
+        let active_count = self.active_count.clone();
+        std::thread::spawn(move || {
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                Self::render(model, &params, &render)
+            }));
+            let result = result.map_err(|_| "Render panicked".to_string());
+            *render.result.lock().unwrap() = Some(result);
+            active_count.fetch_sub(1, Ordering::SeqCst);
+        });
+
+        Ok(id)
+    }
+
+    fn render(model: Model, params: &RenderParams, render: &Arc<Render>) -> RenderOutput {
+        let mut scene = Scene::new();
+        scene.push(model);
+        if scene.get_camera_node_handle().is_none() {
+            scene.push_default_model();
+        }
+        scene.config.samples_per_pixel = params.samples_per_pixel;
+        let progress_render = render.clone();
+        scene.config.progress = Some(Box::new(move |progress: &Progress| {
+            let percent = progress.percent().to_bits();
+            progress_render.progress.store(percent, Ordering::Relaxed);
+        }));
+
+        let (width, height) = (params.width, params.height);
+        let mut checkpoint = Checkpoint::new(width, height, scene.config.seed, 0);
+        checkpoint.tiles = scene.render_rows(width, height, 0..height as usize);
+        let mut image = Image::new(width, height, ColorType::RGBA8);
+        scene.resolve(&checkpoint, &mut image);
+
+        let colors = checkpoint
+            .get_colors()
+            .into_iter()
+            .map(|color| color.unwrap_or(Color::new(0.0, 0.0, 0.0, 0.0)))
+            .collect::<Vec<_>>();
+        RenderOutput {
+            png: image.encode_png(),
+            exr: encode_exr(width, height, &colors),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Sends a request to `server` and returns the status code and the body of the response
+    fn request(server: &RenderServer, request: &[u8]) -> (u32, Vec<u8>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        client.write_all(request).unwrap();
+        server.handle(stream).unwrap();
+
+        let mut response = vec![];
+        client.read_to_end(&mut response).unwrap();
+        let header_end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let status = String::from_utf8_lossy(&response[9..12]).parse().unwrap();
+        (status, response[header_end + 4..].to_vec())
+    }
+
+    #[test]
+    fn render() {
+        let server = RenderServer::new();
+        let gltf = br#"{"asset":{"version":"2.0"},"scenes":[{"nodes":[]}]}"#;
+        let mut submit = format!(
+            "POST /renders?width=8&height=4 HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            gltf.len()
+        )
+        .into_bytes();
+        submit.extend_from_slice(gltf);
+        let (status, body) = request(&server, &submit);
+        assert_eq!(status, 201);
+        assert_eq!(body, br#"{"id":0}"#);
+
+        // Wait for the render to finish
+        loop {
+            let (status, body) = request(&server, b"GET /renders/0 HTTP/1.1\r\n\r\n");
+            assert_eq!(status, 200);
+            let body = String::from_utf8(body).unwrap();
+            if body.contains("done") {
+                break;
+            }
+            assert!(body.contains("rendering"), "{}", body);
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let (status, png) = request(&server, b"GET /renders/0/image.png HTTP/1.1\r\n\r\n");
+        assert_eq!(status, 200);
+        let image = Image::load_png_data(&png).unwrap();
+        assert_eq!((image.width(), image.height()), (8, 4));
+        let (status, exr) = request(&server, b"GET /renders/0/image.exr HTTP/1.1\r\n\r\n");
+        assert_eq!(status, 200);
+        assert_eq!(exr[..4], [0x76, 0x2f, 0x31, 0x01]);
+        assert_eq!(server.active_count.load(Ordering::SeqCst), 0);
+
+        let (status, _) = request(&server, b"GET /renders/1 HTTP/1.1\r\n\r\n");
+        assert_eq!(status, 404);
+        let (status, _) = request(&server, b"POST /renders HTTP/1.1\r\n\r\n");
+        assert_eq!(status, 400);
+        assert_eq!(server.active_count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn limits() {
+        let server = RenderServer::new();
+        let samples = b"POST /renders?samples=4294967295 HTTP/1.1\r\n\r\n";
+        assert_eq!(request(&server, samples).0, 400);
+        let rays = b"POST /renders?width=8192&height=8192&samples=64 HTTP/1.1\r\n\r\n";
+        assert_eq!(request(&server, rays).0, 400);
+
+        let mut long_header = b"GET /renders/0 HTTP/1.1\r\nName: ".to_vec();
+        long_header.extend(std::iter::repeat_n(b'a', MAX_LINE_SIZE));
+        long_header.extend_from_slice(b"\r\n\r\n");
+        assert_eq!(request(&server, &long_header).0, 400);
+
+        // Bodies shorter than their length are refused once the client stops sending
+        let short_body = b"POST /renders HTTP/1.1\r\nContent-Length: 200000000\r\n\r\n{}";
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        client.write_all(short_body).unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        server.handle(stream).unwrap();
+        let mut response = vec![];
+        client.read_to_end(&mut response).unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 400"));
+
+        // Errors are valid JSON strings
+        let escaped = escape_json("a\"b\\c\nd\u{1}");
+        assert_eq!(escaped, "a\\\"b\\\\c\\nd\\u0001");
+        let json = format!("\"{}\"", escaped);
+        assert!(gltf::json::deserialize::from_str::<gltf::json::Value>(&json).is_ok());
+
+        // Busy servers refuse new renders
+        server
+            .active_count
+            .store(MAX_ACTIVE_RENDERS, Ordering::SeqCst);
+        let gltf = br#"{"asset":{"version":"2.0"}}"#;
+        let mut submit = format!(
+            "POST /renders?width=8&height=4 HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            gltf.len()
+        )
+        .into_bytes();
+        submit.extend_from_slice(gltf);
+        assert_eq!(request(&server, &submit).0, 503);
+
+        // Only the last finished renders are kept
+        let mut renders = Renders::default();
+        for _ in 0..MAX_FINISHED_RENDERS + 2 {
+            let render = Arc::new(Render::default());
+            *render.result.lock().unwrap() = Some(Err("Failed".into()));
+            renders.push(render);
+        }
+        assert_eq!(renders.renders.len(), MAX_FINISHED_RENDERS);
+        assert!(!renders.renders.contains_key(&1));
+    }
+}
@@ -0,0 +1,634 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Analytic daylight following "A Practical Analytic Model for Daylight"
+//! by Preetham, Shirley, and Smits.
+
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use super::*;
+
+/// Angular radius of the solar disk as seen from the Earth
+const SUN_ANGULAR_RADIUS: f32 = 0.00465;
+
+/// Coefficients of the Perez formula for the luminance and the chromaticity of the sky
+struct Perez([f32; 5]);
+
+impl Perez {
+    fn luminance(t: f32) -> Self {
+        Self([
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ])
+    }
+
+    fn x(t: f32) -> Self {
+        Self([
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ])
+    }
+
+    fn y(t: f32) -> Self {
+        Self([
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ])
+    }
+
+    /// Relative distribution for a direction at `theta` from the zenith
+    /// and at `gamma` from the sun
+    fn get(&self, theta: f32, gamma: f32) -> f32 {
+        let [a, b, c, d, e] = self.0;
+        let cos_gamma = gamma.cos();
+        (1.0 + a * (b / theta.cos().max(0.01)).exp())
+            * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+/// Evaluates the zenith chromaticity polynomial of the Preetham model
+fn get_zenith_chromaticity(t: f32, theta_sun: f32, coefficients: [[f32; 4]; 3]) -> f32 {
+    let thetas = [
+        theta_sun * theta_sun * theta_sun,
+        theta_sun * theta_sun,
+        theta_sun,
+        1.0,
+    ];
+    let row = |row: [f32; 4]| row.iter().zip(thetas).map(|(c, t)| c * t).sum::<f32>();
+    t * t * row(coefficients[0]) + t * row(coefficients[1]) + row(coefficients[2])
+}
+
+/// Sky lit by the sun, which works as an environment: rays leaving the scene see
+/// the sky, while the sun lights the scene as a directional light of the size of
+/// the solar disk. The sun direction is in the space of the light node.
+/// There is no ground, hence the sky is black below the horizon
+pub struct SkyLight {
+    /// Irradiance of the sun before going through the atmosphere
+    intensity: f32,
+
+    /// Scales the luminance of the sky, which the model computes in kcd/m²
+    pub sky_intensity: f32,
+
+    /// Direction towards the sun, where Y is up
+    pub sun_direction: Vec3,
+
+    /// Haziness of the atmosphere, from 2 for a clear sky to 10 for a hazy one
+    pub turbidity: f32,
+
+    /// Whether camera rays leaving the scene see the sky, otherwise it only lights
+    /// the scene and they see the backplate of the config, if any
+    pub visible_to_camera: bool,
+}
+
+impl SkyLight {
+    pub fn new(sun_direction: Vec3, turbidity: f32) -> Self {
+        Self {
+            intensity: 4.0,
+            sky_intensity: 0.06,
+            sun_direction: sun_direction.get_normalized(),
+            turbidity: turbidity.clamp(1.7, 10.0),
+            visible_to_camera: true,
+        }
+    }
+
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+
+    fn get_sun_theta(&self) -> f32 {
+        self.sun_direction.get_y().clamp(-1.0, 1.0).acos()
+    }
+
+    /// Returns the radiance of the sky without the sun along `dir` in light space
+    pub fn get_sky_radiance(&self, dir: Vec3) -> Color {
+        let dir = dir.get_normalized();
+        if dir.get_y() <= 0.0 {
+            return Color::black();
+        }
+
+        let t = self.turbidity;
+        let theta_sun = self.get_sun_theta().min(FRAC_PI_2);
+        let theta = dir.get_y().acos();
+        let gamma = dir.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let zenith_x = get_zenith_chromaticity(
+            t,
+            theta_sun,
+            [
+                [0.00166, -0.00375, 0.00209, 0.0],
+                [-0.02903, 0.06377, -0.03202, 0.00394],
+                [0.11693, -0.21196, 0.06052, 0.25886],
+            ],
+        );
+        let zenith_y = get_zenith_chromaticity(
+            t,
+            theta_sun,
+            [
+                [0.00275, -0.00610, 0.00317, 0.0],
+                [-0.04214, 0.08970, -0.04153, 0.00516],
+                [0.15346, -0.26756, 0.06670, 0.26688],
+            ],
+        );
+
+        let distribute = |perez: Perez, zenith: f32| {
+            zenith * perez.get(theta, gamma) / perez.get(0.0, theta_sun)
+        };
+        let luminance = distribute(Perez::luminance(t), zenith_luminance) * self.sky_intensity;
+        let x = distribute(Perez::x(t), zenith_x);
+        let y = distribute(Perez::y(t), zenith_y).max(f32::EPSILON);
+
+        // From xyY to linear sRGB
+        let cx = x / y * luminance;
+        let cz = (1.0 - x - y) / y * luminance;
+        Color::new(
+            (3.2406 * cx - 1.5372 * luminance - 0.4986 * cz).max(0.0),
+            (-0.9689 * cx + 1.8758 * luminance + 0.0415 * cz).max(0.0),
+            (0.0557 * cx - 0.2040 * luminance + 1.0570 * cz).max(0.0),
+            1.0,
+        )
+    }
+
+    /// Returns the color of the sun after going through the atmosphere, which gets
+    /// redder as the sun gets lower, due to Rayleigh and aerosol scattering
+    pub fn get_sun_color(&self) -> Color {
+        let theta = self.get_sun_theta();
+        if theta >= FRAC_PI_2 {
+            return Color::black();
+        }
+
+        // Relative optical mass from Kasten and Young
+        let degrees = theta.to_degrees();
+        let mass = 1.0 / (theta.cos() + 0.50572 * (96.07995 - degrees).powf(-1.6364));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = |wavelength: f32| {
+            let rayleigh = 0.008735 * wavelength.powf(-4.08);
+            let aerosol = beta * wavelength.powf(-1.3);
+            (-(rayleigh + aerosol) * mass).exp()
+        };
+        // Wavelengths of red, green, and blue in micrometers
+        Color::new(
+            transmittance(0.68),
+            transmittance(0.55),
+            transmittance(0.44),
+            1.0,
+        )
+    }
+
+    pub fn get_intensity(&self) -> Color {
+        self.intensity * self.get_sun_color()
+    }
+
+    pub fn get_distance(&self) -> f32 {
+        f32::INFINITY
+    }
+
+    pub fn get_fallof(&self) -> f32 {
+        1.0
+    }
+
+    pub fn get_direction(&self, light_trs: &Trs) -> Vec3 {
+        light_trs.rotation * self.sun_direction
+    }
+
+    /// Returns the radiance seen along `dir` in world space, where the light node
+    /// has `light_trs`. The sun is only visible with `with_sun`, as integrators
+    /// usually sample it as a light already
+    pub fn get_radiance(&self, light_trs: &Trs, dir: Vec3, with_sun: bool) -> Color {
+        let local_dir = light_trs.rotation.get_inverse() * dir;
+        let mut ret = self.get_sky_radiance(local_dir);
+        let cos_max = SUN_ANGULAR_RADIUS.cos();
+        if with_sun && local_dir.get_normalized().dot(self.sun_direction) >= cos_max {
+            let solid_angle = TAU * (1.0 - cos_max);
+            ret += self.get_intensity() / solid_angle;
+        }
+        ret
+    }
+
+    /// Rotates the light transform, so that the sun direction points
+    /// to a random point of the solar disk
+    pub fn sample_trs(&self, light_trs: &Trs, rng: &mut Rng) -> Trs {
+        let cos_theta = 1.0 - rng.next_f32() * (1.0 - SUN_ANGULAR_RADIUS.cos());
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = rng.next_f32() * TAU;
+        let (tangent, bitangent) = self.sun_direction.get_orthonormal_basis();
+        let dir = tangent * (sin_theta * phi.cos())
+            + bitangent * (sin_theta * phi.sin())
+            + self.sun_direction * cos_theta;
+
+        let mut ret = light_trs.clone();
+        ret.rotation = light_trs.rotation * Quat::rotation_between(self.sun_direction, dir);
+        ret
+    }
+}
+
+/// Controls how the sky is importance sampled as a light, see `SkyDistribution`
+#[derive(Debug, Clone, Copy)]
+pub struct SkySampling {
+    /// Columns of the latitude-longitude grid tabulating the sky, which has half as many rows
+    pub resolution: u32,
+
+    /// Brightness above which directions are not more likely to be sampled, so that a few
+    /// very bright ones do not take all the samples. The radiance of the sky is not clamped
+    pub max_intensity: f32,
+}
+
+impl Default for SkySampling {
+    fn default() -> Self {
+        Self {
+            resolution: 64,
+            max_intensity: f32::INFINITY,
+        }
+    }
+}
+
+/// Maps texture coordinates of a latitude-longitude grid to a direction,
+/// where the second coordinate goes from the zenith to the nadir
+fn uv_to_dir(uv: &Vec2) -> Vec3 {
+    let theta = PI * uv.y;
+    let phi = TAU * uv.x;
+    Vec3::new(
+        theta.sin() * phi.cos(),
+        theta.cos(),
+        theta.sin() * phi.sin(),
+    )
+}
+
+fn dir_to_uv(dir: &Vec3) -> Vec2 {
+    let theta = dir.get_y().clamp(-1.0, 1.0).acos();
+    let mut phi = dir.get_z().atan2(dir.get_x());
+    if phi < 0.0 {
+        phi += TAU;
+    }
+    Vec2::new(phi / TAU, theta / PI)
+}
+
+/// Brightness of the sky lights of a model tabulated over the directions in world space,
+/// which samples directions where the sky is brighter. The sun is left out, as
+/// integrators sample it as a light already
+#[derive(Clone)]
+pub struct SkyDistribution {
+    distribution: Distribution2D,
+}
+
+impl SkyDistribution {
+    /// Tabulates the sky of `model` following its `SkySampling`,
+    /// returning `None` when there is no sky light
+    pub fn new(model: &Model) -> Option<Self> {
+        let sampling = &model.sky_sampling;
+        let width = sampling.resolution.max(2) as usize;
+        let height = width / 2;
+        let mut weights = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let uv = Vec2::new(
+                    (x as f32 + 0.5) / width as f32,
+                    (y as f32 + 0.5) / height as f32,
+                );
+                let dir = uv_to_dir(&uv);
+                let radiance = model.get_sky_radiance(dir, false)?;
+                let brightness = (radiance.r + radiance.g + radiance.b) / 3.0;
+                // Rows closer to the poles cover smaller solid angles
+                let sin_theta = (PI * uv.y).sin();
+                weights.push(brightness.min(sampling.max_intensity) * sin_theta);
+            }
+        }
+        let distribution = Distribution2D::new(width, height, weights);
+        Some(Self { distribution })
+    }
+
+    /// Returns a direction together with its probability density with respect to
+    /// solid angle, or `None` for directions at the poles
+    pub fn sample(&self, rng: &mut Rng) -> Option<(Vec3, f32)> {
+        let uv = self.distribution.warp(rng.next_f32(), rng.next_f32());
+        let pdf = self.get_uv_pdf(&uv, (PI * uv.y).sin());
+        (pdf > 0.0).then(|| (uv_to_dir(&uv), pdf))
+    }
+
+    /// Returns the probability density of `sample()` returning `dir`
+    pub fn get_pdf(&self, dir: &Vec3) -> f32 {
+        // More precise than going through the angle close to the poles
+        let sin_theta = dir.get_x().hypot(dir.get_z()) / dir.len();
+        self.get_uv_pdf(&dir_to_uv(dir), sin_theta)
+    }
+
+    /// From the unit square to the sphere of directions
+    fn get_uv_pdf(&self, uv: &Vec2, sin_theta: f32) -> f32 {
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.get_pdf(uv) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+/// Daylight at a place on the Earth at a certain time, useful for architectural studies
+#[derive(Debug, Clone, Copy)]
+pub struct Daylight {
+    /// Degrees, positive to the north
+    pub latitude: f32,
+    /// Degrees, positive to the east
+    pub longitude: f32,
+
+    pub year: i32,
+    /// From 1 to 12
+    pub month: u32,
+    /// From 1 to 31
+    pub day: u32,
+    /// Time of the day in UTC, such as 13.5 for half past one in the afternoon
+    pub hours: f32,
+
+    /// See `SkyLight::turbidity`
+    pub turbidity: f32,
+}
+
+impl Default for Daylight {
+    /// Noon of the spring equinox in Greenwich
+    fn default() -> Self {
+        Self {
+            latitude: 51.48,
+            longitude: 0.0,
+            year: 2024,
+            month: 3,
+            day: 20,
+            hours: 12.0,
+            turbidity: 3.0,
+        }
+    }
+}
+
+impl Daylight {
+    fn get_day_of_year(&self) -> u32 {
+        const DAYS_BEFORE_MONTH: [u32; 12] =
+            [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+        let leap = self.year % 4 == 0 && (self.year % 100 != 0 || self.year % 400 == 0);
+        let month = self.month.clamp(1, 12);
+        let leap_day = if leap && month > 2 { 1 } else { 0 };
+        DAYS_BEFORE_MONTH[month as usize - 1] + self.day.clamp(1, 31) + leap_day
+    }
+
+    /// Returns the direction towards the sun, with X pointing east, Y up, and Z south,
+    /// following the approximations of the NOAA Global Monitoring Division
+    pub fn get_sun_direction(&self) -> Vec3 {
+        let days_in_year = if self.year % 4 == 0 { 366.0 } else { 365.0 };
+        let gamma =
+            TAU / days_in_year * (self.get_day_of_year() as f32 - 1.0 + (self.hours - 12.0) / 24.0);
+
+        // Equation of time in minutes, and declination in radians
+        let eqtime = 229.18
+            * (0.000075 + 0.001868 * gamma.cos()
+                - 0.032077 * gamma.sin()
+                - 0.014615 * (2.0 * gamma).cos()
+                - 0.040849 * (2.0 * gamma).sin());
+        let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+            - 0.006758 * (2.0 * gamma).cos()
+            + 0.000907 * (2.0 * gamma).sin()
+            - 0.002697 * (3.0 * gamma).cos()
+            + 0.00148 * (3.0 * gamma).sin();
+
+        let true_solar_minutes = self.hours * 60.0 + eqtime + 4.0 * self.longitude;
+        let hour_angle = (true_solar_minutes / 4.0 - 180.0).to_radians();
+
+        let latitude = self.latitude.to_radians();
+        let up = latitude.sin() * declination.sin()
+            + latitude.cos() * declination.cos() * hour_angle.cos();
+        let east = -declination.cos() * hour_angle.sin();
+        let north = latitude.cos() * declination.sin()
+            - latitude.sin() * declination.cos() * hour_angle.cos();
+        Vec3::new(east, up, -north).get_normalized()
+    }
+
+    /// Returns a sky light with the sun in the right place
+    pub fn create_light(&self) -> Light {
+        Light::sky(self.get_sun_direction(), self.turbidity)
+    }
+}
+
+impl Model {
+    /// Adds a node with the sky light of `daylight` to the root of the model,
+    /// where the model is expected to have Y up and Z pointing south
+    pub fn push_daylight(&mut self, daylight: &Daylight) -> Handle<Node> {
+        let light = self.lights.push(daylight.create_light());
+        let node = self.nodes.push(Node::builder().light(light).build());
+        self.root.children.push(node);
+        node
+    }
+
+    /// Whether the sky reaches the model through portals, see `PortalLight`
+    pub fn has_portals(&self) -> bool {
+        self.light_nodes.iter().any(|handle| {
+            let light_node = self.nodes.get(*handle).unwrap();
+            matches!(self.lights.get(light_node.light), Some(Light::Portal(_)))
+        })
+    }
+
+    /// Returns the intensity of `light` at `frag_pos`, where portals let in
+    /// the radiance of the sky without the sun, which is a light already
+    pub fn get_light_intensity(&self, light: &Light, light_trs: &Trs, frag_pos: &Point3) -> Color {
+        let intensity = light.get_intensity(light_trs, frag_pos);
+        match light {
+            Light::Portal(_) => {
+                let dir = light.get_direction(light_trs, frag_pos);
+                let radiance = self.get_sky_radiance(dir, false);
+                radiance.map_or(Color::black(), |radiance| radiance * intensity.r)
+            }
+            _ => self.working_space.from_srgb(intensity),
+        }
+    }
+
+    /// Returns what camera rays leaving the scene along `dir` see, which is the sun and
+    /// the sky lights visible to camera, or `None` when there are none
+    pub fn get_background(&self, dir: Vec3) -> Option<Color> {
+        let mut ret = None;
+        for light_node_handle in &self.light_nodes {
+            let light_node = self.nodes.get(*light_node_handle).unwrap();
+            if let Some(Light::Sky(sky)) = self.lights.get(light_node.light) {
+                if sky.visible_to_camera {
+                    let light_trs = &self.solved_trs.get(light_node_handle).unwrap().trs;
+                    let radiance = sky.get_radiance(light_trs, dir, true);
+                    ret = Some(ret.unwrap_or(Color::black()) + radiance);
+                }
+            }
+        }
+        ret.map(|radiance| self.working_space.from_srgb(radiance))
+    }
+
+    /// Returns the radiance of the sky lights of the model along `dir`,
+    /// or `None` when there are none. See `SkyLight::get_radiance()`
+    pub fn get_sky_radiance(&self, dir: Vec3, with_sun: bool) -> Option<Color> {
+        let mut ret = None;
+        for light_node_handle in &self.light_nodes {
+            let light_node = self.nodes.get(*light_node_handle).unwrap();
+            if let Some(Light::Sky(sky)) = self.lights.get(light_node.light) {
+                let light_trs = &self.solved_trs.get(light_node_handle).unwrap().trs;
+                let radiance = sky.get_radiance(light_trs, dir, with_sun);
+                ret = Some(ret.unwrap_or(Color::black()) + radiance);
+            }
+        }
+        ret.map(|radiance| self.working_space.from_srgb(radiance))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sky() {
+        let sun = Vec3::new(1.0, 1.0, 0.0).get_normalized();
+        let sky = SkyLight::new(sun, 3.0);
+
+        // Brighter around the sun, and blue overhead
+        let towards_sun = sky.get_sky_radiance(Vec3::new(1.0, 0.5, 0.0));
+        let away_from_sun = sky.get_sky_radiance(Vec3::new(-1.0, 0.5, 0.0));
+        assert!(towards_sun.g > away_from_sun.g);
+        let zenith = sky.get_sky_radiance(Vec3::new(0.0, 1.0, 0.0));
+        assert!(zenith.b > zenith.r);
+        assert_eq!(sky.get_sky_radiance(Vec3::new(0.0, -1.0, 0.0)).g, 0.0);
+
+        // Sunsets are red
+        let noon = SkyLight::new(Vec3::new(0.0, 1.0, 0.0), 3.0).get_sun_color();
+        let sunset = SkyLight::new(Vec3::new(1.0, 0.05, 0.0), 3.0).get_sun_color();
+        assert!(sunset.r / sunset.b > noon.r / noon.b);
+        assert!(noon.g > sunset.g);
+
+        // Samples stay within the solar disk
+        let mut rng = Rng::new(0);
+        let trs = Trs::default();
+        for _ in 0..64 {
+            let sampled_trs = sky.sample_trs(&trs, &mut rng);
+            let dir = sky.get_direction(&sampled_trs);
+            assert!(dir.dot(sun) >= SUN_ANGULAR_RADIUS.cos() - 1e-5);
+        }
+
+        // The sun only appears when asked to
+        let with_sun = sky.get_radiance(&trs, sun, true);
+        let without_sun = sky.get_radiance(&trs, sun, false);
+        assert!(with_sun.g > without_sun.g * 100.0);
+    }
+
+    #[test]
+    fn daylight() {
+        // Equinox noon in Greenwich, the sun is south at 90° minus the latitude
+        let daylight = Daylight::default();
+        let sun = daylight.get_sun_direction();
+        let elevation = sun.get_y().asin().to_degrees();
+        assert!((elevation - (90.0 - daylight.latitude)).abs() < 1.0);
+        assert!(sun.get_z() > 0.9 * sun.get_z().hypot(sun.get_x()));
+
+        // Local noon comes six hours earlier at 90° east
+        let east = Daylight {
+            longitude: 90.0,
+            hours: 6.0,
+            ..daylight
+        };
+        assert!(east.get_sun_direction().close(&sun));
+
+        // Mornings have the sun in the east, nights below the horizon
+        let morning = Daylight {
+            hours: 8.0,
+            ..daylight
+        };
+        assert!(morning.get_sun_direction().get_x() > 0.5);
+        let night = Daylight {
+            hours: 0.0,
+            ..daylight
+        };
+        assert!(night.get_sun_direction().get_y() < 0.0);
+
+        // Summer solstice sun is higher
+        let summer = Daylight {
+            month: 6,
+            day: 21,
+            ..daylight
+        };
+        let summer_elevation = summer.get_sun_direction().get_y().asin().to_degrees();
+        assert!((summer_elevation - elevation - 23.44).abs() < 1.0);
+
+        let mut model = Model::new();
+        let node = model.push_daylight(&daylight);
+        let light = model.nodes.get(node).unwrap().light;
+        assert!(matches!(model.lights.get(light), Some(Light::Sky(_))));
+    }
+
+    #[test]
+    fn distribution() {
+        let mut model = Model::new();
+        assert!(SkyDistribution::new(&model).is_none());
+        model.push_daylight(&Daylight::default());
+        model.collect();
+        let sky = SkyDistribution::new(&model).unwrap();
+
+        // Samples cover the upper hemisphere, as the sky is black below the horizon
+        let mut rng = Rng::new(0);
+        let count = 4096;
+        let mut solid_angle = 0.0;
+        for _ in 0..count {
+            let (dir, pdf) = sky.sample(&mut rng).unwrap();
+            assert!(dir.get_y() > -1e-3);
+            assert!((sky.get_pdf(&dir) - pdf).abs() < pdf * 1e-2);
+            solid_angle += 1.0 / pdf;
+        }
+        solid_angle /= count as f32;
+        assert!((solid_angle - TAU).abs() < TAU * 0.05, "{}", solid_angle);
+
+        // Clamping the brightness flattens the distribution
+        let sun = model
+            .get_sky_radiance(Vec3::new(0.0, 1.0, 0.0), false)
+            .unwrap();
+        let brightest = Vec3::new(0.0, 0.6, 0.8);
+        model.sky_sampling.max_intensity = (sun.r + sun.g + sun.b) / 3.0 / 4.0;
+        let clamped = SkyDistribution::new(&model).unwrap();
+        assert!(clamped.get_pdf(&brightest) < sky.get_pdf(&brightest));
+        assert_eq!(clamped.get_pdf(&Vec3::new(0.0, -1.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn portal() {
+        let mut model = Model::new();
+        let sky = model.lights.push(Light::sky(Vec3::new(1.0, 1.0, 0.0), 3.0));
+        let sky = model.nodes.push(Node::builder().light(sky).build());
+        model.root.children.push(sky);
+        model.collect();
+        assert!(!model.has_portals());
+
+        // Skylight of 1 x 1 at 10 units above the origin
+        let portal = model.lights.push(Light::portal(1.0, 1.0));
+        let portal_trs = Trs::builder()
+            .translation(Vec3::new(0.0, 10.0, 0.0))
+            .build();
+        let portal = model.nodes.push(
+            Node::builder()
+                .light(portal)
+                .trs(portal_trs.clone())
+                .build(),
+        );
+        model.root.children.push(portal);
+        model.collect();
+        assert!(model.has_portals());
+
+        let light = model.lights.get(Handle::new(1)).unwrap();
+        let below = Point3::new(0.0, 0.0, 0.0);
+        let solid_angle = light.get_intensity(&portal_trs, &below).r;
+        assert!((solid_angle - 0.01).abs() < 1e-6);
+
+        let zenith = model.get_sky_radiance(Vec3::new(0.0, 1.0, 0.0), false);
+        let intensity = model.get_light_intensity(light, &portal_trs, &below);
+        assert!((intensity.b - zenith.unwrap().b * solid_angle).abs() < 1e-6);
+
+        // Nothing comes in from the outside
+        let above = Point3::new(0.0, 20.0, 0.0);
+        let intensity = model.get_light_intensity(light, &portal_trs, &above);
+        assert_eq!(intensity.b, 0.0);
+    }
+}
@@ -0,0 +1,245 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use instant::Duration;
+
+use crate::*;
+
+/// Counters of one thread, aligned to a cache line so that threads updating
+/// their own counters do not invalidate the ones of the others
+#[derive(Default)]
+#[repr(align(64))]
+struct StatsShard {
+    primary_rays: AtomicU64,
+    shadow_rays: AtomicU64,
+    bounce_rays: AtomicU64,
+    node_tests: AtomicU64,
+    primitive_tests: AtomicU64,
+}
+
+/// Counters collected while rendering a frame. Every thread of the pool updates its
+/// own shard, and `report()` merges them. Disabled counters are not updated at all
+pub struct Stats {
+    enabled: bool,
+    shards: Box<[StatsShard]>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        // Threads outside of the pool share the first shard
+        #[cfg(feature = "parallel")]
+        let shard_count = rayon::current_num_threads() + 1;
+        #[cfg(not(feature = "parallel"))]
+        let shard_count = 1;
+        Self {
+            enabled: true,
+            shards: (0..shard_count).map(|_| StatsShard::default()).collect(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn get_shard(&self) -> &StatsShard {
+        #[cfg(feature = "parallel")]
+        let index = rayon::current_thread_index().map_or(0, |index| index + 1);
+        #[cfg(not(feature = "parallel"))]
+        let index = 0;
+        &self.shards[index % self.shards.len()]
+    }
+
+    pub fn add_primary_ray(&self) {
+        if self.enabled {
+            self.get_shard()
+                .primary_rays
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn add_shadow_ray(&self) {
+        if self.enabled {
+            self.get_shard().shadow_rays.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn add_bounce_ray(&self) {
+        if self.enabled {
+            self.get_shard().bounce_rays.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records the number of BVH nodes and primitives tested by a single traversal
+    pub fn add_tests(&self, node_tests: u64, primitive_tests: u64) {
+        if self.enabled {
+            let shard = self.get_shard();
+            shard.node_tests.fetch_add(node_tests, Ordering::Relaxed);
+            shard
+                .primitive_tests
+                .fetch_add(primitive_tests, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns a snapshot of the counters collected so far, summed over all threads
+    pub fn report(&self) -> StatsReport {
+        let mut report = StatsReport::default();
+        for shard in self.shards.iter() {
+            report.primary_rays += shard.primary_rays.load(Ordering::Relaxed);
+            report.shadow_rays += shard.shadow_rays.load(Ordering::Relaxed);
+            report.bounce_rays += shard.bounce_rays.load(Ordering::Relaxed);
+            report.node_tests += shard.node_tests.load(Ordering::Relaxed);
+            report.primitive_tests += shard.primitive_tests.load(Ordering::Relaxed);
+        }
+        report
+    }
+}
+
+/// Statistics of a rendered frame, including the time spent in each stage
+#[derive(Clone, Debug, Default)]
+pub struct StatsReport {
+    pub primary_rays: u64,
+    pub shadow_rays: u64,
+    pub bounce_rays: u64,
+    pub node_tests: u64,
+    pub primitive_tests: u64,
+
+    pub bvh_build: Duration,
+    /// Whole frame, from the BVH build to the resolved image
+    pub render: Duration,
+    /// Tracing and shading of the samples of the pixels
+    pub shading: Duration,
+    /// Post effects, such as filters and bloom
+    pub post: Duration,
+    /// Exposure view and conversion of the colors to the image
+    pub resolve: Duration,
+}
+
+impl StatsReport {
+    pub fn ray_count(&self) -> u64 {
+        self.primary_rays + self.shadow_rays + self.bounce_rays
+    }
+
+    /// Returns the number of rays traced per second while rendering
+    pub fn rays_per_second(&self) -> f64 {
+        let secs = self.render.as_secs_f64();
+        if secs > 0.0 {
+            self.ray_count() as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    pub fn log(&self) {
+        print_info!(
+            "Rays",
+            "{} primary, {} shadow, {} bounce",
+            self.primary_rays,
+            self.shadow_rays,
+            self.bounce_rays
+        );
+        print_info!(
+            "Tests",
+            "{} nodes, {} primitives",
+            self.node_tests,
+            self.primitive_tests
+        );
+        print_info!(
+            "Time",
+            "BVH build {:.2}ms, shading {:.2}ms, post {:.2}ms, resolve {:.2}ms, render {:.2}ms",
+            self.bvh_build.as_secs_f64() * 1000.0,
+            self.shading.as_secs_f64() * 1000.0,
+            self.post.as_secs_f64() * 1000.0,
+            self.resolve.as_secs_f64() * 1000.0,
+            self.render.as_secs_f64() * 1000.0
+        );
+        print_info!("Speed", "{:.2} Mrays/s", self.rays_per_second() / 1e6);
+    }
+
+    /// Burns the times and the speed of the frame into the top-left corner of `image`,
+    /// over a dark background which keeps them readable on bright frames
+    pub fn draw(&self, image: &mut Image, scale: u32) {
+        let text = format!(
+            "BVH {:.2}ms\nrender {:.2}ms\n{:.2} Mrays/s",
+            self.bvh_build.as_secs_f64() * 1000.0,
+            self.render.as_secs_f64() * 1000.0,
+            self.rays_per_second() / 1e6
+        );
+        let (width, height) = Image::get_text_size(&text, scale);
+        let margin = scale.max(1);
+        image.draw_rect(
+            0,
+            0,
+            width + 2 * margin,
+            height + 2 * margin,
+            Color::black(),
+        );
+        image.draw_text(&text, margin, margin, scale, Color::white());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn report() {
+        let stats = Stats::new();
+        stats.add_primary_ray();
+        stats.add_shadow_ray();
+        stats.add_shadow_ray();
+        stats.add_bounce_ray();
+        stats.add_tests(3, 5);
+
+        let mut report = stats.report();
+        assert_eq!(report.ray_count(), 4);
+        assert_eq!(report.node_tests, 3);
+        assert_eq!(report.primitive_tests, 5);
+        assert_eq!(report.rays_per_second(), 0.0);
+
+        report.render = Duration::from_secs(2);
+        assert_eq!(report.rays_per_second(), 2.0);
+
+        let mut image = Image::new(128, 32, ColorType::RGBA8);
+        image.clear(RGBA8::from(Color::white()));
+        report.draw(&mut image, 1);
+        // The background of the text is dark, while the rest of the image is not
+        assert_eq!(image.get::<RGBA8>(0, 0).r, 0);
+        assert_eq!(image.get::<RGBA8>(127, 31).r, 255);
+    }
+
+    #[test]
+    fn disabled() {
+        let mut stats = Stats::new();
+        stats.set_enabled(false);
+        stats.add_primary_ray();
+        stats.add_tests(3, 5);
+        assert_eq!(stats.report().ray_count(), 0);
+        assert_eq!(stats.report().node_tests, 0);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn threads() {
+        use rayon::prelude::*;
+
+        let stats = Stats::new();
+        (0..1024)
+            .into_par_iter()
+            .for_each(|_| stats.add_primary_ray());
+        stats.add_primary_ray();
+        assert_eq!(stats.report().primary_rays, 1025);
+    }
+}
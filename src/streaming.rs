@@ -0,0 +1,225 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+use std::{error::Error, path::Path};
+
+use super::*;
+
+type ModelLoader = dyn Fn() -> Result<Model, Box<dyn Error>> + Send + Sync;
+
+/// Whether a model source should be loaded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Residency {
+    /// Loaded when the camera gets close, unloaded when it moves away
+    #[default]
+    Distance,
+    /// Always loaded
+    Resident,
+    /// Never loaded
+    Evicted,
+}
+
+/// A model which is loaded into the scene only when needed, so that huge scenes
+/// do not need to be fully resident
+pub struct ModelSource {
+    loader: Box<ModelLoader>,
+
+    /// Center of a sphere bounding the model in world space
+    pub center: Vec3,
+    pub radius: f32,
+
+    /// The model is loaded when the camera is closer than this to its bounds
+    pub load_distance: f32,
+
+    /// The model is unloaded when the camera is farther than this from its bounds.
+    /// Larger than `load_distance` so that moving around the boundary does not
+    /// load and unload the model repeatedly
+    pub unload_distance: f32,
+
+    pub residency: Residency,
+
+    /// Handles of the objects appended to the scene while loaded
+    handles: Option<ModelHandles>,
+}
+
+impl ModelSource {
+    pub fn new<F>(loader: F, center: Vec3, radius: f32, load_distance: f32) -> Self
+    where
+        F: Fn() -> Result<Model, Box<dyn Error>> + Send + Sync + 'static,
+    {
+        Self {
+            loader: Box::new(loader),
+            center,
+            radius,
+            load_distance,
+            unload_distance: load_distance * 1.25,
+            residency: Residency::default(),
+            handles: None,
+        }
+    }
+
+    /// Creates a source loading a glTF file
+    pub fn path<P: AsRef<Path>>(path: P, center: Vec3, radius: f32, load_distance: f32) -> Self {
+        let path = path.as_ref().to_path_buf();
+        Self::new(
+            move || Ok(Model::builder().path(&path)?.build()?),
+            center,
+            radius,
+            load_distance,
+        )
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.handles.is_some()
+    }
+
+    /// Returns the distance of `point` from the bounds of the model
+    fn get_distance(&self, point: Vec3) -> f32 {
+        ((self.center - point).len() - self.radius).max(0.0)
+    }
+}
+
+impl Scene {
+    /// Adds a model which is loaded only when needed, returning its index
+    pub fn add_source(&mut self, source: ModelSource) -> usize {
+        self.sources.push(source);
+        self.sources.len() - 1
+    }
+
+    /// Loads the model of a source into the scene, if not loaded already
+    pub fn load_source(&mut self, index: usize) -> Result<(), Box<dyn Error>> {
+        let source = &self.sources[index];
+        if source.is_loaded() {
+            return Ok(());
+        }
+        let model = (source.loader)()?;
+        let handles = self.append_model(model);
+        self.sources[index].handles = Some(handles);
+        Ok(())
+    }
+
+    /// Removes the model of a source from the scene, if loaded
+    pub fn unload_source(&mut self, index: usize) {
+        if let Some(handles) = self.sources[index].handles.take() {
+            self.model.remove(&handles);
+        }
+    }
+
+    /// Loads the sources the camera got close to, and unloads the ones it moved away from.
+    /// It is called by `draw()`, but it can be called earlier to load models in advance.
+    /// Sources failing to load are logged and skipped, returning the first error at the end
+    pub fn update_streaming(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.sources.is_empty() {
+            return Ok(());
+        }
+
+        let camera_position = match self.get_camera_node_handle() {
+            Some(camera_node_handle) => {
+                self.model.collect_trs();
+                self.model
+                    .solved_trs
+                    .get(&camera_node_handle)
+                    .map(|solved| solved.trs.translation)
+            }
+            None => None,
+        };
+
+        let mut first_err = None;
+        for index in 0..self.sources.len() {
+            let source = &self.sources[index];
+            let load = match source.residency {
+                Residency::Resident => true,
+                Residency::Evicted => false,
+                Residency::Distance => match camera_position {
+                    Some(position) => {
+                        let distance = source.get_distance(position);
+                        if source.is_loaded() {
+                            distance <= source.unload_distance
+                        } else {
+                            distance <= source.load_distance
+                        }
+                    }
+                    None => source.is_loaded(),
+                },
+            };
+
+            if load {
+                if let Err(err) = self.load_source(index) {
+                    print_warning!("Streaming", "source {}: {}", index, err);
+                    // Avoid trying again every frame
+                    self.sources[index].residency = Residency::Evicted;
+                    first_err.get_or_insert(err);
+                }
+            } else {
+                self.unload_source(index);
+            }
+        }
+
+        match first_err {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sphere_model() -> Result<Model, Box<dyn Error>> {
+        let mut model = Model::new();
+        let prim_handle = model.primitives.push(Primitive::unit_sphere());
+        let mesh_handle = model.meshes.push(Mesh::new(vec![prim_handle]));
+        let node_handle = model.nodes.push(Node::builder().mesh(mesh_handle).build());
+        model.root.children.push(node_handle);
+        Ok(model)
+    }
+
+    #[test]
+    fn distance() {
+        let mut scene = Scene::new();
+        scene.push_default_model();
+        let node_count = scene.model.nodes.len();
+
+        // The default camera is 4 units away from the origin
+        let near = scene.add_source(ModelSource::new(sphere_model, Vec3::default(), 1.0, 4.0));
+        let far = scene.add_source(ModelSource::new(
+            sphere_model,
+            Vec3::new(0.0, 0.0, -64.0),
+            1.0,
+            4.0,
+        ));
+        scene.update_streaming().unwrap();
+        assert!(scene.sources[near].is_loaded());
+        assert!(!scene.sources[far].is_loaded());
+        assert_eq!(scene.model.primitives.len(), 1);
+
+        scene.sources[far].residency = Residency::Resident;
+        scene.sources[near].residency = Residency::Evicted;
+        scene.update_streaming().unwrap();
+        assert!(!scene.sources[near].is_loaded());
+        assert!(scene.sources[far].is_loaded());
+        assert_eq!(scene.model.primitives.len(), 1);
+
+        scene.unload_source(far);
+        assert_eq!(scene.model.nodes.len(), node_count);
+        assert!(scene.get_camera_node_handle().is_some());
+
+        // Failing sources do not stop the others from loading
+        let failing = scene.add_source(ModelSource::path("test", Vec3::default(), 1.0, 4.0));
+        let after = scene.add_source(ModelSource::new(sphere_model, Vec3::default(), 1.0, 4.0));
+        assert!(scene.update_streaming().is_err());
+        assert_eq!(scene.sources[failing].residency, Residency::Evicted);
+        assert!(scene.sources[after].is_loaded());
+
+        // Loading and unloading again reuses the same handles
+        let handles = scene.sources[after].handles.clone().unwrap();
+        scene.unload_source(after);
+        scene.load_source(after).unwrap();
+        let reloaded = scene.sources[after].handles.as_ref().unwrap();
+        assert_eq!(reloaded.root, handles.root);
+        assert_eq!(reloaded.nodes, handles.nodes);
+        assert_eq!(reloaded.primitives, handles.primitives);
+    }
+}
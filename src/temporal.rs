@@ -0,0 +1,153 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Temporal accumulation for interactive viewers: the colors of the previous frame
+//! are reprojected with the motion vectors of the new one, then blended with its samples.
+
+use crate::*;
+
+/// Motion vectors shorter than this, in pixels, do not count as motion
+const MOTION_EPSILON: f32 = 1e-3;
+
+/// Returns `a` moved towards `b` by `weight`, alpha included
+fn blend(a: &Color, b: &Color, weight: f32) -> Color {
+    Color::new(
+        a.r + (b.r - a.r) * weight,
+        a.g + (b.g - a.g) * weight,
+        a.b + (b.b - a.b) * weight,
+        a.a + (b.a - a.a) * weight,
+    )
+}
+
+/// Colors of the previous frames drawn by `Scene::draw_temporal()`
+#[derive(Clone, Debug)]
+pub struct TemporalAccumulation {
+    /// Minimum weight of a new frame for pixels which moved. Lower values reduce noise
+    /// while moving, but leave longer trails behind. Pixels which did not move average
+    /// all their frames
+    pub alpha: f32,
+
+    width: u32,
+    height: u32,
+
+    /// Blended color of every pixel, with the number of frames it averages
+    history: Vec<Option<(Color, u32)>>,
+
+    /// Frames blended since the history was reset
+    frame_count: u32,
+}
+
+impl Default for TemporalAccumulation {
+    fn default() -> Self {
+        Self::new(0.2)
+    }
+}
+
+impl TemporalAccumulation {
+    pub fn new(alpha: f32) -> Self {
+        Self {
+            alpha,
+            width: 0,
+            height: 0,
+            history: vec![],
+            frame_count: 0,
+        }
+    }
+
+    /// Forgets the previous frames, for example after a cut
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.frame_count = 0;
+    }
+
+    pub fn get_frame_count(&self) -> u32 {
+        self.frame_count
+    }
+
+    /// Blends the `colors` of a new frame with the history, reprojected through the
+    /// offsets of `motion`, which should be an `Aov::Motion` buffer of the same size.
+    /// The blended colors replace `colors` and become the new history
+    pub fn accumulate(&mut self, colors: &mut [Option<Color>], motion: &AovBuffer) {
+        let (width, height) = (motion.width(), motion.height());
+        assert!(colors.len() == width as usize * height as usize);
+        if (self.width, self.height) != (width, height) || self.history.is_empty() {
+            self.width = width;
+            self.height = height;
+            self.history = vec![None; colors.len()];
+            self.frame_count = 0;
+        }
+
+        let mut history = vec![None; colors.len()];
+        for y in 0..height {
+            for x in 0..width {
+                let index = (y * width + x) as usize;
+                let Some(color) = colors[index] else {
+                    continue;
+                };
+
+                // Pixels seeing nothing have no motion, hence they may not stay still
+                let offset = motion.get(x, y);
+                let moved = offset.a == 0.0 || offset.r.abs() + offset.g.abs() > MOTION_EPSILON;
+                let previous_x = (x as f32 + offset.r).round();
+                let previous_y = (y as f32 + offset.g).round();
+                let previous = if previous_x >= 0.0
+                    && previous_y >= 0.0
+                    && previous_x < width as f32
+                    && previous_y < height as f32
+                {
+                    self.history[previous_y as usize * width as usize + previous_x as usize]
+                } else {
+                    None
+                };
+
+                let blended = match previous {
+                    Some((previous, frame_count)) => {
+                        let mut weight = 1.0 / (frame_count + 1) as f32;
+                        if moved {
+                            weight = weight.max(self.alpha);
+                        }
+                        (blend(&previous, &color, weight), frame_count + 1)
+                    }
+                    None => (color, 1),
+                };
+                colors[index] = Some(blended.0);
+                history[index] = Some(blended);
+            }
+        }
+
+        self.history = history;
+        self.frame_count += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn accumulate() {
+        let mut temporal = TemporalAccumulation::new(0.5);
+        let mut motion = AovBuffer::new(Aov::Motion, 2, 1);
+        motion.pixels = vec![Color::new(0.0, 0.0, 0.0, 1.0); 2];
+
+        // Pixels which do not move average all their frames
+        for (i, &value) in [1.0, 0.0, 0.0, 0.0].iter().enumerate() {
+            let mut colors = vec![Some(Color::new(value, 0.0, 0.0, 1.0)), None];
+            temporal.accumulate(&mut colors, &motion);
+            assert_eq!(temporal.get_frame_count(), i as u32 + 1);
+            assert!((colors[0].unwrap().r - 1.0 / (i + 1) as f32).abs() < 1e-6);
+            assert!(colors[1].is_none());
+        }
+
+        // The second pixel was the first one in the previous frame
+        motion.pixels[1] = Color::new(-1.0, 0.0, 0.0, 1.0);
+        let mut colors = vec![None, Some(Color::new(1.0, 0.0, 0.0, 1.0))];
+        temporal.accumulate(&mut colors, &motion);
+        assert!(colors[0].is_none());
+        assert!((colors[1].unwrap().r - (0.25 * 0.5 + 0.5)).abs() < 1e-6);
+
+        temporal.reset();
+        assert_eq!(temporal.get_frame_count(), 0);
+    }
+}
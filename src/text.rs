@@ -0,0 +1,261 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Rectangles and text drawn on top of images, to burn statistics, labels,
+//! and watermarks into rendered frames.
+
+use crate::*;
+
+/// Width and height of the glyphs of the font in pixels
+pub const GLYPH_WIDTH: u32 = 5;
+pub const GLYPH_HEIGHT: u32 = 7;
+
+/// Returns the rows of the glyph of `c` from the top, where the highest of the
+/// `GLYPH_WIDTH` bits is the leftmost pixel. Lower case letters use upper case glyphs
+fn get_glyph(c: char) -> Option<[u8; GLYPH_HEIGHT as usize]> {
+    match c {
+        '0' => Some([
+            0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110,
+        ]),
+        '1' => Some([
+            0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ]),
+        '2' => Some([
+            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111,
+        ]),
+        '3' => Some([
+            0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110,
+        ]),
+        '4' => Some([
+            0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010,
+        ]),
+        '5' => Some([
+            0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110,
+        ]),
+        '6' => Some([
+            0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110,
+        ]),
+        '7' => Some([
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000,
+        ]),
+        '8' => Some([
+            0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110,
+        ]),
+        '9' => Some([
+            0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100,
+        ]),
+        'A' => Some([
+            0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ]),
+        'B' => Some([
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110,
+        ]),
+        'C' => Some([
+            0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110,
+        ]),
+        'D' => Some([
+            0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100,
+        ]),
+        'E' => Some([
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111,
+        ]),
+        'F' => Some([
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000,
+        ]),
+        'G' => Some([
+            0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111,
+        ]),
+        'H' => Some([
+            0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ]),
+        'I' => Some([
+            0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ]),
+        'J' => Some([
+            0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100,
+        ]),
+        'K' => Some([
+            0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001,
+        ]),
+        'L' => Some([
+            0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111,
+        ]),
+        'M' => Some([
+            0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001,
+        ]),
+        'N' => Some([
+            0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001,
+        ]),
+        'O' => Some([
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ]),
+        'P' => Some([
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000,
+        ]),
+        'Q' => Some([
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101,
+        ]),
+        'R' => Some([
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001,
+        ]),
+        'S' => Some([
+            0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110,
+        ]),
+        'T' => Some([
+            0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100,
+        ]),
+        'U' => Some([
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ]),
+        'V' => Some([
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100,
+        ]),
+        'W' => Some([
+            0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010,
+        ]),
+        'X' => Some([
+            0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
+        ]),
+        'Y' => Some([
+            0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100,
+        ]),
+        'Z' => Some([
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111,
+        ]),
+        '.' => Some([
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100,
+        ]),
+        ',' => Some([
+            0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000,
+        ]),
+        ':' => Some([
+            0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000,
+        ]),
+        '-' => Some([
+            0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000,
+        ]),
+        '+' => Some([
+            0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000,
+        ]),
+        '/' => Some([
+            0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000,
+        ]),
+        '(' => Some([
+            0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010,
+        ]),
+        ')' => Some([
+            0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000,
+        ]),
+        '%' => Some([
+            0b11000, 0b11001, 0b00010, 0b00100, 0b01000, 0b10011, 0b00011,
+        ]),
+        '=' => Some([
+            0b00000, 0b00000, 0b11111, 0b00000, 0b11111, 0b00000, 0b00000,
+        ]),
+        '_' => Some([
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111,
+        ]),
+        '!' => Some([
+            0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00000, 0b00100,
+        ]),
+        '?' => Some([
+            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b00000, 0b00100,
+        ]),
+        '#' => Some([
+            0b01010, 0b01010, 0b11111, 0b01010, 0b11111, 0b01010, 0b01010,
+        ]),
+        '*' => Some([
+            0b00000, 0b00100, 0b10101, 0b01110, 0b10101, 0b00100, 0b00000,
+        ]),
+        'x' => Some([
+            0b00000, 0b00000, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001,
+        ]),
+        ' ' => Some([0; GLYPH_HEIGHT as usize]),
+        _ if c.is_ascii_lowercase() => get_glyph(c.to_ascii_uppercase()),
+        _ => None,
+    }
+}
+
+impl Image {
+    /// Fills the rectangle of `width` x `height` pixels with its top-left corner
+    /// at `(x, y)`, skipping the pixels out of the image
+    pub fn draw_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: Color) {
+        let rgba8 = RGBA8::from(color);
+        let rgb8 = RGB8 {
+            r: rgba8.r,
+            g: rgba8.g,
+            b: rgba8.b,
+        };
+        for j in y..(y + height).min(self.height()) {
+            for i in x..(x + width).min(self.width()) {
+                match self.color_type {
+                    ColorType::RGBA8 => self.set(i, j, rgba8),
+                    ColorType::RGB8 => self.set(i, j, rgb8),
+                }
+            }
+        }
+    }
+
+    /// Draws `text` with its top-left corner at `(x, y)`, where every pixel of the font
+    /// is a square of `scale` pixels. Lines are separated by new line characters, and
+    /// characters without a glyph are drawn as question marks
+    pub fn draw_text(&mut self, text: &str, x: u32, y: u32, scale: u32, color: Color) {
+        let scale = scale.max(1);
+        let advance = (GLYPH_WIDTH + 1) * scale;
+        let line_height = (GLYPH_HEIGHT + 2) * scale;
+        for (line_index, line) in text.lines().enumerate() {
+            let top = y + line_index as u32 * line_height;
+            for (char_index, c) in line.chars().enumerate() {
+                let left = x + char_index as u32 * advance;
+                let glyph = get_glyph(c).or_else(|| get_glyph('?')).unwrap();
+                for (row, bits) in glyph.iter().enumerate() {
+                    for column in 0..GLYPH_WIDTH {
+                        if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                            let (i, j) = (left + column * scale, top + row as u32 * scale);
+                            self.draw_rect(i, j, scale, scale, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the width and height in pixels of `text` drawn by `draw_text()`
+    pub fn get_text_size(text: &str, scale: u32) -> (u32, u32) {
+        let scale = scale.max(1);
+        let columns = text.lines().map(|line| line.chars().count()).max();
+        let lines = text.lines().count() as u32;
+        match columns {
+            Some(columns) if columns > 0 => (
+                (columns as u32 * (GLYPH_WIDTH + 1) - 1) * scale,
+                (lines * (GLYPH_HEIGHT + 2) - 2) * scale,
+            ),
+            _ => (0, 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn text() {
+        let mut image = Image::new(16, 8, ColorType::RGB8);
+        let white = Color::white();
+        image.draw_text("1i", 0, 0, 1, white);
+        let is_set = |image: &Image, x, y| image.get::<RGB8>(x, y).r == 255;
+        // The foot of the 1 is three pixels wide
+        assert!((1..4).all(|x| is_set(&image, x, 6)));
+        assert!(!is_set(&image, 0, 6) && !is_set(&image, 4, 6));
+        // Lower case letters use upper case glyphs, after a column of space
+        assert!(!is_set(&image, 5, 0) && is_set(&image, 7, 0));
+        assert_eq!(Image::get_text_size("1i", 1), (11, 7));
+        assert_eq!(Image::get_text_size("1i\n1", 2), (22, 32));
+
+        // Rectangles are clipped by the image
+        image.draw_rect(14, 6, 4, 4, white);
+        assert!(is_set(&image, 15, 7) && !is_set(&image, 13, 7));
+    }
+}
@@ -15,3 +15,94 @@ impl Texture {
         Self { image, sampler }
     }
 }
+
+/// Transform applied to texture coordinates before sampling a texture,
+/// as defined by the `KHR_texture_transform` glTF extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureTransform {
+    pub offset: Vec2,
+    /// Counter-clockwise rotation around the origin in radians
+    pub rotation: f32,
+    pub scale: Vec2,
+
+    /// Set of texture coordinates the texture is sampled with
+    pub tex_coord: usize,
+}
+
+impl Default for TextureTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl TextureTransform {
+    pub const IDENTITY: TextureTransform = TextureTransform {
+        offset: Vec2 { x: 0.0, y: 0.0 },
+        rotation: 0.0,
+        scale: Vec2 { x: 1.0, y: 1.0 },
+        tex_coord: 0,
+    };
+
+    pub fn new(offset: Vec2, rotation: f32, scale: Vec2) -> Self {
+        Self {
+            offset,
+            rotation,
+            scale,
+            tex_coord: 0,
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::IDENTITY
+    }
+
+    /// Scales and rotates a texture coordinates derivative, ignoring the offset
+    pub fn apply_vector(&self, v: Vec2) -> Vec2 {
+        let (sin, cos) = self.rotation.sin_cos();
+        let x = v.x * self.scale.x;
+        let y = v.y * self.scale.y;
+        Vec2::new(cos * x + sin * y, cos * y - sin * x)
+    }
+
+    /// Returns the texture coordinates to use for sampling instead of `uv`
+    pub fn apply(&self, uv: &Vec2) -> Vec2 {
+        let v = self.apply_vector(*uv);
+        Vec2::new(v.x + self.offset.x, v.y + self.offset.y)
+    }
+
+    /// Selects the set of `uvs` the texture is sampled with, falling back to
+    /// the first one when missing, and transforms it
+    pub fn get_uv(&self, uvs: &[Vec2]) -> Vec2 {
+        self.apply(uvs.get(self.tex_coord).unwrap_or(&uvs[0]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn transform() {
+        let uv = Vec2::new(0.5, 0.25);
+        assert_eq!(TextureTransform::default().apply(&uv), uv);
+
+        let tiled = TextureTransform::new(Vec2::new(0.5, 0.0), 0.0, Vec2::new(4.0, 2.0));
+        assert_eq!(tiled.apply(&uv), Vec2::new(2.5, 0.5));
+
+        let rotated = TextureTransform::new(
+            Vec2::default(),
+            std::f32::consts::FRAC_PI_2,
+            Vec2::new(1.0, 1.0),
+        );
+        let rotated_uv = rotated.apply(&Vec2::new(1.0, 0.0));
+        assert!(rotated_uv.x.abs() < 1e-6 && (rotated_uv.y + 1.0).abs() < 1e-6);
+        assert_eq!(rotated.apply_vector(Vec2::default()), Vec2::default());
+
+        let second_set = TextureTransform {
+            tex_coord: 1,
+            ..Default::default()
+        };
+        assert_eq!(second_set.get_uv(&[Vec2::default(), uv]), uv);
+        assert_eq!(second_set.get_uv(&[uv]), uv);
+    }
+}
@@ -3,6 +3,9 @@
 // SPDX-License-Identifier: MIT
 
 use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
     hash::{Hash, Hasher},
     iter::FromIterator,
     marker::PhantomData,
@@ -47,7 +50,6 @@ impl Default for Timer {
 /// A handle is a sort of index into a vector of elements of a specific kind.
 /// It is useful when we do not want to keep a reference to an element,
 /// while taking advantage of strong typing to avoid using integers.
-#[derive(Debug)]
 pub struct Handle<T> {
     pub id: usize,
     /// https://stackoverflow.com/a/50201389
@@ -68,7 +70,7 @@ impl<T> From<u32> for Handle<T> {
 
 impl<T> Handle<T> {
     pub const NONE: Self = Self {
-        id: std::usize::MAX,
+        id: usize::MAX,
         phantom: PhantomData,
     };
 
@@ -81,13 +83,13 @@ impl<T> Handle<T> {
 
     pub fn none() -> Self {
         Self {
-            id: std::usize::MAX,
+            id: usize::MAX,
             phantom: PhantomData,
         }
     }
 
     pub fn valid(&self) -> bool {
-        self.id != std::usize::MAX
+        self.id != usize::MAX
     }
 
     pub fn offset(&mut self, offset: usize) {
@@ -96,6 +98,13 @@ impl<T> Handle<T> {
         }
     }
 
+    /// Replaces this handle with the one its id maps to, see `Pack::append()`
+    pub fn remap(&mut self, handles: &[Handle<T>]) {
+        if self.valid() {
+            *self = handles.get(self.id).copied().unwrap_or_default();
+        }
+    }
+
     pub fn is_none(&self) -> bool {
         !self.valid()
     }
@@ -115,6 +124,13 @@ impl<T> Clone for Handle<T> {
 
 impl<T> Copy for Handle<T> {}
 
+/// Implemented manually, as deriving it would require `T` to be `Debug` as well
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handle").field("id", &self.id).finish()
+    }
+}
+
 impl<T> PartialEq for Handle<T> {
     fn eq(&self, other: &Self) -> bool {
         self.id == other.id
@@ -172,6 +188,19 @@ impl<T> Pack<T> {
         vec_index
     }
 
+    /// Returns whether `handle` refers to an element of this pack,
+    /// while `get()` asserts it is not out of range
+    pub fn contains(&self, handle: Handle<T>) -> bool {
+        handle.valid() && handle.id < self.indices.len() && !self.free.contains(&handle.id)
+    }
+
+    /// Returns the handles of the elements of this pack
+    pub fn handles(&self) -> impl Iterator<Item = Handle<T>> + '_ {
+        (0..self.indices.len())
+            .filter(move |id| !self.free.contains(id))
+            .map(Handle::new)
+    }
+
     pub fn get(&self, handle: Handle<T>) -> Option<&T> {
         if !handle.valid() {
             return None;
@@ -205,25 +234,65 @@ impl<T> Pack<T> {
         self.free.push(handle.id);
     }
 
-    /// Appends `other` to the current one, returning the offset for updating handles to `other`
-    pub fn append(&mut self, other: &mut Pack<T>) -> usize {
-        let ret = self.indices.len();
+    /// Removes multiple elements at once, which is much faster than calling `remove()`
+    /// for each of them when the pack is large
+    pub fn remove_many(&mut self, handles: &[Handle<T>]) {
+        // Handle of the element at each position of the vector
+        let mut owners = vec![usize::MAX; self.vec.len()];
+        let mut live = vec![true; self.indices.len()];
+        for id in &self.free {
+            live[*id] = false;
+        }
+        for (id, index) in self.indices.iter().enumerate() {
+            if live[id] {
+                owners[*index] = id;
+            }
+        }
+
+        for handle in handles {
+            let vec_index = self.get_vec_index(*handle);
+            let last_vec_index = self.vec.len() - 1;
+            self.vec.swap_remove(vec_index);
+
+            // Update index that was pointing to last element
+            let moved = owners[last_vec_index];
+            self.indices[moved] = vec_index;
+            owners[vec_index] = moved;
+            owners.pop();
+
+            self.free.push(handle.id);
+        }
+    }
 
-        // Update other indices
-        let index_offset = self.vec.len();
-        for index in &mut other.indices {
-            *index += index_offset;
+    /// Moves the elements of `other` into the current one, reusing the ids freed by
+    /// removals. Returns the handles the ids of `other` map to, for updating handles
+    /// to `other` with `Handle::remap()`. Free ids of `other` map to none
+    pub fn append(&mut self, other: &mut Pack<T>) -> Vec<Handle<T>> {
+        let mut live = vec![true; other.indices.len()];
+        for id in &other.free {
+            live[*id] = false;
         }
-        for free_index in &mut other.free {
-            *free_index += index_offset;
+        // Id of the element at each position of the vector
+        let mut owners = vec![usize::MAX; other.vec.len()];
+        for (id, index) in other.indices.iter().enumerate() {
+            if live[id] {
+                owners[*index] = id;
+            }
         }
 
-        // Append everything
-        self.vec.append(&mut other.vec);
-        self.indices.append(&mut other.indices);
-        self.free.append(&mut other.free);
+        let mut handles = vec![Handle::NONE; other.indices.len()];
+        for (elem, id) in other.vec.drain(..).zip(owners) {
+            handles[id] = self.push(elem);
+        }
+        other.clear();
+        handles
+    }
 
-        ret
+    /// Removes all elements, keeping the allocated memory
+    pub fn clear(&mut self) {
+        self.vec.clear();
+        self.indices.clear();
+        self.free.clear();
     }
 }
 
@@ -265,6 +334,127 @@ impl<T> DerefMut for Pack<T> {
     }
 }
 
+/// Structures which can be emptied while keeping their memory, to be reused by a `FrameArena`
+pub trait Recycle {
+    fn recycle(&mut self);
+}
+
+impl<T> Recycle for Vec<T> {
+    fn recycle(&mut self) {
+        self.clear();
+    }
+}
+
+impl<T> Recycle for Pack<T> {
+    fn recycle(&mut self) {
+        self.clear();
+    }
+}
+
+impl<K, V> Recycle for HashMap<K, V> {
+    fn recycle(&mut self) {
+        self.clear();
+    }
+}
+
+/// Keeps the memory of transient structures built every frame, such as the list of
+/// primitives and the BVH nodes, so that the next frame can reuse it instead of
+/// allocating it again. This reduces allocator pressure when rendering interactively.
+#[derive(Default)]
+pub struct FrameArena {
+    /// Recycled structures by type
+    free: HashMap<TypeId, Vec<Box<dyn Any + Send + Sync>>>,
+}
+
+impl FrameArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns an empty structure, reusing the memory of a recycled one when available
+    pub fn take<T: Recycle + Default + Send + Sync + 'static>(&mut self) -> T {
+        self.free
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|free| free.pop())
+            .and_then(|any| any.downcast::<T>().ok())
+            .map(|boxed| *boxed)
+            .unwrap_or_default()
+    }
+
+    /// Empties `value` and keeps its memory for a subsequent `take()`
+    pub fn give<T: Recycle + Send + Sync + 'static>(&mut self, mut value: T) {
+        value.recycle();
+        self.free
+            .entry(TypeId::of::<T>())
+            .or_default()
+            .push(Box::new(value));
+    }
+
+    /// Releases the memory of all the recycled structures
+    pub fn reset(&mut self) {
+        self.free.clear();
+    }
+}
+
+#[macro_export]
+macro_rules! print_info {
+    ( $s:expr, $( $t:tt )* ) => {
+        if $crate::log_enabled(module_path!(), $crate::LogLevel::Info) {
+            println!("{:>12} {}", owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::blue(&$s)), format!($( $t )*))
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! print_success {
+    ( $s:expr, $( $t:tt )* ) => {
+        if $crate::log_enabled(module_path!(), $crate::LogLevel::Info) {
+            println!("{:>12} {}", owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::green(&$s)), format!($( $t )*))
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! print_warning {
+    ( $s:expr, $( $t:tt )* ) => {
+        if $crate::log_enabled(module_path!(), $crate::LogLevel::Warning) {
+            println!("{:>12} {}", owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::yellow(&$s)), format!($( $t )*))
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! fail {
+    ( $( $t:tt )* ) => {
+        format!("{:>12} {}", owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::red(&"Failed")), format!($( $t )*))
+    }
+}
+
+#[macro_export]
+macro_rules! warn {
+    ( $s:expr, $( $t:tt )* ) => {
+        format!("{:>12} {}", owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::yellow(&$s)), format!($( $t )*))
+    }
+}
+
+#[macro_export]
+macro_rules! print_warn {
+    ( $s:expr, $( $t:tt )* ) => {
+        if $crate::log_enabled(module_path!(), $crate::LogLevel::Warning) {
+            $crate::rlog!("{:>12} {}",
+                owo_colors::OwoColorize::bold(
+                    &owo_colors::OwoColorize::yellow(&$s)), format!($( $t )*))
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! panic_fail {
+    ( $( $t:tt )* ) => {
+        panic!("{:>12} {}", owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::red(&"Failed")), format!($( $t )*))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::{collections::HashMap, thread};
@@ -335,6 +525,33 @@ mod test {
         assert_eq!(pack.get(handle).unwrap().val, 1);
     }
 
+    #[test]
+    fn append_reuses_ids() {
+        let mut pack = Pack::new();
+        let first = pack.push(Thing::new(0));
+        let second = pack.push(Thing::new(1));
+        pack.remove(first);
+
+        let mut other = Pack::new();
+        let removed = other.push(Thing::new(2));
+        let mut kept = other.push(Thing::new(3));
+        other.remove(removed);
+
+        let handles = pack.append(&mut other);
+        assert!(other.is_empty());
+        assert!(!handles[removed.id].valid());
+        kept.remap(&handles);
+        assert_eq!(kept, first);
+        assert_eq!(pack.get(kept).unwrap().val, 3);
+        assert_eq!(pack.get(second).unwrap().val, 1);
+
+        // Appending and removing again does not grow the ids
+        let mut other = Pack::from(vec![Thing::new(4)]);
+        pack.remove(kept);
+        let handles = pack.append(&mut other);
+        assert_eq!(handles[0], first);
+    }
+
     trait Handy {
         fn handy(&self) -> bool;
     }
@@ -354,61 +571,34 @@ mod test {
     }
 
     #[test]
-    fn send_handle() {
-        let handle = Handle::<u32>::none();
-        thread::spawn(move || {
-            assert!(!handle.valid());
-        });
-    }
-}
-
-#[macro_export]
-macro_rules! print_info {
-    ( $s:expr, $( $t:tt )* ) => {
-        println!("{:>12} {}", owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::blue(&$s)), format!($( $t )*))
-    }
-}
-
-#[macro_export]
-macro_rules! print_success {
-    ( $s:expr, $( $t:tt )* ) => {
-        println!("{:>12} {}", owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::green(&$s)), format!($( $t )*))
-    }
-}
+    fn arena() {
+        let mut arena = FrameArena::new();
+        let mut vec: Vec<u32> = arena.take();
+        vec.extend(0..64);
+        let capacity = vec.capacity();
+        arena.give(vec);
 
-#[macro_export]
-macro_rules! print_warning {
-    ( $s:expr, $( $t:tt )* ) => {
-        println!("{:>12} {}", owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::yellow(&$s)), format!($( $t )*))
-    }
-}
+        let vec: Vec<u32> = arena.take();
+        assert!(vec.is_empty());
+        assert_eq!(vec.capacity(), capacity);
 
-#[macro_export]
-macro_rules! fail {
-    ( $( $t:tt )* ) => {
-        format!("{:>12} {}", owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::red(&"Failed")), format!($( $t )*))
-    }
-}
-
-#[macro_export]
-macro_rules! warn {
-    ( $s:expr, $( $t:tt )* ) => {
-        format!("{:>12} {}", owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::yellow(&$s)), format!($( $t )*))
-    }
-}
+        // Nothing left to reuse
+        let other: Vec<u32> = arena.take();
+        assert_eq!(other.capacity(), 0);
 
-#[macro_export]
-macro_rules! print_warn {
-    ( $s:expr, $( $t:tt )* ) => {
-        $crate::rlog!("{:>12} {}",
-            owo_colors::OwoColorize::bold(
-                &owo_colors::OwoColorize::yellow(&$s)), format!($( $t )*))
+        let mut pack = Pack::new();
+        pack.push(0u32);
+        arena.give(pack);
+        let pack: Pack<u32> = arena.take();
+        assert!(pack.is_empty());
+        assert!(pack.capacity() > 0);
     }
-}
 
-#[macro_export]
-macro_rules! panic_fail {
-    ( $( $t:tt )* ) => {
-        panic!("{:>12} {}", owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::red(&"Failed")), format!($( $t )*))
+    #[test]
+    fn send_handle() {
+        let handle = Handle::<u32>::none();
+        thread::spawn(move || {
+            assert!(!handle.valid());
+        });
     }
 }
@@ -0,0 +1,335 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Checks of the assets of a scene, and counts of what it contains, which are useful
+//! before starting long renders, or to reject broken assets in CI.
+
+use std::fmt;
+
+use crate::*;
+
+/// Problem found by `Model::validate()`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Issue {
+    /// Handle referring to an element which does not exist, with a description of
+    /// where it was found
+    DanglingHandle(String),
+
+    /// Primitive without a material, which is rendered with the default one
+    MissingMaterial(Handle<Primitive>),
+
+    /// Vertex whose position is infinite or NaN
+    NonFiniteVertex {
+        primitive: Handle<Primitive>,
+        vertex: usize,
+    },
+
+    /// Triangle without area, which can never be hit
+    DegenerateTriangle {
+        primitive: Handle<Primitive>,
+        triangle: usize,
+    },
+
+    /// Image not used by any texture
+    UnusedImage(Handle<Image>),
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::DanglingHandle(what) => write!(f, "Dangling handle: {}", what),
+            Issue::MissingMaterial(primitive) => {
+                write!(f, "Primitive {} without material", primitive.id)
+            }
+            Issue::NonFiniteVertex { primitive, vertex } => write!(
+                f,
+                "Primitive {} with non-finite vertex {}",
+                primitive.id, vertex
+            ),
+            Issue::DegenerateTriangle {
+                primitive,
+                triangle,
+            } => write!(
+                f,
+                "Primitive {} with degenerate triangle {}",
+                primitive.id, triangle
+            ),
+            Issue::UnusedImage(image) => write!(f, "Image {} is not used", image.id),
+        }
+    }
+}
+
+/// Issues found by validating a model
+#[derive(Clone, Debug, Default)]
+pub struct ValidationReport {
+    pub issues: Vec<Issue>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Returns how many dangling handles were found, which are the issues
+    /// that can make rendering panic
+    pub fn dangling_count(&self) -> usize {
+        self.issues
+            .iter()
+            .filter(|issue| matches!(issue, Issue::DanglingHandle(_)))
+            .count()
+    }
+
+    fn check<T>(&mut self, pack: &Pack<T>, handle: Handle<T>, what: impl FnOnce() -> String) {
+        if handle.valid() && !pack.contains(handle) {
+            self.issues
+                .push(Issue::DanglingHandle(format!("{} {}", what(), handle.id)));
+        }
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts of the elements of a model, and of the memory they take
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SceneStats {
+    pub nodes: usize,
+    pub meshes: usize,
+    pub primitives: usize,
+    pub materials: usize,
+    pub textures: usize,
+    pub images: usize,
+    pub lights: usize,
+    pub triangles: usize,
+    pub vertices: usize,
+
+    /// Bytes of vertices and indices
+    pub geometry_bytes: usize,
+
+    /// Bytes of the pixels of the images
+    pub texture_bytes: usize,
+}
+
+impl Model {
+    /// Checks the handles of every element, and the geometry of the primitives
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+
+        for (name, node) in std::iter::once(("Root", &self.root))
+            .chain(self.nodes.iter().map(|node| (node.name.as_str(), node)))
+        {
+            let what = |field: &str| format!("{} of node \"{}\"", field, name);
+            report.check(&self.meshes, node.mesh, || what("mesh"));
+            report.check(&self.cameras, node.camera, || what("camera"));
+            report.check(&self.lights, node.light, || what("light"));
+            report.check(&self.materials, node.material, || what("material"));
+            for child in &node.children {
+                report.check(&self.nodes, *child, || what("child"));
+            }
+            for lod in node.lod.iter().flat_map(|lod| &lod.levels) {
+                report.check(&self.meshes, lod.mesh, || what("LOD mesh"));
+            }
+        }
+
+        for (i, mesh) in self.meshes.iter().enumerate() {
+            for primitive in &mesh.primitives {
+                report.check(&self.primitives, *primitive, || {
+                    format!("primitive of mesh {}", i)
+                });
+            }
+        }
+
+        for handle in self.primitives.handles() {
+            let primitive = self.primitives.get(handle).unwrap();
+            if primitive.material.is_none() {
+                report.issues.push(Issue::MissingMaterial(handle));
+            }
+            report.check(&self.materials, primitive.material, || {
+                format!("material of primitive {}", handle.id)
+            });
+            if let Geometry::Triangles(triangles) = &primitive.geometry {
+                Self::validate_triangles(&mut report, handle, triangles);
+            }
+        }
+
+        for (i, material) in self.materials.iter().enumerate() {
+            let mut textures = vec![
+                material.albedo_texture,
+                material.normal_texture,
+                material.metallic_roughness_texture,
+                material.occlusion_texture,
+                material.displacement_texture,
+            ];
+            if let Some(mix) = &material.mix {
+                textures.push(mix.mask);
+                for other in [mix.a, mix.b] {
+                    report.check(&self.materials, other, || {
+                        format!("mixed material of material {}", i)
+                    });
+                }
+            }
+            if let Some(graph) = &material.graph {
+                textures.extend(graph.get_textures());
+            }
+            for texture in textures {
+                report.check(&self.textures, texture, || {
+                    format!("texture of material {}", i)
+                });
+            }
+        }
+
+        for (i, texture) in self.textures.iter().enumerate() {
+            report.check(&self.images, texture.image, || {
+                format!("image of texture {}", i)
+            });
+            report.check(&self.samplers, texture.sampler, || {
+                format!("sampler of texture {}", i)
+            });
+        }
+
+        for image in self.images.handles() {
+            if !self.textures.iter().any(|texture| texture.image == image) {
+                report.issues.push(Issue::UnusedImage(image));
+            }
+        }
+
+        report
+    }
+
+    fn validate_triangles(
+        report: &mut ValidationReport,
+        primitive: Handle<Primitive>,
+        triangles: &Triangles,
+    ) {
+        let vertices = &triangles.vertices;
+        for (i, vertex) in vertices.iter().enumerate() {
+            let pos = vertex.pos;
+            if !(pos.get_x().is_finite() && pos.get_y().is_finite() && pos.get_z().is_finite()) {
+                report.issues.push(Issue::NonFiniteVertex {
+                    primitive,
+                    vertex: i,
+                });
+            }
+        }
+
+        for (i, triangle) in triangles.get_indices().chunks_exact(3).enumerate() {
+            let Some([a, b, c]) = triangle
+                .iter()
+                .map(|index| vertices.get(*index as usize).map(|vertex| vertex.pos))
+                .collect::<Option<Vec<_>>>()
+                .map(|points| [points[0], points[1], points[2]])
+            else {
+                report.issues.push(Issue::DanglingHandle(format!(
+                    "vertex of triangle {} of primitive {}",
+                    i, primitive.id
+                )));
+                continue;
+            };
+            if (b - a).cross(&(c - a)).len() <= f32::EPSILON {
+                report.issues.push(Issue::DegenerateTriangle {
+                    primitive,
+                    triangle: i,
+                });
+            }
+        }
+    }
+
+    /// Counts the elements of this model, and the memory they take
+    pub fn get_stats(&self) -> SceneStats {
+        let mut ret = SceneStats {
+            nodes: self.nodes.len(),
+            meshes: self.meshes.len(),
+            primitives: self.primitives.len(),
+            materials: self.materials.len(),
+            textures: self.textures.len(),
+            images: self.images.len(),
+            lights: self.lights.len(),
+            ..Default::default()
+        };
+
+        for primitive in self.primitives.iter() {
+            if let Geometry::Triangles(triangles) = &primitive.geometry {
+                let index_count = triangles.indices.len() / triangles.index_size_in_bytes;
+                ret.triangles += index_count / 3;
+                ret.vertices += triangles.vertices.len();
+                ret.geometry_bytes += triangles.vertices.len() * std::mem::size_of::<Vertex>()
+                    + triangles.indices.len();
+            }
+        }
+        ret.texture_bytes = self.images.iter().map(|image| image.bytes().len()).sum();
+
+        ret
+    }
+}
+
+impl Scene {
+    /// Validates the model of the scene, see `Model::validate()`
+    pub fn validate(&self) -> ValidationReport {
+        self.model.validate()
+    }
+
+    /// Counts the elements of the model of the scene, while the `stats` field
+    /// holds the statistics of the last rendered frame
+    pub fn stats(&self) -> SceneStats {
+        self.model.get_stats()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validate() {
+        let mut model = Model::new();
+        let material = model.materials.push(Material::new());
+        let triangles = Triangles::new(
+            vec![
+                Vertex::new(0.0, 0.0, 0.0),
+                Vertex::new(1.0, 0.0, 0.0),
+                Vertex::new(0.0, 1.0, 0.0),
+                Vertex::new(2.0, 0.0, 0.0),
+            ],
+            vec![0, 1, 2, 0, 1, 3],
+        );
+        let primitive = model.primitives.push(
+            Primitive::builder()
+                .triangles(triangles)
+                .material(material)
+                .build(),
+        );
+        let mesh = model.meshes.push(Mesh::new(vec![primitive]));
+        let node = model.nodes.push(Node::builder().mesh(mesh).build());
+        model.root.children.push(node);
+
+        let stats = model.get_stats();
+        assert_eq!(stats.triangles, 2);
+        assert_eq!(stats.vertices, 4);
+
+        // The second triangle lies on a line
+        let report = model.validate();
+        assert_eq!(
+            report.issues,
+            [Issue::DegenerateTriangle {
+                primitive,
+                triangle: 1
+            }]
+        );
+
+        model.images.push(Image::new(1, 1, ColorType::RGBA8));
+        model.nodes.get_mut(node).unwrap().mesh = Handle::new(4);
+        model.primitives.get_mut(primitive).unwrap().material = Handle::NONE;
+        let report = model.validate();
+        assert_eq!(report.dangling_count(), 1);
+        assert!(report.issues.contains(&Issue::MissingMaterial(primitive)));
+        assert!(report.issues.contains(&Issue::UnusedImage(Handle::new(0))));
+        assert_eq!(model.get_stats().texture_bytes, 4);
+    }
+}
@@ -7,8 +7,8 @@ use std::f32::consts::FRAC_PI_8;
 use js_sys::{ArrayBuffer, Uint8Array};
 use wasm_bindgen::{prelude::*, Clamped, JsCast};
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{window, CanvasRenderingContext2d, HtmlCanvasElement, ImageData};
-use web_sys::{Request, RequestInit, RequestMode, Response};
+use web_sys::{window, CanvasRenderingContext2d, HtmlCanvasElement, ImageData, Window};
+use web_sys::{Request, RequestInit, RequestMode, Response, WorkerGlobalScope};
 
 use crate::*;
 
@@ -50,38 +50,51 @@ fn get_canvas(id: &str) -> Result<CanvasRenderingContext2d, JsValue> {
     Ok(canvas)
 }
 
-async fn get_model() -> Result<Model, JsValue> {
+/// Fetches the resource at `url` without blocking the browser, from a page or a web worker
+pub async fn fetch_bytes(url: &str) -> Result<Vec<u8>, JsValue> {
     let opts = RequestInit::new();
     opts.set_method("GET");
     opts.set_mode(RequestMode::Cors);
 
-    let url = "https://raw.githubusercontent.com/KhronosGroup/glTF-Sample-Models/master/2.0/Box/glTF-Embedded/Box.gltf";
+    let request = Request::new_with_str_and_init(url, &opts)?;
 
-    let request = Request::new_with_str_and_init(&url, &opts).expect("Failed to request");
-
-    let window = web_sys::window().unwrap();
-    let resp_value = JsFuture::from(window.fetch_with_request(&request))
-        .await
-        .unwrap();
+    let global = js_sys::global();
+    let promise = match global.dyn_ref::<Window>() {
+        Some(window) => window.fetch_with_request(&request),
+        None => global
+            .dyn_into::<WorkerGlobalScope>()?
+            .fetch_with_request(&request),
+    };
+    let resp_value = JsFuture::from(promise).await?;
 
     assert!(resp_value.is_instance_of::<Response>());
     let resp: Response = resp_value.dyn_into().unwrap();
+    if !resp.ok() {
+        return Err(JsValue::from_str(&format!(
+            "Failed to fetch {}: {}",
+            url,
+            resp.status()
+        )));
+    }
 
-    let buffer = JsFuture::from(resp.array_buffer().expect("Failed to get array buffer"))
-        .await
-        .expect("Failed to wait for array buffer");
+    let buffer = JsFuture::from(resp.array_buffer()?).await?;
 
     assert!(buffer.is_instance_of::<ArrayBuffer>());
     let buffer: ArrayBuffer = buffer.dyn_into().unwrap();
 
     let array = Uint8Array::new(&buffer);
-    let bytes = array.to_vec();
+    Ok(array.to_vec())
+}
+
+/// Fetches a glTF model with embedded resources, or a binary glTF
+pub async fn fetch_model(url: &str) -> Result<Model, JsValue> {
+    let bytes = fetch_bytes(url).await?;
 
     let model = Model::builder()
         .data(&bytes)
-        .expect("Failed to load data")
+        .map_err(|err| JsValue::from_str(&err.to_string()))?
         .build()
-        .expect("Failed to build model");
+        .map_err(|err| JsValue::from_str(&err.to_string()))?;
 
     Ok(model)
 }
@@ -117,13 +130,55 @@ fn tweak_box_scene(model: &mut Model) {
     model.root.children.push(model.nodes.push(box_node.clone()));
 }
 
+const BOX_URL: &str = "https://raw.githubusercontent.com/KhronosGroup/glTF-Sample-Models/master/2.0/Box/glTF-Embedded/Box.gltf";
+
+/// Samples per pixel the canvas is refined up to, while nothing changes
+const PROGRESSIVE_SAMPLES: u32 = 16;
+
+/// Creates the scene of the model fetched from `url`, or the default scene with a box
+/// without it, returning whether the scene is animated
+pub(crate) async fn create_scene(url: Option<String>) -> Result<(Scene, bool), JsValue> {
+    let model = match &url {
+        Some(url) => fetch_model(url).await?,
+        None => {
+            let mut model = fetch_model(BOX_URL).await?;
+            tweak_box_scene(&mut model);
+            model
+        }
+    };
+
+    let mut scene = Scene::new();
+    scene.push(model);
+    scene.push(Scene::create_default_model());
+    scene.config.samples_per_pixel = PROGRESSIVE_SAMPLES;
+    Ok((scene, url.is_none()))
+}
+
 #[wasm_bindgen]
 pub struct Context {
     canvas: CanvasRenderingContext2d,
     image: Image,
     scene: Scene,
-    image_data: ImageData,
     timer: Timer,
+    controller: CameraController,
+
+    /// Previous frames blended with new ones while the scene changes
+    temporal: TemporalAccumulation,
+
+    /// Samples accumulated since the scene last changed
+    checkpoint: Checkpoint,
+
+    /// Incremented every time the samples start from scratch
+    generation: u32,
+
+    /// Whether progressive samples are rendered by web workers, see `get_frame()`
+    workers: bool,
+
+    /// Whether the scene changed since the last frame drawn on the canvas
+    dirty: bool,
+
+    /// Whether the box of the default scene should keep rotating
+    animate: bool,
 }
 
 #[wasm_bindgen]
@@ -131,35 +186,76 @@ impl Context {
     pub async fn new() -> Result<Context, JsValue> {
         //set_panic_hook();
 
+        let (scene, animate) = create_scene(None).await?;
+        Self::from_scene(scene, animate)
+    }
+
+    /// Creates a context rendering the model fetched from `url`
+    pub async fn with_model(url: String) -> Result<Context, JsValue> {
+        let (scene, animate) = create_scene(Some(url)).await?;
+        let mut ret = Self::from_scene(scene, animate)?;
+        ret.update_camera();
+        Ok(ret)
+    }
+
+    fn from_scene(scene: Scene, animate: bool) -> Result<Context, JsValue> {
         let canvas = get_canvas("area")?;
 
         const WIDTH: u32 = 128;
         let mut image = Image::new(WIDTH, WIDTH, ColorType::RGBA8);
         image.clear(RGBA8::black());
 
-        let mut model = get_model().await.unwrap();
-        tweak_box_scene(&mut model);
-
-        let mut scene = Scene::new();
-        scene.push(model);
-        scene.push(Scene::create_default_model());
+        let mut controller = CameraController::default();
+        controller.smoothing = 0.1;
 
-        let data = Clamped(image.bytes());
-
-        let image_data = ImageData::new_with_u8_clamped_array(data, WIDTH)?;
-        canvas.put_image_data(&image_data, 0.0, 0.0)?;
-
-        Ok(Self {
+        let mut ret = Self {
             canvas,
             image,
             scene,
-            image_data,
             timer: Timer::new(),
-        })
+            controller,
+            temporal: TemporalAccumulation::default(),
+            checkpoint: Checkpoint::default(),
+            generation: 0,
+            workers: false,
+            dirty: true,
+            animate,
+        };
+        ret.present()?;
+        Ok(ret)
     }
 
-    pub fn rotate_box(&mut self) {
-        let delta = self.timer.get_delta().as_secs_f32();
+    /// Rotates the camera around its target by the given angles in radians
+    pub fn orbit(&mut self, delta_yaw: f32, delta_pitch: f32) {
+        self.controller.rotate(delta_yaw, delta_pitch);
+    }
+
+    /// Moves the camera and its target parallel to the view plane
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        self.controller.pan(dx, dy);
+    }
+
+    /// Moves the camera towards its target by a factor of its current distance
+    pub fn zoom(&mut self, delta: f32) {
+        self.controller.zoom(delta);
+    }
+
+    fn update_camera(&mut self) {
+        if let Some(camera_node_handle) = self.scene.get_camera_node_handle() {
+            let camera_node = self.scene.model.nodes.get_mut(camera_node_handle).unwrap();
+            self.controller.apply(camera_node);
+        }
+        self.dirty = true;
+    }
+
+    fn present(&mut self) -> Result<(), JsValue> {
+        // ImageData copies the bytes, hence it needs to be created every frame
+        let data = Clamped(self.image.bytes());
+        let image_data = ImageData::new_with_u8_clamped_array(data, self.image.width())?;
+        self.canvas.put_image_data(&image_data, 0.0, 0.0)
+    }
+
+    fn rotate_box(&mut self, delta: f32) {
         let angle = FRAC_PI_8 * delta;
         self.scene
             .model
@@ -170,12 +266,120 @@ impl Context {
             .rotation *= Quat::new(0.0, angle.sin(), 0.0, angle.cos());
     }
 
+    /// Meant to be called on every animation frame. While something changes, every frame
+    /// is blended with the previous ones reprojected. Otherwise, every frame adds a sample
+    /// per pixel to the canvas
     pub fn draw(&mut self) -> Result<(), JsValue> {
-        self.rotate_box();
-        self.image.clear(RGBA8::black());
-        self.scene.draw(&mut self.image);
+        let delta = self.timer.get_delta().as_secs_f32();
+        if self.animate {
+            self.rotate_box(delta);
+            self.dirty = true;
+        }
+        // The controller moves the camera smoothly towards the requested position
+        if self.controller.update(delta) {
+            self.update_camera();
+        }
+        if self.dirty {
+            self.image.clear(RGBA8::black());
+            self.scene
+                .draw_temporal(&mut self.temporal, &mut self.image);
+            self.checkpoint = Checkpoint::default();
+            self.generation = self.generation.wrapping_add(1);
+            self.dirty = false;
+            return self.present();
+        }
+        if self.workers {
+            return Ok(());
+        }
+
+        if self.checkpoint.get_sample_count() == 0 {
+            self.image.clear(RGBA8::black());
+        }
+        if self
+            .scene
+            .draw_progressive(&mut self.checkpoint, &mut self.image)
+            && self.is_refined()
+        {
+            self.present()?;
+        }
+        Ok(())
+    }
 
-        self.canvas.put_image_data(&self.image_data, 0.0, 0.0)?;
+    /// Progressive samples are shown once they are less noisy than the blended frames
+    fn is_refined(&self) -> bool {
+        let blended_frames = (1.0 / self.temporal.alpha).ceil() as u32;
+        self.checkpoint.get_sample_count() >= blended_frames.min(PROGRESSIVE_SAMPLES)
+    }
+
+    pub fn get_height(&self) -> u32 {
+        self.image.height()
+    }
+
+    /// Makes `draw()` leave progressive samples to web workers, which render the frames
+    /// returned by `get_frame()` with a `RowRenderer`
+    pub fn set_workers(&mut self, workers: bool) {
+        self.workers = workers;
+    }
+
+    /// Returns the frame whose next sample web workers should render, or nothing when
+    /// the scene is changing, or the canvas is refined enough
+    pub fn get_frame(&mut self) -> Option<Vec<u8>> {
+        if self.dirty {
+            return None;
+        }
+        let (width, height) = (self.image.width(), self.image.height());
+        if self.checkpoint.tiles.is_empty() {
+            self.checkpoint = Checkpoint::new(width, height, self.scene.config.seed, 0);
+            self.image.clear(RGBA8::black());
+        }
+        let sample = self.checkpoint.get_sample_count();
+        if sample >= PROGRESSIVE_SAMPLES {
+            return None;
+        }
+
+        let frame = Frame {
+            generation: self.generation,
+            width,
+            height,
+            sample,
+            nodes: self
+                .scene
+                .model
+                .nodes
+                .iter()
+                .map(|node| node.trs.clone())
+                .collect(),
+        };
+        Some(frame.serialize())
+    }
+
+    /// Adds the tiles of the rows from `start`, which a `RowRenderer` rendered for `frame`,
+    /// to the samples of the canvas. Tiles of frames which are out of date are ignored
+    pub fn merge_rows(&mut self, frame: &[u8], start: usize, tiles: &[u8]) -> Result<(), JsValue> {
+        let to_js = |err: Box<dyn std::error::Error>| JsValue::from_str(&err.to_string());
+        let frame = Frame::deserialize(frame).map_err(to_js)?;
+        if frame.generation != self.generation || self.checkpoint.tiles.is_empty() {
+            return Ok(());
+        }
+
+        let mut reader = Reader { data: tiles };
+        let count = reader.u32().map_err(to_js)? as usize;
+        let end = (start + count).min(self.checkpoint.tiles.len());
+        for tile in &mut self.checkpoint.tiles[start.min(end)..end] {
+            let rows = Tile::read(&mut reader, frame.width).map_err(to_js)?;
+            if tile.sample_count == frame.sample {
+                tile.merge(&rows);
+            }
+        }
         Ok(())
     }
+
+    /// Shows the samples merged so far, when there are enough of them
+    pub fn present_samples(&mut self) -> Result<(), JsValue> {
+        if self.dirty || self.checkpoint.tiles.is_empty() || !self.is_refined() {
+            return Ok(());
+        }
+        self.scene.resolve(&self.checkpoint, &mut self.image);
+        self.present()
+    }
 }
@@ -3,4 +3,6 @@
 // SPDX-License-Identifier: MIT
 
 pub mod context;
+pub mod worker;
 pub use context::*;
+pub use worker::*;
@@ -0,0 +1,125 @@
+// Copyright © 2024
+// Author: Antonio Caggiano <info@antoniocaggiano.eu>
+// SPDX-License-Identifier: MIT
+
+//! Rendering of the samples of a `Context` in web workers, so that browsers use more
+//! than one core. Every worker has its own copy of the scene, which follows the one
+//! of the context through the frames it receives, see `Context::get_frame()`.
+
+use std::error::Error;
+
+use wasm_bindgen::prelude::*;
+
+use crate::*;
+
+/// State of the scene of a context, which workers need to render a sample of a frame
+pub(crate) struct Frame {
+    /// Incremented by the context every time its samples start from scratch
+    pub generation: u32,
+    pub width: u32,
+    pub height: u32,
+
+    /// Index of the sample to render
+    pub sample: u32,
+
+    /// Transforms of the nodes of the scene
+    pub nodes: Vec<Trs>,
+}
+
+impl Frame {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut ret = vec![];
+        for value in [self.generation, self.width, self.height, self.sample] {
+            ret.extend_from_slice(&value.to_le_bytes());
+        }
+        ret.extend_from_slice(&(self.nodes.len() as u32).to_le_bytes());
+        for trs in &self.nodes {
+            let (t, r, s) = (&trs.translation, &trs.rotation, &trs.scale);
+            for value in [
+                t.get_x(),
+                t.get_y(),
+                t.get_z(),
+                r.get_x(),
+                r.get_y(),
+                r.get_z(),
+                r.get_w(),
+                s.get_x(),
+                s.get_y(),
+                s.get_z(),
+            ] {
+                ret.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        ret
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = Reader { data };
+        let generation = reader.u32()?;
+        let width = reader.u32()?;
+        let height = reader.u32()?;
+        let sample = reader.u32()?;
+        let node_count = reader.u32()? as usize;
+        // Every node takes 10 floats, which bounds the count before allocating
+        if node_count > reader.data.len() / 40 {
+            return Err(format!("Frame with {} nodes is truncated", node_count).into());
+        }
+        let mut nodes = Vec::with_capacity(node_count);
+        for _ in 0..node_count {
+            let translation = Vec3::new(reader.f32()?, reader.f32()?, reader.f32()?);
+            let rotation = Quat::new(reader.f32()?, reader.f32()?, reader.f32()?, reader.f32()?);
+            let scale = Vec3::new(reader.f32()?, reader.f32()?, reader.f32()?);
+            nodes.push(Trs::new(translation, rotation, scale));
+        }
+        Ok(Self {
+            generation,
+            width,
+            height,
+            sample,
+            nodes,
+        })
+    }
+}
+
+/// Renders samples of the frames of a `Context` in a web worker
+#[wasm_bindgen]
+pub struct RowRenderer {
+    scene: Scene,
+}
+
+#[wasm_bindgen]
+impl RowRenderer {
+    /// Creates a renderer of the scene of a context, which renders the model fetched
+    /// from `url`, or the default scene without it
+    pub async fn new(url: Option<String>) -> Result<RowRenderer, JsValue> {
+        let (scene, _) = create_scene(url).await?;
+        Ok(Self { scene })
+    }
+
+    /// Renders the sample of rows `start..end` of `frame`, which comes from
+    /// `Context::get_frame()`, returning their tiles for `Context::merge_rows()`
+    pub fn render(&mut self, frame: &[u8], start: usize, end: usize) -> Result<Vec<u8>, JsValue> {
+        let frame = Frame::deserialize(frame).map_err(|err| JsValue::from_str(&err.to_string()))?;
+        if frame.nodes.len() != self.scene.model.nodes.len() {
+            return Err(JsValue::from_str(&format!(
+                "Frame with {} nodes for a scene with {}",
+                frame.nodes.len(),
+                self.scene.model.nodes.len()
+            )));
+        }
+        for (node, trs) in self.scene.model.nodes.iter_mut().zip(frame.nodes) {
+            node.trs = trs;
+        }
+        self.scene.model.mark_all_dirty();
+
+        let samples = frame.sample..frame.sample + 1;
+        let tiles = self
+            .scene
+            .render_samples(frame.width, frame.height, start..end, samples);
+        let mut ret = (tiles.len() as u32).to_le_bytes().to_vec();
+        for tile in &tiles {
+            tile.serialize_into(&mut ret);
+        }
+        Ok(ret)
+    }
+}
@@ -4,6 +4,27 @@
 
 use rayca::*;
 
+/// Adds a unit sphere at Z -1, in the middle of the view of the default model,
+/// which is added as well
+fn push_unit_sphere(scene: &mut Scene) {
+    let mut model = Model::new();
+    let prim_handle = model.primitives.push(Primitive::unit_sphere());
+    let mesh_handle = model.meshes.push(Mesh::new(vec![prim_handle]));
+    let node = Node::builder()
+        .mesh(mesh_handle)
+        .translation(Vec3::new(0.0, 0.0, -1.0))
+        .build();
+    let node_handle = model.nodes.push(node);
+    model.root.children.push(node_handle);
+    scene.push(model);
+    scene.push_default_model();
+}
+
+/// Returns the sum of the bytes of `image`, to compare how bright images are
+fn brightness(image: &Image) -> u64 {
+    image.bytes().iter().map(|&byte| byte as u64).sum()
+}
+
 #[test]
 fn sphere() {
     let mut image = Image::new(256, 256, ColorType::RGBA8);
@@ -28,6 +49,117 @@ fn sphere() {
     image.dump_png("target/sphere.png");
 }
 
+#[test]
+fn deterministic() {
+    let render = |seed| {
+        // Points on the quad light of the box are sampled from the random number generator
+        let mut scene = Scene::cornell_box();
+        scene.config.seed = seed;
+        let mut image = Image::new(64, 64, ColorType::RGBA8);
+        scene.draw(&mut image);
+        image
+    };
+
+    let first = render(42);
+    let second = render(42);
+    assert!(first.bytes() == second.bytes());
+
+    // Other seeds sample other points
+    let other = render(7);
+    assert!(first.bytes() != other.bytes());
+}
+
+#[test]
+fn stats() {
+    let mut image = Image::new(32, 32, ColorType::RGBA8);
+    let config = Config {
+        log_stats: true,
+        ..Default::default()
+    };
+    let mut scene = Scene::new_with_config(config);
+    push_unit_sphere(&mut scene);
+
+    let rows = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let progress_rows = rows.clone();
+    scene.config.progress = Some(Box::new(move |progress: &Progress| {
+        assert!(progress.percent() <= 100.0);
+        progress_rows.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }));
+
+    scene.draw(&mut image);
+    assert_eq!(rows.load(std::sync::atomic::Ordering::Relaxed), 32);
+    assert_eq!(scene.stats.primary_rays, 32 * 32);
+    assert!(scene.stats.shadow_rays > 0);
+    assert!(scene.stats.primitive_tests > 0);
+
+    // Rays are not counted without statistics
+    scene.config.log_stats = false;
+    scene.draw(&mut image);
+    assert_eq!(scene.stats.ray_count(), 0);
+}
+
+#[test]
+fn cancel() {
+    let mut image = Image::new(32, 32, ColorType::RGBA8);
+    let config = Config {
+        log_stats: true,
+        ..Default::default()
+    };
+    let mut scene = Scene::new_with_config(config);
+    push_unit_sphere(&mut scene);
+
+    scene.config.cancel.cancel();
+    scene.draw(&mut image);
+    assert_eq!(scene.stats.primary_rays, 0);
+
+    scene.config.cancel.reset();
+    scene.draw(&mut image);
+    assert_eq!(scene.stats.primary_rays, 32 * 32);
+}
+
+#[test]
+fn pick() {
+    let mut scene = Scene::new();
+    push_unit_sphere(&mut scene);
+
+    // The sphere is in the middle of the screen
+    let bvh = scene.build_bvh();
+    let result = scene.pick(&bvh, 16, 16, 32, 32).unwrap();
+    assert!(result.primitive.valid());
+    assert!(result.barycentric.is_none());
+    let node = scene.model.nodes.get(result.node).unwrap();
+    assert!(node.mesh.valid());
+    assert!((result.depth - 4.0).abs() < 0.1);
+
+    // While corners are empty
+    assert!(scene.pick(&bvh, 0, 0, 32, 32).is_none());
+}
+
+#[test]
+fn pick_triangle() {
+    let mut scene = Scene::new();
+    let mut model = Model::new();
+    let prim_handle = model.primitives.push(Primitive::unit_triangle());
+    let mesh_handle = model.meshes.push(Mesh::new(vec![prim_handle]));
+    let node = Node::builder()
+        .mesh(mesh_handle)
+        .translation(Vec3::new(0.0, -1.0, 0.0))
+        .scale(Vec3::new(1.0, 2.0, 1.0))
+        .build();
+    let node_handle = model.nodes.push(node);
+    model.root.children.push(node_handle);
+    scene.push(model);
+    scene.push_default_model();
+
+    // Triangles report where the hit is within them
+    let bvh = scene.build_bvh();
+    let result = scene.pick(&bvh, 16, 16, 32, 32).unwrap();
+    assert_eq!(result.node, node_handle);
+    let barycentric = result.barycentric.unwrap();
+    assert!(barycentric.x >= 0.0 && barycentric.y >= 0.0);
+    assert!(barycentric.x + barycentric.y <= 1.0);
+}
+
 #[test]
 fn triangle() {
     let mut image = Image::new(256, 256, ColorType::RGBA8);
@@ -57,6 +189,820 @@ fn triangle() {
     image.dump_png("target/triangle.png");
 }
 
+#[test]
+fn hair() {
+    let mut image = Image::new(64, 64, ColorType::RGBA8);
+    let mut scene = Scene::new();
+
+    // A fan of strands bending to the right
+    let mut curves = vec![];
+    for i in 0..16 {
+        let x = i as f32 / 8.0 - 1.0;
+        let points = vec![
+            Point3::new(x, -1.0, 0.0),
+            Point3::new(x, 0.0, 0.0),
+            Point3::new(x + 0.2, 0.5, 0.0),
+            Point3::new(x + 0.5, 1.0, 0.0),
+        ];
+        let mut curve = Curve::new(points, vec![0.04, 0.03, 0.02, 0.01]);
+        curve.color = Color::from(0x8B4513FF);
+        curves.push(curve);
+    }
+
+    let mut model = Model::new();
+    let prim = Primitive::builder().curves(Curves::new(curves)).build();
+    let prim_handle = model.primitives.push(prim);
+    let mesh_handle = model.meshes.push(Mesh::new(vec![prim_handle]));
+    let node_handle = model.nodes.push(Node::builder().mesh(mesh_handle).build());
+    model.root.children.push(node_handle);
+    scene.push(model);
+    scene.push_default_model();
+
+    scene.draw(&mut image);
+    image.dump_png("target/hair.png");
+
+    // Thin strands should still be hit along a scanline
+    let bvh = scene.build_bvh();
+    let hits = (0..64)
+        .filter(|&x| scene.pick(&bvh, x, 40, 64, 64).is_some())
+        .count();
+    assert!(hits > 0);
+}
+
+#[test]
+fn heightfield() {
+    // Grayscale image of a bump
+    let mut bump = Image::new(32, 32, ColorType::RGBA8);
+    for y in 0..32 {
+        for x in 0..32 {
+            let (dx, dy) = (x as f32 - 16.0, y as f32 - 16.0);
+            let height = (-(dx * dx + dy * dy) / 64.0).exp();
+            let gray = (height * 255.0) as u8;
+            bump.set(x, y, RGBA8::new(gray, gray, gray, 255));
+        }
+    }
+
+    let mut model = Model::new();
+    let heightfield = Heightfield::from_image(&bump, 64, 64, 0.25);
+    let prim = Primitive::builder().heightfield(heightfield).build();
+    let prim_handle = model.primitives.push(prim);
+    let mesh_handle = model.meshes.push(Mesh::new(vec![prim_handle]));
+    let node = Node::builder()
+        .mesh(mesh_handle)
+        .translation(Vec3::new(0.0, -0.5, 0.0))
+        .scale(Vec3::new(4.0, 4.0, 4.0))
+        .build();
+    let node_handle = model.nodes.push(node);
+    model.root.children.push(node_handle);
+
+    let mut scene = Scene::new();
+    scene.push(model);
+    scene.push_default_model();
+
+    let mut image = Image::new(256, 256, ColorType::RGBA8);
+    scene.draw(&mut image);
+    image.dump_png("target/heightfield.png");
+
+    // The bump rises above the flat ground in front of the camera
+    let bvh = scene.build_bvh();
+    let result = scene.pick(&bvh, 32, 28, 64, 64).unwrap();
+    assert!(result.point.get_y() > 0.0);
+}
+
+#[test]
+fn sdf() {
+    // Two spheres blended together, with a hole carved in the middle
+    let a = SdfExpr::sphere(Point3::new(-0.6, 0.0, 0.0), 0.5);
+    let b = SdfExpr::sphere(Point3::new(0.6, 0.0, 0.0), 0.5);
+    let hole = SdfExpr::torus(Point3::default(), 0.8, 0.1);
+    let expr = a.smooth_union(b, 0.5).subtraction(hole);
+
+    let mut model = Model::new();
+    let prim = Primitive::builder().sdf(Sdf::expr(expr)).build();
+    let prim_handle = model.primitives.push(prim);
+    let mesh_handle = model.meshes.push(Mesh::new(vec![prim_handle]));
+    let node = Node::builder()
+        .mesh(mesh_handle)
+        .translation(Vec3::new(0.0, 0.0, -3.0))
+        .build();
+    let node_handle = model.nodes.push(node);
+    model.root.children.push(node_handle);
+
+    let mut scene = Scene::new();
+    scene.push(model);
+    scene.push_default_model();
+
+    let mut image = Image::new(128, 128, ColorType::RGBA8);
+    scene.draw(&mut image);
+    image.dump_png("target/sdf.png");
+
+    // The blend between the spheres is in front of the camera
+    let bvh = scene.build_bvh();
+    let result = scene.pick(&bvh, 32, 32, 64, 64).unwrap();
+    assert!(result.point.get_z() > -3.0 && result.point.get_z() < -2.5);
+
+    // Shadow rays leaving the surface reach a light in front of it
+    let ray = Ray::new(Point3::default(), Vec3::new(0.0, 0.0, -1.0));
+    let (hit, primitive) = bvh.intersects_iter(&scene.model, &ray).unwrap();
+    let normal = primitive.get_normal(&scene.model, &hit);
+    let light_dir = Vec3::new(0.0, 0.0, 1.0);
+    let shadow_ray = Ray::spawn(&hit.point, &normal, light_dir);
+    assert!(bvh.intersects_occluder(&scene.model, &shadow_ray).is_none());
+}
+
+#[test]
+fn shapes() {
+    let shapes = vec![
+        Triangles::uv_sphere(0.4, 24, 12),
+        Triangles::icosphere(0.4, 2),
+        Triangles::torus(0.3, 0.1, 24, 12),
+        Triangles::cylinder(0.3, 0.8, 24),
+        Triangles::cone(0.3, 0.8, 24),
+    ];
+
+    let mut model = Model::new();
+    let count = shapes.len();
+    for (i, shape) in shapes.into_iter().enumerate() {
+        let prim_handle = model
+            .primitives
+            .push(Primitive::builder().triangles(shape).build());
+        let mesh_handle = model.meshes.push(Mesh::new(vec![prim_handle]));
+        let x = i as f32 - (count - 1) as f32 / 2.0;
+        let node = Node::builder()
+            .mesh(mesh_handle)
+            .translation(Vec3::new(x, 0.0, 0.0))
+            .build();
+        let node_handle = model.nodes.push(node);
+        model.root.children.push(node_handle);
+    }
+    let plane = Primitive::builder()
+        .triangles(Triangles::plane(8.0, 8.0, 4, 4))
+        .build();
+    let prim_handle = model.primitives.push(plane);
+    let mesh_handle = model.meshes.push(Mesh::new(vec![prim_handle]));
+    let node = Node::builder()
+        .mesh(mesh_handle)
+        .translation(Vec3::new(0.0, -0.5, 0.0))
+        .build();
+    let node_handle = model.nodes.push(node);
+    model.root.children.push(node_handle);
+
+    let mut scene = Scene::new();
+    scene.push(model);
+    scene.push_default_model();
+
+    let mut image = Image::new(512, 256, ColorType::RGBA8);
+    scene.draw(&mut image);
+    image.dump_png("target/shapes.png");
+
+    // The sphere in the middle of the row is in front of the camera
+    let bvh = scene.build_bvh();
+    let result = scene.pick(&bvh, 32, 16, 64, 32).unwrap();
+    assert!(result.point.get_z() > 0.0);
+}
+
+#[test]
+fn pbr_test_grid() {
+    let mut scene = Scene::pbr_test_grid();
+    let mut image = Image::new(256, 256, ColorType::RGBA8);
+    scene.draw(&mut image);
+    image.dump_png("target/pbr-grid.png");
+
+    // Every sphere of the grid is in view
+    let bvh = scene.build_bvh();
+    for row in 0..5 {
+        for column in 0..5 {
+            let (x, y) = (column * 10 + 5, 45 - row * 10);
+            let result = scene.pick(&bvh, x, y, 50, 50).unwrap();
+            let primitive = scene.model.primitives.get(result.primitive).unwrap();
+            let material = scene.model.materials.get(primitive.material).unwrap();
+            assert_eq!(material.metallic_factor, row as f32 / 4.0);
+        }
+    }
+}
+
+#[test]
+fn pbr_test_grid_with() {
+    let mut source = Model::new();
+    let image = source.images.push(Image::new(1, 1, ColorType::RGBA8));
+    let sampler = source.samplers.push(Sampler::default());
+    let texture = source.textures.push(Texture::new(image, sampler));
+    let base = source.materials.push(Material {
+        albedo_texture: texture,
+        ..Material::new()
+    });
+
+    // Textures of the base material come along with the grid
+    let scene = Scene::pbr_test_grid_with(source, base, 2);
+    let primitive = scene.model.primitives.handles().next().unwrap();
+    let primitive = scene.model.primitives.get(primitive).unwrap();
+    let material = scene.model.materials.get(primitive.material).unwrap();
+    let texture = scene.model.textures.get(material.albedo_texture).unwrap();
+    assert!(scene.model.images.contains(texture.image));
+}
+
+#[test]
+fn scenes() {
+    let scenes = vec![
+        ("cornell-box", Scene::cornell_box()),
+        ("veach-mis", Scene::veach_mis()),
+        ("furnace", Scene::furnace()),
+    ];
+    for (name, mut scene) in scenes {
+        let mut image = Image::new(128, 128, ColorType::RGBA8);
+        scene.draw(&mut image);
+        image.dump_png(format!("target/{}.png", name));
+        assert!(image.bytes().iter().any(|&byte| byte > 0));
+    }
+
+    // The quad light reaches the floor of the Cornell box through its opening
+    let mut scene = Scene::cornell_box();
+    let bvh = scene.build_bvh();
+    let result = scene.pick(&bvh, 16, 30, 32, 32).unwrap();
+    assert!(result.point.get_y().abs() < 1e-3);
+}
+
+#[test]
+fn sky() {
+    let mut model = Model::new();
+    let plane = model.primitives.push(
+        Primitive::builder()
+            .triangles(Triangles::plane(8.0, 8.0, 1, 1))
+            .build(),
+    );
+    let sphere = model.primitives.push(Primitive::unit_sphere());
+    let mesh = model.meshes.push(Mesh::new(vec![plane, sphere]));
+    let node = model.nodes.push(Node::builder().mesh(mesh).build());
+    model.root.children.push(node);
+
+    let sky = model.lights.push(Light::sky(Vec3::new(1.0, 0.6, 0.5), 3.0));
+    let sky_node = model.nodes.push(Node::builder().light(sky).build());
+    model.root.children.push(sky_node);
+
+    let camera = model.cameras.push(Camera::default());
+    let camera_node = model.nodes.push(
+        Node::builder()
+            .camera(camera)
+            .translation(Vec3::new(0.0, 1.0, 5.0))
+            .build(),
+    );
+    model.root.children.push(camera_node);
+
+    let mut scene = Scene::new();
+    scene.push(model);
+    let mut image = Image::new(64, 64, ColorType::RGBA8);
+    scene.draw(&mut image);
+    image.dump_png("target/sky.png");
+
+    // Rays leaving the scene see a blue sky
+    let sky_pixel = &image.bytes()[..4];
+    assert_eq!(sky_pixel[3], 255);
+    assert!(sky_pixel[2] > sky_pixel[0]);
+}
+
+#[test]
+fn sky_sampling() {
+    let create_scene = |sky_samples: u32, seed: u64| {
+        let mut model = Model::new();
+        let plane = model.primitives.push(
+            Primitive::builder()
+                .triangles(Triangles::plane(8.0, 8.0, 1, 1))
+                .build(),
+        );
+        let mesh = model.meshes.push(Mesh::new(vec![plane]));
+        let node = model.nodes.push(Node::builder().mesh(mesh).build());
+        model.root.children.push(node);
+        // Sun below the horizon, leaving the sky dome as the only light
+        model.push_daylight(&Daylight {
+            hours: 18.5,
+            ..Default::default()
+        });
+        model.sky_sampling.max_intensity = 0.5;
+        let camera = model.cameras.push(Camera::default());
+        let camera_node = model.nodes.push(
+            Node::builder()
+                .camera(camera)
+                .translation(Vec3::new(0.0, 1.0, 5.0))
+                .build(),
+        );
+        model.root.children.push(camera_node);
+
+        let mut scene = Scene::new();
+        scene.push(model);
+        scene.config.seed = seed;
+        scene.config.integrator = Box::new(Scratcher {
+            sky_samples,
+            ..Default::default()
+        });
+        scene
+    };
+
+    let mut image = Image::new(32, 32, ColorType::RGBA8);
+    create_scene(0, 0).draw(&mut image);
+    let unsampled = brightness(&image);
+    create_scene(8, 0).draw(&mut image);
+    image.dump_png("target/sky-sampling.png");
+    let sampled = brightness(&image);
+    assert!(sampled > unsampled);
+
+    // Importance sampling keeps the noise low
+    create_scene(8, 1).draw(&mut image);
+    let other = brightness(&image);
+    assert!(other.abs_diff(sampled) < sampled / 50);
+}
+
+#[test]
+fn backplate() {
+    let mut model = Model::new();
+    let sphere = model.primitives.push(Primitive::unit_sphere());
+    let mesh = model.meshes.push(Mesh::new(vec![sphere]));
+    let node = model.nodes.push(Node::builder().mesh(mesh).build());
+    model.root.children.push(node);
+
+    let mut sky = Light::sky(Vec3::new(1.0, 0.6, 0.5), 3.0);
+    if let Light::Sky(sky) = &mut sky {
+        sky.visible_to_camera = false;
+    }
+    let sky = model.lights.push(sky);
+    let sky_node = model.nodes.push(Node::builder().light(sky).build());
+    model.root.children.push(sky_node);
+
+    let camera = model.cameras.push(Camera::default());
+    let camera_node = model.nodes.push(
+        Node::builder()
+            .camera(camera)
+            .translation(Vec3::new(0.0, 0.0, 5.0))
+            .build(),
+    );
+    model.root.children.push(camera_node);
+
+    let mut scene = Scene::new();
+    scene.push(model);
+    let mut backplate = Image::new(2, 2, ColorType::RGBA8);
+    for pixel in backplate.bytes_mut().chunks_exact_mut(4) {
+        pixel.copy_from_slice(&[255, 0, 0, 255]);
+    }
+    scene.config.backplate = Some(backplate);
+
+    let mut image = Image::new(32, 32, ColorType::RGBA8);
+    scene.draw(&mut image);
+
+    // Corners show the backplate, while the sky still lights the sphere
+    assert_eq!(&image.bytes()[..4], &[255, 0, 0, 255]);
+    let center = (16 * 32 + 16) * 4;
+    let sphere_pixel = &image.bytes()[center..center + 4];
+    assert!(sphere_pixel[1] > 0);
+}
+
+#[test]
+fn shadow_catcher() {
+    let mut model = Model::new();
+    let catcher = model.materials.push(Material {
+        shadow_catcher: true,
+        ..Material::new()
+    });
+    let plane = model.primitives.push(
+        Primitive::builder()
+            .triangles(Triangles::plane(8.0, 8.0, 1, 1))
+            .material(catcher)
+            .build(),
+    );
+    let floor_mesh = model.meshes.push(Mesh::new(vec![plane]));
+    let floor_node = model.nodes.push(Node::builder().mesh(floor_mesh).build());
+    model.root.children.push(floor_node);
+
+    let red = model.materials.push(
+        Material::builder()
+            .color(Color::new(1.0, 0.0, 0.0, 1.0))
+            .build(),
+    );
+    let sphere = model.primitives.push(
+        Primitive::builder()
+            .sphere(Point3::default(), 0.3)
+            .material(red)
+            .build(),
+    );
+    let sphere_mesh = model.meshes.push(Mesh::new(vec![sphere]));
+    let sphere_node = model.nodes.push(
+        Node::builder()
+            .mesh(sphere_mesh)
+            .translation(Vec3::new(0.0, 1.0, 0.0))
+            .build(),
+    );
+    model.root.children.push(sphere_node);
+
+    let mut light = Light::point();
+    light.set_intensity(32.0);
+    let light = model.lights.push(light);
+    let light_node = model.nodes.push(
+        Node::builder()
+            .light(light)
+            .translation(Vec3::new(0.0, 5.0, 0.0))
+            .build(),
+    );
+    model.root.children.push(light_node);
+
+    let camera = model.cameras.push(Camera::default());
+    let camera_node = model.nodes.push(
+        Node::builder()
+            .camera(camera)
+            .translation(Vec3::new(0.0, 2.0, 6.0))
+            .build(),
+    );
+    model.root.children.push(camera_node);
+
+    let mut scene = Scene::new();
+    scene.push(model);
+    let mut backplate = Image::new(2, 2, ColorType::RGBA8);
+    for pixel in backplate.bytes_mut().chunks_exact_mut(4) {
+        pixel.copy_from_slice(&[200, 200, 200, 255]);
+    }
+    scene.config.backplate = Some(backplate);
+
+    let mut image = Image::new(64, 64, ColorType::RGBA8);
+    scene.draw(&mut image);
+
+    // The open floor shows the backplate as it is
+    let bottom_left = 63 * 64 * 4;
+    assert_eq!(
+        &image.bytes()[bottom_left..bottom_left + 4],
+        &[200, 200, 200, 255]
+    );
+
+    // The shadow of the sphere darkens the backplate, without taking its red color
+    let shadow = image
+        .bytes()
+        .chunks_exact(4)
+        .any(|pixel| pixel[0] < 100 && pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+    assert!(shadow);
+}
+
+#[test]
+fn alpha() {
+    let mut model = Model::new();
+    let glass = model.materials.push(
+        Material::builder()
+            .color(Color::new(1.0, 1.0, 1.0, 0.5))
+            .build(),
+    );
+    let sphere = model.primitives.push(
+        Primitive::builder()
+            .sphere(Point3::default(), 1.0)
+            .material(glass)
+            .build(),
+    );
+    let mesh = model.meshes.push(Mesh::new(vec![sphere]));
+    let node = model.nodes.push(Node::builder().mesh(mesh).build());
+    model.root.children.push(node);
+
+    let camera = model.cameras.push(Camera::default());
+    let camera_node = model.nodes.push(
+        Node::builder()
+            .camera(camera)
+            .translation(Vec3::new(0.0, 0.0, 5.0))
+            .build(),
+    );
+    model.root.children.push(camera_node);
+
+    let mut scene = Scene::new();
+    scene.push(model);
+    let mut image = Image::new(32, 32, ColorType::RGBA8);
+    scene.draw(&mut image);
+
+    // Nothing covers the corners, while the camera sees the background through
+    // both the front and the back of the sphere at its center
+    assert_eq!(image.bytes()[3], 0);
+    let center = (16 * 32 + 16) * 4;
+    let alpha = image.bytes()[center + 3];
+    assert!((190..=192).contains(&alpha), "{}", alpha);
+}
+
+#[test]
+fn color_space() {
+    let create_scene = || {
+        let mut model = Model::new();
+        let red = model.materials.push(
+            Material::builder()
+                .color(Color::new(0.8, 0.1, 0.1, 1.0))
+                .build(),
+        );
+        let sphere = model.primitives.push(
+            Primitive::builder()
+                .sphere(Point3::default(), 1.0)
+                .material(red)
+                .build(),
+        );
+        let mesh = model.meshes.push(Mesh::new(vec![sphere]));
+        let node = model.nodes.push(Node::builder().mesh(mesh).build());
+        model.root.children.push(node);
+
+        let light = model.lights.push(Light::point());
+        let light_node = model.nodes.push(
+            Node::builder()
+                .light(light)
+                .translation(Vec3::new(0.0, 2.0, 2.0))
+                .build(),
+        );
+        model.root.children.push(light_node);
+
+        let camera = model.cameras.push(Camera::default());
+        let camera_node = model.nodes.push(
+            Node::builder()
+                .camera(camera)
+                .translation(Vec3::new(0.0, 0.0, 5.0))
+                .build(),
+        );
+        model.root.children.push(camera_node);
+
+        let mut scene = Scene::new();
+        scene.push(model);
+        scene
+    };
+    let center = (16 * 32 + 16) * 4;
+
+    let mut srgb = Image::new(32, 32, ColorType::RGBA8);
+    create_scene().draw(&mut srgb);
+
+    // Rendering in ACEScg and going back to sRGB gives about the same image
+    let mut scene = create_scene();
+    scene.config.working_space = ColorSpace::AcesCg;
+    let mut image = Image::new(32, 32, ColorType::RGBA8);
+    scene.draw(&mut image);
+    assert_eq!(image.color_space, ColorSpace::Srgb);
+    for (a, b) in srgb.bytes().iter().zip(image.bytes()) {
+        assert!((*a as i32 - *b as i32).abs() <= 16);
+    }
+
+    // Images in ACEScg are less saturated when read as sRGB
+    scene.config.output_space = ColorSpace::AcesCg;
+    scene.draw(&mut image);
+    assert_eq!(image.color_space, ColorSpace::AcesCg);
+    assert!(image.bytes()[center] < srgb.bytes()[center]);
+    assert!(image.bytes()[center + 1] > srgb.bytes()[center + 1]);
+}
+
+#[test]
+fn checkpoint() {
+    let create_scene = || {
+        let mut scene = Scene::new();
+        let mut model = Model::new();
+        let sphere = model.primitives.push(Primitive::unit_sphere());
+        let mesh = model.meshes.push(Mesh::new(vec![sphere]));
+        let node = model.nodes.push(Node::builder().mesh(mesh).build());
+        model.root.children.push(node);
+        scene.push(model);
+        scene.push(Scene::create_default_model());
+        scene.config.samples_per_pixel = 4;
+        scene
+    };
+    let path = std::env::temp_dir().join(format!("rayca-checkpoint-{}.rckp", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let mut expected = Image::new(16, 16, ColorType::RGBA8);
+    create_scene().draw(&mut expected);
+
+    // An interrupted render saves no samples
+    let mut scene = create_scene();
+    scene.config.checkpoint = Some(path.clone());
+    scene.config.cancel.cancel();
+    let mut image = Image::new(16, 16, ColorType::RGBA8);
+    scene.draw(&mut image);
+    let checkpoint = Checkpoint::load(&path).unwrap();
+    assert!(checkpoint.tiles.iter().all(|tile| tile.sample_count == 0));
+
+    // Half of the samples first, then the rest from the checkpoint
+    scene.config.cancel.reset();
+    scene.config.samples_per_pixel = 2;
+    scene.draw(&mut image);
+    scene.config.samples_per_pixel = 4;
+    scene.draw(&mut image);
+    let checkpoint = Checkpoint::load(&path).unwrap();
+    assert!(checkpoint.tiles.iter().all(|tile| tile.sample_count == 4));
+    assert_eq!(image.bytes(), expected.bytes());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn distributed() {
+    let create_scene = || {
+        let mut scene = Scene::new();
+        let mut model = Model::new();
+        let sphere = model.primitives.push(Primitive::unit_sphere());
+        let mesh = model.meshes.push(Mesh::new(vec![sphere]));
+        let node = model.nodes.push(Node::builder().mesh(mesh).build());
+        model.root.children.push(node);
+        scene.push(model);
+        scene.push(Scene::create_default_model());
+        scene
+    };
+
+    let mut expected = Image::new(16, 16, ColorType::RGBA8);
+    create_scene().draw(&mut expected);
+
+    let mut workers = vec![];
+    let mut threads = vec![];
+    for _ in 0..2 {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        workers.push(listener.local_addr().unwrap().to_string());
+        threads.push(std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve_connection(&mut create_scene(), stream).unwrap();
+        }));
+    }
+    // A worker which is not there leaves its jobs to the others
+    let missing = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    workers.push(missing.local_addr().unwrap().to_string());
+    drop(missing);
+    // So does a worker failing after the others have taken all the jobs
+    let failing = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    workers.push(failing.local_addr().unwrap().to_string());
+    threads.push(std::thread::spawn(move || {
+        use std::io::Read;
+        let (mut stream, _) = failing.accept().unwrap();
+        let mut len = [0; 4];
+        stream.read_exact(&mut len).unwrap();
+        let mut job = vec![0; u32::from_le_bytes(len) as usize];
+        stream.read_exact(&mut job).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+    }));
+
+    let mut coordinator = Coordinator::new(workers);
+    coordinator.rows_per_job = 3;
+    let mut image = Image::new(16, 16, ColorType::RGBA8);
+    coordinator.render(&mut create_scene(), &mut image).unwrap();
+    assert_eq!(image.bytes(), expected.bytes());
+
+    // Workers stop once the coordinator is done with them
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+#[test]
+fn caustics() {
+    let create_scene = || {
+        let mut model = Model::new();
+        let floor = model.materials.push(Material {
+            metallic_factor: 0.0,
+            ..Material::new()
+        });
+        let mirror = model.materials.push(Material {
+            roughness_factor: 0.0,
+            ..Material::new()
+        });
+        let plane = model.primitives.push(
+            Primitive::builder()
+                .triangles(Triangles::plane(8.0, 8.0, 1, 1))
+                .material(floor)
+                .build(),
+        );
+        let floor_mesh = model.meshes.push(Mesh::new(vec![plane]));
+        let floor_node = model.nodes.push(Node::builder().mesh(floor_mesh).build());
+        model.root.children.push(floor_node);
+
+        // Wall on the left reflecting the light onto the floor
+        let wall = model.primitives.push(
+            Primitive::builder()
+                .triangles(Triangles::plane(2.0, 4.0, 1, 1))
+                .material(mirror)
+                .build(),
+        );
+        let wall_mesh = model.meshes.push(Mesh::new(vec![wall]));
+        let wall_node = model.nodes.push(
+            Node::builder()
+                .mesh(wall_mesh)
+                .translation(Vec3::new(-1.5, 1.0, 0.0))
+                .rotation(Quat::rotation_between(
+                    Vec3::new(0.0, 1.0, 0.0),
+                    Vec3::new(1.0, 0.0, 0.0),
+                ))
+                .build(),
+        );
+        model.root.children.push(wall_node);
+
+        let mut light = Light::point();
+        light.set_intensity(32.0);
+        let light = model.lights.push(light);
+        let light_node = model.nodes.push(
+            Node::builder()
+                .light(light)
+                .translation(Vec3::new(0.0, 1.5, 0.0))
+                .build(),
+        );
+        model.root.children.push(light_node);
+
+        let camera = model.cameras.push(Camera::default());
+        let camera_node = model.nodes.push(
+            Node::builder()
+                .camera(camera)
+                .translation(Vec3::new(0.0, 2.0, 6.0))
+                .build(),
+        );
+        model.root.children.push(camera_node);
+
+        let mut scene = Scene::new();
+        scene.push(model);
+        scene
+    };
+
+    let mut scene = create_scene();
+    let mut image = Image::new(64, 64, ColorType::RGBA8);
+    scene.draw(&mut image);
+    let without_caustics = brightness(&image);
+
+    // Light bouncing off the mirror reaches the floor
+    let mut scene = create_scene();
+    scene.config.integrator = Box::new(PhotonMapper::new(20000, 0.2));
+    scene.draw(&mut image);
+    image.dump_png("target/caustics.png");
+    assert!(brightness(&image) > without_caustics);
+}
+
+#[test]
+fn stratified_lights() {
+    let mut image = Image::new(32, 32, ColorType::RGBA8);
+
+    let mut scene = Scene::cornell_box();
+    scene.draw(&mut image);
+    let jittered = brightness(&image);
+
+    // More samples of the quad light converge to the same brightness
+    let mut scene = Scene::cornell_box();
+    scene.config.integrator = Box::new(Scratcher {
+        light_stratify: 2,
+        light_samples: 2,
+        ..Default::default()
+    });
+    scene.draw(&mut image);
+    image.dump_png("target/stratified.png");
+    let stratified = brightness(&image);
+    assert!(stratified.abs_diff(jittered) < jittered / 10);
+}
+
+#[test]
+fn restir() {
+    let mut image = Image::new(32, 32, ColorType::RGBA8);
+
+    let mut scene = Scene::cornell_box();
+    let mut restir = Restir::default();
+    restir.reuse = false;
+    scene.config.integrator = Box::new(restir);
+    scene.draw(&mut image);
+    let resampled = brightness(&image);
+    assert!(resampled > 0);
+
+    // Reusing the reservoirs of previous frames keeps the same brightness
+    let mut scene = Scene::cornell_box();
+    scene.config.integrator = Box::new(Restir::default());
+    for _ in 0..3 {
+        scene.draw(&mut image);
+    }
+    image.dump_png("target/restir.png");
+    let reused = brightness(&image);
+    assert!(reused.abs_diff(resampled) < resampled / 10);
+}
+
+#[test]
+fn guided() {
+    let mut image = Image::new(32, 32, ColorType::RGBA8);
+
+    let mut scene = Scene::cornell_box();
+    scene.draw(&mut image);
+    let direct = brightness(&image);
+
+    // Light bouncing off the walls brightens the box, while the tree is learning
+    let mut scene = Scene::cornell_box();
+    scene.config.integrator = Box::new(Guided::new());
+    let mut guided = vec![];
+    for _ in 0..4 {
+        scene.draw(&mut image);
+        guided.push(brightness(&image));
+    }
+    image.dump_png("target/guided.png");
+    assert!(guided[3] > direct);
+    assert!(guided[3].abs_diff(guided[2]) < guided[2] / 10);
+}
+
+#[test]
+fn preview() {
+    let render = |seed: u64| {
+        let mut image = Image::new(32, 32, ColorType::RGBA8);
+        let mut scene = Scene::cornell_box();
+        scene.config.integrator = Box::new(Preview::new());
+        scene.config.seed = seed;
+        scene.draw(&mut image);
+        image
+    };
+
+    // Without noise, different seeds render the same image
+    let first = render(0);
+    first.dump_png("target/preview.png");
+    let second = render(1);
+    assert!(first.bytes() == second.bytes());
+    assert!(first.bytes().iter().any(|&byte| byte > 0));
+}
+
 #[test]
 fn cube_over_plane() {
     let mut image = Image::new(512, 512, ColorType::RGBA8);
@@ -2,12 +2,116 @@ import * as rayca from "rayca";
 
 var ctx = null;
 
+// A glTF model can be loaded by passing its URL, e.g. `?model=https://...`
+const modelUrl = new URLSearchParams(window.location.search).get("model");
+
+// Progressive samples are rendered by a pool of web workers, each one with its own
+// copy of the scene. Without workers, the context renders them on the main thread
+const workers = [];
+var readyCount = 0;
+var pendingRows = 0;
+
+const stopWorkers = (reason) => {
+    console.warn("Rendering on the main thread:", reason);
+    for (const worker of workers) {
+        worker.terminate();
+    }
+    workers.length = 0;
+    pendingRows = 0;
+    ctx.set_workers(false);
+}
+
+const onWorkerMessage = (message) => {
+    if (workers.length == 0) {
+        return;
+    }
+    if (message.type == "ready") {
+        readyCount += 1;
+        if (readyCount == workers.length) {
+            ctx.set_workers(true);
+        }
+    } else if (message.type == "rows") {
+        ctx.merge_rows(message.frame, message.start, message.tiles);
+        pendingRows -= 1;
+        if (pendingRows == 0) {
+            ctx.present_samples();
+        }
+    } else if (message.type == "error") {
+        stopWorkers(message.message);
+    }
+}
+
+const startWorkers = () => {
+    const count = (navigator.hardwareConcurrency || 1) - 1;
+    if (typeof Worker === "undefined" || count < 1) {
+        return;
+    }
+    try {
+        for (let i = 0; i < count; ++i) {
+            const worker = new Worker(new URL("./worker.js", import.meta.url));
+            worker.onmessage = (event) => onWorkerMessage(event.data);
+            worker.onerror = (event) => stopWorkers(event.message);
+            worker.postMessage({ type: "init", url: modelUrl });
+            workers.push(worker);
+        }
+    } catch (error) {
+        stopWorkers(error);
+    }
+}
+
+// Splits the rows of the next sample of the canvas among the workers
+const refine = () => {
+    if (workers.length == 0 || readyCount < workers.length || pendingRows > 0) {
+        return;
+    }
+    const frame = ctx.get_frame();
+    if (frame === undefined) {
+        return;
+    }
+    const height = ctx.get_height();
+    const rowsPerWorker = Math.ceil(height / workers.length);
+    for (let start = 0, i = 0; start < height; start += rowsPerWorker, ++i) {
+        const end = Math.min(start + rowsPerWorker, height);
+        workers[i].postMessage({ type: "render", frame: frame, start: start, end: end });
+        pendingRows += 1;
+    }
+}
+
 const tick = async () => {
     if (ctx == null) {
-        ctx = await rayca.Context.new();
+        if (modelUrl != null) {
+            ctx = await rayca.Context.with_model(modelUrl);
+        } else {
+            ctx = await rayca.Context.new();
+        }
+        startWorkers();
     }
     ctx.draw();
+    refine();
     requestAnimationFrame(tick);
 }
 
 requestAnimationFrame(tick);
+
+// Left button orbits, right button pans, and the wheel zooms
+const canvas = document.getElementById("area");
+canvas.addEventListener("contextmenu", (event) => event.preventDefault());
+canvas.addEventListener("mousemove", (event) => {
+    if (ctx == null || event.buttons == 0) {
+        return;
+    }
+    const dx = event.movementX / canvas.clientWidth;
+    const dy = event.movementY / canvas.clientHeight;
+    if (event.buttons & 1) {
+        ctx.orbit(dx * Math.PI, dy * Math.PI);
+    } else if (event.buttons & 2) {
+        ctx.pan(dx, dy);
+    }
+});
+canvas.addEventListener("wheel", (event) => {
+    if (ctx == null) {
+        return;
+    }
+    event.preventDefault();
+    ctx.zoom(Math.sign(event.deltaY) * 0.1);
+});
@@ -0,0 +1,22 @@
+import * as rayca from "rayca";
+
+// Renders samples of the frames of the context of the page, see `RowRenderer`
+var renderer = null;
+
+onmessage = async (event) => {
+    const message = event.data;
+    try {
+        if (message.type == "init") {
+            renderer = await rayca.RowRenderer.new(message.url);
+            postMessage({ type: "ready" });
+        } else if (message.type == "render") {
+            const tiles = renderer.render(message.frame, message.start, message.end);
+            postMessage(
+                { type: "rows", frame: message.frame, start: message.start, tiles: tiles },
+                [tiles.buffer]
+            );
+        }
+    } catch (error) {
+        postMessage({ type: "error", message: String(error) });
+    }
+};